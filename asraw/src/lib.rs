@@ -6,6 +6,17 @@
 
 use core::{mem, slice};
 
+/// Errors from building a structure out of raw bytes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The byte slice is not the same size as the structure.
+    WrongSize,
+    /// The type's validation hook rejected the data.
+    Invalid,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
 pub trait AsRaw : Sized {
     fn as_raw(&self) -> &[u8] {
         unsafe {
@@ -26,6 +37,28 @@ pub unsafe trait AsMutRaw : Sized {
                                       mem::size_of::<Self>())
         }
     }
+
+    /// Check that the data just read is meaningful, such as having the correct
+    /// magic number.  Types override this to be used by `try_from_raw`.
+    fn validate_raw(&self) -> bool {
+        true
+    }
+
+    /// Build this structure from its bytes.  The length must match exactly,
+    /// and the result must pass `validate_raw`.
+    fn try_from_raw(bytes: &[u8]) -> Result<Self>
+        where Self: Default
+    {
+        if bytes.len() != mem::size_of::<Self>() {
+            return Err(Error::WrongSize);
+        }
+        let mut result = Self::default();
+        result.as_mut_raw().copy_from_slice(bytes);
+        if !result.validate_raw() {
+            return Err(Error::Invalid);
+        }
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
@@ -82,4 +115,26 @@ mod tests {
         // checks the defined fields.
         assert!(a == big || a == little);
     }
+
+    #[derive(Debug, Default, Eq, PartialEq)]
+    #[repr(C)]
+    struct Magic {
+        magic: u32,
+    }
+
+    unsafe impl AsMutRaw for Magic {
+        fn validate_raw(&self) -> bool {
+            self.magic == 0x12121212
+        }
+    }
+
+    #[test]
+    fn try_from_raw() {
+        assert_eq!(Item::try_from_raw(&[0u8; 7]), Err(Error::WrongSize));
+        assert_eq!(Item::try_from_raw(&[0u8; 9]), Err(Error::WrongSize));
+        assert_eq!(Item::try_from_raw(&[0u8; 8]), Ok(Item::default()));
+
+        assert_eq!(Magic::try_from_raw(&[0x12; 4]), Ok(Magic { magic: 0x12121212 }));
+        assert_eq!(Magic::try_from_raw(&[0x13; 4]), Err(Error::Invalid));
+    }
 }
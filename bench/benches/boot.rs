@@ -0,0 +1,92 @@
+// Benchmarks of validation, status layout, and swaps, on each flash style.
+
+use std::cell::RefCell;
+
+use bench::build;
+use boot::{request_upgrade, Image, SlotInfo, SwapType};
+use bootsim::{Device, Trust};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use simflash::styles::{AreaLayout, SlotMap, ALL_FLASHES};
+
+/// A name for a flash style, from its geometry.
+fn name(layout: &AreaLayout) -> String {
+    format!("w{}-e{}x{}", layout.write_size, layout.erase_size, layout.sectors)
+}
+
+/// The image size used with a pair of slots: half the smaller one, so there
+/// is always room for the trailer.
+fn image_size(main: &AreaLayout, upgrade: &AreaLayout) -> usize {
+    let capacity = (main.erase_size * main.sectors).min(upgrade.erase_size * upgrade.sectors);
+    (capacity / 2).min(96 * 1024)
+}
+
+fn validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate");
+    for (main, upgrade) in ALL_FLASHES.iter() {
+        let image = build(1, image_size(main, upgrade));
+        let mut flash = main.build().unwrap();
+        flash.install(&image, 0).unwrap();
+        let flash = RefCell::new(flash);
+
+        group.throughput(Throughput::Bytes(image.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name(main)), &flash, |b, flash| {
+            b.iter(|| Image::from_flash(flash).unwrap().validate().unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn status_layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("status_layout");
+    for (main, upgrade) in ALL_FLASHES.iter() {
+        let size = image_size(main, upgrade);
+        let main_flash = main.build().unwrap();
+        let upgrade_flash = upgrade.build().unwrap();
+        let main_info = SlotInfo::from_data(size, &main_flash);
+        let upgrade_info = SlotInfo::from_data(size, &upgrade_flash);
+
+        group.bench_function(BenchmarkId::from_parameter(name(main)), |b| {
+            b.iter(|| main_info.status_layout(&upgrade_info).unwrap());
+        });
+    }
+    group.finish();
+}
+
+/// A whole test upgrade: validating the new image, swapping, and validating
+/// the result, as bootsim does it.
+fn swap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("swap");
+    group.sample_size(10);
+    for (main, upgrade) in ALL_FLASHES.iter() {
+        let size = image_size(main, upgrade);
+        let map = SlotMap {
+            name: name(main),
+            main: (*main).clone(),
+            upgrade: (*upgrade).clone(),
+            scratch: None,
+        };
+        let old = build(1, size);
+        let new = build(2, size);
+
+        group.throughput(Throughput::Bytes(2 * size as u64));
+        group.bench_function(BenchmarkId::from_parameter(name(main)), |b| {
+            b.iter_batched(
+                || {
+                    let dev = Device::load(&map, &old, &new, None).unwrap();
+                    request_upgrade(&mut *dev.secondary.borrow_mut()).unwrap();
+                    dev
+                },
+                |dev| {
+                    let outcome = bootsim::boot(&dev, &Trust::Hash).unwrap();
+                    assert_eq!(outcome.swap_type, SwapType::Test);
+                    outcome.booted.unwrap();
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validation, status_layout, swap);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! Benchmarks of the boot code on simulated flash
+//!
+//! The benchmarks themselves are in `benches/`, and are run with
+//!
+//!     cargo bench
+//!
+//! They cover each of the flash styles in `simflash::styles::ALL_FLASHES`, so
+//! a change to the hashing or swap paths shows up as numbers for each kind of
+//! device.  This only has what they share.
+
+use sha2::{Digest, Sha256};
+
+pub const HEADER_SIZE: usize = 256;
+
+/// Build an image of about `size` bytes, with the given major version, and
+/// just a SHA256.  The payload depends on the version, so different versions
+/// differ throughout.
+pub fn build(major: u8, size: usize) -> Vec<u8> {
+    let payload_len = size.saturating_sub(HEADER_SIZE);
+    let mut image = vec![];
+    image.extend(0x96f3b83du32.to_le_bytes());
+    image.extend(0u32.to_le_bytes());
+    image.extend((HEADER_SIZE as u16).to_le_bytes());
+    image.extend(0u16.to_le_bytes());
+    image.extend((payload_len as u32).to_le_bytes());
+    image.extend(0u32.to_le_bytes());
+    image.extend([major, 0, 0, 0, 0, 0, 0, 0]);
+    image.resize(HEADER_SIZE, 0);
+    image.extend((0..payload_len as u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8 ^ major));
+
+    let hash = Sha256::digest(&image);
+    image.extend(0x6907u16.to_le_bytes());
+    image.extend((4u16 + 4 + 32).to_le_bytes());
+    image.extend(0x10u16.to_le_bytes());
+    image.extend(32u16.to_le_bytes());
+    image.extend(hash);
+    image
+}
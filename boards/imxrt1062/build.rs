@@ -0,0 +1,22 @@
+//! Generate the linker script with imxrt-rt.  This places the FlexSPI
+//! configuration block, the image vector table and boot data at the start of
+//! flash, as the boot ROM expects.
+//!
+//! The bootloader is given only the first part of the flash, the rest holds
+//! the slots (see `src/main.rs`).  All code runs from ITCM, so that the flash
+//! can be programmed without executing from it.
+
+use imxrt_rt::{Family, Memory, RuntimeBuilder};
+
+/// The part of the flash the bootloader may use.
+const BOOTLOADER_SIZE: usize = 64 * 1024;
+
+fn main() {
+    RuntimeBuilder::from_flexspi(Family::Imxrt1060, BOOTLOADER_SIZE)
+        .text(Memory::Itcm)
+        .rodata(Memory::Dtcm)
+        .data(Memory::Dtcm)
+        .bss(Memory::Dtcm)
+        .build()
+        .unwrap();
+}
@@ -0,0 +1,217 @@
+//! i.MX RT1060 FlexSPI NOR flash driver.
+//!
+//! The i.MX RT parts have no internal flash.  They boot from an external NOR
+//! on FlexSPI, which is then read through the memory map at 0x6000_0000.
+//! Programming and erasing go through the boot ROM's FlexSPI NOR driver, which
+//! is configured from the same FlexSPI configuration block (FCB) the ROM
+//! booted with, at the very start of the flash.  This keeps the driver
+//! independent of the particular NOR part.
+//!
+//! The flash can't be read while it is being programmed, so the code calling
+//! the ROM must not run from flash.  The bootloader's code and constant data
+//! are all linked into the TCMs (see `build.rs`).
+//!
+//! The NOR is programmed in pages, and erased in sectors, with the sizes taken
+//! from the FCB.
+
+use core::cell::RefCell;
+
+use cortex_m::peripheral::SCB;
+
+use boot::MappedFlash;
+use storage::{Error, Flash, ReadFlash, Result};
+
+/// The FlexSPI memory-mapped window.
+pub const FLEXSPI_BASE: usize = 0x6000_0000;
+
+/// Where the ROM API tree pointer lives, on the RT1060.
+const ROM_API_TREE: usize = 0x0020_001c;
+
+/// The FlexSPI instance the ROM booted from.
+const INSTANCE: u32 = 0;
+
+/// The FCB is 512 bytes.
+const CONFIG_WORDS: usize = 128;
+
+// Fields of the FCB, as byte offsets.
+const FCB_PAGE_SIZE: usize = 0x1c0;
+const FCB_SECTOR_SIZE: usize = 0x1c4;
+
+/// Largest page supported.
+const MAX_PAGE: usize = 512;
+
+/// ROM status for success.
+const STATUS_SUCCESS: i32 = 0;
+
+// Cortex-M7 cache line size.
+const LINE_SIZE: usize = 32;
+
+/// The ROM's FlexSPI NOR driver.  Only some of the functions are used.
+#[allow(dead_code)]
+#[repr(C)]
+struct NorDriver {
+    version: u32,
+    init: unsafe extern "C" fn(u32, *mut u32) -> i32,
+    program: unsafe extern "C" fn(u32, *mut u32, u32, *const u32) -> i32,
+    erase_all: unsafe extern "C" fn(u32, *mut u32) -> i32,
+    erase: unsafe extern "C" fn(u32, *mut u32, u32, u32) -> i32,
+    read: unsafe extern "C" fn(u32, *mut u32, *mut u32, u32, u32) -> i32,
+    clear_cache: unsafe extern "C" fn(u32),
+}
+
+/// The start of the ROM API tree.
+#[allow(dead_code)]
+#[repr(C)]
+struct ApiTree {
+    version: u32,
+    copyright: *const u8,
+    run_bootloader: unsafe extern "C" fn(*mut u32),
+    reserved: u32,
+    nor_driver: *const NorDriver,
+}
+
+pub struct RtFlash {
+    config: RefCell<[u32; CONFIG_WORDS]>,
+    driver: &'static NorDriver,
+    capacity: usize,
+    page_size: usize,
+    sector_size: usize,
+}
+
+impl RtFlash {
+    /// Initialize the ROM driver from the FCB.  `capacity` is the size of the
+    /// NOR part.
+    pub fn new(capacity: usize) -> Result<RtFlash> {
+        let mut config = [0u32; CONFIG_WORDS];
+        for (i, word) in config.iter_mut().enumerate() {
+            *word = unsafe { core::ptr::read_volatile((FLEXSPI_BASE as *const u32).add(i)) };
+        }
+        let page_size = config[FCB_PAGE_SIZE / 4] as usize;
+        let sector_size = config[FCB_SECTOR_SIZE / 4] as usize;
+        if page_size == 0 || page_size > MAX_PAGE || sector_size == 0 {
+            return Err(Error::Failed);
+        }
+
+        let driver = unsafe {
+            let tree = *(ROM_API_TREE as *const *const ApiTree);
+            &*(*tree).nor_driver
+        };
+        let flash = RtFlash { config: RefCell::new(config), driver, capacity, page_size, sector_size };
+        let status = unsafe { (flash.driver.init)(INSTANCE, flash.config.borrow_mut().as_mut_ptr()) };
+        if status != STATUS_SUCCESS {
+            return Err(Error::Failed);
+        }
+        Ok(flash)
+    }
+
+    /// Build a partition, given its offset from the start of flash.
+    pub fn partition(&self, base: usize, length: usize) -> Result<RtPartition<'_>> {
+        if length == 0 || base > self.capacity || length > self.capacity - base {
+            return Err(Error::OutOfBounds);
+        }
+        if !base.is_multiple_of(self.sector_size) || !length.is_multiple_of(self.sector_size) {
+            return Err(Error::NotAligned);
+        }
+        Ok(RtPartition { flash: self, base, length })
+    }
+
+    /// After a change, the FlexSPI prefetch buffers and the core's data cache
+    /// may still hold old contents.
+    fn changed(&self, offset: usize, len: usize) {
+        unsafe { (self.driver.clear_cache)(INSTANCE) };
+        if SCB::dcache_enabled() {
+            let mut p = unsafe { cortex_m::Peripherals::steal() };
+            let start = (FLEXSPI_BASE + offset) & !(LINE_SIZE - 1);
+            let end = (FLEXSPI_BASE + offset + len).next_multiple_of(LINE_SIZE);
+            unsafe { p.SCB.invalidate_dcache_by_address(start, end - start) };
+        }
+    }
+}
+
+pub struct RtPartition<'a> {
+    flash: &'a RtFlash,
+    base: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for RtPartition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let slice = unsafe {
+            core::slice::from_raw_parts((FLEXSPI_BASE + self.base + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(FLEXSPI_BASE + self.base)
+    }
+}
+
+impl<'a> Flash for RtPartition<'a> {
+    fn write_size(&self) -> usize {
+        self.flash.page_size
+    }
+
+    fn erase_size(&self) -> usize {
+        self.flash.sector_size
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        if from == to {
+            return Ok(());
+        }
+        let start = self.base + from;
+        let flash = self.flash;
+        let status = cortex_m::interrupt::free(|_| unsafe {
+            (flash.driver.erase)(INSTANCE, flash.config.borrow_mut().as_mut_ptr(), start as u32, (to - from) as u32)
+        });
+        flash.changed(start, to - from);
+        if status != STATUS_SUCCESS {
+            return Err(Error::Failed);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+        let start = self.base + offset;
+        let flash = self.flash;
+        let page_size = flash.page_size;
+
+        // The ROM wants word aligned source data.
+        let mut page = [0u32; MAX_PAGE / 4];
+        let mut result = Ok(());
+        for (i, chunk) in bytes.chunks(page_size).enumerate() {
+            for (word, src) in page.iter_mut().zip(chunk.chunks_exact(4)) {
+                *word = u32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+            }
+            let dst = (start + i * page_size) as u32;
+            let status = cortex_m::interrupt::free(|_| unsafe {
+                (flash.driver.program)(INSTANCE, flash.config.borrow_mut().as_mut_ptr(), dst, page.as_ptr())
+            });
+            if status != STATUS_SUCCESS {
+                result = Err(Error::Failed);
+                break;
+            }
+        }
+        flash.changed(start, bytes.len());
+        result
+    }
+}
+
+impl<'a> MappedFlash for RtPartition<'a> {
+    fn get_base(&self) -> usize {
+        FLEXSPI_BASE + self.base
+    }
+}
@@ -0,0 +1,70 @@
+#![no_main]
+#![no_std]
+
+use panic_probe as _;
+use defmt_rtt as _;
+use defmt::{error, info, Debug2Format};
+
+// The FlexSPI configuration block for the EVK's NOR, placed by imxrt-rt.
+use imxrt1060evk_fcb as _;
+
+use core::cell::RefCell;
+
+use boot::{Image, MappedFlash};
+
+mod flash;
+
+/// The NOR on the i.MX RT1060 EVK.
+const FLASH_SIZE: usize = 8 * 1024 * 1024;
+
+// Partitions, as offsets from the start of flash.  The bootloader, with the
+// boot headers, has the first 64 KB (see build.rs).
+const SLOT0_BASE: usize = 0x1_0000;
+const SLOT0_SIZE: usize = 0x20_0000;
+const SLOT1_BASE: usize = 0x21_0000;
+const SLOT1_SIZE: usize = 0x20_0000;
+
+/// The key images must be signed with.
+static SIGNING_KEY: &[u8] = include_bytes!("../../../boot/data/ecdsa-p256-pub.der");
+
+#[imxrt_rt::entry]
+fn main() -> ! {
+    info!("---------- Start of bootloader ----------");
+
+    let flash = match flash::RtFlash::new(FLASH_SIZE) {
+        Ok(flash) => flash,
+        Err(e) => halt("Unable to set up the flash", e.into()),
+    };
+    let mut crypto = boot::SoftCrypto::new();
+
+    let slot0 = flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap();
+    let slot1 = flash.partition(SLOT1_BASE, SLOT1_SIZE).unwrap();
+    info!("slot0: 0x{:x}, slot1: 0x{:x}", slot0.get_base(), slot1.get_base());
+    let slot0 = RefCell::new(slot0);
+
+    let image = match Image::from_flash(&slot0) {
+        Ok(image) => image,
+        Err(e) => halt("No image in slot0", e),
+    };
+    info!("Image found, {} bytes", image.full_image_size());
+
+    if let Err(e) = image.validate_signed(&mut crypto, SIGNING_KEY) {
+        halt("Image is invalid", e);
+    }
+    info!("Image is valid");
+
+    let base = image.get_image_base().unwrap_or_else(|e| halt("Image is misaligned", e));
+    info!("Chaining to 0x{:x}", base);
+    // The image runs in place from the FlexSPI window, and doesn't need its
+    // own boot headers.
+    let Err(e) = unsafe { boot::chain(&boot::CortexM, &image) };
+    halt("Image is misaligned", e)
+}
+
+/// Report a failure to boot, and stop.
+fn halt(message: &str, e: boot::Error) -> ! {
+    error!("{}: {}", message, Debug2Format(&e));
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -1,5 +1,8 @@
 MEMORY
 {
-  FLASH : ORIGIN = 0x00000000, LENGTH = 512K
-  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+  /* Must agree with BOOTLOADER in src/partitions.rs. */
+  FLASH : ORIGIN = 0x00000000, LENGTH = 128K
+  /* The last word of RAM holds the boot request, see BOOT_REQUEST, and the
+     256 bytes below it the boot event log, see EVENT_LOG. */
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K - 4 - 256
 }
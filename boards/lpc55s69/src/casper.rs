@@ -0,0 +1,105 @@
+//! LPC55S6x CASPER coprocessor.
+//!
+//! CASPER is a multiply-accumulate engine that operates on operands placed in
+//! its own RAM.  This uses it for the 256x256 bit multiplies that dominate
+//! ECDSA verification, by providing `boot::WideMul`.  The rest of the P-256
+//! arithmetic is done by the boot crate.
+//!
+//! The product is computed schoolbook style: for each 64-bit word of `b`, the
+//! engine computes `C += A * b[j]` across the four 64-bit words of `A`, with C
+//! offset by one 64-bit word on each pass.
+//!
+//!     let raw = hal.casper.enabled(&mut syscon).release();
+//!     let casper = casper::Casper::new(raw);
+
+use boot::{WideMul, U256};
+use lpc55_hal as hal;
+
+/// Base of the CASPER RAM, as seen by the CPU.
+const CASPER_RAM_BASE: usize = 0x1400_0000;
+
+// Operand locations within the CASPER RAM, as byte offsets.
+const RAM_A: u32 = 0x000;
+const RAM_B: u32 = 0x040;
+const RAM_C: u32 = 0x080;
+
+// CTRL0 fields.
+const CTRL0_ADDR_A_SHIFT: u32 = 0;
+const CTRL0_ADDR_B_SHIFT: u32 = 16;
+
+// CTRL1 fields.
+const CTRL1_ITER_SHIFT: u32 = 0;
+const CTRL1_MODE_SHIFT: u32 = 8;
+const CTRL1_RESADDR_SHIFT: u32 = 16;
+
+/// C = C + A * B, with A iterated over, and B a single 64-bit word.
+const MODE_MUL6464_SUM: u32 = 0x02;
+
+const STATUS_DONE: u32 = 1 << 0;
+
+pub struct Casper {
+    raw: hal::raw::CASPER,
+}
+
+impl Casper {
+    pub fn new(raw: hal::raw::CASPER) -> Casper {
+        Casper { raw }
+    }
+
+    /// Release the raw peripheral.
+    pub fn release(self) -> hal::raw::CASPER {
+        self.raw
+    }
+
+    /// The CASPER RAM on the LPC55S69 is interleaved between two banks, so that
+    /// the engine can fetch both halves of a 64-bit word at once.  Map a byte
+    /// offset, as the engine sees it, to the offset the CPU must use.
+    fn interleave(offset: u32) -> usize {
+        ((((offset >> 2) & 1) << 13) | ((offset >> 3) << 2) | (offset & 3)) as usize
+    }
+
+    fn ram_write(&self, offset: u32, words: &[u32]) {
+        for (i, &word) in words.iter().enumerate() {
+            let addr = CASPER_RAM_BASE + Self::interleave(offset + 4 * i as u32);
+            unsafe { core::ptr::write_volatile(addr as *mut u32, word) };
+        }
+    }
+
+    fn ram_read(&self, offset: u32, words: &mut [u32]) {
+        for (i, word) in words.iter_mut().enumerate() {
+            let addr = CASPER_RAM_BASE + Self::interleave(offset + 4 * i as u32);
+            *word = unsafe { core::ptr::read_volatile(addr as *const u32) };
+        }
+    }
+
+    /// Start an operation, and wait for it to finish.
+    fn run(&self, mode: u32, iterations: u32, a: u32, b: u32, c: u32) {
+        self.raw.ctrl0.write(|w| unsafe {
+            w.bits(((a >> 3) << CTRL0_ADDR_A_SHIFT) | ((b >> 3) << CTRL0_ADDR_B_SHIFT))
+        });
+        self.raw.ctrl1.write(|w| unsafe {
+            w.bits(((iterations - 1) << CTRL1_ITER_SHIFT)
+                   | (mode << CTRL1_MODE_SHIFT)
+                   | ((c >> 3) << CTRL1_RESADDR_SHIFT))
+        });
+        while self.raw.status.read().bits() & STATUS_DONE == 0 {
+        }
+    }
+}
+
+impl WideMul for Casper {
+    fn mul_wide(&mut self, a: &U256, b: &U256) -> [u32; 16] {
+        self.ram_write(RAM_A, a);
+        // The extra word allows the carry of the final pass.
+        self.ram_write(RAM_C, &[0u32; 18]);
+
+        for j in 0..4 {
+            self.ram_write(RAM_B, &b[2 * j .. 2 * j + 2]);
+            self.run(MODE_MUL6464_SUM, 4, RAM_A, RAM_B, RAM_C + 8 * j as u32);
+        }
+
+        let mut result = [0u32; 16];
+        self.ram_read(RAM_C, &mut result);
+        result
+    }
+}
@@ -0,0 +1,36 @@
+//! LPC55S6x protected flash key provisioning.
+//!
+//! The customer manufacturing programmable area (CMPA) is a page of protected
+//! flash that is written once during manufacturing.  We keep the SHA256 of the
+//! image signing key in its customer defined area, rather than compiling a key
+//! into the bootloader.  The ROM's own fields (such as the ROTKH) are left
+//! alone.
+//!
+//! The CMPA is outside of the flash range used for partitions, but it can be
+//! read directly.
+
+use boot::{provisioned, KeyStore, Result};
+
+/// Address of the CMPA page.
+const CMPA_BASE: usize = 0x9_e400;
+
+/// Offset of the customer defined area within the CMPA.
+const CMPA_CUSTOMER: usize = 0x100;
+
+/// Offset of the key hash within the customer area.
+const KEY_HASH_OFFSET: usize = 0;
+
+/// A key store backed by the CMPA.
+pub struct CmpaKeyStore;
+
+impl KeyStore for CmpaKeyStore {
+    fn key_hash(&mut self) -> Result<Option<[u8; 32]>> {
+        let addr = CMPA_BASE + CMPA_CUSTOMER + KEY_HASH_OFFSET;
+        let mut hash = [0u8; 32];
+        let slice = unsafe {
+            core::slice::from_raw_parts(addr as *const u8, hash.len())
+        };
+        hash.copy_from_slice(slice);
+        Ok(provisioned(hash))
+    }
+}
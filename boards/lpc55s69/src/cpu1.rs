@@ -0,0 +1,33 @@
+//! Second core (CPU1) startup.
+//!
+//! The LPC55S69 has a second Cortex-M33, which is held in reset with its clock
+//! off.  Its image lives in its own slot, and is validated just like the main
+//! image.  The core is released just before chaining to the main image, and
+//! starts from the vector table at CPBOOT.  There is no separate stack
+//! register on this part, CPU1 takes its initial stack pointer from the first
+//! word of the vector table.
+//!
+//! The hal doesn't wrap these registers, so this uses the raw peripheral.
+
+use lpc55_hal as hal;
+
+// CPUCTRL register.  Writes only take effect with the key in the upper half.
+const CPUCTRL_KEY: u32 = 0xc0c4_8000;
+const CPUCTRL_CPU1CLKEN: u32 = 1 << 3;
+const CPUCTRL_CPU1RSTEN: u32 = 1 << 5;
+
+/// Start CPU1 running the image whose vector table is at `vector_table`.
+pub fn release(vector_table: usize) {
+    let syscon = unsafe { hal::raw::Peripherals::steal().SYSCON };
+
+    // Hold the core in reset, with its clock running, while setting the boot
+    // address.
+    let ctrl = syscon.cpuctrl.read().bits();
+    syscon.cpuctrl.write(|w| unsafe {
+        w.bits(ctrl | CPUCTRL_KEY | CPUCTRL_CPU1CLKEN | CPUCTRL_CPU1RSTEN)
+    });
+    syscon.cpboot.write(|w| unsafe { w.bits(vector_table as u32) });
+
+    let ctrl = syscon.cpuctrl.read().bits();
+    syscon.cpuctrl.write(|w| unsafe { w.bits((ctrl | CPUCTRL_KEY) & !CPUCTRL_CPU1RSTEN) });
+}
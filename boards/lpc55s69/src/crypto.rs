@@ -0,0 +1,43 @@
+//! LPC55S6x crypto backend.
+//!
+//! Combines the HASHCRYPT engine, for SHA256, with CASPER, for the arithmetic
+//! of ECDSA verification.
+
+use boot::{CryptoBackend, Hash256};
+
+use crate::casper::Casper;
+use crate::hashcrypt::LpcHashCrypt;
+
+pub struct LpcCrypto {
+    hash: LpcHashCrypt,
+    casper: Casper,
+}
+
+impl LpcCrypto {
+    pub fn new(hash: LpcHashCrypt, casper: Casper) -> LpcCrypto {
+        LpcCrypto { hash, casper }
+    }
+}
+
+impl CryptoBackend for LpcCrypto {
+    fn sha256_start(&mut self) {
+        self.hash.sha256_start();
+    }
+
+    fn sha256_update(&mut self, data: &[u8]) {
+        self.hash.sha256_update(data);
+    }
+
+    fn sha256_finish(&mut self) -> Hash256 {
+        self.hash.sha256_finish()
+    }
+
+    fn ecdsa_p256_verify(&mut self, key: &[u8; 65], hash: &Hash256,
+                         r: &[u8; 32], s: &[u8; 32]) -> bool {
+        boot::ecdsa_verify(&mut self.casper, key, hash, r, s)
+    }
+
+    fn scrub(&mut self) {
+        self.hash.scrub();
+    }
+}
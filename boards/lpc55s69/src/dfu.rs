@@ -0,0 +1,138 @@
+//! USB DFU recovery.
+//!
+//! An alternative to serial recovery.  The upgrade slot is exposed to the host
+//! as a single DfuSe memory region, so it can be written with
+//! `dfu-util -a 0 -s 0x40000:leave -D signed.bin`.  When the download is
+//! finished, the image is validated the same way the bootloader validates
+//! before booting, and is only marked as pending if it is good.
+
+use core::cell::RefCell;
+
+use boot::{error, request_upgrade, CryptoBackend, Hash256, Image};
+use storage::{Flash, ReadFlash};
+use usbd_dfu::{DFUManifestationError, DFUMemError, DFUMemIO};
+
+use crate::partitions::SLOT1;
+
+/// Size of a single DFU block.  This matches the flash page size, so each
+/// block is programmed with one write.
+const BLOCK_SIZE: usize = 512;
+
+/// The DfuSe descriptor of the upgrade slot.  This can't be generated from the
+/// partition table, so make sure it is updated along with it.
+const MEM_INFO: &str = "@Upgrade slot/0x00040000/256*512Bg";
+const _: () = assert!(SLOT1.base == 0x40000 && SLOT1.size == 256 * BLOCK_SIZE);
+
+pub struct DfuSlot<'a, F, C> {
+    slot: RefCell<F>,
+    crypto: &'a mut C,
+    key_hash: &'a Hash256,
+    buffer: [u8; BLOCK_SIZE],
+}
+
+impl<'a, F: Flash, C: CryptoBackend> DfuSlot<'a, F, C> {
+    pub fn new(slot: F, crypto: &'a mut C, key_hash: &'a Hash256) -> Self {
+        DfuSlot {
+            slot: RefCell::new(slot),
+            crypto,
+            key_hash,
+            buffer: [0xff; BLOCK_SIZE],
+        }
+    }
+
+    /// Convert a DFU address into an offset into the slot.
+    fn offset(&self, address: u32, length: usize) -> Result<usize, DFUMemError> {
+        let offset = (address as usize)
+            .checked_sub(SLOT1.base)
+            .ok_or(DFUMemError::Address)?;
+        if offset + length > self.slot.borrow().capacity() {
+            return Err(DFUMemError::Address);
+        }
+        Ok(offset)
+    }
+
+    /// Check the uploaded image, and request the upgrade if it is good.
+    fn accept(&mut self) -> boot::Result<()> {
+        // DfuSe only erases the pages it writes, so the page with the magic
+        // still has to be erased.  An image running into that page would lose
+        // its end, so is refused.
+        let (size, page) = {
+            let slot = self.slot.borrow();
+            (slot.capacity(), slot.erase_size())
+        };
+        {
+            let image = Image::from_flash(&self.slot)?;
+            image.validate_key_hash(self.crypto, self.key_hash)?;
+            if image.full_image_size() > size - page {
+                error!("Image runs into the trailer page");
+                return Err(boot::Error::InvalidImage);
+            }
+        }
+
+        let mut slot = self.slot.borrow_mut();
+        slot.erase(size - page, size)?;
+        request_upgrade(&mut *slot)
+    }
+}
+
+impl<'a, F: Flash, C: CryptoBackend> DFUMemIO for DfuSlot<'a, F, C> {
+    const INITIAL_ADDRESS_POINTER: u32 = SLOT1.base as u32;
+    const MEM_INFO_STRING: &'static str = MEM_INFO;
+    const HAS_DOWNLOAD: bool = true;
+    const HAS_UPLOAD: bool = false;
+    // Reset once the image has been checked, rather than staying in DFU.
+    const MANIFESTATION_TOLERANT: bool = false;
+    const TRANSFER_SIZE: u16 = BLOCK_SIZE as u16;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 10;
+    const FULL_ERASE_TIME_MS: u32 = 256 * 10;
+    // Validation hashes the whole slot.
+    const MANIFESTATION_TIME_MS: u32 = 500;
+
+    fn read(&mut self, _address: u32, _length: usize) -> Result<&[u8], DFUMemError> {
+        Err(DFUMemError::Unknown)
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DFUMemError> {
+        let offset = self.offset(address, BLOCK_SIZE)?;
+        self.slot
+            .borrow_mut()
+            .erase(offset, offset + BLOCK_SIZE)
+            .map_err(|_| DFUMemError::Erase)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DFUMemError> {
+        let mut slot = self.slot.borrow_mut();
+        let size = slot.capacity();
+        slot.erase(0, size).map_err(|_| DFUMemError::Erase)
+    }
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        if src.len() > BLOCK_SIZE {
+            return Err(());
+        }
+        // Pad a short final block.
+        self.buffer.fill(0xff);
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DFUMemError> {
+        if length > BLOCK_SIZE || !(address as usize).is_multiple_of(BLOCK_SIZE) {
+            return Err(DFUMemError::Address);
+        }
+        let offset = self.offset(address, BLOCK_SIZE)?;
+        self.slot
+            .borrow_mut()
+            .write(offset, &self.buffer)
+            .map_err(|_| DFUMemError::Prog)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DFUManifestationError> {
+        self.accept().map_err(|_| DFUManifestationError::Verify)
+    }
+
+    fn usb_reset(&mut self) -> ! {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
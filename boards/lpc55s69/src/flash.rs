@@ -7,93 +7,146 @@
 //!   interfaces
 //! - Implement a robust read that will return an error instead of busfaulting
 //!   on unprogrammed data.
+//! - Implement erase and program of 512-byte pages, so that upgrades can be
+//!   performed on the target.
 //!
 //! To use this driver, you should release the FLASH PAC from the hal's driver.
+//! The slots are `boot::Slot`s on the device, which is shared through a
+//! `RefCell`.
 //!
 //!     let flash = hal.flash.release();
-//!     let fl = flash::LpcFlash::new(flash);
+//!     let fl = RefCell::new(flash::LpcFlash::new(flash));
+//!     let slot0 = Slot::new(&fl, SLOT0.base, SLOT0.size, SlotPurpose::Primary)?;
 
-use core::cell::RefCell;
+use core::ops::Range;
 
 use boot::MappedFlash;
-use storage::ReadFlash;
+use byteorder::{ByteOrder, LittleEndian};
+use storage::{Flash, ReadFlash};
 use hal::raw::FLASH;
 use lpc55_hal as hal;
 
+use crate::prince::Prince;
+
 pub use storage::Error;
 
-// use crate::hprintln;
+// use boot::debug;
 
 type Result<T> = core::result::Result<T, Error>;
 
 pub struct LpcFlash {
-    raw: RefCell<hal::raw::FLASH>,
+    raw: hal::raw::FLASH,
+    prince: Option<Prince>,
 }
 
 const LPC_FLASH_BASE: usize = 0;
-const LPC_FLASH_SIZE: usize = 630 * 1024;
+pub const LPC_FLASH_SIZE: usize = 630 * 1024;
+
+/// The flash is erased and programmed in 512-byte pages.
+pub const LPC_PAGE_SIZE: usize = 512;
+
+// Commands for the flash controller.
+const CMD_ERASE_RANGE: u32 = 4;
+const CMD_BLANK_CHECK: u32 = 6;
+const CMD_SET_PAGE_DATA: u32 = 8;
+const CMD_PROGRAM_PAGE: u32 = 12;
+
+// Flush request in the SYSCON FMCFLUSH register.
+const FMCFLUSH_FLUSH: u32 = 1 << 0;
 
 // Flash for the entire device.
 impl LpcFlash {
     pub fn new(raw: hal::raw::FLASH) -> LpcFlash {
-        LpcFlash { raw: RefCell::new(raw) }
+        LpcFlash { raw, prince: None }
     }
 
-    pub fn partition(&self, base: usize, length: usize) -> Result<LpcPartition> {
-        LpcPartition::new(self, base, length)
+    /// Build a flash device that has PRINCE regions configured.  Pages within
+    /// an encrypted region are written with encryption enabled.
+    pub fn with_prince(raw: hal::raw::FLASH, prince: Prince) -> LpcFlash {
+        LpcFlash { raw, prince: Some(prince) }
     }
-}
 
-// A single flash partition.  References the parent.
-pub struct LpcPartition<'a> {
-    flash: &'a LpcFlash,
-    base: usize,
-    length: usize,
-}
+    /// Return the part of `range` of the flash that is encrypted by PRINCE,
+    /// such as that of a slot, as offsets within it.
+    pub fn prince_region(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let prince = self.prince.as_ref()?;
+        (0..crate::prince::REGIONS).find_map(|r| {
+            let region = prince.region(r)?;
+            let start = region.start.max(range.start);
+            let end = region.end.min(range.end);
+            if start < end {
+                Some(start - range.start .. end - range.start)
+            } else {
+                None
+            }
+        })
+    }
 
-impl<'a> LpcPartition<'a> {
-    pub fn new(flash: &'a LpcFlash, base: usize, length: usize) -> Result<Self> {
-        if length == 0 {
-            return Err(Error::OutOfBounds);
-        }
-        // This wouldn't be right if the flash was at the end of the address
-        // space. But as such, it does prevent overflow.  It is safe to subtract
-        // one because we checked that above.
-        let end = match base.checked_add(length) {
-            Some(e) => e - 1,
-            None => return Err(Error::OutOfBounds),
-        };
-        // No overflow check, as these are consts.
-        let self_range = LPC_FLASH_BASE .. LPC_FLASH_BASE + LPC_FLASH_SIZE;
-        if !(self_range.contains(&base) && self_range.contains(&end)) {
-            return Err(Error::OutOfBounds);
+    /// Program whole pages, starting at the given flash address.
+    ///
+    /// Programming a page takes far longer than loading it, so each page is
+    /// started without waiting for it, and the next page's words are unpacked
+    /// while it programs.  Only then is the previous page waited for, as the
+    /// controller must be idle to take the next page's data.
+    fn program(&self, base: usize, bytes: &[u8]) -> Result<()> {
+        let raw = &self.raw;
+        let mut cells = [0u32; LPC_PAGE_SIZE / 4];
+        // The page being programmed, and whether it is being encrypted.
+        let mut busy: Option<Option<&Prince>> = None;
+
+        for (i, page) in bytes.chunks_exact(LPC_PAGE_SIZE).enumerate() {
+            let addr = base + i * LPC_PAGE_SIZE;
+            LittleEndian::read_u32_into(page, &mut cells);
+
+            if let Some(encrypt) = busy.take() {
+                finish_program(raw, encrypt)?;
+            }
+
+            // Pages in an encrypted region must be encrypted as they are
+            // programmed, and only those pages.
+            let encrypt = match self.prince {
+                Some(ref prince) if prince.is_encrypted(addr) => {
+                    prince.set_write_encryption(true);
+                    Some(prince)
+                }
+                _ => None,
+            };
+            busy = Some(encrypt);
+            if !start_program(raw, addr as u32, &cells) {
+                finish_program(raw, encrypt)?;
+                return Err(Error::Failed);
+            }
         }
 
-        Ok(LpcPartition { flash, base, length })
+        match busy {
+            Some(encrypt) => finish_program(raw, encrypt),
+            None => Ok(()),
+        }
     }
 }
 
-impl<'a> ReadFlash for LpcPartition<'a> {
+// The whole flash, addressed from its base.
+impl ReadFlash for LpcFlash {
     // We allow arbitrary alignment of reads.
     fn read_size(&self) -> usize {
         1
     }
 
     fn capacity(&self) -> usize {
-        self.length
+        LPC_FLASH_SIZE
     }
 
     fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
         storage::check_read(self, offset, buf.len())?;
 
-        let offset = offset.checked_add(self.base).ok_or(Error::OutOfBounds)?;
+        let offset = LPC_FLASH_BASE + offset;
 
         // Validate that the entire range has been written.
         let end = offset + buf.len();
         let mut bpage = offset & !511;
         while bpage < end {
-            // hprintln!("Read check: 0x{:x}", bpage);
-            if !read_check(&self.flash.raw.borrow(), bpage as u32) {
+            // debug!("Read check: 0x{:x}", bpage);
+            if !read_check(&self.raw, bpage as u32) {
                 // Indicate read error with Other
                 return Err(Error::NotWritten);
             }
@@ -110,9 +163,42 @@ impl<'a> ReadFlash for LpcPartition<'a> {
     }
 }
 
-impl<'a> MappedFlash for LpcPartition<'a> {
+impl Flash for LpcFlash {
+    fn write_size(&self) -> usize {
+        LPC_PAGE_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        LPC_PAGE_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        if from == to {
+            return Ok(());
+        }
+
+        let base = LPC_FLASH_BASE + from;
+        let good = erase(&self.raw, base as u32, (to - from) as u32);
+        flush_accelerator();
+        if !good {
+            return Err(Error::Failed);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+
+        let result = self.program(LPC_FLASH_BASE + offset, bytes);
+        flush_accelerator();
+        result
+    }
+}
+
+impl MappedFlash for LpcFlash {
     fn get_base(&self) -> usize {
-        LPC_FLASH_BASE + self.base
+        LPC_FLASH_BASE
     }
 }
 
@@ -126,7 +212,37 @@ fn read_check(flash: &FLASH, addr: u32) -> bool {
 
     flash.starta.write(|w| unsafe{w.bits(addr >> 4)});
     flash.stopa.write(|w| unsafe{w.bits(addr >> 4)});
-    flash.cmd.write(|w| unsafe{w.bits(6)});
+    flash.cmd.write(|w| unsafe{w.bits(CMD_BLANK_CHECK)});
+    while flash.int_status.read().done().bit_is_clear() {
+    }
+
+    let good = flash.int_status.read().fail().bit_is_clear();
+
+    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+
+    good
+}
+
+/// Discard anything buffered by the flash accelerator.  Its prefetch and data
+/// buffers are not updated by erase or program, so a read through the memory
+/// map just after either can return the old contents.  The cores have no cache
+/// of their own in front of flash, so this is the only maintenance needed.
+fn flush_accelerator() {
+    let syscon = unsafe { hal::raw::Peripherals::steal().SYSCON };
+    syscon.fmcflush.write(|w| unsafe { w.bits(FMCFLUSH_FLUSH) });
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Erase a range of pages.  The base and length must be page aligned.
+fn erase(flash: &FLASH, base: u32, length: u32) -> bool {
+    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+
+    // The stop address is that of the last page to erase.
+    let ending = base + length - LPC_PAGE_SIZE as u32;
+    flash.starta.write(|w| unsafe{w.bits(base >> 4)});
+    flash.stopa.write(|w| unsafe{w.bits(ending >> 4)});
+    flash.cmd.write(|w| unsafe{w.bits(CMD_ERASE_RANGE)});
     while flash.int_status.read().done().bit_is_clear() {
     }
 
@@ -136,3 +252,43 @@ fn read_check(flash: &FLASH, addr: u32) -> bool {
 
     good
 }
+
+/// Start programming a single page, without waiting for it to finish.  The
+/// page is loaded into the controller's page register 16 bytes at a time.
+/// Each load only waits for the controller to take the data; the fail bit is
+/// sticky, so it is checked once, after the whole page.
+fn start_program(flash: &FLASH, base: u32, cells: &[u32; LPC_PAGE_SIZE / 4]) -> bool {
+    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+    for (word, row) in cells.chunks_exact(4).enumerate() {
+        flash.starta.write(|w| unsafe{w.bits(word as u32)});
+        for (column, &cell) in row.iter().enumerate() {
+            flash.dataw[column].write(|w| unsafe{w.bits(cell)});
+        }
+        flash.cmd.write(|w| unsafe{w.bits(CMD_SET_PAGE_DATA)});
+        while flash.int_status.read().done().bit_is_clear() {
+        }
+    }
+    if flash.int_status.read().fail().bit_is_set() {
+        return false;
+    }
+
+    flash.starta.write(|w| unsafe{w.bits(base >> 4)});
+    flash.cmd.write(|w| unsafe{w.bits(CMD_PROGRAM_PAGE)});
+    true
+}
+
+/// Wait for the page started by `start_program` to finish, and turn off the
+/// encryption it was programmed with.
+fn finish_program(flash: &FLASH, encrypt: Option<&Prince>) -> Result<()> {
+    while flash.int_status.read().done().bit_is_clear() {
+    }
+
+    let good = flash.int_status.read().fail().bit_is_clear();
+
+    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+
+    if let Some(prince) = encrypt {
+        prince.set_write_encryption(false);
+    }
+    if good { Ok(()) } else { Err(Error::Failed) }
+}
@@ -0,0 +1,166 @@
+//! LPC55S6x HASHCRYPT engine.
+//!
+//! The hal's SHA256 support doesn't currently build, so this drives the
+//! HASHCRYPT peripheral directly, providing a `CryptoBackend` for the boot
+//! crate.  The engine consumes 64-byte blocks; partial blocks are buffered, and
+//! the final padding is done here.  Whole blocks that are word aligned are fed
+//! to the engine with its own bus master, rather than word-at-a-time through
+//! INDATA.
+//!
+//! To use this driver, enable the peripheral and release the raw PAC from the
+//! hal's driver.
+//!
+//!     let raw = hal.hashcrypt.enabled(&mut syscon).release();
+//!     let mut crypto = hashcrypt::LpcHashCrypt::new(raw);
+
+use boot::{CryptoBackend, Hash256};
+use byteorder::{BigEndian, ByteOrder};
+use lpc55_hal as hal;
+
+const BLOCK_SIZE: usize = 64;
+
+// CTRL register.
+const CTRL_MODE_SHA256: u32 = 2;
+const CTRL_NEW_HASH: u32 = 1 << 4;
+const CTRL_HASHSWPB: u32 = 1 << 12;
+
+// STATUS register.
+const STATUS_WAITING: u32 = 1 << 0;
+const STATUS_DIGEST: u32 = 1 << 1;
+const STATUS_ERROR: u32 = 1 << 2;
+
+// MEMCTRL register.
+const MEMCTRL_MASTER: u32 = 1 << 0;
+const MEMCTRL_COUNT_SHIFT: u32 = 16;
+const MEMCTRL_COUNT_MAX: usize = 0x7ff;
+
+pub struct LpcHashCrypt {
+    raw: hal::raw::HASHCRYPT,
+    /// Partial block, not yet given to the engine.
+    block: [u8; BLOCK_SIZE],
+    /// Number of bytes in `block`.
+    fill: usize,
+    /// Total bytes hashed so far, for the padding.
+    total: u64,
+}
+
+impl LpcHashCrypt {
+    pub fn new(raw: hal::raw::HASHCRYPT) -> LpcHashCrypt {
+        LpcHashCrypt {
+            raw,
+            block: [0; BLOCK_SIZE],
+            fill: 0,
+            total: 0,
+        }
+    }
+
+    /// Release the raw peripheral.
+    pub fn release(self) -> hal::raw::HASHCRYPT {
+        self.raw
+    }
+
+    /// Wait for the engine to be ready for more data.
+    fn wait_ready(&self) {
+        while self.raw.status.read().bits() & (STATUS_WAITING | STATUS_ERROR) == 0 {
+        }
+    }
+
+    /// Give a single block to the engine through INDATA.
+    fn write_block(&self, block: &[u8]) {
+        self.wait_ready();
+        for word in block.chunks_exact(4) {
+            let word = u32::from_ne_bytes([word[0], word[1], word[2], word[3]]);
+            self.raw.indata.write(|w| unsafe { w.bits(word) });
+        }
+    }
+
+    /// Give whole, aligned blocks to the engine using its bus master.
+    fn master_blocks(&self, data: &[u8]) {
+        for chunk in data.chunks(MEMCTRL_COUNT_MAX * BLOCK_SIZE) {
+            self.wait_ready();
+            let count = (chunk.len() / BLOCK_SIZE) as u32;
+            self.raw.memaddr.write(|w| unsafe { w.bits(chunk.as_ptr() as u32) });
+            self.raw.memctrl.write(|w| unsafe {
+                w.bits(MEMCTRL_MASTER | (count << MEMCTRL_COUNT_SHIFT))
+            });
+            self.wait_ready();
+        }
+        self.raw.memctrl.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+impl CryptoBackend for LpcHashCrypt {
+    fn sha256_start(&mut self) {
+        self.fill = 0;
+        self.total = 0;
+        self.raw.ctrl.write(|w| unsafe {
+            w.bits(CTRL_MODE_SHA256 | CTRL_NEW_HASH | CTRL_HASHSWPB)
+        });
+    }
+
+    fn sha256_update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+
+        // Finish any partial block first.
+        if self.fill > 0 {
+            let todo = (BLOCK_SIZE - self.fill).min(data.len());
+            self.block[self.fill..self.fill + todo].copy_from_slice(&data[..todo]);
+            self.fill += todo;
+            data = &data[todo..];
+            if self.fill < BLOCK_SIZE {
+                return;
+            }
+            self.write_block(&self.block);
+            self.fill = 0;
+        }
+
+        // Whole blocks go directly to the engine.
+        let whole = data.len() & !(BLOCK_SIZE - 1);
+        if whole > 0 {
+            if data.as_ptr() as usize & 3 == 0 {
+                self.master_blocks(&data[..whole]);
+            } else {
+                for block in data[..whole].chunks_exact(BLOCK_SIZE) {
+                    self.write_block(block);
+                }
+            }
+            data = &data[whole..];
+        }
+
+        self.block[..data.len()].copy_from_slice(data);
+        self.fill = data.len();
+    }
+
+    fn sha256_finish(&mut self) -> Hash256 {
+        // Standard SHA256 padding: a one bit, zeros, and the bit length.
+        let bits = self.total * 8;
+        self.block[self.fill] = 0x80;
+        self.block[self.fill + 1..].fill(0);
+        if self.fill + 1 > BLOCK_SIZE - 8 {
+            self.write_block(&self.block);
+            self.block.fill(0);
+        }
+        BigEndian::write_u64(&mut self.block[BLOCK_SIZE - 8..], bits);
+        self.write_block(&self.block);
+        self.fill = 0;
+
+        while self.raw.status.read().bits() & (STATUS_DIGEST | STATUS_ERROR) == 0 {
+        }
+
+        let mut result = [0u8; 32];
+        for (i, out) in result.chunks_exact_mut(4).enumerate() {
+            BigEndian::write_u32(out, self.raw.digest0[i].read().bits());
+        }
+
+        // Disable the engine until the next hash.
+        self.raw.ctrl.write(|w| unsafe { w.bits(0) });
+        result
+    }
+
+    fn scrub(&mut self) {
+        // Starting a new hash clears the engine's digest.
+        boot::zeroize(&mut self.block);
+        self.sha256_start();
+        self.raw.ctrl.write(|w| unsafe { w.bits(0) });
+    }
+}
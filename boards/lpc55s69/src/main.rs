@@ -13,89 +13,164 @@ use defmt_rtt as _;
 
 use core::cell::RefCell;
 
-use boot::{Image, MappedFlash};
+use boot::{
+    error, info, recovery, Access, CryptoBackend, Delay, EventCode, Image, KeyStore, Region,
+    RetainedWord, SerialEscape, Slot, SlotPurpose, VerifiedFlash, Watchdog, WatchedFlash, Window,
+};
 use cortex_m_rt::entry;
 
-use embedded_hal::{digital::v2::OutputPin, timer::CountDown};
-use hal::{drivers::{pins::Level, Timer, timer::Elapsed}, peripherals::ctimer::Ctimer, Enabled};
+use embedded_hal::timer::CountDown;
+use embedded_hal::digital::v2::InputPin;
+use hal::{drivers::{pins::Level, serial::config::Config, Serial, Timer, timer::Elapsed, UsbBus}, peripherals::ctimer::Ctimer, Enabled};
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usbd_dfu::DFUClass;
+
+use partitions::{BOOTLOADER, CORE1, SLOT0, SLOT1};
 use lpc55_hal as hal;
 use embedded_time::rate::Extensions;
 use embedded_time::duration::Extensions as DurationExtensions;
 use embedded_time::duration::Microseconds;
 use embedded_time::fixed_point::FixedPoint;
+use storage::Flash;
 
+mod casper;
+mod cmpa;
+mod cpu1;
+mod crypto;
+mod dfu;
+mod flash;
+mod hashcrypt;
+mod partitions;
+mod prince;
+mod rng;
+mod teardown;
+mod usart;
+mod wwdt;
+
+// Messages, from the boot crate and from here, go out over semihosting or
+// defmt.  With neither, the boot crate compiles them out.
 #[cfg(feature = "semihosting")]
 mod logging {
-    pub use cortex_m_semihosting::{hprintln};
-}
+    use core::fmt;
 
-mod flash;
+    use boot::{Level, Log};
+    use cortex_m_semihosting::hprintln;
 
-// Use 'info' if we are using defmt.
-#[cfg(feature = "rtt")]
-mod logging {
-    macro_rules! hprintln {
-        ($e:expr) => {
-            defmt::error!($e);
-        };
-        ($e:expr, $($args:expr),+) => {
-            defmt::error!($e, $($args),+);
-        };
+    pub struct Semihosting;
+
+    impl Log for Semihosting {
+        fn log(&self, level: Level, args: fmt::Arguments<'_>) {
+            hprintln!("{}: {}", level, args);
+        }
     }
-    pub(crate) use hprintln;
+
+    pub static LOGGER: Semihosting = Semihosting;
 }
 
-// If semihosting is not available, just discard printed messages.  It also
-// "uses" the arguments so disabling printing doesn't cause additional warnings.
-#[cfg(not(any(feature = "semihosting",feature = "rtt")))]
+#[cfg(feature = "rtt")]
 mod logging {
-    macro_rules! hprintln {
-        ($_e:expr) => {{}};
-        ($_e:expr, $($x:expr),+) => {
-            $(let _ = $x;);+
-        };
-    }
-    pub(crate) use hprintln;
+    pub static LOGGER: boot_log::Defmt = boot_log::Defmt;
+}
+
+/// Set to have the primary slot decrypted on the fly by PRINCE region 0.
+const PRINCE_SLOT0: bool = false;
+
+/// The IV for the PRINCE region.  Normally this comes from the protected flash
+/// pages.
+const PRINCE_IV: u64 = 0;
+
+/// Watchdog timeout.  This must be long enough to cover the slowest single
+/// flash operation, and give the application time to start feeding it.
+const WATCHDOG_TIMEOUT_MS: u32 = 2_000;
+
+/// Baud rate of the recovery USART (Flexcomm 0, the debug probe's VCOM port).
+const RECOVERY_BAUD: u32 = 115_200;
+
+/// The word of RAM, excluded from the linker's RAM, where the application can
+/// leave a recovery request.
+const BOOT_REQUEST: usize = 0x2003_fffc;
+
+/// The words of RAM below the boot request, also excluded from the linker's
+/// RAM, where the boot event log is left for the application.
+const EVENT_LOG: usize = 0x2003_fefc;
+const EVENT_LOG_WORDS: usize = 64;
+
+/// Use USB DFU for recovery, instead of the serial port.  DFU only writes the
+/// upgrade slot.
+const RECOVERY_USB: bool = false;
+
+/// Zero all of RAM before chaining, rather than just the stack the crypto
+/// code used.  The event log and boot request word are left alone.
+const SCRUB_RAM: bool = false;
+
+/// The RAM the linker gives the bootloader, up to the event log.
+const RAM: core::ops::Range<usize> = 0x2000_0000..EVENT_LOG;
+
+/// Leave the bootloader's flash read only and execute never, with the MPU,
+/// once the application is running.  The trailers stay accessible, as the
+/// application confirms itself through them.
+const MPU_PROTECT: bool = false;
+
+/// Measure the stack used, by painting it at startup, when there is somewhere
+/// to report it.
+const MEASURE_STACK: bool = cfg!(any(feature = "semihosting", feature = "rtt"));
+
+/// Time programming the start of the upgrade slot, a page at a time and as a
+/// batch, at startup.  This destroys any upgrade there, so is only for
+/// checking changes to the flash driver.
+const BENCH_FLASH: bool = false;
+
+/// Read back every page programmed, so a page that silently failed to take
+/// stops an upgrade or recovery before the rest is overwritten.
+const VERIFY_WRITES: bool = true;
+
+/// Pages programmed by `bench_flash`.
+const BENCH_PAGES: usize = 8;
+
+extern "C" {
+    // Provided by the cortex-m-rt linker script.  The stack runs down from
+    // the top of RAM to the end of the static data.
+    static __sheap: u32;
+    static _stack_start: u32;
 }
 
-pub(crate) use logging::hprintln;
+/// The lowest and highest addresses of the stack.
+fn stack_bounds() -> (usize, usize) {
+    unsafe { (&__sheap as *const u32 as usize, &_stack_start as *const u32 as usize) }
+}
 
 #[entry]
 fn main() -> ! {
+    if MEASURE_STACK {
+        unsafe { boot::paint_stack(stack_bounds().0) };
+    }
+    unsafe {
+        boot::set_event_log(core::slice::from_raw_parts_mut(EVENT_LOG as *mut u32, EVENT_LOG_WORDS));
+    }
+
+    // The hal doesn't wrap the watchdog or the RNG, so take them from the raw
+    // peripherals, before the hal claims them.
+    let (wwdt, raw_rng, raw_syscon) = unsafe {
+        let raw = hal::raw::Peripherals::steal();
+        (raw.WWDT, raw.RNG, raw.SYSCON)
+    };
+    let wdt = wwdt::Wwdt::start(wwdt, &raw_syscon, WATCHDOG_TIMEOUT_MS);
+    // Nothing here draws on it yet; it is for the hash seed of a swap's
+    // status, and the like.
+    let _entropy = rng::Rng::start(raw_rng, &raw_syscon);
+
+    #[cfg(any(feature = "semihosting", feature = "rtt"))]
+    unsafe { boot::set_logger(&logging::LOGGER) };
+
     let hal = hal::new();
 
-    hprintln!("---------- Start of code ----------");
+    info!("---------- Start of code ----------");
 
     let pins = hal::Pins::take().unwrap();
 
     let mut syscon = hal.syscon;
     let mut gpio = hal.gpio.enabled(&mut syscon);
     let mut iocon = hal.iocon.enabled(&mut syscon);
-    // let mut scb = hal.SCB;
-    // let mut cpuid = hal.CPUID;
-
-    /*
-    scb.enable_icache();
-    scb.enable_dcache(&mut cpuid);
-
-    let flash = hal.flash.release();
-    let bits = wait_done(&flash);
-    hprintln!("wait_done status: {:x}", bits);
-    hprintln!("Check 0 {:?}", read_check(&flash, 0));
-    hprintln!("Check 20000 {:?}", read_check(&flash, 0x20000));
-    hprintln!("Check 40000 {:?}", read_check(&flash, 0x40000));
-    */
-
-    /*
-    let st = flash.int_status.read();
-    if st.done().bit_is_set() {
-    hprintln!("Read done");
-} else {
-    hprintln!("Read not done");
-}
-     */
-
-    // Read the status register to make sure it works.
 
     // For now, trying to initialize the clocks again in the target locks up the
     // system.  There is probably something that needs to be fixed in the hal.
@@ -115,209 +190,227 @@ fn main() -> ! {
         .enabled(&mut syscon, clocks.support_1mhz_fro_token().unwrap());
     let mut cdriver = Timer::new(ctimer);
 
-    /*
-    for addr in [0, 0x20000, 0x40000] {
-        let (ok, elapsed) = measure(&mut cdriver, || {
-            (0..1000).map(|_| read_check(&flash, addr)).last().unwrap()
-        });
-        hprintln!("Check 0x{:x} {:?} {}us", addr, ok, elapsed);
-    }
-
-    // There is an image there, read it to get it into the cache.
-    hprintln!("@20000->{:>8x}",
-              unsafe {
-                  *(0x20000 as *const u32)
-              });
-
-    // Erase at 0x20000.
-    let (ok, elapsed) = measure(&mut cdriver, || erase(&flash, 0x20000, 512));
-    hprintln!("Erase 0x20000 {:?} {}us", ok, elapsed);
-
-    // Recheck.
-    let (ok, elapsed) = measure(&mut cdriver, || read_check(&flash, 0x20000));
-    hprintln!("Read check 0x20000 {:?} {}us", ok, elapsed);
-
-    // Program a test pattern.
-    let mut pattern = [0u8; 512];
-    for i in 0..512 {
-        pattern[i] = (i & 0xff) as u8;
-    }
-    let (ok, elapsed) = measure(&mut cdriver, || program_page(&flash, 0x20000, &pattern));
-    hprintln!("Program 0x20000 {:?} {}us", ok, elapsed);
-
-    // Invalidate the caches.
-    /*
-    scb.invalidate_icache();
-    unsafe {
-        scb.invalidate_dcache_by_address(0x20000, 512);
-    }
-    */
-
-    // Print out some, to see.
-    for i in 0..32 {
-        hprintln!("{:>08x}",
-                  unsafe {
-                      *((0x20000 + i * 4) as *const u32)
-                  }
-        );
-    }
-
-    /*
-    cdriver.start(1_000_000.microseconds());
-    let vvv = read_check(&flash, 0);
-    let now = cdriver.elapsed();
-    // hprintln!("Check 0 {:?}", read_check(&flash, 0));
-    hprintln!("Check 0 {:?} {}", vvv, now);
-    hprintln!("Check 20000 {:?}", read_check(&flash, 0x20000));
-    hprintln!("Check 40000 {:?}", read_check(&flash, 0x40000));
-    */
-    */
-
-    let mut red = pins
+    let red = pins
         .pio1_6
         .into_gpio_pin(&mut iocon, &mut gpio)
         .into_output(Level::High);
 
-    let flash = hal.flash.release();
-    let flash = flash::LpcFlash::new(flash);
-    let slot0 = flash.partition(0x20000, 0x20000).unwrap();
-
-    let slot0 = RefCell::new(slot0);
-
-    let image = Image::from_flash(&slot0).unwrap();
-    let ((), elapsed) = measure(&mut cdriver, || image.validate().unwrap());
-    hprintln!("validate: {}us", elapsed.integer());
-    chain(&image).unwrap();
-
-    loop {
-        red.set_low().unwrap();
-        hal::wait_at_least(300_000);
-        red.set_high().unwrap();
-        hal::wait_at_least(300_000);
+    let usart = hal
+        .flexcomm
+        .0
+        .enabled_as_usart(&mut syscon, &clocks.support_flexcomm_token().unwrap());
+    let tx = pins.pio0_30.into_usart0_tx_pin(&mut iocon);
+    let rx = pins.pio0_29.into_usart0_rx_pin(&mut iocon);
+    let serial = Serial::new(usart, (tx, rx), Config::default().speed(RECOVERY_BAUD.Hz()));
+    let mut port = usart::RecoveryPort::new(serial);
+
+    // Holding the user button (PIO1_9, active low) at reset requests
+    // recovery, as does pressing it, or sending ESC on the serial port, during
+    // the startup window, or the application leaving a request before
+    // resetting.
+    let button = pins
+        .pio1_9
+        .into_gpio_pin(&mut iocon, &mut gpio)
+        .into_input();
+    let window = {
+        let mut pressed = || button.is_low().unwrap();
+        let mut escape = SerialEscape(|| port.poll());
+        boot::startup_window(&mut TimerDelay(&mut cdriver), &wdt, &mut [&mut pressed, &mut escape])
+    };
+    let mut request = unsafe { RetainedWord::new(BOOT_REQUEST) };
+    let requested = boot::take_recovery_request(&mut request);
+    let want_recovery = window == Window::Recovery || requested;
+
+    if let Err(name) = partitions::check() {
+        error!("Partition table is invalid: {}", name);
+        panic!("Invalid partition table");
     }
-}
 
-fn measure<T, TT: Ctimer<Enabled>, F: FnOnce() -> T>(timer: &mut Timer<TT>, action: F) -> (T, Microseconds) {
-    timer.start(1_000_000.microseconds());
-    let before = timer.elapsed();
-    let result = action();
-    let after = timer.elapsed();
-    (result, after - before)
-}
-
-/*
-// Try putting some code into RAM, and see if we can execute it there. In this
-// case, we want to try accessing hardware.
-#[inline(never)]
-#[link_section = ".data.flash"]
-fn wait_done(flash: &FLASH) -> u32 {
-    while flash.int_status.read().done().bit_is_clear() {
-    }
-    let bits = flash.int_status.read().bits();
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
-    bits
-}
-*/
-
-/*
-/// Determine if a page has been programmed. If this returns true, it is likely
-/// that reads from that page will not result in bus faults.
-#[inline(never)]
-//#[link_section = ".data.flash"]
-fn read_check(flash: &FLASH, addr: u32) -> bool {
-    // Wait for anything to complete, and clear status.
-    /*
-    while flash.int_status.read().done().bit_is_clear() {
+    let flash = hal.flash.release();
+    let flash = if PRINCE_SLOT0 {
+        let mut prince = prince::Prince::new(hal.prince.enabled(&mut syscon).release());
+        prince.configure(&prince::RegionConfig {
+            region: 0,
+            base: SLOT0.base,
+            length: SLOT0.size,
+            iv: PRINCE_IV,
+        }).unwrap();
+        flash::LpcFlash::with_prince(flash, prince)
+    } else {
+        flash::LpcFlash::new(flash)
+    };
+    if let Some(region) = flash.prince_region(SLOT0.base..SLOT0.base + SLOT0.size) {
+        info!("slot0 encrypted: 0x{:x}..0x{:x}", region.start, region.end);
     }
-    */
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
 
-    flash.starta.write(|w| unsafe{w.bits(addr >> 4)});
-    flash.stopa.write(|w| unsafe{w.bits(addr >> 4)});
-    flash.cmd.write(|w| unsafe{w.bits(6)});
-    while flash.int_status.read().done().bit_is_clear() {
+    // The slots are regions of the one device.
+    let flash = RefCell::new(flash);
+    let slot0 = Slot::new(&flash, SLOT0.base, SLOT0.size, SlotPurpose::Primary).unwrap();
+    let mut slot1 = Slot::new(&flash, SLOT1.base, SLOT1.size, SlotPurpose::Secondary).unwrap();
+    if BENCH_FLASH {
+        bench_flash(&mut slot1, &mut cdriver);
     }
 
-    let good = flash.int_status.read().fail().bit_is_clear();
+    // Feed the watchdog on every flash operation, and check each write.
+    let slot0 = RefCell::new(VerifiedFlash::new(WatchedFlash::new(slot0, &wdt), VERIFY_WRITES));
+    let mut slot1 = VerifiedFlash::new(WatchedFlash::new(slot1, &wdt), VERIFY_WRITES);
+    let core1 = Slot::new(&flash, CORE1.base, CORE1.size, SlotPurpose::Primary).unwrap();
+    let core1 = RefCell::new(VerifiedFlash::new(WatchedFlash::new(core1, &wdt), VERIFY_WRITES));
+
+    let hashcrypt = hashcrypt::LpcHashCrypt::new(hal.hashcrypt.enabled(&mut syscon).release());
+    let casper = casper::Casper::new(hal.casper.enabled(&mut syscon).release());
+    let mut crypto = crypto::LpcCrypto::new(hashcrypt, casper);
+
+    // Images must be signed by the key whose hash is provisioned into the
+    // CMPA.  Refuse to boot anything on an unprovisioned device.
+    let key_hash = match cmpa::CmpaKeyStore.key_hash().unwrap() {
+        Some(hash) => hash,
+        None => {
+            error!("Device is not provisioned with a key hash");
+            loop {
+                cortex_m::asm::wfi();
+            }
+        }
+    };
+
+    let valid = match Image::from_flash(&slot0) {
+        Ok(image) => {
+            let (result, elapsed) = measure(&mut cdriver, || image.validate_key_hash(&mut crypto, &key_hash));
+            info!("validate: {}us", elapsed.integer());
+            boot::event(EventCode::ValidationMs, elapsed.integer() / 1000);
+            result.is_ok()
+        }
+        Err(_) => false,
+    };
+
+    if want_recovery || !valid {
+        if RECOVERY_USB {
+            info!("Entering USB DFU recovery");
+            let vbus = pins.pio0_22.into_usb0_vbus_pin(&mut iocon);
+            let usbfs = hal.usbfs.enabled_as_device(
+                &mut anactrl,
+                &mut pmc,
+                &mut syscon,
+                &mut cdriver,
+                clocks.support_usbfs_token().unwrap(),
+            );
+            let usb_bus = UsbBus::new(usbfs, vbus);
+            let mut dfu = DFUClass::new(&usb_bus, dfu::DfuSlot::new(slot1, &mut crypto, &key_hash));
+            let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1209, 0x0001))
+                .manufacturer("mcuboot-rs")
+                .product("LPC55S69 recovery")
+                .serial_number("0")
+                .build();
+
+            // A successful download resets from within the DFU class.
+            loop {
+                wdt.feed();
+                usb_dev.poll(&mut [&mut dfu]);
+            }
+        }
 
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+        info!("Entering serial recovery");
+        recovery::run(&mut port, &mut [&mut *slot0.borrow_mut(), &mut slot1]);
 
-    good
-}
-*/
-
-/*
-/// Erase a range.
-fn erase(flash: &FLASH, base: u32, length: u32) -> bool {
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
-    if length % 512 != 0 {
-        return false;
-    }
-    let ending = base + length - 511;
-    flash.starta.write(|w| unsafe{w.bits(base >> 4)});
-    flash.stopa.write(|w| unsafe{w.bits(ending >> 4)});
-    flash.cmd.write(|w| unsafe{w.bits(4)});
-    while flash.int_status.read().done().bit_is_clear() {
+        // Start over, and validate whatever was uploaded.
+        cortex_m::peripheral::SCB::sys_reset();
     }
 
-    let good = flash.int_status.read().fail().bit_is_clear();
+    // The second core's image is optional.  If it isn't valid, the main image
+    // still boots, with the core left in reset.
+    let core1_base = match Image::from_flash(&core1) {
+        Ok(image) => match image.validate_key_hash(&mut crypto, &key_hash).and_then(|()| image.get_image_base()) {
+            Ok(base) => Some(base),
+            Err(_) => {
+                error!("Core 1 image is invalid");
+                None
+            }
+        },
+        Err(_) => None,
+    };
 
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
+    let image = Image::from_flash(&slot0).unwrap();
+    // Put the hardware back the way we found it.
+    drop(red);
+    teardown::teardown(&mut syscon, teardown::Used {
+        timer: cdriver,
+        gpio,
+        iocon,
+    });
+
+    if let Some(base) = core1_base {
+        cpu1::release(base);
+    }
 
-    good
-}
-*/
-
-/*
-/// Program a single page.
-fn program_page(flash: &FLASH, base: u32, page: &[u8]) -> bool {
-    flash.int_clr_status.write(|w| w.done().set_bit().err().set_bit().fail().set_bit().ecc_err().set_bit());
-    if page.len() != 512 {
-        return false;
+    if MEASURE_STACK {
+        let (bottom, top) = stack_bounds();
+        let used = unsafe { boot::stack_used(bottom, top) };
+        info!("Stack used: {} of {} bytes", used, top - bottom);
     }
-    for word in 0..32 {
-        flash.starta.write(|w| unsafe{w.bits(word)});
-        for column in 0..4 {
-            let base = ((word << 4) + (column << 2)) as usize;
-            let cell = LittleEndian::read_u32(&page[base..base+4]);
-            flash.dataw[column as usize].write(|w| unsafe{w.bits(cell)});
-        }
-        flash.cmd.write(|w| unsafe{w.bits(8)});
-        while flash.int_status.read().done().bit_is_clear() {
-        }
-        let good = flash.int_status.read().fail().bit_is_clear();
-        if !good {
-            return false;
+
+    // Leave nothing of the hashes behind for the application.
+    crypto.scrub();
+
+    // The watchdog is not fed from here on.  The application must feed it, or
+    // it will reset back into the bootloader.
+    let Err(e) = unsafe {
+        if MPU_PROTECT {
+            let bootloader = Region::new(BOOTLOADER.base..BOOTLOADER.end(), Access::ReadOnly);
+            if let Err(e) = boot::protect(&mut boot::Pmsav8, &[bootloader]) {
+                panic!("Unable to program the MPU: {:?}", e);
+            }
+            boot::chain_scrubbed(&boot::ProtectedCortexM, &image)
+        } else if SCRUB_RAM {
+            boot::chain_clearing(&boot::CortexM, &image, RAM)
+        } else {
+            boot::chain_scrubbed(&boot::CortexM, &image)
         }
-    }
+    };
+    panic!("Unable to chain: {:?}", e)
+}
 
-    flash.starta.write(|w| unsafe{w.bits(base >> 4)});
-    flash.cmd.write(|w| unsafe{w.bits(12)});
-    while flash.int_status.read().done().bit_is_clear() {
+/// The startup window's delay, on the ctimer.
+struct TimerDelay<'a, TT: Ctimer<Enabled>>(&'a mut Timer<TT>);
+
+impl<'a, TT: Ctimer<Enabled>> Delay for TimerDelay<'a, TT> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.0.start((ms * 1000).microseconds());
+        let _ = nb::block!(self.0.wait());
     }
-    let good = flash.int_status.read().fail().bit_is_clear();
-    good
 }
-*/
-
-// For debugging.
-// TODO: Handle the storage error, converting to a better error.
-#[inline(never)]
-pub fn chain<'f, F: MappedFlash>(image: &Image<'f, F>) -> Result<(), flash::Error> {
-    // Chain the next image, assuming the image has been validated.
 
-    let reset_base = image.get_image_base();
-    // let reset = unsafe {&*(reset_base as *const ResetVector)};
-    // hprintln!("chain {}", reset);
-    unsafe {
-        #[allow(unused_mut)]
-        let mut p = cortex_m::Peripherals::steal();
-        p.SCB.vtor.write(reset_base as u32);
+fn measure<T, TT: Ctimer<Enabled>, F: FnOnce() -> T>(timer: &mut Timer<TT>, action: F) -> (T, Microseconds) {
+    timer.start(1_000_000.microseconds());
+    let before = timer.elapsed();
+    let result = action();
+    let after = timer.elapsed();
+    (result, after - before)
+}
 
-        cortex_m::asm::bootload(reset_base as *const u32);
+/// Program `BENCH_PAGES` pages at the start of `slot`, first one write per
+/// page, then as a single write, which the driver batches.  Swap throughput
+/// is dominated by this.  The pages are left erased.
+fn bench_flash<TT: Ctimer<Enabled>>(slot: &mut Slot<'_, flash::LpcFlash>, timer: &mut Timer<TT>) {
+    let mut pattern = [0u8; BENCH_PAGES * flash::LPC_PAGE_SIZE];
+    for (i, byte) in pattern.iter_mut().enumerate() {
+        *byte = i as u8;
     }
-
-    // Ok(())
+    let len = pattern.len();
+
+    let _ = slot.erase(0, len);
+    let (ok, single) = measure(timer, || {
+        pattern
+            .chunks_exact(flash::LPC_PAGE_SIZE)
+            .enumerate()
+            .try_for_each(|(i, page)| slot.write(i * flash::LPC_PAGE_SIZE, page))
+    });
+    info!("Program {} pages singly: {:?} {}us", BENCH_PAGES, ok, single.integer());
+
+    let _ = slot.erase(0, len);
+    let (ok, batched) = measure(timer, || slot.write(0, &pattern));
+    info!("Program {} pages batched: {:?} {}us", BENCH_PAGES, ok, batched.integer());
+
+    let _ = slot.erase(0, len);
 }
 
 // TODO: We don't really want to just read this directly, as it will fault if no
@@ -0,0 +1,63 @@
+//! Flash partition table.
+//!
+//! The whole flash layout of the board is described here, rather than spread
+//! through the code as literals.  Outside of this crate, the hello
+//! application's `memory.x` and the `--slot-size` it is signed with must agree
+//! with the primary slot, and this crate's `memory.x` must keep the bootloader
+//! within its partition.  `check` verifies what it can at startup.
+
+use crate::flash::{LPC_FLASH_SIZE, LPC_PAGE_SIZE};
+
+pub struct Partition {
+    pub name: &'static str,
+    pub base: usize,
+    pub size: usize,
+}
+
+impl Partition {
+    pub const fn end(&self) -> usize {
+        self.base + self.size
+    }
+}
+
+pub const BOOTLOADER: Partition = Partition { name: "bootloader", base: 0x00000, size: 0x20000 };
+pub const SLOT0: Partition = Partition { name: "slot0", base: 0x20000, size: 0x20000 };
+pub const SLOT1: Partition = Partition { name: "slot1", base: 0x40000, size: 0x20000 };
+pub const CORE1: Partition = Partition { name: "core1", base: 0x60000, size: 0x20000 };
+
+/// All partitions, in address order.
+pub const ALL: [&Partition; 4] = [&BOOTLOADER, &SLOT0, &SLOT1, &CORE1];
+
+extern "C" {
+    // Provided by the cortex-m-rt linker script.
+    static __sidata: u32;
+    static __sdata: u32;
+    static __edata: u32;
+}
+
+/// Check that the partitions are page aligned, in order, don't overlap, and
+/// fit in the flash, and that the bootloader itself fits in its partition.
+pub fn check() -> Result<(), &'static str> {
+    let mut prev_end = 0;
+    for part in ALL {
+        if !part.base.is_multiple_of(LPC_PAGE_SIZE) || !part.size.is_multiple_of(LPC_PAGE_SIZE) {
+            return Err(part.name);
+        }
+        if part.size == 0 || part.base < prev_end || part.end() > LPC_FLASH_SIZE {
+            return Err(part.name);
+        }
+        prev_end = part.end();
+    }
+
+    // The initialized data is the last thing placed in flash.
+    let image_end = unsafe {
+        let sidata = &__sidata as *const u32 as usize;
+        let sdata = &__sdata as *const u32 as usize;
+        let edata = &__edata as *const u32 as usize;
+        sidata + (edata - sdata)
+    };
+    if image_end > BOOTLOADER.end() {
+        return Err(BOOTLOADER.name);
+    }
+    Ok(())
+}
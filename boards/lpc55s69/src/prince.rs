@@ -0,0 +1,129 @@
+//! LPC55S6x PRINCE on-the-fly encryption.
+//!
+//! PRINCE transparently decrypts reads from up to three regions of internal
+//! flash, allowing the primary slot to hold an image that is only ever stored
+//! encrypted.  Each region is enabled in 8 KB subregions.
+//!
+//! The keys are never visible to software: they are delivered from the PUF
+//! over the hardware key bus, which is set up by the ROM.  The bootloader is
+//! responsible for the base address, subregion enables, and IVs of each region.
+//!
+//! Writes into an encrypted region must be made with encryption enabled in
+//! the PRINCE engine, so the flash driver asks this module whether a page is
+//! within a region before programming it.
+
+use core::ops::Range;
+
+use lpc55_hal as hal;
+
+/// Number of PRINCE regions.
+pub const REGIONS: usize = 3;
+
+/// Regions are enabled in units of this many bytes.
+pub const SUBREGION_SIZE: usize = 8 * 1024;
+
+/// Each region has 32 subregion enable bits.
+const MAX_SUBREGIONS: usize = 32;
+
+/// Size of the address space covered by one region.
+const REGION_SIZE: usize = SUBREGION_SIZE * MAX_SUBREGIONS;
+
+/// Configuration of a single region.
+pub struct RegionConfig {
+    /// Which region (0-2) to use.
+    pub region: usize,
+    /// Flash address of the start of the encrypted area.  Must be aligned to
+    /// a subregion.
+    pub base: usize,
+    /// Length of the encrypted area, rounded up to a subregion.  The area must
+    /// not cross a 256 KB boundary.
+    pub length: usize,
+    /// The 64-bit IV for this region.
+    pub iv: u64,
+}
+
+pub struct Prince {
+    raw: hal::raw::PRINCE,
+}
+
+impl Prince {
+    pub fn new(raw: hal::raw::PRINCE) -> Prince {
+        Prince { raw }
+    }
+
+    /// Set up a region for decryption.  This is done during boot, before
+    /// anything reads the encrypted slot.
+    pub fn configure(&mut self, config: &RegionConfig) -> Option<()> {
+        if config.region >= REGIONS || config.base % SUBREGION_SIZE != 0 {
+            return None;
+        }
+        let region_base = config.base & !(REGION_SIZE - 1);
+        let first = (config.base - region_base) / SUBREGION_SIZE;
+        let subregions = config.length.div_ceil(SUBREGION_SIZE);
+        if subregions == 0 || first + subregions > MAX_SUBREGIONS {
+            return None;
+        }
+        let enables = if subregions == MAX_SUBREGIONS {
+            !0
+        } else {
+            ((1u32 << subregions) - 1) << first
+        };
+        let base = region_base as u32;
+        let iv_lsb = config.iv as u32;
+        let iv_msb = (config.iv >> 32) as u32;
+
+        let raw = &self.raw;
+        match config.region {
+            0 => {
+                raw.base_addr0.write(|w| unsafe { w.bits(base) });
+                raw.iv_lsb0.write(|w| unsafe { w.bits(iv_lsb) });
+                raw.iv_msb0.write(|w| unsafe { w.bits(iv_msb) });
+                raw.sr_enable0.write(|w| unsafe { w.bits(enables) });
+            }
+            1 => {
+                raw.base_addr1.write(|w| unsafe { w.bits(base) });
+                raw.iv_lsb1.write(|w| unsafe { w.bits(iv_lsb) });
+                raw.iv_msb1.write(|w| unsafe { w.bits(iv_msb) });
+                raw.sr_enable1.write(|w| unsafe { w.bits(enables) });
+            }
+            _ => {
+                raw.base_addr2.write(|w| unsafe { w.bits(base) });
+                raw.iv_lsb2.write(|w| unsafe { w.bits(iv_lsb) });
+                raw.iv_msb2.write(|w| unsafe { w.bits(iv_msb) });
+                raw.sr_enable2.write(|w| unsafe { w.bits(enables) });
+            }
+        }
+        Some(())
+    }
+
+    /// Return the encrypted address range of the given region, if it has any
+    /// subregions enabled.  The enabled subregions are assumed to be
+    /// contiguous.
+    pub fn region(&self, region: usize) -> Option<Range<usize>> {
+        let (base, enables) = match region {
+            0 => (self.raw.base_addr0.read().bits(), self.raw.sr_enable0.read().bits()),
+            1 => (self.raw.base_addr1.read().bits(), self.raw.sr_enable1.read().bits()),
+            2 => (self.raw.base_addr2.read().bits(), self.raw.sr_enable2.read().bits()),
+            _ => return None,
+        };
+        if enables == 0 {
+            return None;
+        }
+        let first = enables.trailing_zeros() as usize;
+        let last = (32 - enables.leading_zeros()) as usize;
+        let base = base as usize;
+        Some(base + first * SUBREGION_SIZE .. base + last * SUBREGION_SIZE)
+    }
+
+    /// Is the given flash address within an encrypted region.
+    pub fn is_encrypted(&self, addr: usize) -> bool {
+        (0..REGIONS).any(|r| self.region(r).map_or(false, |range| range.contains(&addr)))
+    }
+
+    /// Enable or disable encryption of data written through the flash
+    /// controller.  This must be enabled only while programming pages within
+    /// an encrypted region.
+    pub fn set_write_encryption(&self, enable: bool) {
+        self.raw.enc_enable.write(|w| unsafe { w.bits(enable as u32) });
+    }
+}
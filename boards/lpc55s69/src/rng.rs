@@ -0,0 +1,100 @@
+//! LPC55S6x random number generator.
+//!
+//! The RNG accumulates entropy from ring oscillators into its output
+//! register.  A word is only fresh once the refresh counter has reached 31
+//! since the last read.  The RNG also keeps a chi-squared test of its input.
+//! If that test goes over 4, more entropy is gathered for each word, by
+//! raising SHIFT4X.  When that can't be raised further, the read fails.
+//! This follows the sequence in the user manual, and NXP's driver.
+//!
+//! The hal doesn't wrap the RNG, so this uses the raw peripheral.
+
+use boot::{EntropyError, EntropySource};
+use lpc55_hal as hal;
+
+// Clock control for the RNG in AHBCLKCTRL2.
+const AHBCLKCTRL2_RNG: u32 = 1 << 13;
+
+// COUNTER_VAL: the refresh counter, full once 32 bits have been gathered.
+const REFRESH_CNT_SHIFT: u32 = 8;
+const REFRESH_CNT_MASK: u32 = 0x1f;
+const REFRESH_FULL: u32 = 31;
+
+// COUNTER_CFG: counting mode, the clock the statistics are on, and the shift.
+const CFG_MODE_MASK: u32 = 0x3;
+const CFG_MODE_UPDATE: u32 = 1;
+const CFG_CLOCK_SEL_SHIFT: u32 = 2;
+const CFG_CLOCK_SEL_MASK: u32 = 0x7 << CFG_CLOCK_SEL_SHIFT;
+const CFG_CLOCK_SEL: u32 = 4;
+const CFG_SHIFT4X_SHIFT: u32 = 5;
+const CFG_SHIFT4X_MASK: u32 = 0x7 << CFG_SHIFT4X_SHIFT;
+const SHIFT4X_MAX: u32 = 7;
+
+// ONLINE_TEST_CFG.
+const TEST_ACTIVATE: u32 = 1 << 0;
+
+// ONLINE_TEST_VAL: the largest chi-squared seen.
+const MAX_CHI_SHIFT: u32 = 16;
+const MAX_CHI_MASK: u32 = 0xf;
+const MAX_CHI_GOOD: u32 = 4;
+
+/// Polls of the refresh counter for each word before giving up.
+const REFRESH_POLLS: u32 = 1_000_000;
+
+pub struct Rng {
+    raw: hal::raw::RNG,
+}
+
+impl Rng {
+    /// Enable the RNG clock, and start the statistics.
+    pub fn start(raw: hal::raw::RNG, syscon: &hal::raw::SYSCON) -> Rng {
+        syscon.ahbclkctrlset2.write(|w| unsafe { w.bits(AHBCLKCTRL2_RNG) });
+        raw.counter_cfg.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(CFG_MODE_MASK | CFG_CLOCK_SEL_MASK)) |
+                   CFG_MODE_UPDATE | (CFG_CLOCK_SEL << CFG_CLOCK_SEL_SHIFT))
+        });
+        raw.online_test_cfg.write(|w| unsafe { w.bits(TEST_ACTIVATE) });
+        Rng { raw }
+    }
+
+    fn word(&mut self) -> Result<u32, EntropyError> {
+        loop {
+            let mut polls = 0;
+            while (self.raw.counter_val.read().bits() >> REFRESH_CNT_SHIFT) & REFRESH_CNT_MASK < REFRESH_FULL {
+                polls += 1;
+                if polls == REFRESH_POLLS {
+                    return Err(EntropyError::Timeout);
+                }
+            }
+            // Reading the word starts the refresh count again.
+            let word = self.raw.random_number.read().bits();
+
+            let max_chi = (self.raw.online_test_val.read().bits() >> MAX_CHI_SHIFT) & MAX_CHI_MASK;
+            if max_chi <= MAX_CHI_GOOD {
+                return Ok(word);
+            }
+
+            // Gather more for each word, and discard this one.
+            let cfg = self.raw.counter_cfg.read().bits();
+            let shift = (cfg & CFG_SHIFT4X_MASK) >> CFG_SHIFT4X_SHIFT;
+            if shift == SHIFT4X_MAX {
+                return Err(EntropyError::Health);
+            }
+            self.raw.online_test_cfg.write(|w| unsafe { w.bits(0) });
+            self.raw.counter_cfg.write(|w| unsafe {
+                w.bits((cfg & !CFG_SHIFT4X_MASK) | ((shift + 1) << CFG_SHIFT4X_SHIFT))
+            });
+            self.raw.online_test_cfg.write(|w| unsafe { w.bits(TEST_ACTIVATE) });
+        }
+    }
+}
+
+impl EntropySource for Rng {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        for chunk in buf.chunks_mut(4) {
+            let word = self.word()?.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
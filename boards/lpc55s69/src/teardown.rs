@@ -0,0 +1,62 @@
+//! Peripheral de-initialization before chaining.
+//!
+//! The application should start with the hardware in a well defined state,
+//! not with whatever the bootloader happened to leave configured.  Everything
+//! the bootloader enabled is disabled and put back through reset here, just
+//! before the jump.
+//!
+//! The watchdog is the one exception, as it is intended to keep running into
+//! the application.
+
+use hal::{
+    drivers::Timer,
+    peripherals::ctimer::Ctimer1,
+    typestates::init_state::Enabled,
+    Gpio, Iocon, Syscon,
+};
+use lpc55_hal as hal;
+
+/// Revert the clock tree to the FRO as well.  The PLL setup in the hal can
+/// currently only be done once, so an application that configures its own
+/// clocks needs this.
+pub const REVERT_CLOCKS: bool = true;
+
+// Main clock selection values for the 12 MHz FRO.
+const MAINCLKSELA_FRO12M: u32 = 0;
+const MAINCLKSELB_MAINCLKSELA: u32 = 0;
+
+/// The peripherals the bootloader has used.
+pub struct Used {
+    pub timer: Timer<Ctimer1<Enabled>>,
+    pub gpio: Gpio<Enabled>,
+    pub iocon: Iocon<Enabled>,
+}
+
+/// Return everything to reset state.
+pub fn teardown(syscon: &mut Syscon, used: Used) {
+    let mut timer = used.timer.release().disabled(syscon).release();
+    syscon.reset(&mut timer);
+
+    let mut gpio = used.gpio.disabled(syscon).release();
+    syscon.reset(&mut gpio);
+
+    let mut iocon = used.iocon.disabled(syscon).release();
+    syscon.reset(&mut iocon);
+
+    if REVERT_CLOCKS {
+        revert_clocks();
+    }
+}
+
+/// Switch the main clock back to the 12 MHz FRO, with no divider, and power
+/// down the PLL.
+fn revert_clocks() {
+    let (syscon, pmc) = unsafe {
+        let raw = hal::raw::Peripherals::steal();
+        (raw.SYSCON, raw.PMC)
+    };
+    syscon.mainclksela.write(|w| unsafe { w.bits(MAINCLKSELA_FRO12M) });
+    syscon.mainclkselb.write(|w| unsafe { w.bits(MAINCLKSELB_MAINCLKSELA) });
+    syscon.ahbclkdiv.write(|w| unsafe { w.bits(0) });
+    pmc.pdruncfgset0.write(|w| w.pden_pll0().set_bit());
+}
@@ -0,0 +1,37 @@
+//! Serial recovery over a USART.
+//!
+//! Adapts an embedded-hal serial port to the byte interface used by the
+//! recovery protocol.
+
+use boot::recovery::Serial;
+use embedded_hal::serial::{Read, Write};
+
+pub struct RecoveryPort<S>(S);
+
+impl<S> RecoveryPort<S> {
+    pub fn new(serial: S) -> RecoveryPort<S> {
+        RecoveryPort(serial)
+    }
+}
+
+impl<S: Read<u8>> RecoveryPort<S> {
+    /// A received byte, if there is one, without waiting.
+    pub fn poll(&mut self) -> Option<u8> {
+        self.0.read().ok()
+    }
+}
+
+impl<S: Read<u8> + Write<u8>> Serial for RecoveryPort<S> {
+    fn read(&mut self) -> Option<u8> {
+        nb::block!(self.0.read()).ok()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // There is nothing useful to do with a transmit error, the host will
+        // time out and retry.
+        for &byte in bytes {
+            let _ = nb::block!(self.0.write(byte));
+        }
+        let _ = nb::block!(self.0.flush());
+    }
+}
@@ -0,0 +1,59 @@
+//! LPC55S6x windowed watchdog.
+//!
+//! The watchdog is started early in boot, and fed by the flash driver (through
+//! `boot::WatchedFlash`) during validation and flash operations.  It is
+//! deliberately left running, and no longer fed, when chaining to the
+//! application.  The application must take over feeding it, and a hung
+//! application will reset back into the bootloader.
+//!
+//! The hal doesn't wrap the WWDT, so this uses the raw peripheral.
+
+use boot::Watchdog;
+use lpc55_hal as hal;
+
+/// The watchdog counts a 1 MHz FRO, divided by the fixed prescaler of 4.
+const WDT_TICKS_PER_MS: u32 = 1_000 / 4;
+
+// MOD register.
+const MOD_WDEN: u32 = 1 << 0;
+const MOD_WDRESET: u32 = 1 << 1;
+
+// The timer constant is 24 bits.
+const TC_MAX: u32 = 0xff_ffff;
+
+// Clock control for the WWDT in AHBCLKCTRL0.
+const AHBCLKCTRL0_WWDT: u32 = 1 << 22;
+
+pub struct Wwdt {
+    raw: hal::raw::WWDT,
+}
+
+impl Wwdt {
+    /// Enable the watchdog clock, and start the watchdog with a timeout of
+    /// roughly `timeout_ms`.  Once started, it cannot be stopped.
+    pub fn start(raw: hal::raw::WWDT, syscon: &hal::raw::SYSCON, timeout_ms: u32) -> Wwdt {
+        syscon.ahbclkctrlset0.write(|w| unsafe { w.bits(AHBCLKCTRL0_WWDT) });
+        // Run the watchdog clock undivided.
+        syscon.wdtclkdiv.write(|w| unsafe { w.bits(0) });
+
+        let ticks = timeout_ms.saturating_mul(WDT_TICKS_PER_MS).min(TC_MAX);
+        raw.tc.write(|w| unsafe { w.bits(ticks) });
+        raw.mod_.write(|w| unsafe { w.bits(MOD_WDEN | MOD_WDRESET) });
+
+        let wdt = Wwdt { raw };
+        // The watchdog doesn't start counting until the first feed.
+        wdt.feed();
+        wdt
+    }
+}
+
+impl Watchdog for Wwdt {
+    fn feed(&self) {
+        // The feed sequence must not be interrupted by another access to the
+        // watchdog.
+        cortex_m::interrupt::free(|_| {
+            self.raw.feed.write(|w| unsafe { w.bits(0xaa) });
+            self.raw.feed.write(|w| unsafe { w.bits(0x55) });
+        });
+    }
+}
@@ -0,0 +1,12 @@
+/* nRF5340 application core.  The layout must agree with src/main.rs.
+   0x00000 - 64k  - bootloader
+   0x10000 - 704k - slot 0 (application core)
+   0xc0000 - 256k - network core image
+*/
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 64K
+  /* The last 16 bytes of RAM hold the command for the network core's
+     bootloader, see src/pcd.rs. */
+  RAM : ORIGIN = 0x20000000, LENGTH = 512K - 16
+}
@@ -0,0 +1,134 @@
+//! nRF5340 application core flash driver.
+//!
+//! The internal flash is 1 MB, memory mapped from address 0, and driven by
+//! the NVMC.  It is written a 32-bit word at a time, and erased in 4 KB pages.
+//! A page is erased by writing all ones to any word in it, with erasing
+//! enabled.
+//!
+//! To use this driver, give it the secure NVMC from the PAC.
+//!
+//!     let flash = flash::NrfFlash::new(p.NVMC_S);
+//!     let slot0 = flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap();
+
+use core::cell::RefCell;
+
+use boot::MappedFlash;
+use nrf5340_app_pac as pac;
+use storage::{Error, Flash, ReadFlash, Result};
+
+pub const FLASH_SIZE: usize = 1024 * 1024;
+pub const PAGE_SIZE: usize = 4096;
+pub const WORD_SIZE: usize = 4;
+
+pub struct NrfFlash {
+    nvmc: RefCell<pac::NVMC_S>,
+}
+
+impl NrfFlash {
+    pub fn new(nvmc: pac::NVMC_S) -> NrfFlash {
+        NrfFlash { nvmc: RefCell::new(nvmc) }
+    }
+
+    /// Build a partition, given its offset from the start of flash.
+    pub fn partition(&self, base: usize, length: usize) -> Result<NrfPartition<'_>> {
+        if length == 0 || base > FLASH_SIZE || length > FLASH_SIZE - base {
+            return Err(Error::OutOfBounds);
+        }
+        if !base.is_multiple_of(PAGE_SIZE) || !length.is_multiple_of(PAGE_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        Ok(NrfPartition { nvmc: &self.nvmc, base, length })
+    }
+}
+
+pub struct NrfPartition<'a> {
+    nvmc: &'a RefCell<pac::NVMC_S>,
+    base: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for NrfPartition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let slice = unsafe {
+            core::slice::from_raw_parts((self.base + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(self.base)
+    }
+}
+
+impl<'a> Flash for NrfPartition<'a> {
+    fn write_size(&self) -> usize {
+        WORD_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        let nvmc = self.nvmc.borrow();
+        nvmc.config.write(|w| w.wen().een());
+        for page in (from..to).step_by(PAGE_SIZE) {
+            let addr = (self.base + page) as *mut u32;
+            unsafe { core::ptr::write_volatile(addr, 0xffff_ffff) };
+            wait_ready(&nvmc);
+        }
+        nvmc.config.write(|w| w.wen().ren());
+        verify_erased(self.base + from, to - from)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+        let nvmc = self.nvmc.borrow();
+        nvmc.config.write(|w| w.wen().wen());
+        let base = self.base + offset;
+        for (i, word) in bytes.chunks_exact(WORD_SIZE).enumerate() {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            let addr = (base + i * WORD_SIZE) as *mut u32;
+            unsafe { core::ptr::write_volatile(addr, word) };
+            wait_ready(&nvmc);
+        }
+        nvmc.config.write(|w| w.wen().ren());
+
+        let written = unsafe { core::slice::from_raw_parts(base as *const u8, bytes.len()) };
+        if written != bytes {
+            return Err(Error::Failed);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> MappedFlash for NrfPartition<'a> {
+    fn get_base(&self) -> usize {
+        self.base
+    }
+}
+
+fn wait_ready(nvmc: &pac::NVMC_S) {
+    while nvmc.ready.read().ready().is_busy() {
+    }
+}
+
+/// The NVMC doesn't report failures, so check that an erase took.
+fn verify_erased(addr: usize, len: usize) -> Result<()> {
+    let words = unsafe { core::slice::from_raw_parts(addr as *const u32, len / WORD_SIZE) };
+    if words.iter().any(|&w| w != 0xffff_ffff) {
+        return Err(Error::Failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+#![no_main]
+#![no_std]
+
+use panic_probe as _;
+use defmt_rtt as _;
+use defmt::{error, info, warn, Debug2Format};
+
+use core::cell::RefCell;
+
+use boot::{upgrade_requested, Image};
+use nrf5340_app_pac as pac;
+use storage::{Flash, ReadFlash};
+
+mod flash;
+mod netcore;
+
+// Partitions, as offsets from the start of flash.  These must agree with
+// memory.x.
+const SLOT0_BASE: usize = 0x1_0000;
+const SLOT0_SIZE: usize = 0xb_0000;
+const NET_BASE: usize = 0xc_0000;
+const NET_SIZE: usize = 0x4_0000;
+
+/// The key images must be signed with.
+static SIGNING_KEY: &[u8] = include_bytes!("../../../boot/data/ecdsa-p256-pub.der");
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let p = pac::Peripherals::take().unwrap();
+
+    info!("---------- Start of bootloader ----------");
+
+    // The network core starts out held in reset, and stays there until its
+    // image has been dealt with.
+    netcore::hold();
+    netcore::clear();
+
+    let flash = flash::NrfFlash::new(p.NVMC_S);
+    let mut crypto = boot::SoftCrypto::new();
+
+    let slot0 = RefCell::new(flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap());
+    let net_slot = RefCell::new(flash.partition(NET_BASE, NET_SIZE).unwrap());
+
+    let image = match Image::from_flash(&slot0) {
+        Ok(image) => image,
+        Err(e) => halt("No image in slot0", e),
+    };
+    info!("Image found, {} bytes", image.full_image_size());
+
+    if let Err(e) = image.validate_signed(&mut crypto, SIGNING_KEY) {
+        halt("Image is invalid", e);
+    }
+    info!("Image is valid");
+
+    // The network core image is only needed when it is to be installed.  The
+    // two images are validated as a set, and either may depend on the other.
+    let pending = upgrade_requested(&mut *net_slot.borrow_mut()).unwrap_or(false);
+    let net_image = if pending {
+        match Image::from_flash(&net_slot) {
+            Ok(net_image) => match net_image.validate_signed(&mut crypto, SIGNING_KEY) {
+                Ok(()) => Some(net_image),
+                Err(e) => {
+                    warn!("Network core image is invalid: {}", Debug2Format(&e));
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("No network core image: {}", Debug2Format(&e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let net_image = match net_image {
+        Some(net_image) => {
+            let versions = [image.version(), net_image.version()];
+            if let Err(e) = image.check_dependencies(&versions) {
+                halt("Application image dependencies not met", e);
+            }
+            match net_image.check_dependencies(&versions) {
+                Ok(()) => Some(net_image),
+                Err(_) => {
+                    warn!("Network core image dependencies not met, not installing");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    match net_image {
+        Some(net_image) => {
+            let size = net_image.full_image_size();
+            drop(net_image);
+            info!("Installing network core image, {} bytes", size);
+            if netcore::install(NET_BASE, size) {
+                info!("Network core image installed");
+                // Clear the request, so it isn't installed again.
+                let mut net_slot = net_slot.borrow_mut();
+                let capacity = net_slot.capacity();
+                let page = net_slot.erase_size();
+                if let Err(e) = net_slot.erase(capacity - page, capacity) {
+                    warn!("Unable to clear the request: {}", Debug2Format(&e));
+                }
+            } else {
+                warn!("Network core install failed");
+            }
+        }
+        None => netcore::release(),
+    }
+
+    let base = image.get_image_base().unwrap_or_else(|e| halt("Image is misaligned", e));
+    info!("Chaining to 0x{:x}", base);
+    let Err(e) = unsafe { boot::chain(&boot::CortexM, &image) };
+    halt("Image is misaligned", e)
+}
+
+/// Report a failure to boot, and stop.
+fn halt(message: &str, e: boot::Error) -> ! {
+    error!("{}: {}", message, Debug2Format(&e));
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,81 @@
+//! The nRF5340 network core.
+//!
+//! The network core has its own flash, which the application core can't
+//! write.  Its image is kept in a slot in the application core's flash, and
+//! validated here along with the application image.  To install it, a copy
+//! command is left in RAM shared by the two cores, and the network core's own
+//! bootloader (b0n in nRF Connect) does the copy when it is released from
+//! reset.  This is the same "peripheral CPU delegation" protocol MCUboot uses,
+//! so an unmodified b0n can be used.
+//!
+//! The command lives in the last 16 bytes of the application core's RAM,
+//! which is excluded from the linker's RAM.
+
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+use nrf5340_app_pac as pac;
+
+/// Where the copy command is placed.  The network core bootloader must be
+/// built with the same address.
+pub const PCD_CMD_ADDRESS: usize = 0x2007_fff0;
+
+// Command states.  The network core replaces the magic when it is done.
+const PCD_CMD_MAGIC_COPY: u32 = 0xb5b4_b3b6;
+const PCD_CMD_MAGIC_DONE: u32 = 0xf103_ce5d;
+const PCD_CMD_MAGIC_FAIL: u32 = 0x0000_0001;
+
+/// How long to wait for the copy, in polls of the command.  Copying a full
+/// image to the network core's flash takes a few seconds.
+const COPY_POLLS: u32 = 100_000_000;
+
+#[repr(C)]
+struct PcdCmd {
+    magic: u32,
+    data: u32,
+    len: u32,
+    offset: u32,
+}
+
+/// Ask the network core's bootloader to install the image at `data`, `len`
+/// bytes long, and release the core to do it.  Waits for the copy to finish,
+/// returning whether it succeeded.  The core is left running.
+pub fn install(data: usize, len: usize) -> bool {
+    let cmd = PCD_CMD_ADDRESS as *mut PcdCmd;
+    unsafe {
+        write_volatile(addr_of_mut!((*cmd).data), data as u32);
+        write_volatile(addr_of_mut!((*cmd).len), len as u32);
+        write_volatile(addr_of_mut!((*cmd).offset), 0);
+        write_volatile(addr_of_mut!((*cmd).magic), PCD_CMD_MAGIC_COPY);
+    }
+    cortex_m::asm::dsb();
+
+    release();
+
+    for _ in 0..COPY_POLLS {
+        match unsafe { read_volatile(addr_of_mut!((*cmd).magic)) } {
+            PCD_CMD_MAGIC_DONE => return true,
+            PCD_CMD_MAGIC_FAIL => return false,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// Clear any command left from an earlier boot, so the network core doesn't
+/// act on it.
+pub fn clear() {
+    let cmd = PCD_CMD_ADDRESS as *mut PcdCmd;
+    unsafe { write_volatile(addr_of_mut!((*cmd).magic), 0) };
+}
+
+/// Release the network core from reset.
+pub fn release() {
+    let reset = unsafe { &*pac::RESET_S::ptr() };
+    reset.network.forceoff.write(|w| w.forceoff().release());
+}
+
+/// Hold the network core in reset.
+pub fn hold() {
+    let reset = unsafe { &*pac::RESET_S::ptr() };
+    reset.network.forceoff.write(|w| w.forceoff().hold());
+}
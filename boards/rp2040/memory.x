@@ -0,0 +1,24 @@
+/* RP2040, with 2 MB of QSPI flash, which is the only flash.  Everything runs
+   from the XIP window.  The layout must agree with src/main.rs.
+   0x10000000 - 256    - second stage bootloader (boot2)
+   0x10000100 - 64k    - this bootloader
+   0x10010000 - 960k   - slot 0
+   0x10100000 - 960k   - slot 1
+   0x101f0000 - 64k    - unused
+*/
+MEMORY
+{
+  BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+  FLASH : ORIGIN = 0x10000100, LENGTH = 64K - 0x100
+  RAM   : ORIGIN = 0x20000000, LENGTH = 256K
+}
+
+EXTERN(BOOT2_FIRMWARE)
+
+SECTIONS {
+    /* ### Boot loader */
+    .boot2 ORIGIN(BOOT2) :
+    {
+        KEEP(*(.boot2));
+    } > BOOT2
+} INSERT BEFORE .text;
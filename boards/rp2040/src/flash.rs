@@ -0,0 +1,180 @@
+//! RP2040 QSPI flash driver.
+//!
+//! The RP2040 has no internal flash.  Everything, this bootloader included,
+//! runs from the external QSPI NOR through the XIP window at 0x1000_0000.
+//! Reads go through that window.  Programs and erases use the boot ROM's flash
+//! functions, which take the flash out of XIP mode while they run, so the
+//! code doing them must run from RAM, with interrupts disabled, and must not
+//! touch flash until XIP is restored.
+//!
+//! The ROM can only restore a slow, single line XIP mode.  To get back the
+//! fast mode set up at boot, the second stage bootloader (boot2) is copied to
+//! RAM at startup, and called again after each operation, as the Pico SDK
+//! does.
+//!
+//! The flash is programmed in 256 byte pages, and erased in 4 KB sectors.
+//! The data being programmed must be in RAM, as the flash can't be read while
+//! it is being programmed.
+
+use boot::MappedFlash;
+use rp2040_hal::rom_data;
+use storage::{Error, Flash, ReadFlash, Result};
+
+/// The XIP window.
+pub const XIP_BASE: usize = 0x1000_0000;
+
+/// The flash on the Pico.
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The ROM uses 64 KB block erases where it can.
+const BLOCK_SIZE: u32 = 65536;
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+
+/// boot2 is the first 256 bytes of flash.
+const BOOT2_WORDS: usize = 64;
+
+/// The ROM functions.  These are looked up before starting an operation,
+/// because the lookup code lives in flash.
+struct RomFns {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+pub struct RpFlash {
+    boot2: [u32; BOOT2_WORDS],
+}
+
+impl RpFlash {
+    /// Take a copy of boot2, while XIP is still running.
+    pub fn new() -> RpFlash {
+        let mut boot2 = [0u32; BOOT2_WORDS];
+        for (i, word) in boot2.iter_mut().enumerate() {
+            *word = unsafe { core::ptr::read_volatile((XIP_BASE as *const u32).add(i)) };
+        }
+        RpFlash { boot2 }
+    }
+
+    /// Build a partition, given its offset from the start of flash.
+    pub fn partition(&self, base: usize, length: usize) -> Result<RpPartition<'_>> {
+        if length == 0 || base > FLASH_SIZE || length > FLASH_SIZE - base {
+            return Err(Error::OutOfBounds);
+        }
+        if !base.is_multiple_of(SECTOR_SIZE) || !length.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        Ok(RpPartition { flash: self, base, length })
+    }
+
+    /// Run a flash operation with XIP disabled, restoring it afterwards.
+    fn operation(&self, erase: bool, offset: usize, data: *const u8, len: usize) {
+        let fns = RomFns {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        };
+        let boot2 = self.boot2.as_ptr() as usize + 1;
+        cortex_m::interrupt::free(|_| unsafe {
+            ram_operation(&fns, boot2, erase, offset as u32, data, len);
+        });
+    }
+}
+
+impl Default for RpFlash {
+    fn default() -> Self {
+        RpFlash::new()
+    }
+}
+
+/// The part of a flash operation that runs with XIP disabled.  This must not
+/// call anything in flash, or use any constant data placed there.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn ram_operation(fns: &RomFns, boot2: usize, erase: bool,
+                        offset: u32, data: *const u8, len: usize) {
+    (fns.connect_internal_flash)();
+    (fns.flash_exit_xip)();
+    if erase {
+        (fns.flash_range_erase)(offset, len, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    } else {
+        (fns.flash_range_program)(offset, data, len);
+    }
+    (fns.flash_flush_cache)();
+    let boot2: extern "C" fn() = core::mem::transmute(boot2);
+    boot2();
+}
+
+pub struct RpPartition<'a> {
+    flash: &'a RpFlash,
+    base: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for RpPartition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let slice = unsafe {
+            core::slice::from_raw_parts((XIP_BASE + self.base + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(XIP_BASE + self.base)
+    }
+}
+
+impl<'a> Flash for RpPartition<'a> {
+    fn write_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        if from == to {
+            return Ok(());
+        }
+        self.flash.operation(true, self.base + from, core::ptr::null(), to - from);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+        self.flash.operation(false, self.base + offset, bytes.as_ptr(), bytes.len());
+
+        let mut check = [0u8; PAGE_SIZE];
+        for (i, chunk) in bytes.chunks(PAGE_SIZE).enumerate() {
+            self.read(offset + i * PAGE_SIZE, &mut check[..chunk.len()])?;
+            if check[..chunk.len()] != *chunk {
+                return Err(Error::Failed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> MappedFlash for RpPartition<'a> {
+    fn get_base(&self) -> usize {
+        XIP_BASE + self.base
+    }
+}
@@ -0,0 +1,11 @@
+/* STM32F407VG, 1 MB of flash in sectors of mixed sizes.  The layout must
+   agree with src/main.rs.
+   0x08000000 - 64k  - bootloader (sectors 0-3, 16k each)
+   0x08010000 - 448k - slot 0 (sector 4, 64k, and sectors 5-7, 128k each)
+   0x08080000 - 512k - slot 1 (sectors 8-11, 128k each)
+*/
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+  RAM : ORIGIN = 0x20000000, LENGTH = 128K
+}
@@ -0,0 +1,249 @@
+//! STM32F4 flash driver.
+//!
+//! The STM32F4 flash is divided into sectors of different sizes: four of
+//! 16 KB, one of 64 KB, then 128 KB sectors for the rest of the device.
+//! Erases must be of whole sectors, so this driver keeps the real sector map,
+//! and a partition can span sectors of different sizes.
+//!
+//! The storage traits only have a single erase size.  A partition reports the
+//! largest sector within it, which is how MCUboot treats these devices, and
+//! the boot code's status layout is sized from that.  `erase` itself accepts
+//! any range that starts and ends on a real sector boundary, and
+//! `F4Partition::sectors` gives the actual map, for code that can make use of
+//! the smaller sectors.
+//!
+//! Writes are done 32 bits at a time, which requires a supply of at least
+//! 2.7 V.
+//!
+//! To use this driver, give it the FLASH peripheral from the PAC.
+//!
+//!     let flash = flash::F4Flash::new(dp.FLASH);
+
+use core::cell::RefCell;
+
+use boot::MappedFlash;
+use stm32f4xx_hal::pac;
+use storage::{Error, Flash, ReadFlash, Result};
+
+pub const FLASH_BASE: usize = 0x0800_0000;
+
+/// The sizes of the sectors, in order, for a 1 MB part.
+const SECTOR_SIZES: [usize; 12] = [
+    16 * 1024, 16 * 1024, 16 * 1024, 16 * 1024,
+    64 * 1024,
+    128 * 1024, 128 * 1024, 128 * 1024, 128 * 1024,
+    128 * 1024, 128 * 1024, 128 * 1024,
+];
+
+pub const WRITE_SIZE: usize = 4;
+
+// Unlock keys.
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+// FLASH_CR.
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_SNB_SHIFT: u32 = 3;
+const CR_PSIZE_X32: u32 = 2 << 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+// FLASH_SR.
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = 0xf2;
+
+// FLASH_ACR.
+const ACR_ICEN: u32 = 1 << 9;
+const ACR_DCEN: u32 = 1 << 10;
+const ACR_ICRST: u32 = 1 << 11;
+const ACR_DCRST: u32 = 1 << 12;
+
+/// A single sector: its offset from the start of flash, and size.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sector {
+    pub base: usize,
+    pub size: usize,
+}
+
+/// All of the sectors of the device.
+pub fn sectors() -> impl Iterator<Item = Sector> {
+    SECTOR_SIZES.iter().scan(0, |base, &size| {
+        let sector = Sector { base: *base, size };
+        *base += size;
+        Some(sector)
+    })
+}
+
+/// The index of the sector starting at `offset`, if there is one.
+fn sector_at(offset: usize) -> Option<usize> {
+    sectors().position(|s| s.base == offset)
+}
+
+pub fn flash_size() -> usize {
+    SECTOR_SIZES.iter().sum()
+}
+
+pub struct F4Flash {
+    raw: RefCell<pac::FLASH>,
+}
+
+impl F4Flash {
+    pub fn new(raw: pac::FLASH) -> F4Flash {
+        F4Flash { raw: RefCell::new(raw) }
+    }
+
+    /// Build a partition, given its offset from the start of flash.  The
+    /// partition must start and end on sector boundaries.
+    pub fn partition(&self, base: usize, length: usize) -> Result<F4Partition<'_>> {
+        let end = base.checked_add(length).ok_or(Error::OutOfBounds)?;
+        if length == 0 || end > flash_size() {
+            return Err(Error::OutOfBounds);
+        }
+        if sector_at(base).is_none() || (end != flash_size() && sector_at(end).is_none()) {
+            return Err(Error::NotAligned);
+        }
+        let erase_size = sectors()
+            .filter(|s| s.base >= base && s.base < end)
+            .map(|s| s.size)
+            .max()
+            .unwrap_or(0);
+        Ok(F4Partition { raw: &self.raw, base, length, erase_size })
+    }
+}
+
+pub struct F4Partition<'a> {
+    raw: &'a RefCell<pac::FLASH>,
+    base: usize,
+    length: usize,
+    /// The largest sector in the partition.
+    erase_size: usize,
+}
+
+impl<'a> F4Partition<'a> {
+    /// The sectors of this partition, with offsets relative to its start.
+    pub fn sectors(&self) -> impl Iterator<Item = Sector> + '_ {
+        sectors()
+            .filter(|s| s.base >= self.base && s.base < self.base + self.length)
+            .map(|s| Sector { base: s.base - self.base, size: s.size })
+    }
+}
+
+impl<'a> ReadFlash for F4Partition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let slice = unsafe {
+            core::slice::from_raw_parts((FLASH_BASE + self.base + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(FLASH_BASE + self.base)
+    }
+}
+
+impl<'a> Flash for F4Partition<'a> {
+    fn write_size(&self) -> usize {
+        WRITE_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
+
+    /// Erase a range of sectors.  Unlike most devices, the range only needs to
+    /// be on sector boundaries, not multiples of `erase_size`.
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        if from > to || to > self.length {
+            return Err(Error::OutOfBounds);
+        }
+        let first = sector_at(self.base + from).ok_or(Error::NotAligned)?;
+        let end = self.base + to;
+        if end != flash_size() && sector_at(end).is_none() {
+            return Err(Error::NotAligned);
+        }
+
+        let raw = self.raw.borrow();
+        unlock(&raw);
+        let mut result = Ok(());
+        for (snb, sector) in sectors().enumerate().skip(first) {
+            if sector.base >= end {
+                break;
+            }
+            raw.cr.write(|w| unsafe { w.bits(CR_SER | CR_PSIZE_X32 | ((snb as u32) << CR_SNB_SHIFT)) });
+            raw.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_STRT) });
+            if let Err(e) = wait(&raw) {
+                result = Err(e);
+                break;
+            }
+        }
+        raw.cr.write(|w| unsafe { w.bits(CR_LOCK) });
+        flush_caches(&raw);
+        result
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+
+        let raw = self.raw.borrow();
+        unlock(&raw);
+        raw.cr.write(|w| unsafe { w.bits(CR_PG | CR_PSIZE_X32) });
+        let base = FLASH_BASE + self.base + offset;
+        let mut result = Ok(());
+        for (i, word) in bytes.chunks_exact(WRITE_SIZE).enumerate() {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            unsafe { core::ptr::write_volatile((base + i * WRITE_SIZE) as *mut u32, word) };
+            if let Err(e) = wait(&raw) {
+                result = Err(e);
+                break;
+            }
+        }
+        raw.cr.write(|w| unsafe { w.bits(CR_LOCK) });
+        flush_caches(&raw);
+        result
+    }
+}
+
+impl<'a> MappedFlash for F4Partition<'a> {
+    fn get_base(&self) -> usize {
+        FLASH_BASE + self.base
+    }
+}
+
+fn unlock(raw: &pac::FLASH) {
+    if raw.cr.read().bits() & CR_LOCK != 0 {
+        raw.keyr.write(|w| unsafe { w.bits(KEY1) });
+        raw.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+/// Wait for an operation to finish, and report (and clear) any errors.
+fn wait(raw: &pac::FLASH) -> Result<()> {
+    while raw.sr.read().bits() & SR_BSY != 0 {
+    }
+    let errors = raw.sr.read().bits() & SR_ERRORS;
+    if errors != 0 {
+        raw.sr.write(|w| unsafe { w.bits(errors) });
+        return Err(Error::Failed);
+    }
+    Ok(())
+}
+
+/// The ART accelerator's caches may hold old contents after a change to the
+/// flash.  They can only be reset while disabled.
+fn flush_caches(raw: &pac::FLASH) {
+    let acr = raw.acr.read().bits();
+    raw.acr.write(|w| unsafe { w.bits(acr & !(ACR_ICEN | ACR_DCEN)) });
+    raw.acr.write(|w| unsafe { w.bits((acr & !(ACR_ICEN | ACR_DCEN)) | ACR_ICRST | ACR_DCRST) });
+    raw.acr.write(|w| unsafe { w.bits(acr & !(ACR_ICRST | ACR_DCRST)) });
+}
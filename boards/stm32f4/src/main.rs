@@ -0,0 +1,66 @@
+#![no_main]
+#![no_std]
+
+use panic_probe as _;
+use defmt_rtt as _;
+use defmt::{error, info, Debug2Format};
+
+use core::cell::RefCell;
+
+use boot::{Image, MappedFlash};
+use stm32f4xx_hal::pac;
+
+mod flash;
+
+// Partitions, as offsets from the start of flash.  These must agree with
+// memory.x, and fall on sector boundaries.  Slot 0 starts with the 64 KB
+// sector, followed by 128 KB sectors.
+const SLOT0_BASE: usize = 0x1_0000;
+const SLOT0_SIZE: usize = 0x7_0000;
+const SLOT1_BASE: usize = 0x8_0000;
+const SLOT1_SIZE: usize = 0x8_0000;
+
+/// The key images must be signed with.
+static SIGNING_KEY: &[u8] = include_bytes!("../../../boot/data/ecdsa-p256-pub.der");
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    info!("---------- Start of bootloader ----------");
+
+    let flash = flash::F4Flash::new(dp.FLASH);
+    let mut crypto = boot::SoftCrypto::new();
+
+    let slot0 = flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap();
+    let slot1 = flash.partition(SLOT1_BASE, SLOT1_SIZE).unwrap();
+    info!("slot0: 0x{:x}, slot1: 0x{:x}", slot0.get_base(), slot1.get_base());
+    for sector in slot0.sectors() {
+        info!("  slot0 sector at 0x{:x}, {} KB", sector.base, sector.size / 1024);
+    }
+    let slot0 = RefCell::new(slot0);
+
+    let image = match Image::from_flash(&slot0) {
+        Ok(image) => image,
+        Err(e) => halt("No image in slot0", e),
+    };
+    info!("Image found, {} bytes", image.full_image_size());
+
+    if let Err(e) = image.validate_signed(&mut crypto, SIGNING_KEY) {
+        halt("Image is invalid", e);
+    }
+    info!("Image is valid");
+
+    let base = image.get_image_base().unwrap_or_else(|e| halt("Image is misaligned", e));
+    info!("Chaining to 0x{:x}", base);
+    let Err(e) = unsafe { boot::chain(&boot::CortexM, &image) };
+    halt("Image is misaligned", e)
+}
+
+/// Report a failure to boot, and stop.
+fn halt(message: &str, e: boot::Error) -> ! {
+    error!("{}: {}", message, Debug2Format(&e));
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -8,18 +8,23 @@ MEMORY
     SRAM1         : ORIGIN = 0x30000000, LENGTH = 128K /* 32-bit AHB bus matrix, D2 domain */
     SRAM2         : ORIGIN = 0x30020000, LENGTH = 128K /* 32-bit AHB bus matrix, D2 domain */
     SRAM3   (RW)  : ORIGIN = 0x30040000, LENGTH = 32K  /* 32-bit AHB bus matrix, D2 domain */
-    SRAM4   (RW)  : ORIGIN = 0x38000000, LENGTH = 64K  /* 32-bit AHB bus matrix, D3 domain */
+    SRAM4   (RW)  : ORIGIN = 0x38000100, LENGTH = 64K - 256 /* 32-bit AHB bus matrix, D3 domain */
+    /* The start of SRAM4 holds the boot vector for the CM4, see src/cm4.rs. */
     BSRAM         : ORIGIN = 0x38800000, LENGTH = 4K   /* 32-bit AHB bus matrix, D3 domain */
     AXISRAM (RWX) : ORIGIN = 0x24000000, LENGTH = 512K /* 64-bit AXI bus matrix, D1 domain */
     DTCMRAM (RWX) : ORIGIN = 0x20000000, LENGTH = 128K /* 64-bit AXI bus matrix, D1 domain */
     FLASH2  (RX)  : ORIGIN = 0x08100000, LENGTH = 1M   /* 64-bit AXI bus matrix, D1 domain */
     FLASH1  (RX)  : ORIGIN = 0x08000000, LENGTH = 1M   /* 64-bit AXI bus matrix, D1 domain */
     ITCMRAM (RWX) : ORIGIN = 0x00000000, LENGTH = 64K  /* 64-bit AXI bus matrix, D1 domain */
+
+    /* The bootloader itself only uses the first sector of bank 1, the rest is
+       the primary slot. */
+    BOOT    (RX)  : ORIGIN = 0x08000000, LENGTH = 128K
 }
 
 /* stm32h7xx-hal uses a PROVIDE that expects RAM and FLASH symbols to exist */
 REGION_ALIAS(RAM, DTCMRAM);
-REGION_ALIAS(FLASH, FLASH1);
+REGION_ALIAS(FLASH, BOOT);
 
 /* The location of the stack can be overridden using the
    `_stack_start` symbol.  Place the stack at the end of RAM */
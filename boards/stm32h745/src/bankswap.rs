@@ -0,0 +1,124 @@
+//! Upgrades by bank swapping.
+//!
+//! On the dual-bank parts, an upgrade doesn't need to copy anything.  The new
+//! image is written into the inactive bank, at the same offset within the bank
+//! as the primary slot, and an upgrade requested with the usual magic at the
+//! end of that slot.  At boot, the bootloader validates the new image, makes
+//! sure the inactive bank also holds a copy of the bootloader, and toggles
+//! SWAP_BANK.  After the reset, the banks have traded places in the memory
+//! map, and the new image is the primary slot.
+//!
+//! The option byte change is atomic, so a power failure at any point leaves
+//! the device booting either the old or the new image.  The old image stays
+//! in the inactive bank, and is not touched until the next upgrade writes
+//! over it.
+//!
+//! If the new image hangs, the watchdog resets back into the bootloader, which
+//! swaps back to the previous image, as long as it is still valid.  See
+//! `try_revert`.
+//!
+//! The bank is the unit of the swap.  If the CM4 is used, the upgrade must
+//! also write the CM4 slot in the inactive bank.
+
+use core::cell::RefCell;
+
+use boot::{upgrade_requested, CryptoBackend, Image};
+use defmt::info;
+use storage::{Flash, ReadFlash};
+
+use crate::options::{self, OPTSR_SWAP_BANK};
+
+/// Bytes compared or copied at a time.  A multiple of the write size.
+const CHUNK: usize = 256;
+
+/// The partitions involved in a bank swap upgrade.
+pub struct Banks<F> {
+    /// The bootloader, in the active bank.
+    pub boot: F,
+    /// Where the bootloader goes in the inactive bank.
+    pub inactive_boot: F,
+    /// The new image, in the inactive bank.
+    pub upgrade: F,
+}
+
+/// Are the banks currently swapped?
+pub fn swapped() -> bool {
+    options::optsr() & OPTSR_SWAP_BANK != 0
+}
+
+/// If an upgrade is requested, and the new image is valid, swap the banks and
+/// reset.  Returns if there is nothing to do, or the upgrade is not possible.
+pub fn try_upgrade<F: Flash, C: CryptoBackend>(banks: Banks<F>, crypto: &mut C, key: &[u8]) -> boot::Result<()> {
+    let Banks { mut boot, mut inactive_boot, mut upgrade } = banks;
+    if !upgrade_requested(&mut upgrade)? {
+        return Ok(());
+    }
+
+    info!("Bank swap upgrade requested");
+    let upgrade = RefCell::new(upgrade);
+    Image::from_flash(&upgrade)?.validate_signed(crypto, key)?;
+    info!("New image is valid");
+
+    sync(&mut boot, &mut inactive_boot)?;
+    swap()
+}
+
+/// The image in the primary slot has failed, so go back to the image in the
+/// inactive bank, if it is valid.  The first and last sectors of the failed
+/// image are erased, so that it is neither booted again, nor seen as a pending
+/// upgrade once it is in the inactive bank.  Returns if there is no valid image
+/// to go back to.
+pub fn try_revert<F: Flash, C: CryptoBackend>(mut failed: F, previous: F, crypto: &mut C, key: &[u8]) -> boot::Result<()> {
+    let previous = RefCell::new(previous);
+    Image::from_flash(&previous)?.validate_signed(crypto, key)?;
+    info!("Previous image is valid, reverting");
+
+    let sector = failed.erase_size();
+    let size = failed.capacity();
+    failed.erase(0, sector)?;
+    failed.erase(size - sector, size)?;
+    swap()
+}
+
+/// Toggle SWAP_BANK, and reset so that it takes effect.
+fn swap() -> ! {
+    let optsr = options::optsr();
+    options::program(optsr ^ OPTSR_SWAP_BANK, options::boot4());
+    info!("Banks swapped, resetting");
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Make the destination a copy of the source, if it isn't already.
+fn sync<F: Flash>(src: &mut F, dest: &mut F) -> boot::Result<()> {
+    if same(src, dest)? {
+        return Ok(());
+    }
+
+    info!("Copying the bootloader to the inactive bank");
+    let size = dest.capacity();
+    dest.erase(0, size)?;
+    let mut buf = [0u8; CHUNK];
+    for pos in (0..size).step_by(CHUNK) {
+        src.read(pos, &mut buf)?;
+        dest.write(pos, &buf)?;
+    }
+
+    if !same(src, dest)? {
+        return Err(boot::Error::CannotUpgrade);
+    }
+    Ok(())
+}
+
+/// Do two partitions have the same contents?  Anything unreadable in the
+/// destination counts as different.
+fn same<F: Flash>(a: &mut F, b: &mut F) -> boot::Result<bool> {
+    let mut abuf = [0u8; CHUNK];
+    let mut bbuf = [0u8; CHUNK];
+    for pos in (0..a.capacity()).step_by(CHUNK) {
+        a.read(pos, &mut abuf)?;
+        if b.read(pos, &mut bbuf).is_err() || abuf != bbuf {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
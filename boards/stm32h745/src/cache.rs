@@ -0,0 +1,39 @@
+//! Cache maintenance.
+//!
+//! The Cortex-M7 caches don't see changes made to the flash by its controller
+//! (or the QSPI in indirect mode), so after an erase or program, the affected
+//! lines are invalidated, or reads through the memory map could return the old
+//! contents.  Before chaining, the caches are cleaned and invalidated, so the
+//! application starts with nothing left over from the bootloader.
+//!
+//! The flash is never written through the cache, so invalidating flash lines
+//! never loses data.
+
+use cortex_m::peripheral::SCB;
+
+/// Cortex-M7 cache line size.
+const LINE_SIZE: usize = 32;
+
+/// The memory-mapped range starting at `address` has been changed behind the
+/// caches' back.
+pub fn flash_changed(address: usize, len: usize) {
+    let mut p = unsafe { cortex_m::Peripherals::steal() };
+    if SCB::dcache_enabled() {
+        let start = address & !(LINE_SIZE - 1);
+        let end = (address + len).next_multiple_of(LINE_SIZE);
+        unsafe { p.SCB.invalidate_dcache_by_address(start, end - start) };
+    }
+    if SCB::icache_enabled() {
+        p.SCB.invalidate_icache();
+    }
+}
+
+/// Make sure nothing the bootloader cached outlives it.  Any dirty data is
+/// written back, and both caches invalidated.
+pub fn before_chain() {
+    let mut p = unsafe { cortex_m::Peripherals::steal() };
+    if SCB::dcache_enabled() {
+        p.SCB.clean_invalidate_dcache(&mut p.CPUID);
+    }
+    p.SCB.invalidate_icache();
+}
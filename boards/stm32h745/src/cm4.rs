@@ -0,0 +1,57 @@
+//! Cortex-M4 coprocessor startup.
+//!
+//! The CM4 image lives in its own slot, and is validated along with the CM7
+//! image as a multi-image set: the CM7 image is image 0, the CM4 image 1, and
+//! either may depend on the version of the other.
+//!
+//! The CM4 boots from the address in the BOOT_CM4_ADD0 option byte, which only
+//! holds the upper 16 bits, so it has to be 64 KB aligned.  An image's vector
+//! table follows its header, so it can't be used directly.  Instead, the CM4
+//! boots from a two word vector table at the start of SRAM4, which the CM7
+//! fills in with the image's initial stack pointer and reset vector.  The CM4
+//! image must set its own VTOR.
+//!
+//! The option bytes are set so that the CM4 is held at reset (BCM4 clear), and
+//! then released with BOOT_C2 in RCC_GCR once the image is known to be good.
+//! The option bytes are only programmed if they need to change.
+
+use stm32h7xx_hal::pac;
+
+use crate::options::{self, OPTSR_BCM4};
+
+/// Where the CM4 boots from.
+pub const BOOT_VECTOR: usize = 0x3800_0000;
+
+// RCC_GCR.
+const GCR_BOOT_C2: u32 = 1 << 3;
+
+/// Make sure the option bytes hold the CM4 at reset, and boot it from
+/// `BOOT_VECTOR`.  The CM4 is already running if BCM4 was set at this reset,
+/// so a reset is needed after changing it.  Returns true if a reset is needed.
+pub fn configure() -> bool {
+    let addr = (BOOT_VECTOR >> 16) as u32;
+    let boot4 = addr | (addr << 16);
+    let optsr = options::optsr();
+    let was_running = optsr & OPTSR_BCM4 != 0;
+    if !was_running && options::boot4() == boot4 {
+        return false;
+    }
+
+    options::program(optsr & !OPTSR_BCM4, boot4);
+    was_running
+}
+
+/// Start the CM4 running the image whose vector table is at `vector_table`.
+pub fn release(vector_table: usize) {
+    let boot = BOOT_VECTOR as *mut u32;
+    let image = vector_table as *const u32;
+    unsafe {
+        // Initial stack pointer and reset vector.
+        boot.write_volatile(image.read_volatile());
+        boot.add(1).write_volatile(image.add(1).read_volatile());
+    }
+    cortex_m::asm::dsb();
+
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.gcr.modify(|r, w| unsafe { w.bits(r.bits() | GCR_BOOT_C2) });
+}
@@ -0,0 +1,175 @@
+//! STM32H7 flash driver.
+//!
+//! Wraps the flash banks from the hal in the storage traits, so the boot code
+//! can run unchanged on this part.  The flash is written 32 bytes (a flash
+//! word) at a time, and erased in 128 KB sectors.
+//!
+//! The two banks are separate devices in the hal, each addressed from its own
+//! start.  Partitions are described by their offset from the start of bank 1,
+//! as they are in the memory map, and must not straddle the two banks.  With
+//! SWAP_BANK set, the banks trade places in the memory map, and the bank 1
+//! registers operate on whichever bank is mapped first, so this addressing
+//! always follows the memory map.  See `bankswap`.
+//!
+//! Each flash word carries ECC.  A flash word whose programming was
+//! interrupted, such as by a reset during an upgrade, can fail with a double
+//! ECC error, which would normally be a bus fault.  Reads are instead done with
+//! bus faults ignored, and the bank's DBECCERR flag checked afterwards, turning
+//! these into `Error::NotWritten`, as the LPC55 driver does for unprogrammed
+//! pages.
+
+use core::arch::asm;
+use core::cell::RefCell;
+
+use boot::MappedFlash;
+use cortex_m::peripheral::SCB;
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+use hal::flash::{FlashExt, LockedFlashBank, UnlockedFlashBank};
+use hal::pac;
+use storage::{Error, Flash, ReadFlash, Result};
+
+use crate::cache;
+
+use stm32h7xx_hal as hal;
+
+/// Base of bank 1 in the memory map.  Bank 2 directly follows it.
+pub const FLASH_BASE: usize = 0x0800_0000;
+
+/// The size of each bank.
+pub const BANK_SIZE: usize = 1024 * 1024;
+
+/// Program unit: a 256-bit flash word.
+pub const WRITE_SIZE: usize = <UnlockedFlashBank as NorFlash>::WRITE_SIZE;
+
+/// Erase unit: a sector.
+pub const ERASE_SIZE: usize = <UnlockedFlashBank as NorFlash>::ERASE_SIZE;
+
+// Double ECC error, in FLASH_SRx, and its clear bit in FLASH_CCRx.
+const SR_DBECCERR: u32 = 1 << 26;
+const CCR_CLR_DBECCERR: u32 = 1 << 26;
+
+// SCB CCR: ignore bus faults while FAULTMASK is set.
+const SCB_CCR_BFHFNMIGN: u32 = 1 << 8;
+
+pub struct H7Flash {
+    banks: [Option<RefCell<LockedFlashBank>>; 2],
+}
+
+impl H7Flash {
+    pub fn new(raw: pac::FLASH) -> H7Flash {
+        let (bank1, bank2) = raw.split();
+        H7Flash { banks: [Some(RefCell::new(bank1)), bank2.map(RefCell::new)] }
+    }
+
+    /// Build a partition, given its offset from the start of bank 1.
+    pub fn partition(&self, base: usize, length: usize) -> Result<H7Partition<'_>> {
+        let bank_num = base / BANK_SIZE;
+        let offset = base % BANK_SIZE;
+        let bank = match self.banks.get(bank_num) {
+            Some(Some(bank)) => bank,
+            _ => return Err(Error::OutOfBounds),
+        };
+        if length == 0 || offset + length > bank.borrow().len() {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(H7Partition { bank, base, offset, length })
+    }
+}
+
+/// A single partition, within one bank.
+pub struct H7Partition<'a> {
+    bank: &'a RefCell<LockedFlashBank>,
+    /// Offset from the start of bank 1.
+    base: usize,
+    /// Offset within the bank.
+    offset: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for H7Partition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        ecc_read(self.base / BANK_SIZE, FLASH_BASE + self.base + offset, buf)
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(FLASH_BASE + self.base)
+    }
+}
+
+impl<'a> Flash for H7Partition<'a> {
+    fn write_size(&self) -> usize {
+        WRITE_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        ERASE_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        let start = (self.offset + from) as u32;
+        let end = (self.offset + to) as u32;
+        let result = self.bank.borrow_mut().unlocked().erase(start, end).map_err(convert);
+        cache::flash_changed(FLASH_BASE + self.base + from, to - from);
+        result
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+        let start = (self.offset + offset) as u32;
+        let result = self.bank.borrow_mut().unlocked().write(start, bytes).map_err(convert);
+        cache::flash_changed(FLASH_BASE + self.base + offset, bytes.len());
+        result
+    }
+}
+
+impl<'a> MappedFlash for H7Partition<'a> {
+    fn get_base(&self) -> usize {
+        FLASH_BASE + self.base
+    }
+}
+
+/// Copy from the memory map, with bus faults ignored, so that a double ECC error
+/// only sets the DBECCERR flag of the bank.
+fn ecc_read(bank: usize, address: usize, buf: &mut [u8]) -> Result<()> {
+    let regs = unsafe { &*pac::FLASH::ptr() };
+    let regs = if bank == 0 { regs.bank1() } else { regs.bank2() };
+    regs.ccr.write(|w| unsafe { w.bits(CCR_CLR_DBECCERR) });
+
+    unsafe {
+        let scb = &*SCB::PTR;
+        asm!("cpsid f");
+        scb.ccr.modify(|r| r | SCB_CCR_BFHFNMIGN);
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = core::ptr::read_volatile((address + i) as *const u8);
+        }
+        cortex_m::asm::dsb();
+        scb.ccr.modify(|r| r & !SCB_CCR_BFHFNMIGN);
+        asm!("cpsie f");
+    }
+
+    if regs.sr.read().bits() & SR_DBECCERR != 0 {
+        regs.ccr.write(|w| unsafe { w.bits(CCR_CLR_DBECCERR) });
+        return Err(Error::NotWritten);
+    }
+    Ok(())
+}
+
+/// Convert an error from the hal.  The arguments have already been checked, so
+/// anything else is a failure of the device.
+fn convert<E: NorFlashError>(e: E) -> Error {
+    match e.kind() {
+        NorFlashErrorKind::NotAligned => Error::NotAligned,
+        NorFlashErrorKind::OutOfBounds => Error::OutOfBounds,
+        _ => Error::Failed,
+    }
+}
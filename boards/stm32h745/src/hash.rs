@@ -0,0 +1,223 @@
+//! STM32H7 HASH engine.
+//!
+//! The HASH peripheral computes SHA256 in hardware, providing a
+//! `CryptoBackend` for the boot crate.  Data handed to `sha256_update` is fed
+//! to the engine a word at a time through DIN.  When an image is hashed
+//! directly from internal flash, the flash is instead read by DMA2, which
+//! keeps the engine busy without the CPU copying every word.  The buffers
+//! the boot crate uses live in DTCM, which the DMA engines cannot reach, so
+//! only flash is read this way.
+//!
+//! The HASH peripheral is only present on the parts with crypto (the
+//! STM32H755 rather than the STM32H745), and the PAC for the non-crypto
+//! parts doesn't describe it, so the registers are accessed directly.  Enable
+//! the `hw-hash` feature to use it.
+
+use boot::{CryptoBackend, Hash256};
+use storage::ReadFlash;
+
+const HASH_BASE: usize = 0x4802_1400;
+const HASH_CR: usize = HASH_BASE;
+const HASH_DIN: usize = HASH_BASE + 0x04;
+const HASH_STR: usize = HASH_BASE + 0x08;
+const HASH_SR: usize = HASH_BASE + 0x24;
+const HASH_HR: usize = HASH_BASE + 0x310;
+
+// HASH_CR.  The data type of bytes has the engine swap each word, so words
+// are simply read little endian from memory.
+const CR_INIT: u32 = 1 << 2;
+const CR_DMAE: u32 = 1 << 3;
+const CR_DATATYPE_BYTES: u32 = 2 << 4;
+const CR_ALGO_SHA256: u32 = (1 << 18) | (1 << 7);
+const CR_MDMAT: u32 = 1 << 13;
+
+// HASH_STR.
+const STR_DCAL: u32 = 1 << 8;
+
+// HASH_SR.
+const SR_DCIS: u32 = 1 << 1;
+const SR_BUSY: u32 = 1 << 3;
+
+// RCC enables for the HASH and DMA2.
+const RCC_AHB1ENR: usize = 0x5802_44d8;
+const RCC_AHB2ENR: usize = 0x5802_44dc;
+const AHB1ENR_DMA2EN: u32 = 1 << 1;
+const AHB2ENR_HASHEN: u32 = 1 << 5;
+
+// DMA2 stream 0, and the DMAMUX1 channel that feeds it.
+const DMA2_BASE: usize = 0x4002_0400;
+const DMA_LISR: usize = DMA2_BASE;
+const DMA_LIFCR: usize = DMA2_BASE + 0x08;
+const DMA_S0CR: usize = DMA2_BASE + 0x10;
+const DMA_S0NDTR: usize = DMA2_BASE + 0x14;
+const DMA_S0PAR: usize = DMA2_BASE + 0x18;
+const DMA_S0M0AR: usize = DMA2_BASE + 0x1c;
+const DMAMUX1_C8CR: usize = 0x4002_0820;
+const DMAMUX_HASH_IN: u32 = 118;
+
+// DMA_SxCR.  Memory to peripheral, incrementing the memory address, words on
+// both sides.
+const DMA_CR_EN: u32 = 1 << 0;
+const DMA_CR_MEM_TO_PERIPH: u32 = 1 << 6;
+const DMA_CR_MINC: u32 = 1 << 10;
+const DMA_CR_PSIZE_WORD: u32 = 2 << 11;
+const DMA_CR_MSIZE_WORD: u32 = 2 << 13;
+
+// Stream 0 flags, in LISR and LIFCR.
+const DMA_TEIF0: u32 = 1 << 3;
+const DMA_TCIF0: u32 = 1 << 5;
+const DMA_ALL0: u32 = 0x3d;
+
+/// Largest transfer count of a single DMA transfer.
+const DMA_MAX_WORDS: usize = 0xffff;
+
+/// Memory below this (the TCMs) is not reachable by DMA2.
+const DMA_MIN_ADDRESS: usize = 0x0800_0000;
+
+pub struct H7Hash {
+    /// Bytes of a partial word, not yet given to the engine.
+    word: [u8; 4],
+    /// Number of bytes in `word`.
+    fill: usize,
+}
+
+impl H7Hash {
+    /// Enable the HASH and DMA2 clocks.
+    pub fn new() -> H7Hash {
+        unsafe {
+            modify(RCC_AHB1ENR, |r| r | AHB1ENR_DMA2EN);
+            modify(RCC_AHB2ENR, |r| r | AHB2ENR_HASHEN);
+        }
+        H7Hash { word: [0; 4], fill: 0 }
+    }
+
+    fn write_word(&self, word: [u8; 4]) {
+        unsafe { write(HASH_DIN, u32::from_le_bytes(word)) };
+    }
+
+    /// Feed `words` words of memory, starting at `address`, to the engine by
+    /// DMA.
+    fn dma_words(&self, mut address: usize, words: usize) -> bool {
+        let mut ok = true;
+        unsafe {
+            write(DMAMUX1_C8CR, DMAMUX_HASH_IN);
+            modify(HASH_CR, |r| r | CR_DMAE | CR_MDMAT);
+            let mut remaining = words;
+            while remaining > 0 {
+                let count = remaining.min(DMA_MAX_WORDS);
+                write(DMA_LIFCR, DMA_ALL0);
+                write(DMA_S0PAR, HASH_DIN as u32);
+                write(DMA_S0M0AR, address as u32);
+                write(DMA_S0NDTR, count as u32);
+                write(DMA_S0CR, DMA_CR_MEM_TO_PERIPH | DMA_CR_MINC |
+                      DMA_CR_PSIZE_WORD | DMA_CR_MSIZE_WORD | DMA_CR_EN);
+                let status = loop {
+                    let status = read(DMA_LISR);
+                    if status & (DMA_TCIF0 | DMA_TEIF0) != 0 {
+                        break status;
+                    }
+                };
+                write(DMA_S0CR, 0);
+                if status & DMA_TEIF0 != 0 {
+                    ok = false;
+                    break;
+                }
+                address += count * 4;
+                remaining -= count;
+            }
+            while read(HASH_SR) & SR_BUSY != 0 {
+            }
+            modify(HASH_CR, |r| r & !CR_DMAE);
+        }
+        ok
+    }
+}
+
+impl Default for H7Hash {
+    fn default() -> Self {
+        H7Hash::new()
+    }
+}
+
+impl CryptoBackend for H7Hash {
+    fn sha256_start(&mut self) {
+        unsafe { write(HASH_CR, CR_ALGO_SHA256 | CR_DATATYPE_BYTES | CR_INIT) };
+        self.fill = 0;
+    }
+
+    fn sha256_update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.word[self.fill] = byte;
+            self.fill += 1;
+            if self.fill == 4 {
+                self.write_word(self.word);
+                self.fill = 0;
+            }
+        }
+    }
+
+    fn sha256_finish(&mut self) -> Hash256 {
+        // The last word gives the number of valid bits in it, zero meaning all
+        // of them.
+        if self.fill > 0 {
+            self.word[self.fill..].fill(0);
+            self.write_word(self.word);
+        }
+        let mut result = [0u8; 32];
+        unsafe {
+            write(HASH_STR, (self.fill as u32) * 8);
+            write(HASH_STR, (self.fill as u32) * 8 | STR_DCAL);
+            while read(HASH_SR) & SR_DCIS == 0 {
+            }
+            for (i, out) in result.chunks_exact_mut(4).enumerate() {
+                out.copy_from_slice(&read(HASH_HR + i * 4).to_be_bytes());
+            }
+        }
+        self.fill = 0;
+        result
+    }
+
+    fn sha256_update_flash<F: ReadFlash>(&mut self, flash: &mut F,
+                                         offset: usize, len: usize) -> storage::Result<()> {
+        let mut pos = offset;
+        let end = offset + len;
+
+        // Aligned whole words of memory-mapped flash go by DMA.  The rest, and
+        // all of a flash that isn't mapped, is read and fed by the CPU.
+        if let Some(base) = flash.memory_address() {
+            storage::check_read(flash, offset, len)?;
+            let address = base + offset;
+            let words = len / 4;
+            if self.fill == 0 && address.is_multiple_of(4) &&
+                address >= DMA_MIN_ADDRESS && words > 0
+            {
+                if !self.dma_words(address, words) {
+                    return Err(storage::Error::Failed);
+                }
+                pos += words * 4;
+            }
+        }
+
+        let mut buffer = [0u8; 128];
+        while pos < end {
+            let todo = (end - pos).min(buffer.len());
+            let buf = &mut buffer[0..todo];
+            flash.read(pos, buf)?;
+            self.sha256_update(buf);
+            pos += todo;
+        }
+        Ok(())
+    }
+}
+
+unsafe fn read(address: usize) -> u32 {
+    core::ptr::read_volatile(address as *const u32)
+}
+
+unsafe fn write(address: usize, value: u32) {
+    core::ptr::write_volatile(address as *mut u32, value)
+}
+
+unsafe fn modify(address: usize, f: impl FnOnce(u32) -> u32) {
+    write(address, f(read(address)))
+}
@@ -0,0 +1,80 @@
+//! Overwrite upgrades from an external slot.
+//!
+//! When the upgrade slot is not in the other bank, the banks can't simply be
+//! swapped.  Instead, a valid requested image is copied over the primary slot,
+//! and the upgrade slot is erased, which clears the request.  If power fails
+//! during the copy, the request is still present, and the copy is redone at
+//! the next boot.  There is no revert.
+//!
+//! The copy is hashed on its way through the buffer, so checking it only
+//! reads back its header and TLV.  Anything left after it from a larger
+//! previous image is then erased.
+
+use core::cell::RefCell;
+
+use boot::{erase_remnants, upgrade_requested, CryptoBackend, Image, SlotInfo};
+use defmt::info;
+use storage::{Flash, ReadFlash};
+
+/// Bytes copied at a time.  A multiple of the write sizes of both slots.
+const CHUNK: usize = 256;
+
+/// If an upgrade is requested, and the new image is valid, copy it into the
+/// destination.
+pub fn try_install<S, D, C>(src: S, dest: D, crypto: &mut C, key: &[u8]) -> boot::Result<()>
+    where S: Flash, D: Flash, C: CryptoBackend,
+{
+    let (mut src, mut dest) = (src, dest);
+    if !upgrade_requested(&mut src)? {
+        return Ok(());
+    }
+
+    info!("Upgrade requested");
+    let src = RefCell::new(src);
+    let (size, hashed) = {
+        let image = Image::from_flash(&src)?;
+        image.validate_signed(crypto, key)?;
+        (image.full_image_size(), image.hashed_size())
+    };
+    let mut src = src.into_inner();
+    if size > dest.capacity() {
+        return Err(boot::Error::CannotUpgrade);
+    }
+    info!("Installing {} bytes", size);
+
+    let erase_size = dest.erase_size();
+    dest.erase(0, size.next_multiple_of(erase_size))?;
+    let mut buf = [0u8; CHUNK];
+    crypto.sha256_start();
+    for pos in (0..size).step_by(CHUNK) {
+        let len = CHUNK.min(size - pos);
+        buf.fill(0xff);
+        src.read(pos, &mut buf[..len])?;
+        let padded = len.next_multiple_of(dest.write_size());
+        dest.write(pos, &buf[..padded])?;
+        if pos < hashed {
+            crypto.sha256_update(&buf[..len.min(hashed - pos)]);
+        }
+    }
+    let hash = crypto.sha256_finish();
+
+    // Check the copy before dropping the source.
+    let dest = RefCell::new(dest);
+    let image = Image::from_flash(&dest)?;
+    if image.hashed_size() != hashed {
+        return Err(boot::Error::InvalidImage);
+    }
+    image.validate_signed_with_hash(crypto, key, &hash)?;
+
+    // The copy only erased what the new image needed, so clear out what is
+    // left of a larger previous one, up to where a status would go.
+    let layout = SlotInfo::from_data(size, &*dest.borrow()).status_layout(&SlotInfo::from_data(size, &src))?;
+    if let Some(offset) = erase_remnants(&dest, &layout)? {
+        info!("Old data left at 0x{:x}", offset);
+    }
+
+    let src_size = src.capacity();
+    src.erase(0, src_size)?;
+    info!("Upgrade installed");
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! STM32H7 independent watchdog.
+//!
+//! The watchdog is started early in boot, and fed by the flash driver (through
+//! `boot::WatchedFlash`) during validation and upgrades.  It is deliberately
+//! left running, and no longer fed, when chaining to the application.  The
+//! application must take over feeding it, and a hung application will reset
+//! back into the bootloader, which can then go back to the previous image.
+//!
+//! The hal's driver needs `&mut` to feed, but the watchdog is shared by all of
+//! the flash devices, so this uses the raw peripheral.
+
+use boot::Watchdog;
+use stm32h7xx_hal::pac;
+
+/// The watchdog counts the LSI, nominally 32 kHz.
+const LSI_TICKS_PER_MS: u32 = 32;
+
+// Key register values.
+const KR_FEED: u32 = 0xaaaa;
+const KR_UNLOCK: u32 = 0x5555;
+const KR_START: u32 = 0xcccc;
+
+// The prescaler divides by 4 << PR, and the reload value is 12 bits.
+const PR_MAX: u32 = 6;
+const RLR_MAX: u32 = 0xfff;
+
+// Prescaler and reload updates in progress, in SR.
+const SR_BUSY: u32 = 0x3;
+
+// RCC_RSR: the IWDG1 reset flag, and the bit that clears all of the flags.
+const RSR_IWDG1RSTF: u32 = 1 << 26;
+const RSR_RMVF: u32 = 1 << 16;
+
+pub struct Iwdg {
+    raw: pac::IWDG1,
+}
+
+impl Iwdg {
+    /// Start the watchdog with a timeout of roughly `timeout_ms`.  Once
+    /// started, it cannot be stopped.
+    pub fn start(raw: pac::IWDG1, timeout_ms: u32) -> Iwdg {
+        // Starting the watchdog also starts the LSI.
+        raw.kr.write(|w| unsafe { w.bits(KR_START) });
+
+        let mut pr = 0;
+        let mut ticks = timeout_ms.saturating_mul(LSI_TICKS_PER_MS) / 4;
+        while ticks > RLR_MAX + 1 && pr < PR_MAX {
+            pr += 1;
+            ticks /= 2;
+        }
+        let rlr = ticks.clamp(1, RLR_MAX + 1) - 1;
+
+        raw.kr.write(|w| unsafe { w.bits(KR_UNLOCK) });
+        raw.pr.write(|w| unsafe { w.bits(pr) });
+        raw.rlr.write(|w| unsafe { w.bits(rlr) });
+        while raw.sr.read().bits() & SR_BUSY != 0 {
+        }
+
+        let wdt = Iwdg { raw };
+        wdt.feed();
+        wdt
+    }
+}
+
+impl Watchdog for Iwdg {
+    fn feed(&self) {
+        self.raw.kr.write(|w| unsafe { w.bits(KR_FEED) });
+    }
+}
+
+/// Was the last reset caused by the watchdog?  Clears the reset flags, so this
+/// must be called only once, before the hal takes the RCC.
+pub fn reset_by_watchdog(rcc: &pac::RCC) -> bool {
+    let rsr = rcc.rsr.read().bits();
+    rcc.rsr.modify(|r, w| unsafe { w.bits(r.bits() | RSR_RMVF) });
+    rsr & RSR_IWDG1RSTF != 0
+}
@@ -4,23 +4,83 @@
 // use panic_semihosting as _;
 use panic_probe as _;
 use defmt_rtt as _;
-use defmt::{warn, info};
+use defmt::{error, info, warn, Debug2Format};
 
-use hal::{rcc::PllConfigStrategy, flash::UnlockedFlashBank};
+use core::cell::RefCell;
+
+use boot::{Image, MappedFlash, VerifiedFlash, WatchedFlash};
+use hal::rcc::PllConfigStrategy;
 use hal::pac;
-use hal::flash::FlashExt;
 use hal::gpio::GpioExt;
 use hal::pwr::PwrExt;
 use hal::rcc::RccExt;
 use fugit::RateExtU32;
-use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
 
 use stm32h7xx_hal as hal;
 
+mod bankswap;
+mod cache;
+mod cm4;
+mod flash;
+#[cfg(feature = "hw-hash")]
+mod hash;
+mod install;
+mod iwdg;
+mod options;
+mod qspi;
+mod rng;
+
+// Partitions, as offsets from the start of flash, as it is mapped.  The
+// bootloader has the first sector of bank 1, followed by the primary slot and
+// the CM4 slot.  Bank 2 is laid out the same way, and its primary slot is the
+// upgrade slot.
+const BOOT_BASE: usize = 0;
+const BOOT_SIZE: usize = 0x2_0000;
+const SLOT0_BASE: usize = 0x2_0000;
+const SLOT0_SIZE: usize = 0x8_0000;
+const CM4_BASE: usize = 0xa_0000;
+const CM4_SIZE: usize = 0x6_0000;
+const BANK2_BASE: usize = 0x10_0000;
+const SLOT1_BASE: usize = BANK2_BASE + SLOT0_BASE;
+const SLOT1_SIZE: usize = SLOT0_SIZE;
+
+/// Upgrade by swapping banks, rather than copying.
+const BANK_SWAP: bool = true;
+
+/// Keep the upgrade slot in external QSPI NOR, rather than in bank 2.  The
+/// upgrade is then installed by copying it over the primary slot.
+const UPGRADE_QSPI: bool = false;
+
+/// Read back each page as an upgrade is copied from QSPI, so a page that
+/// silently failed to program stops the copy.  Bank swaps don't copy.
+const VERIFY_WRITES: bool = true;
+
+/// The part of the QSPI NOR that is used, and the slot within it.
+const QSPI_SIZE: usize = 16 * 1024 * 1024;
+const QSPI_SLOT1_BASE: usize = 0;
+
+/// Divider from the 200 MHz kernel clock to the QSPI bus clock (+1).
+const QSPI_PRESCALER: u8 = 3;
+
+/// Watchdog timeout.  This must be long enough to cover the slowest single
+/// flash operation (a sector erase), and give the application time to start
+/// feeding it.
+const WATCHDOG_TIMEOUT_MS: u32 = 8_000;
+
+/// The key images must be signed with.
+static SIGNING_KEY: &[u8] = include_bytes!("../../../boot/data/ecdsa-p256-pub.der");
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let dp = pac::Peripherals::take().unwrap();
 
+    // The reset flags must be read before the hal takes the RCC.
+    let watchdog_reset = iwdg::reset_by_watchdog(&dp.RCC);
+    let wdt = iwdg::Iwdg::start(dp.IWDG1, WATCHDOG_TIMEOUT_MS);
+    // Started now, as it needs the RCC too.  Nothing here draws on it yet;
+    // it is for the hash seed of a swap's status, and the like.
+    let _entropy = rng::Rng::start(dp.RNG, &dp.RCC);
+
     // - power & clocks -------------------------------------------------------
 
     let pwr = dp.PWR.constrain();
@@ -36,55 +96,145 @@ fn main() -> ! {
 
     let gpiob = dp.GPIOB.split(ccdr.peripheral.GPIOB);
     let mut led_user = gpiob.pb14.into_push_pull_output();
-    led_user.set_low();
+    led_user.set_high();
 
-    warn!("Running");
-    info!("Dual bank? {}", dp.FLASH.dual_bank());
-    // - Flash memory.
-    let (mut flash1, mut flash2) = dp.FLASH.split();
-    info!("Capacity1: 0x{:x}", flash1.len());
-    // info!("Capacity2: 0x{:x}", flash2.len());
+    info!("---------- Start of bootloader ----------");
+    if watchdog_reset {
+        warn!("Reset by the watchdog");
+    }
 
-    if let Some(ref mut f2) = flash2 {
-        info!("Capacity2: 0x{:x}", f2.len());
+    // The CM4 must be held until its image is validated.
+    if cm4::configure() {
+        info!("CM4 option bytes changed, resetting");
+        cortex_m::peripheral::SCB::sys_reset();
     }
 
-    // Erase a ways in on bank 2.
-    {
-        let mut f = flash1.unlocked();
-        let esize = <UnlockedFlashBank as NorFlash>::ERASE_SIZE;
-        let wsize = <UnlockedFlashBank as NorFlash>::WRITE_SIZE;
-        info!("Erase size: {}", esize);
-        info!("Write size: {}", wsize);
-        f.erase(0x40000, 0x40000).unwrap();
-
-        // Write a boring pattern.
-        let mut buf = [0u8; 128];
-        for i in 0..128 {
-            buf[i as usize] = i;
+    // - flash ----------------------------------------------------------------
+
+    let flash = flash::H7Flash::new(dp.FLASH);
+    #[cfg(feature = "hw-hash")]
+    let mut crypto = hash::H7Hash::new();
+    #[cfg(not(feature = "hw-hash"))]
+    let mut crypto = boot::SoftCrypto::new();
+    info!("Banks swapped: {}", bankswap::swapped());
+
+    if UPGRADE_QSPI {
+        // The QSPI NOR on the STM32H745I Discovery board.
+        let gpiod = dp.GPIOD.split(ccdr.peripheral.GPIOD);
+        let gpioe = dp.GPIOE.split(ccdr.peripheral.GPIOE);
+        let gpiog = dp.GPIOG.split(ccdr.peripheral.GPIOG);
+        let _clk = gpiob.pb2.into_alternate::<9>();
+        let _ncs = gpiog.pg6.into_alternate::<10>();
+        let _io0 = gpiod.pd11.into_alternate::<9>();
+        let _io1 = gpiod.pd12.into_alternate::<9>();
+        let _io2 = gpioe.pe2.into_alternate::<9>();
+        let _io3 = gpiod.pd13.into_alternate::<9>();
+        ccdr.peripheral.QSPI.enable().reset();
+
+        let mut qspi = qspi::Qspi::new(dp.QUADSPI, QSPI_SIZE, QSPI_PRESCALER);
+        let src = WatchedFlash::new(qspi.partition(QSPI_SLOT1_BASE, SLOT1_SIZE).unwrap(), &wdt);
+        let dest = WatchedFlash::new(flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap(), &wdt);
+        let dest = VerifiedFlash::new(dest, VERIFY_WRITES);
+        if let Err(e) = install::try_install(src, dest, &mut crypto, SIGNING_KEY) {
+            warn!("Upgrade failed: {}", Debug2Format(&e));
+        }
+    } else if BANK_SWAP {
+        // A watchdog reset means the running image has hung.
+        if watchdog_reset {
+            let failed = WatchedFlash::new(flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap(), &wdt);
+            let previous = WatchedFlash::new(flash.partition(SLOT1_BASE, SLOT1_SIZE).unwrap(), &wdt);
+            if let Err(e) = bankswap::try_revert(failed, previous, &mut crypto, SIGNING_KEY) {
+                warn!("Revert failed: {}", Debug2Format(&e));
+            }
+        }
+
+        let banks = bankswap::Banks {
+            boot: WatchedFlash::new(flash.partition(BOOT_BASE, BOOT_SIZE).unwrap(), &wdt),
+            inactive_boot: WatchedFlash::new(flash.partition(BANK2_BASE + BOOT_BASE, BOOT_SIZE).unwrap(), &wdt),
+            upgrade: WatchedFlash::new(flash.partition(SLOT1_BASE, SLOT1_SIZE).unwrap(), &wdt),
+        };
+        if let Err(e) = bankswap::try_upgrade(banks, &mut crypto, SIGNING_KEY) {
+            warn!("Upgrade failed: {}", Debug2Format(&e));
         }
-        f.write(0x40000, &buf).unwrap();
     }
-    info!("Address of flash: 0x{:x}", flash1.address());
 
-    // Fault.
-    // let _ = unsafe { *(0x0040_0000 as *const u32) };
+    let slot0 = WatchedFlash::new(flash.partition(SLOT0_BASE, SLOT0_SIZE).unwrap(), &wdt);
+    let slot1 = flash.partition(SLOT1_BASE, SLOT1_SIZE).unwrap();
+    info!("slot0: 0x{:x}, slot1: 0x{:x}", slot0.get_base(), slot1.get_base());
+    let slot0 = RefCell::new(slot0);
 
-    // - main loop ------------------------------------------------------------
+    // - image ----------------------------------------------------------------
 
-    loop {
-        loop {
-            led_user.toggle();
-            for _ in 0..1 {
-                // cortex_m::asm::delay(480_000_000);
-                cortex_m::asm::delay(100_000_000);
+    let cm4_slot = RefCell::new(WatchedFlash::new(flash.partition(CM4_BASE, CM4_SIZE).unwrap(), &wdt));
+
+    let image = match Image::from_flash(&slot0) {
+        Ok(image) => image,
+        Err(e) => halt("No image in slot0", e),
+    };
+    info!("Image found, {} bytes", image.full_image_size());
+
+    if let Err(e) = image.validate_signed(&mut crypto, SIGNING_KEY) {
+        halt("Image is invalid", e);
+    }
+    info!("Image is valid");
+
+    // The CM4 image is optional, unless the CM7 image depends on it.
+    let cm4_image = match Image::from_flash(&cm4_slot) {
+        Ok(cm4_image) => match cm4_image.validate_signed(&mut crypto, SIGNING_KEY) {
+            Ok(()) => Some(cm4_image),
+            Err(e) => {
+                warn!("CM4 image is invalid: {}", Debug2Format(&e));
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    // Check the dependencies within the set.
+    let cm4_image = match cm4_image {
+        Some(cm4_image) => {
+            let versions = [image.version(), cm4_image.version()];
+            if let Err(e) = image.check_dependencies(&versions) {
+                halt("CM7 image dependencies not met", e);
+            }
+            match cm4_image.check_dependencies(&versions) {
+                Ok(()) => Some(cm4_image),
+                Err(_) => {
+                    warn!("CM4 image dependencies not met, leaving it in reset");
+                    None
+                }
+            }
+        }
+        None => {
+            if let Err(e) = image.check_dependencies(&[image.version()]) {
+                halt("CM7 image depends on a missing CM4 image", e);
+            }
+            None
+        }
+    };
+
+    if let Some(cm4_image) = cm4_image {
+        match cm4_image.get_image_base() {
+            Ok(base) => {
+                info!("Starting CM4 at 0x{:x}", base);
+                cm4::release(base);
             }
+            Err(_) => warn!("CM4 image is misaligned, leaving it in reset"),
         }
     }
+
+    led_user.set_low();
+    let base = image.get_image_base().unwrap_or_else(|e| halt("Image is misaligned", e));
+    info!("Chaining to 0x{:x}", base);
+    cache::before_chain();
+    let Err(e) = unsafe { boot::chain(&boot::CortexM, &image) };
+    halt("Image is misaligned", e)
 }
 
-// Make this a little easier to debug.
-#[inline(never)]
-fn show() {
-    warn!("Show what is happening");
+/// Report a failure to boot, and stop.
+fn halt(message: &str, e: boot::Error) -> ! {
+    error!("{}: {}", message, Debug2Format(&e));
+    loop {
+        cortex_m::asm::wfi();
+    }
 }
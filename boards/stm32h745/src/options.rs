@@ -0,0 +1,49 @@
+//! Option byte programming.
+//!
+//! The option bytes are changed by writing the PRG registers, and starting the
+//! programming, which updates the CUR registers.  Some options, such as
+//! SWAP_BANK and BCM4, only take effect at the next reset.
+
+use stm32h7xx_hal::pac;
+
+// Option byte unlock keys.
+const OPTKEY1: u32 = 0x0819_2a3b;
+const OPTKEY2: u32 = 0x4c5d_6e7f;
+
+// FLASH_OPTCR.
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTART: u32 = 1 << 1;
+
+// FLASH_OPTSR.
+const OPTSR_OPT_BUSY: u32 = 1 << 0;
+pub const OPTSR_BCM4: u32 = 1 << 22;
+pub const OPTSR_SWAP_BANK: u32 = 1 << 31;
+
+fn flash() -> &'static pac::flash::RegisterBlock {
+    unsafe { &*pac::FLASH::ptr() }
+}
+
+/// The current value of FLASH_OPTSR.
+pub fn optsr() -> u32 {
+    flash().optsr_cur.read().bits()
+}
+
+/// The current value of FLASH_BOOT4 (the CM4 boot addresses).
+pub fn boot4() -> u32 {
+    flash().boot4_curr.read().bits()
+}
+
+/// Program new values of FLASH_OPTSR and FLASH_BOOT4.
+pub fn program(optsr: u32, boot4: u32) {
+    let flash = flash();
+    if flash.optcr.read().bits() & OPTCR_OPTLOCK != 0 {
+        flash.optkeyr.write(|w| unsafe { w.bits(OPTKEY1) });
+        flash.optkeyr.write(|w| unsafe { w.bits(OPTKEY2) });
+    }
+    flash.optsr_prg.write(|w| unsafe { w.bits(optsr) });
+    flash.boot4_prgr.write(|w| unsafe { w.bits(boot4) });
+    flash.optcr.modify(|r, w| unsafe { w.bits(r.bits() | OPTCR_OPTSTART) });
+    while flash.optsr_cur.read().bits() & OPTSR_OPT_BUSY != 0 {
+    }
+    flash.optcr.modify(|r, w| unsafe { w.bits(r.bits() | OPTCR_OPTLOCK) });
+}
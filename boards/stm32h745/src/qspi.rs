@@ -0,0 +1,241 @@
+//! QUADSPI external NOR flash.
+//!
+//! Drives a quad SPI NOR flash (such as the MT25QL512 on the STM32H745I
+//! Discovery board) through the QUADSPI peripheral.  Reads go through the
+//! memory-mapped window at 0x9000_0000; programs and erases use indirect
+//! mode, which requires leaving memory-mapped mode first.  The device is used
+//! with 3-byte addresses, so only the first 16 MB are reachable.
+//!
+//! The hal configures the pins and the clock, and the peripheral itself is
+//! driven through its registers.
+
+use stm32h7xx_hal::pac;
+use storage::{Error, Flash, ReadFlash, Result};
+
+use crate::cache;
+
+/// The memory-mapped window.
+const MAPPED_BASE: usize = 0x9000_0000;
+
+/// Largest addressable size, with 3-byte addresses.
+const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// The device is programmed in pages, and erased in 4 KB subsectors.
+const PAGE_SIZE: usize = 256;
+const SUBSECTOR_SIZE: usize = 4096;
+
+// NOR commands.
+const CMD_WRITE_ENABLE: u32 = 0x06;
+const CMD_READ_STATUS: u32 = 0x05;
+const CMD_PAGE_PROGRAM: u32 = 0x02;
+const CMD_SUBSECTOR_ERASE: u32 = 0x20;
+const CMD_QUAD_OUTPUT_READ: u32 = 0x6b;
+
+/// Write in progress, in the status register.
+const STATUS_WIP: u32 = 1 << 0;
+
+// QUADSPI_CR.
+const CR_EN: u32 = 1 << 0;
+const CR_ABORT: u32 = 1 << 1;
+const CR_PRESCALER_SHIFT: u32 = 24;
+
+// QUADSPI_DCR.
+const DCR_FSIZE_SHIFT: u32 = 16;
+const DCR_CSHT_SHIFT: u32 = 8;
+
+// QUADSPI_SR, and the matching clear bits in QUADSPI_FCR.
+const SR_TCF: u32 = 1 << 1;
+const SR_FTF: u32 = 1 << 2;
+const SR_BUSY: u32 = 1 << 5;
+const FCR_ALL: u32 = 0x1b;
+
+// QUADSPI_CCR fields.  Modes are 1 for a single line, 3 for four lines.
+const CCR_IMODE_1: u32 = 1 << 8;
+const CCR_ADMODE_1: u32 = 1 << 10;
+const CCR_ADSIZE_24: u32 = 2 << 12;
+const CCR_DCYC_SHIFT: u32 = 18;
+const CCR_DMODE_1: u32 = 1 << 24;
+const CCR_DMODE_4: u32 = 3 << 24;
+const CCR_FMODE_WRITE: u32 = 0;
+const CCR_FMODE_READ: u32 = 1 << 26;
+const CCR_FMODE_MAPPED: u32 = 3 << 26;
+
+/// Dummy cycles of the quad output fast read.
+const READ_DUMMY: u32 = 8;
+
+pub struct Qspi {
+    raw: pac::QUADSPI,
+    size: usize,
+}
+
+impl Qspi {
+    /// Take over the peripheral, which must already be clocked and have its
+    /// pins configured.  `size` is the size of the device in bytes, and
+    /// `prescaler` divides the kernel clock for the bus.
+    pub fn new(raw: pac::QUADSPI, size: usize, prescaler: u8) -> Qspi {
+        assert!(size.is_power_of_two() && size <= MAX_SIZE);
+        let fsize = size.trailing_zeros() - 1;
+        raw.dcr.write(|w| unsafe {
+            w.bits((fsize << DCR_FSIZE_SHIFT) | (1 << DCR_CSHT_SHIFT))
+        });
+        raw.cr.write(|w| unsafe {
+            w.bits(((prescaler as u32) << CR_PRESCALER_SHIFT) | CR_EN)
+        });
+        let mut qspi = Qspi { raw, size };
+        qspi.memory_mapped();
+        qspi
+    }
+
+    /// Build a partition of the device.
+    pub fn partition(&mut self, base: usize, length: usize) -> Result<QspiPartition<'_>> {
+        match base.checked_add(length) {
+            Some(end) if length > 0 && end <= self.size => Ok(QspiPartition { qspi: self, base, length }),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Leave memory-mapped (or any other) mode.
+    fn abort(&mut self) {
+        self.raw.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_ABORT) });
+        while self.raw.cr.read().bits() & CR_ABORT != 0 {
+        }
+        while self.raw.sr.read().bits() & SR_BUSY != 0 {
+        }
+    }
+
+    /// Enter memory-mapped mode, using the quad output read.
+    fn memory_mapped(&mut self) {
+        self.raw.fcr.write(|w| unsafe { w.bits(FCR_ALL) });
+        self.raw.ccr.write(|w| unsafe {
+            w.bits(CMD_QUAD_OUTPUT_READ | CCR_IMODE_1 | CCR_ADMODE_1 | CCR_ADSIZE_24 |
+                   (READ_DUMMY << CCR_DCYC_SHIFT) | CCR_DMODE_4 | CCR_FMODE_MAPPED)
+        });
+    }
+
+    /// Run a command with no address or data.
+    fn command(&mut self, cmd: u32) {
+        self.raw.fcr.write(|w| unsafe { w.bits(FCR_ALL) });
+        self.raw.ccr.write(|w| unsafe { w.bits(cmd | CCR_IMODE_1 | CCR_FMODE_WRITE) });
+        self.wait_complete();
+    }
+
+    /// Run a command with an address, and optionally data to write.
+    fn command_addr(&mut self, cmd: u32, addr: usize, data: &[u8]) {
+        self.raw.fcr.write(|w| unsafe { w.bits(FCR_ALL) });
+        let mut ccr = cmd | CCR_IMODE_1 | CCR_ADMODE_1 | CCR_ADSIZE_24 | CCR_FMODE_WRITE;
+        if !data.is_empty() {
+            ccr |= CCR_DMODE_1;
+            self.raw.dlr.write(|w| unsafe { w.bits(data.len() as u32 - 1) });
+        }
+        self.raw.ccr.write(|w| unsafe { w.bits(ccr) });
+        self.raw.ar.write(|w| unsafe { w.bits(addr as u32) });
+
+        // The data register takes single bytes with a byte access.
+        let dr = self.raw.dr.as_ptr() as *mut u8;
+        for &byte in data {
+            while self.raw.sr.read().bits() & SR_FTF == 0 {
+            }
+            unsafe { dr.write_volatile(byte) };
+        }
+        self.wait_complete();
+    }
+
+    /// Read the status register.
+    fn status(&mut self) -> u32 {
+        self.raw.fcr.write(|w| unsafe { w.bits(FCR_ALL) });
+        self.raw.dlr.write(|w| unsafe { w.bits(0) });
+        self.raw.ccr.write(|w| unsafe {
+            w.bits(CMD_READ_STATUS | CCR_IMODE_1 | CCR_DMODE_1 | CCR_FMODE_READ)
+        });
+        let dr = self.raw.dr.as_ptr() as *const u8;
+        while self.raw.sr.read().bits() & (SR_FTF | SR_TCF) == 0 {
+        }
+        let status = unsafe { dr.read_volatile() } as u32;
+        self.wait_complete();
+        status
+    }
+
+    fn wait_complete(&mut self) {
+        while self.raw.sr.read().bits() & SR_TCF == 0 {
+        }
+        self.raw.fcr.write(|w| unsafe { w.bits(SR_TCF) });
+    }
+
+    /// Wait for a program or erase to finish.
+    fn wait_ready(&mut self) {
+        while self.status() & STATUS_WIP != 0 {
+        }
+    }
+
+    /// Perform a program or erase operation, leaving and returning to
+    /// memory-mapped mode around it.
+    fn modify(&mut self, cmd: u32, addr: usize, data: &[u8]) {
+        self.abort();
+        self.command(CMD_WRITE_ENABLE);
+        self.command_addr(cmd, addr, data);
+        self.wait_ready();
+        self.memory_mapped();
+    }
+}
+
+pub struct QspiPartition<'a> {
+    qspi: &'a mut Qspi,
+    base: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for QspiPartition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let addr = MAPPED_BASE + self.base + offset;
+        let slice = unsafe {
+            core::slice::from_raw_parts(addr as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+}
+
+impl<'a> Flash for QspiPartition<'a> {
+    fn write_size(&self) -> usize {
+        1
+    }
+
+    fn erase_size(&self) -> usize {
+        SUBSECTOR_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+        for addr in (from..to).step_by(SUBSECTOR_SIZE) {
+            self.qspi.modify(CMD_SUBSECTOR_ERASE, self.base + addr, &[]);
+        }
+        cache::flash_changed(MAPPED_BASE + self.base + from, to - from);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+
+        // A page program can't cross a page boundary.
+        let mut addr = self.base + offset;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let room = PAGE_SIZE - addr % PAGE_SIZE;
+            let (chunk, rest) = bytes.split_at(room.min(bytes.len()));
+            self.qspi.modify(CMD_PAGE_PROGRAM, addr, chunk);
+            addr += chunk.len();
+            bytes = rest;
+        }
+        cache::flash_changed(MAPPED_BASE + self.base + offset, addr - self.base - offset);
+        Ok(())
+    }
+}
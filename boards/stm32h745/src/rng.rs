@@ -0,0 +1,77 @@
+//! STM32H7 true random number generator.
+//!
+//! The RNG is clocked from the HSI48, which is started here, before the hal
+//! takes the RCC.  The peripheral runs its own health checks, and a clock or
+//! seed error fails the read, rather than giving data that may not be random.
+//! The data register reads as zero after a seed error, so the status is
+//! checked again after each word is taken.
+//!
+//! The hal's driver needs the clocks frozen first, and its own RNG token, so
+//! this uses the raw peripheral, as the watchdog does.
+
+use boot::{EntropyError, EntropySource};
+use stm32h7xx_hal::pac;
+
+// RCC_CR: HSI48 enable and ready.
+const RCC_CR_HSI48ON: u32 = 1 << 12;
+const RCC_CR_HSI48RDY: u32 = 1 << 13;
+
+// RCC_AHB2ENR: the RNG clock.  RNGSEL in D2CCIP2R is left at its reset
+// value, which selects the HSI48.
+const AHB2ENR_RNGEN: u32 = 1 << 6;
+
+// RNG_CR.
+const CR_RNGEN: u32 = 1 << 2;
+
+// RNG_SR: data ready, and the current clock and seed errors.
+const SR_DRDY: u32 = 1 << 0;
+const SR_CECS: u32 = 1 << 1;
+const SR_SECS: u32 = 1 << 2;
+
+/// Polls of the status for each word before giving up.  A word takes a few
+/// hundred cycles of the core at most.
+const READY_POLLS: u32 = 100_000;
+
+pub struct Rng {
+    raw: pac::RNG,
+}
+
+impl Rng {
+    /// Start the HSI48 and the RNG.  This must be called before the hal takes
+    /// the RCC.
+    pub fn start(raw: pac::RNG, rcc: &pac::RCC) -> Rng {
+        rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | RCC_CR_HSI48ON) });
+        while rcc.cr.read().bits() & RCC_CR_HSI48RDY == 0 {
+        }
+        rcc.ahb2enr.modify(|r, w| unsafe { w.bits(r.bits() | AHB2ENR_RNGEN) });
+        raw.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_RNGEN) });
+        Rng { raw }
+    }
+
+    fn word(&mut self) -> Result<u32, EntropyError> {
+        for _ in 0..READY_POLLS {
+            let sr = self.raw.sr.read().bits();
+            if sr & (SR_CECS | SR_SECS) != 0 {
+                return Err(EntropyError::Health);
+            }
+            if sr & SR_DRDY != 0 {
+                let word = self.raw.dr.read().bits();
+                if self.raw.sr.read().bits() & SR_SECS != 0 {
+                    return Err(EntropyError::Health);
+                }
+                return Ok(word);
+            }
+        }
+        Err(EntropyError::Timeout)
+    }
+}
+
+impl EntropySource for Rng {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        for chunk in buf.chunks_mut(4) {
+            let word = self.word()?.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
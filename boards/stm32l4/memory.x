@@ -0,0 +1,12 @@
+/* STM32L476RG, 1 MB of flash in two banks of 2 KB pages.  The layout must
+   agree with src/main.rs.
+   0x08000000 - 64k  - bootloader
+   0x08010000 - 448k - slot 0
+   0x08080000 - 448k - slot 1 (the start of bank 2)
+   0x080f0000 - 64k  - unused
+*/
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+  RAM : ORIGIN = 0x20000000, LENGTH = 96K
+}
@@ -0,0 +1,187 @@
+//! STM32L4 flash driver.
+//!
+//! The STM32L4 flash is made of small, uniform 2 KB pages, written a double
+//! word (8 bytes) at a time.  Each double word carries ECC, so it can only be
+//! written once between erases.  The 1 MB parts have two banks of 256 pages,
+//! and pages are numbered within their bank.
+//!
+//! To use this driver, give it the FLASH peripheral from the PAC.
+//!
+//!     let flash = flash::L4Flash::new(dp.FLASH);
+
+use core::cell::RefCell;
+
+use boot::MappedFlash;
+use stm32l4xx_hal::pac;
+use storage::{Error, Flash, ReadFlash, Result};
+
+pub const FLASH_BASE: usize = 0x0800_0000;
+pub const FLASH_SIZE: usize = 1024 * 1024;
+pub const BANK_SIZE: usize = FLASH_SIZE / 2;
+
+pub const PAGE_SIZE: usize = 2048;
+pub const WRITE_SIZE: usize = 8;
+
+// Unlock keys.
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+// FLASH_CR.
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_PNB_SHIFT: u32 = 3;
+const CR_BKER: u32 = 1 << 11;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+// FLASH_SR.
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = 0xc3fa;
+
+// FLASH_ACR.
+const ACR_ICEN: u32 = 1 << 9;
+const ACR_DCEN: u32 = 1 << 10;
+const ACR_ICRST: u32 = 1 << 11;
+const ACR_DCRST: u32 = 1 << 12;
+
+pub struct L4Flash {
+    raw: RefCell<pac::FLASH>,
+}
+
+impl L4Flash {
+    pub fn new(raw: pac::FLASH) -> L4Flash {
+        L4Flash { raw: RefCell::new(raw) }
+    }
+
+    /// Build a partition, given its offset from the start of flash.
+    pub fn partition(&self, base: usize, length: usize) -> Result<L4Partition<'_>> {
+        if length == 0 || base > FLASH_SIZE || length > FLASH_SIZE - base {
+            return Err(Error::OutOfBounds);
+        }
+        if !base.is_multiple_of(PAGE_SIZE) || !length.is_multiple_of(PAGE_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        Ok(L4Partition { raw: &self.raw, base, length })
+    }
+}
+
+pub struct L4Partition<'a> {
+    raw: &'a RefCell<pac::FLASH>,
+    base: usize,
+    length: usize,
+}
+
+impl<'a> ReadFlash for L4Partition<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn capacity(&self) -> usize {
+        self.length
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, buf.len())?;
+        let slice = unsafe {
+            core::slice::from_raw_parts((FLASH_BASE + self.base + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(slice);
+        Ok(())
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        Some(FLASH_BASE + self.base)
+    }
+}
+
+impl<'a> Flash for L4Partition<'a> {
+    fn write_size(&self) -> usize {
+        WRITE_SIZE
+    }
+
+    fn erase_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        storage::check_erase(self, from, to)?;
+
+        let raw = self.raw.borrow();
+        unlock(&raw);
+        let mut result = Ok(());
+        for page in (self.base + from..self.base + to).step_by(PAGE_SIZE) {
+            let bank = if page >= BANK_SIZE { CR_BKER } else { 0 };
+            let pnb = ((page % BANK_SIZE) / PAGE_SIZE) as u32;
+            raw.cr.write(|w| unsafe { w.bits(CR_PER | bank | (pnb << CR_PNB_SHIFT)) });
+            raw.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_STRT) });
+            if let Err(e) = wait(&raw) {
+                result = Err(e);
+                break;
+            }
+        }
+        raw.cr.write(|w| unsafe { w.bits(CR_LOCK) });
+        flush_caches(&raw);
+        result
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+
+        let raw = self.raw.borrow();
+        unlock(&raw);
+        raw.cr.write(|w| unsafe { w.bits(CR_PG) });
+        let base = FLASH_BASE + self.base + offset;
+        let mut result = Ok(());
+        for (i, dword) in bytes.chunks_exact(WRITE_SIZE).enumerate() {
+            // The two words must be written back to back, low word first.
+            let lo = u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]);
+            let hi = u32::from_le_bytes([dword[4], dword[5], dword[6], dword[7]]);
+            let addr = (base + i * WRITE_SIZE) as *mut u32;
+            unsafe {
+                core::ptr::write_volatile(addr, lo);
+                core::ptr::write_volatile(addr.add(1), hi);
+            }
+            if let Err(e) = wait(&raw) {
+                result = Err(e);
+                break;
+            }
+        }
+        raw.cr.write(|w| unsafe { w.bits(CR_LOCK) });
+        flush_caches(&raw);
+        result
+    }
+}
+
+impl<'a> MappedFlash for L4Partition<'a> {
+    fn get_base(&self) -> usize {
+        FLASH_BASE + self.base
+    }
+}
+
+fn unlock(raw: &pac::FLASH) {
+    if raw.cr.read().bits() & CR_LOCK != 0 {
+        raw.keyr.write(|w| unsafe { w.bits(KEY1) });
+        raw.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+/// Wait for an operation to finish, and report (and clear) any errors.
+fn wait(raw: &pac::FLASH) -> Result<()> {
+    while raw.sr.read().bits() & SR_BSY != 0 {
+    }
+    let errors = raw.sr.read().bits() & SR_ERRORS;
+    if errors != 0 {
+        raw.sr.write(|w| unsafe { w.bits(errors) });
+        return Err(Error::Failed);
+    }
+    Ok(())
+}
+
+/// The ART accelerator's caches may hold old contents after a change to the
+/// flash.  They can only be reset while disabled.
+fn flush_caches(raw: &pac::FLASH) {
+    let acr = raw.acr.read().bits();
+    raw.acr.write(|w| unsafe { w.bits(acr & !(ACR_ICEN | ACR_DCEN)) });
+    raw.acr.write(|w| unsafe { w.bits((acr & !(ACR_ICEN | ACR_DCEN)) | ACR_ICRST | ACR_DCRST) });
+    raw.acr.write(|w| unsafe { w.bits(acr & !(ACR_ICRST | ACR_DCRST)) });
+}
@@ -0,0 +1,151 @@
+#ifndef MCUBOOT_RS_H
+#define MCUBOOT_RS_H
+
+/* Generated by cbindgen from boot-ffi.  Do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Returned by a slot's read function for flash that is erased, on devices
+ * where that can't be read.  Any other value but 0 is a failure.
+ */
+#define MCUBOOT_FLASH_NOT_WRITTEN 1
+
+/**
+ * The result of each function.
+ */
+typedef enum McubootStatus {
+  MCUBOOT_STATUS_OK = 0,
+  /**
+   * Reading, writing, or erasing the flash failed.
+   */
+  MCUBOOT_STATUS_FLASH = -1,
+  /**
+   * The image failed to validate.
+   */
+  MCUBOOT_STATUS_INVALID_IMAGE = -2,
+  /**
+   * The image doesn't fit, or the slots can't be swapped.
+   */
+  MCUBOOT_STATUS_CANNOT_UPGRADE = -3,
+  /**
+   * A pointer was null, or a flash function missing.
+   */
+  MCUBOOT_STATUS_INVALID_ARGUMENT = -4,
+} McubootStatus;
+
+/**
+ * The swap the bootloader will do on the next boot.
+ */
+typedef enum McubootSwapType {
+  MCUBOOT_SWAP_TYPE_NONE = 0,
+  MCUBOOT_SWAP_TYPE_TEST = 1,
+  MCUBOOT_SWAP_TYPE_PERM = 2,
+  MCUBOOT_SWAP_TYPE_REVERT = 3,
+} McubootSwapType;
+
+/**
+ * A slot, as the C code provides it.
+ */
+typedef struct McubootFlash {
+  /**
+   * Passed to each of the functions.
+   */
+  void *ctx;
+  uint32_t read_size;
+  uint32_t write_size;
+  uint32_t erase_size;
+  /**
+   * The size of the slot, in bytes.
+   */
+  uint32_t capacity;
+  int32_t (*read)(void *ctx, uint32_t offset, uint8_t *buf, uint32_t len);
+  int32_t (*write)(void *ctx, uint32_t offset, const uint8_t *buf, uint32_t len);
+  /**
+   * Erase from `from` up to `to`, both multiples of the erase size.
+   */
+  int32_t (*erase)(void *ctx, uint32_t from, uint32_t to);
+} McubootFlash;
+
+/**
+ * An image's version, as in its header.
+ */
+typedef struct McubootVersion {
+  uint8_t major;
+  uint8_t minor;
+  uint16_t revision;
+  uint32_t build_num;
+} McubootVersion;
+
+/**
+ * The state of the pair of slots, from the application's point of view.
+ */
+typedef struct McubootState {
+  McubootSwapType swap_type;
+  /**
+   * The image in the primary slot has been confirmed.
+   */
+  bool confirmed;
+  /**
+   * Whether each slot holds an image, and if so, its version.
+   */
+  bool has_primary;
+  McubootVersion primary_version;
+  bool has_secondary;
+  McubootVersion secondary_version;
+} McubootState;
+
+/**
+ * Check the image in `slot` against its SHA256.
+ *
+ * # Safety
+ *
+ * `slot` must point to a valid `McubootFlash`.
+ */
+McubootStatus mcuboot_validate(const McubootFlash *slot);
+
+/**
+ * Check the image in `slot`, requiring a signature made with `key`, a DER
+ * SubjectPublicKeyInfo of `key_len` bytes.
+ *
+ * # Safety
+ *
+ * `slot` must point to a valid `McubootFlash`, and `key` to `key_len` bytes.
+ */
+McubootStatus mcuboot_validate_signed(const McubootFlash *slot, const uint8_t *key, size_t key_len);
+
+/**
+ * Fill in `state` from the trailers and headers of the two slots.
+ *
+ * # Safety
+ *
+ * The slots must point to valid `McubootFlash`es, and `state` to an
+ * `McubootState`.
+ */
+McubootStatus mcuboot_boot_state(const McubootFlash *primary,
+                                 const McubootFlash *secondary,
+                                 McubootState *state);
+
+/**
+ * Mark the image in `secondary` to be installed on the next boot, for a test
+ * boot, or, if `permanent`, for good.
+ *
+ * # Safety
+ *
+ * `secondary` must point to a valid `McubootFlash`.
+ */
+McubootStatus mcuboot_set_pending(const McubootFlash *secondary, bool permanent);
+
+/**
+ * Confirm the running image, in `primary`, so it isn't reverted.  Does
+ * nothing if it already is.
+ *
+ * # Safety
+ *
+ * `primary` must point to a valid `McubootFlash`.
+ */
+McubootStatus mcuboot_set_confirmed(const McubootFlash *primary);
+
+#endif /* MCUBOOT_RS_H */
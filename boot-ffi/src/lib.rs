@@ -0,0 +1,294 @@
+//! A C interface to the boot crate
+//!
+//! Existing C firmware can adopt the Rust bootloader a piece at a time, and
+//! its application can use the same trailer handling as the bootloader,
+//! through these functions.  The library builds as a static library, and
+//! `include/mcuboot_rs.h` (generated with cbindgen) declares them:
+//!
+//! ```text
+//! cargo build --release --no-default-features --target thumbv7em-none-eabihf
+//! ```
+//!
+//! The C code describes each slot with an `McubootFlash`: its geometry, and
+//! functions to read, write and erase it, given an offset within the slot.
+//! These return 0 on success, or `MCUBOOT_FLASH_NOT_WRITTEN` from a read of
+//! erased flash that can't be read, as on the LPC55.  Every function returns an `McubootStatus`,
+//! which is 0 on success, and negative on failure.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::{cell::RefCell, ffi::c_void, slice};
+
+use boot::{
+    boot_state, confirm, is_confirmed, request_upgrade, Error, Image, ImageVersion, SoftCrypto,
+    SwapType,
+};
+use storage::{check_erase, check_read, check_write, Flash, ReadFlash};
+
+/// Returned by a slot's read function for flash that is erased, on devices
+/// where that can't be read.  Any other value but 0 is a failure.
+pub const MCUBOOT_FLASH_NOT_WRITTEN: i32 = 1;
+
+/// The result of each function.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub enum McubootStatus {
+    Ok = 0,
+    /// Reading, writing, or erasing the flash failed.
+    Flash = -1,
+    /// The image failed to validate.
+    InvalidImage = -2,
+    /// The image doesn't fit, or the slots can't be swapped.
+    CannotUpgrade = -3,
+    /// A pointer was null, or a flash function missing.
+    InvalidArgument = -4,
+}
+
+impl From<Error> for McubootStatus {
+    fn from(err: Error) -> McubootStatus {
+        match err {
+            Error::Flash(_) => McubootStatus::Flash,
+            Error::InvalidImage => McubootStatus::InvalidImage,
+            Error::CannotUpgrade => McubootStatus::CannotUpgrade,
+        }
+    }
+}
+
+impl From<boot::Result<()>> for McubootStatus {
+    fn from(result: boot::Result<()>) -> McubootStatus {
+        result.map_or_else(McubootStatus::from, |()| McubootStatus::Ok)
+    }
+}
+
+/// A slot, as the C code provides it.
+#[repr(C)]
+pub struct McubootFlash {
+    /// Passed to each of the functions.
+    pub ctx: *mut c_void,
+    pub read_size: u32,
+    pub write_size: u32,
+    pub erase_size: u32,
+    /// The size of the slot, in bytes.
+    pub capacity: u32,
+    pub read: Option<unsafe extern "C" fn(ctx: *mut c_void, offset: u32, buf: *mut u8, len: u32) -> i32>,
+    pub write: Option<unsafe extern "C" fn(ctx: *mut c_void, offset: u32, buf: *const u8, len: u32) -> i32>,
+    /// Erase from `from` up to `to`, both multiples of the erase size.
+    pub erase: Option<unsafe extern "C" fn(ctx: *mut c_void, from: u32, to: u32) -> i32>,
+}
+
+/// The swap the bootloader will do on the next boot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub enum McubootSwapType {
+    None = 0,
+    Test = 1,
+    Perm = 2,
+    Revert = 3,
+}
+
+impl From<SwapType> for McubootSwapType {
+    fn from(kind: SwapType) -> McubootSwapType {
+        match kind {
+            SwapType::None => McubootSwapType::None,
+            SwapType::Test => McubootSwapType::Test,
+            SwapType::Perm => McubootSwapType::Perm,
+            SwapType::Revert => McubootSwapType::Revert,
+        }
+    }
+}
+
+/// An image's version, as in its header.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct McubootVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build_num: u32,
+}
+
+impl From<ImageVersion> for McubootVersion {
+    fn from(ver: ImageVersion) -> McubootVersion {
+        McubootVersion { major: ver.major, minor: ver.minor, revision: ver.revision, build_num: ver.build_num }
+    }
+}
+
+/// The state of the pair of slots, from the application's point of view.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct McubootState {
+    pub swap_type: McubootSwapType,
+    /// The image in the primary slot has been confirmed.
+    pub confirmed: bool,
+    /// Whether each slot holds an image, and if so, its version.
+    pub has_primary: bool,
+    pub primary_version: McubootVersion,
+    pub has_secondary: bool,
+    pub secondary_version: McubootVersion,
+}
+
+/// An `McubootFlash`, as the boot crate's flash.
+struct CFlash<'a>(&'a McubootFlash);
+
+impl CFlash<'_> {
+    /// The flash behind `ptr`, if it is usable.
+    unsafe fn new<'a>(ptr: *const McubootFlash) -> Option<CFlash<'a>> {
+        let flash = ptr.as_ref()?;
+        let valid = flash.read.is_some() && flash.write.is_some() && flash.erase.is_some() &&
+            flash.read_size > 0 && flash.write_size > 0 && flash.erase_size > 0;
+        valid.then_some(CFlash(flash))
+    }
+}
+
+fn result(code: i32) -> storage::Result<()> {
+    match code {
+        0 => Ok(()),
+        MCUBOOT_FLASH_NOT_WRITTEN => Err(storage::Error::NotWritten),
+        _ => Err(storage::Error::Failed),
+    }
+}
+
+impl ReadFlash for CFlash<'_> {
+    fn read_size(&self) -> usize {
+        self.0.read_size as usize
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        check_read(self, offset, bytes.len())?;
+        let read = self.0.read.ok_or(storage::Error::Failed)?;
+        result(unsafe { read(self.0.ctx, offset as u32, bytes.as_mut_ptr(), bytes.len() as u32) })
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.capacity as usize
+    }
+}
+
+impl Flash for CFlash<'_> {
+    fn write_size(&self) -> usize {
+        self.0.write_size as usize
+    }
+
+    fn erase_size(&self) -> usize {
+        self.0.erase_size as usize
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> storage::Result<()> {
+        check_erase(self, from, to)?;
+        let erase = self.0.erase.ok_or(storage::Error::Failed)?;
+        result(unsafe { erase(self.0.ctx, from as u32, to as u32) })
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> storage::Result<()> {
+        check_write(self, offset, bytes.len())?;
+        let write = self.0.write.ok_or(storage::Error::Failed)?;
+        result(unsafe { write(self.0.ctx, offset as u32, bytes.as_ptr(), bytes.len() as u32) })
+    }
+}
+
+/// Check the image in `slot` against its SHA256.
+///
+/// # Safety
+///
+/// `slot` must point to a valid `McubootFlash`.
+#[no_mangle]
+pub unsafe extern "C" fn mcuboot_validate(slot: *const McubootFlash) -> McubootStatus {
+    let Some(flash) = CFlash::new(slot) else {
+        return McubootStatus::InvalidArgument;
+    };
+    let flash = RefCell::new(flash);
+    Image::from_flash(&flash).and_then(|image| image.validate()).into()
+}
+
+/// Check the image in `slot`, requiring a signature made with `key`, a DER
+/// SubjectPublicKeyInfo of `key_len` bytes.
+///
+/// # Safety
+///
+/// `slot` must point to a valid `McubootFlash`, and `key` to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mcuboot_validate_signed(slot: *const McubootFlash, key: *const u8,
+                                                 key_len: usize) -> McubootStatus {
+    let Some(flash) = CFlash::new(slot) else {
+        return McubootStatus::InvalidArgument;
+    };
+    if key.is_null() {
+        return McubootStatus::InvalidArgument;
+    }
+    let key = slice::from_raw_parts(key, key_len);
+    let flash = RefCell::new(flash);
+    Image::from_flash(&flash)
+        .and_then(|image| image.validate_signed(&mut SoftCrypto::new(), key))
+        .into()
+}
+
+/// Fill in `state` from the trailers and headers of the two slots.
+///
+/// # Safety
+///
+/// The slots must point to valid `McubootFlash`es, and `state` to an
+/// `McubootState`.
+#[no_mangle]
+pub unsafe extern "C" fn mcuboot_boot_state(primary: *const McubootFlash, secondary: *const McubootFlash,
+                                            state: *mut McubootState) -> McubootStatus {
+    let (Some(primary), Some(secondary), Some(state)) = (CFlash::new(primary), CFlash::new(secondary), state.as_mut())
+    else {
+        return McubootStatus::InvalidArgument;
+    };
+    match boot_state(&RefCell::new(primary), &RefCell::new(secondary)) {
+        Ok(found) => {
+            *state = McubootState {
+                swap_type: found.swap_type.into(),
+                confirmed: found.confirmed,
+                has_primary: found.primary_version.is_some(),
+                primary_version: found.primary_version.map(Into::into).unwrap_or_default(),
+                has_secondary: found.secondary_version.is_some(),
+                secondary_version: found.secondary_version.map(Into::into).unwrap_or_default(),
+            };
+            McubootStatus::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Mark the image in `secondary` to be installed on the next boot, for a test
+/// boot, or, if `permanent`, for good.
+///
+/// # Safety
+///
+/// `secondary` must point to a valid `McubootFlash`.
+#[no_mangle]
+pub unsafe extern "C" fn mcuboot_set_pending(secondary: *const McubootFlash, permanent: bool) -> McubootStatus {
+    let Some(mut flash) = CFlash::new(secondary) else {
+        return McubootStatus::InvalidArgument;
+    };
+    request_upgrade(&mut flash)
+        .and_then(|()| if permanent { confirm(&mut flash) } else { Ok(()) })
+        .into()
+}
+
+/// Confirm the running image, in `primary`, so it isn't reverted.  Does
+/// nothing if it already is.
+///
+/// # Safety
+///
+/// `primary` must point to a valid `McubootFlash`.
+#[no_mangle]
+pub unsafe extern "C" fn mcuboot_set_confirmed(primary: *const McubootFlash) -> McubootStatus {
+    let Some(mut flash) = CFlash::new(primary) else {
+        return McubootStatus::InvalidArgument;
+    };
+    match is_confirmed(&mut flash) {
+        Ok(true) => McubootStatus::Ok,
+        Ok(false) => confirm(&mut flash).into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
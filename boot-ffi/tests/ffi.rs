@@ -0,0 +1,107 @@
+// The C interface, called as C would, on simulated flash.
+
+use std::ffi::c_void;
+use std::ptr;
+
+use boot_ffi::{
+    mcuboot_boot_state, mcuboot_set_confirmed, mcuboot_set_pending, mcuboot_validate,
+    mcuboot_validate_signed, McubootFlash, MCUBOOT_FLASH_NOT_WRITTEN, McubootState, McubootStatus, McubootSwapType, McubootVersion,
+};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../../boot/data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../../boot/data/ecdsa-p256-pub.der");
+
+unsafe extern "C" fn read(ctx: *mut c_void, offset: u32, buf: *mut u8, len: u32) -> i32 {
+    let flash = &mut *(ctx as *mut SimFlash);
+    let buf = std::slice::from_raw_parts_mut(buf, len as usize);
+    match flash.read(offset as usize, buf) {
+        Ok(()) => 0,
+        Err(storage::Error::NotWritten) => MCUBOOT_FLASH_NOT_WRITTEN,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn write(ctx: *mut c_void, offset: u32, buf: *const u8, len: u32) -> i32 {
+    let flash = &mut *(ctx as *mut SimFlash);
+    let buf = std::slice::from_raw_parts(buf, len as usize);
+    flash.write(offset as usize, buf).map_or(-1, |()| 0)
+}
+
+unsafe extern "C" fn erase(ctx: *mut c_void, from: u32, to: u32) -> i32 {
+    let flash = &mut *(ctx as *mut SimFlash);
+    flash.erase(from as usize, to as usize).map_or(-1, |()| 0)
+}
+
+fn describe(flash: &mut SimFlash) -> McubootFlash {
+    McubootFlash {
+        read_size: flash.read_size() as u32,
+        write_size: flash.write_size() as u32,
+        erase_size: flash.erase_size() as u32,
+        capacity: flash.capacity() as u32,
+        ctx: flash as *mut SimFlash as *mut c_void,
+        read: Some(read),
+        write: Some(write),
+        erase: Some(erase),
+    }
+}
+
+fn slot() -> SimFlash {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.load(IMAGE).unwrap();
+    flash
+}
+
+#[test]
+fn validate() {
+    let mut flash = slot();
+    let slot = describe(&mut flash);
+    unsafe {
+        assert_eq!(mcuboot_validate_signed(&slot, KEY.as_ptr(), KEY.len()), McubootStatus::Ok);
+        let mut bad_key = KEY.to_vec();
+        bad_key[40] ^= 1;
+        assert_eq!(mcuboot_validate_signed(&slot, bad_key.as_ptr(), bad_key.len()), McubootStatus::InvalidImage);
+        // The signature can't be checked without a key.
+        assert_eq!(mcuboot_validate(&slot), McubootStatus::InvalidImage);
+
+        assert_eq!(mcuboot_validate(ptr::null()), McubootStatus::InvalidArgument);
+        let missing = McubootFlash { erase: None, ..describe(&mut flash) };
+        assert_eq!(mcuboot_validate(&missing), McubootStatus::InvalidArgument);
+    }
+}
+
+#[test]
+fn pending_and_confirmed() {
+    let mut primary = slot();
+    let mut secondary = slot();
+    let primary = describe(&mut primary);
+    let secondary = describe(&mut secondary);
+    let version = McubootVersion { major: 0, minor: 0, revision: 0, build_num: 0 };
+
+    let mut state = McubootState {
+        swap_type: McubootSwapType::Revert,
+        confirmed: true,
+        has_primary: false,
+        primary_version: version,
+        has_secondary: false,
+        secondary_version: version,
+    };
+    unsafe {
+        assert_eq!(mcuboot_boot_state(&primary, &secondary, &mut state), McubootStatus::Ok);
+        assert_eq!(state.swap_type, McubootSwapType::None);
+        assert!(!state.confirmed);
+        assert!(state.has_primary && state.has_secondary);
+
+        assert_eq!(mcuboot_set_pending(&secondary, false), McubootStatus::Ok);
+        assert_eq!(mcuboot_boot_state(&primary, &secondary, &mut state), McubootStatus::Ok);
+        assert_eq!(state.swap_type, McubootSwapType::Test);
+
+        assert_eq!(mcuboot_set_confirmed(&primary), McubootStatus::Ok);
+        assert_eq!(mcuboot_set_confirmed(&primary), McubootStatus::Ok);
+        assert_eq!(mcuboot_boot_state(&primary, &secondary, &mut state), McubootStatus::Ok);
+        assert!(state.confirmed);
+
+        assert_eq!(mcuboot_boot_state(&primary, &secondary, ptr::null_mut()), McubootStatus::InvalidArgument);
+    }
+}
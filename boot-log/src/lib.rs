@@ -0,0 +1,50 @@
+//! Loggers for the boot crate
+//!
+//! The boot crate doesn't depend on any logging framework.  A board installs
+//! one of these with `boot::set_logger`, choosing the framework by feature:
+//!
+//! - `defmt`: `Defmt` sends the messages to defmt, typically over RTT.  The
+//!   boot crate formats them, so they are sent as strings.
+//! - `log`: `LogFacade` sends them to the `log` crate, under the target
+//!   "boot".
+
+#![no_std]
+
+#[allow(unused_imports)]
+use boot::{Level, Log};
+#[allow(unused_imports)]
+use core::fmt;
+
+/// Log to defmt.
+#[cfg(feature = "defmt")]
+pub struct Defmt;
+
+#[cfg(feature = "defmt")]
+impl Log for Defmt {
+    fn log(&self, level: Level, args: fmt::Arguments<'_>) {
+        let args = defmt::Display2Format(&args);
+        match level {
+            Level::Error => defmt::error!("{}", args),
+            Level::Warn => defmt::warn!("{}", args),
+            Level::Info => defmt::info!("{}", args),
+            Level::Debug => defmt::debug!("{}", args),
+        }
+    }
+}
+
+/// Log to the `log` crate.
+#[cfg(feature = "log")]
+pub struct LogFacade;
+
+#[cfg(feature = "log")]
+impl Log for LogFacade {
+    fn log(&self, level: Level, args: fmt::Arguments<'_>) {
+        let level = match level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+        };
+        log::log!(target: "boot", level, "{}", args);
+    }
+}
@@ -0,0 +1,66 @@
+#! /usr/bin/env python3
+
+# Trailers as C MCUboot's bootutil writes them, for the golden vector tests.
+#
+# Each file is the last 256 bytes of a slot.  The fields are placed as
+# boot_magic_off(), boot_image_ok_off(), boot_copy_done_off() and
+# boot_swap_info_off() place them, for a BOOT_MAX_ALIGN of 8 (the default)
+# and of 32, and hold what boot_write_magic(), boot_write_image_ok(),
+# boot_write_copy_done() and boot_write_swap_info() write: each flag is a
+# single byte, padded with the erased value to the alignment.
+#
+# Trailers dumped from a device running C MCUboot can be added alongside
+# these, and listed in vectors.txt.
+
+TAIL = 256
+
+MAGIC = bytes([0x77, 0xc2, 0x95, 0xf3, 0x60, 0xd2, 0xef, 0x7f,
+               0x35, 0x52, 0x50, 0x0f, 0x2c, 0xb6, 0x79, 0x80])
+ALIGNED_MAGIC = bytes([0x2d, 0xe1, 0x5d, 0x29, 0x41, 0x0b, 0x8d, 0x77,
+                       0x67, 0x9c, 0x11, 0x0f, 0x1f, 0x8a])
+
+FLAG_SET = 0x01
+SWAP_TYPE_TEST = 2
+SWAP_TYPE_PERM = 3
+
+
+def trailer(align, magic=False, image_ok=False, copy_done=False, swap_info=None):
+    tail = bytearray([0xff] * TAIL)
+    end = TAIL
+    if magic:
+        if align == 8:
+            tail[end - 16:end] = MAGIC
+        else:
+            tail[end - 16:end] = align.to_bytes(2, 'little') + ALIGNED_MAGIC
+    image_ok_off = (end - 16 - align) & ~(align - 1)
+    copy_done_off = image_ok_off - align
+    swap_info_off = copy_done_off - align
+    if image_ok:
+        tail[image_ok_off] = FLAG_SET
+    if copy_done:
+        tail[copy_done_off] = FLAG_SET
+    if swap_info is not None:
+        # The swap type in the low nibble, the image number (0) above it.
+        tail[swap_info_off] = swap_info
+    return bytes(tail)
+
+
+VECTORS = {
+    # boot_set_pending(0): a test upgrade.
+    'pending-test': dict(magic=True, swap_info=SWAP_TYPE_TEST),
+    # boot_set_pending(1): a permanent upgrade.
+    'pending-perm': dict(magic=True, image_ok=True, swap_info=SWAP_TYPE_PERM),
+    # The primary slot after a test swap, before the image confirms itself.
+    'swapped-test': dict(magic=True, copy_done=True, swap_info=SWAP_TYPE_TEST),
+    # The same, after boot_set_confirmed().
+    'swapped-confirmed': dict(magic=True, image_ok=True, copy_done=True, swap_info=SWAP_TYPE_TEST),
+    # A swap interrupted before copy done was written.
+    'swapped-partial': dict(magic=True, swap_info=SWAP_TYPE_TEST),
+    # Flags without the magic, such as from an interrupted erase.
+    'no-magic': dict(image_ok=True, copy_done=True),
+}
+
+for name, fields in VECTORS.items():
+    for align in (8, 32):
+        with open(f'{name}-align{align}.bin', 'wb') as f:
+            f.write(trailer(align, **fields))
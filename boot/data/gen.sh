@@ -13,3 +13,41 @@ imgtool sign \
         -v "0.1.0" \
         --header-size 256 \
         --slot-size 0x20000
+
+# An image signed with the ECDSA P-256 test key.  The public half is in
+# ecdsa-p256-pub.der, as output by 'imgtool getpub -e der'.
+imgtool sign \
+        sample.bin sample-ecdsa.bin \
+        --key ecdsa-p256.pem \
+        --align 4 \
+        -v "0.1.0" \
+        --header-size 256 \
+        --slot-size 0x20000
+
+# The same, but carrying the full public key, for devices that only have the
+# key hash provisioned.
+imgtool sign \
+        sample.bin sample-ecdsa-pubkey.bin \
+        --key ecdsa-p256.pem \
+        --public-key-format full \
+        --align 4 \
+        -v "0.1.0" \
+        --header-size 256 \
+        --slot-size 0x20000
+
+# More signatures of sample-ecdsa.bin, as imgtool would make them: a DER
+# SEQUENCE of the two integers, which is 70, 71 or 72 bytes depending on
+# whether each has its top bit set.  One of each length, concatenated.
+size=$(( 256 + 76800 ))
+head -c $size sample-ecdsa.bin > signed-part.bin
+for len in 70 71 72; do
+        while true; do
+                openssl dgst -sha256 -sign ecdsa-p256.pem signed-part.bin > sig.der
+                [ $(stat -c %s sig.der) = $len ] && break
+        done
+        cat sig.der
+done > sample-ecdsa-sigs.der
+rm -f signed-part.bin sig.der
+
+# The golden vectors in c-vectors/ are listed, with C MCUboot's decision for
+# each, in c-vectors/vectors.txt.  The trailers are made by c-vectors/gen.py.
@@ -0,0 +1,248 @@
+//! The whole upgrade lifecycle, simulated on SimFlash.
+//!
+//! This walks through what happens on a device: a factory image is installed
+//! and confirms itself, the application downloads a new version and marks it
+//! pending, the bootloader swaps it in for a test boot, and the new image
+//! either confirms itself, or fails to and is reverted on the next boot.  A
+//! corrupt download is rejected, and the application finds out why.
+//!
+//! The application's side only uses the trailer API (`request_upgrade`,
+//! `confirm`, `boot_state`, `last_boot_error`) and the shared boot info block.  The boot crate
+//! doesn't yet have a swap engine, so `boot` below stands in for one with a
+//! simple whole-image swap, and writes the trailer as MCUboot does.
+//!
+//!     cargo run --example lifecycle
+//!
+//! It is also run by `cargo test`.
+
+use std::cell::RefCell;
+
+use boot::{
+    boot_state, confirm, last_boot_error, read_shared, record_boot_error, request_upgrade,
+    set_copy_done, swap_type, validate_cached, write_shared, BootError, BootInfo, ErrorCode, Image,
+    ImageVersion, SwapType, Validation, SHARED_SIZE,
+};
+use sha2::{Digest, Sha256};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+const HEADER_SIZE: usize = 256;
+
+/// A device: the two slots, and the RAM shared between the bootloader and the
+/// application, which survives a reset.
+struct Device {
+    primary: RefCell<SimFlash>,
+    secondary: RefCell<SimFlash>,
+    shared: [u8; SHARED_SIZE],
+}
+
+fn version(major: u8) -> ImageVersion {
+    ImageVersion { major, minor: 0, revision: 0, build_num: 0 }
+}
+
+/// Build an image with the given version, and just a SHA256.
+fn build(ver: ImageVersion) -> Vec<u8> {
+    let payload: Vec<u8> = (0..3000u32).map(|i| (i * ver.major as u32) as u8).collect();
+
+    let mut image = vec![];
+    image.extend(0x96f3b83du32.to_le_bytes());
+    image.extend(0u32.to_le_bytes());
+    image.extend((HEADER_SIZE as u16).to_le_bytes());
+    image.extend(0u16.to_le_bytes());
+    image.extend((payload.len() as u32).to_le_bytes());
+    image.extend(0u32.to_le_bytes());
+    image.extend([ver.major, ver.minor]);
+    image.extend(ver.revision.to_le_bytes());
+    image.extend(ver.build_num.to_le_bytes());
+    image.resize(HEADER_SIZE, 0);
+    image.extend(&payload);
+
+    let hash = Sha256::digest(&image);
+    image.extend(0x6907u16.to_le_bytes());
+    image.extend((4u16 + 4 + 32).to_le_bytes());
+    image.extend(0x10u16.to_le_bytes());
+    image.extend(32u16.to_le_bytes());
+    image.extend(hash);
+    image
+}
+
+/// The image in a slot, without its trailer.
+fn image_bytes(slot: &RefCell<SimFlash>) -> Vec<u8> {
+    let size = Image::from_flash(slot).unwrap().full_image_size();
+    let mut data = vec![0u8; size];
+    slot.borrow_mut().read(0, &mut data).unwrap();
+    data
+}
+
+fn erase(slot: &RefCell<SimFlash>) {
+    let mut slot = slot.borrow_mut();
+    let size = slot.capacity();
+    slot.erase(0, size).unwrap();
+}
+
+/// Exchange the images in the two slots, clearing both trailers.
+fn swap(dev: &Device) {
+    let a = image_bytes(&dev.primary);
+    let b = image_bytes(&dev.secondary);
+    erase(&dev.primary);
+    erase(&dev.secondary);
+    dev.primary.borrow_mut().install(&b, 0).unwrap();
+    dev.secondary.borrow_mut().install(&a, 0).unwrap();
+}
+
+/// The bootloader: act on the trailers, then validate the primary image and
+/// leave the boot info for the application.  Returns the booted version.
+fn boot(dev: &mut Device) -> ImageVersion {
+    let kind = swap_type(&mut *dev.primary.borrow_mut(), &mut *dev.secondary.borrow_mut()).unwrap();
+    println!("  boot: {:?}", kind);
+    match kind {
+        SwapType::None => (),
+        SwapType::Test | SwapType::Perm => {
+            // A bad upgrade is left where it is, with the reason recorded.
+            let valid = Image::from_flash(&dev.secondary).and_then(|image| image.validate());
+            if let Err(err) = valid {
+                let err = BootError::new(&err, 0);
+                record_boot_error(&mut *dev.secondary.borrow_mut(), &err).unwrap();
+                println!("  boot: upgrade rejected, {:?}", err.code);
+                return boot_primary(dev, err.code as u16);
+            }
+            swap(dev);
+            let mut primary = dev.primary.borrow_mut();
+            request_upgrade(&mut *primary).unwrap();
+            if kind == SwapType::Perm {
+                confirm(&mut *primary).unwrap();
+            }
+            set_copy_done(&mut *primary).unwrap();
+        }
+        SwapType::Revert => {
+            // The old image goes back, and is known good.
+            swap(dev);
+            let mut primary = dev.primary.borrow_mut();
+            request_upgrade(&mut *primary).unwrap();
+            confirm(&mut *primary).unwrap();
+            set_copy_done(&mut *primary).unwrap();
+        }
+    }
+    boot_primary(dev, 0)
+}
+
+/// Validate the primary image, and leave the boot info for the application.
+/// Once confirmed, the image is only hashed on the first boot.
+fn boot_primary(dev: &mut Device, last_error: u16) -> ImageVersion {
+    let validation = validate_cached(&dev.primary, |image| image.validate()).unwrap();
+    if validation == Validation::Cached {
+        println!("  boot: image validated on an earlier boot");
+    }
+    let image = Image::from_flash(&dev.primary).unwrap();
+    let info = BootInfo {
+        version: image.version(),
+        image_base: 0,
+        image_size: image.full_image_size() as u32,
+        slot: 0,
+        reserved: 0,
+        last_error,
+        stack_used: 0,
+    };
+    write_shared(&mut dev.shared, &info).unwrap();
+    info.version
+}
+
+/// The application, at startup: report where it is, and confirm itself if
+/// it's happy.
+fn app_start(dev: &Device, healthy: bool) {
+    let info = read_shared(&dev.shared).unwrap();
+    let state = boot_state(&dev.primary, &dev.secondary).unwrap();
+    println!("  app: running {:?}, confirmed {}, next {:?}",
+             info.version, state.confirmed, state.swap_type);
+    if !state.confirmed && healthy {
+        confirm(&mut *dev.primary.borrow_mut()).unwrap();
+        println!("  app: confirmed");
+    }
+}
+
+/// The application, receiving an upgrade.
+fn app_download(dev: &Device, ver: ImageVersion, permanent: bool) {
+    app_download_image(dev, &build(ver), permanent);
+    println!("  app: {:?} pending, permanent {}", ver, permanent);
+}
+
+fn app_download_image(dev: &Device, image: &[u8], permanent: bool) {
+    erase(&dev.secondary);
+    dev.secondary.borrow_mut().install(image, 0).unwrap();
+    let mut secondary = dev.secondary.borrow_mut();
+    request_upgrade(&mut *secondary).unwrap();
+    if permanent {
+        confirm(&mut *secondary).unwrap();
+    }
+}
+
+fn new_device() -> Device {
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let dev = Device {
+        primary: RefCell::new(primary),
+        secondary: RefCell::new(secondary),
+        shared: [0; SHARED_SIZE],
+    };
+    erase(&dev.primary);
+    erase(&dev.secondary);
+    dev.primary.borrow_mut().install(&build(version(1)), 0).unwrap();
+    dev
+}
+
+fn main() {
+    println!("Test upgrade, confirmed:");
+    let mut dev = new_device();
+    assert_eq!(boot(&mut dev), version(1));
+    app_start(&dev, true);
+    app_download(&dev, version(2), false);
+    assert_eq!(boot(&mut dev), version(2));
+    app_start(&dev, true);
+    assert_eq!(boot(&mut dev), version(2));
+    app_start(&dev, true);
+
+    println!("Test upgrade, not confirmed:");
+    let mut dev = new_device();
+    assert_eq!(boot(&mut dev), version(1));
+    app_start(&dev, true);
+    app_download(&dev, version(2), false);
+    assert_eq!(boot(&mut dev), version(2));
+    app_start(&dev, false);
+    let state = boot_state(&dev.primary, &dev.secondary).unwrap();
+    assert_eq!(state.swap_type, SwapType::Revert);
+    assert_eq!(state.secondary_version, Some(version(1)));
+    assert_eq!(boot(&mut dev), version(1));
+    app_start(&dev, true);
+    assert_eq!(boot(&mut dev), version(1));
+
+    println!("Permanent upgrade:");
+    let mut dev = new_device();
+    assert_eq!(boot(&mut dev), version(1));
+    app_start(&dev, true);
+    app_download(&dev, version(3), true);
+    assert_eq!(boot(&mut dev), version(3));
+    app_start(&dev, false);
+    assert_eq!(boot(&mut dev), version(3));
+
+    println!("Corrupt upgrade:");
+    let mut dev = new_device();
+    assert_eq!(boot(&mut dev), version(1));
+    app_start(&dev, true);
+    let mut image = build(version(2));
+    image[HEADER_SIZE + 10] ^= 1;
+    app_download_image(&dev, &image, false);
+    assert_eq!(boot(&mut dev), version(1));
+    let err = last_boot_error(&mut *dev.secondary.borrow_mut()).unwrap().unwrap();
+    println!("  app: last upgrade rejected, {:?}", err.code);
+    assert_eq!(err.code, ErrorCode::InvalidImage);
+    assert_eq!(read_shared(&dev.shared).unwrap().last_error, err.code as u16);
+
+    // A new download clears it.
+    app_download(&dev, version(2), false);
+    assert_eq!(last_boot_error(&mut *dev.secondary.borrow_mut()).unwrap(), None);
+    assert_eq!(boot(&mut dev), version(2));
+}
+
+#[test]
+fn lifecycle() {
+    main();
+}
@@ -0,0 +1,37 @@
+#![no_main]
+
+// The image header and TLVs, read from a slot holding anything at all.
+
+use std::cell::RefCell;
+
+use boot::{Image, ImageVersion};
+use boot_fuzz::RamFlash;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let flash = RefCell::new(RamFlash::new(data, data.len(), 4, 4));
+    let Ok(image) = Image::from_flash(&flash) else {
+        return;
+    };
+    let _ = image.version();
+    let _ = image.full_image_size();
+    let _ = image.hashed_size();
+
+    for elt in image.protected_tlvs() {
+        let Ok(elt) = elt else { break };
+        let mut buf = vec![0; elt.data_len()];
+        let _ = elt.read_data(&mut buf);
+    }
+    if let Ok(tlvs) = image.tlvs() {
+        for elt in tlvs {
+            let Ok(elt) = elt else { break };
+            let mut buf = vec![0; elt.data_len()];
+            let _ = elt.read_data(&mut buf);
+        }
+    }
+
+    let _ = image.security_counter();
+    let _ = image.stored_sha256();
+    let _ = image.check_dependencies(&[ImageVersion::default()]);
+    let _ = image.validate();
+});
@@ -0,0 +1,49 @@
+#![no_main]
+
+// The trailers and status of a pair of slots, holding anything at all.
+
+use std::cell::RefCell;
+
+use boot::{
+    boot_state, copy_done, image_ok, last_boot_error, migrate_c_trailer, swap_type,
+    upgrade_requested, validate_cached, Image, SlotInfo,
+};
+use boot_fuzz::slots;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((mut primary, mut secondary)) = slots(data) else {
+        return;
+    };
+
+    for slot in [&mut primary, &mut secondary] {
+        let _ = upgrade_requested(slot);
+        let _ = image_ok(slot);
+        let _ = copy_done(slot);
+        let _ = last_boot_error(slot);
+    }
+    let _ = swap_type(&mut primary, &mut secondary);
+
+    // The status layout for the images, as far as their headers say.
+    let size = |slot| {
+        let slot = RefCell::new(slot);
+        let size = Image::from_flash(&slot).map_or(0, |image| image.full_image_size());
+        (slot.into_inner(), size)
+    };
+    let (mut primary, primary_size) = size(primary);
+    let (secondary, secondary_size) = size(secondary);
+    let primary_info = SlotInfo::from_data(primary_size, &primary);
+    let secondary_info = SlotInfo::from_data(secondary_size, &secondary);
+    if let Ok(layout) = primary_info.status_layout(&secondary_info) {
+        layout.check().unwrap();
+        let _ = layout.read(&mut primary);
+    }
+
+    // These write to the trailers, so go last.
+    let primary = RefCell::new(primary);
+    let secondary = RefCell::new(secondary);
+    let _ = boot_state(&primary, &secondary);
+    let _ = migrate_c_trailer(&primary);
+    let _ = validate_cached(&primary, |image| image.validate());
+    let _ = validate_cached(&primary, |image| image.validate());
+});
@@ -0,0 +1,49 @@
+//! Build a starting corpus for the fuzz targets, in `corpus/`.
+//!
+//!     cargo run --bin corpus
+
+use std::fs;
+use std::path::Path;
+
+use boot::{confirm, record_boot_error, request_upgrade, set_copy_done, BootError, ErrorCode};
+use boot_fuzz::{status_input, RamFlash, GEOMETRIES};
+use simflash::gen::GenBuilder;
+
+fn main() -> anyhow::Result<()> {
+    let mut images = vec![];
+    for (seed, size) in [(1, 512), (2, 1000), (3, 3000)] {
+        images.push(GenBuilder::default().seed(seed).size(size).build()?.data);
+    }
+
+    let dir = Path::new("corpus/image");
+    fs::create_dir_all(dir)?;
+    for (n, image) in images.iter().enumerate() {
+        fs::write(dir.join(format!("gen-{}", n)), image)?;
+    }
+    fs::write(dir.join("sample-signed"), include_bytes!("../../../data/sample-signed.bin"))?;
+    fs::write(dir.join("sample-ecdsa"), include_bytes!("../../../data/sample-ecdsa.bin"))?;
+
+    // Each geometry, in each of the trailer states the bootloader acts on.
+    let dir = Path::new("corpus/status");
+    fs::create_dir_all(dir)?;
+    for (kind, &(write_size, erase_size, sectors)) in GEOMETRIES.iter().enumerate() {
+        let capacity = erase_size * sectors;
+        for state in ["none", "test", "perm", "revert", "rejected"] {
+            let mut primary = RamFlash::new(&images[0], capacity, write_size, erase_size);
+            let mut secondary = RamFlash::new(&images[1], capacity, write_size, erase_size);
+            let result = match state {
+                "none" => Ok(()),
+                "test" => request_upgrade(&mut secondary),
+                "perm" => request_upgrade(&mut secondary).and_then(|()| confirm(&mut secondary)),
+                "revert" => request_upgrade(&mut primary).and_then(|()| set_copy_done(&mut primary)),
+                _ => request_upgrade(&mut secondary).and_then(|()| {
+                    record_boot_error(&mut secondary, &BootError { code: ErrorCode::InvalidImage, offset: 0 })
+                }),
+            };
+            result.map_err(|e| anyhow::anyhow!("Unable to write the {} trailer: {:?}", state, e))?;
+            let input = status_input(kind as u8, &primary, &secondary);
+            fs::write(dir.join(format!("{}-{}", state, kind)), input)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Fuzzing the boot crate's parsers
+//!
+//! The targets feed arbitrary bytes to the code that reads what is in flash:
+//! the image header and TLVs (`image`), and the trailers and status (`status`).
+//! Nothing in flash can be trusted, so none of this may panic, overflow, or
+//! loop forever, whatever it is given.
+//!
+//!     cargo fuzz run image
+//!
+//! `cargo run --bin corpus` fills in a starting corpus of real images and
+//! trailers, which gets the fuzzer past the magic numbers quickly.  It signs
+//! with imgtool, through simflash's `GenBuilder`.
+
+use storage::{check_erase, check_read, check_write, Error, Flash, ReadFlash, Result};
+
+/// Flash held in memory.  Erased flash reads as 0xff, and anything can be
+/// written, so the boot code can update trailers as it goes.
+pub struct RamFlash {
+    data: Vec<u8>,
+    write_size: usize,
+    erase_size: usize,
+}
+
+impl RamFlash {
+    /// A flash of `capacity` bytes, starting with `data`, and erased after it.
+    pub fn new(data: &[u8], capacity: usize, write_size: usize, erase_size: usize) -> RamFlash {
+        let mut data = data[..data.len().min(capacity)].to_vec();
+        data.resize(capacity, 0xff);
+        RamFlash { data, write_size, erase_size }
+    }
+}
+
+impl ReadFlash for RamFlash {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        check_read(self, offset, bytes.len())?;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Flash for RamFlash {
+    fn write_size(&self) -> usize {
+        self.write_size
+    }
+
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        check_erase(self, from, to)?;
+        self.data[from..to].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        check_write(self, offset, bytes.len())?;
+        let dest = &mut self.data[offset..offset + bytes.len()];
+        if dest.iter().any(|&b| b != 0xff) {
+            return Err(Error::NotErased);
+        }
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Slot geometries for the status target: write size, erase size, sectors.
+/// Small, so the fuzzer can fill a whole slot, but covering both status
+/// styles.
+pub const GEOMETRIES: [(usize, usize, usize); 4] = [
+    (4, 1024, 4),
+    (8, 2048, 2),
+    (32, 1024, 4),
+    (512, 512, 8),
+];
+
+/// Split a status input into its two slots.  The first byte chooses the
+/// geometry, and the rest is the primary slot, then the secondary one.
+pub fn slots(data: &[u8]) -> Option<(RamFlash, RamFlash)> {
+    let (&kind, data) = data.split_first()?;
+    let (write_size, erase_size, sectors) = GEOMETRIES[kind as usize % GEOMETRIES.len()];
+    let capacity = erase_size * sectors;
+    let (primary, secondary) = data.split_at(data.len().min(capacity));
+    Some((RamFlash::new(primary, capacity, write_size, erase_size),
+          RamFlash::new(secondary, capacity, write_size, erase_size)))
+}
+
+/// The status input for two slots, the inverse of `slots`.
+pub fn status_input(kind: u8, primary: &RamFlash, secondary: &RamFlash) -> Vec<u8> {
+    let mut data = vec![kind];
+    data.extend(&primary.data);
+    data.extend(&secondary.data);
+    data
+}
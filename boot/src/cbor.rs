@@ -0,0 +1,136 @@
+//! Just enough CBOR
+//!
+//! Decodes the definite length items of RFC 8949 from a byte slice, without
+//! allocating.  Arrays and maps only give their length; their contents follow
+//! as the next items.  Floats and indefinite lengths aren't supported.
+
+use crate::{Error, Result};
+
+/// Arrays and maps nested deeper than this are refused by `skip`.
+const MAX_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Item<'a> {
+    Uint(u64),
+    /// A negative integer, -1 - n.
+    Nint(u64),
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Array(usize),
+    Map(usize),
+    Tag(u64),
+    Bool(bool),
+    Null,
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// What hasn't been decoded yet.
+    pub fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(Error::InvalidImage);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    /// The argument of an item, from its additional information.
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        let len = match info {
+            0..=23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(Error::InvalidImage),
+        };
+        Ok(self.take(len)?.iter().fold(0, |acc, &b| acc << 8 | b as u64))
+    }
+
+    pub fn next(&mut self) -> Result<Item<'a>> {
+        let initial = self.take(1)?[0];
+        let info = initial & 0x1f;
+        let arg = self.argument(info)?;
+        let len = || usize::try_from(arg).map_err(|_| Error::InvalidImage);
+        Ok(match initial >> 5 {
+            0 => Item::Uint(arg),
+            1 => Item::Nint(arg),
+            2 => Item::Bytes(self.take(len()?)?),
+            3 => Item::Text(core::str::from_utf8(self.take(len()?)?).map_err(|_| Error::InvalidImage)?),
+            4 => Item::Array(len()?),
+            5 => Item::Map(len()?),
+            6 => Item::Tag(arg),
+            _ => match info {
+                20 => Item::Bool(false),
+                21 => Item::Bool(true),
+                22 => Item::Null,
+                _ => return Err(Error::InvalidImage),
+            },
+        })
+    }
+
+    pub fn uint(&mut self) -> Result<u64> {
+        match self.next()? {
+            Item::Uint(value) => Ok(value),
+            _ => Err(Error::InvalidImage),
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        match self.next()? {
+            Item::Bytes(value) => Ok(value),
+            _ => Err(Error::InvalidImage),
+        }
+    }
+
+    pub fn array(&mut self) -> Result<usize> {
+        match self.next()? {
+            Item::Array(len) => Ok(len),
+            _ => Err(Error::InvalidImage),
+        }
+    }
+
+    pub fn map(&mut self) -> Result<usize> {
+        match self.next()? {
+            Item::Map(len) => Ok(len),
+            _ => Err(Error::InvalidImage),
+        }
+    }
+
+    /// Skip over the next item, including anything inside it.
+    pub fn skip(&mut self) -> Result<()> {
+        self.skip_depth(0)
+    }
+
+    fn skip_depth(&mut self, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::InvalidImage);
+        }
+        let count = match self.next()? {
+            Item::Array(len) => len,
+            Item::Map(len) => len.checked_mul(2).ok_or(Error::InvalidImage)?,
+            Item::Tag(_) => 1,
+            _ => 0,
+        };
+        for _ in 0..count {
+            self.skip_depth(depth + 1)?;
+        }
+        Ok(())
+    }
+}
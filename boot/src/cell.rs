@@ -0,0 +1,32 @@
+//! Sharing flash
+//!
+//! An image keeps hold of the flash it is in, and reads from it as needed,
+//! while other code, such as the trailer functions, uses the same device.  On
+//! the host, and on most targets, a `RefCell` is all the sharing that is
+//! needed.  Targets where the flash is also used from an interrupt or another
+//! task, such as under RTIC or Embassy, need a mutex or a critical section
+//! around each access instead.
+//!
+//! `FlashCell` is what `Image` and `Slot` need of the sharing: to run some
+//! code with the device held.  It is implemented for `RefCell`, which is the
+//! default, and a target can implement it for its own mutex type, and build
+//! images and slots over that, without any change to the boot code.
+
+use core::cell::RefCell;
+
+/// A flash device that can be shared, and held for each access.
+pub trait FlashCell {
+    type Flash;
+
+    /// Run `f` with the device held.  It must not try to hold the device
+    /// again.
+    fn with<R>(&self, f: impl FnOnce(&mut Self::Flash) -> R) -> R;
+}
+
+impl<F> FlashCell for RefCell<F> {
+    type Flash = F;
+
+    fn with<R>(&self, f: impl FnOnce(&mut F) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+}
@@ -0,0 +1,171 @@
+//! Chaining to the next image
+//!
+//! Once an image has been validated, control is handed over to it.  How that
+//! is done depends on the architecture: on Cortex-M, the vector table at the
+//! start of the image gives the initial stack pointer and the reset vector.
+//! A `Chainer` lets other architectures, or variants such as a TrustZone
+//! secure to non-secure transition, supply their own.
+
+use core::convert::Infallible;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use core::ops::Range;
+
+use crate::{event, info, EventCode, Image, MappedFlash, Result};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::{ClearRam, Handoff};
+
+/// The alignment the architecture requires of a vector table, which is where
+/// an image's code starts.  Cortex-M's VTOR ignores the low 7 bits, and a
+/// table with more than 32 entries needs more; see `Image::image_base`.
+#[cfg(target_arch = "arm")]
+pub const VECTOR_ALIGN: usize = 128;
+
+/// The alignment the architecture requires of a vector table, which is where
+/// an image's code starts.
+#[cfg(not(target_arch = "arm"))]
+pub const VECTOR_ALIGN: usize = 4;
+
+/// Transfers control to an image.
+pub trait Chainer {
+    /// Start the image whose code begins at `base`.  On Cortex-M, this is the
+    /// vector table.
+    ///
+    /// # Safety
+    ///
+    /// The image must be validated, and anything the bootloader set up that
+    /// the image doesn't expect must already be undone.
+    unsafe fn chain(&self, base: usize) -> !;
+}
+
+/// Chain to a validated image.  Only returns if the image's code isn't
+/// suitably aligned.
+///
+/// # Safety
+///
+/// See `Chainer::chain`.
+pub unsafe fn chain<C: Chainer, F: MappedFlash>(chainer: &C, image: &Image<'_, F>) -> Result<Infallible> {
+    let base = image.get_image_base()?;
+    info!("Booting image at 0x{:x}", base);
+    event(EventCode::Chain, 0);
+    chainer.chain(base)
+}
+
+/// Chaining on Cortex-M: point VTOR at the image's vector table, load the
+/// stack pointer from it, and jump to the reset vector.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub struct CortexM;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Chainer for CortexM {
+    unsafe fn chain(&self, base: usize) -> ! {
+        const VTOR: usize = 0xe000_ed08;
+        core::ptr::write_volatile(VTOR as *mut u32, base as u32);
+        let sp = core::ptr::read_volatile(base as *const u32);
+        let reset = core::ptr::read_volatile((base + 4) as *const u32);
+        core::arch::asm!(
+            "dsb",
+            "isb",
+            "msr msp, {sp}",
+            "bx {reset}",
+            sp = in(reg) sp,
+            reset = in(reg) reset,
+            options(noreturn),
+        );
+    }
+}
+
+/// Passing a value on Cortex-M, in `r0`, where the image's reset handler
+/// finds its first argument.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Handoff for CortexM {
+    unsafe fn chain_with(&self, base: usize, arg: usize) -> ! {
+        const VTOR: usize = 0xe000_ed08;
+        core::ptr::write_volatile(VTOR as *mut u32, base as u32);
+        let sp = core::ptr::read_volatile(base as *const u32);
+        let reset = core::ptr::read_volatile((base + 4) as *const u32);
+        core::arch::asm!(
+            "dsb",
+            "isb",
+            "msr msp, {sp}",
+            "bx {reset}",
+            sp = in(reg) sp,
+            reset = in(reg) reset,
+            in("r0") arg,
+            options(noreturn),
+        );
+    }
+}
+
+/// Clearing RAM on Cortex-M.  The stack pointer and reset vector are read
+/// from the vector table, in flash, before the loop, which only uses
+/// registers.  The instructions are all in ARMv6-M, for the Cortex-M0+.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl ClearRam for CortexM {
+    unsafe fn chain_clearing(&self, base: usize, ram: Range<usize>) -> ! {
+        const VTOR: usize = 0xe000_ed08;
+        core::ptr::write_volatile(VTOR as *mut u32, base as u32);
+        let sp = core::ptr::read_volatile(base as *const u32);
+        let reset = core::ptr::read_volatile((base + 4) as *const u32);
+        core::arch::asm!(
+            "2:",
+            "cmp r0, r1",
+            "bhs 3f",
+            "str r2, [r0]",
+            "adds r0, r0, #4",
+            "b 2b",
+            "3:",
+            "dsb",
+            "isb",
+            "msr msp, r3",
+            "bx r12",
+            in("r0") ram.start,
+            in("r1") ram.end,
+            in("r2") 0u32,
+            in("r3") sp,
+            in("r12") reset,
+            options(noreturn),
+        );
+    }
+}
+
+/// Chaining on Cortex-M as `CortexM` does, turning on the MPU, as `protect`
+/// left it, with the jump.  Privileged code keeps the default memory map
+/// outside of the regions.  The jump runs from RAM, as the bootloader's own
+/// flash may be execute never once the MPU is on.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub struct ProtectedCortexM;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Chainer for ProtectedCortexM {
+    unsafe fn chain(&self, base: usize) -> ! {
+        const VTOR: usize = 0xe000_ed08;
+        core::ptr::write_volatile(VTOR as *mut u32, base as u32);
+        let sp = core::ptr::read_volatile(base as *const u32);
+        let reset = core::ptr::read_volatile((base + 4) as *const u32);
+        // Called through a pointer, as RAM is out of reach of a direct call.
+        let jump: unsafe extern "C" fn(u32, u32) -> ! = enable_mpu_and_jump;
+        core::ptr::read_volatile(&jump)(sp, reset)
+    }
+}
+
+/// Turn on the MPU, with the default map for privileged code, and start the
+/// image.  This is placed in `.data`, which the runtime copies into RAM.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[link_section = ".data.enable_mpu_and_jump"]
+#[inline(never)]
+unsafe extern "C" fn enable_mpu_and_jump(sp: u32, reset: u32) -> ! {
+    const MPU_CTRL: u32 = 0xe000_ed94;
+    const ENABLE_PRIVDEFENA: u32 = 0b101;
+    core::arch::asm!(
+        "str {enable}, [{ctrl}]",
+        "dsb",
+        "isb",
+        "msr msp, {sp}",
+        "bx {reset}",
+        enable = in(reg) ENABLE_PRIVDEFENA,
+        ctrl = in(reg) MPU_CTRL,
+        sp = in(reg) sp,
+        reset = in(reg) reset,
+        options(noreturn),
+    );
+}
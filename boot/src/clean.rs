@@ -0,0 +1,163 @@
+//! Checking the unused part of a slot is erased
+//!
+//! An upgrade only erases as much of the slot as the new image needs.  If the
+//! previous image was larger, its tail is left between the end of the new
+//! image and the trailer.  Nothing reads it normally, but it can confuse a
+//! later probe for a header (such as after a partial erase), and it leaves old
+//! code in the device.
+//!
+//! `find_remnant` checks for such data, and `erase_remnants` clears it, after
+//! an upgrade has installed a new image.  Only whole sectors between the image
+//! and the first sector of the status can be erased: data in the sector
+//! holding the end of the image is reported instead.  The status sectors, and
+//! the trailer in or above them, come from the slot's `StatusLayout`, so that
+//! status pages and hashes aren't taken for remnants.
+//!
+//! Once an upgrade is confirmed, the secondary slot holds either the image
+//! that was replaced, kept so a revert is possible, or, after an overwrite,
+//! the upgrade itself, which a stray request could apply again.  Which to do
+//! with it is a `SecondaryCleanup`, given by the `BootPolicy`, and
+//! `confirm_upgrade` carries it out as part of confirming, so a download
+//! started later is never touched.
+
+use core::cell::RefCell;
+
+use storage::Flash;
+
+use crate::trailer::{confirm, is_confirmed, validated_offset};
+use crate::{error, info, Error, Image, Result, StatusLayout};
+
+/// What is done with the secondary slot once an upgrade is confirmed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SecondaryCleanup {
+    /// Leave it as it is, such as to keep the previous image to go back to.
+    #[default]
+    Keep,
+    /// Erase its first and last sectors, so that it holds neither an image
+    /// header nor a request.  Quicker than erasing it all, and enough that it
+    /// can't be booted or taken as an upgrade.
+    Consume,
+    /// Erase all of it, ready for the next download.
+    Erase,
+}
+
+/// Largest read used while scanning.
+const SCAN_CHUNK: usize = 512;
+
+/// The part of the slot that should be erased: from the end of the image (or
+/// the start of the slot, if it has none) to the first sector of the status.
+fn unused<F: Flash>(slot: &RefCell<F>, layout: &StatusLayout) -> (usize, usize) {
+    let start = Image::from_flash(slot).map(|image| image.full_image_size()).unwrap_or(0);
+    let flash = slot.borrow();
+    let status = flash.capacity().saturating_sub(layout.status_sectors() * layout.erase_size);
+    (start, status.min(validated_offset(&*flash)))
+}
+
+/// The offset of the first byte in `from..to` that isn't erased.
+fn scan<F: Flash>(flash: &mut F, from: usize, to: usize) -> Result<Option<usize>> {
+    let read_size = flash.read_size();
+    if read_size > SCAN_CHUNK {
+        return Err(Error::CannotUpgrade);
+    }
+    let chunk = SCAN_CHUNK / read_size * read_size;
+    let mut buf = [0u8; SCAN_CHUNK];
+    let mut pos = from / read_size * read_size;
+    while pos < to {
+        let len = chunk.min(to.next_multiple_of(read_size) - pos);
+        if let Some(offset) = scan_chunk(flash, pos, &mut buf[..len], from, to)? {
+            return Ok(Some(offset));
+        }
+        pos += len;
+    }
+    Ok(None)
+}
+
+/// Scan a single chunk.  Some devices can't read erased flash, so if the
+/// chunk can't be read, it is read again a unit at a time, skipping the
+/// erased ones.
+fn scan_chunk<F: Flash>(flash: &mut F, pos: usize, buf: &mut [u8], from: usize, to: usize) -> Result<Option<usize>> {
+    match flash.read(pos, buf) {
+        Ok(()) => (),
+        Err(storage::Error::NotWritten) if buf.len() > flash.read_size() => {
+            let read_size = flash.read_size();
+            for (n, unit) in buf.chunks_mut(read_size).enumerate() {
+                if let Some(offset) = scan_chunk(flash, pos + n * read_size, unit, from, to)? {
+                    return Ok(Some(offset));
+                }
+            }
+            return Ok(None);
+        }
+        Err(storage::Error::NotWritten) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(buf.iter()
+        .enumerate()
+        .map(|(n, &b)| (pos + n, b))
+        .find(|&(offset, b)| offset >= from && offset < to && b != 0xff)
+        .map(|(offset, _)| offset))
+}
+
+/// Find data left between the end of the image in this slot and its status,
+/// as laid out by `layout`.  Returns the offset of the first byte that isn't
+/// erased, if any.
+pub fn find_remnant<F: Flash>(slot: &RefCell<F>, layout: &StatusLayout) -> Result<Option<usize>> {
+    let (start, end) = unused(slot, layout);
+    scan(&mut *slot.borrow_mut(), start, end)
+}
+
+/// Erase each sector between the end of the image in this slot and its
+/// status, as laid out by `layout`, that holds anything.  Returns the first
+/// data that couldn't be erased, as it shares a sector with the image.
+pub fn erase_remnants<F: Flash>(slot: &RefCell<F>, layout: &StatusLayout) -> Result<Option<usize>> {
+    let (start, end) = unused(slot, layout);
+    let mut flash = slot.borrow_mut();
+    let erase_size = flash.erase_size();
+    let mut left = None;
+    let mut pos = start;
+    while let Some(offset) = scan(&mut *flash, pos, end)? {
+        let sector = offset / erase_size * erase_size;
+        let sector_end = sector + erase_size;
+        if sector >= start && sector_end <= end {
+            info!("Erasing old data at 0x{:x}", sector);
+            flash.erase(sector, sector_end)?;
+        } else {
+            error!("Old data at 0x{:x} shares a sector with the image", offset);
+            left = left.or(Some(offset));
+        }
+        pos = sector_end;
+    }
+    Ok(left)
+}
+
+/// Clean up the secondary slot after an upgrade, as `cleanup` says.
+pub fn clean_secondary<F: Flash>(secondary: &mut F, cleanup: SecondaryCleanup) -> Result<()> {
+    let size = secondary.capacity();
+    let sector = secondary.erase_size();
+    match cleanup {
+        SecondaryCleanup::Keep => (),
+        SecondaryCleanup::Consume => {
+            info!("Consuming the secondary slot");
+            secondary.erase(0, sector)?;
+            secondary.erase(size - sector, size)?;
+        }
+        SecondaryCleanup::Erase => {
+            info!("Erasing the secondary slot");
+            secondary.erase(0, size)?;
+        }
+    }
+    Ok(())
+}
+
+/// Confirm the image in the primary slot, then clean up the secondary slot
+/// as `cleanup` says.  The cleanup is only done along with the confirmation,
+/// and not if the image was already confirmed, when the secondary slot may
+/// since have been given a new download.
+pub fn confirm_upgrade<P: Flash, S: Flash>(primary: &mut P, secondary: &mut S, cleanup: SecondaryCleanup)
+    -> Result<()>
+{
+    if is_confirmed(primary)? {
+        return Ok(());
+    }
+    confirm(primary)?;
+    clean_secondary(secondary, cleanup)
+}
@@ -0,0 +1,109 @@
+//! Crypto providers
+//!
+//! Image validation needs a handful of cryptographic primitives.  By default,
+//! these are provided by software implementations, but many targets have
+//! hardware engines that are considerably faster.  A `CryptoBackend` allows a
+//! board to supply its own implementation.
+
+use sha2::{Digest, Sha256};
+use storage::ReadFlash;
+
+use crate::ecdsa::{self, SoftMul};
+use crate::fih::LoopCounter;
+
+/// The result of a SHA256 hash, appropriate for stack allocation.
+pub type Hash256 = [u8; 32];
+
+/// A provider of cryptographic operations.  The SHA256 operations are
+/// streaming: `sha256_start` begins a new hash, discarding any in progress.
+pub trait CryptoBackend {
+    /// Begin a new SHA256 hash.
+    fn sha256_start(&mut self);
+    /// Add data to the hash in progress.
+    fn sha256_update(&mut self, data: &[u8]);
+    /// Finish the hash, returning the result.
+    fn sha256_finish(&mut self) -> Hash256;
+
+    /// Add `len` bytes of flash, starting at `offset`, to the hash in
+    /// progress.  The default reads the flash in small pieces; a backend that
+    /// can fetch the data itself may override this.
+    fn sha256_update_flash<F: ReadFlash>(&mut self, flash: &mut F,
+                                         offset: usize, len: usize) -> storage::Result<()> {
+        let mut buffer = [0u8; 128];
+        let mut pos = offset;
+        let end = offset + len;
+        let mut counter = LoopCounter::new();
+        while pos < end {
+            let todo = (end - pos).min(buffer.len());
+            let buf = &mut buffer[0..todo];
+            flash.read(pos, buf)?;
+            self.sha256_update(buf);
+            pos += todo;
+            counter.step();
+        }
+        // Every chunk must have been hashed.
+        counter.check(len.div_ceil(buffer.len()));
+        Ok(())
+    }
+
+    /// Verify an ECDSA P-256 signature (r, s) of the given hash.  The key is an
+    /// uncompressed SEC1 point.  The default is done entirely in software.
+    fn ecdsa_p256_verify(&mut self, key: &[u8; 65], hash: &Hash256,
+                         r: &[u8; 32], s: &[u8; 32]) -> bool {
+        ecdsa::verify(&mut SoftMul, key, hash, r, s)
+    }
+
+    /// Clear the hash state, and anything else derived from the images or
+    /// keys, before chaining.  The default starts a new hash, discarding the
+    /// state of the last one; a backend holding more should zero it as well.
+    fn scrub(&mut self) {
+        self.sha256_start();
+    }
+}
+
+/// Compare two byte strings, taking the same time wherever they differ, so
+/// the time taken doesn't reveal how much of a guess was right.  Every
+/// comparison of hashes, keys, and signature values goes through this (or
+/// `fih_eq`, which compares the same way in its own counted loop), rather
+/// than `==`.
+#[inline(never)]
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    core::hint::black_box(diff) == 0
+}
+
+/// Software crypto, using the RustCrypto crates.
+#[derive(Default)]
+pub struct SoftCrypto {
+    sha256: Sha256,
+}
+
+impl SoftCrypto {
+    pub fn new() -> SoftCrypto {
+        SoftCrypto::default()
+    }
+}
+
+impl CryptoBackend for SoftCrypto {
+    fn sha256_start(&mut self) {
+        self.sha256 = Sha256::new();
+    }
+
+    fn sha256_update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+    }
+
+    fn sha256_finish(&mut self) -> Hash256 {
+        let mut result = [0u8; 32];
+        result.copy_from_slice(self.sha256.finalize_reset().as_slice());
+        result
+    }
+
+    fn scrub(&mut self) {
+        // A plain assignment could be left out, as nothing reads it again.
+        unsafe { core::ptr::write_volatile(&mut self.sha256, Sha256::new()) };
+    }
+}
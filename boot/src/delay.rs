@@ -0,0 +1,112 @@
+//! Startup window
+//!
+//! During development, it helps to have the bootloader wait a moment before
+//! validating and chaining, so a debugger can be attached before the image
+//! starts, or recovery forced without a boot pin.  The length of the window
+//! is fixed when the bootloader is built, from the `BOOT_DELAY_MS` environment
+//! variable, so every board gets it the same way:
+//!
+//! ```text
+//! BOOT_DELAY_MS=3000 cargo build --release
+//! ```
+//!
+//! Without it, there is no window, and the escapes are only checked once, so
+//! a button held at reset still works.
+//!
+//! The board supplies the escapes: anything that can be polled, such as a
+//! button, or a serial port receiving `ESCAPE_CHAR`.
+
+use crate::{info, Watchdog};
+
+/// Length of the startup window, in milliseconds.
+pub const BOOT_DELAY_MS: u32 = parse_ms(option_env!("BOOT_DELAY_MS"));
+
+/// Receiving this character on a serial port during the window requests
+/// recovery.  This is ESC.
+pub const ESCAPE_CHAR: u8 = 0x1b;
+
+/// How often the escapes are polled.
+const POLL_MS: u32 = 10;
+
+const fn parse_ms(value: Option<&str>) -> u32 {
+    let bytes = match value {
+        Some(value) => value.as_bytes(),
+        None => return 0,
+    };
+    let mut ms = 0u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "BOOT_DELAY_MS must be a number of milliseconds");
+        ms = ms * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    ms
+}
+
+/// A busy wait.
+pub trait Delay {
+    fn delay_ms(&mut self, ms: u32);
+}
+
+/// Something that can request recovery during the window.  This must not
+/// block.  Any closure returning a bool is an escape, such as one reading a
+/// button.
+pub trait Escape {
+    fn requested(&mut self) -> bool;
+}
+
+impl<F: FnMut() -> bool> Escape for F {
+    fn requested(&mut self) -> bool {
+        self()
+    }
+}
+
+/// An escape from a serial port.  The closure returns a received byte, if
+/// there is one, without waiting.
+pub struct SerialEscape<R>(pub R);
+
+impl<R: FnMut() -> Option<u8>> Escape for SerialEscape<R> {
+    fn requested(&mut self) -> bool {
+        (self.0)() == Some(ESCAPE_CHAR)
+    }
+}
+
+/// How the window ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Window {
+    /// It ran out, carry on booting.
+    Elapsed,
+    /// An escape requested recovery.
+    Recovery,
+}
+
+/// Wait out the startup window configured at build time.
+pub fn startup_window<D, W>(delay: &mut D, watchdog: &W, escapes: &mut [&mut dyn Escape]) -> Window
+    where D: Delay, W: Watchdog,
+{
+    wait_window(BOOT_DELAY_MS, delay, watchdog, escapes)
+}
+
+/// Wait up to `ms` milliseconds, polling the escapes, and feeding the
+/// watchdog.  The escapes are polled at least once.
+pub fn wait_window<D, W>(ms: u32, delay: &mut D, watchdog: &W, escapes: &mut [&mut dyn Escape]) -> Window
+    where D: Delay, W: Watchdog,
+{
+    if ms > 0 {
+        info!("Waiting {}ms before booting", ms);
+    }
+    let mut waited = 0;
+    loop {
+        watchdog.feed();
+        if escapes.iter_mut().any(|escape| escape.requested()) {
+            info!("Recovery requested during the startup window");
+            return Window::Recovery;
+        }
+        if waited >= ms {
+            return Window::Elapsed;
+        }
+        let step = POLL_MS.min(ms - waited);
+        delay.delay_ms(step);
+        waited += step;
+    }
+}
@@ -0,0 +1,597 @@
+//! ECDSA P-256 verification
+//!
+//! A small implementation of signature verification over the NIST P-256 curve.
+//! Only verification is needed in the bootloader, which only handles public
+//! data, so no attempt is made to be constant time.
+//!
+//! Numbers are held as eight 32-bit limbs, least significant first.  All of
+//! the expensive work comes down to 256x256 bit multiplies, which is done
+//! through the `WideMul` trait, so that targets with a multiply accelerator
+//! (such as the LPC55's CASPER) can provide it.  Everything else is done in
+//! software, using Montgomery multiplication for both the field and the group
+//! order.
+//!
+//! With `std`, there is also signing, for host tools.  The nonce is derived
+//! from the key and hash as in RFC 6979, so signatures are repeatable.  It is
+//! no more constant time than verification, so it doesn't belong on a device.
+
+use crate::crypto::ct_eq;
+use crate::fih::LoopCounter;
+
+/// A 256-bit number, least significant limb first.
+pub type U256 = [u32; 8];
+
+/// A provider of full width multiplication.
+pub trait WideMul {
+    /// Compute the full 512-bit product of two 256-bit values.
+    fn mul_wide(&mut self, a: &U256, b: &U256) -> [u32; 16];
+}
+
+/// Software multiplication.
+#[derive(Default)]
+pub struct SoftMul;
+
+impl WideMul for SoftMul {
+    fn mul_wide(&mut self, a: &U256, b: &U256) -> [u32; 16] {
+        let mut result = [0u32; 16];
+        for i in 0..8 {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let v = result[i + j] as u64 + a[i] as u64 * b[j] as u64 + carry;
+                result[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            result[i + 8] = carry as u32;
+        }
+        result
+    }
+}
+
+/// Build a number from limbs given most significant first, to allow the
+/// constants below to be read in the usual order.
+const fn be(words: [u32; 8]) -> U256 {
+    [words[7], words[6], words[5], words[4], words[3], words[2], words[1], words[0]]
+}
+
+/// The field prime.
+const P: U256 = be([0xffffffff, 0x00000001, 0x00000000, 0x00000000,
+                    0x00000000, 0xffffffff, 0xffffffff, 0xffffffff]);
+/// The order of the group.
+const N: U256 = be([0xffffffff, 0x00000000, 0xffffffff, 0xffffffff,
+                    0xbce6faad, 0xa7179e84, 0xf3b9cac2, 0xfc632551]);
+/// The curve constant b (a is -3).
+const B: U256 = be([0x5ac635d8, 0xaa3a93e7, 0xb3ebbd55, 0x769886bc,
+                    0x651d06b0, 0xcc53b0f6, 0x3bce3c3e, 0x27d2604b]);
+/// The generator.
+const GX: U256 = be([0x6b17d1f2, 0xe12c4247, 0xf8bce6e5, 0x63a440f2,
+                     0x77037d81, 0x2deb33a0, 0xf4a13945, 0xd898c296]);
+const GY: U256 = be([0x4fe342e2, 0xfe1a7f9b, 0x8ee7eb4a, 0x7c0f9e16,
+                     0x2bce3357, 0x6b315ece, 0xcbb64068, 0x37bf51f5]);
+
+const ZERO: U256 = [0; 8];
+
+fn from_be_bytes(bytes: &[u8; 32]) -> U256 {
+    let mut result = ZERO;
+    for (i, chunk) in bytes.rchunks_exact(4).enumerate() {
+        result[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    result
+}
+
+#[cfg(feature = "std")]
+fn to_be_bytes(a: &U256) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    for (i, chunk) in result.rchunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&a[i].to_be_bytes());
+    }
+    result
+}
+
+/// The bytes of a, least significant first, for comparing with `ct_eq`.
+fn to_le_bytes(a: &U256) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    for (chunk, limb) in result.chunks_exact_mut(4).zip(a) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    result
+}
+
+fn is_zero(a: &U256) -> bool {
+    a.iter().all(|&x| x == 0)
+}
+
+/// Is a >= b.
+fn ge(a: &U256, b: &U256) -> bool {
+    for i in (0..8).rev() {
+        if a[i] != b[i] {
+            return a[i] > b[i];
+        }
+    }
+    true
+}
+
+/// a + b, returning the carry.
+fn add(a: &U256, b: &U256) -> (U256, bool) {
+    let mut result = ZERO;
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let v = a[i] as u64 + b[i] as u64 + carry;
+        result[i] = v as u32;
+        carry = v >> 32;
+    }
+    (result, carry != 0)
+}
+
+/// a - b, returning the borrow.
+fn sub(a: &U256, b: &U256) -> (U256, bool) {
+    let mut result = ZERO;
+    let mut borrow = 0i64;
+    for i in 0..8 {
+        let v = a[i] as i64 - b[i] as i64 + borrow;
+        result[i] = v as u32;
+        borrow = v >> 32;
+    }
+    (result, borrow != 0)
+}
+
+/// Arithmetic modulo an odd modulus, with values held in Montgomery form
+/// (a * 2^256 mod m).
+struct Mont {
+    m: U256,
+    /// -m^-1 mod 2^32.
+    m0inv: u32,
+    /// 2^256 mod m, which is one in Montgomery form.
+    one: U256,
+    /// 2^512 mod m, used to convert into Montgomery form.
+    r2: U256,
+}
+
+impl Mont {
+    fn new(m: &U256) -> Mont {
+        // Newton iteration for the inverse of m[0] mod 2^32.
+        let mut inv = 1u32;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u32.wrapping_sub(m[0].wrapping_mul(inv)));
+        }
+
+        // Compute 2^256 and 2^512 by repeated doubling of one.
+        let mut value = ZERO;
+        value[0] = 1;
+        let mut one = ZERO;
+        for i in 0..512 {
+            let (sum, carry) = add(&value, &value);
+            value = sum;
+            if carry || ge(&value, m) {
+                value = sub(&value, m).0;
+            }
+            if i == 255 {
+                one = value;
+            }
+        }
+
+        Mont { m: *m, m0inv: inv.wrapping_neg(), one, r2: value }
+    }
+
+    /// Montgomery multiply, a * b * 2^-256 mod m.
+    fn mul<W: WideMul>(&self, w: &mut W, a: &U256, b: &U256) -> U256 {
+        let wide = w.mul_wide(a, b);
+        let mut t = [0u32; 17];
+        t[..16].copy_from_slice(&wide);
+
+        for i in 0..8 {
+            let u = t[i].wrapping_mul(self.m0inv);
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let v = t[i + j] as u64 + u as u64 * self.m[j] as u64 + carry;
+                t[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            let mut k = i + 8;
+            while carry != 0 && k < t.len() {
+                let v = t[k] as u64 + carry;
+                t[k] = v as u32;
+                carry = v >> 32;
+                k += 1;
+            }
+        }
+
+        let mut result = ZERO;
+        result.copy_from_slice(&t[8..16]);
+        if t[16] != 0 || ge(&result, &self.m) {
+            result = sub(&result, &self.m).0;
+        }
+        result
+    }
+
+    fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add(a, b);
+        if carry || ge(&sum, &self.m) {
+            sub(&sum, &self.m).0
+        } else {
+            sum
+        }
+    }
+
+    fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (diff, borrow) = sub(a, b);
+        if borrow {
+            add(&diff, &self.m).0
+        } else {
+            diff
+        }
+    }
+
+    fn enter_mont<W: WideMul>(&self, w: &mut W, a: &U256) -> U256 {
+        self.mul(w, a, &self.r2)
+    }
+
+    fn leave_mont<W: WideMul>(&self, w: &mut W, a: &U256) -> U256 {
+        let mut one = ZERO;
+        one[0] = 1;
+        self.mul(w, a, &one)
+    }
+
+    /// Inverse, by Fermat's little theorem (m must be prime).  Input and output
+    /// are in Montgomery form.
+    fn invert<W: WideMul>(&self, w: &mut W, a: &U256) -> U256 {
+        let mut exp = self.m;
+        exp[0] -= 2;
+        let mut result = self.one;
+        for i in (0..256).rev() {
+            result = self.mul(w, &result, &result);
+            if (exp[i / 32] >> (i % 32)) & 1 != 0 {
+                result = self.mul(w, &result, a);
+            }
+        }
+        result
+    }
+}
+
+/// A point in Jacobian coordinates, with the field elements in Montgomery
+/// form.  Z of zero is the point at infinity.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+/// Curve arithmetic.
+struct Curve<'w, W> {
+    w: &'w mut W,
+    fp: Mont,
+}
+
+impl<'w, W: WideMul> Curve<'w, W> {
+    fn mul(&mut self, a: &U256, b: &U256) -> U256 {
+        self.fp.mul(self.w, a, b)
+    }
+
+    fn infinity(&self) -> Point {
+        Point { x: self.fp.one, y: self.fp.one, z: ZERO }
+    }
+
+    /// Convert an affine point, in normal form, to a Jacobian point.
+    fn point(&mut self, x: &U256, y: &U256) -> Point {
+        Point {
+            x: self.fp.enter_mont(self.w, x),
+            y: self.fp.enter_mont(self.w, y),
+            z: self.fp.one,
+        }
+    }
+
+    /// Check that the affine point (x, y) in normal form is on the curve.
+    fn on_curve(&mut self, x: &U256, y: &U256) -> bool {
+        if ge(x, &self.fp.m) || ge(y, &self.fp.m) {
+            return false;
+        }
+        let x = self.fp.enter_mont(self.w, x);
+        let y = self.fp.enter_mont(self.w, y);
+        let b = self.fp.enter_mont(self.w, &B);
+
+        let y2 = self.mul(&y, &y);
+        let x2 = self.mul(&x, &x);
+        let x3 = self.mul(&x2, &x);
+        let three_x = self.fp.add(&self.fp.add(&x, &x), &x);
+        let rhs = self.fp.add(&self.fp.sub(&x3, &three_x), &b);
+        ct_eq(&to_le_bytes(&y2), &to_le_bytes(&rhs))
+    }
+
+    fn double(&mut self, p: &Point) -> Point {
+        if is_zero(&p.z) || is_zero(&p.y) {
+            return self.infinity();
+        }
+        let fp = &self.fp;
+        let delta = self.fp.mul(self.w, &p.z, &p.z);
+        let gamma = self.fp.mul(self.w, &p.y, &p.y);
+        let beta = self.fp.mul(self.w, &p.x, &gamma);
+
+        let t = self.fp.mul(self.w, &fp.sub(&p.x, &delta), &fp.add(&p.x, &delta));
+        let alpha = fp.add(&fp.add(&t, &t), &t);
+
+        let beta2 = fp.add(&beta, &beta);
+        let beta4 = fp.add(&beta2, &beta2);
+        let beta8 = fp.add(&beta4, &beta4);
+        let alpha2 = self.fp.mul(self.w, &alpha, &alpha);
+        let x = fp.sub(&alpha2, &beta8);
+
+        let yz = fp.add(&p.y, &p.z);
+        let yz2 = self.fp.mul(self.w, &yz, &yz);
+        let z = fp.sub(&fp.sub(&yz2, &gamma), &delta);
+
+        let gamma2 = self.fp.mul(self.w, &gamma, &gamma);
+        let gamma2_2 = fp.add(&gamma2, &gamma2);
+        let gamma2_4 = fp.add(&gamma2_2, &gamma2_2);
+        let gamma2_8 = fp.add(&gamma2_4, &gamma2_4);
+        let t = self.fp.mul(self.w, &alpha, &fp.sub(&beta4, &x));
+        let y = fp.sub(&t, &gamma2_8);
+
+        Point { x, y, z }
+    }
+
+    fn add(&mut self, p: &Point, q: &Point) -> Point {
+        if is_zero(&p.z) {
+            return *q;
+        }
+        if is_zero(&q.z) {
+            return *p;
+        }
+
+        let z1z1 = self.mul(&p.z, &p.z);
+        let z2z2 = self.mul(&q.z, &q.z);
+        let u1 = self.mul(&p.x, &z2z2);
+        let u2 = self.mul(&q.x, &z1z1);
+        let t = self.mul(&q.z, &z2z2);
+        let s1 = self.mul(&p.y, &t);
+        let t = self.mul(&p.z, &z1z1);
+        let s2 = self.mul(&q.y, &t);
+
+        let h = self.fp.sub(&u2, &u1);
+        let r = self.fp.sub(&s2, &s1);
+        if is_zero(&h) {
+            if is_zero(&r) {
+                return self.double(p);
+            }
+            return self.infinity();
+        }
+
+        let hh = self.mul(&h, &h);
+        let hhh = self.mul(&h, &hh);
+        let v = self.mul(&u1, &hh);
+
+        let r2 = self.mul(&r, &r);
+        let x = self.fp.sub(&self.fp.sub(&r2, &hhh), &self.fp.add(&v, &v));
+        let t = self.mul(&r, &self.fp.sub(&v, &x));
+        let t2 = self.mul(&s1, &hhh);
+        let y = self.fp.sub(&t, &t2);
+        let t = self.mul(&p.z, &q.z);
+        let z = self.mul(&t, &h);
+
+        Point { x, y, z }
+    }
+
+    /// Compute a * P + b * Q, using Shamir's trick.
+    fn mul_add(&mut self, a: &U256, p: &Point, b: &U256, q: &Point) -> Point {
+        let pq = self.add(p, q);
+        let mut result = self.infinity();
+        let mut counter = LoopCounter::new();
+        for i in (0..256).rev() {
+            result = self.double(&result);
+            let abit = (a[i / 32] >> (i % 32)) & 1 != 0;
+            let bbit = (b[i / 32] >> (i % 32)) & 1 != 0;
+            result = match (abit, bbit) {
+                (true, true) => self.add(&result, &pq),
+                (true, false) => self.add(&result, p),
+                (false, true) => self.add(&result, q),
+                (false, false) => result,
+            };
+            counter.step();
+        }
+        counter.check(256);
+        result
+    }
+
+    /// Return the affine x coordinate, in normal form.  Returns None for the
+    /// point at infinity.
+    fn affine_x(&mut self, p: &Point) -> Option<U256> {
+        if is_zero(&p.z) {
+            return None;
+        }
+        let zinv = self.fp.invert(self.w, &p.z);
+        let zinv2 = self.mul(&zinv, &zinv);
+        let x = self.mul(&p.x, &zinv2);
+        Some(self.fp.leave_mont(self.w, &x))
+    }
+
+    /// Return the affine coordinates, in normal form.
+    #[cfg(feature = "std")]
+    fn affine(&mut self, p: &Point) -> Option<(U256, U256)> {
+        if is_zero(&p.z) {
+            return None;
+        }
+        let zinv = self.fp.invert(self.w, &p.z);
+        let zinv2 = self.mul(&zinv, &zinv);
+        let zinv3 = self.mul(&zinv2, &zinv);
+        let x = self.mul(&p.x, &zinv2);
+        let y = self.mul(&p.y, &zinv3);
+        Some((self.fp.leave_mont(self.w, &x), self.fp.leave_mont(self.w, &y)))
+    }
+
+    /// Compute k * G.
+    #[cfg(feature = "std")]
+    fn mul_base(&mut self, k: &U256) -> Point {
+        let g = self.point(&GX, &GY);
+        let inf = self.infinity();
+        self.mul_add(k, &g, &ZERO, &inf)
+    }
+}
+
+/// Verify an ECDSA P-256 signature.  The key is an uncompressed SEC1 point (a
+/// 0x04 byte followed by x and y).  `r` and `s` are the signature values as
+/// big-endian bytes.  The hash is used directly as the message representative,
+/// which is correct for SHA-256.
+pub fn verify<W: WideMul>(w: &mut W, key: &[u8; 65], hash: &[u8; 32],
+                          r: &[u8; 32], s: &[u8; 32]) -> bool {
+    if key[0] != 0x04 {
+        return false;
+    }
+    let mut coord = [0u8; 32];
+    coord.copy_from_slice(&key[1..33]);
+    let qx = from_be_bytes(&coord);
+    coord.copy_from_slice(&key[33..65]);
+    let qy = from_be_bytes(&coord);
+
+    let r = from_be_bytes(r);
+    let s = from_be_bytes(s);
+
+    // r and s must be in [1, n-1].
+    if is_zero(&r) || is_zero(&s) || ge(&r, &N) || ge(&s, &N) {
+        return false;
+    }
+
+    // The message representative, reduced mod n.  Because 2^256 < 2n, one
+    // subtraction is enough.
+    let mut e = from_be_bytes(hash);
+    if ge(&e, &N) {
+        e = sub(&e, &N).0;
+    }
+
+    // u1 = e / s, u2 = r / s.  Multiplying a normal value by one in Montgomery
+    // form yields a normal value.
+    let fn_ = Mont::new(&N);
+    let sinv = fn_.enter_mont(w, &s);
+    let sinv = fn_.invert(w, &sinv);
+    let u1 = fn_.mul(w, &e, &sinv);
+    let u2 = fn_.mul(w, &r, &sinv);
+
+    let mut curve = Curve { fp: Mont::new(&P), w };
+    if !curve.on_curve(&qx, &qy) {
+        return false;
+    }
+    let g = curve.point(&GX, &GY);
+    let q = curve.point(&qx, &qy);
+    let point = curve.mul_add(&u1, &g, &u2, &q);
+
+    let x = match curve.affine_x(&point) {
+        Some(x) => x,
+        None => return false,
+    };
+    // x < p, and p < 2n, so again, a single subtraction reduces mod n.
+    let x = if ge(&x, &N) { sub(&x, &N).0 } else { x };
+    ct_eq(&to_le_bytes(&x), &to_le_bytes(&r))
+}
+
+/// The public key for a private key, as an uncompressed SEC1 point.  The
+/// private key is a big-endian scalar, and must be in [1, n-1].
+#[cfg(feature = "std")]
+pub fn public_key<W: WideMul>(w: &mut W, private: &[u8; 32]) -> Option<[u8; 65]> {
+    let d = from_be_bytes(private);
+    if is_zero(&d) || ge(&d, &N) {
+        return None;
+    }
+    let mut curve = Curve { fp: Mont::new(&P), w };
+    let point = curve.mul_base(&d);
+    let (x, y) = curve.affine(&point)?;
+    let mut key = [0u8; 65];
+    key[0] = 0x04;
+    key[1..33].copy_from_slice(&to_be_bytes(&x));
+    key[33..].copy_from_slice(&to_be_bytes(&y));
+    Some(key)
+}
+
+/// Sign a SHA-256 hash, returning `r` and `s` as big-endian bytes.  Returns
+/// None if the private key is out of range.
+#[cfg(feature = "std")]
+pub fn sign<W: WideMul>(w: &mut W, private: &[u8; 32], hash: &[u8; 32]) -> Option<([u8; 32], [u8; 32])> {
+    let d = from_be_bytes(private);
+    if is_zero(&d) || ge(&d, &N) {
+        return None;
+    }
+    let mut e = from_be_bytes(hash);
+    if ge(&e, &N) {
+        e = sub(&e, &N).0;
+    }
+
+    let mut nonces = Rfc6979::new(private, &to_be_bytes(&e));
+    loop {
+        let k = nonces.next();
+        if is_zero(&k) || ge(&k, &N) {
+            continue;
+        }
+
+        let mut curve = Curve { fp: Mont::new(&P), w: &mut *w };
+        let point = curve.mul_base(&k);
+        let x = match curve.affine_x(&point) {
+            Some(x) => x,
+            None => continue,
+        };
+        let r = if ge(&x, &N) { sub(&x, &N).0 } else { x };
+        if is_zero(&r) {
+            continue;
+        }
+
+        // s = (e + r * d) / k.  As in `verify`, multiplying a normal value by
+        // a Montgomery one gives a normal value.
+        let fn_ = Mont::new(&N);
+        let kinv = fn_.enter_mont(w, &k);
+        let kinv = fn_.invert(w, &kinv);
+        let rm = fn_.enter_mont(w, &r);
+        let rd = fn_.mul(w, &rm, &d);
+        let s = fn_.mul(w, &fn_.add(&e, &rd), &kinv);
+        if is_zero(&s) {
+            continue;
+        }
+        return Some((to_be_bytes(&r), to_be_bytes(&s)));
+    }
+}
+
+/// The deterministic nonce generator of RFC 6979, section 3.2, for SHA-256
+/// and a 256-bit order.
+#[cfg(feature = "std")]
+struct Rfc6979 {
+    k: [u8; 32],
+    v: [u8; 32],
+    first: bool,
+}
+
+#[cfg(feature = "std")]
+impl Rfc6979 {
+    fn new(private: &[u8; 32], hash: &[u8; 32]) -> Rfc6979 {
+        let k = [0u8; 32];
+        let v = [1u8; 32];
+        let k = hmac(&k, &[&v, &[0x00], private, hash]);
+        let v = hmac(&k, &[&v]);
+        let k = hmac(&k, &[&v, &[0x01], private, hash]);
+        let v = hmac(&k, &[&v]);
+        Rfc6979 { k, v, first: true }
+    }
+
+    /// The next candidate.  The caller rejects those out of range.
+    fn next(&mut self) -> U256 {
+        if !self.first {
+            self.k = hmac(&self.k, &[&self.v, &[0x00]]);
+            self.v = hmac(&self.k, &[&self.v]);
+        }
+        self.first = false;
+        self.v = hmac(&self.k, &[&self.v]);
+        from_be_bytes(&self.v)
+    }
+}
+
+/// HMAC-SHA256 of the concatenated parts, with a 32-byte key.
+#[cfg(feature = "std")]
+fn hmac(key: &[u8; 32], parts: &[&[u8]]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+
+    let mut pad = [0u8; 64];
+    pad[..32].copy_from_slice(key);
+    let mut inner = Sha256::new();
+    inner.update(pad.map(|b| b ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(pad.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
@@ -0,0 +1,82 @@
+//! Entropy
+//!
+//! A few values the bootloader makes up must not be predictable, or must at
+//! least differ from one device, or one boot, to the next: the hash seed of
+//! a swap's status (see `new_hash_seed`), the nonces of an encrypted image,
+//! the seed of the SMP wipe challenges, and any blinding a crypto backend
+//! does.  `EntropySource` is where all of them come from, so a target only
+//! has to provide its TRNG once, and a backend that blinds takes the same
+//! source as everything else.
+//!
+//! Boards implement it over their TRNG peripheral.  `SeededEntropy` is a
+//! deterministic one, for host tests and simulations that have to be
+//! reproducible.  It is not random, and must never be used on a device.
+
+use crate::Error;
+
+/// Reasons a source can't give entropy.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EntropyError {
+    /// The source failed its health checks, such as a stuck or biased
+    /// output, or a clock fault.
+    Health,
+    /// The source didn't produce data in time.
+    Timeout,
+}
+
+/// Without entropy, nothing that needs it can go ahead.
+impl From<EntropyError> for Error {
+    fn from(_: EntropyError) -> Self {
+        Error::CannotUpgrade
+    }
+}
+
+/// A source of random bytes.
+pub trait EntropySource {
+    /// Fill `buf` with random bytes.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError>;
+
+    /// A random word.
+    fn next_u32(&mut self) -> Result<u32, EntropyError> {
+        let mut buf = [0u8; 4];
+        self.fill(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+impl<E: EntropySource + ?Sized> EntropySource for &mut E {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        (**self).fill(buf)
+    }
+}
+
+/// A deterministic source, giving the same bytes for the same seed.  This is
+/// SplitMix64, which is fast and well distributed, but trivially predicted.
+#[derive(Clone, Debug)]
+pub struct SeededEntropy {
+    state: u64,
+}
+
+impl SeededEntropy {
+    pub fn new(seed: u64) -> SeededEntropy {
+        SeededEntropy { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl EntropySource for SeededEntropy {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+//! Boot event log
+//!
+//! Log messages are gone once the bootloader has chained, unless something was
+//! listening.  For diagnostics from the field, the bootloader can also record
+//! terse event codes into a ring buffer in RAM that neither it nor the
+//! application initializes, such as a region the linker script leaves out, so
+//! the application can read back what happened on the last boot and upload it.
+//!
+//! The buffer is a slice of words:
+//!
+//! +--------+-------+---------+---------+-----+
+//! | magic  | count | entry 0 | entry 1 | ... |
+//! +--------+-------+---------+---------+-----+
+//!
+//! The count is of all events recorded this boot.  Once the entries are full,
+//! the oldest is overwritten.  Each entry holds the event code in its low
+//! byte, and a 24 bit value above it, saturated if the value doesn't fit.
+//!
+//! The board installs the buffer with `set_event_log`, which starts a new log,
+//! and the boot crate then records its decisions there.  Anything the crate
+//! can't see, such as how long validation took, the board records with
+//! `event`.
+
+use core::ptr::addr_of_mut;
+
+/// Magic value in the first word of the buffer.
+pub const EVENTS_MAGIC: u32 = 0x6273_6576;
+
+/// Words of the buffer that come before the entries.
+const EVENTS_HEADER: usize = 2;
+
+/// Largest value an entry holds.
+pub const MAX_EVENT_VALUE: u32 = 0xff_ffff;
+
+/// What happened.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum EventCode {
+    /// A new log was started.
+    Start = 1,
+    /// The trailers were decoded.  The value is the swap type: 0 for none,
+    /// then test, permanent and revert.
+    SwapType = 2,
+    /// An image validated.
+    Valid = 3,
+    /// An image failed to validate.  The value is the `ErrorCode`.
+    Invalid = 4,
+    /// How long validation took, in milliseconds.
+    ValidationMs = 5,
+    /// A step of a swap was recorded.  The value is the number of steps done.
+    SwapStep = 6,
+    /// A swap finished.
+    SwapDone = 7,
+    /// A slot was rejected.  The value is the `ErrorCode`.
+    BootError = 8,
+    /// An image was confirmed.
+    Confirmed = 9,
+    /// The bootloader chained to the image.
+    Chain = 10,
+}
+
+impl EventCode {
+    fn from_u8(code: u8) -> Option<EventCode> {
+        use EventCode::*;
+        [Start, SwapType, Valid, Invalid, ValidationMs, SwapStep, SwapDone, BootError, Confirmed, Chain]
+            .into_iter()
+            .find(|&kind| kind as u8 == code)
+    }
+}
+
+/// One entry of the log.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub code: EventCode,
+    pub value: u32,
+}
+
+/// The bootloader's half: a log being recorded into.
+pub struct EventLog<'a> {
+    buf: &'a mut [u32],
+}
+
+impl<'a> EventLog<'a> {
+    /// Start a new, empty log in `buf`.  Returns None if there isn't room
+    /// for any entries.
+    pub fn start(buf: &'a mut [u32]) -> Option<EventLog<'a>> {
+        if buf.len() <= EVENTS_HEADER {
+            return None;
+        }
+        buf[0] = EVENTS_MAGIC;
+        buf[1] = 0;
+        Some(EventLog { buf })
+    }
+
+    pub fn record(&mut self, code: EventCode, value: u32) {
+        let capacity = (self.buf.len() - EVENTS_HEADER) as u32;
+        let count = self.buf[1];
+        let entry = (value.min(MAX_EVENT_VALUE) << 8) | code as u32;
+        self.buf[EVENTS_HEADER + (count % capacity) as usize] = entry;
+        self.buf[1] = count.wrapping_add(1);
+    }
+}
+
+/// The application's half: the events in `buf`, oldest first.  Returns None
+/// if there is no log, such as after a power cycle.  Codes this version
+/// doesn't know are skipped.
+pub fn read_events(buf: &[u32]) -> Option<impl Iterator<Item = Event> + '_> {
+    if buf.len() <= EVENTS_HEADER || buf[0] != EVENTS_MAGIC {
+        return None;
+    }
+    let entries = &buf[EVENTS_HEADER..];
+    let count = buf[1] as usize;
+    // Before wrapping, the oldest is the first.  After, it is the next to be
+    // overwritten.
+    let (len, first) = if count <= entries.len() {
+        (count, 0)
+    } else {
+        (entries.len(), count % entries.len())
+    };
+    Some((0..len).filter_map(move |n| {
+        let entry = entries[(first + n) % entries.len()];
+        let code = EventCode::from_u8(entry as u8)?;
+        Some(Event { code, value: entry >> 8 })
+    }))
+}
+
+static mut EVENTS: Option<EventLog<'static>> = None;
+
+/// Start a log in `buf`, and have the boot crate record its events there.
+///
+/// # Safety
+///
+/// As with `set_logger`, this isn't synchronized.  It must be called before
+/// any events, typically at the start of `main`, and not from an interrupt
+/// handler.
+pub unsafe fn set_event_log(buf: &'static mut [u32]) {
+    *addr_of_mut!(EVENTS) = EventLog::start(buf);
+    event(EventCode::Start, 0);
+}
+
+/// Record an event, if a log has been installed.
+pub fn event(code: EventCode, value: u32) {
+    // SAFETY: The bootloader is single threaded, see `set_event_log`.
+    if let Some(log) = unsafe { (*addr_of_mut!(EVENTS)).as_mut() } {
+        log.record(code, value);
+    }
+}
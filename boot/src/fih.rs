@@ -0,0 +1,162 @@
+//! Fault injection hardening
+//!
+//! A voltage or clock glitch at the right moment can skip an instruction, or
+//! corrupt a value, such as the branch taken on whether an image is valid.
+//! With the `fih` feature, the decisions that matter are hardened, much as in
+//! TF-M's FIH:
+//!
+//! - `FihBool` holds a decision as a value far from its opposite in Hamming
+//!   distance, along with its inverse, and checks both, twice, when used.
+//! - `fih_eq` compares in constant time, and checks the result again.
+//! - `LoopCounter` counts the iterations of the hash and verify loops, so a
+//!   glitch that ends one early is caught.
+//!
+//! An inconsistency calls `fih_panic`, which never returns.  Without the
+//! feature, the same types are plain, and cost next to nothing.
+
+use core::ptr;
+
+use crate::error;
+
+/// The value of a true `FihBool`.
+const TRUE_VAL: u32 = 0x3c5a_c3a5;
+/// The value of a false `FihBool`, with every bit different.
+const FALSE_VAL: u32 = !TRUE_VAL;
+
+/// Read a value so the compiler can't reuse an earlier read, or assume what
+/// it holds.
+fn fresh<T: Copy>(value: &T) -> T {
+    unsafe { ptr::read_volatile(value) }
+}
+
+/// Stop, as a fault has been detected.
+#[inline(never)]
+pub fn fih_panic() -> ! {
+    error!("Fault injection detected");
+    #[cfg(feature = "std")]
+    panic!("Fault injection detected");
+    #[cfg(not(feature = "std"))]
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// A hardened boolean.
+#[derive(Clone, Copy, Debug)]
+pub struct FihBool {
+    val: u32,
+    #[cfg(feature = "fih")]
+    inv: u32,
+}
+
+impl FihBool {
+    pub const TRUE: FihBool = FihBool::from_val(TRUE_VAL);
+    pub const FALSE: FihBool = FihBool::from_val(FALSE_VAL);
+
+    const fn from_val(val: u32) -> FihBool {
+        FihBool {
+            val,
+            #[cfg(feature = "fih")]
+            inv: !val,
+        }
+    }
+
+    pub fn new(value: bool) -> FihBool {
+        if value { FihBool::TRUE } else { FihBool::FALSE }
+    }
+
+    /// Is this true?  With `fih`, the value must agree with its inverse, and
+    /// be one of the two values, and is read twice.
+    #[inline(never)]
+    pub fn is_true(&self) -> bool {
+        let val = fresh(&self.val);
+        #[cfg(feature = "fih")]
+        {
+            if val != !fresh(&self.inv) || (val != TRUE_VAL && val != FALSE_VAL) {
+                fih_panic();
+            }
+            if fresh(&self.val) != val {
+                fih_panic();
+            }
+        }
+        val == TRUE_VAL
+    }
+
+    /// Both this and `other`.
+    pub fn and(self, other: FihBool) -> FihBool {
+        FihBool::new(self.is_true() && other.is_true())
+    }
+}
+
+/// Compare two byte strings as `ct_eq` does, counting the bytes compared,
+/// and, with `fih`, checking a match a second time.
+#[inline(never)]
+pub fn fih_eq(a: &[u8], b: &[u8]) -> FihBool {
+    if a.len() != b.len() {
+        return FihBool::FALSE;
+    }
+    let mut diff = 0u8;
+    let mut counter = LoopCounter::new();
+    for (x, y) in a.iter().zip(b) {
+        diff |= x ^ y;
+        counter.step();
+    }
+    counter.check(a.len());
+    let result = FihBool::new(fresh(&diff) == 0);
+
+    // A second comparison, which a single glitch can't also skip.
+    #[cfg(feature = "fih")]
+    if result.is_true() && a.iter().zip(b).any(|(x, y)| fresh(x) != fresh(y)) {
+        fih_panic();
+    }
+    result
+}
+
+/// Counts the iterations of a loop, to check it ran to completion.
+pub struct LoopCounter {
+    #[cfg(feature = "fih")]
+    count: usize,
+    #[cfg(feature = "fih")]
+    inv: usize,
+}
+
+impl LoopCounter {
+    pub fn new() -> LoopCounter {
+        LoopCounter {
+            #[cfg(feature = "fih")]
+            count: 0,
+            #[cfg(feature = "fih")]
+            inv: !0,
+        }
+    }
+
+    /// Count an iteration.
+    #[inline(always)]
+    pub fn step(&mut self) {
+        #[cfg(feature = "fih")]
+        {
+            self.count = fresh(&self.count) + 1;
+            self.inv = fresh(&self.inv) - 1;
+        }
+    }
+
+    /// With `fih`, check the loop ran `expected` times, and stop if not.
+    #[inline(never)]
+    pub fn check(&self, expected: usize) {
+        #[cfg(feature = "fih")]
+        {
+            let count = fresh(&self.count);
+            if count != expected || fresh(&self.inv) != !expected || fresh(&self.count) != count {
+                fih_panic();
+            }
+        }
+        #[cfg(not(feature = "fih"))]
+        let _ = expected;
+    }
+}
+
+impl Default for LoopCounter {
+    fn default() -> LoopCounter {
+        LoopCounter::new()
+    }
+}
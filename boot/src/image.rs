@@ -1,237 +1,581 @@
 //! Boot image support
 
-use core::{cell::RefCell, mem::size_of};
+use core::{cell::RefCell, fmt, marker::PhantomData, mem::size_of};
 
 use asraw::{AsMutRaw, AsRaw};
-use storage::ReadFlash;
-use sha2::{Digest, Sha256};
+use storage::{Flash, ReadFlash};
 
-use crate::{MappedFlash, Error, Result};
-
-/// To make development a little easier, allow println in the 'std' code, and
-/// just make it vanish when we are no_std.
-#[cfg(not(feature = "std"))]
-macro_rules! println {
-    ($($_e:expr),+) => { {} };
-}
+use crate::{
+    crypto::{CryptoBackend, Hash256, SoftCrypto},
+    scheme::{EcdsaP256, SignatureScheme, MAX_SIGNATURE, TLV_ECDSA_SIG},
+    tlv::{TlvWriter, TLV_HEADER_LEN},
+    chain::VECTOR_ALIGN, error, event, fih_eq, info, ErrorCode, EventCode, FihBool, FlashCell, MappedFlash, Error,
+    Result,
+};
 
 /// The image header contains the following magic value, indicating the
 /// interpretation of the rest of the image header.
 pub const IMAGE_MAGIC: u32 = 0x96f3b83d;
 
-/// The result of a SHA256 hash, appropriate for stack allocation.
-type Hash256 = [u8; 32];
+/// Bytes read at a time when rewriting TLVs.  Also the largest write size
+/// they can be rewritten to.
+const REWRITE_CHUNK: usize = 512;
+
+/// The largest header size accepted.
+pub const MAX_HEADER_SIZE: usize = 4096;
+
+/// The most entries accepted in either TLV block.  An image has a handful;
+/// this only bounds the work done on a crafted one.
+pub const MAX_TLV_ENTRIES: usize = 64;
+
+/// The kinds that can only appear once in a TLV block, as it would be
+/// ambiguous which of them was checked.
+const SINGLE_TLVS: [u16; 6] = [TLV_KEYHASH, TLV_PUBKEY, TLV_SHA256, TLV_SEC_CNT, TLV_MANIFEST, TLV_ECDSA_SIG];
 
 /// An image is a bootable image residing in a flash partition.  There is a
 /// header at the beginning, and metadata immediately following the image.
-/// This holds on to a RefCell to the flash to bind the data to a particular flash.
-pub struct Image<'f, F> {
-    flash: &'f RefCell<F>,
+/// This holds on to the shared flash, a RefCell unless another `FlashCell` is
+/// given, to bind the data to a particular flash.
+pub struct Image<'f, F, S: ?Sized = RefCell<F>> {
+    flash: &'f S,
+    _flash: PhantomData<F>,
     #[allow(dead_code)]
     pub header: ImageHeader,
+    /// The end of the image payload, which is where any protected Tlv starts.
+    payload_end: usize,
+    /// The start of the (unprotected) Tlv.  Everything before this is hashed.
     tlv_base: usize,
     tlv_size: usize,
 }
 
-impl<'f, F: ReadFlash> Image<'f, F> {
+/// What a slot holds, as far as its header and TLV show.
+pub enum Probe<'f, F, S: ?Sized = RefCell<F>> {
+    /// Nothing has been written: the header is erased.
+    Empty,
+    /// Something was written, but it isn't an image, or its header or TLV
+    /// are damaged, such as by an interrupted download.
+    Corrupt,
+    /// An image, with a header and TLV that can be read.  Its contents are
+    /// yet to be validated.
+    Image(Image<'f, F, S>),
+}
+
+impl<'f, F: ReadFlash, S: FlashCell<Flash = F> + ?Sized> Image<'f, F, S> {
+    /// Find out what is in a slot, telling an empty slot, such as one with
+    /// no upgrade, from one holding something that isn't an image, which
+    /// `from_flash` fails on alike.  Flash that can't be read when erased is
+    /// empty.  Only failures of the flash itself are errors.
+    pub fn probe(flash: &'f S) -> Result<Probe<'f, F, S>> {
+        let mut buf = [0u8; size_of::<ImageHeader>()];
+        match flash.with(|flash| flash.read(0, &mut buf)) {
+            Ok(()) if buf.iter().all(|&b| b == 0xff) => return Ok(Probe::Empty),
+            Ok(()) => (),
+            Err(storage::Error::NotWritten) => return Ok(Probe::Empty),
+            Err(e) => return Err(e.into()),
+        }
+        match Image::from_flash(flash) {
+            Ok(image) => Ok(Probe::Image(image)),
+            // A TLV that is missing, or past the end of the slot.
+            Err(Error::InvalidImage |
+                Error::Flash(storage::Error::NotWritten | storage::Error::OutOfBounds)) => {
+                error!("Slot holds a damaged image");
+                Ok(Probe::Corrupt)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Make an image from flash, if the image has a valid header. This does not
     /// indicate that the image itself is valid, merely that the header
     /// indicates an image is present.
-    pub fn from_flash(flash: &'f RefCell<F>) -> Result<Image<'f, F>> {
-        let mut header = ImageHeader::default();
-        flash.borrow_mut().read(0, header.as_mut_raw())?;
-
-        if header.magic != IMAGE_MAGIC {
+    pub fn from_flash(flash: &'f S) -> Result<Image<'f, F, S>> {
+        let mut buf = [0u8; size_of::<ImageHeader>()];
+        flash.with(|flash| flash.read(0, &mut buf))?;
+        let header = ImageHeader::try_from_raw(&buf)?;
+
+        // The header area must at least hold the header, and isn't expected
+        // to be larger than a sector of any flash.
+        let hdr_size = header.hdr_size as usize;
+        if !(size_of::<ImageHeader>()..=MAX_HEADER_SIZE).contains(&hdr_size) {
+            error!("Bad header size: {}", hdr_size);
             return Err(Error::InvalidImage);
         }
 
-        // Find the base address of the TLV.
-        let tlv_base = (header.img_size as usize)
+        // Find the end of the image payload, where the TLV begins.
+        let payload_end = (header.img_size as usize)
             .checked_add(header.hdr_size as usize)
             .ok_or(Error::InvalidImage)?;
 
         // Overflow of the partition will be checked by the flash device.
         // Capacity is not guaranteed to be returned.
 
-        // Simple case of just a single TLV entry for hash.  TODO: More
-        // sophisticated handling should be done separate from here.
-        let mut info = TlvInfo::default();
-        flash
-            .borrow_mut()
-            .read(tlv_base, info.as_mut_raw())?;
-
-        // println!("header: {:#x?}", header);
-        // println!("tlv: {:#x?}", info);
+        // The protected TLV, if present, comes first.  Its size is given in the
+        // header, and it is covered by the image hash.
+        let prot_size = header.protected_tlv_size as usize;
+        if prot_size > 0 {
+            let mut buf = [0u8; size_of::<TlvInfo>()];
+            flash.with(|flash| flash.read(payload_end, &mut buf))?;
+            let info = TlvInfo::try_from_raw(&buf)?;
+            if info.magic != TLV_PROT_INFO_MAGIC || info.len as usize != prot_size {
+                return Err(Error::InvalidImage);
+            }
+        }
+        let tlv_base = payload_end + prot_size;
 
+        let mut buf = [0u8; size_of::<TlvInfo>()];
+        flash.with(|flash| flash.read(tlv_base, &mut buf))?;
+        let info = TlvInfo::try_from_raw(&buf)?;
         if info.magic != TLV_INFO_MAGIC {
             return Err(Error::InvalidImage);
         }
-        // TODO: If we support the protected TLV, the size computation will have
-        // to change.
-        let tlv_size = info.len as usize;
-
-        // TODO: This can be done just with validate.
-        let mut pos = size_of::<TlvEntry>();
-        while pos < info.len as usize {
-            let mut entry = TlvEntry::default();
-            flash
-                .borrow_mut()
-                .read(tlv_base + pos, entry.as_mut_raw())?;
-            // println!("entry: {:x?}", entry);
 
-            pos += size_of::<TlvEntry>() + entry.len as usize;
-        }
+        // println!("header: {:#x?}", header);
+        // println!("tlv: {:#x?}", info);
+        let tlv_size = info.len as usize;
 
-        Ok(Image {
+        let image = Image {
             flash,
+            _flash: PhantomData,
             header,
+            payload_end,
             tlv_base,
             tlv_size,
-        })
+        };
+
+        // Walk both blocks, so that an image whose entries don't fit them is
+        // rejected here, rather than part way through using it.
+        for elt in image.protected_tlvs().chain(image.tlvs()?) {
+            elt?;
+        }
+        Ok(image)
     }
 
     /// Iterate over the elements of the Tlv.
-    pub fn tlvs<'a>(&'a self) -> Result<TlvIter<'a, 'f, F>> {
+    pub fn tlvs<'a>(&'a self) -> Result<TlvIter<'a, 'f, F, S>> {
         // Check the header.
-        let mut info = TlvInfo::default();
-        self.flash
-            .borrow_mut()
-            .read(self.tlv_base, info.as_mut_raw())?;
-
-        if info.magic != TLV_INFO_MAGIC {
-            return Err(Error::InvalidImage);
-        }
+        let mut buf = [0u8; size_of::<TlvInfo>()];
+        self.flash.with(|flash| flash.read(self.tlv_base, &mut buf))?;
+        let info = TlvInfo::try_from_raw(&buf)?;
 
         Ok(TlvIter {
             image: self,
+            base: self.tlv_base,
             pos: size_of::<TlvInfo>(),
             limit: info.len as usize,
+            count: 0,
+            seen: 0,
         })
     }
 
+    /// Iterate over the elements of the protected Tlv.  This is empty if the
+    /// image has no protected Tlv.
+    pub fn protected_tlvs<'a>(&'a self) -> TlvIter<'a, 'f, F, S> {
+        TlvIter {
+            image: self,
+            base: self.payload_end,
+            pos: size_of::<TlvInfo>(),
+            limit: self.header.protected_tlv_size as usize,
+            count: 0,
+            seen: 0,
+        }
+    }
+
+    /// Check this image's dependencies against the versions of the other
+    /// images in a multi-image set.  `versions` is indexed by image number, and
+    /// a dependency on an image that isn't in the set fails.
+    pub fn check_dependencies(&self, versions: &[ImageVersion]) -> Result<()> {
+        for elt in self.protected_tlvs() {
+            let elt = elt?;
+            if elt.kind() != TLV_DEPENDENCY {
+                continue;
+            }
+            let mut dep = ImageDependency::default();
+            elt.read_data(dep.as_mut_raw())?;
+            match versions.get(dep.image_id as usize) {
+                Some(version) if version.at_least(&dep.min_version) => (),
+                _ => {
+                    error!("Dependency on image {} not met", dep.image_id);
+                    return Err(Error::InvalidImage);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The version of this image.
+    pub fn version(&self) -> ImageVersion {
+        self.header.version
+    }
+
+    /// The image's security counter, from its protected SEC_CNT TLV
+    /// (imgtool's `--security-counter`).  Images without one have a counter of
+    /// zero.
+    pub fn security_counter(&self) -> Result<u32> {
+        for elt in self.protected_tlvs() {
+            let elt = elt?;
+            if elt.kind() == TLV_SEC_CNT {
+                let mut counter = [0u8; 4];
+                elt.read_data(&mut counter)?;
+                return Ok(u32::from_le_bytes(counter));
+            }
+        }
+        Ok(0)
+    }
+
+    /// The hash recorded in the image's SHA256 TLV.  This is not checked
+    /// against the image, so it only identifies the image, until it is
+    /// validated.
+    pub fn stored_sha256(&self) -> Result<Hash256> {
+        for elt in self.tlvs()? {
+            let elt = elt?;
+            if elt.kind() == TLV_SHA256 {
+                let mut hash = [0u8; 32];
+                elt.read_data(&mut hash)?;
+                return Ok(hash);
+            }
+        }
+        Err(Error::InvalidImage)
+    }
+
     /// Validate this image. Check the TLV entries, making sure that they are
     /// sufficient, and that indicated items, such as hashes and signatures are
     /// valid.
     pub fn validate(&self) -> Result<()> {
+        self.validate_with(&mut SoftCrypto::new())
+    }
+
+    /// Validate this image, using the given crypto backend.
+    pub fn validate_with<C: CryptoBackend>(&self, crypto: &mut C) -> Result<()> {
+        self.validate_inner(crypto, Trust::None, None, &[&EcdsaP256])
+    }
+
+    /// Validate this image, additionally requiring a valid signature made with
+    /// the given public key.  The key is the DER SubjectPublicKeyInfo, as
+    /// output by `imgtool getpub`.
+    pub fn validate_signed<C: CryptoBackend>(&self, crypto: &mut C, key: &[u8]) -> Result<()> {
+        self.validate_signed_by(crypto, key, &[&EcdsaP256])
+    }
+
+    /// Validate this image as `validate_signed` does, but with the signature
+    /// checked by whichever of `schemes` handles it.
+    pub fn validate_signed_by<C: CryptoBackend>(&self, crypto: &mut C, key: &[u8],
+                                                schemes: &[&dyn SignatureScheme<C>]) -> Result<()> {
+        self.validate_inner(crypto, Trust::Key(key), None, schemes)
+    }
+
+    /// Validate this image as `validate_signed` does, giving the decision as
+    /// a `FihBool`, for a bootloader to branch on when it chooses whether to
+    /// chain to the image.  With `fih`, the decision is checked against the
+    /// result it came from before it is returned.
+    pub fn validate_signed_fih<C: CryptoBackend>(&self, crypto: &mut C, key: &[u8]) -> FihBool {
+        let result = self.validate_signed(crypto, key);
+        let valid = FihBool::new(result.is_ok());
+        #[cfg(feature = "fih")]
+        if valid.is_true() != result.is_ok() {
+            crate::fih_panic();
+        }
+        valid
+    }
+
+    /// Validate this image, requiring a valid signature made with the public
+    /// key carried in the image itself, in its PUBKEY TLV.  The SHA256 of that
+    /// key must match the given hash.  This allows the device to only store
+    /// the hash of the key.
+    pub fn validate_key_hash<C: CryptoBackend>(&self, crypto: &mut C, key_hash: &Hash256) -> Result<()> {
+        self.validate_key_hash_by(crypto, key_hash, &[&EcdsaP256])
+    }
+
+    /// Validate this image as `validate_key_hash` does, but with the signature
+    /// checked by whichever of `schemes` handles it.
+    pub fn validate_key_hash_by<C: CryptoBackend>(&self, crypto: &mut C, key_hash: &Hash256,
+                                                  schemes: &[&dyn SignatureScheme<C>]) -> Result<()> {
+        self.validate_inner(crypto, Trust::KeyHash(key_hash), None, schemes)
+    }
+
+    /// Validate this image as `validate_signed` does, but with the hash of the
+    /// image already computed by the caller, such as while it was being
+    /// copied, so the image isn't read again.  The hash must cover
+    /// `hashed_size` bytes of what is in this image's flash.
+    pub fn validate_signed_with_hash<C: CryptoBackend>(&self, crypto: &mut C, key: &[u8], hash: &Hash256) -> Result<()> {
+        self.validate_inner(crypto, Trust::Key(key), Some(hash), &[&EcdsaP256])
+    }
+
+    fn validate_inner<C: CryptoBackend>(&self, crypto: &mut C, trust: Trust, hash: Option<&Hash256>,
+                                        schemes: &[&dyn SignatureScheme<C>]) -> Result<()> {
+        let result = self.check_tlvs(crypto, trust, hash, schemes);
+        match &result {
+            Ok(()) => event(EventCode::Valid, 0),
+            Err(err) => event(EventCode::Invalid, ErrorCode::from(err) as u32),
+        }
+        result
+    }
+
+    fn check_tlvs<C: CryptoBackend>(&self, crypto: &mut C, trust: Trust, hash: Option<&Hash256>,
+                                    schemes: &[&dyn SignatureScheme<C>]) -> Result<()> {
         // Things we must see.
         let mut seen_sha = false;
-
-        for elt in self.tlvs()? {
+        // A public key found in the image.
+        let mut image_key = [0u8; MAX_SIGNATURE];
+        let mut key_len = None;
+        // The hash of the image, once it has been verified.
+        let mut image_hash = None;
+        // The signature, if present, to be checked after the hash, and the
+        // scheme that checks it.
+        let mut sig_buf = [0u8; MAX_SIGNATURE];
+        let mut signature = None;
+
+        for elt in self.protected_tlvs().chain(self.tlvs()?) {
             let elt = elt?;
             // println!("TLV: 0x{:x}", elt.kind());
             match elt.kind() {
+                TLV_DEPENDENCY => {
+                    // Checked against the rest of the set by check_dependencies.
+                }
+                TLV_SEC_CNT => {
+                    // Checked against the device's counter by check_rollback.
+                }
+                TLV_MANIFEST => {
+                    // Checked against the device's policy by check_manifest.
+                }
                 TLV_SHA256 => {
                     if seen_sha {
                         // Only a single hash is allowed.
                         return Err(Error::InvalidImage);
                     }
                     seen_sha = true;
-                    let mut hash = [0u8; 32];
-                    elt.read_data(&mut hash)?;
-                    let image_hash = self.calculate_sha256()?;
-                    if hash != image_hash {
-                        println!("Hash verification failure");
+                    let mut stored = [0u8; 32];
+                    elt.read_data(&mut stored)?;
+                    let calculated = match hash {
+                        Some(hash) => *hash,
+                        None => self.calculate_sha256(crypto)?,
+                    };
+                    if !fih_eq(&stored, &calculated).is_true() {
+                        error!("Hash verification failure");
                         return Err(Error::InvalidImage);
                     }
+                    image_hash = Some(calculated);
+                }
+                TLV_KEYHASH => {
+                    // Only meaningful if we are checking signatures.  If so,
+                    // the hash must match the key we are given.
+                    if let Trust::Key(key) = trust {
+                        let mut hash = [0u8; 32];
+                        elt.read_data(&mut hash)?;
+                        crypto.sha256_start();
+                        crypto.sha256_update(key);
+                        if !fih_eq(&crypto.sha256_finish(), &hash).is_true() {
+                            error!("Key hash mismatch");
+                            return Err(Error::InvalidImage);
+                        }
+                    }
+                }
+                TLV_PUBKEY => {
+                    // Only used when we hold just the hash of the key.
+                    if let Trust::KeyHash(key_hash) = trust {
+                        let len = elt.data_len();
+                        if key_len.is_some() || len > image_key.len() {
+                            return Err(Error::InvalidImage);
+                        }
+                        key_len = Some(len);
+                        elt.read_data(&mut image_key[..len])?;
+                        crypto.sha256_start();
+                        crypto.sha256_update(&image_key[..len]);
+                        if !fih_eq(&crypto.sha256_finish(), key_hash).is_true() {
+                            error!("Public key does not match provisioned hash");
+                            return Err(Error::InvalidImage);
+                        }
+                    }
                 }
                 kind => {
-                    // Allow to be unused for embedded.
-                    let _ = kind;
-                    println!("Unexpected TLV 0x{:x}", kind);
-                    return Err(Error::InvalidImage);
+                    let scheme = match schemes.iter().find(|scheme| scheme.tlv_kind() == kind) {
+                        Some(scheme) => scheme,
+                        None => {
+                            error!("Unexpected TLV 0x{:x}", kind);
+                            return Err(Error::InvalidImage);
+                        }
+                    };
+                    if signature.is_some() || trust == Trust::None {
+                        return Err(Error::InvalidImage);
+                    }
+                    let len = elt.data_len();
+                    if len > sig_buf.len() {
+                        return Err(Error::InvalidImage);
+                    }
+                    elt.read_data(&mut sig_buf[..len])?;
+                    signature = Some((*scheme, len));
                 }
             }
         }
         if !seen_sha {
-            println!("Expecting SHA TLV");
+            error!("Expecting SHA TLV");
             return Err(Error::InvalidImage);
         }
+
+        let key = match (trust, key_len) {
+            (Trust::None, _) => None,
+            (Trust::Key(key), _) => Some(key),
+            (Trust::KeyHash(_), Some(len)) => Some(&image_key[..len]),
+            (Trust::KeyHash(_), None) => {
+                error!("Expecting PUBKEY TLV");
+                return Err(Error::InvalidImage);
+            }
+        };
+
+        if let Some(key) = key {
+            let (scheme, len) = signature.ok_or(Error::InvalidImage)?;
+            let hash = image_hash.ok_or(Error::InvalidImage)?;
+            let verified = FihBool::new(scheme.verify(crypto, key, &hash, &sig_buf[..len]));
+            if !verified.is_true() {
+                error!("Signature verification failure");
+                return Err(Error::InvalidImage);
+            }
+        }
+        info!("Image {} is valid", self.version());
         Ok(())
     }
 
-    /// Compute the hash of the data portion of the image.
-    fn calculate_sha256(&self) -> Result<Hash256> {
-        let mut hasher = Sha256::new();
-        let mut buffer = [0u8; 128];
-        let mut pos = 0;
-        while pos < self.tlv_base {
-            let todo = (self.tlv_base - pos).min(buffer.len());
-            let buf = &mut buffer[0..todo];
-            self.flash.borrow_mut().read(pos, buf)?;
-            hasher.update(buf);
-            pos += todo;
+    /// Write this image's unprotected TLVs into `dest`, at the same place,
+    /// leaving out the kinds `keep` rejects, and adding `extra` after the
+    /// rest.  This is for when the image has been transformed on its way to
+    /// `dest`: the header, payload, and protected TLVs must already be there,
+    /// up to `hashed_size` rounded down to `dest`'s write size.  Returns the
+    /// full size of the new image.
+    pub fn rewrite_tlvs<D: Flash>(&self, dest: &mut D, keep: impl Fn(u16) -> bool,
+                                  extra: &[(u16, &[u8])]) -> Result<usize> {
+        let mut len = TLV_HEADER_LEN;
+        for elt in self.tlvs()? {
+            let elt = elt?;
+            if keep(elt.kind()) {
+                len += TLV_HEADER_LEN + elt.data_len();
+            }
+        }
+        for (_, data) in extra {
+            len += TLV_HEADER_LEN + data.len();
+        }
+
+        // The end of the payload that shares a write unit with the TLVs.
+        let start = self.tlv_base / dest.write_size() * dest.write_size();
+        let mut prefix = [0u8; REWRITE_CHUNK];
+        let prefix = prefix.get_mut(..self.tlv_base - start).ok_or(Error::CannotUpgrade)?;
+        self.flash.with(|flash| flash.read(start, prefix))?;
+
+        let mut writer = TlvWriter::new(dest, start, prefix, false, len)?;
+        let mut buf = [0u8; REWRITE_CHUNK];
+        for elt in self.tlvs()? {
+            let elt = elt?;
+            if !keep(elt.kind()) {
+                continue;
+            }
+            writer.start_entry(elt.kind(), elt.data_len())?;
+            for pos in (0..elt.data_len()).step_by(REWRITE_CHUNK) {
+                let buf = &mut buf[..REWRITE_CHUNK.min(elt.data_len() - pos)];
+                elt.read_part(pos, buf)?;
+                writer.data(buf)?;
+            }
+        }
+        for (kind, data) in extra {
+            writer.entry(*kind, data)?;
         }
-        let mut result = [0u8; 32];
-        result.copy_from_slice(hasher.finalize().as_slice());
-        Ok(result)
+        writer.finish()?;
+        Ok(self.tlv_base + len)
+    }
+
+    /// Compute the hash of the data portion of the image.
+    fn calculate_sha256<C: CryptoBackend>(&self, crypto: &mut C) -> Result<Hash256> {
+        crypto.sha256_start();
+        self.flash.with(|flash| crypto.sha256_update_flash(flash, 0, self.tlv_base))?;
+        Ok(crypto.sha256_finish())
     }
 }
 
-impl<'a, F> Image<'a, F> {
+impl<'a, F, S: ?Sized> Image<'a, F, S> {
     /// Return the size, in bytes, of the entire image, including the TLV.
     pub fn full_image_size(&self) -> usize {
         self.tlv_base + self.tlv_size
     }
+
+    /// Return the size, in bytes, of the part of the image covered by the
+    /// hash: the header, payload, and protected TLV.
+    pub fn hashed_size(&self) -> usize {
+        self.tlv_base
+    }
 }
 
-pub struct TlvIter<'a, 'f, F> {
-    image: &'a Image<'f, F>,
+/// The entries of a TLV block.  An entry that doesn't fit within the block,
+/// more than `MAX_TLV_ENTRIES` of them, or a second entry of a kind that
+/// must be unique (such as a hash or a signature), is an error, after which
+/// the iteration ends.
+pub struct TlvIter<'a, 'f, F, S: ?Sized = RefCell<F>> {
+    image: &'a Image<'f, F, S>,
+    base: usize,
     pos: usize,
     limit: usize,
+    /// Entries returned so far.
+    count: usize,
+    /// Which of `SINGLE_TLVS` have been seen, by index.
+    seen: u32,
 }
 
-pub struct TlvIterEntry<'f, F> {
-    flash: &'f RefCell<F>,
+pub struct TlvIterEntry<'f, F, S: ?Sized = RefCell<F>> {
+    flash: &'f S,
+    _flash: PhantomData<F>,
     kind: u16,
     pos: usize,
     len: usize,
 }
 
-/// Helper like '?' for iterator operations, where errors should return
-/// Some(Err(e)) instead of just the error.  This macro contains a return.
-macro_rules! iter_try {
-    ($e:expr) => {
-        match $e {
-            Ok(r) => r,
-            Err(e) => return Some(Err(e.into())),
+impl<'a, 'f, F: ReadFlash, S: FlashCell<Flash = F> + ?Sized> TlvIter<'a, 'f, F, S> {
+    fn next_entry(&mut self) -> Result<TlvIterEntry<'f, F, S>> {
+        if self.count == MAX_TLV_ENTRIES {
+            error!("More than {} TLV entries", MAX_TLV_ENTRIES);
+            return Err(Error::InvalidImage);
+        }
+        self.count += 1;
+
+        let mut entry = TlvEntry::default();
+        let pos = self.base.checked_add(self.pos).ok_or(Error::InvalidImage)?;
+        self.image.flash.with(|flash| flash.read(pos, entry.as_mut_raw()))?;
+
+        // The position is relative to the start of the TLV block, which the
+        // entry must fit in.
+        let next = self.pos + size_of::<TlvEntry>() + entry.len as usize;
+        if next > self.limit {
+            error!("TLV 0x{:x} overruns its block", entry.kind);
+            return Err(Error::InvalidImage);
         }
-    };
+        if let Some(n) = SINGLE_TLVS.iter().position(|&kind| kind == entry.kind) {
+            if self.seen & (1 << n) != 0 {
+                error!("Duplicate TLV 0x{:x}", entry.kind);
+                return Err(Error::InvalidImage);
+            }
+            self.seen |= 1 << n;
+        }
+        self.pos = next;
+        Ok(TlvIterEntry {
+            flash: self.image.flash,
+            _flash: PhantomData,
+            kind: entry.kind,
+            pos: pos + size_of::<TlvEntry>(),
+            len: entry.len as usize,
+        })
+    }
 }
 
-impl<'a, 'f, F: ReadFlash> Iterator for TlvIter<'a, 'f, F> {
-    type Item = Result<TlvIterEntry<'f, F>>;
+impl<'a, 'f, F: ReadFlash, S: FlashCell<Flash = F> + ?Sized> Iterator for TlvIter<'a, 'f, F, S> {
+    type Item = Result<TlvIterEntry<'f, F, S>>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.pos >= self.limit {
             return None;
         }
-
-        let mut entry = TlvEntry::default();
-        let pos = iter_try!(self
-            .image
-            .tlv_base
-            .checked_add(self.pos)
-            .ok_or(Error::InvalidImage));
-        iter_try!(self
-            .image
-            .flash
-            .borrow_mut()
-            .read(pos, entry.as_mut_raw()));
-        let data_pos = iter_try!(pos
-            .checked_add(size_of::<TlvEntry>())
-            .ok_or(Error::InvalidImage));
-        self.pos = iter_try!(data_pos
-            .checked_add(entry.len as usize)
-            .ok_or(Error::InvalidImage));
-        Some(Ok(TlvIterEntry {
-            flash: self.image.flash,
-            kind: entry.kind,
-            pos: data_pos,
-            len: entry.len as usize,
-        }))
+        let result = self.next_entry();
+        if result.is_err() {
+            self.pos = self.limit;
+        }
+        Some(result)
     }
 }
 
-impl<'f, F: ReadFlash> TlvIterEntry<'f, F> {
+impl<'f, F: ReadFlash, S: FlashCell<Flash = F> + ?Sized> TlvIterEntry<'f, F, S> {
     /// What is the kind of this TLV entry.
     pub fn kind(&self) -> u16 {
         self.kind
@@ -248,15 +592,52 @@ impl<'f, F: ReadFlash> TlvIterEntry<'f, F> {
             // TODO: Is something more meaningful here?
             return Err(Error::InvalidImage);
         }
-        self.flash.borrow_mut().read(self.pos, data)?;
+        self.flash.with(|flash| flash.read(self.pos, data))?;
+        Ok(())
+    }
+
+    /// Read part of the payload, starting `offset` bytes in.
+    pub(crate) fn read_part(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        if offset + data.len() > self.len {
+            return Err(Error::InvalidImage);
+        }
+        self.flash.with(|flash| flash.read(self.pos + offset, data))?;
         Ok(())
     }
 }
 
+/// What signatures are checked against.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Trust<'k> {
+    /// Signatures are not checked, only the hash.
+    None,
+    /// The image must be signed by this key.
+    Key(&'k [u8]),
+    /// The image must be signed by the key it carries, which must have this
+    /// hash.
+    KeyHash(&'k Hash256),
+}
+
 /// For mapped flash, we can get the base address of the XIP area.
-impl<'f, F: MappedFlash> Image<'f, F> {
-    pub fn get_image_base(&self) -> usize {
-        self.flash.borrow().get_base() + self.header.hdr_size as usize
+impl<'f, F: MappedFlash, S: FlashCell<Flash = F> + ?Sized> Image<'f, F, S> {
+    /// The address of the image's code, which follows the header.  It must be
+    /// aligned as the architecture requires of a vector table.
+    pub fn get_image_base(&self) -> Result<usize> {
+        self.image_base(VECTOR_ALIGN)
+    }
+
+    /// The address of the image's code, which must be a multiple of `align`.
+    /// For targets whose vector table needs more alignment than the
+    /// architecture's minimum.
+    pub fn image_base(&self, align: usize) -> Result<usize> {
+        let base = self.flash.with(|flash| flash.get_base())
+            .checked_add(self.header.hdr_size as usize)
+            .ok_or(Error::InvalidImage)?;
+        if base % align != 0 {
+            error!("Image code at 0x{:x} is not aligned to {}", base, align);
+            return Err(Error::InvalidImage);
+        }
+        Ok(base)
     }
 }
 
@@ -289,19 +670,52 @@ pub struct ImageHeader {
 }
 
 impl AsRaw for ImageHeader {}
-unsafe impl AsMutRaw for ImageHeader {}
+unsafe impl AsMutRaw for ImageHeader {
+    fn validate_raw(&self) -> bool {
+        self.magic == IMAGE_MAGIC
+    }
+}
 
 /// Each image has a version.  This is a pseudo-semantic version used to
 /// determine upgrade elligibility and compatible between multi-image setups.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct ImageVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build_num: u32,
+}
+
+impl ImageVersion {
+    /// Is this version at least the given one?  As with MCUboot, the build
+    /// number is not considered.
+    pub fn at_least(&self, other: &ImageVersion) -> bool {
+        (self.major, self.minor, self.revision) >= (other.major, other.minor, other.revision)
+    }
+}
+
+/// Versions are shown as imgtool writes them, "major.minor.revision+build".
+impl fmt::Display for ImageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}+{}", self.major, self.minor, self.revision, self.build_num)
+    }
+}
+
+/// A dependency of one image in a multi-image set on another.  This is the
+/// payload of the DEPENDENCY TLV, which is always protected.
 #[derive(Debug, Default)]
 #[repr(C)]
-struct ImageVersion {
-    major: u8,
-    minor: u8,
-    revision: u16,
-    build_num: u32,
+struct ImageDependency {
+    image_id: u8,
+    pad1: u8,
+    pad2: u16,
+    min_version: ImageVersion,
 }
 
+impl AsRaw for ImageDependency {}
+unsafe impl AsMutRaw for ImageDependency {}
+
 /// The TLV block contains this header.
 #[derive(Debug, Default)]
 #[repr(C)]
@@ -312,13 +726,24 @@ struct TlvInfo {
     len: u16,
 }
 
-const TLV_INFO_MAGIC: u16 = 0x6907;
+pub(crate) const TLV_INFO_MAGIC: u16 = 0x6907;
+pub(crate) const TLV_PROT_INFO_MAGIC: u16 = 0x6908;
 
 // Supported TLVS
+const TLV_KEYHASH: u16 = 0x01;
+const TLV_PUBKEY: u16 = 0x02;
 const TLV_SHA256: u16 = 0x10;
+const TLV_DEPENDENCY: u16 = 0x40;
+const TLV_SEC_CNT: u16 = 0x50;
+/// In the vendor range, as SUIT manifests have no assigned type.
+pub(crate) const TLV_MANIFEST: u16 = 0xa0;
 
 impl AsRaw for TlvInfo {}
-unsafe impl AsMutRaw for TlvInfo {}
+unsafe impl AsMutRaw for TlvInfo {
+    fn validate_raw(&self) -> bool {
+        self.magic == TLV_INFO_MAGIC || self.magic == TLV_PROT_INFO_MAGIC
+    }
+}
 
 /// Each TLV entry is preceeded by this header.
 #[derive(Debug, Default)]
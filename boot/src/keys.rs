@@ -0,0 +1,59 @@
+//! Key provisioning
+//!
+//! Rather than compile the public key into the bootloader, a device can have
+//! the SHA256 of the key provisioned into some protected storage.  Images then
+//! carry the full public key (imgtool's `--public-key-format full`), which is
+//! checked against this hash before use.
+
+use storage::{Error as FlashError, ReadFlash};
+
+use crate::{Error, Hash256, Result};
+
+/// A source of the provisioned key hash.
+pub trait KeyStore {
+    /// Return the provisioned key hash, or None if the device has not been
+    /// provisioned.
+    fn key_hash(&mut self) -> Result<Option<Hash256>>;
+}
+
+/// A key store holding the hash at a given offset in a flash device.  This
+/// allows host tests to simulate the protected flash of a device.
+///
+/// A hash that has never been written, or is all 0x00 or 0xff, is treated as
+/// unprovisioned.
+pub struct FlashKeyStore<F> {
+    flash: F,
+    offset: usize,
+}
+
+impl<F: ReadFlash> FlashKeyStore<F> {
+    pub fn new(flash: F, offset: usize) -> FlashKeyStore<F> {
+        FlashKeyStore { flash, offset }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: ReadFlash> KeyStore for FlashKeyStore<F> {
+    fn key_hash(&mut self) -> Result<Option<Hash256>> {
+        let mut hash = [0u8; 32];
+        match self.flash.read(self.offset, &mut hash) {
+            Ok(()) => (),
+            Err(FlashError::NotWritten) => return Ok(None),
+            Err(e) => return Err(Error::Flash(e)),
+        }
+        Ok(provisioned(hash))
+    }
+}
+
+/// Check a hash read from storage, returning None if it looks blank.
+pub fn provisioned(hash: Hash256) -> Option<Hash256> {
+    if hash.iter().all(|&b| b == 0x00) || hash.iter().all(|&b| b == 0xff) {
+        None
+    } else {
+        Some(hash)
+    }
+}
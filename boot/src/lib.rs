@@ -4,13 +4,111 @@
 
 #![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+mod cbor;
+mod cell;
+mod chain;
+mod clean;
+mod crypto;
+mod delay;
+mod ecdsa;
+mod entropy;
+mod events;
+mod fih;
 mod image;
+mod keys;
+mod listing;
+mod load;
+mod logging;
+mod manifest;
+mod migrate;
+mod mpu;
+mod policy;
+pub mod recovery;
+mod remap;
+mod request;
+mod revert;
+mod rollback;
+mod scheme;
+mod scrub;
+mod selftest;
+mod shared;
+mod slot;
+mod stack;
+mod stages;
 mod status;
+mod tlv;
+mod trailer;
+mod validated;
+mod verify;
+mod wake;
+mod watchdog;
+mod wrap;
 
-pub use image::Image;
-pub use status::SlotInfo;
+pub use cell::FlashCell;
+pub use chain::{chain, Chainer, VECTOR_ALIGN};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use chain::{CortexM, ProtectedCortexM};
+pub use clean::{clean_secondary, confirm_upgrade, erase_remnants, find_remnant, SecondaryCleanup};
+pub use crypto::{ct_eq, CryptoBackend, Hash256, SoftCrypto};
+pub use delay::{startup_window, wait_window, Delay, Escape, SerialEscape, Window, BOOT_DELAY_MS, ESCAPE_CHAR};
+pub use ecdsa::{verify as ecdsa_verify, SoftMul, WideMul, U256};
+#[cfg(feature = "std")]
+pub use ecdsa::{public_key as ecdsa_public_key, sign as ecdsa_sign};
+pub use entropy::{EntropyError, EntropySource, SeededEntropy};
+pub use events::{event, read_events, set_event_log, Event, EventCode, EventLog, EVENTS_MAGIC, MAX_EVENT_VALUE};
+pub use fih::{fih_eq, fih_panic, FihBool, LoopCounter};
+pub use image::{Image, ImageVersion, Probe, MAX_HEADER_SIZE, MAX_TLV_ENTRIES};
+pub use keys::{provisioned, FlashKeyStore, KeyStore};
+pub use listing::{
+    list_slots, read_listing, write_listing, Listing, SlotSummary, LISTING_MAGIC, LISTING_SIZE, LISTING_VERSION,
+    SLOT_ACTIVE, SLOT_CONFIRMED, SLOT_PENDING, SLOT_PERMANENT, SLOT_PRESENT,
+};
+pub use load::{load_to_internal, Loaded};
+pub use logging::{log, set_logger, Level, Log};
+pub use manifest::{
+    check_manifest, Condition, Conditions, Manifest, ManifestPolicy, Uuid, Value, CONDITION_COMPONENT_SLOT,
+    CONDITION_DEVICE_ID, CONDITION_MINIMUM_BATTERY, CONDITION_USE_BEFORE, MAX_MANIFEST,
+};
+pub use migrate::migrate_c_trailer;
+pub use mpu::{protect, Access, Mpu, MpuError, Region};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use mpu::{Pmsav7, Pmsav8};
+pub use policy::{check_version, BootAction, BootPolicy, BootView, DefaultPolicy, SlotView, VersionPolicy};
+pub use remap::{remap_boot, remap_swap_type, Remap, RemapRegion, RemapState, RemapStatus};
+pub use request::{request_recovery, take_recovery_request, Retained, RetainedWord, RECOVERY_REQUEST};
+pub use revert::{copy_back, CopyBack};
+pub use rollback::{advance_rollback, check_rollback, FlashCounter, Otp, OtpCounter, RollbackCounter};
+pub use scheme::{EcdsaP256, SignatureScheme, MAX_SIGNATURE};
+pub use scrub::{chain_clearing, chain_scrubbed, scrub_stack, zeroize, ClearRam, SCRUB_STACK};
+pub use selftest::{confirm_after, record_test_boot, test_boot_attempts, TestAttempts, TEST_BOOT};
+pub use shared::{clear_shared, read_shared, write_shared, BootInfo, SharedError, SHARED_MAGIC, SHARED_SIZE, SHARED_VERSION};
+pub use slot::{Slot, SlotPurpose};
+pub use stack::{paint_stack, stack_used, STACK_PAINT};
+#[cfg(feature = "std")]
+pub use stack::measure_stack;
+pub use stages::{
+    chain_next, chain_stages, read_handoff, validate_stages, write_handoff, Handoff, StageInfo, Stages,
+    HANDOFF_MAGIC, HANDOFF_SIZE, HANDOFF_VERSION, MAX_STAGES,
+};
+pub use status::{
+    advance_generation, check_generation, group_hash, new_hash_seed, next_generation, request_upgrade,
+    upgrade_requested,
+    SlotInfo, Status, StatusLayout, StatusRead, StatusStyle, DEFAULT_STATUS_PAGES, MAGIC as TRAILER_MAGIC,
+    MAX_PROGRESS_GROUP, MAX_STATUS_PAGES, STATUS_VERSION,
+};
+pub use tlv::{TlvWriter, TLV_HEADER_LEN};
+pub use trailer::{
+    boot_state, confirm, copy_done, image_ok, is_confirmed, last_boot_error, record_boot_error,
+    set_copy_done, swap_type, trailer_size, BootError, BootState, ErrorCode, Flag, SwapType, ERROR_CANNOT_UPGRADE,
+    ERROR_FLASH, ERROR_FLASH_FAILED, ERROR_FLASH_NOT_ALIGNED, ERROR_FLASH_NOT_ERASED,
+    ERROR_FLASH_NOT_WRITTEN, ERROR_FLASH_OUT_OF_BOUNDS, ERROR_INVALID_IMAGE,
+};
+pub use validated::{invalidate, validate_cached, GuardedFlash, Validation};
+pub use verify::VerifiedFlash;
+pub use wake::{clear_wake_marker, set_wake_marker, wake_fast_path, WakeGuard, WAKE_MAGIC};
+pub use watchdog::{NoWatchdog, Watchdog, WatchedFlash};
 
-type Result<T> = core::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 // Use the error kind to avoid this depending on the particular flash.
 #[derive(Debug)]
@@ -27,6 +125,13 @@ impl From<storage::Error> for Error {
     }
 }
 
+/// A structure read from flash that fails to parse indicates a bad image.
+impl From<asraw::Error> for Error {
+    fn from(_: asraw::Error) -> Self {
+        Error::InvalidImage
+    }
+}
+
 /// Some kinds of flash can be mapped into memory.  This is needed for XIP devices.
 pub trait MappedFlash {
     /// Return the base address of this flash partition, as mapped into memory.
@@ -0,0 +1,161 @@
+//! Listing the slots
+//!
+//! Management protocols, such as SMP's image list, and the application,
+//! through the shared RAM, want the same description of the slots: what
+//! image each holds, and where it stands in an upgrade.  `list_slots` builds
+//! it from the headers, TLVs and trailers, so none of them parse the slots
+//! themselves.
+//!
+//! A `Listing` is plain data, with the layout given here, so it can be
+//! encoded by the protocol, or copied as it is into a block framed as the
+//! shared block is (see `write_shared`), with its own magic.  Each slot has:
+//!
+//! +---------+------+---------+------+-------+----------+
+//! | version | size | hash    | slot | flags | reserved |
+//! | 8       | u32  | 32      | u8   | u8    | u16      |
+//! +---------+------+---------+------+-------+----------+
+//!
+//! The hash is the whole SHA-256 from the image's TLV, not a prefix, as
+//! mcumgr names an image by it to test or confirm it.  It is not checked
+//! against the image here.
+
+use asraw::{AsMutRaw, AsRaw};
+use storage::Flash;
+
+use crate::shared::{block_size, read_block, write_block};
+use crate::{is_confirmed, swap_type, FlashCell, Hash256, Image, ImageVersion, Result, SharedError, SwapType};
+
+/// Magic value at the start of the listing block.
+pub const LISTING_MAGIC: u32 = 0x6273_6c73;
+
+/// Version of the layout of `Listing`.  Any change to it must change this.
+pub const LISTING_VERSION: u16 = 1;
+
+/// Bytes needed for the whole listing block.
+pub const LISTING_SIZE: usize = block_size::<Listing>();
+
+// Slot flags.
+/// The slot holds an image, with a header and TLV that can be read.
+pub const SLOT_PRESENT: u8 = 0x01;
+/// The image is the one running, or that will run without an upgrade.
+pub const SLOT_ACTIVE: u8 = 0x02;
+/// The image is to be swapped in, or back, on the next boot.
+pub const SLOT_PENDING: u8 = 0x04;
+/// The image has been confirmed, so won't be reverted.
+pub const SLOT_CONFIRMED: u8 = 0x08;
+/// The pending upgrade is permanent, rather than a test.
+pub const SLOT_PERMANENT: u8 = 0x10;
+
+/// What one slot holds.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SlotSummary {
+    pub version: ImageVersion,
+    /// Size of the image, including the header and TLV.
+    pub size: u32,
+    /// The hash recorded in the image.
+    pub hash: Hash256,
+    /// The slot number, 0 for the primary.
+    pub slot: u8,
+    /// `SLOT_*` flags.
+    pub flags: u8,
+    pub reserved: u16,
+}
+
+impl SlotSummary {
+    /// The slot holds an image.  The other fields mean nothing if not.
+    pub fn present(&self) -> bool {
+        self.flags & SLOT_PRESENT != 0
+    }
+
+    pub fn active(&self) -> bool {
+        self.flags & SLOT_ACTIVE != 0
+    }
+
+    pub fn pending(&self) -> bool {
+        self.flags & SLOT_PENDING != 0
+    }
+
+    pub fn confirmed(&self) -> bool {
+        self.flags & SLOT_CONFIRMED != 0
+    }
+
+    pub fn permanent(&self) -> bool {
+        self.flags & SLOT_PERMANENT != 0
+    }
+}
+
+/// Both slots, the primary first.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct Listing {
+    pub slots: [SlotSummary; 2],
+}
+
+impl AsRaw for SlotSummary {}
+unsafe impl AsMutRaw for SlotSummary {}
+impl AsRaw for Listing {}
+unsafe impl AsMutRaw for Listing {}
+
+impl Listing {
+    /// The slots that hold an image.
+    pub fn images(&self) -> impl Iterator<Item = &SlotSummary> {
+        self.slots.iter().filter(|slot| slot.present())
+    }
+}
+
+/// The image in a slot, if it has one whose hash can be read.
+fn summarize<F: Flash, S: FlashCell<Flash = F> + ?Sized>(slot: u8, flash: &S, flags: u8) -> SlotSummary {
+    let image = match Image::from_flash(flash) {
+        Ok(image) => image,
+        Err(_) => return SlotSummary { slot, ..SlotSummary::default() },
+    };
+    match image.stored_sha256() {
+        Ok(hash) => SlotSummary {
+            version: image.version(),
+            size: image.full_image_size() as u32,
+            hash,
+            slot,
+            flags: flags | SLOT_PRESENT,
+            reserved: 0,
+        },
+        Err(_) => SlotSummary { slot, ..SlotSummary::default() },
+    }
+}
+
+/// Describe both slots, each shared as an `Image` is, such as through a
+/// `RefCell`.  A slot without a readable image is listed, but not present.
+/// Only failures reading the trailers are errors.
+pub fn list_slots<F: Flash>(primary: &impl FlashCell<Flash = F>, secondary: &impl FlashCell<Flash = F>)
+    -> Result<Listing>
+{
+    let kind = primary.with(|primary| secondary.with(|secondary| swap_type(primary, secondary)))?;
+    let confirmed = primary.with(is_confirmed)?;
+
+    let mut flags = SLOT_ACTIVE;
+    if confirmed {
+        flags |= SLOT_CONFIRMED;
+    }
+    if kind == SwapType::Revert {
+        flags |= SLOT_PENDING;
+    }
+    let primary = summarize(0, primary, flags);
+
+    let flags = match kind {
+        SwapType::Test => SLOT_PENDING,
+        SwapType::Perm => SLOT_PENDING | SLOT_PERMANENT,
+        _ => 0,
+    };
+    let secondary = summarize(1, secondary, flags);
+    Ok(Listing { slots: [primary, secondary] })
+}
+
+/// Write the listing block at the start of `buf`.
+pub fn write_listing(buf: &mut [u8], listing: &Listing) -> core::result::Result<(), SharedError> {
+    write_block(buf, LISTING_MAGIC, LISTING_VERSION, listing)
+}
+
+/// Read the listing block at the start of `buf`.
+pub fn read_listing(buf: &[u8]) -> core::result::Result<Listing, SharedError> {
+    read_block(buf, LISTING_MAGIC, LISTING_VERSION)
+}
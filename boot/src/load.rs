@@ -0,0 +1,97 @@
+//! Loading images into internal flash
+//!
+//! Some configurations keep the image in external flash that can't be
+//! executed from, and run it from internal flash.  The image in the external
+//! slot is the authoritative one, and the internal slot holds a copy of it.
+//!
+//! Copying on every boot would wear out the internal flash and slow the boot,
+//! so the hash recorded in each image is compared first.  When they match, the
+//! internal copy is validated and booted as is.  Only when they differ is the
+//! external image validated and copied.  A copy interrupted by a reset leaves
+//! an internal image that fails to validate, and the copy is redone.
+//!
+//! The copy is hashed as it passes through the buffer, so checking it
+//! afterwards only reads the TLV, rather than the whole internal image again.
+//! Only the sectors the image needs are erased; a board that wants the rest
+//! of the internal slot cleared can call `erase_remnants` after a copy.
+
+use core::cell::RefCell;
+
+use storage::{Flash, ReadFlash};
+
+use crate::{ct_eq, error, info, CryptoBackend, Error, Image, Result};
+
+/// Bytes copied at a time.  Must be a multiple of the destination's write
+/// size.
+const CHUNK: usize = 512;
+
+/// What `load_to_internal` did.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Loaded {
+    /// The internal copy was already up to date.
+    Unchanged,
+    /// The image was copied.
+    Copied,
+}
+
+/// Make sure `dest` holds a validated copy of the image in `src`.  On success,
+/// the image in `dest` has been validated with the given key, and can be
+/// booted.  If `src` has no valid image, an error is returned, and the board
+/// may still choose to validate and boot whatever is in `dest`.
+pub fn load_to_internal<S, D, C>(src: &RefCell<S>, dest: &RefCell<D>, crypto: &mut C, key: &[u8]) -> Result<Loaded>
+    where S: ReadFlash, D: Flash, C: CryptoBackend,
+{
+    let src_image = Image::from_flash(src)?;
+    let src_hash = src_image.stored_sha256()?;
+
+    if let Ok(dest_image) = Image::from_flash(dest) {
+        if dest_image.stored_sha256().is_ok_and(|hash| ct_eq(&hash, &src_hash)) &&
+            dest_image.validate_signed(crypto, key).is_ok()
+        {
+            info!("Internal image is up to date");
+            return Ok(Loaded::Unchanged);
+        }
+    }
+
+    src_image.validate_signed(crypto, key)?;
+    let size = src_image.full_image_size();
+    let hashed = src_image.hashed_size();
+
+    {
+        let mut src = src.borrow_mut();
+        let mut dest = dest.borrow_mut();
+        if size > dest.capacity() || !CHUNK.is_multiple_of(dest.write_size()) {
+            error!("Image of {} bytes can't be loaded", size);
+            return Err(Error::CannotUpgrade);
+        }
+        info!("Loading {} bytes to internal flash", size);
+
+        let erase_size = dest.erase_size();
+        dest.erase(0, size.next_multiple_of(erase_size))?;
+        let mut buf = [0u8; CHUNK];
+        crypto.sha256_start();
+        for pos in (0..size).step_by(CHUNK) {
+            let len = CHUNK.min(size - pos);
+            let read_len = len.next_multiple_of(src.read_size()).min(CHUNK);
+            src.read(pos, &mut buf[..read_len])?;
+            buf[len..].fill(0xff);
+            let padded = len.next_multiple_of(dest.write_size());
+            dest.write(pos, &buf[..padded])?;
+            if pos < hashed {
+                crypto.sha256_update(&buf[..len.min(hashed - pos)]);
+            }
+        }
+    }
+    let hash = crypto.sha256_finish();
+
+    // The copy is what will be booted, so check it, rather than the source.
+    // The header and TLV are read back from it, and the hash is of what was
+    // written.
+    let dest_image = Image::from_flash(dest)?;
+    if dest_image.hashed_size() != hashed {
+        error!("Internal copy doesn't match");
+        return Err(Error::InvalidImage);
+    }
+    dest_image.validate_signed_with_hash(crypto, key, &hash)?;
+    Ok(Loaded::Copied)
+}
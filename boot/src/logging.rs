@@ -0,0 +1,111 @@
+//! Logging
+//!
+//! The bootloader reports what it decides (the status it decoded, whether an
+//! image validated, each step of an upgrade) through leveled messages:
+//! `error!`, `warn!`, `info!` and `debug!`.  These are exported, so boards can
+//! use them as well.
+//!
+//! Without the `logging` feature, the messages, and their formatting, are
+//! compiled out.  With it, they go to the logger installed by the board with
+//! `set_logger`.  The `boot-log` crate has loggers for defmt and the log
+//! crate.  Under `std`, which enables `logging`, messages are printed when no
+//! logger has been installed.
+
+use core::fmt;
+
+/// The importance of a message.  Lower is more important.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        })
+    }
+}
+
+/// A destination for log messages.
+pub trait Log: Sync {
+    fn log(&self, level: Level, args: fmt::Arguments<'_>);
+}
+
+static mut LOGGER: Option<&'static dyn Log> = None;
+
+/// Install the logger.
+///
+/// # Safety
+///
+/// The bootloader is single threaded, and this isn't synchronized.  It must be
+/// called before anything is logged, typically at the start of `main`, and not
+/// from an interrupt handler.
+pub unsafe fn set_logger(logger: &'static dyn Log) {
+    LOGGER = Some(logger);
+}
+
+/// Send a message to the logger.  This is called by the macros.
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    // SAFETY: Only written by `set_logger`, before anything is logged.
+    let logger = unsafe { *core::ptr::addr_of!(LOGGER) };
+    match logger {
+        Some(logger) => logger.log(level, args),
+        #[cfg(feature = "std")]
+        None => println!("{}: {}", level, args),
+        #[cfg(not(feature = "std"))]
+        None => (),
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "logging")]
+#[macro_export]
+macro_rules! __log {
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log($crate::Level::$level, format_args!($($arg)+))
+    };
+}
+
+/// Without logging, still type check the arguments, so that a message can't
+/// break the build when logging is enabled, but generate nothing.
+#[doc(hidden)]
+#[cfg(not(feature = "logging"))]
+#[macro_export]
+macro_rules! __log {
+    ($level:ident, $($arg:tt)+) => {
+        if false {
+            $crate::log($crate::Level::$level, format_args!($($arg)+))
+        }
+    };
+}
+
+/// Log a failure, such as an image that doesn't validate.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::__log!(Error, $($arg)+) };
+}
+
+/// Log something unexpected, that the bootloader can continue past.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::__log!(Warn, $($arg)+) };
+}
+
+/// Log a decision: which image is booted, and why.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::__log!(Info, $($arg)+) };
+}
+
+/// Log details, such as the status that was decoded.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::__log!(Debug, $($arg)+) };
+}
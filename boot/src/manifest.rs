@@ -0,0 +1,233 @@
+//! Manifests
+//!
+//! An image can carry a manifest, loosely following IETF SUIT, in its
+//! protected TLV, so it is covered by the hash and signature.  It says what
+//! the image is for, and under what conditions it may be installed.  It is a
+//! CBOR map:
+//!
+//! ```text
+//! manifest = {
+//!     1 => bstr,              ; component identifier
+//!     ? 2 => bstr .size 16,   ; vendor UUID
+//!     ? 3 => bstr .size 16,   ; class UUID
+//!     ? 4 => [* condition],   ; install conditions
+//! }
+//! condition = [kind: uint, value: uint / bstr / tstr / bool]
+//! ```
+//!
+//! Condition kinds use SUIT's numbering where there is one (see the
+//! `CONDITION_` constants).  Other keys in the manifest are ignored, so it can
+//! grow.
+//!
+//! The bootloader decides what is acceptable through a `ManifestPolicy`, and
+//! `check_manifest` applies it, in the same way as `check_rollback`.
+
+use storage::ReadFlash;
+
+use crate::cbor::{Decoder, Item};
+use crate::image::TLV_MANIFEST;
+use crate::{error, Error, Image, Result};
+
+/// Largest manifest supported.
+pub const MAX_MANIFEST: usize = 512;
+
+/// The device's unique identifier (a bstr) must match.
+pub const CONDITION_DEVICE_ID: u64 = 24;
+/// The image must go in the given slot of its component (a uint).
+pub const CONDITION_COMPONENT_SLOT: u64 = 5;
+/// The image must not be installed after this time (a uint, in seconds since
+/// the epoch).
+pub const CONDITION_USE_BEFORE: u64 = 4;
+/// The battery must be charged to at least this level (a uint, in percent).
+pub const CONDITION_MINIMUM_BATTERY: u64 = 26;
+
+const KEY_COMPONENT: u64 = 1;
+const KEY_VENDOR: u64 = 2;
+const KEY_CLASS: u64 = 3;
+const KEY_CONDITIONS: u64 = 4;
+
+pub type Uuid = [u8; 16];
+
+/// The value of a condition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Value<'a> {
+    Uint(u64),
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Bool(bool),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Condition<'a> {
+    pub kind: u64,
+    pub value: Value<'a>,
+}
+
+/// A decoded manifest, borrowing from its encoding.
+#[derive(Clone, Copy, Debug)]
+pub struct Manifest<'a> {
+    pub component: &'a [u8],
+    pub vendor: Option<&'a Uuid>,
+    pub class: Option<&'a Uuid>,
+    /// The encoded conditions, after the array header.
+    conditions: &'a [u8],
+    condition_count: usize,
+}
+
+impl<'a> Manifest<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Manifest<'a>> {
+        let mut dec = Decoder::new(data);
+        let mut component = None;
+        let mut vendor = None;
+        let mut class = None;
+        let mut conditions: &[u8] = &[];
+        let mut condition_count = 0;
+        for _ in 0..dec.map()? {
+            match dec.next()? {
+                Item::Uint(KEY_COMPONENT) => component = Some(dec.bytes()?),
+                Item::Uint(KEY_VENDOR) => vendor = Some(uuid(dec.bytes()?)?),
+                Item::Uint(KEY_CLASS) => class = Some(uuid(dec.bytes()?)?),
+                Item::Uint(KEY_CONDITIONS) => {
+                    condition_count = dec.array()?;
+                    conditions = dec.rest();
+                    for _ in 0..condition_count {
+                        dec.skip()?;
+                    }
+                    conditions = &conditions[..conditions.len() - dec.rest().len()];
+                }
+                Item::Array(_) | Item::Map(_) | Item::Tag(_) => return Err(Error::InvalidImage),
+                _ => dec.skip()?,
+            }
+        }
+        if !dec.is_empty() {
+            return Err(Error::InvalidImage);
+        }
+        Ok(Manifest {
+            component: component.ok_or(Error::InvalidImage)?,
+            vendor,
+            class,
+            conditions,
+            condition_count,
+        })
+    }
+
+    /// The install conditions.
+    pub fn conditions(&self) -> Conditions<'a> {
+        Conditions { dec: Decoder::new(self.conditions), left: self.condition_count }
+    }
+}
+
+fn uuid(data: &[u8]) -> Result<&Uuid> {
+    data.try_into().map_err(|_| Error::InvalidImage)
+}
+
+pub struct Conditions<'a> {
+    dec: Decoder<'a>,
+    left: usize,
+}
+
+impl<'a> Conditions<'a> {
+    fn decode(&mut self) -> Result<Condition<'a>> {
+        if self.dec.array()? != 2 {
+            return Err(Error::InvalidImage);
+        }
+        let kind = self.dec.uint()?;
+        let value = match self.dec.next()? {
+            Item::Uint(value) => Value::Uint(value),
+            Item::Bytes(value) => Value::Bytes(value),
+            Item::Text(value) => Value::Text(value),
+            Item::Bool(value) => Value::Bool(value),
+            _ => return Err(Error::InvalidImage),
+        };
+        Ok(Condition { kind, value })
+    }
+}
+
+impl<'a> Iterator for Conditions<'a> {
+    type Item = Result<Condition<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        Some(self.decode())
+    }
+}
+
+/// What a device accepts.  Vendor and class, when given, must match the
+/// manifest's; the rest is up to the board.
+pub trait ManifestPolicy {
+    /// The device's vendor UUID.
+    fn vendor(&self) -> Option<Uuid> {
+        None
+    }
+
+    /// The device's class UUID.
+    fn class(&self) -> Option<Uuid> {
+        None
+    }
+
+    /// Refuse images without a manifest.
+    fn require_manifest(&self) -> bool {
+        false
+    }
+
+    /// Is this component one that belongs in the slot?
+    fn accept_component(&mut self, _component: &[u8]) -> bool {
+        true
+    }
+
+    /// Is this install condition met?  Conditions the device doesn't know
+    /// about must fail.
+    fn check_condition(&mut self, condition: &Condition) -> bool;
+}
+
+/// Check an image's manifest against the device's policy.
+pub fn check_manifest<F, P>(image: &Image<'_, F>, policy: &mut P) -> Result<()>
+    where F: ReadFlash, P: ManifestPolicy,
+{
+    let mut buf = [0u8; MAX_MANIFEST];
+    let mut found = None;
+    for elt in image.protected_tlvs() {
+        let elt = elt?;
+        if elt.kind() != TLV_MANIFEST {
+            continue;
+        }
+        let len = elt.data_len();
+        if found.is_some() || len > buf.len() {
+            return Err(Error::InvalidImage);
+        }
+        elt.read_data(&mut buf[..len])?;
+        found = Some(len);
+    }
+    let manifest = match found {
+        Some(len) => Manifest::parse(&buf[..len])?,
+        None if policy.require_manifest() => {
+            error!("Image has no manifest");
+            return Err(Error::InvalidImage);
+        }
+        None => return Ok(()),
+    };
+
+    let matches = |ours: Option<Uuid>, theirs: Option<&Uuid>| match ours {
+        Some(ours) => theirs == Some(&ours),
+        None => true,
+    };
+    if !matches(policy.vendor(), manifest.vendor) || !matches(policy.class(), manifest.class) {
+        error!("Manifest is for another device");
+        return Err(Error::InvalidImage);
+    }
+    if !policy.accept_component(manifest.component) {
+        error!("Manifest is for another component");
+        return Err(Error::InvalidImage);
+    }
+    for condition in manifest.conditions() {
+        let condition = condition?;
+        if !policy.check_condition(&condition) {
+            error!("Manifest condition {} not met", condition.kind);
+            return Err(Error::InvalidImage);
+        }
+    }
+    Ok(())
+}
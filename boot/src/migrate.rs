@@ -0,0 +1,140 @@
+//! Migration from C MCUboot trailers
+//!
+//! A device moving from the C bootloader to this one, by an over the air
+//! update of the bootloader itself, keeps the trailers the C bootloader and
+//! its application wrote.  Those describe the same state as ours (see
+//! `trailer`), with the same magic and flags at the same places, except:
+//!
+//! - Built with a `BOOT_MAX_ALIGN` other than 8, C MCUboot uses a different
+//!   magic, starting with that alignment, and spaces the flags by it rather
+//!   than by the write size.
+//! - C MCUboot keeps the swap info where our boot error record is, which
+//!   would be read as a bad record.
+//!
+//! `migrate_c_trailer` finds such a trailer, and rewrites it in our format,
+//! keeping whether an upgrade is requested (the magic), and the image ok and
+//! copy done flags.  It is meant to be called on each slot early in every
+//! boot; once a slot is migrated, or if it never had a C trailer, it does
+//! nothing.
+//!
+//! Rewriting means erasing the sectors holding the trailer, which is only done
+//! if the image in the slot ends before them.  The flags are written back
+//! before the magic, so a reset part way through can lose the pending request
+//! or a revert, but never make a revert out of a confirmed image.
+
+use core::cell::RefCell;
+
+use storage::Flash;
+
+use crate::status::{request_upgrade, MAGIC};
+use crate::trailer::{boot_error_offset, copy_done_offset, image_ok_offset, write_flag, FLAG_SET};
+use crate::{error, info, Error, Image, Result};
+
+/// The magic C MCUboot uses when `BOOT_MAX_ALIGN` isn't 8, after the 16-bit
+/// alignment.
+const C_ALIGNED_MAGIC: [u8; 14] = [
+    0x2d, 0xe1, 0x5d, 0x29, 0x41, 0x0b, 0x8d, 0x77,
+    0x67, 0x9c, 0x11, 0x0f, 0x1f, 0x8a,
+];
+
+/// Largest read supported.
+const MAX_READ: usize = 512;
+
+/// Where the fields of a C trailer are.
+struct CTrailer {
+    image_ok: usize,
+    copy_done: usize,
+    swap_info: usize,
+}
+
+impl CTrailer {
+    fn new(capacity: usize, align: usize) -> CTrailer {
+        let image_ok = (capacity - MAGIC.len() - align) & !(align - 1);
+        CTrailer { image_ok, copy_done: image_ok - align, swap_info: image_ok - 2 * align }
+    }
+}
+
+/// Read at least `len` bytes of a field, with erased flash reading as 0xff.
+fn read<F: Flash>(flash: &mut F, offset: usize, len: usize, buf: &mut [u8; MAX_READ]) -> Result<()> {
+    let size = flash.read_size().max(len);
+    if size > MAX_READ {
+        return Err(Error::CannotUpgrade);
+    }
+    match flash.read(offset, &mut buf[..size]) {
+        Ok(()) => Ok(()),
+        Err(storage::Error::NotWritten) => {
+            buf.fill(0xff);
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find a C trailer that needs migrating.
+fn find<F: Flash>(flash: &mut F) -> Result<Option<CTrailer>> {
+    let mut buf = [0u8; MAX_READ];
+    let size = flash.read_size().max(MAGIC.len());
+    read(flash, flash.capacity() - size, size, &mut buf)?;
+    let magic = &buf[size - MAGIC.len()..size];
+
+    if magic == MAGIC {
+        // Either ours, or C with an alignment of 8, which is only the same
+        // layout for small write sizes.  Then the swap info tells them apart:
+        // a single byte, where our record has a 16-bit code.
+        if flash.write_size() > 8 {
+            return Ok(None);
+        }
+        let trailer = CTrailer::new(flash.capacity(), 8);
+        read(flash, trailer.swap_info, 2, &mut buf)?;
+        let swap_info = buf[0] != 0xff && buf[1] == 0xff;
+        return Ok(if swap_info { Some(trailer) } else { None });
+    }
+
+    if magic[2..] == C_ALIGNED_MAGIC {
+        let align = u16::from_le_bytes([magic[0], magic[1]]) as usize;
+        if !align.is_power_of_two() || align < flash.write_size() || align > MAX_READ {
+            error!("C trailer with a bad alignment: {}", align);
+            return Err(Error::CannotUpgrade);
+        }
+        return Ok(Some(CTrailer::new(flash.capacity(), align)));
+    }
+    Ok(None)
+}
+
+/// Rewrite a trailer left by C MCUboot in this bootloader's format.  Returns
+/// whether there was one.
+pub fn migrate_c_trailer<F: Flash>(slot: &RefCell<F>) -> Result<bool> {
+    let trailer = match find(&mut *slot.borrow_mut())? {
+        Some(trailer) => trailer,
+        None => return Ok(false),
+    };
+
+    let image_end = Image::from_flash(slot).map_or(0, |image| image.full_image_size());
+    let mut flash = slot.borrow_mut();
+    let mut buf = [0u8; MAX_READ];
+    read(&mut *flash, trailer.image_ok, 1, &mut buf)?;
+    let image_ok = buf[0] == FLAG_SET;
+    read(&mut *flash, trailer.copy_done, 1, &mut buf)?;
+    let copy_done = buf[0] == FLAG_SET;
+
+    let lowest = trailer.swap_info.min(boot_error_offset(&*flash));
+    let start = lowest - lowest % flash.erase_size();
+    if image_end > start {
+        error!("Image runs into the C trailer, can't migrate it");
+        return Err(Error::CannotUpgrade);
+    }
+    info!("Migrating C trailer: image ok {}, copy done {}", image_ok, copy_done);
+
+    let capacity = flash.capacity();
+    flash.erase(start, capacity)?;
+    if image_ok {
+        let offset = image_ok_offset(&*flash);
+        write_flag(&mut *flash, offset)?;
+    }
+    if copy_done {
+        let offset = copy_done_offset(&*flash);
+        write_flag(&mut *flash, offset)?;
+    }
+    request_upgrade(&mut *flash)?;
+    Ok(true)
+}
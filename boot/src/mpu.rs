@@ -0,0 +1,190 @@
+//! Memory protection before chaining
+//!
+//! The bootloader can leave the MPU programmed when it chains, so that the
+//! application can't run code from the bootloader's flash, or, through a
+//! stray pointer, read or overwrite what it shouldn't.  The bootloader's own
+//! flash is made read only and execute never, and the status area can be
+//! blocked entirely.  Everything else keeps the default memory map, for
+//! privileged code.
+//!
+//! This is a defense against mistakes and unprivileged code: privileged code
+//! can reprogram the MPU, and flash controllers that program through their
+//! own registers, rather than writes to the flash, aren't affected by it.
+//!
+//! MPUs differ in how many regions they have, and what a region can be:
+//! ARMv7-M's are powers of two, aligned to their size, and ARMv8-M's are
+//! multiples of 32 bytes.  A board supplies an `Mpu` for its part, which
+//! checks each region, and `protect` programs them.  `Pmsav7` and `Pmsav8`
+//! are the Cortex-M ones.
+//!
+//! The MPU is only turned on by the jump to the image, as the bootloader is
+//! running from the flash it makes execute never.  On Cortex-M, chaining with
+//! `ProtectedCortexM` does this, from RAM.
+
+use core::ops::Range;
+
+use crate::{error, info};
+
+/// What may be done with a region.  Neither allows execution.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// Reads only.
+    ReadOnly,
+    /// Nothing at all, as far as the MPU can enforce it.
+    NoAccess,
+}
+
+/// An area of memory to protect.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub access: Access,
+}
+
+impl Region {
+    pub fn new(range: Range<usize>, access: Access) -> Region {
+        Region { range, access }
+    }
+}
+
+/// Reasons the regions can't be programmed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MpuError {
+    /// There are more regions than the MPU has.
+    TooManyRegions,
+    /// The MPU can't protect the region with this index as given, such as
+    /// when it isn't aligned.
+    Unsupported(usize),
+}
+
+/// A memory protection unit.
+pub trait Mpu {
+    /// The number of regions the MPU has.
+    fn regions(&self) -> usize;
+
+    /// Can this MPU protect exactly `region`?
+    fn supports(&self, region: &Region) -> bool;
+
+    /// Program region `index` with `region`, which `supports` allows.  The
+    /// MPU must be off.
+    ///
+    /// # Safety
+    ///
+    /// The region must not take effect before the chain, see the module
+    /// documentation.
+    unsafe fn set_region(&mut self, index: usize, region: &Region);
+}
+
+/// Program `regions` into the MPU, ready for the chain to turn it on.
+/// Nothing is changed if any region can't be programmed.
+///
+/// # Safety
+///
+/// See `Mpu::set_region`.  This is meant to be called just before chaining.
+pub unsafe fn protect<M: Mpu>(mpu: &mut M, regions: &[Region]) -> Result<(), MpuError> {
+    if regions.len() > mpu.regions() {
+        error!("{} MPU regions, but only {} available", regions.len(), mpu.regions());
+        return Err(MpuError::TooManyRegions);
+    }
+    if let Some(index) = regions.iter().position(|region| !mpu.supports(region)) {
+        error!("MPU can't protect 0x{:x}..0x{:x}", regions[index].range.start, regions[index].range.end);
+        return Err(MpuError::Unsupported(index));
+    }
+    for (index, region) in regions.iter().enumerate() {
+        info!("MPU region {}: 0x{:x}..0x{:x} {:?}", index, region.range.start, region.range.end, region.access);
+        mpu.set_region(index, region);
+    }
+    Ok(())
+}
+
+// The registers are at the same addresses on ARMv7-M and ARMv8-M.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod regs {
+    pub const TYPE: usize = 0xe000_ed90;
+    pub const RNR: usize = 0xe000_ed98;
+    pub const RBAR: usize = 0xe000_ed9c;
+    /// RASR on ARMv7-M, RLAR on ARMv8-M.
+    pub const RASR: usize = 0xe000_eda0;
+    pub const MAIR0: usize = 0xe000_edc0;
+
+    pub unsafe fn write(reg: usize, value: u32) {
+        core::ptr::write_volatile(reg as *mut u32, value);
+    }
+
+    /// The number of regions, from MPU_TYPE.
+    pub fn regions() -> usize {
+        let value = unsafe { core::ptr::read_volatile(TYPE as *const u32) };
+        ((value >> 8) & 0xff) as usize
+    }
+}
+
+/// The ARMv7-M MPU (Cortex-M3, M4, and M7).  Regions are a power of two of
+/// at least 32 bytes, aligned to their size.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub struct Pmsav7;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Mpu for Pmsav7 {
+    fn regions(&self) -> usize {
+        regs::regions()
+    }
+
+    fn supports(&self, region: &Region) -> bool {
+        let size = region.range.len();
+        size >= 32 && size.is_power_of_two() && region.range.start.is_multiple_of(size)
+    }
+
+    unsafe fn set_region(&mut self, index: usize, region: &Region) {
+        const XN: u32 = 1 << 28;
+        const AP_NONE: u32 = 0b000 << 24;
+        const AP_RO: u32 = 0b110 << 24;
+        // Normal memory, write through, as flash is.
+        const C: u32 = 1 << 17;
+        const ENABLE: u32 = 1;
+
+        let size = region.range.len().trailing_zeros() - 1;
+        let ap = match region.access {
+            Access::ReadOnly => AP_RO,
+            Access::NoAccess => AP_NONE,
+        };
+        regs::write(regs::RNR, index as u32);
+        regs::write(regs::RBAR, region.range.start as u32);
+        regs::write(regs::RASR, XN | ap | C | (size << 1) | ENABLE);
+    }
+}
+
+/// The ARMv8-M MPU (Cortex-M23, M33, and M55).  Regions are multiples of 32
+/// bytes, 32 byte aligned.  There is no way to deny privileged reads, so a
+/// `NoAccess` region is read only for privileged code, and closed to
+/// unprivileged code.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub struct Pmsav8;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Mpu for Pmsav8 {
+    fn regions(&self) -> usize {
+        regs::regions()
+    }
+
+    fn supports(&self, region: &Region) -> bool {
+        !region.range.is_empty() && region.range.start.is_multiple_of(32) && region.range.end.is_multiple_of(32)
+    }
+
+    unsafe fn set_region(&mut self, index: usize, region: &Region) {
+        const XN: u32 = 1;
+        const AP_RO_PRIV: u32 = 0b10 << 1;
+        const AP_RO: u32 = 0b11 << 1;
+        const ENABLE: u32 = 1;
+        // Attribute 0: normal memory, write through, read allocate.
+        const ATTR_FLASH: u32 = 0xaa;
+
+        let ap = match region.access {
+            Access::ReadOnly => AP_RO,
+            Access::NoAccess => AP_RO_PRIV,
+        };
+        regs::write(regs::MAIR0, ATTR_FLASH);
+        regs::write(regs::RNR, index as u32);
+        regs::write(regs::RBAR, region.range.start as u32 | ap | XN);
+        regs::write(regs::RASR, (region.range.end - 32) as u32 | ENABLE);
+    }
+}
@@ -0,0 +1,164 @@
+//! Upgrade policy
+//!
+//! Validation says whether an upgrade is genuine, not whether the device
+//! should take it.  Products differ in whether they accept the same version
+//! again, or an older one, so the comparison of the upgrade's version with
+//! the running image's is chosen by a `VersionPolicy`.  Versions are compared
+//! as major, minor and revision, with the build number only considered where
+//! the policy says so.
+//!
+//! This is separate from anti-rollback protection (see `check_rollback`),
+//! which uses the security counter, and can't be relaxed by configuration.
+//!
+//! What to do on each boot is decided by a `BootPolicy`, given what has been
+//! found in the slots: the swap type from the trailers, whether each image
+//! validated and had its dependencies met, and their versions.  The default,
+//! `DefaultPolicy`, follows the state table in the `status` module:
+//!
+//! | Swap type   | Decision                                                  |
+//! |-------------|-----------------------------------------------------------|
+//! | None        | Boot the primary image                                    |
+//! | Test, Perm  | Swap, if the upgrade is valid, its dependencies are met,  |
+//! |             | and the version policy accepts it.  Otherwise reject it,  |
+//! |             | and boot the primary image                                |
+//! | Revert      | Swap back, if the old image is still valid                |
+//!
+//! Wherever that would boot a primary image that isn't valid, it goes to
+//! recovery instead.  The policy also says what becomes of the secondary slot
+//! once an upgrade is confirmed (see `SecondaryCleanup`).  A product with
+//! other needs implements `BootPolicy` itself, rather than changing the code
+//! that carries out the actions.
+
+use crate::{error, Error, ErrorCode, ImageVersion, Result, SecondaryCleanup, SwapType};
+
+/// Which upgrade versions are accepted, given the running version.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum VersionPolicy {
+    /// Any version at all.
+    #[default]
+    Any,
+    /// Only a newer version.
+    Newer,
+    /// A newer version, or a rebuild of the same one: only the build number
+    /// differs.
+    AllowRebuild,
+    /// A newer version, or, while the flag is set, an older or equal one.
+    /// The flag comes from the device, such as a setting for a support
+    /// engineer, so a downgrade has to be deliberate.
+    AllowDowngrade(bool),
+    /// Decided by the function, given the running and upgrade versions.
+    Custom(fn(&ImageVersion, &ImageVersion) -> bool),
+}
+
+impl VersionPolicy {
+    /// Does the policy accept `upgrade` replacing `running`?
+    pub fn accepts(&self, running: &ImageVersion, upgrade: &ImageVersion) -> bool {
+        let triple = |v: &ImageVersion| (v.major, v.minor, v.revision);
+        let newer = triple(upgrade) > triple(running);
+        match *self {
+            VersionPolicy::Any => true,
+            VersionPolicy::Newer => newer,
+            VersionPolicy::AllowRebuild => {
+                newer || (triple(upgrade) == triple(running) && upgrade.build_num != running.build_num)
+            }
+            VersionPolicy::AllowDowngrade(allowed) => newer || allowed,
+            VersionPolicy::Custom(accepts) => accepts(running, upgrade),
+        }
+    }
+}
+
+/// Check that an upgrade is one the device accepts.  Without a running image,
+/// such as on a new device, any version is accepted.
+pub fn check_version(policy: &VersionPolicy, running: Option<&ImageVersion>, upgrade: &ImageVersion) -> Result<()> {
+    match running {
+        Some(running) if !policy.accepts(running, upgrade) => {
+            error!("Upgrade to {} from {} not allowed", upgrade, running);
+            Err(Error::InvalidImage)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// What is known about the image in one slot.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SlotView {
+    /// The image's version, if the slot has an image header.
+    pub version: Option<ImageVersion>,
+    /// The image validated.
+    pub valid: bool,
+    /// The image's dependencies on the rest of the set are met.
+    pub dependencies_met: bool,
+}
+
+/// What is known on a boot, for a `BootPolicy` to decide from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BootView {
+    pub swap_type: SwapType,
+    /// The image in the primary slot has been confirmed.
+    pub confirmed: bool,
+    pub primary: SlotView,
+    pub secondary: SlotView,
+}
+
+/// What the bootloader does next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BootAction {
+    /// Boot the image in the primary slot as it is.
+    BootPrimary,
+    /// Swap the upgrade in.  A permanent swap needs no confirmation.
+    Swap { permanent: bool },
+    /// Leave the upgrade where it is, recording why in its trailer, then boot
+    /// the primary image.
+    Reject(ErrorCode),
+    /// Swap the previous image back in.
+    Revert,
+    /// There is nothing that can be booted.  Wait for a new image.
+    Recovery,
+}
+
+/// Decides what to do on each boot.
+pub trait BootPolicy {
+    fn decide(&self, view: &BootView) -> BootAction;
+
+    /// What to do with the secondary slot once an upgrade is confirmed.
+    fn cleanup(&self) -> SecondaryCleanup {
+        SecondaryCleanup::Keep
+    }
+}
+
+/// The documented behaviour, with upgrade versions checked by `versions`, and
+/// the secondary slot cleaned up after an upgrade as `cleanup` says.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultPolicy {
+    pub versions: VersionPolicy,
+    pub cleanup: SecondaryCleanup,
+}
+
+impl BootPolicy for DefaultPolicy {
+    fn decide(&self, view: &BootView) -> BootAction {
+        let primary = if view.primary.valid { BootAction::BootPrimary } else { BootAction::Recovery };
+        match view.swap_type {
+            SwapType::None => primary,
+            SwapType::Test | SwapType::Perm => {
+                let upgrade = &view.secondary;
+                let accepted = match upgrade.version {
+                    Some(version) => check_version(&self.versions, view.primary.version.as_ref(), &version).is_ok(),
+                    None => false,
+                };
+                if upgrade.valid && upgrade.dependencies_met && accepted {
+                    BootAction::Swap { permanent: view.swap_type == SwapType::Perm }
+                } else if view.primary.valid {
+                    BootAction::Reject(ErrorCode::InvalidImage)
+                } else {
+                    BootAction::Recovery
+                }
+            }
+            SwapType::Revert if view.secondary.valid => BootAction::Revert,
+            SwapType::Revert => primary,
+        }
+    }
+
+    fn cleanup(&self) -> SecondaryCleanup {
+        self.cleanup
+    }
+}
@@ -0,0 +1,198 @@
+//! Serial recovery
+//!
+//! When there is no valid image, or the user requests it, the bootloader can
+//! run a simple protocol over a serial port that allows the slots to be erased
+//! and a new image uploaded.  After the upload, the device is reset, and the
+//! new image goes through normal validation.
+//!
+//! Each request is a frame:
+//!
+//! +------+-----+--------+---------+-----------+
+//! | 0x7e | cmd | len:16 | payload | crc16:16  |
+//! +------+-----+--------+---------+-----------+
+//!
+//! Multi-byte values are little endian.  The CRC is CRC16-CCITT (XMODEM) over
+//! the command, length and payload.  Each request is answered with a three
+//! byte response: 0x7e, the command, and a status code.
+//!
+//! The commands are:
+//!
+//! - Ping: no payload.
+//! - Erase: payload is the slot number.  Erases the entire slot.
+//! - Write: payload is the slot number, the offset (32 bits), then up to
+//!   `MAX_DATA` bytes of data.  The offset must be aligned to the slot's write
+//!   size.  Data that is not a multiple of the write size is padded with 0xff,
+//!   so only the last write of an image may be short.
+//! - Reset: no payload.  Ends recovery, so the caller can reset the device.
+
+use storage::Flash;
+
+/// A byte oriented serial port.
+pub trait Serial {
+    /// Wait for a byte.  Returns None on a receive error, such as a framing
+    /// error or overrun.
+    fn read(&mut self) -> Option<u8>;
+    /// Send bytes, waiting until they have been queued.
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Frames begin with this byte.
+pub const SYNC: u8 = 0x7e;
+
+/// Largest amount of data in a single write.
+pub const MAX_DATA: usize = 512;
+
+// Commands.
+pub const CMD_PING: u8 = 0x01;
+pub const CMD_ERASE: u8 = 0x02;
+pub const CMD_WRITE: u8 = 0x03;
+pub const CMD_RESET: u8 = 0x04;
+
+// Status codes.
+pub const STATUS_OK: u8 = 0;
+pub const STATUS_BAD_CRC: u8 = 1;
+pub const STATUS_BAD_COMMAND: u8 = 2;
+pub const STATUS_BAD_SLOT: u8 = 3;
+pub const STATUS_FLASH_ERROR: u8 = 4;
+pub const STATUS_BAD_REQUEST: u8 = 5;
+
+/// Header of a write payload: slot and offset.
+const WRITE_HEADER: usize = 5;
+
+/// Largest payload of any command.
+const MAX_PAYLOAD: usize = WRITE_HEADER + MAX_DATA;
+
+/// Run the recovery protocol on the given slots until a reset is requested.
+pub fn run<S: Serial, F: Flash>(serial: &mut S, slots: &mut [&mut F]) {
+    let mut payload = [0u8; MAX_PAYLOAD];
+    loop {
+        let (cmd, len) = match read_frame(serial, &mut payload) {
+            Ok(frame) => frame,
+            Err((cmd, status)) => {
+                serial.write(&[SYNC, cmd, status]);
+                continue;
+            }
+        };
+        let status = match cmd {
+            CMD_PING => STATUS_OK,
+            CMD_ERASE => erase(slots, &payload[..len]),
+            CMD_WRITE => write(slots, &payload[..len]),
+            CMD_RESET => {
+                serial.write(&[SYNC, cmd, STATUS_OK]);
+                return;
+            }
+            _ => STATUS_BAD_COMMAND,
+        };
+        serial.write(&[SYNC, cmd, status]);
+    }
+}
+
+/// Read a single frame into the payload buffer, returning the command and
+/// the payload length.  On error, returns the command and status to report.
+fn read_frame<S: Serial>(serial: &mut S, payload: &mut [u8]) -> Result<(u8, usize), (u8, u8)> {
+    // Wait for the start of a frame.
+    while serial.read() != Some(SYNC) {
+    }
+
+    let mut crc = Crc16::new();
+    let cmd = serial.read().ok_or((0, STATUS_BAD_CRC))?;
+    let lo = serial.read().ok_or((cmd, STATUS_BAD_CRC))?;
+    let hi = serial.read().ok_or((cmd, STATUS_BAD_CRC))?;
+    crc.update(&[cmd, lo, hi]);
+    let len = u16::from_le_bytes([lo, hi]) as usize;
+
+    // A payload that is too long is read and discarded, to stay in sync.
+    let mut ok = true;
+    for i in 0..len {
+        let byte = serial.read().ok_or((cmd, STATUS_BAD_CRC))?;
+        crc.update(&[byte]);
+        match payload.get_mut(i) {
+            Some(b) => *b = byte,
+            None => ok = false,
+        }
+    }
+
+    let lo = serial.read().ok_or((cmd, STATUS_BAD_CRC))?;
+    let hi = serial.read().ok_or((cmd, STATUS_BAD_CRC))?;
+    if u16::from_le_bytes([lo, hi]) != crc.finish() {
+        return Err((cmd, STATUS_BAD_CRC));
+    }
+    if !ok {
+        return Err((cmd, STATUS_BAD_REQUEST));
+    }
+    Ok((cmd, len))
+}
+
+fn erase<F: Flash>(slots: &mut [&mut F], payload: &[u8]) -> u8 {
+    if payload.len() != 1 {
+        return STATUS_BAD_REQUEST;
+    }
+    let slot = match slots.get_mut(payload[0] as usize) {
+        Some(slot) => slot,
+        None => return STATUS_BAD_SLOT,
+    };
+    let size = slot.capacity();
+    match slot.erase(0, size) {
+        Ok(()) => STATUS_OK,
+        Err(_) => STATUS_FLASH_ERROR,
+    }
+}
+
+fn write<F: Flash>(slots: &mut [&mut F], payload: &[u8]) -> u8 {
+    if payload.len() <= WRITE_HEADER {
+        return STATUS_BAD_REQUEST;
+    }
+    let slot = match slots.get_mut(payload[0] as usize) {
+        Some(slot) => slot,
+        None => return STATUS_BAD_SLOT,
+    };
+    let offset = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]) as usize;
+
+    // Pad out to the write size.  The buffer is always large enough, as long
+    // as the write size divides MAX_DATA.
+    let write_size = slot.write_size();
+    let len = payload.len() - WRITE_HEADER;
+    let padded = len.next_multiple_of(write_size);
+    if !offset.is_multiple_of(write_size) || padded > MAX_DATA {
+        return STATUS_BAD_REQUEST;
+    }
+    let mut data = [0xffu8; MAX_DATA];
+    data[..len].copy_from_slice(&payload[WRITE_HEADER..]);
+
+    match slot.write(offset, &data[..padded]) {
+        Ok(()) => STATUS_OK,
+        Err(_) => STATUS_FLASH_ERROR,
+    }
+}
+
+/// CRC16-CCITT, with a zero initial value (the XMODEM variant).
+pub struct Crc16(u16);
+
+impl Crc16 {
+    pub fn new() -> Crc16 {
+        Crc16(0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= (byte as u16) << 8;
+            for _ in 0..8 {
+                self.0 = if self.0 & 0x8000 != 0 {
+                    (self.0 << 1) ^ 0x1021
+                } else {
+                    self.0 << 1
+                };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Crc16::new()
+    }
+}
@@ -0,0 +1,336 @@
+//! Upgrades by flash remap
+//!
+//! Parts with address remap hardware, such as the FlexSPI remap on the
+//! i.MX RT, can show either of two flash regions at the address images run
+//! from.  On these, an upgrade needs no swap.  The new image is downloaded
+//! into the region not in use, and the bootloader validates it, then points
+//! the remap window at it.  Nothing is copied, so there is no swap to resume
+//! after a power failure, and reverting is just pointing the window back.
+//!
+//! The board drives the window, through `Remap`.  Which region is active is
+//! kept in a status area of its own (`RemapStatus`), as a log of records, one
+//! per write unit, newest last, as `FlashCounter` keeps its values.  Each
+//! record is the region, and whether it is on test.  Switching is a single
+//! record write, so a power failure leaves either the old region active or
+//! the new one.
+//!
+//! The area is split into two halves, of whole erase units, used in turn.
+//! When one is full, the other is erased and the new record written there,
+//! leaving the full half as it is until the next time round.  Each record
+//! also holds the generation of its half, one more than the half before, so
+//! with both halves written the newer is found.  A power failure during the
+//! erase, or before the write, leaves the full half, with the old record, as
+//! the newest.  An area found empty reads as region A.
+//!
+//! Each region has the usual trailer.  The application requests an upgrade by
+//! writing the magic into the inactive region (`request_upgrade`), and makes
+//! it permanent by also confirming it there.  The bootloader sets copy done in
+//! a region once it has switched to it, so a region is only switched to once
+//! per download.  The swap type is then:
+//!
+//! | Trailers                                              | Swap type  |
+//! |-------------------------------------------------------|------------|
+//! | Inactive: magic, copy done unset, no boot error       | Test, Perm |
+//! | Active: on test, and not confirmed                    | Revert     |
+//! | Otherwise                                             | None       |
+//!
+//! The decision is a `BootPolicy`'s, as for a swap, with the active region
+//! as the primary slot and the inactive one as the secondary.  A swap is
+//! carried out by switching to the inactive region, and a revert by switching
+//! back, which makes the old image permanent.
+
+use core::cell::RefCell;
+
+use storage::{Error as FlashError, Flash};
+
+use crate::policy::{BootAction, BootPolicy, BootView, SlotView};
+use crate::trailer::{
+    confirm, copy_done, image_ok, last_boot_error, record_boot_error, set_copy_done, BootError, Flag,
+    SwapType,
+};
+use crate::{error, info, upgrade_requested, Error, Image, Result};
+
+/// Marks a record in the status area.
+const REMAP_MARKER: u16 = 0x4d52;
+
+/// Largest write size supported for the status area.
+const MAX_RECORD: usize = 512;
+
+/// One of the two regions the remap window can show.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RemapRegion {
+    /// The region the window shows without remapping.
+    #[default]
+    A,
+    B,
+}
+
+impl RemapRegion {
+    /// The other region.
+    pub fn other(self) -> RemapRegion {
+        match self {
+            RemapRegion::A => RemapRegion::B,
+            RemapRegion::B => RemapRegion::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The remap hardware.
+pub trait Remap {
+    /// Show `region` at the address images run from.
+    fn remap(&mut self, region: RemapRegion);
+}
+
+/// A record of the status area: which region is active.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RemapState {
+    pub active: RemapRegion,
+    /// The active region was switched to for a test, and is reverted unless
+    /// its image is confirmed before the next boot.
+    pub test: bool,
+}
+
+/// The status area of a remap upgrade.
+pub struct RemapStatus<F> {
+    flash: F,
+}
+
+/// The newest record in the status area.
+struct Newest {
+    state: RemapState,
+    /// The half it is in, and that half's generation.
+    half: usize,
+    generation: u32,
+    /// The offset of the next free record, which may be past the half.
+    next: usize,
+}
+
+/// What is found in one half of the status area.
+enum Half {
+    Empty,
+    /// Something other than a run of records, such as an erase that didn't
+    /// finish.
+    Bad,
+    /// The newest record, the half's generation, and the offset of the next
+    /// free record.
+    Records(RemapState, u32, usize),
+}
+
+impl<F: Flash> RemapStatus<F> {
+    /// Use the given flash for the status.  An erased area has region A
+    /// active.
+    pub fn new(flash: F) -> RemapStatus<F> {
+        RemapStatus { flash }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn record_size(&self) -> usize {
+        self.flash.write_size().max(8)
+    }
+
+    /// The size of each half of the area.
+    fn half_size(&self) -> Result<usize> {
+        let erase_size = self.flash.erase_size();
+        let half = self.flash.capacity() / erase_size / 2 * erase_size;
+        if half < self.record_size() {
+            error!("Remap status area too small");
+            return Err(Error::CannotUpgrade);
+        }
+        Ok(half)
+    }
+
+    /// The current state.
+    pub fn state(&mut self) -> Result<RemapState> {
+        Ok(self.scan()?.state)
+    }
+
+    /// Record a new state, moving on to the other half if this one is full.
+    pub fn set_state(&mut self, state: &RemapState) -> Result<()> {
+        let half = self.half_size()?;
+        let newest = self.scan()?;
+        let size = self.record_size();
+        let (mut offset, mut generation) = (newest.next, newest.generation);
+        if offset + size > (newest.half + 1) * half {
+            // The full half is left alone until the record is in the other.
+            offset = (1 - newest.half) * half;
+            generation = generation.wrapping_add(1);
+            self.flash.erase(offset, offset + half)?;
+        }
+        let mut buf = [0xffu8; MAX_RECORD];
+        buf[..2].copy_from_slice(&REMAP_MARKER.to_le_bytes());
+        buf[2] = state.active as u8;
+        buf[3] = state.test as u8;
+        buf[4..8].copy_from_slice(&generation.to_le_bytes());
+        self.flash.write(offset, &buf[..size])?;
+        Ok(())
+    }
+
+    /// Find the newest record, in whichever half has it.
+    fn scan(&mut self) -> Result<Newest> {
+        let half = self.half_size()?;
+        let halves = [self.scan_half(0, half)?, self.scan_half(half, half)?];
+        let newest = |index, state, generation, next| Newest { state, half: index, generation, next };
+        match halves {
+            [Half::Empty, Half::Empty] => Ok(newest(0, RemapState::default(), 0, 0)),
+            [Half::Records(state, generation, next), Half::Empty | Half::Bad] => {
+                Ok(newest(0, state, generation, next))
+            }
+            [Half::Empty | Half::Bad, Half::Records(state, generation, next)] => {
+                Ok(newest(1, state, generation, next))
+            }
+            [Half::Records(a, gen_a, next_a), Half::Records(b, gen_b, next_b)] => {
+                if gen_b == gen_a.wrapping_add(1) {
+                    Ok(newest(1, b, gen_b, next_b))
+                } else if gen_a == gen_b.wrapping_add(1) {
+                    Ok(newest(0, a, gen_a, next_a))
+                } else {
+                    error!("Remap status halves out of sequence");
+                    Err(Error::CannotUpgrade)
+                }
+            }
+            _ => {
+                error!("Bad remap status");
+                Err(Error::CannotUpgrade)
+            }
+        }
+    }
+
+    /// Read the records in the half at `base`.
+    fn scan_half(&mut self, base: usize, half: usize) -> Result<Half> {
+        let size = self.record_size();
+        if size > MAX_RECORD {
+            return Err(Error::CannotUpgrade);
+        }
+        let mut buf = [0u8; MAX_RECORD];
+        let mut found = Half::Empty;
+        let mut offset = base;
+        while offset + size <= base + half {
+            match self.flash.read(offset, &mut buf[..size]) {
+                Ok(()) => (),
+                Err(FlashError::NotWritten) => break,
+                Err(e) => return Err(e.into()),
+            }
+            if buf[..size].iter().all(|&b| b == 0xff) {
+                break;
+            }
+            if u16::from_le_bytes([buf[0], buf[1]]) != REMAP_MARKER {
+                error!("Bad remap status record at 0x{:x}", offset);
+                return Ok(Half::Bad);
+            }
+            let generation = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+            let state = match (buf[2], buf[3], &found) {
+                (_, _, Half::Records(_, current, _)) if *current != generation => None,
+                (0, test @ (0 | 1), _) => Some(RemapState { active: RemapRegion::A, test: test == 1 }),
+                (1, test @ (0 | 1), _) => Some(RemapState { active: RemapRegion::B, test: test == 1 }),
+                _ => None,
+            };
+            let Some(state) = state else {
+                error!("Bad remap status record at 0x{:x}", offset);
+                return Ok(Half::Bad);
+            };
+            offset += size;
+            found = Half::Records(state, generation, offset);
+        }
+        Ok(found)
+    }
+}
+
+/// The swap type of a remap upgrade, from the status and the trailers of the
+/// two regions.
+pub fn remap_swap_type<F: Flash>(state: &RemapState, regions: [&RefCell<F>; 2]) -> Result<SwapType> {
+    let mut inactive = regions[state.active.other().index()].borrow_mut();
+    if upgrade_requested(&mut *inactive)? &&
+        copy_done(&mut *inactive)? == Flag::Unset &&
+        last_boot_error(&mut *inactive)?.is_none()
+    {
+        return Ok(if image_ok(&mut *inactive)? == Flag::Set { SwapType::Perm } else { SwapType::Test });
+    }
+    drop(inactive);
+
+    let mut active = regions[state.active.index()].borrow_mut();
+    if state.test && image_ok(&mut *active)? != Flag::Set {
+        return Ok(SwapType::Revert);
+    }
+    Ok(SwapType::None)
+}
+
+/// Run the remap upgrade on a boot.  `regions` are the two regions, A then B,
+/// `status` says which is active, and `policy` decides what to do, with each
+/// image checked by `validate`.  The window is pointed at the region to boot,
+/// which is returned.  If there is nothing to boot, the window is left alone,
+/// and this fails.
+pub fn remap_boot<F, S, M, V>(
+    regions: [&RefCell<F>; 2],
+    status: &mut RemapStatus<S>,
+    window: &mut M,
+    policy: &dyn BootPolicy,
+    mut validate: V,
+) -> Result<RemapRegion>
+    where F: Flash, S: Flash, M: Remap, V: FnMut(&Image<'_, F>) -> Result<()>,
+{
+    let state = status.state()?;
+    let swap_type = remap_swap_type(&state, regions)?;
+    let active = regions[state.active.index()];
+    let inactive = regions[state.active.other().index()];
+    let view = BootView {
+        swap_type,
+        confirmed: !state.test || image_ok(&mut *active.borrow_mut())? == Flag::Set,
+        primary: slot_view(active, &mut validate),
+        secondary: slot_view(inactive, &mut validate),
+    };
+
+    let region = match policy.decide(&view) {
+        BootAction::BootPrimary => state.active,
+        BootAction::Recovery => {
+            error!("No region to boot");
+            return Err(Error::InvalidImage);
+        }
+        BootAction::Reject(code) => {
+            // The upgrade is left where it is, with the reason recorded.
+            record_boot_error(&mut *inactive.borrow_mut(), &BootError { code, offset: 0 })?;
+            state.active
+        }
+        BootAction::Swap { permanent } => {
+            let next = state.active.other();
+            status.set_state(&RemapState { active: next, test: !permanent })?;
+            set_copy_done(&mut *inactive.borrow_mut())?;
+            info!("Switched to region {:?}", next);
+            next
+        }
+        BootAction::Revert => {
+            let next = state.active.other();
+            status.set_state(&RemapState { active: next, test: false })?;
+            let mut flash = inactive.borrow_mut();
+            if image_ok(&mut *flash)? != Flag::Set {
+                confirm(&mut *flash)?;
+            }
+            info!("Reverted to region {:?}", next);
+            next
+        }
+    };
+    window.remap(region);
+    Ok(region)
+}
+
+fn slot_view<F, V>(slot: &RefCell<F>, validate: &mut V) -> SlotView
+    where F: Flash, V: FnMut(&Image<'_, F>) -> Result<()>,
+{
+    let image = match Image::from_flash(slot) {
+        Ok(image) => image,
+        Err(_) => return SlotView::default(),
+    };
+    let version = image.version();
+    SlotView {
+        version: Some(version),
+        valid: validate(&image).is_ok(),
+        dependencies_met: image.check_dependencies(&[version]).is_ok(),
+    }
+}
@@ -0,0 +1,57 @@
+//! Boot requests
+//!
+//! The application can ask the bootloader to enter recovery on the next boot,
+//! rather than booting the image.  The request is a magic value left in a word
+//! that survives a reset, such as a retention register, or a word of RAM that
+//! neither the bootloader nor the application initialize.  The bootloader
+//! clears the request when it checks it, so a request only applies to a single
+//! boot.
+
+use crate::info;
+
+/// The value indicating a recovery request.
+pub const RECOVERY_REQUEST: u32 = 0x7265_6376;
+
+/// A word that is retained across a reset.
+pub trait Retained {
+    fn read(&self) -> u32;
+    fn write(&mut self, value: u32);
+}
+
+/// A retained word at a fixed address, such as a reserved word of RAM.
+pub struct RetainedWord(*mut u32);
+
+impl RetainedWord {
+    /// # Safety
+    ///
+    /// The address must be a valid, aligned word that nothing else uses.
+    pub const unsafe fn new(addr: usize) -> RetainedWord {
+        RetainedWord(addr as *mut u32)
+    }
+}
+
+impl Retained for RetainedWord {
+    fn read(&self) -> u32 {
+        unsafe { self.0.read_volatile() }
+    }
+
+    fn write(&mut self, value: u32) {
+        unsafe { self.0.write_volatile(value) }
+    }
+}
+
+/// Request recovery on the next boot.  The caller then resets the device.
+pub fn request_recovery<R: Retained>(word: &mut R) {
+    word.write(RECOVERY_REQUEST);
+}
+
+/// Check for a recovery request, clearing it.  After a power cycle, the word
+/// holds whatever the RAM came up with, which is unlikely to be the request.
+pub fn take_recovery_request<R: Retained>(word: &mut R) -> bool {
+    let requested = word.read() == RECOVERY_REQUEST;
+    word.write(0);
+    if requested {
+        info!("Recovery requested");
+    }
+    requested
+}
@@ -0,0 +1,106 @@
+//! Reverting by copying back
+//!
+//! A test image that is never confirmed is reverted by putting back the image
+//! it replaced.  Where that image is still held in another slot, such as the
+//! secondary after an upgrade, `copy_back` copies it into the primary, a group
+//! of sectors at a time.
+//!
+//! Each group is hashed before it is erased, and compared with the hash the
+//! swap recorded for it in the status (see `StatusLayout::write_hashes`).
+//! Groups that already hold what they should are left alone: those the two
+//! images have in common, and those copied before a power cut stopped an
+//! earlier attempt.  This saves the time, and the wear, of erasing and
+//! writing them after a failed test boot.  The group to copy is checked
+//! against the same hash, so that a copy that has changed since the swap is
+//! never written.  Minimal mode keeps no hashes, so it can't revert this way.
+
+use storage::Flash;
+
+use crate::{error, info, Error, Result, StatusLayout, StatusRead};
+
+/// What `copy_back` did, in groups of sectors.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CopyBack {
+    /// Groups erased and written.
+    pub copied: usize,
+    /// Groups left alone, as they already matched their hash.
+    pub skipped: usize,
+}
+
+/// Largest piece a group is copied in.
+const COPY_CHUNK: usize = 512;
+
+/// Copy `image` (0 for the main image, 1 for the upgrade) of the swap
+/// recorded in the status of `to`, laid out by `layout`, back from `from`,
+/// where it is held at the same offsets.  Only the groups that don't match
+/// their hash are erased and written, and the status is left as it is.
+pub fn copy_back<C, F, G>(crypto: &mut C, layout: &StatusLayout, image: usize, from: &mut F, to: &mut G)
+    -> Result<CopyBack>
+where
+    C: crate::CryptoBackend,
+    F: Flash,
+    G: Flash,
+{
+    let StatusRead::Valid(status) = layout.read(to)? else {
+        error!("No status to revert with");
+        return Err(Error::CannotUpgrade);
+    };
+    let size = if image == 0 { status.main_size } else { status.upgrade_size } as usize;
+    let groups = layout.image_groups(image);
+    let group_size = layout.group * layout.erase_size;
+    let status_start = to.capacity().checked_sub(layout.status_sectors() * layout.erase_size);
+    let Some(status_start) = status_start.filter(|_| size <= groups * group_size) else {
+        error!("Image of {} bytes doesn't fit the status layout", size);
+        return Err(Error::CannotUpgrade);
+    };
+
+    let mut done = CopyBack::default();
+    for group in 0..groups {
+        let Some(hash) = layout.read_hash(to, layout.record(image, group))? else {
+            error!("No hash recorded for group {}", group);
+            return Err(Error::CannotUpgrade);
+        };
+        match layout.record_hash(crypto, &status, image, group, to) {
+            Ok(current) if current == hash => {
+                done.skipped += 1;
+                continue;
+            }
+            // Erased, such as by an earlier attempt.
+            Ok(_) | Err(Error::Flash(storage::Error::NotWritten)) => (),
+            Err(e) => return Err(e),
+        }
+        if layout.record_hash(crypto, &status, image, group, from)? != hash {
+            error!("Group {} to copy back has changed since the swap", group);
+            return Err(Error::InvalidImage);
+        }
+
+        let (offset, len) = layout.group_span(&status, image, group);
+        if offset + group_size > status_start {
+            error!("Group {} shares a sector with the status", group);
+            return Err(Error::CannotUpgrade);
+        }
+        to.erase(offset, offset + group_size)?;
+        copy(from, to, offset, len)?;
+        done.copied += 1;
+    }
+    info!("Copied back {} groups, {} already matched", done.copied, done.skipped);
+    Ok(done)
+}
+
+/// Copy `len` bytes at `offset` from one device to the other.
+fn copy<F: Flash, G: Flash>(from: &mut F, to: &mut G, offset: usize, len: usize) -> Result<()> {
+    let (write, read) = (to.write_size(), from.read_size());
+    let unit = write.max(read);
+    if !COPY_CHUNK.is_multiple_of(unit) || !unit.is_multiple_of(write) || !unit.is_multiple_of(read) {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0u8; COPY_CHUNK];
+    let mut pos = offset;
+    while pos < offset + len {
+        let buf = &mut buf[..(offset + len - pos).min(COPY_CHUNK)];
+        from.read(pos, buf)?;
+        to.write(pos, buf)?;
+        pos += buf.len();
+    }
+    Ok(())
+}
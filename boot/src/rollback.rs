@@ -0,0 +1,169 @@
+//! Anti-rollback protection
+//!
+//! Each image can carry a security counter in its protected TLV.  The device
+//! keeps a monotonic counter, and refuses to boot images whose counter is below
+//! it.  Once an image is known good, the device counter is advanced to the
+//! image's, so older images with known problems can't be installed again.
+//!
+//! Where the counter is kept depends on the device.  Most have some one-time
+//! programmable (OTP) memory, which `OtpCounter` uses through the `Otp` hook.
+//! `FlashCounter` keeps it in ordinary flash, which is useful for simulation.
+
+use storage::{Error as FlashError, Flash, ReadFlash};
+
+use crate::{error, Error, Image, Result};
+
+/// A counter that can only move forward.
+pub trait RollbackCounter {
+    /// The current value.
+    fn read(&mut self) -> Result<u32>;
+
+    /// Advance the counter to `value`.  Values at or below the current value
+    /// leave the counter unchanged.
+    fn advance(&mut self, value: u32) -> Result<()>;
+}
+
+/// Check that an image is not older than the device allows.
+pub fn check_rollback<F, R>(image: &Image<'_, F>, counter: &mut R) -> Result<()>
+    where F: ReadFlash, R: RollbackCounter,
+{
+    let image_counter = image.security_counter()?;
+    let device_counter = counter.read()?;
+    if image_counter < device_counter {
+        error!("Security counter {} is below the device's {}", image_counter, device_counter);
+        return Err(Error::InvalidImage);
+    }
+    Ok(())
+}
+
+/// Advance the device counter to that of an image, once it has been
+/// confirmed.
+pub fn advance_rollback<F, R>(image: &Image<'_, F>, counter: &mut R) -> Result<()>
+    where F: ReadFlash, R: RollbackCounter,
+{
+    counter.advance(image.security_counter()?)
+}
+
+/// One-time programmable words, where bits can be set, but never cleared.  A
+/// board implements this over its OTP or fuse controller.
+pub trait Otp {
+    /// Number of words available to the counter.
+    fn words(&self) -> usize;
+
+    /// Read a word.
+    fn read_word(&mut self, index: usize) -> Result<u32>;
+
+    /// Set the given bits in a word.  Bits already set stay set.
+    fn program_word(&mut self, index: usize, bits: u32) -> Result<()>;
+}
+
+/// A counter kept in OTP, one bit per count.  This limits the counter to 32
+/// times the number of words.
+pub struct OtpCounter<O> {
+    otp: O,
+}
+
+impl<O: Otp> OtpCounter<O> {
+    pub fn new(otp: O) -> OtpCounter<O> {
+        OtpCounter { otp }
+    }
+
+    pub fn into_inner(self) -> O {
+        self.otp
+    }
+}
+
+impl<O: Otp> RollbackCounter for OtpCounter<O> {
+    fn read(&mut self) -> Result<u32> {
+        let mut count = 0;
+        for i in 0..self.otp.words() {
+            count += self.otp.read_word(i)?.count_ones();
+        }
+        Ok(count)
+    }
+
+    fn advance(&mut self, value: u32) -> Result<()> {
+        if value as usize > self.otp.words() * 32 {
+            return Err(Error::CannotUpgrade);
+        }
+        // Bits are set from the bottom of the first word up.
+        let current = self.read()?;
+        for count in current..value {
+            let index = (count / 32) as usize;
+            self.otp.program_word(index, 1 << (count % 32))?;
+        }
+        Ok(())
+    }
+}
+
+/// Largest write size supported by `FlashCounter`.
+const MAX_RECORD: usize = 512;
+
+/// A counter kept in flash, as a log of values, one per write unit.  The
+/// newest is the last written.  Unlike OTP, flash can be erased, so this only
+/// gives protection if the flash itself is protected.
+pub struct FlashCounter<F> {
+    flash: F,
+}
+
+impl<F: Flash> FlashCounter<F> {
+    /// Use the given flash for the counter.  It must be erased before first
+    /// use.
+    pub fn new(flash: F) -> FlashCounter<F> {
+        FlashCounter { flash }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn record_size(&self) -> usize {
+        self.flash.write_size().max(4)
+    }
+
+    /// Find the value of the last record, and the offset of the next free one.
+    fn scan(&mut self) -> Result<(u32, usize)> {
+        let size = self.record_size();
+        if size > MAX_RECORD {
+            return Err(Error::CannotUpgrade);
+        }
+        let mut buf = [0u8; MAX_RECORD];
+        let mut value = 0;
+        let mut offset = 0;
+        while offset + size <= self.flash.capacity() {
+            match self.flash.read(offset, &mut buf[..size]) {
+                Ok(()) => (),
+                Err(FlashError::NotWritten) => break,
+                Err(e) => return Err(e.into()),
+            }
+            if buf[..size].iter().all(|&b| b == 0xff) {
+                break;
+            }
+            value = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            offset += size;
+        }
+        Ok((value, offset))
+    }
+}
+
+impl<F: Flash> RollbackCounter for FlashCounter<F> {
+    fn read(&mut self) -> Result<u32> {
+        Ok(self.scan()?.0)
+    }
+
+    fn advance(&mut self, value: u32) -> Result<()> {
+        let (current, offset) = self.scan()?;
+        if value <= current {
+            return Ok(());
+        }
+        let size = self.record_size();
+        if offset + size > self.flash.capacity() {
+            return Err(Error::CannotUpgrade);
+        }
+        let mut buf = [0xffu8; MAX_RECORD];
+        buf[..4].copy_from_slice(&value.to_le_bytes());
+        self.flash.write(offset, &buf[..size])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//! Signature schemes
+//!
+//! Image validation checks the signature with whichever of a list of schemes
+//! claims its TLV kind.  `Image::validate_signed` only knows `EcdsaP256`,
+//! what imgtool signs with by default.  A product can pass
+//! its own list to `validate_signed_by`, leaving out schemes it doesn't use,
+//! or adding its own, without changing the image code.
+//!
+//! A scheme sees the key as given to the bootloader (or carried in the image,
+//! for `validate_key_hash_by`), the SHA256 of the image, and the contents of
+//! its signature TLV.
+
+use crate::crypto::{CryptoBackend, Hash256};
+use crate::{Error, Result};
+
+/// Largest signature TLV, and largest public key carried in an image, that
+/// can be checked.  Enough for RSA-3072.
+pub const MAX_SIGNATURE: usize = 512;
+
+/// A way images can be signed.
+pub trait SignatureScheme<C: CryptoBackend> {
+    /// The kind of the TLV holding this scheme's signatures.
+    fn tlv_kind(&self) -> u16;
+
+    /// Check `signature` is a signature of `hash`, made with `key`.
+    fn verify(&self, crypto: &mut C, key: &[u8], hash: &Hash256, signature: &[u8]) -> bool;
+}
+
+/// ECDSA over P-256, with the key as a DER SubjectPublicKeyInfo, as output by
+/// `imgtool getpub`.  The verification itself is done by the crypto backend.
+pub struct EcdsaP256;
+
+impl<C: CryptoBackend> SignatureScheme<C> for EcdsaP256 {
+    fn tlv_kind(&self) -> u16 {
+        TLV_ECDSA_SIG
+    }
+
+    fn verify(&self, crypto: &mut C, key: &[u8], hash: &Hash256, signature: &[u8]) -> bool {
+        let (point, (r, s)) = match (ecdsa_point(key), parse_ecdsa_sig(signature)) {
+            (Ok(point), Ok(rs)) => (point, rs),
+            _ => return false,
+        };
+        crypto.ecdsa_p256_verify(point, hash, &r, &s)
+    }
+}
+
+/// The TLV kind of an ECDSA P-256 signature.
+pub(crate) const TLV_ECDSA_SIG: u16 = 0x22;
+
+/// Length of the DER encoding of a P-256 public key.
+const P256_SPKI_LEN: usize = P256_SPKI_PREFIX.len() + 65;
+
+/// The DER encoding of a P-256 SubjectPublicKeyInfo, up to the point itself.
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03,
+    0x42, 0x00,
+];
+
+/// Extract the SEC1 point from a DER encoded P-256 public key.
+fn ecdsa_point(key: &[u8]) -> Result<&[u8; 65]> {
+    if key.len() != P256_SPKI_LEN || key[..P256_SPKI_PREFIX.len()] != P256_SPKI_PREFIX {
+        return Err(Error::InvalidImage);
+    }
+    key[P256_SPKI_PREFIX.len()..].try_into().map_err(|_| Error::InvalidImage)
+}
+
+/// Decode an ECDSA signature.  imgtool generates a DER `SEQUENCE { r INTEGER,
+/// s INTEGER }`, whose length depends on the values, and older versions pad it
+/// with zeros to 72 bytes.  A raw 64 byte `r || s` is also accepted.
+fn parse_ecdsa_sig(sig: &[u8]) -> Result<([u8; 32], [u8; 32])> {
+    match parse_der_sig(sig) {
+        Ok(rs) => Ok(rs),
+        Err(_) if sig.len() == 64 => {
+            let mut r = [0u8; 32];
+            let mut s = [0u8; 32];
+            r.copy_from_slice(&sig[..32]);
+            s.copy_from_slice(&sig[32..]);
+            Ok((r, s))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Decode a DER signature, allowing zero padding after it.
+fn parse_der_sig(der: &[u8]) -> Result<([u8; 32], [u8; 32])> {
+    let (body, padding) = match der {
+        [0x30, len, rest @ ..] if (*len as usize) <= rest.len() => rest.split_at(*len as usize),
+        _ => return Err(Error::InvalidImage),
+    };
+    if padding.iter().any(|&b| b != 0) {
+        return Err(Error::InvalidImage);
+    }
+    let (r, rest) = parse_der_uint(body)?;
+    let (s, rest) = parse_der_uint(rest)?;
+    if !rest.is_empty() {
+        return Err(Error::InvalidImage);
+    }
+    Ok((r, s))
+}
+
+/// Decode a single DER INTEGER, which must be positive and fit in 32 bytes.
+/// Returns the value, and the remaining data.
+fn parse_der_uint(der: &[u8]) -> Result<([u8; 32], &[u8])> {
+    let (value, rest) = match der {
+        [0x02, len, rest @ ..] if (*len as usize) <= rest.len() => rest.split_at(*len as usize),
+        _ => return Err(Error::InvalidImage),
+    };
+    // Positive values with the high bit set have a leading zero.
+    let value = match value {
+        [0, tail @ ..] => tail,
+        _ => value,
+    };
+    if value.is_empty() || value.len() > 32 {
+        return Err(Error::InvalidImage);
+    }
+    let mut result = [0u8; 32];
+    result[32 - value.len()..].copy_from_slice(value);
+    Ok((result, rest))
+}
+
@@ -0,0 +1,88 @@
+//! Scrubbing secrets before chaining
+//!
+//! The booted application shares RAM with the bootloader, and can read
+//! whatever the bootloader left there: the key of an encrypted image, the
+//! state of the last hash, and the locals of the crypto code further down the
+//! stack.  Before chaining, each of these is zeroed:
+//!
+//! - Buffers holding keys are cleared with `zeroize`, by whoever owns them.
+//! - `CryptoBackend::scrub` clears a backend's hash state, and anything it
+//!   keeps of the keys.
+//! - `chain_scrubbed` zeroes `SCRUB_STACK` bytes of stack below the caller,
+//!   where the crypto code ran, and then chains.
+//!
+//! Optionally, `chain_clearing` zeroes all of the bootloader's RAM, its own
+//! stack included, just before jumping to the image.  This needs a chainer
+//! that can do so without using the stack, a `ClearRam`.
+//!
+//! The writes are volatile, so they aren't optimized away as dead stores.
+
+use core::{
+    convert::Infallible,
+    ops::Range,
+    ptr,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+use crate::{event, info, Chainer, EventCode, Image, MappedFlash, Result};
+
+/// Bytes of stack below the caller that `chain_scrubbed` zeroes.  This must
+/// cover the deepest the validation went, which is the ECDSA verification.
+pub const SCRUB_STACK: usize = 8192;
+
+/// Zero `buf`, in a way the compiler can't leave out.
+pub fn zeroize(buf: &mut [u8]) {
+    for byte in buf {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Zero `SCRUB_STACK` bytes of stack below the caller's frame.
+#[inline(never)]
+pub fn scrub_stack() {
+    let mut stack = [0u8; SCRUB_STACK];
+    zeroize(&mut stack);
+}
+
+/// A chainer that can zero RAM, its own stack included, before starting an
+/// image.
+pub trait ClearRam: Chainer {
+    /// Zero `ram`, then start the image as `Chainer::chain` does.  Nothing
+    /// may be read from `ram` once it is being cleared.
+    ///
+    /// # Safety
+    ///
+    /// As `Chainer::chain`.  `ram` must be word aligned, and hold nothing
+    /// the image needs, such as a `RetainedWord`.
+    unsafe fn chain_clearing(&self, base: usize, ram: Range<usize>) -> !;
+}
+
+/// Chain to a validated image, as `chain` does, after zeroing the stack the
+/// bootloader used.  Only returns if the image's code isn't suitably
+/// aligned.
+///
+/// # Safety
+///
+/// See `Chainer::chain`.
+pub unsafe fn chain_scrubbed<C: Chainer, F: MappedFlash>(chainer: &C, image: &Image<'_, F>) -> Result<Infallible> {
+    let base = image.get_image_base()?;
+    info!("Booting image at 0x{:x}, after scrubbing the stack", base);
+    event(EventCode::Chain, 0);
+    scrub_stack();
+    chainer.chain(base)
+}
+
+/// Chain to a validated image, as `chain` does, zeroing all of `ram` first.
+/// Only returns if the image's code isn't suitably aligned.
+///
+/// # Safety
+///
+/// See `ClearRam::chain_clearing`.
+pub unsafe fn chain_clearing<C: ClearRam, F: MappedFlash>(chainer: &C, image: &Image<'_, F>,
+                                                          ram: Range<usize>) -> Result<Infallible> {
+    let base = image.get_image_base()?;
+    info!("Booting image at 0x{:x}, after clearing 0x{:x}..0x{:x}", base, ram.start, ram.end);
+    event(EventCode::Chain, 0);
+    chainer.chain_clearing(base, ram)
+}
@@ -0,0 +1,89 @@
+//! Self-tested confirmation
+//!
+//! After a test upgrade, the new image should only confirm itself once it has
+//! shown it works.  `confirm_after` runs the application's self-test, and
+//! only confirms the image if it passes.  Until then, the image is on test.
+//!
+//! A failed self-test, or a crash before one finishes, ends in a reset, and
+//! the next boot would revert.  Some failures are transient, so the
+//! bootloader can allow a few attempts first.  It counts boots of an image on
+//! test in a retained word (see `Retained`), with `record_test_boot`, and
+//! `TestAttempts` wraps its `BootPolicy` to boot the image again, rather than
+//! revert, until the attempts are used up.  The word also tells the
+//! application that this is a test boot, and which attempt, through
+//! `test_boot_attempts`.
+//!
+//! The word is kept as a marker in the upper bits, and the attempt in the low
+//! byte.  After a power cycle, it holds whatever RAM came up with, which is
+//! unlikely to look like the marker, so the count starts again.
+
+use storage::Flash;
+
+use crate::{confirm, info, is_confirmed, BootAction, BootPolicy, BootView, Result, Retained, SecondaryCleanup};
+
+/// The marker of a test boot, in the upper 24 bits of the word.
+pub const TEST_BOOT: u32 = 0x7465_7300;
+
+/// Run `self_test`, and confirm the image in the primary slot if it passes.
+/// Returns whether the image is confirmed, which it stays if it already was,
+/// without running the test.
+pub fn confirm_after<F: Flash>(primary: &mut F, self_test: impl FnOnce() -> bool) -> Result<bool> {
+    if is_confirmed(primary)? {
+        return Ok(true);
+    }
+    if !self_test() {
+        info!("Self-test failed, not confirming");
+        return Ok(false);
+    }
+    confirm(primary)?;
+    Ok(true)
+}
+
+/// The bootloader's half: count this boot in `word` if the primary image is on
+/// test, that is, the swap type is `Revert`, or clear it if it isn't.  Returns
+/// which attempt this is, from 1, or 0 if the image isn't on test.
+pub fn record_test_boot<R: Retained>(word: &mut R, on_test: bool) -> u32 {
+    if !on_test {
+        word.write(0);
+        return 0;
+    }
+    let attempts = test_boot_attempts(word).unwrap_or(0).saturating_add(1).min(0xff);
+    word.write(TEST_BOOT | attempts);
+    info!("Test boot, attempt {}", attempts);
+    attempts
+}
+
+/// The application's half: which attempt at booting the image on test this
+/// is, or None if this isn't a test boot.
+pub fn test_boot_attempts<R: Retained>(word: &R) -> Option<u32> {
+    let value = word.read();
+    if value & !0xff == TEST_BOOT {
+        Some(value & 0xff)
+    } else {
+        None
+    }
+}
+
+/// A policy that boots an image on test again, instead of reverting it, for
+/// up to `max` attempts.  `attempts` is what `record_test_boot` gave for this
+/// boot.
+pub struct TestAttempts<P> {
+    pub policy: P,
+    pub attempts: u32,
+    pub max: u32,
+}
+
+impl<P: BootPolicy> BootPolicy for TestAttempts<P> {
+    fn decide(&self, view: &BootView) -> BootAction {
+        match self.policy.decide(view) {
+            BootAction::Revert if view.primary.valid && (1..=self.max).contains(&self.attempts) => {
+                BootAction::BootPrimary
+            }
+            action => action,
+        }
+    }
+
+    fn cleanup(&self) -> SecondaryCleanup {
+        self.policy.cleanup()
+    }
+}
@@ -0,0 +1,153 @@
+//! Data shared with the application
+//!
+//! Before chaining, the bootloader can leave a small block of information for
+//! the application in a region of RAM that neither initializes, such as which
+//! image was booted.  The two are built separately, so the block carries its
+//! own description, and the reader rejects anything it doesn't understand
+//! rather than misreading it.
+//!
+//! +--------+---------+--------+-------+----------+---------+
+//! | magic  | version | length | crc   | reserved | payload |
+//! | u32    | u16     | u16    | u16   | u16      |         |
+//! +--------+---------+--------+-------+----------+---------+
+//!
+//! The length is that of the payload, and the CRC (CRC16-CCITT, as used by
+//! recovery) covers the payload.  All values are little endian, as the
+//! structures are just copied out of memory.
+
+use core::mem::size_of;
+
+use asraw::{AsMutRaw, AsRaw};
+
+use crate::recovery::Crc16;
+use crate::ImageVersion;
+
+/// Magic value at the start of the block.
+pub const SHARED_MAGIC: u32 = 0x6273_6864;
+
+/// Version of the layout described here.  Any change to `BootInfo` must
+/// change this.
+pub const SHARED_VERSION: u16 = 3;
+
+/// Bytes needed for the whole block.
+pub const SHARED_SIZE: usize = block_size::<BootInfo>();
+
+/// Reasons the shared block can't be used.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SharedError {
+    /// The buffer is too small for the block.
+    TooSmall,
+    /// There is no block, such as after a power cycle.
+    Missing,
+    /// The block was written by a bootloader using a different layout.
+    Version(u16),
+    /// The length doesn't match the layout.
+    Length,
+    /// The payload has been corrupted.
+    Crc,
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+struct SharedHeader {
+    magic: u32,
+    version: u16,
+    length: u16,
+    crc: u16,
+    reserved: u16,
+}
+
+impl AsRaw for SharedHeader {}
+unsafe impl AsMutRaw for SharedHeader {}
+
+/// The information passed to the application.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct BootInfo {
+    /// Version of the image that was booted.
+    pub version: ImageVersion,
+    /// Address of the start of the image, including its header.
+    pub image_base: u32,
+    /// Size of the image, including the header and TLV.
+    pub image_size: u32,
+    /// Which slot the image was booted from.
+    pub slot: u8,
+    pub reserved: u8,
+    /// The code of the error the bootloader rejected an upgrade for on this
+    /// boot, see `ErrorCode`, or 0 if none was.
+    pub last_error: u16,
+    /// The most stack the bootloader used, in bytes, or 0 if it wasn't
+    /// measured.  See `stack_used`.
+    pub stack_used: u32,
+}
+
+impl AsRaw for BootInfo {}
+unsafe impl AsMutRaw for BootInfo {}
+
+/// The bootloader's half: fill in the block at the start of `buf`.
+pub fn write_shared(buf: &mut [u8], info: &BootInfo) -> Result<(), SharedError> {
+    write_block(buf, SHARED_MAGIC, SHARED_VERSION, info)
+}
+
+/// The application's half: read the block at the start of `buf`.
+pub fn read_shared(buf: &[u8]) -> Result<BootInfo, SharedError> {
+    read_block(buf, SHARED_MAGIC, SHARED_VERSION)
+}
+
+/// Bytes needed for a block carrying `T`.
+pub(crate) const fn block_size<T>() -> usize {
+    size_of::<SharedHeader>() + size_of::<T>()
+}
+
+/// Write a block of this framing, with the given magic and version, at the
+/// start of `buf`.
+pub(crate) fn write_block<T: AsRaw>(buf: &mut [u8], magic: u32, version: u16, payload: &T) -> Result<(), SharedError> {
+    if buf.len() < block_size::<T>() {
+        return Err(SharedError::TooSmall);
+    }
+    let payload = payload.as_raw();
+    let mut crc = Crc16::new();
+    crc.update(payload);
+    let header = SharedHeader {
+        magic,
+        version,
+        length: payload.len() as u16,
+        crc: crc.finish(),
+        reserved: 0,
+    };
+    let (head, rest) = buf.split_at_mut(size_of::<SharedHeader>());
+    head.copy_from_slice(header.as_raw());
+    rest[..payload.len()].copy_from_slice(payload);
+    Ok(())
+}
+
+/// Read a block written by `write_block`.
+pub(crate) fn read_block<T: AsMutRaw + Default>(buf: &[u8], magic: u32, version: u16) -> Result<T, SharedError> {
+    if buf.len() < block_size::<T>() {
+        return Err(SharedError::TooSmall);
+    }
+    let (head, rest) = buf.split_at(size_of::<SharedHeader>());
+    let header = SharedHeader::try_from_raw(head).map_err(|_| SharedError::Missing)?;
+    if header.magic != magic {
+        return Err(SharedError::Missing);
+    }
+    if header.version != version {
+        return Err(SharedError::Version(header.version));
+    }
+    if header.length as usize != size_of::<T>() {
+        return Err(SharedError::Length);
+    }
+    let payload = &rest[..size_of::<T>()];
+    let mut crc = Crc16::new();
+    crc.update(payload);
+    if crc.finish() != header.crc {
+        return Err(SharedError::Crc);
+    }
+    T::try_from_raw(payload).map_err(|_| SharedError::Length)
+}
+
+/// Clear the block, so a stale copy isn't seen after the next boot.
+pub fn clear_shared(buf: &mut [u8]) {
+    let len = buf.len().min(size_of::<SharedHeader>());
+    buf[..len].fill(0);
+}
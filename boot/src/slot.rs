@@ -0,0 +1,116 @@
+//! Slots
+//!
+//! The boot code addresses each slot as a flash device of its own: the image
+//! header is at offset 0, and the trailer at the end of its capacity.  On most
+//! parts, though, both slots, and any scratch area, are regions of the same
+//! device.  A `Slot` is one such region: the device it is on, where it starts,
+//! its size, and what it is for.  It is itself a `Flash`, addressed from its
+//! own start, so `Image`, the trailer and the status code all work on it as
+//! they are, and can't reach outside it.
+//!
+//! The device is shared through a `FlashCell`, a `RefCell` on most targets,
+//! and only held for each operation, so the slots on it can be used together,
+//! as `swap_type` uses the primary and secondary.
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use storage::{Flash, ReadFlash};
+
+use crate::{error, Error, FlashCell, MappedFlash, Result};
+
+/// What a slot is for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SlotPurpose {
+    /// The image that runs.
+    Primary,
+    /// Where an upgrade is downloaded to.
+    Secondary,
+    /// Scratch space for a swap.
+    Scratch,
+}
+
+/// A region of a flash device, holding one slot.  The device is shared
+/// through a RefCell unless another `FlashCell` is given.
+pub struct Slot<'d, F, S: ?Sized = RefCell<F>> {
+    dev: &'d S,
+    _flash: PhantomData<F>,
+    base: usize,
+    size: usize,
+    purpose: SlotPurpose,
+}
+
+impl<'d, F: Flash, S: FlashCell<Flash = F> + ?Sized> Slot<'d, F, S> {
+    /// The slot at `base`, of `size` bytes.  It must be within the device,
+    /// and aligned to its erase size.
+    pub fn new(dev: &'d S, base: usize, size: usize, purpose: SlotPurpose) -> Result<Slot<'d, F, S>> {
+        let (erase_size, capacity) = dev.with(|flash| (flash.erase_size(), flash.capacity()));
+        let end = base.checked_add(size).ok_or(Error::CannotUpgrade)?;
+        if end > capacity || !base.is_multiple_of(erase_size) || !size.is_multiple_of(erase_size) {
+            error!("{:?} slot at 0x{:x}, 0x{:x} bytes, doesn't fit the device", purpose, base, size);
+            return Err(Error::CannotUpgrade);
+        }
+        Ok(Slot { dev, _flash: PhantomData, base, size, purpose })
+    }
+}
+
+impl<'d, F, S: ?Sized> Slot<'d, F, S> {
+    /// Where the slot starts on its device.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn purpose(&self) -> SlotPurpose {
+        self.purpose
+    }
+
+    /// The device the slot is on.
+    pub fn device(&self) -> &'d S {
+        self.dev
+    }
+}
+
+impl<'d, F: ReadFlash, S: FlashCell<Flash = F> + ?Sized> ReadFlash for Slot<'d, F, S> {
+    fn read_size(&self) -> usize {
+        self.dev.with(|flash| flash.read_size())
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        storage::check_read(self, offset, bytes.len())?;
+        self.dev.with(|flash| flash.read(self.base + offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        self.size
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        self.dev.with(|flash| flash.memory_address()).map(|addr| addr + self.base)
+    }
+}
+
+impl<'d, F: Flash, S: FlashCell<Flash = F> + ?Sized> Flash for Slot<'d, F, S> {
+    fn write_size(&self) -> usize {
+        self.dev.with(|flash| flash.write_size())
+    }
+
+    fn erase_size(&self) -> usize {
+        self.dev.with(|flash| flash.erase_size())
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> storage::Result<()> {
+        storage::check_erase(self, from, to)?;
+        self.dev.with(|flash| flash.erase(self.base + from, self.base + to))
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> storage::Result<()> {
+        storage::check_write(self, offset, bytes.len())?;
+        self.dev.with(|flash| flash.write(self.base + offset, bytes))
+    }
+}
+
+impl<'d, F: MappedFlash, S: FlashCell<Flash = F> + ?Sized> MappedFlash for Slot<'d, F, S> {
+    fn get_base(&self) -> usize {
+        self.dev.with(|flash| flash.get_base()) + self.base
+    }
+}
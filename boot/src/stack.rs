@@ -0,0 +1,87 @@
+//! Stack usage
+//!
+//! The hash buffers, signature buffers, and heapless vectors all live on the
+//! stack, which makes it the scarcest resource the bootloader has.  To see
+//! how much is really used, the stack is painted with `STACK_PAINT` at
+//! startup, and before chaining, the high water mark is found by looking for
+//! the lowest word that is no longer paint:
+//!
+//! ```text
+//! unsafe { boot::paint_stack(stack_bottom) };
+//! // ... validate, swap ...
+//! let used = unsafe { boot::stack_used(stack_bottom, stack_top) };
+//! ```
+//!
+//! The result can be logged, and passed to the application in `BootInfo`.
+//!
+//! With `std`, `measure_stack` does the same for a closure on the host, so
+//! tests can check the worst case of validation and swaps on the simulator.
+//! Host builds use far more stack than optimized target builds, so this shows
+//! trends and relative costs, not the target's figures.
+
+use core::ptr;
+
+/// The word the unused stack is painted with.
+pub const STACK_PAINT: u32 = 0x5ac5_5ac5;
+
+/// Stack left unpainted below the caller of `paint_stack`, for its own frame.
+const PAINT_MARGIN: usize = 256;
+
+/// Paint the stack from `bottom`, its lowest address, up to a little below
+/// the caller's frame.
+///
+/// # Safety
+///
+/// `bottom` must be the word aligned bottom of the current stack, with
+/// nothing below the caller's frame in use.
+#[inline(never)]
+pub unsafe fn paint_stack(bottom: usize) {
+    let here = 0u32;
+    let top = (ptr::addr_of!(here) as usize).saturating_sub(PAINT_MARGIN) & !3;
+    let mut pos = bottom;
+    while pos < top {
+        ptr::write_volatile(pos as *mut u32, STACK_PAINT);
+        pos += 4;
+    }
+}
+
+/// The most stack that has been used since `paint_stack`, in bytes: from
+/// `top` down to the lowest word that isn't paint.
+///
+/// # Safety
+///
+/// `bottom..top` must be the stack that was painted.
+pub unsafe fn stack_used(bottom: usize, top: usize) -> usize {
+    let mut pos = bottom;
+    while pos < top && ptr::read_volatile(pos as *const u32) == STACK_PAINT {
+        pos += 4;
+    }
+    top - pos
+}
+
+/// Depth, in words, that `measure_stack` paints below its caller.
+#[cfg(feature = "std")]
+const MEASURE_WORDS: usize = 64 * 1024;
+
+/// Run `action`, returning what it returned and about how many bytes of
+/// stack it used, at most `4 * MEASURE_WORDS`.  Run this on a thread with
+/// room for the painted area, such as a test's.
+#[cfg(feature = "std")]
+pub fn measure_stack<T, F: FnOnce() -> T>(action: F) -> (T, usize) {
+    let bottom = paint_below();
+    let top = bottom + 4 * MEASURE_WORDS;
+    let result = action();
+    (result, unsafe { stack_used(bottom, top) })
+}
+
+/// Paint an area of stack the size of `MEASURE_WORDS`, in a frame the
+/// caller's next call reuses.  Returns the lowest address of the area.
+#[cfg(feature = "std")]
+#[inline(never)]
+fn paint_below() -> usize {
+    let mut area = [0u32; MEASURE_WORDS];
+    for word in area.iter_mut() {
+        unsafe { ptr::write_volatile(word, STACK_PAINT) };
+    }
+    core::hint::black_box(&mut area).as_ptr() as usize
+}
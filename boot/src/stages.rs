@@ -0,0 +1,176 @@
+//! Multi-stage boot
+//!
+//! Some configurations boot through more than one stage: the bootloader starts
+//! a second-stage loader, or a secure runtime such as TF-M, which in turn
+//! starts the application.  Each stage is an image in its own slot, and
+//! `validate_stages` validates all of them, and their dependencies on each
+//! other, before the first is started.  A bad application is then found while
+//! the bootloader can still do something about it, rather than by a stage
+//! that may have no way to recover.
+//!
+//! The stages learn about each other from a handoff block in RAM, framed as
+//! the shared block is (see `write_shared`), but with its own magic, and a
+//! payload of `Stages`: the count, then an entry for each stage, in the order
+//! they boot.
+//!
+//! The address of the block is passed to each stage in a register, the one a
+//! function's first argument goes in (`r0` on Cortex-M), by a `Handoff`
+//! chainer.  So a stage written in C can take it as the argument of its reset
+//! handler.  A stage using this crate starts the one after it with
+//! `chain_next`, passing the same block on.
+
+use core::convert::Infallible;
+
+use storage::ReadFlash;
+
+use crate::shared::{block_size, read_block, write_block};
+use crate::{error, event, info, Chainer, Error, EventCode, Image, ImageVersion, MappedFlash, Result, SharedError};
+use asraw::{AsMutRaw, AsRaw};
+
+/// The most stages a handoff describes.
+pub const MAX_STAGES: usize = 4;
+
+/// Magic value at the start of the handoff block.
+pub const HANDOFF_MAGIC: u32 = 0x6273_6866;
+
+/// Version of the layout of `Stages`.  Any change to it must change this.
+pub const HANDOFF_VERSION: u16 = 1;
+
+/// Bytes needed for the whole handoff block.
+pub const HANDOFF_SIZE: usize = block_size::<Stages>();
+
+/// One stage of the boot.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct StageInfo {
+    /// Version of the stage's image.
+    pub version: ImageVersion,
+    /// Address of the stage's code, which follows the image header.  This is
+    /// what is chained to.
+    pub code_base: u32,
+    /// Size of the image, including the header and TLV.
+    pub image_size: u32,
+}
+
+/// The stages of the boot, the payload of the handoff block.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct Stages {
+    /// Number of entries in use.
+    pub count: u32,
+    pub stages: [StageInfo; MAX_STAGES],
+}
+
+impl AsRaw for StageInfo {}
+unsafe impl AsMutRaw for StageInfo {}
+impl AsRaw for Stages {}
+unsafe impl AsMutRaw for Stages {}
+
+impl Stages {
+    /// The stages in use, in the order they boot.
+    pub fn stages(&self) -> &[StageInfo] {
+        &self.stages[..(self.count as usize).min(MAX_STAGES)]
+    }
+
+    /// The stage after the one whose code is at `code_base`, for that stage
+    /// to start.  None for the last stage, or one that isn't in the list.
+    pub fn next(&self, code_base: u32) -> Option<&StageInfo> {
+        let stages = self.stages();
+        let current = stages.iter().position(|stage| stage.code_base == code_base)?;
+        stages.get(current + 1)
+    }
+}
+
+/// Validate the image of every stage with `validate`, and check each one's
+/// dependencies against the others, indexed by stage.  Only if all of them
+/// pass are the stages returned, for the handoff.
+pub fn validate_stages<F, V>(images: &[Image<'_, F>], mut validate: V) -> Result<Stages>
+    where F: ReadFlash + MappedFlash, V: FnMut(&Image<'_, F>) -> Result<()>,
+{
+    if images.is_empty() || images.len() > MAX_STAGES {
+        error!("Unsupported number of stages: {}", images.len());
+        return Err(Error::InvalidImage);
+    }
+
+    let mut versions = [ImageVersion::default(); MAX_STAGES];
+    for (n, image) in images.iter().enumerate() {
+        if let Err(err) = validate(image) {
+            error!("Stage {} is invalid", n);
+            return Err(err);
+        }
+        versions[n] = image.version();
+    }
+
+    let mut stages = Stages { count: images.len() as u32, ..Stages::default() };
+    for (n, image) in images.iter().enumerate() {
+        image.check_dependencies(&versions[..images.len()])?;
+        stages.stages[n] = StageInfo {
+            version: versions[n],
+            code_base: image.get_image_base()? as u32,
+            image_size: image.full_image_size() as u32,
+        };
+    }
+    info!("Validated {} stages", images.len());
+    Ok(stages)
+}
+
+/// Write the handoff block at the start of `buf`.
+pub fn write_handoff(buf: &mut [u8], stages: &Stages) -> core::result::Result<(), SharedError> {
+    write_block(buf, HANDOFF_MAGIC, HANDOFF_VERSION, stages)
+}
+
+/// Read the handoff block at the start of `buf`.
+pub fn read_handoff(buf: &[u8]) -> core::result::Result<Stages, SharedError> {
+    read_block(buf, HANDOFF_MAGIC, HANDOFF_VERSION)
+}
+
+/// A chainer that passes a value to the image it starts.
+pub trait Handoff: Chainer {
+    /// Start the image whose code begins at `base`, as `Chainer::chain` does,
+    /// with `arg` in the register of a function's first argument.
+    ///
+    /// # Safety
+    ///
+    /// As `Chainer::chain`.
+    unsafe fn chain_with(&self, base: usize, arg: usize) -> !;
+}
+
+/// Start the first of the stages, passing it `handoff`, which must hold the
+/// block written for them by `write_handoff`.  Only returns if it doesn't.
+///
+/// # Safety
+///
+/// As `Chainer::chain`.  Every stage must have been validated, and `handoff`
+/// must be in RAM that none of the stages initializes.
+pub unsafe fn chain_stages<C: Handoff>(chainer: &C, handoff: &[u8]) -> Result<Infallible> {
+    let first = match read_handoff(handoff) {
+        Ok(stages) => stages.stages().first().copied(),
+        Err(_) => None,
+    };
+    let Some(first) = first else {
+        error!("No stages to boot");
+        return Err(Error::InvalidImage);
+    };
+    info!("Booting first stage at 0x{:x}", first.code_base);
+    event(EventCode::Chain, 0);
+    chainer.chain_with(first.code_base as usize, handoff.as_ptr() as usize)
+}
+
+/// From the stage whose code is at `code_base`, start the next one, passing
+/// `handoff` on.  Only returns if there is no next stage.
+///
+/// # Safety
+///
+/// As `chain_stages`.
+pub unsafe fn chain_next<C: Handoff>(chainer: &C, handoff: &[u8], code_base: u32) -> Result<Infallible> {
+    let next = match read_handoff(handoff) {
+        Ok(stages) => stages.next(code_base).copied(),
+        Err(_) => None,
+    };
+    let Some(next) = next else {
+        error!("No stage after 0x{:x}", code_base);
+        return Err(Error::InvalidImage);
+    };
+    info!("Booting next stage at 0x{:x}", next.code_base);
+    chainer.chain_with(next.code_base as usize, handoff.as_ptr() as usize)
+}
@@ -45,6 +45,12 @@
 //! The characteristics of the flash device itself indicate whether we are in
 //! "paged" status mode, or in "overwrite" status mode.
 //!
+//! Either way, the end of the slot is left to the trailer the application
+//! uses to confirm images (see `trailer`), so neither disturbs the other.  In
+//! overwrite mode the status sits just below it in the same sector.  Paged
+//! mode erases its pages, so they start in the sector below it; the sector
+//! numbers below count from there.
+//!
 //! Paged mode views the flash as follows (high address at the top, each section
 //! is one sector).
 //! +-----+--------------------------+
@@ -70,11 +76,38 @@
 //! |     | etc
 //! +-----+--------------------------+
 //!
+//! The status pages are rewritten at every step of a swap, so on parts with
+//! small sectors they wear far faster than the rest of the slot.  Rather than
+//! just the two pages shown, they can rotate through a pool of more sectors
+//! (`SlotInfo::with_status_pages`), with the hashes below the pool.  Each
+//! write goes to the page after the newest, which is the one with the highest
+//! age.
+//!
+//! Each swap is given a generation, one past any seen before, which every
+//! status written during the swap carries (see `next_generation`).  A status
+//! page replayed from an earlier swap, by an attacker or a confused copy,
+//! then shows up as older than the other pages, or the other slot, or the
+//! device's counter, if it keeps one in OTP (`check_generation`), rather
+//! than rolling the swap back.
+//!
+//! The hashes record the swap's progress, one per sector by default.  With
+//! `SlotInfo::with_progress_group`, each covers a group of sectors instead,
+//! so there are fewer of them to write, and a resume redoes the whole group
+//! it was in.
+//!
+//! Minimal mode is for devices where even the hashes don't fit, such as
+//! those upgrading a single large sector.  It is laid out as overwrite mode,
+//! but below the flags is a counter: a write unit for each group of sectors,
+//! written as the group is swapped.  Without hashes a resume can't tell how
+//! far into a group the swap got, so it redoes the group the counter stopped
+//! at.
+//!
 //! Overwrite mode is instead, as follows.  It makes the assumption that the
 //! write size is smaller, and blocks for the flags can be left unwritten.
 //! There is a single sector at the end of flash containing the information.
 //! +-----+--------------------------------+
-//! | n-1 | magic
+//! | n-1 | trailer (image ok, magic, ...)
+//! |     | magic
 //! |     | overwrite marker + alignment
 //! |     | status hash (skips the flags as those can change.)
 //! |     | hash-seed
@@ -106,9 +139,49 @@
 
 use core::mem::size_of;
 
-use crate::Result;
+use crate::trailer::{image_ok, trailer_size, Flag, FLAG_SET};
+use crate::{debug, error, event, CryptoBackend, EntropySource, Error, EventCode, Result, RollbackCounter};
 use asraw::{AsRaw, AsMutRaw};
-use storage::Flash;
+use storage::{Flash, ReadFlash};
+
+/// The magic number that ends the status data of a slot.
+pub const MAGIC: [u8; 16] = [
+    0x77, 0xc2, 0x95, 0xf3, 0x60, 0xd2, 0xef, 0x7f,
+    0x35, 0x52, 0x50, 0x0f, 0x2c, 0xb6, 0x79, 0x80,
+];
+
+/// Largest read or write size supported when accessing the magic by itself.
+const MAX_MAGIC_WRITE: usize = 512;
+
+/// Mark the image in the upgrade slot as pending, moving to the 'Request'
+/// state.  This writes the magic at the very end of the slot, which must be
+/// erased.
+pub fn request_upgrade<F: Flash>(flash: &mut F) -> Result<()> {
+    let size = flash.write_size().max(MAGIC.len());
+    if size > MAX_MAGIC_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0xffu8; MAX_MAGIC_WRITE];
+    buf[size - MAGIC.len()..size].copy_from_slice(&MAGIC);
+    flash.write(flash.capacity() - size, &buf[..size])?;
+    Ok(())
+}
+
+/// Is an upgrade of the image in this slot requested?
+pub fn upgrade_requested<F: ReadFlash>(flash: &mut F) -> Result<bool> {
+    let size = flash.read_size().max(MAGIC.len());
+    if size > MAX_MAGIC_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0u8; MAX_MAGIC_WRITE];
+    match flash.read(flash.capacity() - size, &mut buf[..size]) {
+        Ok(()) => (),
+        // Some devices can't read erased flash, which is never a request.
+        Err(storage::Error::NotWritten) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(buf[size - MAGIC.len()..size] == MAGIC)
+}
 
 mod sizes {
     /// Maximum expected image size.
@@ -137,11 +210,12 @@ mod sizes {
     };
     // PAGED_HASHES.max(OVERWRITE_HASHES);
 
-    /// How many sectors of hash can we expect in paged.
+    /// How many sectors of hash can we expect in paged.  Each hash is 4
+    /// bytes.
     pub const MAX_PAGED_HASH_SECTORS: usize =
-        (PAGED_HASHES + PAGED_HASHES).div_ceil(SMALLEST_PAGED_SECTOR);
+        ((PAGED_HASHES + PAGED_HASHES) * 4).div_ceil(SMALLEST_PAGED_SECTOR);
     pub const MAX_OVERWRITE_HASH_SECTORS: usize =
-        (OVERWRITE_HASHES + OVERWRITE_HASHES).div_ceil(SMALLEST_SECTOR);
+        ((OVERWRITE_HASHES + OVERWRITE_HASHES) * 4).div_ceil(SMALLEST_SECTOR);
 
     pub const MAX_HASH_SECTORS: usize = {
         if MAX_PAGED_HASH_SECTORS > MAX_OVERWRITE_HASH_SECTORS {
@@ -167,6 +241,12 @@ pub struct SlotInfo {
     pub capacity: usize,
     /// Size, in bytes, of the image, including trailing TLV, etc.
     pub image_size: usize,
+    /// Number of sectors the status pages rotate through, in paged mode.
+    pub status_pages: usize,
+    /// Number of sectors the swap progress is recorded for at a time.
+    pub progress_group: usize,
+    /// Record progress with a counter rather than hashes.
+    pub minimal: bool,
 }
 
 impl SlotInfo {
@@ -175,21 +255,52 @@ impl SlotInfo {
         let write_size = flash.write_size();
         let erase_size = flash.erase_size();
         let capacity = flash.capacity();
-        SlotInfo { write_size, erase_size, capacity, image_size }
+        SlotInfo {
+            write_size,
+            erase_size,
+            capacity,
+            image_size,
+            status_pages: DEFAULT_STATUS_PAGES,
+            progress_group: 1,
+            minimal: false,
+        }
+    }
+
+    /// Rotate the status pages through `pages` sectors, rather than two.
+    pub fn with_status_pages(self, pages: usize) -> SlotInfo {
+        SlotInfo { status_pages: pages, ..self }
+    }
+
+    /// Record the swap progress once per `sectors` sectors, rather than after
+    /// each one.  There are fewer status writes, but a swap interrupted by a
+    /// power failure redoes up to a whole group of sectors when it resumes.
+    pub fn with_progress_group(self, sectors: usize) -> SlotInfo {
+        SlotInfo { progress_group: sectors, ..self }
+    }
+
+    /// Use the minimal status style, which records progress with a counter
+    /// rather than hashes, for devices where the hashes don't fit.
+    pub fn with_minimal_status(self) -> SlotInfo {
+        SlotInfo { minimal: true, ..self }
     }
 
     /// Determine the status style for this slot.
-    pub fn status_style(&self) -> StatusStyle {
+    pub fn status_style(&self) -> Result<StatusStyle> {
+        if self.minimal {
+            return Ok(StatusStyle::Minimal);
+        }
+
         if self.write_size <= 32 {
-            return StatusStyle::OverWrite;
+            return Ok(StatusStyle::OverWrite);
         }
 
         if self.erase_size <= 4096 {
-            return StatusStyle::Paged;
+            return Ok(StatusStyle::Paged);
         }
 
-        // It is unclear how to support this flash.
-        panic!("Device configuration unsupported");
+        // Large writes in large sectors only fit the minimal style.
+        error!("Device configuration unsupported");
+        Err(Error::CannotUpgrade)
     }
 
     /// Given our info, compute the status layout for this particular slot.  The
@@ -198,28 +309,64 @@ impl SlotInfo {
         // Use the larger of the two erase sizes for the swap.
         let erase_size = self.erase_size.max(upgrade.erase_size);
 
-        assert!(self.erase_size.is_power_of_two());
-        assert!(self.write_size.is_power_of_two());
+        // The layout aligns by masking, and each slot's sectors must divide
+        // the swap's.
+        let sizes = [self.erase_size, upgrade.erase_size, self.write_size];
+        if !sizes.iter().all(|size| size.is_power_of_two()) {
+            error!("Flash sizes must be powers of two");
+            return Err(Error::CannotUpgrade);
+        }
 
         let image_sectors = [
             self.image_size.div_ceil(erase_size),
             upgrade.image_size.div_ceil(erase_size)
         ];
-        let style = self.status_style();
-        // println!("Erase size: {}", erase_size);
-        // println!("Image sectors: {:?}", image_sectors);
-        // println!("Tail size: {}", size_of::<StatusTail>());
-        // println!("Style: {:?}", style);
+        let style = self.status_style()?;
+        let pages = match style {
+            StatusStyle::Paged => self.status_pages,
+            StatusStyle::OverWrite | StatusStyle::Minimal => 1,
+        };
+        if style == StatusStyle::Paged && !(2..=MAX_STATUS_PAGES).contains(&pages) {
+            return Err(Error::CannotUpgrade);
+        }
+        let group = self.progress_group;
+        if !(1..=MAX_PROGRESS_GROUP).contains(&group) {
+            return Err(Error::CannotUpgrade);
+        }
+        debug!("Erase size: {}", erase_size);
+        debug!("Image sectors: {:?}", image_sectors);
+        debug!("Tail size: {}", size_of::<StatusTail>());
+        debug!("Style: {:?}", style);
+        debug!("Status pages: {}", pages);
+        debug!("Progress group: {}", group);
+
+        // The trailer the application uses is at the end of the slot.  In
+        // overwrite mode, the status goes below it in the same sector.  Paged
+        // mode erases its pages, so they start at the sector below it.
+        let trailer = trailer_size(self.write_size);
+        debug!("Trailer size: {}", trailer);
 
         // Calculate the layout of our last page, or two, depending on mode.
         let mut pos = erase_size;
+        if style != StatusStyle::Paged {
+            pos = pos.checked_sub(trailer).ok_or(Error::CannotUpgrade)?;
+        }
 
-        // The tail goes at the end.
-        pos -= size_of::<StatusTail>();
+        // The tail goes at the end, in sectors big enough for it.
+        pos = pos.checked_sub(size_of::<StatusTail>()).ok_or(Error::CannotUpgrade)?;
         let tail_pos = pos;
 
+        // Minimal mode may be asked for where the flags don't fit, and tiny
+        // sectors may not have room for them either.
+        if style == StatusStyle::Minimal && self.write_size > MAX_TAIL_SPAN {
+            return Err(Error::CannotUpgrade);
+        }
+        if style != StatusStyle::Paged && pos / self.write_size < 3 {
+            return Err(Error::CannotUpgrade);
+        }
+
         // The status flags are present
-        let flags = if style == StatusStyle::OverWrite {
+        let flags = if style != StatusStyle::Paged {
             // Round down to be write aligned.
             pos = pos & !(self.write_size - 1);
 
@@ -237,34 +384,49 @@ impl SlotInfo {
             None
         };
 
+        // A hash is recorded for each group of sectors, or in minimal mode, a
+        // write unit of the counter below the flags.
+        let total_groups = image_sectors[0].div_ceil(group) + image_sectors[1].div_ceil(group);
+        let counter = if style == StatusStyle::Minimal {
+            pos = pos.checked_sub(total_groups * self.write_size).ok_or(Error::CannotUpgrade)?;
+            Some(pos)
+        } else {
+            None
+        };
+
         let end_hashes = pos;
         pos &= !(erase_size - 1);
 
-        let total_image_sectors = image_sectors[0] + image_sectors[1];
-        let inline_hashes = ((end_hashes - pos) / 4).min(total_image_sectors);
+        let inline_hashes = if counter.is_some() { 0 } else { ((end_hashes - pos) / 4).min(total_groups) };
 
         // Calculate additional pages of hashes.
         let mut hash_pages = sizes::HashVec::new();
-        let mut count = total_image_sectors - inline_hashes;
+        let mut count = if counter.is_some() { 0 } else { total_groups - inline_hashes };
         while count > 0 {
             let n = (erase_size / 4).min(count);
-            hash_pages.push(n).unwrap();
+            // Images bigger than expected, in small sectors.
+            hash_pages.push(n).map_err(|_| Error::CannotUpgrade)?;
             count -= n;
         }
 
-        // println!("Hashes: {} bytes", end_hashes - pos);
-        // println!("Tail pos: {}", tail_pos);
-        // println!("flags pos: {:?}", flags);
-        // println!("inline hashes: {}", inline_hashes);
-        // println!("Additional hashes: {:?}", hash_pages);
+        debug!("Hashes: {} bytes", end_hashes - pos);
+        debug!("Tail pos: {}", tail_pos);
+        debug!("flags pos: {:?}", flags);
+        debug!("counter pos: {:?}", counter);
+        debug!("inline hashes: {}", inline_hashes);
+        debug!("Additional hashes: {:?}", hash_pages);
 
         Ok(StatusLayout {
             style,
             erase_size,
             write_size: self.write_size,
             image_sectors,
+            group,
+            pages,
+            trailer,
             tail_pos,
             flags,
+            counter,
             inline_hashes,
             hash_pages,
         })
@@ -274,7 +436,10 @@ impl SlotInfo {
 #[derive(Debug, Eq, PartialEq)]
 pub enum StatusStyle {
     Paged,
-    OverWrite
+    OverWrite,
+    /// Like overwrite, but with a counter of the groups swapped instead of
+    /// their hashes.
+    Minimal,
 }
 
 #[derive(Debug)]
@@ -283,31 +448,784 @@ pub struct StatusLayout {
     pub erase_size: usize,
     pub write_size: usize,
     pub image_sectors: [usize; 2],
+    /// Sectors the swap progress is recorded for at a time.
+    pub group: usize,
+    /// Sectors at the end of the slot the status pages rotate through.
+    pub pages: usize,
+    /// Bytes at the end of the slot kept for the trailer (see `trailer_size`).
+    /// In paged mode, the status pages start at the sector below it.
+    pub trailer: usize,
     pub tail_pos: usize,
+    /// In overwrite and minimal modes, the offsets in the last page of the
+    /// move done, copy done and image ok flags.  Setting image ok clears the
+    /// key (see `clear_enc_key`).
     pub flags: Option<[usize; 3]>,
+    /// In minimal mode, the offset in the last page of the progress counter:
+    /// a write unit for each progress record.
+    pub counter: Option<usize>,
     pub inline_hashes: usize,
     pub hash_pages: sizes::HashVec<usize>,
 }
 
 impl StatusLayout {
-    pub fn read<F: Flash>(&self, flash: &mut F) -> Result<()> {
-        // Calculate the address of the last page.
-        let last_page = ((flash.capacity() / flash.erase_size()) - 1) * flash.erase_size();
+    /// The number of progress records a swap of these images makes, one for
+    /// each group of sectors of each image.
+    pub fn progress_records(&self) -> usize {
+        self.image_sectors.iter().map(|sectors| sectors.div_ceil(self.group)).sum()
+    }
+
+    /// Is progress recorded once `sector` of an image of `sectors` sectors has
+    /// been swapped?  This is at the end of each group, and of the image.
+    pub fn records_after(&self, sector: usize, sectors: usize) -> bool {
+        (sector + 1).is_multiple_of(self.group) || sector + 1 == sectors
+    }
+
+    /// Where to resume a swap that recorded `done` groups as complete: the
+    /// first sector of the next group.
+    pub fn resume_sector(&self, done: usize) -> usize {
+        done * self.group
+    }
+
+    /// The sectors at the end of the slot the status takes, along with the
+    /// trailer: in paged mode, those holding the trailer, then the pages the
+    /// tail rotates through, and the additional pages of hashes below them.
+    pub fn status_sectors(&self) -> usize {
+        self.trailer_sectors() + self.pages + self.hash_pages.len()
+    }
+
+    /// The sectors at the end of the slot given over to the trailer alone.
+    fn trailer_sectors(&self) -> usize {
+        match self.style {
+            StatusStyle::Paged => self.trailer.div_ceil(self.erase_size),
+            StatusStyle::OverWrite | StatusStyle::Minimal => 0,
+        }
+    }
 
-        println!("Last page: {:x}", last_page);
-        let last_tail_pos = last_page + self.tail_pos;
+    /// Check the invariants of the layout, all relative to the start of the
+    /// last status sector.  The tail ends the sector, or in overwrite mode,
+    /// ends where the trailer starts.  The flags, and the counter,
+    /// are write aligned, and stacked below the tail without overlapping.
+    /// The inline hashes fit below all of these, and together with those in
+    /// the additional sectors, there is one hash for each progress record.
+    pub fn check(&self) -> Result<()> {
+        let ws = self.write_size;
+        let minimal = self.style == StatusStyle::Minimal;
+        let pages = match self.style {
+            StatusStyle::Paged => (2..=MAX_STATUS_PAGES).contains(&self.pages),
+            StatusStyle::OverWrite | StatusStyle::Minimal => self.pages == 1,
+        };
+        if !ws.is_power_of_two() || !self.erase_size.is_power_of_two() || ws > self.erase_size
+            || !pages || !(1..=MAX_PROGRESS_GROUP).contains(&self.group)
+        {
+            error!("Status layout: bad geometry");
+            return Err(Error::CannotUpgrade);
+        }
+
+        let top = match self.style {
+            StatusStyle::Paged => Some(self.erase_size),
+            StatusStyle::OverWrite | StatusStyle::Minimal => self.erase_size.checked_sub(self.trailer),
+        };
+        if self.trailer != trailer_size(ws) || top.is_none()
+            || self.tail_pos.checked_add(size_of::<StatusTail>()) != top
+        {
+            error!("Status layout: tail doesn't end below the trailer");
+            return Err(Error::CannotUpgrade);
+        }
+
+        // Walk down from the tail, each region ending at or below the start
+        // of the one above it.
+        let mut top = self.tail_pos;
+        match (&self.style, self.flags) {
+            (StatusStyle::Paged, None) => (),
+            (StatusStyle::OverWrite | StatusStyle::Minimal, Some(flags)) => {
+                for flag in flags {
+                    if flag % ws != 0 || flag + ws > top {
+                        error!("Status layout: flag at 0x{:x} misplaced", flag);
+                        return Err(Error::CannotUpgrade);
+                    }
+                    top = flag;
+                }
+            }
+            _ => {
+                error!("Status layout: flags don't match the style");
+                return Err(Error::CannotUpgrade);
+            }
+        }
+        match self.counter {
+            Some(counter) if minimal => {
+                let end = self.progress_records().checked_mul(ws).and_then(|len| counter.checked_add(len));
+                if counter % ws != 0 || end.is_none_or(|end| end > top) {
+                    error!("Status layout: counter at 0x{:x} misplaced", counter);
+                    return Err(Error::CannotUpgrade);
+                }
+                top = counter;
+            }
+            None if !minimal => (),
+            _ => {
+                error!("Status layout: counter doesn't match the style");
+                return Err(Error::CannotUpgrade);
+            }
+        }
+
+        if self.inline_hashes * 4 > top {
+            error!("Status layout: inline hashes overlap the flags");
+            return Err(Error::CannotUpgrade);
+        }
+        if self.hash_pages.iter().any(|&n| n == 0 || n > self.erase_size / 4) {
+            error!("Status layout: hash page overfull");
+            return Err(Error::CannotUpgrade);
+        }
+        let hashes = self.inline_hashes + self.hash_pages.iter().sum::<usize>();
+        let expected = if minimal { 0 } else { self.progress_records() };
+        if hashes != expected {
+            error!("Status layout: {} hashes for {} records", hashes, expected);
+            return Err(Error::CannotUpgrade);
+        }
+        Ok(())
+    }
 
-        let mut last_tail = StatusTail::default();
-        flash.read(last_tail_pos, last_tail.as_mut_raw())?;
+    /// In minimal mode, the number of progress records made so far: the
+    /// written units at the start of the counter.  The swap resumes at
+    /// `resume_sector` of this, redoing the group it was in, since without
+    /// hashes there is no telling how far into the group it got.
+    pub fn progress<F: Flash>(&self, flash: &mut F) -> Result<usize> {
+        let (base, unit) = self.counter_base(flash)?;
+        let mut buf = [0u8; MAX_TAIL_SPAN];
+        for record in 0..self.progress_records() {
+            let buf = &mut buf[..unit];
+            let written = match flash.read(base + record * unit, buf) {
+                Ok(()) => buf.iter().any(|&b| b != 0xff),
+                Err(storage::Error::NotWritten) => false,
+                Err(e) => return Err(e.into()),
+            };
+            if !written {
+                return Ok(record);
+            }
+        }
+        Ok(self.progress_records())
+    }
 
+    /// In minimal mode, record that one more group has been swapped.
+    pub fn advance<F: Flash>(&self, flash: &mut F) -> Result<()> {
+        let done = self.progress(flash)?;
+        if done == self.progress_records() {
+            return Err(Error::CannotUpgrade);
+        }
+        let (base, unit) = self.counter_base(flash)?;
+        let buf = [0u8; MAX_TAIL_SPAN];
+        flash.write(base + done * unit, &buf[..unit])?;
+        event(EventCode::SwapStep, done as u32 + 1);
         Ok(())
     }
+
+    /// Where the counter is, and the size of its units.  The read size must
+    /// fit within the write size for each unit to be read alone.
+    fn counter_base<F: Flash>(&self, flash: &F) -> Result<(usize, usize)> {
+        let counter = self.counter.ok_or(Error::CannotUpgrade)?;
+        let unit = flash.write_size();
+        if unit != self.write_size || !unit.is_multiple_of(flash.read_size()) {
+            return Err(Error::CannotUpgrade);
+        }
+        Ok((self.page_base(flash, 0)? + counter, unit))
+    }
+
+    /// The span holding the tail of the page `page` sectors from the end: from
+    /// `unit` alignment before it, to the `unit` alignment after it.  The tail
+    /// is only accessed as a whole span.
+    fn tail_span<F: Flash>(&self, flash: &F, page: usize, unit: usize) -> Result<(usize, usize, usize)> {
+        let base = self.page_base(flash, page)?;
+        debug!("Status page: {:x}", base);
+        let tail = base + self.tail_pos;
+        let start = tail / unit * unit;
+        let end = (tail + size_of::<StatusTail>()).next_multiple_of(unit);
+        if end - start > MAX_TAIL_SPAN || end > flash.capacity() {
+            return Err(Error::CannotUpgrade);
+        }
+        Ok((start, tail - start, end - start))
+    }
+
+    /// The offset of the status page `page` sectors below the last one, which
+    /// in paged mode is the sector below the trailer.
+    pub fn page_base<F: ReadFlash>(&self, flash: &F, page: usize) -> Result<usize> {
+        (flash.capacity() / self.erase_size)
+            .checked_sub(self.trailer_sectors() + page + 1)
+            .map(|sector| sector * self.erase_size)
+            .ok_or(Error::CannotUpgrade)
+    }
+
+    /// Read the status tail of this slot.  In paged mode, this is the newest of
+    /// the pages.  Tails written in an older format are converted to the
+    /// current one.  The key reads as cleared once `clear_enc_key` has set
+    /// the image ok flag.
+    pub fn read<F: Flash>(&self, flash: &mut F) -> Result<StatusRead> {
+        Ok(match self.current(flash)? {
+            Some((_, StatusRead::Valid(mut status))) if self.style != StatusStyle::Paged => {
+                if status.has_enc_key() && self.key_cleared(flash)? {
+                    status.enc_key = [0; 16];
+                }
+                StatusRead::Valid(status)
+            }
+            Some((_, status)) => status,
+            None => StatusRead::Empty,
+        })
+    }
+
+    /// Write the status tail of this slot, in the current format, recording
+    /// this layout's progress group.  In overwrite mode, the tail must be
+    /// erased.  In paged mode, the status goes in the
+    /// page after the current one, which is erased first, with the next age,
+    /// and may not be of an earlier generation than the current one.
+    pub fn write<F: Flash>(&self, flash: &mut F, status: &Status) -> Result<()> {
+        self.write_status(flash, status, None)
+    }
+
+    /// Write the status tail, as `write`.  In paged mode, the inline hashes go
+    /// in the new page with it: `hashes` if given, otherwise those of the
+    /// current page, if it is of the same swap.
+    fn write_status<F: Flash>(&self, flash: &mut F, status: &Status, hashes: Option<&[u32]>) -> Result<()> {
+        let (page, age, carry) = match self.style {
+            StatusStyle::OverWrite | StatusStyle::Minimal => (0, OVERWRITE_AGE, None),
+            StatusStyle::Paged => {
+                let (page, age, carry) = match self.current(flash)? {
+                    Some((_, StatusRead::Valid(current))) if current.generation > status.generation => {
+                        error!("Status of generation {} would replace {}", status.generation, current.generation);
+                        return Err(Error::CannotUpgrade);
+                    }
+                    Some((page, StatusRead::Valid(current))) => {
+                        let same = current.generation == status.generation && current.hash_seed == status.hash_seed;
+                        ((page + 1) % self.pages, next_age(current.age), same.then_some(page))
+                    }
+                    _ => (0, 0, None),
+                };
+                let base = self.page_base(flash, page)?;
+                flash.erase(base, base + self.erase_size)?;
+                (page, age, carry)
+            }
+        };
+
+        let unit = flash.write_size();
+        let (start, pos, len) = self.tail_span(flash, page, unit)?;
+        let tail = StatusTail {
+            version: STATUS_VERSION,
+            group: self.group as u8,
+            generation: status.generation,
+            enc_key: status.enc_key,
+            main_size: status.main_size,
+            upgrade_size: status.upgrade_size,
+            hash_seed: status.hash_seed,
+            write_log: status.write_log,
+            erase_log: status.erase_log,
+            flags: status.flags,
+            age,
+            magic: MAGIC,
+        };
+        if self.style != StatusStyle::Paged || (hashes.is_none() && carry.is_none()) {
+            let mut buf = [0xffu8; MAX_TAIL_SPAN];
+            buf[pos..pos + size_of::<StatusTail>()].copy_from_slice(tail.as_raw());
+            flash.write(start, &buf[..len])?;
+            return Ok(());
+        }
+
+        // The hashes are written first, and the tail, which makes the page
+        // current, last.
+        self.hash_unit(flash)?;
+        let old = carry.map(|page| self.page_base(flash, page)).transpose()?;
+        let hash_end = self.inline_hashes * 4;
+        let tail = tail.as_raw();
+        let base = self.page_base(flash, page)?;
+        self.write_start(flash, base, start + len, |flash, at, buf| {
+            match (hashes, old) {
+                (Some(hashes), _) => fill_hashes(hashes, at, buf),
+                (None, Some(old)) if at < hash_end => {
+                    let len = (hash_end - at).next_multiple_of(unit).min(buf.len());
+                    match flash.read(old + at, &mut buf[..len]) {
+                        Ok(()) => (),
+                        Err(storage::Error::NotWritten) => buf[..len].fill(0xff),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                _ => (),
+            }
+            for (n, byte) in buf.iter_mut().enumerate() {
+                if let Some(&b) = (at + n).checked_sub(self.tail_pos).and_then(|pos| tail.get(pos)) {
+                    *byte = b;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Record the hash of each progress record (see `record_hash`), as a swap
+    /// starts, once its first status is written.  The hash pages are erased
+    /// and written.  In overwrite mode, the inline hashes are written in
+    /// place, and must be erased.  In paged mode, they go in the next page,
+    /// with the status again, and each later page carries them on.  Minimal
+    /// mode has no hashes.
+    pub fn write_hashes<F: Flash>(&self, flash: &mut F, hashes: &[u32]) -> Result<()> {
+        if self.counter.is_some() || hashes.len() != self.progress_records() {
+            return Err(Error::CannotUpgrade);
+        }
+        let unit = self.hash_unit(flash)?;
+        let (inline, mut rest) = hashes.split_at(self.inline_hashes);
+        for (n, &count) in self.hash_pages.iter().enumerate() {
+            let (page, next) = rest.split_at(count);
+            let base = self.page_base(flash, self.pages + n)?;
+            flash.erase(base, base + self.erase_size)?;
+            let end = (count * 4).next_multiple_of(unit);
+            self.write_start(flash, base, end, |_, at, buf| {
+                fill_hashes(page, at, buf);
+                Ok(())
+            })?;
+            rest = next;
+        }
+
+        if self.style == StatusStyle::Paged {
+            let StatusRead::Valid(status) = self.read(flash)? else {
+                return Err(Error::CannotUpgrade);
+            };
+            return self.write_status(flash, &status, Some(inline));
+        }
+        let base = self.page_base(flash, 0)?;
+        let end = (inline.len() * 4).next_multiple_of(unit);
+        self.write_start(flash, base, end, |_, at, buf| {
+            fill_hashes(inline, at, buf);
+            Ok(())
+        })
+    }
+
+    /// The hash recorded for progress record `record` by `write_hashes`, or
+    /// None if there is none, such as in minimal mode, or before the hashes
+    /// are written.
+    pub fn read_hash<F: Flash>(&self, flash: &mut F, record: usize) -> Result<Option<u32>> {
+        if self.counter.is_some() || record >= self.progress_records() {
+            return Ok(None);
+        }
+        let page = match self.style {
+            StatusStyle::Paged => match self.current(flash)? {
+                Some((page, StatusRead::Valid(_))) => page,
+                _ => return Ok(None),
+            },
+            StatusStyle::OverWrite | StatusStyle::Minimal => 0,
+        };
+        let offset = self.hash_offset(flash, page, record)?;
+        let unit = flash.read_size();
+        let start = offset / unit * unit;
+        let end = (offset + 4).next_multiple_of(unit);
+        if end - start > MAX_TAIL_SPAN {
+            return Err(Error::CannotUpgrade);
+        }
+        let mut buf = [0u8; MAX_TAIL_SPAN];
+        match flash.read(start, &mut buf[..end - start]) {
+            Ok(()) => (),
+            Err(storage::Error::NotWritten) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let pos = offset - start;
+        let hash = u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+        Ok(if hash == u32::MAX { None } else { Some(hash) })
+    }
+
+    /// The groups of sectors of `image`, 0 for the main image and 1 for the
+    /// upgrade, each with a progress record.
+    pub fn image_groups(&self, image: usize) -> usize {
+        self.image_sectors[image].div_ceil(self.group)
+    }
+
+    /// The progress record of `group` of `image`: those of the main image
+    /// come first.
+    pub fn record(&self, image: usize, group: usize) -> usize {
+        if image == 0 { group } else { self.image_groups(0) + group }
+    }
+
+    /// The bytes of `group` of `image` that its hash covers: from the start
+    /// of the group, to its end or the end of the image, of the size given in
+    /// `status`, rounded up to a write.  Past the end of the image, a group
+    /// may hold anything.
+    pub fn group_span(&self, status: &Status, image: usize, group: usize) -> (usize, usize) {
+        let size = if image == 0 { status.main_size } else { status.upgrade_size } as usize;
+        let bytes = self.group * self.erase_size;
+        let start = group * bytes;
+        let end = (start + bytes).min(size.next_multiple_of(self.write_size));
+        (start, end.saturating_sub(start))
+    }
+
+    /// The hash of `group` of `image`, as held in `flash`, to compare with, or
+    /// record as, that of its progress record.
+    pub fn record_hash<C: CryptoBackend, F: ReadFlash>(&self, crypto: &mut C, status: &Status, image: usize,
+                                                       group: usize, flash: &mut F) -> Result<u32> {
+        let (offset, len) = self.group_span(status, image, group);
+        group_hash(crypto, status.hash_seed, flash, offset, len)
+    }
+
+    /// Where the hash of progress record `record` is: the first hashes at the
+    /// start of the status page `page`, and the rest filling the hash pages
+    /// below the status pages in turn.
+    fn hash_offset<F: ReadFlash>(&self, flash: &F, page: usize, record: usize) -> Result<usize> {
+        if record < self.inline_hashes {
+            return Ok(self.page_base(flash, page)? + record * 4);
+        }
+        let mut record = record - self.inline_hashes;
+        for (n, &count) in self.hash_pages.iter().enumerate() {
+            if record < count {
+                return Ok(self.page_base(flash, self.pages + n)? + record * 4);
+            }
+            record -= count;
+        }
+        Err(Error::CannotUpgrade)
+    }
+
+    /// The size hashes are written in: a write unit, within the largest
+    /// span, and read whole when carried to the next page.
+    fn hash_unit<F: Flash>(&self, flash: &F) -> Result<usize> {
+        let unit = flash.write_size();
+        if unit != self.write_size || unit > MAX_TAIL_SPAN || !unit.is_multiple_of(flash.read_size()) {
+            return Err(Error::CannotUpgrade);
+        }
+        Ok(unit)
+    }
+
+    /// Write the start of the page at `base`, up to `end`, a span at a time.
+    /// `fill` is given each span, erased, with its offset in the page, to put
+    /// the contents in.  Spans left erased aren't written.
+    fn write_start<F, W>(&self, flash: &mut F, base: usize, end: usize, mut fill: W) -> Result<()>
+    where
+        F: Flash,
+        W: FnMut(&mut F, usize, &mut [u8]) -> Result<()>,
+    {
+        let mut buf = [0xffu8; MAX_TAIL_SPAN];
+        let mut at = 0;
+        while at < end {
+            let buf = &mut buf[..(end - at).min(MAX_TAIL_SPAN)];
+            buf.fill(0xff);
+            fill(flash, at, buf)?;
+            if buf.iter().any(|&b| b != 0xff) {
+                flash.write(base + at, buf)?;
+            }
+            at += buf.len();
+        }
+        Ok(())
+    }
+
+    /// The wrapped encryption key recorded with the current status, needed to
+    /// resume an encrypted swap that was interrupted.
+    pub fn enc_key<F: Flash>(&self, flash: &mut F) -> Result<Option<[u8; 16]>> {
+        match self.read(flash)? {
+            StatusRead::Valid(status) if status.has_enc_key() => Ok(Some(status.enc_key)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Clear the encryption key from the status, once the upgrade is
+    /// confirmed and it is no longer needed.  Until the image ok flag of the
+    /// slot's trailer is set, a revert may still need the key, so this is
+    /// refused.
+    ///
+    /// Written flash can't be changed in place, and the sector holding the
+    /// status may also hold the trailer, so neither is erased.  In paged mode,
+    /// the status goes to the next page with the key cleared, and the older
+    /// pages holding the key are erased.  Otherwise, the status's image ok
+    /// flag is set, after which the key reads as cleared; the wrapped key
+    /// itself stays until the status is erased for the next swap.  A power cut
+    /// leaves either the key, or none, and calling this again finishes.
+    pub fn clear_enc_key<F: Flash>(&self, flash: &mut F) -> Result<()> {
+        if image_ok(flash)? != Flag::Set {
+            error!("Encryption key kept, the upgrade isn't confirmed");
+            return Err(Error::CannotUpgrade);
+        }
+        let mut status = match self.read(flash)? {
+            StatusRead::Valid(status) => status,
+            _ => return Ok(()),
+        };
+
+        if self.style != StatusStyle::Paged {
+            if status.has_enc_key() {
+                let (base, unit) = self.flag_base(flash)?;
+                let mut buf = [0xffu8; MAX_TAIL_SPAN];
+                buf[0] = FLAG_SET;
+                flash.write(base, &buf[..unit])?;
+            }
+            return Ok(());
+        }
+
+        if status.has_enc_key() {
+            status.enc_key = [0; 16];
+            self.write(flash, &status)?;
+        }
+        let Some((current, _)) = self.current(flash)? else {
+            return Err(Error::CannotUpgrade);
+        };
+        for page in (0..self.pages).filter(|&page| page != current) {
+            if matches!(self.read_page(flash, page)?, StatusRead::Valid(old) if old.has_enc_key()) {
+                let base = self.page_base(flash, page)?;
+                flash.erase(base, base + self.erase_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// In overwrite and minimal modes, the status's image ok flag: where it
+    /// is, and the size of its unit, which is read and written whole.
+    fn flag_base<F: Flash>(&self, flash: &F) -> Result<(usize, usize)> {
+        let [_, _, image_ok] = self.flags.ok_or(Error::CannotUpgrade)?;
+        let unit = flash.write_size();
+        if unit != self.write_size || unit > MAX_TAIL_SPAN || !unit.is_multiple_of(flash.read_size()) {
+            return Err(Error::CannotUpgrade);
+        }
+        Ok((self.page_base(flash, 0)? + image_ok, unit))
+    }
+
+    /// Has the key been cleared by setting the status's image ok flag?
+    fn key_cleared<F: Flash>(&self, flash: &mut F) -> Result<bool> {
+        let (base, unit) = self.flag_base(flash)?;
+        let mut buf = [0u8; MAX_TAIL_SPAN];
+        match flash.read(base, &mut buf[..unit]) {
+            Ok(()) => Ok(buf[0] == FLAG_SET),
+            Err(storage::Error::NotWritten) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Find the page holding the current status, and read it.  A page in an
+    /// unknown format is returned over any other, as nothing about the pages
+    /// can be trusted then.  The newest page must also be of the latest
+    /// generation; if an older page has a later one, the newest is a replay
+    /// of an old status, and the status is refused.
+    fn current<F: Flash>(&self, flash: &mut F) -> Result<Option<(usize, StatusRead)>> {
+        let mut current: Option<(usize, StatusRead)> = None;
+        let mut latest = 0;
+        for page in 0..self.pages {
+            let status = self.read_page(flash, page)?;
+            if let StatusRead::Valid(status) = &status {
+                latest = latest.max(status.generation);
+            }
+            let newer = match (&status, &current) {
+                (StatusRead::Empty, _) => false,
+                (_, None) => true,
+                (_, Some((_, StatusRead::Unknown(_)))) => false,
+                (StatusRead::Unknown(_), _) => true,
+                (StatusRead::Valid(a), Some((_, StatusRead::Valid(b)))) => newer_age(a.age, b.age),
+                (StatusRead::Valid(_), Some((_, StatusRead::Empty))) => true,
+            };
+            if newer {
+                current = Some((page, status));
+            }
+        }
+        if let Some((_, StatusRead::Valid(status))) = &current {
+            if status.generation < latest {
+                error!("Status of generation {} is older than another page's {}", status.generation, latest);
+                return Err(Error::CannotUpgrade);
+            }
+        }
+        Ok(current)
+    }
+
+    /// Read the tail of a single page.
+    fn read_page<F: Flash>(&self, flash: &mut F, page: usize) -> Result<StatusRead> {
+        let (start, pos, len) = self.tail_span(flash, page, flash.read_size())?;
+        let mut buf = [0u8; MAX_TAIL_SPAN];
+        match flash.read(start, &mut buf[..len]) {
+            Ok(()) => (),
+            // Some devices can't read erased flash.
+            Err(storage::Error::NotWritten) => return Ok(StatusRead::Empty),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut tail = StatusTail::default();
+        tail.as_mut_raw().copy_from_slice(&buf[pos..pos + size_of::<StatusTail>()]);
+        if tail.magic != MAGIC {
+            return Ok(StatusRead::Empty);
+        }
+
+        match tail.version {
+            STATUS_VERSION => Ok(StatusRead::Valid(tail.status(STATUS_VERSION))),
+            // Written before there was a generation.
+            2 => Ok(StatusRead::Valid(tail.status(2))),
+            // Progress was recorded for every sector.
+            1 => Ok(StatusRead::Valid(Status { group: 1, ..tail.status(1) })),
+            // A magic with nothing else is only an upgrade request.
+            0xff if tail.hash_seed == 0xffff_ffff => Ok(StatusRead::Empty),
+            // Written before there was a version, when this byte was left
+            // erased.  The fields are otherwise the same as version 1.
+            0xff => Ok(StatusRead::Valid(Status { group: 1, ..tail.status(0) })),
+            version => {
+                error!("Status in unknown format {}, ignoring it", version);
+                Ok(StatusRead::Unknown(version))
+            }
+        }
+    }
+}
+
+/// The generation for the status of a new swap: one past that of the status
+/// in either slot, and past `floor`, the value of the device's generation
+/// counter, if it keeps one.  Every status written during the swap carries
+/// it.
+pub fn next_generation(a: &StatusRead, b: &StatusRead, floor: u32) -> Result<u16> {
+    let generation = |read: &StatusRead| match read {
+        StatusRead::Valid(status) => status.generation as u32,
+        _ => 0,
+    };
+    let next = generation(a).max(generation(b)).max(floor) + 1;
+    u16::try_from(next).map_err(|_| Error::CannotUpgrade)
+}
+
+/// The hash of the `len` bytes at `offset` of `flash`, as recorded for a
+/// group of sectors: the first four bytes, little endian, of the SHA256 of
+/// the swap's hash seed followed by the bytes.  All ones, which reads as no
+/// hash, is taken as zero.
+pub fn group_hash<C: CryptoBackend, F: ReadFlash>(crypto: &mut C, seed: u32, flash: &mut F, offset: usize,
+                                                  len: usize) -> Result<u32> {
+    crypto.sha256_start();
+    crypto.sha256_update(&seed.to_le_bytes());
+    crypto.sha256_update_flash(flash, offset, len)?;
+    let hash = crypto.sha256_finish();
+    let hash = u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]);
+    Ok(if hash == u32::MAX { 0 } else { hash })
+}
+
+/// Put the bytes of `hashes` that fall in `buf`, which is `at` bytes into
+/// them.
+fn fill_hashes(hashes: &[u32], at: usize, buf: &mut [u8]) {
+    for (n, byte) in buf.iter_mut().enumerate() {
+        if let Some(hash) = hashes.get((at + n) / 4) {
+            *byte = hash.to_le_bytes()[(at + n) % 4];
+        }
+    }
+}
+
+/// The hash seed for the status of a new swap, from `entropy`.  It is never
+/// all ones, which would read as an erased tail.
+pub fn new_hash_seed<E: EntropySource + ?Sized>(entropy: &mut E) -> Result<u32> {
+    loop {
+        let seed = entropy.next_u32()?;
+        if seed != 0xffff_ffff {
+            return Ok(seed);
+        }
+    }
+}
+
+/// Check that a status isn't a replay of an old one: it may not be of an
+/// earlier generation than the status in the other slot, nor than the
+/// device's counter, if it keeps one.
+pub fn check_generation(status: &Status, other: &StatusRead, counter: Option<&mut dyn RollbackCounter>) -> Result<()> {
+    let mut floor = match other {
+        StatusRead::Valid(other) => other.generation as u32,
+        _ => 0,
+    };
+    if let Some(counter) = counter {
+        floor = floor.max(counter.read()?);
+    }
+    if (status.generation as u32) < floor {
+        error!("Status of generation {} is below {}", status.generation, floor);
+        return Err(Error::CannotUpgrade);
+    }
+    Ok(())
+}
+
+/// Advance the device's generation counter to that of a status, once its
+/// swap is complete, so the status can't be replayed after both slots have
+/// moved on.
+pub fn advance_generation<R: RollbackCounter>(status: &Status, counter: &mut R) -> Result<()> {
+    counter.advance(status.generation as u32)
+}
+
+/// The number of status pages used in paged mode, unless configured
+/// otherwise: one to write while the other holds the last status.
+pub const DEFAULT_STATUS_PAGES: usize = 2;
+
+/// The most status pages that can be configured.  The ages of the pages must
+/// stay well within half their range for the newest to be found.
+pub const MAX_STATUS_PAGES: usize = 16;
+
+/// The age that marks a tail as being in overwrite mode.
+const OVERWRITE_AGE: u8 = 0xff;
+
+/// The age of the page written after one of `age`.  Ages count through every
+/// value but `OVERWRITE_AGE`.
+fn next_age(age: u8) -> u8 {
+    if age >= OVERWRITE_AGE - 1 { 0 } else { age + 1 }
+}
+
+/// Is a page of age `a` newer than one of age `b`?  The ages in use are all
+/// within `MAX_STATUS_PAGES` of each other, so this works across the wrap.
+fn newer_age(a: u8, b: u8) -> bool {
+    let span = OVERWRITE_AGE as usize;
+    let diff = (a as usize + span - b as usize) % span;
+    diff != 0 && diff < span / 2
+}
+
+/// The format of the status tail written by this code.  Any change to the
+/// status layout needs a new version, and `StatusLayout::read` must convert
+/// the older ones it may find on a device.
+///
+/// - 0: No version byte (it was left erased).
+/// - 1: The version byte added, the fields unchanged.
+/// - 2: The progress group added, in the first reserved byte.
+/// - 3: The generation added, in the rest of the reserved bytes.
+pub const STATUS_VERSION: u8 = 3;
+
+/// The largest progress group, as it is recorded in a byte.
+pub const MAX_PROGRESS_GROUP: usize = 0xff;
+
+/// Largest span the tail is read or written in.
+const MAX_TAIL_SPAN: usize = 512;
+
+/// The status held in the tail, converted to the current format.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Status {
+    /// The format the tail was written in.
+    pub version: u8,
+    /// Sectors the swap progress was recorded for at a time.  A swap must be
+    /// resumed with the group it was started with.
+    pub group: u8,
+    /// The key the image being swapped is encrypted with, wrapped by the
+    /// caller so it is never stored in the clear.  Cleared (all zero) once the
+    /// upgrade is done, and erased if there is none.
+    pub enc_key: [u8; 16],
+    pub main_size: u32,
+    pub upgrade_size: u32,
+    pub hash_seed: u32,
+    pub write_log: u8,
+    pub erase_log: u8,
+    pub flags: u8,
+    pub age: u8,
+    /// The generation of the swap, see `next_generation`.  Zero for tails
+    /// written before there was one.
+    pub generation: u16,
+}
+
+impl Status {
+    /// Is an encryption key recorded?
+    pub fn has_enc_key(&self) -> bool {
+        self.enc_key.iter().any(|&b| b != 0) && self.enc_key.iter().any(|&b| b != 0xff)
+    }
+}
+
+/// The result of reading a status tail.
+#[derive(Debug, Eq, PartialEq)]
+pub enum StatusRead {
+    /// No status has been written.
+    Empty,
+    /// The status.
+    Valid(Status),
+    /// Status in a format newer than this code knows, with its version.  This
+    /// happens if a newer bootloader is replaced by an older one.  None of the
+    /// fields can be trusted, so this must be handled as if there were no
+    /// status, rather than resuming an operation.
+    Unknown(u8),
 }
 
 /// The status tail.  This data is placed at the very end of the slot.
 #[derive(Debug, Default)]
 #[repr(C)]
 struct StatusTail {
+    /// The format of the tail, `STATUS_VERSION`.  Left erased by code older
+    /// than this field.
+    version: u8,
+    /// Sectors the swap progress is recorded for at a time.
+    group: u8,
+    /// The generation of the swap this status is for.  Left erased before
+    /// version 3.
+    generation: u16,
     /// The encryption key, used if we are encrypting in/out of slot0.
     enc_key: [u8; 16],
     /// Size of the main image, in bytes, includes TLV.
@@ -330,6 +1248,24 @@ struct StatusTail {
     magic: [u8; 16],
 }
 
+impl StatusTail {
+    fn status(&self, version: u8) -> Status {
+        Status {
+            version,
+            group: self.group,
+            enc_key: self.enc_key,
+            main_size: self.main_size,
+            upgrade_size: self.upgrade_size,
+            hash_seed: self.hash_seed,
+            write_log: self.write_log,
+            erase_log: self.erase_log,
+            flags: self.flags,
+            age: self.age,
+            generation: if version >= 3 { self.generation } else { 0 },
+        }
+    }
+}
+
 impl AsRaw for StatusTail {}
 unsafe impl AsMutRaw for StatusTail {}
 
@@ -0,0 +1,144 @@
+//! Writing TLVs
+//!
+//! When the bootloader transforms an image as it installs it, such as writing
+//! a decrypted copy into the primary slot, some of the image's TLVs no longer
+//! describe what is stored (the encryption keys, in that case), and others may
+//! be needed.  `TlvWriter` writes a TLV area to flash, a piece at a time, so
+//! the stored image stays self-consistent.  `Image::rewrite_tlvs` uses it to
+//! copy an image's TLVs, leaving some out and adding others.
+//!
+//! Only the unprotected TLVs can be changed this way: the protected ones are
+//! covered by the image hash.  The size of the unprotected area is only
+//! recorded in its own info header, so nothing else in the image changes.
+
+use storage::Flash;
+
+use crate::image::{TLV_INFO_MAGIC, TLV_PROT_INFO_MAGIC};
+use crate::{Error, Result};
+
+/// Largest write size supported.
+const MAX_WRITE: usize = 512;
+
+/// Size of the info header, and of each entry's header.
+pub const TLV_HEADER_LEN: usize = 4;
+
+/// Writes a TLV area: the info header, then each entry's header and data.
+/// The total length is given up front, as it goes in the info header, which
+/// is written first.  Writes are buffered to the flash's write size, and the
+/// last one is padded with 0xff.
+pub struct TlvWriter<'a, F> {
+    flash: &'a mut F,
+    /// Where the buffered write unit goes.
+    offset: usize,
+    buf: [u8; MAX_WRITE],
+    used: usize,
+    /// Bytes still to come, of the length given.
+    left: usize,
+    /// Data still to come for the current entry.
+    entry_left: usize,
+}
+
+impl<'a, F: Flash> TlvWriter<'a, F> {
+    /// Begin a TLV area of `len` bytes, including its info header.  Writing
+    /// starts at `start`, which must be aligned to the write size, with
+    /// `prefix`: the end of the payload that shares a write unit with the
+    /// TLVs, which is empty if they start on a unit boundary.
+    pub fn new(flash: &'a mut F, start: usize, prefix: &[u8], protected: bool, len: usize) -> Result<TlvWriter<'a, F>> {
+        let write_size = flash.write_size();
+        if write_size > MAX_WRITE || !start.is_multiple_of(write_size) || len < TLV_HEADER_LEN || len > u16::MAX as usize {
+            return Err(Error::CannotUpgrade);
+        }
+        let mut writer = TlvWriter {
+            flash,
+            offset: start,
+            buf: [0xff; MAX_WRITE],
+            used: 0,
+            left: len,
+            entry_left: 0,
+        };
+        writer.put(prefix)?;
+        let magic = if protected { TLV_PROT_INFO_MAGIC } else { TLV_INFO_MAGIC };
+        writer.header(magic, len)?;
+        Ok(writer)
+    }
+
+    /// Add a whole entry.
+    pub fn entry(&mut self, kind: u16, data: &[u8]) -> Result<()> {
+        self.start_entry(kind, data.len())?;
+        self.data(data)
+    }
+
+    /// Begin an entry, whose `len` bytes of data follow through `data`.
+    pub fn start_entry(&mut self, kind: u16, len: usize) -> Result<()> {
+        if self.entry_left != 0 || len > u16::MAX as usize {
+            return Err(Error::CannotUpgrade);
+        }
+        self.header(kind, len)?;
+        self.entry_left = len;
+        Ok(())
+    }
+
+    /// Add data to the current entry.
+    pub fn data(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > self.entry_left {
+            return Err(Error::CannotUpgrade);
+        }
+        self.entry_left -= data.len();
+        self.add(data)
+    }
+
+    /// Write out the rest.  Every entry must be complete, and the length given
+    /// at the start reached.  Returns the offset just past the area.
+    pub fn finish(mut self) -> Result<usize> {
+        if self.left != 0 || self.entry_left != 0 {
+            return Err(Error::CannotUpgrade);
+        }
+        let end = self.offset + self.used;
+        if self.used > 0 {
+            self.flush()?;
+        }
+        Ok(end)
+    }
+
+    /// Add an info or entry header.
+    fn header(&mut self, kind: u16, len: usize) -> Result<()> {
+        let mut header = [0u8; TLV_HEADER_LEN];
+        header[..2].copy_from_slice(&kind.to_le_bytes());
+        header[2..].copy_from_slice(&(len as u16).to_le_bytes());
+        self.add(&header)
+    }
+
+    /// Add bytes counted against the length.
+    fn add(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > self.left {
+            return Err(Error::CannotUpgrade);
+        }
+        self.left -= data.len();
+        self.put(data)
+    }
+
+    /// Buffer bytes, writing each unit as it fills.
+    fn put(&mut self, mut data: &[u8]) -> Result<()> {
+        let write_size = self.flash.write_size();
+        while !data.is_empty() {
+            let count = (write_size - self.used).min(data.len());
+            self.buf[self.used..self.used + count].copy_from_slice(&data[..count]);
+            self.used += count;
+            data = &data[count..];
+            if self.used == write_size {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the buffered unit, padded.
+    fn flush(&mut self) -> Result<()> {
+        let write_size = self.flash.write_size();
+        self.buf[self.used..write_size].fill(0xff);
+        self.flash.write(self.offset, &self.buf[..write_size])?;
+        self.offset += write_size;
+        self.used = 0;
+        Ok(())
+    }
+}
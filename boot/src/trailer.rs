@@ -0,0 +1,385 @@
+//! Trailer state, as seen by the application
+//!
+//! The running application needs to know a little about the upgrade state:
+//! whether an upgrade is pending, whether the image it is running has been
+//! confirmed, and what is in each slot.  This mirrors `boot_swap_type()` and
+//! `boot_is_img_confirmed()` from the C bootutil.
+//!
+//! Besides the magic at the very end of the slot (see `request_upgrade`), the
+//! trailer has two flags, each in its own write unit just below the magic, and
+//! below them, a record of why the bootloader last rejected the slot:
+//!
+//! +-----------------+
+//! | magic           |  last max(write_size, 16) bytes
+//! | image ok        |  max(write_size, 8) bytes
+//! | copy done       |  max(write_size, 8) bytes
+//! | boot error      |  max(write_size, 8) bytes
+//! | invalidated     |  max(write_size, 8) bytes
+//! | validated       |  40 bytes, rounded up to the write size
+//! +-----------------+
+//! | swap status     |  see `StatusLayout`
+//! +-----------------+
+//!
+//! The bootloader's swap status is kept below the trailer (`trailer_size`
+//! gives its size), so the application can confirm an image while a status
+//! is recorded in the same slot, and the other way round.
+//!
+//! A flag is set when its first byte is 0x01, and unset when erased.
+//!
+//! The boot error is a little endian 16-bit code (see `ErrorCode`), two
+//! reserved bytes, and the 32-bit offset in the slot where the failure was
+//! found.  It is written when an upgrade is rejected, and stays until the slot
+//! is erased, such as by the next upload.
+//!
+//! The last two fields cache the validation of a confirmed image, see
+//! `validate_cached`.
+
+use core::cell::RefCell;
+
+use storage::Flash;
+
+use crate::status::{upgrade_requested, MAGIC};
+use crate::{debug, error, event, info, Error, EventCode, Image, ImageVersion, Result};
+
+/// Value of a set flag.
+pub(crate) const FLAG_SET: u8 = 0x01;
+
+/// Largest write size supported for a flag.
+const MAX_FLAG_WRITE: usize = 512;
+
+/// The validated record: the image's SHA256, its full size, and a marker.
+pub(crate) const VALIDATED_LEN: usize = 40;
+
+/// The kind of swap the bootloader will do on the next boot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SwapType {
+    /// Nothing to do, boot the primary slot.
+    None,
+    /// Swap in the upgrade image, and revert on the next boot unless the new
+    /// image confirms itself.
+    Test,
+    /// Swap in the upgrade image, permanently.
+    Perm,
+    /// The running image was a test that was never confirmed, swap back.
+    Revert,
+}
+
+/// The state of a single trailer flag.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flag {
+    Set,
+    Unset,
+    /// Neither set nor erased.
+    Bad,
+}
+
+/// `ErrorCode::Flash`.
+pub const ERROR_FLASH: u16 = 0x0001;
+/// `ErrorCode::InvalidImage`.
+pub const ERROR_INVALID_IMAGE: u16 = 0x0002;
+/// `ErrorCode::CannotUpgrade`.
+pub const ERROR_CANNOT_UPGRADE: u16 = 0x0003;
+/// `ErrorCode::FlashNotAligned`.
+pub const ERROR_FLASH_NOT_ALIGNED: u16 = 0x0101;
+/// `ErrorCode::FlashOutOfBounds`.
+pub const ERROR_FLASH_OUT_OF_BOUNDS: u16 = 0x0201;
+/// `ErrorCode::FlashNotWritten`.
+pub const ERROR_FLASH_NOT_WRITTEN: u16 = 0x0301;
+/// `ErrorCode::FlashNotErased`.
+pub const ERROR_FLASH_NOT_ERASED: u16 = 0x0401;
+/// `ErrorCode::FlashFailed`.
+pub const ERROR_FLASH_FAILED: u16 = 0x0501;
+
+/// Why the bootloader rejected a slot.
+///
+/// Each has a numeric code, also exported as a constant, for applications and
+/// tools that only have the number, such as from the boot error record, the
+/// shared block, or an SMP response.  These are fixed: a code is never reused
+/// or renumbered, and new ones are only added.
+///
+/// The low byte is the kind of error, as in `Error`, and the high byte, when
+/// not zero, gives more detail within it.  So a reader that only knows the
+/// kinds can still use `code & 0xff`.
+///
+/// | Code   | Meaning                                         |
+/// |--------|-------------------------------------------------|
+/// | 0x0001 | Flash failure, of no recorded kind              |
+/// | 0x0002 | The image failed to validate                    |
+/// | 0x0003 | The image doesn't fit, or can't be swapped      |
+/// | 0x0101 | Flash: access not aligned to the write size     |
+/// | 0x0201 | Flash: access beyond the end of the device      |
+/// | 0x0301 | Flash: read of erased flash that can't be read  |
+/// | 0x0401 | Flash: write to flash that isn't erased         |
+/// | 0x0501 | Flash: the device reported a failure            |
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// Reading, writing, or erasing the flash failed.
+    Flash = ERROR_FLASH,
+    /// The image failed to validate.
+    InvalidImage = ERROR_INVALID_IMAGE,
+    /// The image doesn't fit, or the slots can't be swapped.
+    CannotUpgrade = ERROR_CANNOT_UPGRADE,
+    /// A flash access wasn't aligned to the write size.
+    FlashNotAligned = ERROR_FLASH_NOT_ALIGNED,
+    /// A flash access went beyond the end of the device.
+    FlashOutOfBounds = ERROR_FLASH_OUT_OF_BOUNDS,
+    /// Erased flash was read, on a device where that can't be done.
+    FlashNotWritten = ERROR_FLASH_NOT_WRITTEN,
+    /// Flash was written without being erased.
+    FlashNotErased = ERROR_FLASH_NOT_ERASED,
+    /// The flash device reported a failure.
+    FlashFailed = ERROR_FLASH_FAILED,
+}
+
+impl ErrorCode {
+    /// The error with the numeric `code`, if it is one of those listed.
+    pub fn from_u16(code: u16) -> Option<ErrorCode> {
+        match code {
+            ERROR_FLASH => Some(ErrorCode::Flash),
+            ERROR_INVALID_IMAGE => Some(ErrorCode::InvalidImage),
+            ERROR_CANNOT_UPGRADE => Some(ErrorCode::CannotUpgrade),
+            ERROR_FLASH_NOT_ALIGNED => Some(ErrorCode::FlashNotAligned),
+            ERROR_FLASH_OUT_OF_BOUNDS => Some(ErrorCode::FlashOutOfBounds),
+            ERROR_FLASH_NOT_WRITTEN => Some(ErrorCode::FlashNotWritten),
+            ERROR_FLASH_NOT_ERASED => Some(ErrorCode::FlashNotErased),
+            ERROR_FLASH_FAILED => Some(ErrorCode::FlashFailed),
+            _ => None,
+        }
+    }
+
+    /// The kind of error, without the detail: one of `Flash`,
+    /// `InvalidImage`, or `CannotUpgrade`.
+    pub fn kind(self) -> ErrorCode {
+        ErrorCode::from_u16(self as u16 & 0xff).unwrap_or(ErrorCode::Flash)
+    }
+}
+
+impl From<&Error> for ErrorCode {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::Flash(storage::Error::NotAligned) => ErrorCode::FlashNotAligned,
+            Error::Flash(storage::Error::OutOfBounds) => ErrorCode::FlashOutOfBounds,
+            Error::Flash(storage::Error::NotWritten) => ErrorCode::FlashNotWritten,
+            Error::Flash(storage::Error::NotErased) => ErrorCode::FlashNotErased,
+            Error::Flash(storage::Error::Failed) => ErrorCode::FlashFailed,
+            Error::InvalidImage => ErrorCode::InvalidImage,
+            Error::CannotUpgrade => ErrorCode::CannotUpgrade,
+        }
+    }
+}
+
+/// The recorded reason for the last rejection of a slot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BootError {
+    pub code: ErrorCode,
+    /// Where in the slot the failure was found, such as the sector being
+    /// copied.  Zero when the failure isn't tied to a place, such as a bad
+    /// signature.
+    pub offset: u32,
+}
+
+impl BootError {
+    pub fn new(err: &Error, offset: usize) -> BootError {
+        BootError { code: err.into(), offset: offset as u32 }
+    }
+}
+
+/// A summary of the upgrade state, from the application's point of view.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BootState {
+    pub swap_type: SwapType,
+    /// The image in the primary slot has been confirmed.
+    pub confirmed: bool,
+    pub primary_version: Option<ImageVersion>,
+    pub secondary_version: Option<ImageVersion>,
+}
+
+/// Each flag takes a whole write unit.
+pub(crate) fn flag_size<F: Flash>(flash: &F) -> usize {
+    flash.write_size().max(8)
+}
+
+pub(crate) fn image_ok_offset<F: Flash>(flash: &F) -> usize {
+    let magic = flash.write_size().max(MAGIC.len());
+    flash.capacity() - magic - flag_size(flash)
+}
+
+pub(crate) fn copy_done_offset<F: Flash>(flash: &F) -> usize {
+    image_ok_offset(flash) - flag_size(flash)
+}
+
+/// The boot error record, in the trailer, so above any swap status.
+pub(crate) fn boot_error_offset<F: Flash>(flash: &F) -> usize {
+    copy_done_offset(flash) - flag_size(flash)
+}
+
+pub(crate) fn invalidated_offset<F: Flash>(flash: &F) -> usize {
+    boot_error_offset(flash) - flag_size(flash)
+}
+
+/// The validated record is the lowest field, so this is also where the
+/// trailer starts.
+pub(crate) fn validated_offset<F: Flash>(flash: &F) -> usize {
+    flash.capacity() - trailer_size(flash.write_size())
+}
+
+/// The size of the trailer of a slot written `write_size` bytes at a time,
+/// from the validated record to the end of the slot.
+pub const fn trailer_size(write_size: usize) -> usize {
+    let magic = if write_size > MAGIC.len() { write_size } else { MAGIC.len() };
+    let flag = if write_size > 8 { write_size } else { 8 };
+    magic + 4 * flag + VALIDATED_LEN.div_ceil(write_size) * write_size
+}
+
+pub(crate) fn validated_size<F: Flash>(flash: &F) -> usize {
+    let write = flash.write_size();
+    VALIDATED_LEN.div_ceil(write) * write
+}
+
+pub(crate) fn read_flag<F: Flash>(flash: &mut F, offset: usize) -> Result<Flag> {
+    let size = flash.read_size();
+    if size > MAX_FLAG_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0u8; MAX_FLAG_WRITE];
+    match flash.read(offset, &mut buf[..size]) {
+        Ok(()) => (),
+        // Some devices can't read erased flash.
+        Err(storage::Error::NotWritten) => return Ok(Flag::Unset),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(match buf[0] {
+        FLAG_SET => Flag::Set,
+        0xff => Flag::Unset,
+        _ => Flag::Bad,
+    })
+}
+
+pub(crate) fn write_flag<F: Flash>(flash: &mut F, offset: usize) -> Result<()> {
+    let size = flag_size(flash);
+    if size > MAX_FLAG_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0xffu8; MAX_FLAG_WRITE];
+    buf[0] = FLAG_SET;
+    flash.write(offset, &buf[..size])?;
+    Ok(())
+}
+
+/// Read the image ok flag of a slot.
+pub fn image_ok<F: Flash>(flash: &mut F) -> Result<Flag> {
+    let offset = image_ok_offset(flash);
+    read_flag(flash, offset)
+}
+
+/// Read the copy done flag of a slot.
+pub fn copy_done<F: Flash>(flash: &mut F) -> Result<Flag> {
+    let offset = copy_done_offset(flash);
+    read_flag(flash, offset)
+}
+
+/// Record why the bootloader rejected this slot, so the application can find
+/// out.  Only the first failure since the slot was erased is kept, later ones
+/// are ignored.
+pub fn record_boot_error<F: Flash>(flash: &mut F, err: &BootError) -> Result<()> {
+    if last_boot_error(flash)?.is_some() {
+        return Ok(());
+    }
+    let size = flag_size(flash);
+    if size > MAX_FLAG_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    error!("Rejected slot: {:?} at 0x{:x}", err.code, err.offset);
+    event(EventCode::BootError, err.code as u32);
+    let mut buf = [0xffu8; MAX_FLAG_WRITE];
+    buf[..2].copy_from_slice(&(err.code as u16).to_le_bytes());
+    buf[4..8].copy_from_slice(&err.offset.to_le_bytes());
+    let offset = boot_error_offset(flash);
+    flash.write(offset, &buf[..size])?;
+    Ok(())
+}
+
+/// The recorded reason the bootloader last rejected this slot, if any.  A
+/// record that can't be understood is an error.
+pub fn last_boot_error<F: Flash>(flash: &mut F) -> Result<Option<BootError>> {
+    let size = flash.read_size().max(8);
+    if size > MAX_FLAG_WRITE {
+        return Err(Error::CannotUpgrade);
+    }
+    let mut buf = [0u8; MAX_FLAG_WRITE];
+    let offset = boot_error_offset(flash);
+    match flash.read(offset, &mut buf[..size]) {
+        Ok(()) => (),
+        Err(storage::Error::NotWritten) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let code = u16::from_le_bytes([buf[0], buf[1]]);
+    if code == 0xffff {
+        return Ok(None);
+    }
+    let code = ErrorCode::from_u16(code).ok_or(Error::CannotUpgrade)?;
+    let offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    Ok(Some(BootError { code, offset }))
+}
+
+/// Confirm the image in this slot.  Called by the application on the primary
+/// slot once it is happy with a test image, so it won't be reverted.  Calling
+/// it on the upgrade slot after `request_upgrade` makes the upgrade permanent.
+/// Confirming an already confirmed image does nothing.
+pub fn confirm<F: Flash>(flash: &mut F) -> Result<()> {
+    match image_ok(flash)? {
+        Flag::Set => Ok(()),
+        Flag::Unset => {
+            info!("Confirming image");
+            event(EventCode::Confirmed, 0);
+            let offset = image_ok_offset(flash);
+            write_flag(flash, offset)
+        }
+        Flag::Bad => Err(Error::CannotUpgrade),
+    }
+}
+
+/// Record that a swap into this slot has finished.  This is done by the
+/// bootloader, on the primary slot.
+pub fn set_copy_done<F: Flash>(flash: &mut F) -> Result<()> {
+    info!("Swap complete");
+    event(EventCode::SwapDone, 0);
+    let offset = copy_done_offset(flash);
+    write_flag(flash, offset)
+}
+
+/// Is the image in the primary slot confirmed?
+pub fn is_confirmed<F: Flash>(primary: &mut F) -> Result<bool> {
+    Ok(image_ok(primary)? == Flag::Set)
+}
+
+/// Determine what the bootloader will do on the next boot, from the trailers
+/// of the two slots.
+pub fn swap_type<F: Flash>(primary: &mut F, secondary: &mut F) -> Result<SwapType> {
+    let kind = if upgrade_requested(secondary)? {
+        match image_ok(secondary)? {
+            Flag::Set => SwapType::Perm,
+            _ => SwapType::Test,
+        }
+    } else if upgrade_requested(primary)? &&
+        copy_done(primary)? == Flag::Set &&
+        image_ok(primary)? == Flag::Unset
+    {
+        SwapType::Revert
+    } else {
+        SwapType::None
+    };
+    debug!("Swap type: {:?}", kind);
+    event(EventCode::SwapType, kind as u32);
+    Ok(kind)
+}
+
+/// Gather the whole state.  A slot without a readable image has no version.
+pub fn boot_state<F: Flash>(primary: &RefCell<F>, secondary: &RefCell<F>) -> Result<BootState> {
+    let swap_type = swap_type(&mut *primary.borrow_mut(), &mut *secondary.borrow_mut())?;
+    let confirmed = is_confirmed(&mut *primary.borrow_mut())?;
+    let primary_version = Image::from_flash(primary).ok().map(|image| image.version());
+    let secondary_version = Image::from_flash(secondary).ok().map(|image| image.version());
+    Ok(BootState { swap_type, confirmed, primary_version, secondary_version })
+}
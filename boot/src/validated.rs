@@ -0,0 +1,203 @@
+//! Cached validation
+//!
+//! Hashing a large image on every boot takes a while, especially from slow
+//! flash.  Once an image is confirmed, it doesn't change until the next
+//! upgrade, so the bootloader can record in the trailer that it has validated
+//! it (see `trailer`), and on later boots only check the header and that
+//! record.
+//!
+//! The record holds the image's SHA256 and size, and is only trusted while the
+//! invalidated flag next to it is erased.  Erasing the slot clears both, and
+//! anything that writes to the image while there is a record must set the flag
+//! first, which `GuardedFlash` does.  Once the flag is set, the image is
+//! validated in full on every boot until the slot is erased.
+//!
+//! The record and the flag are part of the trailer, which `StatusLayout`
+//! keeps the swap status below, so a status can be written alongside them.
+
+use core::cell::RefCell;
+
+use storage::Flash;
+
+use crate::trailer::{
+    image_ok, invalidated_offset, read_flag, validated_offset, validated_size, write_flag, Flag,
+    VALIDATED_LEN,
+};
+use crate::wrap::{flash_wrapper, FlashHooks};
+use crate::{ct_eq, debug, error, info, Error, Image, Result};
+
+/// Marks a written validated record.
+const VALIDATED_MAGIC: u32 = 0x5641_4c44;
+
+/// Largest record supported, after rounding up to the write size.
+const MAX_RECORD: usize = 512;
+
+/// How `validate_cached` decided the image was valid.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Validation {
+    /// The image was validated in full.
+    Full,
+    /// The image was validated on an earlier boot, and hasn't changed.
+    Cached,
+}
+
+/// Validate the image in a slot, with `validate`, unless an earlier boot has
+/// already done so.  Only confirmed images are cached, and the record is
+/// written the first time one validates in full.
+pub fn validate_cached<'f, F, V>(slot: &'f RefCell<F>, validate: V) -> Result<Validation>
+    where F: Flash, V: FnOnce(&Image<'f, F>) -> Result<()>,
+{
+    let image = Image::from_flash(slot)?;
+    let confirmed = image_ok(&mut *slot.borrow_mut())? == Flag::Set;
+    if confirmed && is_cached(slot, &image)? {
+        info!("Image {} validated on an earlier boot", image.version());
+        return Ok(Validation::Cached);
+    }
+
+    validate(&image)?;
+    if confirmed {
+        // Not being able to cache the result only costs time on later boots.
+        if let Err(err) = record(slot, &image) {
+            error!("Unable to record validation: {:?}", err);
+        }
+    }
+    Ok(Validation::Full)
+}
+
+/// Forget any cached validation of the image in this slot.  Without a record,
+/// there is nothing to forget, and the flag is left erased.
+pub fn invalidate<F: Flash>(flash: &mut F) -> Result<()> {
+    let offset = invalidated_offset(flash);
+    let mut buf = [0u8; MAX_RECORD];
+    if read_flag(flash, offset)? != Flag::Unset || read_record(flash, &mut buf)?.is_none() {
+        return Ok(());
+    }
+    debug!("Invalidating cached validation");
+    write_flag(flash, offset)
+}
+
+/// Read the validated record, if there is one.
+fn read_record<F: Flash>(flash: &mut F, buf: &mut [u8; MAX_RECORD]) -> Result<Option<usize>> {
+    let size = validated_size(flash);
+    if size > MAX_RECORD || !size.is_multiple_of(flash.read_size()) {
+        return Err(Error::CannotUpgrade);
+    }
+    let offset = validated_offset(flash);
+    match flash.read(offset, &mut buf[..size]) {
+        Ok(()) => (),
+        Err(storage::Error::NotWritten) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if buf[..size].iter().all(|&b| b == 0xff) {
+        return Ok(None);
+    }
+    Ok(Some(size))
+}
+
+fn is_cached<F: Flash>(slot: &RefCell<F>, image: &Image<'_, F>) -> Result<bool> {
+    let mut flash = slot.borrow_mut();
+    let offset = invalidated_offset(&*flash);
+    if read_flag(&mut *flash, offset)? != Flag::Unset {
+        return Ok(false);
+    }
+    let mut buf = [0u8; MAX_RECORD];
+    if read_record(&mut *flash, &mut buf)?.is_none() {
+        return Ok(false);
+    }
+    drop(flash);
+
+    let magic = u32::from_le_bytes([buf[36], buf[37], buf[38], buf[39]]);
+    let size = u32::from_le_bytes([buf[32], buf[33], buf[34], buf[35]]) as usize;
+    Ok(magic == VALIDATED_MAGIC &&
+       size == image.full_image_size() &&
+       ct_eq(&buf[..32], &image.stored_sha256()?))
+}
+
+fn record<F: Flash>(slot: &RefCell<F>, image: &Image<'_, F>) -> Result<()> {
+    let hash = image.stored_sha256()?;
+    let mut flash = slot.borrow_mut();
+    if image.full_image_size() > validated_offset(&*flash) {
+        // The image runs into the trailer, there is no room.
+        return Ok(());
+    }
+    let offset = invalidated_offset(&*flash);
+    let mut buf = [0u8; MAX_RECORD];
+    if read_flag(&mut *flash, offset)? != Flag::Unset || read_record(&mut *flash, &mut buf)?.is_some() {
+        // Already written, possibly for an older image.  Only erasing the
+        // slot allows a new record.
+        return Ok(());
+    }
+
+    let size = validated_size(&*flash);
+    buf[..size].fill(0xff);
+    buf[..32].copy_from_slice(&hash);
+    buf[32..36].copy_from_slice(&(image.full_image_size() as u32).to_le_bytes());
+    buf[36..VALIDATED_LEN].copy_from_slice(&VALIDATED_MAGIC.to_le_bytes());
+    let offset = validated_offset(&*flash);
+    flash.write(offset, &buf[..size])?;
+    debug!("Recorded validation");
+    Ok(())
+}
+
+/// A slot that forgets its cached validation before anything other than the
+/// trailer is written or erased.  Writing the image, such as an upgrade being
+/// copied in, should go through this.
+pub struct GuardedFlash<F> {
+    inner: F,
+    /// There is no record to forget: it has been invalidated, or erased.
+    clear: bool,
+}
+
+impl<F: Flash> GuardedFlash<F> {
+    pub fn new(inner: F) -> Self {
+        GuardedFlash { inner, clear: false }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Invalidate before changing anything below the trailer.
+    fn guard(&mut self, offset: usize) -> storage::Result<()> {
+        if self.clear || offset >= validated_offset(&self.inner) {
+            return Ok(());
+        }
+        invalidate(&mut self.inner).map_err(|err| match err {
+            Error::Flash(err) => err,
+            _ => storage::Error::Failed,
+        })?;
+        self.clear = true;
+        Ok(())
+    }
+}
+
+impl<F: Flash> FlashHooks for GuardedFlash<F> {
+    fn before_erase(&mut self, from: usize, _to: usize) -> storage::Result<()> {
+        self.guard(from)
+    }
+
+    fn after_erase(&mut self, from: usize, to: usize, result: storage::Result<()>) -> storage::Result<()> {
+        result?;
+        let record = validated_offset(&self.inner);
+        if from <= record && to > record {
+            self.clear = true;
+        }
+        Ok(())
+    }
+
+    fn before_write(&mut self, offset: usize) -> storage::Result<()> {
+        self.guard(offset)
+    }
+
+    fn after_write(&mut self, offset: usize, _bytes: &[u8], result: storage::Result<()>) -> storage::Result<()> {
+        result?;
+        // A new record, which later writes must invalidate.
+        if offset == validated_offset(&self.inner) {
+            self.clear = false;
+        }
+        Ok(())
+    }
+}
+
+flash_wrapper!([F] GuardedFlash<F>);
@@ -0,0 +1,81 @@
+//! Verifying writes
+//!
+//! Worn flash can fail to program a cell without the controller noticing, so
+//! a write reports success but leaves different data behind.  The upgrade
+//! code checks the image once it is all copied, but by then an overwrite has
+//! already destroyed the old one.
+//!
+//! Wrapping the destination in a `VerifiedFlash` reads back each write as it
+//! is made, and compares it with what was written.  The copy loops in the
+//! boot code, and in the boards, then stop at the first bad page, with the
+//! source still intact.  It doubles the reads of a copy, so it is up to the
+//! board: the check can be turned off, leaving the wrapper passing writes
+//! straight through, so a board can choose with a constant.
+
+use storage::{Error, Flash, Result};
+
+use crate::wrap::{flash_wrapper, FlashHooks};
+use crate::error;
+
+/// Largest read used to check a write.
+const VERIFY_CHUNK: usize = 128;
+
+/// A flash device that reads back each write.
+pub struct VerifiedFlash<F> {
+    inner: F,
+    verify: bool,
+}
+
+impl<F: Flash> VerifiedFlash<F> {
+    /// Wrap `inner`, checking writes if `verify` is set.
+    pub fn new(inner: F, verify: bool) -> Self {
+        VerifiedFlash { inner, verify }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Check that `bytes` are at `offset`.  A write that doesn't cover whole
+    /// read units is checked with the units around it.
+    fn check(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        let read_size = self.inner.read_size();
+        if read_size > VERIFY_CHUNK {
+            return Err(Error::NotAligned);
+        }
+        let chunk = VERIFY_CHUNK / read_size * read_size;
+        let mut buf = [0u8; VERIFY_CHUNK];
+        let end = offset + bytes.len();
+        let mut pos = offset / read_size * read_size;
+        while pos < end {
+            let len = chunk.min(end.next_multiple_of(read_size) - pos);
+            // Some devices can't read erased flash, which is what a write
+            // that didn't take at all leaves.
+            self.inner.read(pos, &mut buf[..len]).map_err(|e| match e {
+                Error::NotWritten => Error::Failed,
+                e => e,
+            })?;
+            let from = offset.max(pos);
+            let to = end.min(pos + len);
+            if buf[from - pos..to - pos] != bytes[from - offset..to - offset] {
+                error!("Write at 0x{:x} didn't take", from);
+                return Err(Error::Failed);
+            }
+            pos += len;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Flash> FlashHooks for VerifiedFlash<F> {
+    fn after_write(&mut self, offset: usize, bytes: &[u8], result: Result<()>) -> Result<()> {
+        result?;
+        if self.verify {
+            self.check(offset, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+flash_wrapper!([F] VerifiedFlash<F>);
@@ -0,0 +1,88 @@
+//! Waking from standby
+//!
+//! On many parts, waking from standby or hibernate is a reset, so the
+//! bootloader runs again, and validating the image adds its full time to every
+//! wake.  Nothing can have changed the image while the device slept, so the
+//! bootloader can leave a marker in memory that is retained through standby,
+//! such as a backup register (see `Retained`), once it has validated the
+//! image.  On a wake, with the marker intact, it skips validation and jumps
+//! straight to the image.
+//!
+//! The marker is tied to the image, by the hash recorded in it, so an image
+//! replaced some other way isn't taken as validated.  Anything that writes to
+//! the primary slot must clear the marker first, which `WakeGuard` does, and
+//! only the board knows the reset cause, so it says whether this boot is a
+//! wake.  A power cycle, or any other reset, validates in full.
+
+use core::cell::RefCell;
+
+use storage::ReadFlash;
+
+use crate::wrap::{flash_wrapper, FlashHooks};
+use crate::{info, Image, Result, Retained};
+
+/// Mixed into the marker, so that a word that happens to hold part of the
+/// hash isn't taken as one.
+pub const WAKE_MAGIC: u32 = 0x7761_6b65;
+
+/// The marker for an image.
+fn marker<F: ReadFlash>(image: &Image<'_, F>) -> Result<u32> {
+    let hash = image.stored_sha256()?;
+    Ok(u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]) ^ WAKE_MAGIC)
+}
+
+/// Record that `image` has been validated, so a wake from standby can skip
+/// validating it again.
+pub fn set_wake_marker<R: Retained, F: ReadFlash>(word: &mut R, image: &Image<'_, F>) -> Result<()> {
+    word.write(marker(image)?);
+    Ok(())
+}
+
+/// Forget that the image was validated.
+pub fn clear_wake_marker<R: Retained>(word: &mut R) {
+    word.write(0);
+}
+
+/// Can validation of `image` be skipped?  Only if this boot is a wake from
+/// standby, `woke`, and the marker left for this image is intact.  Otherwise,
+/// the marker is cleared, to be set again once the image validates.
+pub fn wake_fast_path<R: Retained, F: ReadFlash>(word: &mut R, woke: bool, image: &Image<'_, F>) -> bool {
+    let valid = woke && matches!(marker(image), Ok(expected) if word.read() == expected);
+    if valid {
+        info!("Woke from standby, image {} already validated", image.version());
+    } else {
+        clear_wake_marker(word);
+    }
+    valid
+}
+
+/// A primary slot that clears the wake marker before every write or erase.
+pub struct WakeGuard<'w, F, R> {
+    inner: F,
+    word: &'w RefCell<R>,
+}
+
+impl<'w, F, R: Retained> WakeGuard<'w, F, R> {
+    pub fn new(inner: F, word: &'w RefCell<R>) -> Self {
+        WakeGuard { inner, word }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<'w, F, R: Retained> FlashHooks for WakeGuard<'w, F, R> {
+    fn before_erase(&mut self, _from: usize, _to: usize) -> storage::Result<()> {
+        clear_wake_marker(&mut *self.word.borrow_mut());
+        Ok(())
+    }
+
+    fn before_write(&mut self, _offset: usize) -> storage::Result<()> {
+        clear_wake_marker(&mut *self.word.borrow_mut());
+        Ok(())
+    }
+}
+
+flash_wrapper!(['w, F, R] WakeGuard<'w, F, R>);
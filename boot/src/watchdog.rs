@@ -0,0 +1,65 @@
+//! Watchdog support
+//!
+//! Validation and upgrades can take long enough that a watchdog will expire.
+//! Rather than thread the watchdog through all of the code, the flash devices
+//! can be wrapped in a `WatchedFlash`, which feeds the watchdog on each
+//! operation.  Every long running operation in the boot code is made of many
+//! flash operations, so this is frequent enough.
+
+use storage::Result;
+
+use crate::wrap::{flash_wrapper, FlashHooks};
+
+/// A watchdog that must be fed periodically.  This takes `&self` because the
+/// watchdog is shared by all of the flash devices.
+pub trait Watchdog {
+    fn feed(&self);
+}
+
+/// A watchdog that does nothing, for targets without one.
+pub struct NoWatchdog;
+
+impl Watchdog for NoWatchdog {
+    fn feed(&self) {}
+}
+
+/// A flash device that feeds a watchdog around each operation.
+pub struct WatchedFlash<'w, F, W> {
+    inner: F,
+    watchdog: &'w W,
+}
+
+impl<'w, F, W: Watchdog> WatchedFlash<'w, F, W> {
+    pub fn new(inner: F, watchdog: &'w W) -> Self {
+        WatchedFlash { inner, watchdog }
+    }
+
+    /// Recover the underlying flash device.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<'w, F, W: Watchdog> FlashHooks for WatchedFlash<'w, F, W> {
+    fn before_read(&mut self) {
+        self.watchdog.feed();
+    }
+
+    // Erases can be slow, so feed on both sides.
+    fn before_erase(&mut self, _from: usize, _to: usize) -> Result<()> {
+        self.watchdog.feed();
+        Ok(())
+    }
+
+    fn after_erase(&mut self, _from: usize, _to: usize, result: Result<()>) -> Result<()> {
+        self.watchdog.feed();
+        result
+    }
+
+    fn before_write(&mut self, _offset: usize) -> Result<()> {
+        self.watchdog.feed();
+        Ok(())
+    }
+}
+
+flash_wrapper!(['w, F, W] WatchedFlash<'w, F, W>);
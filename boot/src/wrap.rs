@@ -0,0 +1,104 @@
+//! Flash wrappers
+//!
+//! Several wrappers sit between the boot code and a flash device, each doing
+//! something around its operations, such as feeding a watchdog or checking a
+//! write, and passing everything else straight through.  A wrapper implements
+//! `FlashHooks` for the operations it cares about, and `flash_wrapper!`
+//! writes the rest.
+
+use storage::Result;
+
+/// What a wrapper does around the operations of the device it wraps.  Each
+/// hook does nothing by default.  The `after` hooks are given the result of
+/// the operation, and return the result to pass on.
+pub(crate) trait FlashHooks {
+    fn before_read(&mut self) {}
+
+    fn before_erase(&mut self, _from: usize, _to: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn after_erase(&mut self, _from: usize, _to: usize, result: Result<()>) -> Result<()> {
+        result
+    }
+
+    fn before_write(&mut self, _offset: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn after_write(&mut self, _offset: usize, _bytes: &[u8], result: Result<()>) -> Result<()> {
+        result
+    }
+}
+
+/// Implement `ReadFlash`, `Flash` and `MappedFlash` for a wrapper, calling
+/// its `FlashHooks` around each operation.  The wrapper keeps the device in a
+/// field `inner`, of the type parameter `F`, which is given first, in
+/// brackets, with the other parameters:
+///
+/// ```ignore
+/// flash_wrapper!(['w, F, W] WatchedFlash<'w, F, W>);
+/// ```
+macro_rules! flash_wrapper {
+    ([$($gen:tt)*] $ty:ty) => {
+        impl<$($gen)*> storage::ReadFlash for $ty
+        where
+            F: storage::ReadFlash,
+            Self: $crate::wrap::FlashHooks,
+        {
+            fn read_size(&self) -> usize {
+                self.inner.read_size()
+            }
+
+            fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+                $crate::wrap::FlashHooks::before_read(self);
+                self.inner.read(offset, bytes)
+            }
+
+            fn capacity(&self) -> usize {
+                self.inner.capacity()
+            }
+
+            fn memory_address(&self) -> Option<usize> {
+                self.inner.memory_address()
+            }
+        }
+
+        impl<$($gen)*> storage::Flash for $ty
+        where
+            F: storage::Flash,
+            Self: $crate::wrap::FlashHooks,
+        {
+            fn write_size(&self) -> usize {
+                self.inner.write_size()
+            }
+
+            fn erase_size(&self) -> usize {
+                self.inner.erase_size()
+            }
+
+            fn erase(&mut self, from: usize, to: usize) -> storage::Result<()> {
+                $crate::wrap::FlashHooks::before_erase(self, from, to)?;
+                let result = self.inner.erase(from, to);
+                $crate::wrap::FlashHooks::after_erase(self, from, to, result)
+            }
+
+            fn write(&mut self, offset: usize, bytes: &[u8]) -> storage::Result<()> {
+                $crate::wrap::FlashHooks::before_write(self, offset)?;
+                let result = self.inner.write(offset, bytes);
+                $crate::wrap::FlashHooks::after_write(self, offset, bytes, result)
+            }
+        }
+
+        impl<$($gen)*> $crate::MappedFlash for $ty
+        where
+            F: $crate::MappedFlash,
+        {
+            fn get_base(&self) -> usize {
+                self.inner.get_base()
+            }
+        }
+    };
+}
+
+pub(crate) use flash_wrapper;
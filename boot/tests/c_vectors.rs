@@ -0,0 +1,179 @@
+// Golden vectors, checking decisions against C MCUboot's.
+//
+// The vectors are listed, with what C MCUboot decides for each, in
+// `data/c-vectors/vectors.txt`.  The images are signed by imgtool (see
+// `data/gen.sh`), and the trailers are laid out as C bootutil writes them
+// (see `data/c-vectors/gen.py`).
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use boot::{boot_state, migrate_c_trailer, Image, ImageVersion, SoftCrypto, SwapType};
+use sha2::{Digest, Sha256};
+use simflash::styles::AreaLayout;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+/// The slots the trailer vectors are placed in, the size the samples were
+/// signed for.
+static SLOT: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 8,
+    erase_size: 4096,
+    sectors: 32,
+};
+
+#[derive(Debug)]
+enum Trust {
+    Hash,
+    Key,
+    KeyHash,
+}
+
+#[derive(Debug)]
+enum Vector {
+    Image { file: String, trust: Trust, accept: bool, flip: Option<isize> },
+    Slots { primary: Option<String>, secondary: Option<String>, swap_type: SwapType, confirmed: bool },
+}
+
+fn data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("data")
+}
+
+fn read_data(file: &str) -> Vec<u8> {
+    let path = data_dir().join(file);
+    fs::read(&path).unwrap_or_else(|e| panic!("Unable to read {}: {}", path.display(), e))
+}
+
+fn bad(n: usize, line: &str) -> ! {
+    panic!("vectors.txt:{}: bad line: {:?}", n + 1, line);
+}
+
+/// Load the list of vectors.
+fn load() -> Vec<Vector> {
+    let text = fs::read_to_string(data_dir().join("c-vectors/vectors.txt")).unwrap();
+    let mut vectors = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let vector = match fields[..] {
+            [] => continue,
+            ["image", file, trust, decision, ref rest @ ..] => {
+                let trust = match trust {
+                    "hash" => Trust::Hash,
+                    "key" => Trust::Key,
+                    "key-hash" => Trust::KeyHash,
+                    _ => bad(n, line),
+                };
+                let accept = match decision {
+                    "accept" => true,
+                    "reject" => false,
+                    _ => bad(n, line),
+                };
+                let flip = match rest {
+                    [] => None,
+                    ["flip", offset] => Some(offset.parse().unwrap_or_else(|_| bad(n, line))),
+                    _ => bad(n, line),
+                };
+                Vector::Image { file: file.to_string(), trust, accept, flip }
+            }
+            ["slots", primary, secondary, swap_type, confirmed] => {
+                let trailer = |name: &str| if name == "-" { None } else { Some(name.to_string()) };
+                let swap_type = match swap_type {
+                    "none" => SwapType::None,
+                    "test" => SwapType::Test,
+                    "perm" => SwapType::Perm,
+                    "revert" => SwapType::Revert,
+                    _ => bad(n, line),
+                };
+                Vector::Slots {
+                    primary: trailer(primary),
+                    secondary: trailer(secondary),
+                    swap_type,
+                    confirmed: confirmed.parse().unwrap_or_else(|_| bad(n, line)),
+                }
+            }
+            _ => bad(n, line),
+        };
+        vectors.push(vector);
+    }
+    vectors
+}
+
+fn validate(data: &[u8], trust: &Trust) -> boot::Result<()> {
+    let key = read_data("ecdsa-p256-pub.der");
+    let mut flash = SLOT.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    let image = Image::from_flash(&flash)?;
+    match trust {
+        Trust::Hash => image.validate(),
+        Trust::Key => image.validate_signed(&mut SoftCrypto::new(), &key),
+        Trust::KeyHash => {
+            let hash: [u8; 32] = Sha256::digest(&key).into();
+            image.validate_key_hash(&mut SoftCrypto::new(), &hash)
+        }
+    }
+}
+
+/// A slot holding the sample image, ending with the given trailer.
+fn slot(trailer: &Option<String>) -> RefCell<SimFlash> {
+    let mut flash = SLOT.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(&read_data("sample-signed.bin"), 0).unwrap();
+    if let Some(trailer) = trailer {
+        // Only the written parts: erased flash can't be written over.
+        let tail = read_data(trailer);
+        let base = size - tail.len();
+        for (pos, chunk) in tail.chunks(SLOT.write_size).enumerate() {
+            if chunk.iter().any(|&b| b != 0xff) {
+                flash.write(base + pos * SLOT.write_size, chunk).unwrap();
+            }
+        }
+    }
+    RefCell::new(flash)
+}
+
+#[test]
+fn images() {
+    let mut count = 0;
+    for vector in load() {
+        let Vector::Image { file, trust, accept, flip } = vector else { continue };
+        let mut data = read_data(&file);
+        if let Some(offset) = flip {
+            let offset = if offset < 0 { data.len() - offset.unsigned_abs() } else { offset as usize };
+            data[offset] ^= 0xff;
+        }
+        let result = validate(&data, &trust);
+        assert_eq!(result.is_ok(), accept, "{} {:?} flip {:?}: {:?}", file, trust, flip, result);
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn trailers() {
+    let version = Some(ImageVersion { major: 0, minor: 1, revision: 0, build_num: 0 });
+    let mut count = 0;
+    for vector in load() {
+        let Vector::Slots { primary, secondary, swap_type, confirmed } = vector else { continue };
+        let name = format!("{:?} {:?}", primary, secondary);
+        let (primary, secondary) = (slot(&primary), slot(&secondary));
+        migrate_c_trailer(&primary).unwrap();
+        migrate_c_trailer(&secondary).unwrap();
+
+        let state = boot_state(&primary, &secondary).unwrap();
+        assert_eq!(state.swap_type, swap_type, "{}", name);
+        assert_eq!(state.confirmed, confirmed, "{}", name);
+
+        // Neither image is disturbed.
+        assert_eq!(state.primary_version, version, "{}", name);
+        assert_eq!(state.secondary_version, version, "{}", name);
+        Image::from_flash(&primary).unwrap().validate().unwrap();
+        Image::from_flash(&secondary).unwrap().validate().unwrap();
+        count += 1;
+    }
+    assert!(count > 0);
+}
@@ -0,0 +1,50 @@
+// Flash sharing testing.
+
+use std::cell::RefCell;
+use std::sync::Mutex;
+
+use boot::{FlashCell, Image, SoftCrypto};
+use simflash::SimFlash;
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// Flash behind a mutex, as a target sharing it between tasks would have.
+struct Locked(Mutex<SimFlash>);
+
+impl FlashCell for Locked {
+    type Flash = SimFlash;
+
+    fn with<R>(&self, f: impl FnOnce(&mut SimFlash) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+fn flash() -> SimFlash {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(SAMPLE, 0).unwrap();
+    flash
+}
+
+#[test]
+fn other_cells() {
+    let locked = Locked(Mutex::new(flash()));
+    let image = Image::from_flash(&locked).unwrap();
+    image.validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+
+    // The same image, through a RefCell, reads the same.
+    let shared = RefCell::new(flash());
+    let other = Image::from_flash(&shared).unwrap();
+    assert_eq!(image.version(), other.version());
+    assert_eq!(image.full_image_size(), other.full_image_size());
+    assert_eq!(image.stored_sha256().unwrap(), other.stored_sha256().unwrap());
+    let kinds: Vec<u16> = image.tlvs().unwrap().map(|t| t.unwrap().kind()).collect();
+    let other_kinds: Vec<u16> = other.tlvs().unwrap().map(|t| t.unwrap().kind()).collect();
+    assert_eq!(kinds, other_kinds);
+
+    // Anything the image reads goes through the cell.
+    let mut bad = SAMPLE.to_vec();
+    bad[300] ^= 1;
+    locked.with(|flash| flash.install(&bad, 0)).unwrap();
+    assert!(image.validate_signed(&mut SoftCrypto::new(), KEY).is_err());
+}
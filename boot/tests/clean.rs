@@ -0,0 +1,156 @@
+// Checking the unused part of a slot is erased.
+
+use std::cell::RefCell;
+
+use boot::{
+    clean_secondary, confirm, confirm_upgrade, erase_remnants, find_remnant, is_confirmed, request_upgrade,
+    upgrade_requested, Image, SecondaryCleanup, SlotInfo, SoftCrypto, Status, StatusLayout, StatusRead,
+    StatusStyle,
+};
+use simflash::styles::{AreaLayout, K64_MAIN, LPC_MAIN};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// A slot that held a larger image, with the sample installed over it, and
+/// confirmed.
+fn upgraded(layout: &AreaLayout) -> RefCell<SimFlash> {
+    let mut flash = layout.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(&[0x5a; 120 * 1024], 0).unwrap();
+    flash.install(IMAGE, 0).unwrap();
+    request_upgrade(&mut flash).unwrap();
+    confirm(&mut flash).unwrap();
+    RefCell::new(flash)
+}
+
+/// The status layout of a slot holding the sample, swapping with another.
+fn status(slot: &RefCell<SimFlash>) -> StatusLayout {
+    let info = SlotInfo::from_data(IMAGE.len(), &*slot.borrow());
+    info.status_layout(&SlotInfo::from_data(IMAGE.len(), &*slot.borrow())).unwrap()
+}
+
+#[test]
+fn remnants() {
+    for layout in [&K64_MAIN, &LPC_MAIN] {
+        let slot = upgraded(layout);
+        // Installing erased the sector holding the end of the image, but not
+        // the ones after it.
+        let old = IMAGE.len().next_multiple_of(layout.erase_size);
+        let status = status(&slot);
+        assert_eq!(find_remnant(&slot, &status).unwrap(), Some(old));
+
+        assert_eq!(erase_remnants(&slot, &status).unwrap(), None);
+        assert_eq!(find_remnant(&slot, &status).unwrap(), None);
+
+        // The image and trailer are untouched.
+        assert!(upgrade_requested(&mut *slot.borrow_mut()).unwrap());
+        Image::from_flash(&slot).unwrap().validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    }
+}
+
+#[test]
+fn clean() {
+    let mut flash = K64_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(IMAGE, 0).unwrap();
+    let slot = RefCell::new(flash);
+    let status = status(&slot);
+    assert_eq!(find_remnant(&slot, &status).unwrap(), None);
+    assert_eq!(erase_remnants(&slot, &status).unwrap(), None);
+}
+
+#[test]
+fn shared_sector() {
+    // Data after the image, in the image's last sector, can't be erased
+    // without losing the image, so it is left, and reported.
+    let slot = upgraded(&K64_MAIN);
+    let status = status(&slot);
+    let stray = IMAGE.len().next_multiple_of(8);
+    assert!(stray < IMAGE.len().next_multiple_of(K64_MAIN.erase_size));
+    slot.borrow_mut().write(stray, &[0; 8]).unwrap();
+
+    assert_eq!(erase_remnants(&slot, &status).unwrap(), Some(stray));
+    assert_eq!(find_remnant(&slot, &status).unwrap(), Some(stray));
+    Image::from_flash(&slot).unwrap().validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+}
+
+#[test]
+fn status_kept() {
+    // The status pages, and in paged mode the sectors below the trailer,
+    // aren't remnants.
+    for layout in [&K64_MAIN, &LPC_MAIN] {
+        let slot = upgraded(layout);
+        let status = status(&slot);
+        let written = Status { hash_seed: 0x1234, ..Status::default() };
+        for _ in 0..status.pages {
+            status.write(&mut *slot.borrow_mut(), &written).unwrap();
+        }
+        if status.style == StatusStyle::Paged {
+            assert!(status.trailer > layout.erase_size);
+        }
+
+        assert_eq!(erase_remnants(&slot, &status).unwrap(), None);
+        let read = status.read(&mut *slot.borrow_mut()).unwrap();
+        assert!(matches!(read, StatusRead::Valid(read) if read.hash_seed == 0x1234));
+        assert!(upgrade_requested(&mut *slot.borrow_mut()).unwrap());
+        assert!(is_confirmed(&mut *slot.borrow_mut()).unwrap());
+    }
+}
+
+/// A secondary slot holding the sample, with an upgrade requested.
+fn secondary() -> SimFlash {
+    let mut flash = K64_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(IMAGE, 0).unwrap();
+    request_upgrade(&mut flash).unwrap();
+    flash
+}
+
+#[test]
+fn secondary_cleanup() {
+    let sector = K64_MAIN.erase_size;
+
+    let mut flash = secondary();
+    let before = flash.dump();
+    clean_secondary(&mut flash, SecondaryCleanup::Keep).unwrap();
+    assert_eq!(flash.dump(), before);
+
+    // Consuming leaves neither a header nor a request, but the rest of the
+    // image is still there.
+    let mut flash = secondary();
+    clean_secondary(&mut flash, SecondaryCleanup::Consume).unwrap();
+    assert!(!upgrade_requested(&mut flash).unwrap());
+    let slot = RefCell::new(flash);
+    assert!(Image::from_flash(&slot).is_err());
+    assert_eq!(slot.borrow().dump()[sector..2 * sector], IMAGE[sector..2 * sector]);
+
+    let mut flash = secondary();
+    clean_secondary(&mut flash, SecondaryCleanup::Erase).unwrap();
+    assert!(flash.dump().iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn confirm_and_clean() {
+    let mut primary = K64_MAIN.build().unwrap();
+    let size = primary.capacity();
+    primary.erase(0, size).unwrap();
+    primary.install(IMAGE, 0).unwrap();
+    request_upgrade(&mut primary).unwrap();
+
+    let mut flash = secondary();
+    confirm_upgrade(&mut primary, &mut flash, SecondaryCleanup::Erase).unwrap();
+    assert!(is_confirmed(&mut primary).unwrap());
+    assert!(flash.dump().iter().all(|&b| b == 0xff));
+
+    // Once confirmed, a new download is left alone.
+    let mut flash = secondary();
+    let before = flash.dump();
+    confirm_upgrade(&mut primary, &mut flash, SecondaryCleanup::Erase).unwrap();
+    assert_eq!(flash.dump(), before);
+}
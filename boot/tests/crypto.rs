@@ -0,0 +1,39 @@
+// Crypto backend testing.
+
+use boot::{ct_eq, CryptoBackend, SoftCrypto};
+
+#[test]
+fn soft_sha256() {
+    let mut crypto = SoftCrypto::new();
+
+    // FIPS 180-2 test vector for "abc".
+    let abc = [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea,
+        0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+        0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c,
+        0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+    ];
+    crypto.sha256_start();
+    crypto.sha256_update(b"a");
+    crypto.sha256_update(b"bc");
+    assert_eq!(crypto.sha256_finish(), abc);
+
+    // Starting again discards anything in progress.
+    crypto.sha256_update(b"junk");
+    crypto.sha256_start();
+    crypto.sha256_update(b"abc");
+    assert_eq!(crypto.sha256_finish(), abc);
+}
+
+#[test]
+fn constant_time_eq() {
+    let a = [0xa5u8; 32];
+    assert!(ct_eq(&a, &a));
+    assert!(ct_eq(&[], &[]));
+    for pos in 0..a.len() {
+        let mut b = a;
+        b[pos] ^= 1;
+        assert!(!ct_eq(&a, &b));
+    }
+    assert!(!ct_eq(&a, &a[..31]));
+}
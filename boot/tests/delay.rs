@@ -0,0 +1,56 @@
+// Startup window testing.
+
+use std::cell::Cell;
+
+use boot::{wait_window, Delay, Escape, SerialEscape, Watchdog, Window, ESCAPE_CHAR};
+
+/// Time passes only when asked to.
+#[derive(Default)]
+struct FakeDelay {
+    now: u32,
+}
+
+impl Delay for FakeDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.now += ms;
+    }
+}
+
+#[derive(Default)]
+struct CountingWatchdog(Cell<usize>);
+
+impl Watchdog for CountingWatchdog {
+    fn feed(&self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn elapsed() {
+    let mut delay = FakeDelay::default();
+    let watchdog = CountingWatchdog::default();
+    let mut button = || false;
+    assert_eq!(wait_window(1005, &mut delay, &watchdog, &mut [&mut button]), Window::Elapsed);
+    assert_eq!(delay.now, 1005);
+    assert!(watchdog.0.get() > 100);
+
+    // No window still checks the escapes.
+    let mut delay = FakeDelay::default();
+    let mut pressed = || true;
+    assert_eq!(wait_window(0, &mut delay, &watchdog, &mut [&mut pressed]), Window::Recovery);
+    assert_eq!(delay.now, 0);
+}
+
+#[test]
+fn serial_escape() {
+    let mut delay = FakeDelay::default();
+    let watchdog = CountingWatchdog::default();
+
+    // Other characters are ignored, until the escape arrives.
+    let mut input = b"hello".iter().copied().chain([ESCAPE_CHAR]);
+    let mut serial = SerialEscape(|| input.next());
+    let mut button = || false;
+    let escapes: &mut [&mut dyn Escape] = &mut [&mut button, &mut serial];
+    assert_eq!(wait_window(1000, &mut delay, &watchdog, escapes), Window::Recovery);
+    assert_eq!(delay.now, 50);
+}
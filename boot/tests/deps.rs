@@ -0,0 +1,74 @@
+// Multi-image dependency testing.
+
+use std::cell::RefCell;
+
+use boot::{Image, ImageVersion};
+use simflash::gen::ImageBuilder;
+
+const HEADER_SIZE: usize = 256;
+
+fn version(major: u8, minor: u8, revision: u16) -> ImageVersion {
+    ImageVersion { major, minor, revision, build_num: 0 }
+}
+
+/// Build an image with the given version and dependencies, and just a SHA256.
+fn build(ver: ImageVersion, deps: &[(u8, ImageVersion)]) -> Vec<u8> {
+    let payload: Vec<u8> = (0..4000u32).map(|i| (i * 7) as u8).collect();
+    let mut image = ImageBuilder::default();
+    image.version(ver.major, ver.minor, ver.revision, ver.build_num).payload(&payload);
+    for (id, min) in deps {
+        let mut dep = vec![*id, 0, 0, 0, min.major, min.minor];
+        dep.extend(min.revision.to_le_bytes());
+        dep.extend(min.build_num.to_le_bytes());
+        image.protected(0x40, &dep);
+    }
+    image.build()
+}
+
+fn check(data: &[u8], versions: &[ImageVersion]) -> boot::Result<()> {
+    let mut flash = simflash::styles::STM32H_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    let image = Image::from_flash(&flash)?;
+    image.validate()?;
+    image.check_dependencies(versions)
+}
+
+#[test]
+fn dependencies() {
+    let app = build(version(1, 2, 0), &[(1, version(1, 1, 0))]);
+
+    // Met exactly, and by a newer version.  The build number doesn't matter.
+    check(&app, &[version(1, 2, 0), version(1, 1, 0)]).unwrap();
+    check(&app, &[version(1, 2, 0), version(2, 0, 0)]).unwrap();
+    let mut build_num = version(1, 1, 0);
+    build_num.build_num = 7;
+    check(&app, &[version(1, 2, 0), build_num]).unwrap();
+
+    // Too old, or missing from the set.
+    assert!(check(&app, &[version(1, 2, 0), version(1, 0, 9)]).is_err());
+    assert!(check(&app, &[version(1, 2, 0)]).is_err());
+
+    // No dependencies are always met.
+    let lone = build(version(1, 0, 0), &[]);
+    check(&lone, &[]).unwrap();
+}
+
+#[test]
+fn protected_hashed() {
+    // The protected TLV is covered by the hash.
+    let mut app = build(version(1, 2, 0), &[(1, version(1, 1, 0))]);
+    let pos = HEADER_SIZE + 4000 + 8 + 4;
+    app[pos] ^= 1;
+    assert!(check(&app, &[version(1, 2, 0), version(9, 0, 0)]).is_err());
+}
+
+#[test]
+fn image_version() {
+    let data = build(version(3, 4, 5), &[]);
+    let mut flash = simflash::styles::STM32H_MAIN.build().unwrap();
+    flash.install(&data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    let image = Image::from_flash(&flash).unwrap();
+    assert_eq!(image.version(), version(3, 4, 5));
+}
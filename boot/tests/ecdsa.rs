@@ -0,0 +1,167 @@
+// ECDSA signature testing.
+
+use std::cell::RefCell;
+
+use boot::{ecdsa_public_key, ecdsa_sign, CryptoBackend, Image, SoftCrypto, SoftMul};
+
+// A key, hash and signature generated with the Python cryptography package.
+static KEY: [u8; 65] = [
+    0x04, 0x47, 0x1c, 0x3e, 0x75, 0x8c, 0x49, 0x04, 0x28, 0x5b, 0xba, 0x7e, 0x53, 0x11, 0x8e, 0xd0,
+    0xf5, 0x24, 0xad, 0xeb, 0x07, 0x57, 0xd2, 0x5b, 0xd2, 0xf8, 0xe7, 0xb0, 0xd7, 0x6d, 0xfa, 0x71,
+    0x4c, 0xdd, 0x52, 0x0f, 0x7a, 0xca, 0x8a, 0x8b, 0x91, 0x7a, 0xcc, 0x37, 0xf5, 0x1d, 0xe8, 0xf0,
+    0xc9, 0xbb, 0xe3, 0xad, 0x85, 0x83, 0x82, 0xe7, 0x02, 0xdc, 0x25, 0xa1, 0x2d, 0x09, 0xf7, 0xa8,
+    0x58,
+];
+static HASH: [u8; 32] = [
+    0x2c, 0x5b, 0x41, 0x08, 0x16, 0x32, 0xe1, 0x7c, 0x59, 0x16, 0xde, 0x9b, 0x6c, 0x1e, 0x6e, 0x12,
+    0x2e, 0xc7, 0xcf, 0x40, 0x6d, 0x88, 0x19, 0xdb, 0x2a, 0x5c, 0x9c, 0x0d, 0xed, 0x56, 0xb3, 0x04,
+];
+static R: [u8; 32] = [
+    0x54, 0x26, 0xd3, 0xdb, 0x42, 0xda, 0x95, 0xd5, 0x1b, 0x69, 0xc3, 0xd2, 0x20, 0xa0, 0xc8, 0x40,
+    0x0a, 0x1d, 0xfd, 0x04, 0x15, 0x4d, 0xba, 0x90, 0xbe, 0x5a, 0x3a, 0xcd, 0xcf, 0x5e, 0x31, 0x7a,
+];
+static S: [u8; 32] = [
+    0x0c, 0x9a, 0x73, 0x13, 0xbc, 0x75, 0x8d, 0x56, 0x39, 0x26, 0x17, 0x6b, 0xe4, 0x9e, 0xe4, 0x3f,
+    0x8c, 0x84, 0x5e, 0x07, 0xbc, 0xaa, 0xf6, 0x93, 0x27, 0xda, 0x3f, 0x9a, 0x6a, 0xe3, 0x15, 0x71,
+];
+
+#[test]
+fn ecdsa_verify() {
+    let mut crypto = SoftCrypto::new();
+    assert!(crypto.ecdsa_p256_verify(&KEY, &HASH, &R, &S));
+
+    // Any change should cause a failure.
+    let mut hash = HASH;
+    hash[5] ^= 1;
+    assert!(!crypto.ecdsa_p256_verify(&KEY, &hash, &R, &S));
+
+    let mut r = R;
+    r[31] ^= 1;
+    assert!(!crypto.ecdsa_p256_verify(&KEY, &HASH, &r, &S));
+
+    let mut key = KEY;
+    key[64] ^= 1;
+    assert!(!crypto.ecdsa_p256_verify(&key, &HASH, &R, &S));
+
+    // Zero is never a valid signature value.
+    assert!(!crypto.ecdsa_p256_verify(&KEY, &HASH, &[0; 32], &S));
+}
+
+#[test]
+fn ecdsa_image() {
+    let data = include_bytes!("../data/sample-ecdsa.bin");
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+
+    let image = Image::from_flash(&flash).unwrap();
+    image.validate_signed(&mut SoftCrypto::new(), key).unwrap();
+
+    // Without a key, the signature can't be checked.
+    assert!(image.validate().is_err());
+
+    // A different key must be rejected.
+    let mut bad_key = *key;
+    bad_key[40] ^= 1;
+    assert!(image.validate_signed(&mut SoftCrypto::new(), &bad_key).is_err());
+}
+
+/// Replace the signature in an image with the given one.
+fn with_signature(image: &[u8], sig: &[u8]) -> Vec<u8> {
+    // The sample has no protected TLVs.
+    let start = 256 + u32::from_le_bytes(image[12..16].try_into().unwrap()) as usize;
+    let mut tlvs = vec![];
+    let mut pos = start + 4;
+    while pos < image.len() {
+        let kind = u16::from_le_bytes([image[pos], image[pos + 1]]);
+        let len = u16::from_le_bytes([image[pos + 2], image[pos + 3]]) as usize;
+        let data = if kind == 0x22 { sig } else { &image[pos + 4..pos + 4 + len] };
+        tlvs.extend(kind.to_le_bytes());
+        tlvs.extend((data.len() as u16).to_le_bytes());
+        tlvs.extend(data);
+        pos += 4 + len;
+    }
+    let mut result = image[..start].to_vec();
+    result.extend(0x6907u16.to_le_bytes());
+    result.extend((tlvs.len() as u16 + 4).to_le_bytes());
+    result.extend(tlvs);
+    result
+}
+
+fn validate(data: &[u8], key: &[u8]) -> boot::Result<()> {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    Image::from_flash(&flash)?.validate_signed(&mut SoftCrypto::new(), key)
+}
+
+#[test]
+fn ecdsa_encodings() {
+    let data = include_bytes!("../data/sample-ecdsa.bin");
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+    let mut sigs: &[u8] = include_bytes!("../data/sample-ecdsa-sigs.der");
+
+    // Each of the lengths imgtool produces.
+    let mut lengths = vec![];
+    while !sigs.is_empty() {
+        let (sig, rest) = sigs.split_at(2 + sigs[1] as usize);
+        sigs = rest;
+        lengths.push(sig.len());
+        validate(&with_signature(data, sig), key).unwrap();
+
+        // Older imgtool pads the signature to 72 bytes.
+        let mut padded = sig.to_vec();
+        padded.resize(72, 0);
+        validate(&with_signature(data, &padded), key).unwrap();
+        if sig.len() < 72 {
+            *padded.last_mut().unwrap() = 1;
+            assert!(validate(&with_signature(data, &padded), key).is_err());
+        }
+
+        // The same values, raw.
+        let r_len = sig[3] as usize;
+        let r = &sig[4..4 + r_len];
+        let s = &sig[6 + r_len..];
+        let mut raw = [0u8; 64];
+        raw[32 - r.len().min(32)..32].copy_from_slice(&r[r.len().saturating_sub(32)..]);
+        raw[64 - s.len().min(32)..].copy_from_slice(&s[s.len().saturating_sub(32)..]);
+        validate(&with_signature(data, &raw), key).unwrap();
+
+        // Truncated, it's rejected.
+        assert!(validate(&with_signature(data, &sig[..sig.len() - 1]), key).is_err());
+        assert!(validate(&with_signature(data, &raw[..63]), key).is_err());
+    }
+    assert_eq!(lengths, [70, 71, 72]);
+}
+
+fn unhex<const N: usize>(text: &str) -> [u8; N] {
+    let mut result = [0u8; N];
+    for (i, byte) in result.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).unwrap();
+    }
+    result
+}
+
+#[test]
+fn ecdsa_sign_rfc6979() {
+    // The P-256, SHA-256, "sample" vector from RFC 6979, appendix A.2.5.
+    let private = unhex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+    let ux: [u8; 32] = unhex("60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6");
+    let uy: [u8; 32] = unhex("7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299");
+    let hash: [u8; 32] = unhex("af2bdbe1aa9b6ec1e2ade1d694f41fc71a831d0268e9891562113d8a62add1bf");
+    let r: [u8; 32] = unhex("efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716");
+    let s: [u8; 32] = unhex("f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8");
+
+    let key = ecdsa_public_key(&mut SoftMul, &private).unwrap();
+    assert_eq!(key[0], 0x04);
+    assert_eq!(key[1..33], ux);
+    assert_eq!(key[33..], uy);
+
+    assert_eq!(ecdsa_sign(&mut SoftMul, &private, &hash), Some((r, s)));
+    assert!(SoftCrypto::new().ecdsa_p256_verify(&key, &hash, &r, &s));
+
+    // Zero is not a key.
+    assert_eq!(ecdsa_sign(&mut SoftMul, &[0; 32], &hash), None);
+}
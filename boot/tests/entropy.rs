@@ -0,0 +1,53 @@
+// Entropy testing.
+
+use boot::{new_hash_seed, EntropyError, EntropySource, SeededEntropy};
+
+/// Gives out the words it is given, then fails.
+struct Words(Vec<u32>);
+
+impl EntropySource for Words {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        for chunk in buf.chunks_mut(4) {
+            if self.0.is_empty() {
+                return Err(EntropyError::Timeout);
+            }
+            let word = self.0.remove(0).to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn seeded() {
+    // The same seed gives the same bytes, however they are asked for.
+    let mut a = SeededEntropy::new(1);
+    let mut b = SeededEntropy::new(1);
+    let mut whole = [0u8; 20];
+    a.fill(&mut whole).unwrap();
+    let mut parts = [0u8; 20];
+    b.fill(&mut parts[..8]).unwrap();
+    b.fill(&mut parts[8..16]).unwrap();
+    b.fill(&mut parts[16..]).unwrap();
+    assert_eq!(whole, parts);
+
+    let mut other = [0u8; 20];
+    SeededEntropy::new(2).fill(&mut other).unwrap();
+    assert_ne!(whole, other);
+}
+
+#[test]
+fn hash_seed() {
+    assert_eq!(new_hash_seed(&mut Words(vec![0x1234_5678])).unwrap(), 0x1234_5678);
+
+    // An erased looking seed is drawn again.
+    assert_eq!(new_hash_seed(&mut Words(vec![0xffff_ffff, 7])).unwrap(), 7);
+
+    // A source that fails stops the swap from starting.
+    assert!(matches!(new_hash_seed(&mut Words(vec![0xffff_ffff])), Err(boot::Error::CannotUpgrade)));
+
+    // Any source will do, such as one passed on by reference.
+    let mut source = SeededEntropy::new(3);
+    let seed = new_hash_seed(&mut &mut source).unwrap();
+    assert_eq!(seed, new_hash_seed(&mut SeededEntropy::new(3)).unwrap());
+}
@@ -0,0 +1,80 @@
+// Boot event log testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    event, read_events, request_upgrade, set_event_log, swap_type, ErrorCode, Event, EventCode,
+    EventLog, Image, SoftCrypto, MAX_EVENT_VALUE,
+};
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+fn codes(buf: &[u32]) -> Vec<(EventCode, u32)> {
+    read_events(buf).unwrap().map(|e| (e.code, e.value)).collect()
+}
+
+#[test]
+fn ring() {
+    let mut buf = [0u32; 5];
+    assert!(read_events(&buf).is_none());
+    let mut log = EventLog::start(&mut buf).unwrap();
+    log.record(EventCode::Start, 0);
+    log.record(EventCode::ValidationMs, 12);
+    assert_eq!(codes(&buf), [(EventCode::Start, 0), (EventCode::ValidationMs, 12)]);
+
+    // Once full, the oldest go first.
+    let mut log = EventLog::start(&mut buf).unwrap();
+    for ms in 1..=5 {
+        log.record(EventCode::ValidationMs, ms);
+    }
+    let values: Vec<u32> = read_events(&buf).unwrap().map(|e| e.value).collect();
+    assert_eq!(values, [3, 4, 5]);
+
+    // Values saturate, and unknown codes are skipped.
+    let mut log = EventLog::start(&mut buf).unwrap();
+    log.record(EventCode::ValidationMs, u32::MAX);
+    buf[3] = 0x1234_56ff;
+    buf[1] = 2;
+    assert_eq!(read_events(&buf).unwrap().collect::<Vec<_>>(),
+               [Event { code: EventCode::ValidationMs, value: MAX_EVENT_VALUE }]);
+
+    assert!(EventLog::start(&mut [0; 2]).is_none());
+}
+
+#[test]
+fn recorded() {
+    let buf: &'static mut [u32] = Box::leak(Box::new([0u32; 16]));
+    let ptr = buf.as_ptr();
+    unsafe { set_event_log(buf) };
+    let read = || codes(unsafe { std::slice::from_raw_parts(ptr, 16) });
+
+    let mut primary = simflash::styles::LPC_MAIN.build().unwrap();
+    let mut secondary = simflash::styles::LPC_MAIN.build().unwrap();
+    for slot in [&mut primary, &mut secondary] {
+        let size = slot.capacity();
+        slot.erase(0, size).unwrap();
+    }
+    request_upgrade(&mut secondary).unwrap();
+    swap_type(&mut primary, &mut secondary).unwrap();
+
+    let mut bad = SAMPLE.to_vec();
+    bad[300] ^= 1;
+    for (data, valid) in [(SAMPLE, true), (&bad[..], false)] {
+        let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+        flash.install(data, 0).unwrap();
+        let flash = RefCell::new(flash);
+        let image = Image::from_flash(&flash).unwrap();
+        assert_eq!(image.validate_signed(&mut SoftCrypto::new(), KEY).is_ok(), valid);
+    }
+    event(EventCode::ValidationMs, 42);
+
+    assert_eq!(read(), [
+        (EventCode::Start, 0),
+        (EventCode::SwapType, 1),
+        (EventCode::Valid, 0),
+        (EventCode::Invalid, ErrorCode::InvalidImage as u32),
+        (EventCode::ValidationMs, 42),
+    ]);
+}
@@ -0,0 +1,82 @@
+// Flash errors, injected with FaultyFlash.
+
+use std::cell::RefCell;
+
+use boot::{request_upgrade, upgrade_requested, Error, Image, SlotInfo, Status, StatusRead, StatusStyle};
+use simflash::faulty::{Fault, FaultyFlash, Op};
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-signed.bin");
+
+#[test]
+fn request() {
+    for flashes in simflash::styles::all_flashes() {
+        let (flash, _) = flashes.unwrap();
+        let size = flash.capacity();
+
+        // A write that fails is reported, and leaves no request.
+        let mut flash = FaultyFlash::new(flash).fault(Fault::nth(Op::Write, 0, storage::Error::Failed));
+        flash.erase(0, size).unwrap();
+        assert!(matches!(request_upgrade(&mut flash), Err(Error::Flash(storage::Error::Failed))));
+        assert!(!upgrade_requested(&mut flash).unwrap());
+
+        // Flash that can't be read because it is erased isn't a request, but
+        // other read errors are passed on.
+        request_upgrade(&mut flash).unwrap();
+        let mut flash = flash
+            .fault(Fault::range(Op::Read, size - 1..size, storage::Error::NotWritten).times(1))
+            .fault(Fault::range(Op::Read, size - 1..size, storage::Error::Failed).times(1));
+        assert!(!upgrade_requested(&mut flash).unwrap());
+        assert!(matches!(upgrade_requested(&mut flash), Err(Error::Flash(storage::Error::Failed))));
+        assert!(upgrade_requested(&mut flash).unwrap());
+    }
+}
+
+#[test]
+fn image() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut flash, _) = flashes.unwrap();
+        flash.install(IMAGE, 0).unwrap();
+
+        // Failing to read the header, or the payload, is a flash error, not
+        // an invalid image.
+        let faulty = FaultyFlash::new(flash).fault(Fault::nth(Op::Read, 0, storage::Error::Failed));
+        let faulty = RefCell::new(faulty);
+        assert!(matches!(Image::from_flash(&faulty).err(), Some(Error::Flash(storage::Error::Failed))));
+
+        let flash = faulty.into_inner().into_inner();
+        let faulty = FaultyFlash::new(flash).fault(Fault::range(Op::Read, 1024..1025, storage::Error::Failed));
+        let faulty = RefCell::new(faulty);
+        let image = Image::from_flash(&faulty).unwrap();
+        assert!(matches!(image.validate(), Err(Error::Flash(storage::Error::Failed))));
+    }
+}
+
+#[test]
+fn status_page() {
+    let mut count = 0;
+    for flashes in simflash::styles::all_flashes() {
+        let (main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let info = SlotInfo::from_data(size / 4, &main);
+        if info.status_style().unwrap() != StatusStyle::Paged {
+            continue;
+        }
+        let layout = info.status_layout(&SlotInfo::from_data(size / 4, &upgrade)).unwrap();
+
+        let mut main = FaultyFlash::new(main);
+        main.erase(0, size).unwrap();
+        let first = Status { hash_seed: 1, ..Status::default() };
+        layout.write(&mut main, &first).unwrap();
+
+        // The next page can't be erased, which leaves the current status.
+        let page = layout.page_base(&main, 1).unwrap();
+        let mut main = main.fault(Fault::range(Op::Erase, page..page + 1, storage::Error::Failed));
+        let second = Status { hash_seed: 2, ..Status::default() };
+        assert!(matches!(layout.write(&mut main, &second), Err(Error::Flash(storage::Error::Failed))));
+        let StatusRead::Valid(status) = layout.read(&mut main).unwrap() else { panic!("No status") };
+        assert_eq!(status.hash_seed, 1);
+        count += 1;
+    }
+    assert!(count > 0);
+}
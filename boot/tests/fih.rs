@@ -0,0 +1,66 @@
+// Fault injection hardening.
+
+use std::cell::RefCell;
+
+use boot::{fih_eq, FihBool, Image, LoopCounter, SoftCrypto};
+
+#[test]
+fn fih_bool() {
+    assert!(FihBool::TRUE.is_true());
+    assert!(!FihBool::FALSE.is_true());
+    assert!(FihBool::new(true).is_true());
+    assert!(!FihBool::new(false).is_true());
+    assert!(FihBool::TRUE.and(FihBool::TRUE).is_true());
+    assert!(!FihBool::TRUE.and(FihBool::FALSE).is_true());
+    assert!(!FihBool::FALSE.and(FihBool::TRUE).is_true());
+}
+
+#[test]
+fn compare() {
+    let a = [0x5au8; 32];
+    assert!(fih_eq(&a, &a).is_true());
+    for pos in [0, 17, 31] {
+        let mut b = a;
+        b[pos] ^= 0x80;
+        assert!(!fih_eq(&a, &b).is_true());
+    }
+    assert!(!fih_eq(&a, &a[..31]).is_true());
+    assert!(fih_eq(&[], &[]).is_true());
+}
+
+#[test]
+fn loop_counter() {
+    let mut counter = LoopCounter::new();
+    for _ in 0..10 {
+        counter.step();
+    }
+    counter.check(10);
+}
+
+// A loop cut short, as a glitch would, is only caught when hardened.
+#[test]
+#[cfg_attr(feature = "fih", should_panic(expected = "Fault injection detected"))]
+fn loop_cut_short() {
+    let mut counter = LoopCounter::new();
+    for _ in 0..9 {
+        counter.step();
+    }
+    counter.check(10);
+}
+
+#[test]
+fn validate_fih() {
+    let data = include_bytes!("../data/sample-ecdsa.bin");
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+
+    let image = Image::from_flash(&flash).unwrap();
+    assert!(image.validate_signed_fih(&mut SoftCrypto::new(), key).is_true());
+
+    let mut bad_key = *key;
+    bad_key[40] ^= 1;
+    assert!(!image.validate_signed_fih(&mut SoftCrypto::new(), &bad_key).is_true());
+}
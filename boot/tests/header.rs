@@ -0,0 +1,113 @@
+// Header size and code alignment checks.
+
+use std::cell::RefCell;
+
+use boot::{Image, MappedFlash, Probe, MAX_HEADER_SIZE, VECTOR_ALIGN};
+use simflash::gen::ImageBuilder;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+
+/// A slot mapped into memory at `base`.
+struct Mapped {
+    flash: SimFlash,
+    base: usize,
+}
+
+impl ReadFlash for Mapped {
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl MappedFlash for Mapped {
+    fn get_base(&self) -> usize {
+        self.base
+    }
+}
+
+/// An image with the given header size, hashed, at `base`.
+fn build(hdr_size: usize, base: usize) -> RefCell<Mapped> {
+    let image = ImageBuilder::default().header_size(hdr_size).payload(&[0x5a; 1000]).build();
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(&image, 0).unwrap();
+    RefCell::new(Mapped { flash, base })
+}
+
+#[test]
+fn header_size() {
+    for hdr_size in [32, 256, 0x400, MAX_HEADER_SIZE] {
+        let flash = build(hdr_size, 0x1_0000);
+        Image::from_flash(&flash).unwrap().validate().unwrap();
+    }
+
+    // Too small to hold the header, or larger than allowed.
+    for hdr_size in [0, 16, 31, MAX_HEADER_SIZE + 1, 0x8000] {
+        let flash = build(hdr_size, 0x1_0000);
+        assert!(Image::from_flash(&flash).is_err(), "hdr_size {}", hdr_size);
+    }
+}
+
+#[test]
+fn alignment() {
+    let flash = build(0x200, 0x1_0000);
+    let image = Image::from_flash(&flash).unwrap();
+    assert_eq!(image.get_image_base().unwrap(), 0x1_0200);
+    assert_eq!(image.image_base(0x200).unwrap(), 0x1_0200);
+    assert!(image.image_base(0x400).is_err());
+
+    // A header that leaves the code misaligned, either by its own size, or by
+    // where the slot is.
+    let flash = build(34, 0x1_0000);
+    let image = Image::from_flash(&flash).unwrap();
+    assert!(image.get_image_base().is_err());
+
+    let flash = build(0x200, 0x1_0000 + VECTOR_ALIGN / 2);
+    let image = Image::from_flash(&flash).unwrap();
+    assert!(image.get_image_base().is_err());
+}
+
+#[test]
+fn probe() {
+    let kind = |flash: SimFlash| {
+        let flash = RefCell::new(flash);
+        match Image::probe(&flash).unwrap() {
+            Probe::Empty => "empty",
+            Probe::Corrupt => "corrupt",
+            Probe::Image(_) => "image",
+        }
+    };
+    for (layout, _) in simflash::styles::ALL_FLASHES {
+        let with = |data: &[u8]| {
+            let mut flash = layout.build().unwrap();
+            let size = flash.capacity();
+            flash.erase(0, size).unwrap();
+            flash.install(data, 0).unwrap();
+            flash
+        };
+
+        // Devices that can't read erased flash are empty all the same.
+        assert_eq!(kind(with(&[])), "empty");
+        assert_eq!(kind(with(SAMPLE)), "image");
+
+        // A download cut short has no TLV.
+        assert_eq!(kind(with(&SAMPLE[..SAMPLE.len() / 2])), "corrupt");
+        let mut bad = SAMPLE.to_vec();
+        bad[0] ^= 1;
+        assert_eq!(kind(with(&bad)), "corrupt");
+
+        // An image too large for its slot.
+        let mut big = SAMPLE.to_vec();
+        big[12..16].copy_from_slice(&0x7fff_0000u32.to_le_bytes());
+        assert_eq!(kind(with(&big)), "corrupt");
+    }
+}
@@ -0,0 +1,72 @@
+// Key provisioning testing.
+
+use std::cell::RefCell;
+
+use boot::{FlashKeyStore, Image, KeyStore, SoftCrypto};
+use sha2::{Digest, Sha256};
+use simflash::styles::AreaLayout;
+use storage::Flash;
+
+/// A simulated protected flash page, holding the key hash.
+static PROTECTED: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 16,
+    erase_size: 512,
+    sectors: 1,
+};
+
+/// Offset of the key hash within the protected page.
+const KEY_HASH_OFFSET: usize = 0x100;
+
+#[test]
+fn provisioned_key() {
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+    let data = include_bytes!("../data/sample-ecdsa-pubkey.bin");
+
+    // An unprovisioned device has no hash.
+    let protected = PROTECTED.build().unwrap();
+    let mut store = FlashKeyStore::new(protected, KEY_HASH_OFFSET);
+    assert_eq!(store.key_hash().unwrap(), None);
+
+    // Erased isn't provisioned either.
+    let mut protected = store.into_inner();
+    protected.erase(0, 512).unwrap();
+    protected.write(KEY_HASH_OFFSET, &[0xff; 32]).unwrap();
+    let mut store = FlashKeyStore::new(protected, KEY_HASH_OFFSET);
+    assert_eq!(store.key_hash().unwrap(), None);
+
+    // Provision the device.
+    let mut protected = store.into_inner();
+    let hash: [u8; 32] = Sha256::digest(key).into();
+    protected.erase(0, 512).unwrap();
+    protected.write(KEY_HASH_OFFSET, &hash).unwrap();
+    let mut store = FlashKeyStore::new(protected, KEY_HASH_OFFSET);
+    let key_hash = store.key_hash().unwrap().unwrap();
+    assert_eq!(key_hash, hash);
+
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    let image = Image::from_flash(&flash).unwrap();
+    image.validate_key_hash(&mut SoftCrypto::new(), &key_hash).unwrap();
+
+    // The wrong hash is rejected.
+    let mut bad_hash = key_hash;
+    bad_hash[0] ^= 1;
+    assert!(image.validate_key_hash(&mut SoftCrypto::new(), &bad_hash).is_err());
+}
+
+#[test]
+fn key_hash_needs_pubkey() {
+    // An image with only the key hash can't be checked against a provisioned
+    // hash.
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+    let data = include_bytes!("../data/sample-ecdsa.bin");
+    let hash: [u8; 32] = Sha256::digest(key).into();
+
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+    let image = Image::from_flash(&flash).unwrap();
+    assert!(image.validate_key_hash(&mut SoftCrypto::new(), &hash).is_err());
+}
@@ -0,0 +1,162 @@
+// Status layout properties, over random geometries.
+
+use std::panic;
+
+use boot::{trailer_size, SlotInfo, StatusStyle, MAX_PROGRESS_GROUP, MAX_STATUS_PAGES};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256Plus;
+
+/// Geometries tried by each property.
+const CASES: usize = 20_000;
+
+/// A slot of arbitrary geometry: mostly power of two write and erase sizes,
+/// with the erase size at least the write size, but sometimes sizes the
+/// layout doesn't support, images of any size, and settings in and out of
+/// range.
+fn slot(rng: &mut Xoshiro256Plus) -> SlotInfo {
+    let (write_size, erase_size) = if rng.gen_range(0..8) == 0 {
+        let write_size = rng.gen_range(1..=600);
+        (write_size, write_size * rng.gen_range(1..=256))
+    } else {
+        let write_log = rng.gen_range(0..=9);
+        (1 << write_log, 1 << rng.gen_range(write_log..=17))
+    };
+    let image_size = match rng.gen_range(0..4) {
+        0 => 0,
+        1 => rng.gen_range(1..=4) * erase_size,
+        _ => rng.gen_range(0..=2 * 1024 * 1024),
+    };
+    SlotInfo {
+        write_size,
+        erase_size,
+        capacity: 4 * 1024 * 1024,
+        image_size,
+        status_pages: rng.gen_range(0..=MAX_STATUS_PAGES + 1),
+        progress_group: rng.gen_range(0..=MAX_PROGRESS_GROUP + 1),
+        minimal: rng.gen_range(0..4) == 0,
+    }
+}
+
+/// Run `property` on `CASES` pairs of slots, reporting the seed of the
+/// first that fails.
+fn for_all(seed: u64, property: impl Fn(&SlotInfo, &SlotInfo) + panic::RefUnwindSafe) {
+    for case in 0..CASES {
+        let seed = seed + case as u64;
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+        let (main, upgrade) = (slot(&mut rng), slot(&mut rng));
+        if panic::catch_unwind(|| property(&main, &upgrade)).is_err() {
+            panic!("Property fails for seed {}: {:#x?} {:#x?}", seed, main, upgrade);
+        }
+    }
+}
+
+#[test]
+fn invariants() {
+    // Any layout given out holds its invariants, and anything else is an
+    // error, not a panic.
+    for_all(1, |main, upgrade| {
+        if let Ok(layout) = main.status_layout(upgrade) {
+            layout.check().unwrap();
+        }
+    });
+}
+
+#[test]
+fn geometry() {
+    for_all(100_000, |main, upgrade| {
+        let Ok(layout) = main.status_layout(upgrade) else { return };
+
+        // The swap uses the larger sector of the two slots, and this slot's
+        // write size.
+        assert_eq!(layout.erase_size, main.erase_size.max(upgrade.erase_size));
+        assert_eq!(layout.write_size, main.write_size);
+        assert_eq!(layout.image_sectors, [
+            main.image_size.div_ceil(layout.erase_size),
+            upgrade.image_size.div_ceil(layout.erase_size),
+        ]);
+
+        // The flags are in the one sector, in order, a write unit apart.
+        if let Some(flags) = layout.flags {
+            assert!(flags[0] < layout.tail_pos);
+            assert_eq!(flags[0] - flags[1], layout.write_size);
+            assert_eq!(flags[1] - flags[2], layout.write_size);
+        }
+
+        // Every sector of hashes but the last is full.
+        if let Some((_, full)) = layout.hash_pages.split_last() {
+            assert!(full.iter().all(|&n| n == layout.erase_size / 4));
+        }
+        // Paged mode leaves the trailer sectors of their own.
+        let trailer = match layout.style {
+            StatusStyle::Paged => layout.trailer.div_ceil(layout.erase_size),
+            _ => 0,
+        };
+        assert_eq!(layout.trailer, trailer_size(main.write_size));
+        assert_eq!(layout.status_sectors(), trailer + layout.pages + layout.hash_pages.len());
+    });
+}
+
+#[test]
+fn supported() {
+    // Images up to a megabyte lay out in the sectors the status modes are
+    // meant for, however they are grouped.
+    for seed in 0..CASES as u64 {
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+        let (write_size, erase_size) = if rng.gen() {
+            (1 << rng.gen_range(0..=5), 1 << rng.gen_range(12..=17))
+        } else {
+            (1 << rng.gen_range(6..=9), 1 << rng.gen_range(9..=12))
+        };
+        let status_pages = rng.gen_range(2..=MAX_STATUS_PAGES);
+        let progress_group = rng.gen_range(1..=MAX_PROGRESS_GROUP);
+        let info = |image_size| SlotInfo {
+            write_size,
+            erase_size,
+            capacity: 4 * 1024 * 1024,
+            image_size,
+            status_pages,
+            progress_group,
+            minimal: false,
+        };
+        let (main, upgrade) = (rng.gen_range(0..=1024 * 1024), rng.gen_range(0..=1024 * 1024));
+        let layout = info(main).status_layout(&info(upgrade));
+        assert!(layout.is_ok(), "No layout for seed {}", seed);
+    }
+}
+
+#[test]
+fn settings() {
+    for_all(200_000, |main, upgrade| {
+        let result = main.status_layout(upgrade);
+
+        // Out of range settings are refused, as are devices the layout
+        // can't handle: sizes that aren't powers of two, and large writes in
+        // large sectors outside minimal mode.
+        if !(1..=MAX_PROGRESS_GROUP).contains(&main.progress_group) {
+            assert!(result.is_err());
+        }
+        let sizes = [main.write_size, main.erase_size, upgrade.erase_size];
+        if !sizes.iter().all(|size| size.is_power_of_two()) {
+            assert!(result.is_err());
+        }
+        if main.write_size > 32 && main.erase_size > 4096 && !main.minimal {
+            assert!(main.status_style().is_err());
+            assert!(result.is_err());
+        }
+        let Ok(layout) = result else { return };
+        assert_eq!(layout.group, main.progress_group);
+        match layout.style {
+            StatusStyle::Paged => {
+                assert_eq!(layout.pages, main.status_pages);
+                assert!((2..=MAX_STATUS_PAGES).contains(&layout.pages));
+            }
+            StatusStyle::OverWrite => assert_eq!(layout.pages, 1),
+            StatusStyle::Minimal => {
+                assert!(main.minimal);
+                assert_eq!(layout.pages, 1);
+                assert_eq!(layout.inline_hashes, 0);
+                assert!(layout.hash_pages.is_empty());
+            }
+        }
+    });
+}
@@ -0,0 +1,62 @@
+// Slot listing testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    confirm, list_slots, read_listing, request_upgrade, write_listing, Image, SharedError, LISTING_SIZE,
+    SLOT_ACTIVE, SLOT_CONFIRMED, SLOT_PENDING, SLOT_PERMANENT, SLOT_PRESENT,
+};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+
+fn erase(flash: &mut SimFlash) {
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+}
+
+#[test]
+fn listing() {
+    let (mut primary, mut secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    erase(&mut primary);
+    erase(&mut secondary);
+    primary.install(SAMPLE, 0).unwrap();
+    confirm(&mut primary).unwrap();
+    let primary = RefCell::new(primary);
+    let secondary = RefCell::new(secondary);
+
+    // Only the running image.
+    let listing = list_slots(&primary, &secondary).unwrap();
+    assert_eq!(listing.images().count(), 1);
+    let image = Image::from_flash(&primary).unwrap();
+    let running = listing.slots[0];
+    assert_eq!(running.slot, 0);
+    assert_eq!(running.flags, SLOT_PRESENT | SLOT_ACTIVE | SLOT_CONFIRMED);
+    assert_eq!(running.version, image.version());
+    assert_eq!(running.size as usize, SAMPLE.len());
+    assert_eq!(running.hash, image.stored_sha256().unwrap());
+    assert_eq!(listing.slots[1].slot, 1);
+    assert!(!listing.slots[1].present());
+
+    // A download, marked for test, then made permanent.
+    secondary.borrow_mut().install(SAMPLE, 0).unwrap();
+    assert_eq!(list_slots(&primary, &secondary).unwrap().slots[1].flags, SLOT_PRESENT);
+    request_upgrade(&mut *secondary.borrow_mut()).unwrap();
+    let upgrade = list_slots(&primary, &secondary).unwrap().slots[1];
+    assert!(upgrade.pending() && !upgrade.permanent() && !upgrade.active());
+    confirm(&mut *secondary.borrow_mut()).unwrap();
+    let listing = list_slots(&primary, &secondary).unwrap();
+    assert_eq!(listing.slots[1].flags, SLOT_PRESENT | SLOT_PENDING | SLOT_PERMANENT);
+    assert_eq!(listing.slots[1].hash, running.hash);
+    assert_eq!(listing.slots[0], running);
+
+    // The same, through the shared RAM.
+    let mut buf = [0u8; LISTING_SIZE];
+    assert_eq!(read_listing(&buf), Err(SharedError::Missing));
+    assert_eq!(write_listing(&mut buf[..LISTING_SIZE - 1], &listing), Err(SharedError::TooSmall));
+    write_listing(&mut buf, &listing).unwrap();
+    assert_eq!(read_listing(&buf), Ok(listing));
+    buf[LISTING_SIZE - 1] ^= 1;
+    assert_eq!(read_listing(&buf), Err(SharedError::Crc));
+}
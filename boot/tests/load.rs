@@ -0,0 +1,124 @@
+// Copy to internal flash testing.
+
+use std::cell::{Cell, RefCell};
+
+use boot::{load_to_internal, Image, Loaded, SoftCrypto};
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+#[test]
+fn load() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut internal, mut external) = flashes.unwrap();
+        let size = internal.capacity();
+        internal.erase(0, size).unwrap();
+        external.install(IMAGE, 0).unwrap();
+        let internal = RefCell::new(internal);
+        let external = RefCell::new(external);
+        let mut crypto = SoftCrypto::new();
+
+        // The first boot copies, and later ones don't.
+        assert_eq!(load_to_internal(&external, &internal, &mut crypto, KEY).unwrap(), Loaded::Copied);
+        Image::from_flash(&internal).unwrap().validate_signed(&mut crypto, KEY).unwrap();
+        assert_eq!(load_to_internal(&external, &internal, &mut crypto, KEY).unwrap(), Loaded::Unchanged);
+    }
+}
+
+#[test]
+fn damaged_copy() {
+    // A copy with the right hash recorded, but different contents, such as
+    // from an interrupted copy, is replaced.
+    let mut damaged = IMAGE.to_vec();
+    damaged[300] ^= 1;
+
+    for flashes in simflash::styles::all_flashes() {
+        let (mut internal, mut external) = flashes.unwrap();
+        internal.install(&damaged, 0).unwrap();
+        external.install(IMAGE, 0).unwrap();
+        let internal = RefCell::new(internal);
+        let external = RefCell::new(external);
+        let mut crypto = SoftCrypto::new();
+
+        assert_eq!(load_to_internal(&external, &internal, &mut crypto, KEY).unwrap(), Loaded::Copied);
+        assert_eq!(load_to_internal(&external, &internal, &mut crypto, KEY).unwrap(), Loaded::Unchanged);
+    }
+}
+
+#[test]
+fn invalid_source() {
+    // An external image that fails validation is never copied.
+    let mut bad = IMAGE.to_vec();
+    bad[300] ^= 1;
+
+    for flashes in simflash::styles::all_flashes() {
+        let (mut internal, mut external) = flashes.unwrap();
+        let size = internal.capacity();
+        internal.erase(0, size).unwrap();
+        external.install(&bad, 0).unwrap();
+        let internal = RefCell::new(internal);
+        let external = RefCell::new(external);
+
+        assert!(load_to_internal(&external, &internal, &mut SoftCrypto::new(), KEY).is_err());
+        assert!(Image::from_flash(&internal).is_err());
+    }
+}
+
+/// A flash that counts the bytes read from it.
+struct Counted<'c, F> {
+    inner: F,
+    read: &'c Cell<usize>,
+}
+
+impl<'c, F: ReadFlash> ReadFlash for Counted<'c, F> {
+    fn read_size(&self) -> usize {
+        self.inner.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        self.read.set(self.read.get() + bytes.len());
+        self.inner.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<'c, F: Flash> Flash for Counted<'c, F> {
+    fn write_size(&self) -> usize {
+        self.inner.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.inner.erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> storage::Result<()> {
+        self.inner.erase(from, to)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> storage::Result<()> {
+        self.inner.write(offset, bytes)
+    }
+}
+
+#[test]
+fn single_pass() {
+    // The copy is hashed as it is written, so checking it reads little of
+    // the internal flash.
+    for flashes in simflash::styles::all_flashes() {
+        let (mut internal, mut external) = flashes.unwrap();
+        let size = internal.capacity();
+        internal.erase(0, size).unwrap();
+        external.install(IMAGE, 0).unwrap();
+        let read = Cell::new(0);
+        let internal = RefCell::new(Counted { inner: internal, read: &read });
+        let external = RefCell::new(external);
+
+        assert_eq!(load_to_internal(&external, &internal, &mut SoftCrypto::new(), KEY).unwrap(), Loaded::Copied);
+        assert!(read.get() < 1024, "read {} bytes", read.get());
+        Image::from_flash(&internal).unwrap().validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+// Logging testing.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Mutex;
+
+use boot::{load_to_internal, set_logger, Image, Level, Log, SoftCrypto};
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// Collects the messages.
+struct Collect(Mutex<Vec<(Level, String)>>);
+
+impl Log for Collect {
+    fn log(&self, level: Level, args: fmt::Arguments<'_>) {
+        self.0.lock().unwrap().push((level, args.to_string()));
+    }
+}
+
+static LOGGER: Collect = Collect(Mutex::new(Vec::new()));
+
+fn take() -> Vec<(Level, String)> {
+    LOGGER.0.lock().unwrap().drain(..).collect()
+}
+
+// The logger is global, so this is a single test.
+#[test]
+fn messages() {
+    unsafe { set_logger(&LOGGER) };
+
+    let (mut internal, mut external) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = internal.capacity();
+    internal.erase(0, size).unwrap();
+    external.install(IMAGE, 0).unwrap();
+    let internal = RefCell::new(internal);
+    let external = RefCell::new(external);
+    let mut crypto = SoftCrypto::new();
+
+    load_to_internal(&external, &internal, &mut crypto, KEY).unwrap();
+    let log = take();
+    assert!(log.iter().any(|(level, msg)| *level == Level::Info && msg.starts_with("Loading ")));
+    assert!(log.iter().any(|(level, msg)| *level == Level::Info && msg.ends_with(" is valid")));
+    assert!(log.iter().all(|(level, _)| *level > Level::Warn));
+
+    // A failure is reported as an error.
+    let mut bad = IMAGE.to_vec();
+    bad[300] ^= 1;
+    external.borrow_mut().install(&bad, 0).unwrap();
+    assert!(Image::from_flash(&external).unwrap().validate().is_err());
+    let log = take();
+    assert!(log.contains(&(Level::Error, "Hash verification failure".to_string())));
+}
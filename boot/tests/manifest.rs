@@ -0,0 +1,152 @@
+// Manifest testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    check_manifest, Condition, Image, Manifest, ManifestPolicy, Uuid, Value, CONDITION_DEVICE_ID,
+    CONDITION_MINIMUM_BATTERY,
+};
+use simflash::gen::ImageBuilder;
+use simflash::SimFlash;
+
+const VENDOR: Uuid = [0x11; 16];
+const CLASS: Uuid = [0x22; 16];
+
+/// A manifest for component "app", from VENDOR and CLASS, for device "dev-1",
+/// needing the battery at 30%.
+fn manifest() -> Vec<u8> {
+    let mut cbor = vec![0xa4];
+    cbor.extend([0x01, 0x43]);
+    cbor.extend(b"app");
+    cbor.extend([0x02, 0x50]);
+    cbor.extend(VENDOR);
+    cbor.extend([0x03, 0x50]);
+    cbor.extend(CLASS);
+    cbor.extend([0x04, 0x82]);
+    cbor.extend([0x82, 0x18, 24, 0x45]);
+    cbor.extend(b"dev-1");
+    cbor.extend([0x82, 0x18, 26, 0x18, 30]);
+    cbor
+}
+
+/// Build an image with a SHA256, and the given manifest in its protected TLV.
+fn build(manifest: Option<&[u8]>) -> Vec<u8> {
+    let mut image = ImageBuilder::default();
+    image.payload(&[0x5a; 1000]);
+    if let Some(manifest) = manifest {
+        image.protected(0xa0, manifest);
+    }
+    image.build()
+}
+
+fn install(image: &[u8]) -> RefCell<SimFlash> {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(image, 0).unwrap();
+    RefCell::new(flash)
+}
+
+/// A device from VENDOR and CLASS, with the given battery level.
+struct Device {
+    battery: u64,
+    require: bool,
+}
+
+impl ManifestPolicy for Device {
+    fn vendor(&self) -> Option<Uuid> {
+        Some(VENDOR)
+    }
+
+    fn class(&self) -> Option<Uuid> {
+        Some(CLASS)
+    }
+
+    fn require_manifest(&self) -> bool {
+        self.require
+    }
+
+    fn accept_component(&mut self, component: &[u8]) -> bool {
+        component == b"app"
+    }
+
+    fn check_condition(&mut self, condition: &Condition) -> bool {
+        match (condition.kind, condition.value) {
+            (CONDITION_DEVICE_ID, Value::Bytes(id)) => id == b"dev-1",
+            (CONDITION_MINIMUM_BATTERY, Value::Uint(level)) => self.battery >= level,
+            _ => false,
+        }
+    }
+}
+
+fn check(image: &[u8], device: &mut Device) -> boot::Result<()> {
+    let flash = install(image);
+    let image = Image::from_flash(&flash)?;
+    image.validate()?;
+    check_manifest(&image, device)
+}
+
+#[test]
+fn parse() {
+    let data = manifest();
+    let parsed = Manifest::parse(&data).unwrap();
+    assert_eq!(parsed.component, b"app");
+    assert_eq!(parsed.vendor, Some(&VENDOR));
+    assert_eq!(parsed.class, Some(&CLASS));
+    let conditions: Vec<Condition> = parsed.conditions().map(Result::unwrap).collect();
+    assert_eq!(conditions, [
+        Condition { kind: CONDITION_DEVICE_ID, value: Value::Bytes(b"dev-1") },
+        Condition { kind: CONDITION_MINIMUM_BATTERY, value: Value::Uint(30) },
+    ]);
+
+    // Unknown keys are skipped, whatever they hold.
+    let mut extended = data.clone();
+    extended[0] = 0xa5;
+    extended.extend([0x63, b'x', b'y', b'z', 0x82, 0xa1, 0x01, 0x02, 0xf6]);
+    assert_eq!(Manifest::parse(&extended).unwrap().component, b"app");
+
+    // Only the component is needed.
+    let minimal = [0xa1, 0x01, 0x41, 0x00];
+    let parsed = Manifest::parse(&minimal).unwrap();
+    assert_eq!(parsed.vendor, None);
+    assert_eq!(parsed.conditions().count(), 0);
+
+    // Malformed manifests.
+    assert!(Manifest::parse(&[0xa0]).is_err());
+    assert!(Manifest::parse(&data[..data.len() - 1]).is_err());
+    assert!(Manifest::parse(&[data.clone(), vec![0]].concat()).is_err());
+    let mut short_uuid = data.clone();
+    short_uuid[7] = 0x4f;
+    assert!(Manifest::parse(&short_uuid).is_err());
+    // Indefinite lengths aren't supported.
+    assert!(Manifest::parse(&[0xbf, 0x01, 0x41, 0x00, 0xff]).is_err());
+}
+
+#[test]
+fn policy() {
+    let manifest = manifest();
+    let image = build(Some(&manifest));
+    check(&image, &mut Device { battery: 50, require: true }).unwrap();
+
+    // A condition that isn't met.
+    assert!(check(&image, &mut Device { battery: 20, require: true }).is_err());
+
+    // Another vendor's image.
+    let mut other = manifest.clone();
+    other[8] ^= 1;
+    assert!(check(&build(Some(&other)), &mut Device { battery: 50, require: false }).is_err());
+
+    // Another component.
+    let mut other = manifest.clone();
+    other[3] = b'b';
+    assert!(check(&build(Some(&other)), &mut Device { battery: 50, require: false }).is_err());
+
+    // A condition the device doesn't know.
+    let mut other = manifest.clone();
+    let len = other.len();
+    other[len - 3] = 27;
+    assert!(check(&build(Some(&other)), &mut Device { battery: 50, require: false }).is_err());
+
+    // Without a manifest, it depends on the policy.
+    let image = build(None);
+    check(&image, &mut Device { battery: 50, require: false }).unwrap();
+    assert!(check(&image, &mut Device { battery: 50, require: true }).is_err());
+}
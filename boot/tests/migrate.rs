@@ -0,0 +1,132 @@
+// Migration from C MCUboot trailers.
+
+use std::cell::RefCell;
+
+use boot::{
+    copy_done, image_ok, last_boot_error, migrate_c_trailer, record_boot_error, request_upgrade,
+    swap_type, upgrade_requested, BootError, ErrorCode, Flag, SwapType, TRAILER_MAGIC,
+};
+use simflash::gen::ImageBuilder;
+use simflash::styles::AreaLayout;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+const HEADER_SIZE: usize = 256;
+
+/// Slots with 8-byte writes.
+static SLOT: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 8,
+    erase_size: 4096,
+    sectors: 8,
+};
+
+/// C MCUboot's magic when `BOOT_MAX_ALIGN` isn't 8.
+fn aligned_magic(align: u16) -> Vec<u8> {
+    let mut magic = align.to_le_bytes().to_vec();
+    magic.extend([0x2d, 0xe1, 0x5d, 0x29, 0x41, 0x0b, 0x8d, 0x77, 0x67, 0x9c, 0x11, 0x0f, 0x1f, 0x8a]);
+    magic
+}
+
+/// A slot holding a minimal image, of the given payload size.
+fn slot(payload: usize) -> RefCell<SimFlash> {
+    let image = ImageBuilder::default().header_size(HEADER_SIZE).payload(&vec![0; payload]).no_hash().build();
+
+    let mut flash = SLOT.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(&image, 0).unwrap();
+    RefCell::new(flash)
+}
+
+/// Write a C trailer field: a byte, padded to the alignment.
+fn write_byte(slot: &RefCell<SimFlash>, offset: usize, value: u8, align: usize) {
+    let mut buf = vec![0xff; align];
+    buf[0] = value;
+    slot.borrow_mut().write(offset, &buf).unwrap();
+}
+
+#[test]
+fn c_pending() {
+    // What the C `boot_set_pending` leaves in the upgrade slot, with 8-byte
+    // alignment: the magic, and the swap info (test, image 0).
+    let secondary = slot(1000);
+    let end = secondary.borrow().capacity();
+    secondary.borrow_mut().write(end - 16, &TRAILER_MAGIC).unwrap();
+    write_byte(&secondary, end - 40, 0x02, 8);
+
+    // Unmigrated, the swap info looks like a bad boot error record.
+    assert!(last_boot_error(&mut *secondary.borrow_mut()).is_err());
+
+    assert!(migrate_c_trailer(&secondary).unwrap());
+    let mut flash = secondary.borrow_mut();
+    assert!(upgrade_requested(&mut *flash).unwrap());
+    assert_eq!(image_ok(&mut *flash).unwrap(), Flag::Unset);
+    assert_eq!(last_boot_error(&mut *flash).unwrap(), None);
+    record_boot_error(&mut *flash, &BootError { code: ErrorCode::InvalidImage, offset: 0 }).unwrap();
+    drop(flash);
+
+    // It is only done once, and the image is untouched.
+    assert!(!migrate_c_trailer(&secondary).unwrap());
+    assert_eq!(secondary.borrow().dump()[..HEADER_SIZE + 1000], slot(1000).borrow().dump()[..HEADER_SIZE + 1000]);
+
+    let primary = slot(1000);
+    assert_eq!(swap_type(&mut *primary.borrow_mut(), &mut *secondary.borrow_mut()).unwrap(), SwapType::Test);
+}
+
+#[test]
+fn c_aligned() {
+    // A primary slot after a confirmed swap, from C MCUboot built with a
+    // `BOOT_MAX_ALIGN` of 32: the flags are 32 bytes apart.
+    let primary = slot(1000);
+    let end = primary.borrow().capacity();
+    primary.borrow_mut().write(end - 32, &[[0xff; 16].to_vec(), aligned_magic(32)].concat()).unwrap();
+    write_byte(&primary, end - 64, 0x01, 32);
+    write_byte(&primary, end - 96, 0x01, 32);
+    write_byte(&primary, end - 128, 0x02, 32);
+
+    let mut flash = primary.borrow_mut();
+    assert!(!upgrade_requested(&mut *flash).unwrap());
+    drop(flash);
+    assert!(migrate_c_trailer(&primary).unwrap());
+    let mut flash = primary.borrow_mut();
+    assert!(upgrade_requested(&mut *flash).unwrap());
+    assert_eq!(image_ok(&mut *flash).unwrap(), Flag::Set);
+    assert_eq!(copy_done(&mut *flash).unwrap(), Flag::Set);
+    assert_eq!(last_boot_error(&mut *flash).unwrap(), None);
+    drop(flash);
+
+    // Not confirmed, it is a revert.
+    let primary = slot(1000);
+    primary.borrow_mut().write(end - 32, &[[0xff; 16].to_vec(), aligned_magic(32)].concat()).unwrap();
+    write_byte(&primary, end - 96, 0x01, 32);
+    assert!(migrate_c_trailer(&primary).unwrap());
+    let secondary = slot(1000);
+    assert_eq!(swap_type(&mut *primary.borrow_mut(), &mut *secondary.borrow_mut()).unwrap(), SwapType::Revert);
+
+    // A nonsense alignment isn't guessed at.
+    let primary = slot(1000);
+    primary.borrow_mut().write(end - 16, &aligned_magic(24)).unwrap();
+    assert!(migrate_c_trailer(&primary).is_err());
+}
+
+#[test]
+fn not_c() {
+    // Erased, and our own trailers, are left alone.
+    let secondary = slot(1000);
+    assert!(!migrate_c_trailer(&secondary).unwrap());
+    request_upgrade(&mut *secondary.borrow_mut()).unwrap();
+    record_boot_error(&mut *secondary.borrow_mut(), &BootError { code: ErrorCode::Flash, offset: 0x1000 }).unwrap();
+    let before = secondary.borrow().dump();
+    assert!(!migrate_c_trailer(&secondary).unwrap());
+    assert_eq!(secondary.borrow().dump(), before);
+
+    // An image reaching into the trailer's sector can't be migrated.
+    let end = SLOT.erase_size * SLOT.sectors;
+    let secondary = slot(end - 4096 - HEADER_SIZE);
+    secondary.borrow_mut().write(end - 16, &TRAILER_MAGIC).unwrap();
+    write_byte(&secondary, end - 40, 0x02, 8);
+    let before = secondary.borrow().dump();
+    assert!(migrate_c_trailer(&secondary).is_err());
+    assert_eq!(secondary.borrow().dump(), before);
+}
@@ -0,0 +1,42 @@
+// Memory protection.
+
+use boot::{protect, Access, Mpu, MpuError, Region};
+
+/// An MPU with power of two regions, recording what is programmed.
+#[derive(Default)]
+struct FakeMpu {
+    set: Vec<(usize, Region)>,
+}
+
+impl Mpu for FakeMpu {
+    fn regions(&self) -> usize {
+        2
+    }
+
+    fn supports(&self, region: &Region) -> bool {
+        let size = region.range.len();
+        size.is_power_of_two() && region.range.start.is_multiple_of(size)
+    }
+
+    unsafe fn set_region(&mut self, index: usize, region: &Region) {
+        self.set.push((index, region.clone()));
+    }
+}
+
+#[test]
+fn regions() {
+    let bootloader = Region::new(0..0x20000, Access::ReadOnly);
+    let status = Region::new(0x3f000..0x40000, Access::NoAccess);
+
+    let mut mpu = FakeMpu::default();
+    unsafe { protect(&mut mpu, &[bootloader.clone(), status.clone()]) }.unwrap();
+    assert_eq!(mpu.set, [(0, bootloader.clone()), (1, status.clone())]);
+
+    // Nothing is programmed unless all of the regions can be.
+    let mut mpu = FakeMpu::default();
+    let odd = Region::new(0x3f000..0x3f800 + 0x100, Access::NoAccess);
+    assert_eq!(unsafe { protect(&mut mpu, &[bootloader.clone(), odd]) }, Err(MpuError::Unsupported(1)));
+    let three = [bootloader.clone(), status.clone(), bootloader];
+    assert_eq!(unsafe { protect(&mut mpu, &three) }, Err(MpuError::TooManyRegions));
+    assert!(mpu.set.is_empty());
+}
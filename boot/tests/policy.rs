@@ -0,0 +1,88 @@
+// Upgrade version policy testing.
+
+use boot::{
+    check_version, BootAction, BootPolicy, BootView, DefaultPolicy, ErrorCode, ImageVersion, SlotView,
+    SwapType, VersionPolicy,
+};
+
+fn v(major: u8, minor: u8, revision: u16, build_num: u32) -> ImageVersion {
+    ImageVersion { major, minor, revision, build_num }
+}
+
+#[test]
+fn policies() {
+    let running = v(1, 2, 3, 4);
+    let newer = [v(1, 2, 4, 0), v(1, 3, 0, 0), v(2, 0, 0, 0)];
+    let rebuild = v(1, 2, 3, 5);
+    let same = running;
+    let older = [v(1, 2, 2, 9), v(0, 9, 9, 9)];
+
+    let check = |policy: VersionPolicy, upgrade: &ImageVersion| policy.accepts(&running, upgrade);
+    for upgrade in &newer {
+        for policy in [VersionPolicy::Any, VersionPolicy::Newer, VersionPolicy::AllowRebuild,
+                       VersionPolicy::AllowDowngrade(false), VersionPolicy::AllowDowngrade(true)] {
+            assert!(check(policy, upgrade), "{:?} {}", policy, upgrade);
+        }
+    }
+
+    assert!(!check(VersionPolicy::Newer, &rebuild));
+    assert!(check(VersionPolicy::AllowRebuild, &rebuild));
+    assert!(!check(VersionPolicy::AllowRebuild, &same));
+    assert!(!check(VersionPolicy::AllowDowngrade(false), &rebuild));
+    assert!(check(VersionPolicy::AllowDowngrade(true), &same));
+    for upgrade in &older {
+        assert!(check(VersionPolicy::Any, upgrade));
+        assert!(!check(VersionPolicy::Newer, upgrade));
+        assert!(!check(VersionPolicy::AllowRebuild, upgrade));
+        assert!(!check(VersionPolicy::AllowDowngrade(false), upgrade));
+        assert!(check(VersionPolicy::AllowDowngrade(true), upgrade));
+    }
+
+    // Only major version changes.
+    let major = VersionPolicy::Custom(|running, upgrade| upgrade.major > running.major);
+    assert!(check(major, &newer[2]));
+    assert!(!check(major, &newer[1]));
+}
+
+#[test]
+fn check() {
+    let running = v(2, 0, 0, 0);
+    assert!(check_version(&VersionPolicy::Newer, Some(&running), &v(3, 0, 0, 0)).is_ok());
+    assert!(matches!(check_version(&VersionPolicy::Newer, Some(&running), &v(1, 0, 0, 0)),
+                     Err(boot::Error::InvalidImage)));
+    // Anything goes on a blank device.
+    assert!(check_version(&VersionPolicy::Newer, None, &v(1, 0, 0, 0)).is_ok());
+}
+
+fn slot(major: u8, valid: bool) -> SlotView {
+    SlotView { version: Some(v(major, 0, 0, 0)), valid, dependencies_met: true }
+}
+
+#[test]
+fn default_policy() {
+    let policy = DefaultPolicy::default();
+    let decide = |swap_type, primary, secondary| {
+        policy.decide(&BootView { swap_type, confirmed: true, primary, secondary })
+    };
+    let good = slot(1, true);
+    let upgrade = slot(2, true);
+    let bad = slot(2, false);
+    let empty = SlotView::default();
+
+    assert_eq!(decide(SwapType::None, good, empty), BootAction::BootPrimary);
+    assert_eq!(decide(SwapType::None, bad, upgrade), BootAction::Recovery);
+    assert_eq!(decide(SwapType::Test, good, upgrade), BootAction::Swap { permanent: false });
+    assert_eq!(decide(SwapType::Perm, empty, upgrade), BootAction::Swap { permanent: true });
+    assert_eq!(decide(SwapType::Test, good, bad), BootAction::Reject(ErrorCode::InvalidImage));
+    assert_eq!(decide(SwapType::Test, empty, bad), BootAction::Recovery);
+    let unmet = SlotView { dependencies_met: false, ..upgrade };
+    assert_eq!(decide(SwapType::Test, good, unmet), BootAction::Reject(ErrorCode::InvalidImage));
+    assert_eq!(decide(SwapType::Revert, upgrade, good), BootAction::Revert);
+    assert_eq!(decide(SwapType::Revert, upgrade, empty), BootAction::BootPrimary);
+
+    // The version policy applies to upgrades, but not reverts.
+    let policy = DefaultPolicy { versions: VersionPolicy::Newer, ..DefaultPolicy::default() };
+    let view = |swap_type, primary, secondary| BootView { swap_type, confirmed: true, primary, secondary };
+    assert_eq!(policy.decide(&view(SwapType::Test, upgrade, good)), BootAction::Reject(ErrorCode::InvalidImage));
+    assert_eq!(policy.decide(&view(SwapType::Revert, upgrade, good)), BootAction::Revert);
+}
@@ -0,0 +1,99 @@
+// Serial recovery testing.
+
+use std::{cell::RefCell, collections::VecDeque};
+
+use boot::{
+    recovery::{self, Crc16, Serial},
+    Image,
+};
+
+/// A serial port that replays canned input, and captures the output.
+struct Scripted {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Scripted {
+    fn new() -> Scripted {
+        Scripted { input: VecDeque::new(), output: vec![] }
+    }
+
+    fn frame(&mut self, cmd: u8, payload: &[u8]) {
+        let len = (payload.len() as u16).to_le_bytes();
+        let mut crc = Crc16::new();
+        crc.update(&[cmd, len[0], len[1]]);
+        crc.update(payload);
+        self.input.push_back(recovery::SYNC);
+        self.input.extend([cmd, len[0], len[1]]);
+        self.input.extend(payload);
+        self.input.extend(crc.finish().to_le_bytes());
+    }
+
+    /// The status of each response.
+    fn statuses(&self) -> Vec<u8> {
+        self.output.chunks(3).map(|r| r[2]).collect()
+    }
+}
+
+impl Serial for Scripted {
+    fn read(&mut self) -> Option<u8> {
+        // Running out means the test is broken.
+        Some(self.input.pop_front().expect("Recovery read past end of script"))
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.output.extend_from_slice(bytes);
+    }
+}
+
+#[test]
+fn crc16() {
+    // The standard check value for CRC16/XMODEM.
+    let mut crc = Crc16::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.finish(), 0x31c3);
+}
+
+#[test]
+fn upload() {
+    let data = include_bytes!("../data/sample-signed.bin");
+
+    for (main, upgrade) in simflash::styles::ALL_FLASHES.iter() {
+        let mut slot0 = main.build().unwrap();
+        let mut slot1 = upgrade.build().unwrap();
+
+        // All of the write sizes divide the maximum data size.
+        let chunk_size = recovery::MAX_DATA;
+        assert_eq!(chunk_size % main.write_size, 0);
+
+        let mut serial = Scripted::new();
+        serial.frame(recovery::CMD_PING, &[]);
+        serial.frame(recovery::CMD_ERASE, &[0]);
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            let mut payload = vec![0];
+            payload.extend(((i * chunk_size) as u32).to_le_bytes());
+            payload.extend(chunk);
+            serial.frame(recovery::CMD_WRITE, &payload);
+        }
+        // An invalid slot, and a corrupted frame.
+        serial.frame(recovery::CMD_ERASE, &[7]);
+        serial.frame(recovery::CMD_PING, &[]);
+        let last = serial.input.len() - 1;
+        serial.input[last] ^= 1;
+        serial.frame(recovery::CMD_RESET, &[]);
+
+        recovery::run(&mut serial, &mut [&mut slot0, &mut slot1]);
+
+        let statuses = serial.statuses();
+        let writes = data.len().div_ceil(chunk_size);
+        assert_eq!(statuses.len(), writes + 5);
+        assert!(statuses[..writes + 2].iter().all(|&s| s == recovery::STATUS_OK));
+        assert_eq!(statuses[writes + 2], recovery::STATUS_BAD_SLOT);
+        assert_eq!(statuses[writes + 3], recovery::STATUS_BAD_CRC);
+        assert_eq!(statuses[writes + 4], recovery::STATUS_OK);
+
+        let slot0 = RefCell::new(slot0);
+        let image = Image::from_flash(&slot0).unwrap();
+        image.validate().unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+// Remap upgrade testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    confirm, copy_done, last_boot_error, remap_boot, remap_swap_type, request_upgrade, DefaultPolicy,
+    ErrorCode, Flag, Image, Remap, RemapRegion, RemapState, RemapStatus, SoftCrypto, SwapType,
+};
+use simflash::faulty::{Fault, FaultyFlash, Op};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// Remembers where the window points.
+#[derive(Default)]
+struct Window(Option<RemapRegion>);
+
+impl Remap for Window {
+    fn remap(&mut self, region: RemapRegion) {
+        self.0 = Some(region);
+    }
+}
+
+fn erased() -> SimFlash {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash
+}
+
+/// Download `image` into a region, requesting an upgrade to it.
+fn download(region: &RefCell<SimFlash>, image: &[u8], permanent: bool) {
+    let mut flash = region.borrow_mut();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(image, 0).unwrap();
+    request_upgrade(&mut *flash).unwrap();
+    if permanent {
+        confirm(&mut *flash).unwrap();
+    }
+}
+
+fn boot(regions: [&RefCell<SimFlash>; 2], status: &mut RemapStatus<SimFlash>, window: &mut Window)
+    -> boot::Result<RemapRegion>
+{
+    let validate = |image: &Image<'_, SimFlash>| image.validate_signed(&mut SoftCrypto::new(), KEY);
+    remap_boot(regions, status, window, &DefaultPolicy::default(), validate)
+}
+
+#[test]
+fn remap() {
+    let a = RefCell::new(erased());
+    a.borrow_mut().install(SAMPLE, 0).unwrap();
+    let b = RefCell::new(erased());
+    let regions = [&a, &b];
+    let mut status = RemapStatus::new(erased());
+    let mut window = Window::default();
+
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+    assert_eq!(window.0, Some(RemapRegion::A));
+
+    // A test upgrade is switched to, and reverted unless confirmed.
+    download(&b, SAMPLE, false);
+    assert_eq!(remap_swap_type(&status.state().unwrap(), regions).unwrap(), SwapType::Test);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::B);
+    assert_eq!(status.state().unwrap(), RemapState { active: RemapRegion::B, test: true });
+    assert_eq!(copy_done(&mut *b.borrow_mut()).unwrap(), Flag::Set);
+    assert_eq!(remap_swap_type(&status.state().unwrap(), regions).unwrap(), SwapType::Revert);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+    assert_eq!(window.0, Some(RemapRegion::A));
+
+    // The reverted upgrade isn't tried again.
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+
+    // Once confirmed, it stays.
+    download(&b, SAMPLE, false);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::B);
+    confirm(&mut *b.borrow_mut()).unwrap();
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::B);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::B);
+
+    // The next upgrade goes into the other region, and can be permanent.
+    download(&a, SAMPLE, true);
+    assert_eq!(remap_swap_type(&status.state().unwrap(), regions).unwrap(), SwapType::Perm);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+    assert_eq!(status.state().unwrap(), RemapState { active: RemapRegion::A, test: false });
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+
+    // A bad upgrade is rejected, and the window left alone.
+    let mut bad = SAMPLE.to_vec();
+    bad[300] ^= 1;
+    download(&b, &bad, false);
+    assert_eq!(boot(regions, &mut status, &mut window).unwrap(), RemapRegion::A);
+    let err = last_boot_error(&mut *b.borrow_mut()).unwrap().unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidImage);
+    assert_eq!(remap_swap_type(&status.state().unwrap(), regions).unwrap(), SwapType::None);
+
+    // Nothing valid to boot.
+    let size = a.borrow().capacity();
+    a.borrow_mut().erase(0, size).unwrap();
+    assert!(boot(regions, &mut status, &mut window).is_err());
+}
+
+#[test]
+fn status_log() {
+    let mut status = RemapStatus::new(erased());
+    assert_eq!(status.state().unwrap(), RemapState::default());
+
+    // Filling each half moves on to the other, keeping the newest record.
+    let records = {
+        let flash = erased();
+        flash.capacity() / flash.write_size().max(8)
+    };
+    for n in 0..records + 3 {
+        let active = if n % 2 == 0 { RemapRegion::B } else { RemapRegion::A };
+        let state = RemapState { active, test: n % 3 == 0 };
+        status.set_state(&state).unwrap();
+        assert_eq!(status.state().unwrap(), state);
+    }
+
+    // Anything that isn't a record is an error.
+    let mut flash = status.into_inner();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    let mut junk = vec![0xff; flash.write_size()];
+    junk[0] = 0x12;
+    flash.write(0, &junk).unwrap();
+    assert!(RemapStatus::new(flash).state().is_err());
+}
+
+#[test]
+fn status_power_cut() {
+    // The records that fit in half the area.
+    let half = {
+        let flash = erased();
+        flash.capacity() / flash.erase_size() / 2 * flash.erase_size() / flash.write_size().max(8)
+    };
+    let old = RemapState { active: RemapRegion::B, test: false };
+    let new = RemapState { active: RemapRegion::A, test: true };
+
+    // Power is lost once the other half is erased, before the record moving
+    // on to it is written.
+    let flash = FaultyFlash::new(erased()).fault(Fault::nth(Op::Write, half, storage::Error::Failed));
+    let mut status = RemapStatus::new(flash);
+    for _ in 0..half {
+        status.set_state(&old).unwrap();
+    }
+    assert!(status.set_state(&new).is_err());
+    let flash = status.into_inner();
+    assert_eq!(flash.count(Op::Erase), 1);
+
+    // The old record is still the newest, and the switch can be tried again.
+    let mut status = RemapStatus::new(flash.into_inner());
+    assert_eq!(status.state().unwrap(), old);
+    status.set_state(&new).unwrap();
+    assert_eq!(status.state().unwrap(), new);
+
+    // Power is lost during the erase.
+    let flash = FaultyFlash::new(erased()).fault(Fault::nth(Op::Erase, 0, storage::Error::Failed));
+    let mut status = RemapStatus::new(flash);
+    for _ in 0..half {
+        status.set_state(&old).unwrap();
+    }
+    assert!(status.set_state(&new).is_err());
+    let mut status = RemapStatus::new(status.into_inner().into_inner());
+    assert_eq!(status.state().unwrap(), old);
+}
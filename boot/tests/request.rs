@@ -0,0 +1,19 @@
+// Boot request testing.
+
+use boot::{request_recovery, take_recovery_request, Retained, RetainedWord};
+
+#[test]
+fn request() {
+    let mut word = 0xdead_beefu32;
+    let mut word = unsafe { RetainedWord::new(&mut word as *mut u32 as usize) };
+
+    // Random contents are not a request.
+    assert!(!take_recovery_request(&mut word));
+
+    request_recovery(&mut word);
+    assert!(take_recovery_request(&mut word));
+
+    // The request only applies once.
+    assert!(!take_recovery_request(&mut word));
+    assert_eq!(word.read(), 0);
+}
@@ -0,0 +1,165 @@
+// Reverting by copying back, skipping the groups that already match.
+
+use boot::{copy_back, CopyBack, Error, SlotInfo, SoftCrypto, Status, StatusLayout, StatusStyle};
+use simflash::faulty::{Fault, FaultyFlash, Op};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+/// The image replaced by an upgrade, of a little less than `sectors` of the
+/// status layout's sectors, and the upgrade, which differs from it only in
+/// its first and third sectors.
+fn images(erase_size: usize, sectors: usize) -> (Vec<u8>, Vec<u8>) {
+    let old: Vec<u8> = (0..sectors * erase_size - 100).map(|i| (i * 7 + i / 251) as u8).collect();
+    let mut new = old.clone();
+    new[10] ^= 0xff;
+    new[2 * erase_size + 10] ^= 0xff;
+    (old, new)
+}
+
+/// A device as an upgrade on test leaves it: the upgrade in the primary
+/// slot, with the status and the hashes of both images, and the old image in
+/// the secondary.  None if the images and the status don't fit.
+fn tested(mut primary: SimFlash, mut secondary: SimFlash, sectors: usize)
+    -> Option<(StatusLayout, SimFlash, SimFlash, Vec<u8>)>
+{
+    let erase_size = primary.erase_size().max(secondary.erase_size());
+    let (old, new) = images(erase_size, sectors);
+    let layout = SlotInfo::from_data(old.len(), &primary)
+        .status_layout(&SlotInfo::from_data(new.len(), &secondary)).ok()?;
+    if (sectors + layout.status_sectors()) * erase_size > primary.capacity().min(secondary.capacity()) {
+        return None;
+    }
+
+    let (size, other) = (primary.capacity(), secondary.capacity());
+    primary.erase(0, size).unwrap();
+    secondary.erase(0, other).unwrap();
+    primary.install(&new, 0).unwrap();
+    secondary.install(&old, 0).unwrap();
+    let status = Status {
+        main_size: old.len() as u32,
+        upgrade_size: new.len() as u32,
+        hash_seed: 0x1234_5678,
+        generation: 1,
+        ..Status::default()
+    };
+    layout.write(&mut primary, &status).unwrap();
+
+    let mut crypto = SoftCrypto::new();
+    let mut hashes = vec![];
+    for image in 0..2 {
+        for group in 0..layout.image_groups(image) {
+            let flash = if image == 0 { &mut secondary } else { &mut primary };
+            hashes.push(layout.record_hash(&mut crypto, &status, image, group, flash).unwrap());
+        }
+    }
+    layout.write_hashes(&mut primary, &hashes).unwrap();
+    Some((layout, primary, secondary, old))
+}
+
+/// Each pair of slots, with the sectors of image to use: the styles, with a
+/// few, and slots of small sectors, with enough that some of the hashes are
+/// in pages of their own, in overwrite and paged modes.
+fn devices() -> impl Iterator<Item = (SimFlash, SimFlash, usize)> {
+    let small = |write_size, sectors| SimFlash::new(1, write_size, 512, sectors).unwrap();
+    simflash::styles::all_flashes()
+        .map(|flashes| flashes.unwrap())
+        .map(|(primary, secondary)| (primary, secondary, 4))
+        .chain([(small(8, 80), small(8, 80), 60), (small(512, 160), small(512, 160), 70)])
+}
+
+/// A device like `flash`, holding the same.
+fn copy_of(flash: &SimFlash) -> SimFlash {
+    let sectors = flash.capacity() / flash.erase_size();
+    let mut copy = SimFlash::new(flash.read_size(), flash.write_size(), flash.erase_size(), sectors).unwrap();
+    copy.load(&flash.dump()).unwrap();
+    copy
+}
+
+#[test]
+fn copy_back_skips() {
+    let mut count = 0;
+    let mut hash_pages = false;
+    for (primary, secondary, sectors) in devices() {
+        let Some((layout, mut primary, mut secondary, old)) = tested(primary, secondary, sectors) else { continue };
+        let mut crypto = SoftCrypto::new();
+        hash_pages |= !layout.hash_pages.is_empty();
+
+        // Only the first and third sectors differ.
+        let groups = layout.image_groups(0);
+        let done = copy_back(&mut crypto, &layout, 0, &mut secondary, &mut primary).unwrap();
+        assert_eq!(done, CopyBack { copied: 2, skipped: groups - 2 });
+        assert_eq!(primary.dump()[..old.len()], old);
+
+        // Once back, there is nothing to do.
+        let done = copy_back(&mut crypto, &layout, 0, &mut secondary, &mut primary).unwrap();
+        assert_eq!(done, CopyBack { copied: 0, skipped: groups });
+        count += 1;
+    }
+    assert!(count > 0 && hash_pages);
+}
+
+/// Cut the power at each erase and write of the copy.  Going again finishes
+/// the copy, and skips what was done before the cut.
+#[test]
+fn copy_back_power_cut() {
+    let mut count = 0;
+    for (primary, secondary, sectors) in devices() {
+        let Some((layout, primary, mut secondary, old)) = tested(primary, secondary, sectors) else { continue };
+        let mut crypto = SoftCrypto::new();
+
+        for op in [Op::Erase, Op::Write] {
+            for n in 0.. {
+                let mut faulty = FaultyFlash::new(copy_of(&primary))
+                    .fault(Fault::nth(op, n, storage::Error::Failed));
+                let result = copy_back(&mut crypto, &layout, 0, &mut secondary, &mut faulty);
+                let done = faulty.count(op) <= n;
+                let mut flash = faulty.into_inner();
+                assert_eq!(result.is_ok(), done);
+
+                let again = copy_back(&mut crypto, &layout, 0, &mut secondary, &mut flash).unwrap();
+                assert_eq!(flash.dump()[..old.len()], old);
+                if done {
+                    break;
+                }
+                assert!(again.copied <= 2);
+                count += 1;
+            }
+        }
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn copy_back_refused() {
+    for (primary, secondary, sectors) in devices() {
+        let Some((layout, mut primary, mut secondary, _)) = tested(primary, secondary, sectors) else { continue };
+        let mut crypto = SoftCrypto::new();
+
+        // A copy that has changed since the swap isn't written.
+        let erase_size = layout.erase_size;
+        let mut changed = secondary.dump();
+        changed[2 * erase_size] ^= 0x01;
+        secondary.load(&changed).unwrap();
+        assert!(matches!(copy_back(&mut crypto, &layout, 0, &mut secondary, &mut primary),
+                         Err(Error::InvalidImage)));
+
+        // Without a status, there are no hashes to go by.
+        let size = primary.capacity();
+        primary.erase(size - layout.status_sectors() * erase_size, size).unwrap();
+        assert!(matches!(copy_back(&mut crypto, &layout, 0, &mut secondary, &mut primary),
+                         Err(Error::CannotUpgrade)));
+    }
+
+    // Nor does minimal mode keep any.
+    let (mut primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = primary.capacity();
+    let info = |flash: &SimFlash| SlotInfo::from_data(size / 4, flash).with_minimal_status();
+    let layout = info(&primary).status_layout(&info(&secondary)).unwrap();
+    assert_eq!(layout.style, StatusStyle::Minimal);
+    primary.erase(0, size).unwrap();
+    layout.write(&mut primary, &Status { main_size: 100, hash_seed: 1, ..Status::default() }).unwrap();
+    assert!(matches!(layout.write_hashes(&mut primary, &[]), Err(Error::CannotUpgrade)));
+    let mut secondary = secondary;
+    assert!(matches!(copy_back(&mut SoftCrypto::new(), &layout, 0, &mut secondary, &mut primary),
+                     Err(Error::CannotUpgrade)));
+}
@@ -0,0 +1,119 @@
+// Anti-rollback testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    advance_rollback, check_rollback, FlashCounter, Image, Otp, OtpCounter, RollbackCounter,
+};
+use simflash::gen::ImageBuilder;
+use storage::{Flash, ReadFlash};
+
+/// Build an image with the given security counter, if any, and just a SHA256.
+fn build(counter: Option<u32>) -> Vec<u8> {
+    let payload: Vec<u8> = (0..1000u32).map(|i| (i * 3) as u8).collect();
+    let mut image = ImageBuilder::default();
+    image.payload(&payload);
+    if let Some(counter) = counter {
+        image.protected(0x50, &counter.to_le_bytes());
+    }
+    image.build()
+}
+
+/// OTP, simulated in memory.
+struct SimOtp(Vec<u32>);
+
+impl Otp for SimOtp {
+    fn words(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read_word(&mut self, index: usize) -> boot::Result<u32> {
+        Ok(self.0[index])
+    }
+
+    fn program_word(&mut self, index: usize, bits: u32) -> boot::Result<()> {
+        self.0[index] |= bits;
+        Ok(())
+    }
+}
+
+#[test]
+fn flash_counter() {
+    for flashes in simflash::styles::all_flashes() {
+        let (_, mut flash) = flashes.unwrap();
+        let size = flash.capacity();
+        flash.erase(0, size).unwrap();
+
+        let mut counter = FlashCounter::new(flash);
+        assert_eq!(counter.read().unwrap(), 0);
+        counter.advance(3).unwrap();
+        assert_eq!(counter.read().unwrap(), 3);
+
+        // Never moves backward.
+        counter.advance(2).unwrap();
+        assert_eq!(counter.read().unwrap(), 3);
+
+        // The advance must persist across a reboot.
+        let flash = counter.into_inner();
+        let mut counter = FlashCounter::new(flash);
+        assert_eq!(counter.read().unwrap(), 3);
+        counter.advance(7).unwrap();
+        let mut counter = FlashCounter::new(counter.into_inner());
+        assert_eq!(counter.read().unwrap(), 7);
+    }
+}
+
+#[test]
+fn otp_counter() {
+    let mut counter = OtpCounter::new(SimOtp(vec![0; 2]));
+    assert_eq!(counter.read().unwrap(), 0);
+    counter.advance(35).unwrap();
+    assert_eq!(counter.read().unwrap(), 35);
+    counter.advance(10).unwrap();
+    assert_eq!(counter.read().unwrap(), 35);
+
+    // Persisted in the OTP itself.
+    let otp = counter.into_inner();
+    assert_eq!(otp.0, vec![0xffff_ffff, 0x7]);
+    let mut counter = OtpCounter::new(otp);
+    assert_eq!(counter.read().unwrap(), 35);
+
+    // Out of bits.
+    assert!(counter.advance(65).is_err());
+}
+
+#[test]
+fn rollback() {
+    let mut flash = simflash::styles::STM32H_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    let mut counter = FlashCounter::new(flash);
+
+    let install = |data: &[u8]| {
+        let mut slot = simflash::styles::STM32H_UPGRADE.build().unwrap();
+        slot.install(data, 0).unwrap();
+        RefCell::new(slot)
+    };
+
+    // Images without a counter have a counter of zero.
+    let none = install(&build(None));
+    let none = Image::from_flash(&none).unwrap();
+    none.validate().unwrap();
+    assert_eq!(none.security_counter().unwrap(), 0);
+
+    let old = install(&build(Some(2)));
+    let new = install(&build(Some(5)));
+    let old = Image::from_flash(&old).unwrap();
+    let new = Image::from_flash(&new).unwrap();
+    new.validate().unwrap();
+    assert_eq!(new.security_counter().unwrap(), 5);
+
+    check_rollback(&old, &mut counter).unwrap();
+    check_rollback(&new, &mut counter).unwrap();
+
+    // Once the new image is confirmed, the old one is refused.
+    advance_rollback(&new, &mut counter).unwrap();
+    check_rollback(&new, &mut counter).unwrap();
+    assert!(check_rollback(&old, &mut counter).is_err());
+    assert!(check_rollback(&none, &mut counter).is_err());
+}
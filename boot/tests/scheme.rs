@@ -0,0 +1,96 @@
+// Signature scheme registry.
+
+use std::cell::RefCell;
+
+use boot::{CryptoBackend, EcdsaP256, Hash256, Image, SignatureScheme, SoftCrypto};
+use simflash::SimFlash;
+
+/// A made up scheme, whose signature is the SHA256 of the key and the hash.
+struct Toy;
+
+const TLV_TOY_SIG: u16 = 0x7f10;
+
+impl<C: CryptoBackend> SignatureScheme<C> for Toy {
+    fn tlv_kind(&self) -> u16 {
+        TLV_TOY_SIG
+    }
+
+    fn verify(&self, crypto: &mut C, key: &[u8], hash: &Hash256, signature: &[u8]) -> bool {
+        crypto.sha256_start();
+        crypto.sha256_update(key);
+        crypto.sha256_update(hash);
+        crypto.sha256_finish() == signature
+    }
+}
+
+fn toy_sign(key: &[u8], hash: &Hash256) -> Hash256 {
+    let mut crypto = SoftCrypto::new();
+    crypto.sha256_start();
+    crypto.sha256_update(key);
+    crypto.sha256_update(hash);
+    crypto.sha256_finish()
+}
+
+fn load(data: &[u8]) -> RefCell<SimFlash> {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    RefCell::new(flash)
+}
+
+/// The sample image, with a TLV appended.
+fn with_tlv(image: &[u8], kind: u16, data: &[u8]) -> Vec<u8> {
+    // The sample has no protected TLVs.
+    let start = 256 + u32::from_le_bytes(image[12..16].try_into().unwrap()) as usize;
+    let len = u16::from_le_bytes([image[start + 2], image[start + 3]]) as usize;
+    let mut result = image[..start + len].to_vec();
+    result.extend(kind.to_le_bytes());
+    result.extend((data.len() as u16).to_le_bytes());
+    result.extend(data);
+    let len = (len + 4 + data.len()) as u16;
+    result[start + 2..start + 4].copy_from_slice(&len.to_le_bytes());
+    result
+}
+
+#[test]
+fn ecdsa() {
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+    let flash = load(include_bytes!("../data/sample-ecdsa.bin"));
+    let image = Image::from_flash(&flash).unwrap();
+    let mut crypto = SoftCrypto::new();
+
+    image.validate_signed_by(&mut crypto, key, &[&EcdsaP256]).unwrap();
+    image.validate_signed_by(&mut crypto, key, &[&Toy, &EcdsaP256]).unwrap();
+
+    // Without the scheme, the signature isn't recognized.
+    assert!(image.validate_signed_by(&mut crypto, key, &[]).is_err());
+    assert!(image.validate_signed_by(&mut crypto, key, &[&Toy]).is_err());
+}
+
+#[test]
+fn custom() {
+    let key = b"a proprietary key";
+    let sample = include_bytes!("../data/sample-signed.bin");
+    let flash = load(sample);
+    let hash = Image::from_flash(&flash).unwrap().stored_sha256().unwrap();
+    let flash = load(&with_tlv(sample, TLV_TOY_SIG, &toy_sign(key, &hash)));
+    let image = Image::from_flash(&flash).unwrap();
+    let mut crypto = SoftCrypto::new();
+
+    image.validate_signed_by(&mut crypto, key, &[&EcdsaP256, &Toy]).unwrap();
+    assert!(image.validate_signed_by(&mut crypto, b"another key", &[&EcdsaP256, &Toy]).is_err());
+    assert!(image.validate_signed(&mut crypto, key).is_err());
+
+    // Carrying the key in the image.
+    let signed = with_tlv(sample, TLV_TOY_SIG, &toy_sign(key, &hash));
+    let flash = load(&with_tlv(&signed, 0x02, key));
+    let image = Image::from_flash(&flash).unwrap();
+    let mut crypto = SoftCrypto::new();
+    crypto.sha256_start();
+    crypto.sha256_update(key);
+    let key_hash = crypto.sha256_finish();
+    image.validate_key_hash_by(&mut crypto, &key_hash, &[&Toy]).unwrap();
+    assert!(image.validate_key_hash(&mut crypto, &key_hash).is_err());
+
+    // Without a key, an unknown signature is rejected.
+    assert!(image.validate().is_err());
+}
@@ -0,0 +1,25 @@
+// Scrubbing before chaining.
+
+use boot::{scrub_stack, zeroize, CryptoBackend, SoftCrypto};
+
+#[test]
+fn zero() {
+    let mut key = [0x5au8; 16];
+    zeroize(&mut key);
+    assert_eq!(key, [0; 16]);
+    scrub_stack();
+}
+
+#[test]
+fn soft_crypto() {
+    let mut crypto = SoftCrypto::new();
+    crypto.sha256_start();
+    crypto.sha256_update(b"abc");
+    let abc = crypto.sha256_finish();
+
+    // What was hashed before the scrub is gone.
+    crypto.sha256_update(b"secret");
+    crypto.scrub();
+    crypto.sha256_update(b"abc");
+    assert_eq!(crypto.sha256_finish(), abc);
+}
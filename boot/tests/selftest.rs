@@ -0,0 +1,52 @@
+// Self-tested confirmation testing.
+
+use boot::{
+    confirm_after, is_confirmed, record_test_boot, test_boot_attempts, BootAction, BootPolicy,
+    BootView, DefaultPolicy, ImageVersion, RetainedWord, SlotView, SwapType, TestAttempts,
+};
+use storage::{Flash, ReadFlash};
+
+#[test]
+fn confirm_on_pass() {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+
+    // A failing self-test leaves the image on test.
+    assert!(!confirm_after(&mut flash, || false).unwrap());
+    assert!(!is_confirmed(&mut flash).unwrap());
+
+    assert!(confirm_after(&mut flash, || true).unwrap());
+    assert!(is_confirmed(&mut flash).unwrap());
+
+    // Once confirmed, there is nothing left to test.
+    assert!(confirm_after(&mut flash, || panic!("Self-test run again")).unwrap());
+}
+
+#[test]
+fn attempts() {
+    let mut word = 0xdead_beefu32;
+    let mut word = unsafe { RetainedWord::new(&mut word as *mut u32 as usize) };
+    assert_eq!(test_boot_attempts(&word), None);
+    assert_eq!(record_test_boot(&mut word, true), 1);
+    assert_eq!(record_test_boot(&mut word, true), 2);
+    assert_eq!(test_boot_attempts(&word), Some(2));
+    assert_eq!(record_test_boot(&mut word, false), 0);
+    assert_eq!(test_boot_attempts(&word), None);
+}
+
+#[test]
+fn attempts_policy() {
+    let version = Some(ImageVersion::default());
+    let good = SlotView { version, valid: true, dependencies_met: true };
+    let view = BootView { swap_type: SwapType::Revert, confirmed: false, primary: good, secondary: good };
+    let policy = |attempts| TestAttempts { policy: DefaultPolicy::default(), attempts, max: 2 };
+    assert_eq!(policy(1).decide(&view), BootAction::BootPrimary);
+    assert_eq!(policy(2).decide(&view), BootAction::BootPrimary);
+    assert_eq!(policy(3).decide(&view), BootAction::Revert);
+    assert_eq!(policy(0).decide(&view), BootAction::Revert);
+
+    // An image on test that doesn't validate goes straight back.
+    let broken = BootView { primary: SlotView { valid: false, ..good }, ..view };
+    assert_eq!(policy(1).decide(&broken), BootAction::Revert);
+}
@@ -0,0 +1,53 @@
+// Shared data testing.
+
+use boot::{
+    clear_shared, read_shared, write_shared, BootInfo, ImageVersion, SharedError, ERROR_INVALID_IMAGE,
+    SHARED_SIZE,
+};
+
+fn info() -> BootInfo {
+    BootInfo {
+        version: ImageVersion { major: 1, minor: 2, revision: 3, build_num: 4 },
+        image_base: 0x1002_0000,
+        image_size: 0x4321,
+        slot: 1,
+        reserved: 0,
+        last_error: ERROR_INVALID_IMAGE,
+        stack_used: 0x1a40,
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut buf = [0xa5u8; 64];
+    assert_eq!(read_shared(&buf), Err(SharedError::Missing));
+
+    write_shared(&mut buf, &info()).unwrap();
+    assert_eq!(read_shared(&buf), Ok(info()));
+
+    clear_shared(&mut buf);
+    assert_eq!(read_shared(&buf), Err(SharedError::Missing));
+}
+
+#[test]
+fn rejected() {
+    let mut small = [0u8; SHARED_SIZE - 1];
+    assert_eq!(write_shared(&mut small, &info()), Err(SharedError::TooSmall));
+    assert_eq!(read_shared(&small), Err(SharedError::TooSmall));
+
+    let mut buf = [0u8; SHARED_SIZE];
+    write_shared(&mut buf, &info()).unwrap();
+
+    // The layout is described by the version and length after the magic.
+    let mut other = buf;
+    other[4] = 4;
+    assert_eq!(read_shared(&other), Err(SharedError::Version(4)));
+    let mut other = buf;
+    other[6] += 4;
+    assert_eq!(read_shared(&other), Err(SharedError::Length));
+
+    // The payload is covered by the CRC.
+    let mut other = buf;
+    other[SHARED_SIZE - 5] ^= 1;
+    assert_eq!(read_shared(&other), Err(SharedError::Crc));
+}
@@ -0,0 +1,125 @@
+// Slot testing.
+
+use std::cell::RefCell;
+use std::sync::Mutex;
+
+use boot::{
+    boot_state, confirm, request_upgrade, swap_type, FlashCell, Image, Slot, SlotInfo, SlotPurpose, SoftCrypto, Status,
+    StatusRead, SwapType, STATUS_VERSION,
+};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+const SECTOR: usize = 4096;
+const SLOT: usize = 32 * SECTOR;
+
+/// A device with room for both slots, and a sector after them.
+fn device() -> RefCell<SimFlash> {
+    let mut dev = SimFlash::new(1, 8, SECTOR, 2 * SLOT / SECTOR + 1).unwrap();
+    let size = dev.capacity();
+    dev.erase(0, size).unwrap();
+    RefCell::new(dev)
+}
+
+#[test]
+fn shared_device() {
+    let dev = device();
+    let mut primary = Slot::new(&dev, 0, SLOT, SlotPurpose::Primary).unwrap();
+    let mut secondary = Slot::new(&dev, SLOT, SLOT, SlotPurpose::Secondary).unwrap();
+    assert_eq!(secondary.base(), SLOT);
+    assert_eq!(secondary.purpose(), SlotPurpose::Secondary);
+    assert_eq!(secondary.capacity(), SLOT);
+
+    // The image and trailer code see each slot from its own start.
+    dev.borrow_mut().install(SAMPLE, SLOT).unwrap();
+    request_upgrade(&mut secondary).unwrap();
+    assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Test);
+    confirm(&mut secondary).unwrap();
+    assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Perm);
+
+    let secondary = RefCell::new(secondary);
+    let image = Image::from_flash(&secondary).unwrap();
+    image.validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    let primary = RefCell::new(primary);
+    let state = boot_state(&primary, &secondary).unwrap();
+    assert_eq!(state.secondary_version, Some(image.version()));
+    assert_eq!(state.primary_version, None);
+
+    // The trailer went at the end of the slot, not of the device.
+    let data = dev.borrow().dump();
+    assert_ne!(data[2 * SLOT - 16..2 * SLOT], [0xff; 16]);
+    assert_eq!(data[2 * SLOT..], vec![0xff; SECTOR][..]);
+
+    // So does the status.
+    let mut primary = primary.into_inner();
+    let secondary = secondary.into_inner();
+    let info = SlotInfo::from_data(SLOT / 2, &primary);
+    let layout = info.status_layout(&SlotInfo::from_data(SLOT / 2, &secondary)).unwrap();
+    let status = Status {
+        version: STATUS_VERSION,
+        group: 1,
+        enc_key: [0; 16],
+        main_size: 0x1000,
+        upgrade_size: 0x2000,
+        hash_seed: 0x1234_5678,
+        write_log: 3,
+        erase_log: 12,
+        flags: 0xfe,
+        age: 0xff,
+        generation: 1,
+    };
+    layout.write(&mut primary, &status).unwrap();
+    assert!(matches!(layout.read(&mut primary).unwrap(), StatusRead::Valid(read) if read.main_size == 0x1000));
+    assert!(dev.borrow().dump()[SLOT - SECTOR..SLOT].iter().any(|&b| b != 0xff));
+    assert!(matches!(layout.read(&mut Slot::new(&dev, SLOT, SLOT, SlotPurpose::Secondary).unwrap()),
+                     Ok(StatusRead::Empty)));
+}
+
+#[test]
+fn bounds() {
+    let dev = device();
+    let size = dev.borrow().capacity();
+    assert!(Slot::new(&dev, SECTOR / 2, SLOT, SlotPurpose::Primary).is_err());
+    assert!(Slot::new(&dev, 0, SLOT + 1, SlotPurpose::Primary).is_err());
+    assert!(Slot::new(&dev, size - SECTOR, 2 * SECTOR, SlotPurpose::Scratch).is_err());
+    assert!(Slot::new(&dev, usize::MAX - SECTOR + 1, 2 * SECTOR, SlotPurpose::Scratch).is_err());
+
+    // Nothing outside the slot can be reached through it.
+    let mut scratch = Slot::new(&dev, size - SECTOR, SECTOR, SlotPurpose::Scratch).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(scratch.read(SECTOR - 4, &mut buf), Err(storage::Error::OutOfBounds));
+    assert_eq!(scratch.write(SECTOR, &buf), Err(storage::Error::OutOfBounds));
+    assert_eq!(scratch.erase(0, 2 * SECTOR), Err(storage::Error::OutOfBounds));
+    scratch.write(0, &buf).unwrap();
+    assert_eq!(dev.borrow().dump()[size - SECTOR..size - SECTOR + 8], buf);
+}
+
+/// A device behind a mutex, as a target sharing it between tasks would have.
+struct Locked(Mutex<SimFlash>);
+
+impl FlashCell for Locked {
+    type Flash = SimFlash;
+
+    fn with<R>(&self, f: impl FnOnce(&mut SimFlash) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+#[test]
+fn other_cells() {
+    let dev = Locked(Mutex::new(device().into_inner()));
+    let mut primary = Slot::new(&dev, 0, SLOT, SlotPurpose::Primary).unwrap();
+    let mut secondary = Slot::new(&dev, SLOT, SLOT, SlotPurpose::Secondary).unwrap();
+
+    // Both slots on the device can be used together, as with a RefCell.
+    dev.with(|flash| flash.install(SAMPLE, SLOT)).unwrap();
+    request_upgrade(&mut secondary).unwrap();
+    assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Test);
+    let secondary = RefCell::new(secondary);
+    let image = Image::from_flash(&secondary).unwrap();
+    image.validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    assert!(dev.with(|flash| flash.dump())[..SLOT].iter().all(|&b| b == 0xff));
+}
@@ -0,0 +1,50 @@
+// Stack usage.
+
+use std::cell::RefCell;
+
+use boot::{measure_stack, stack_used, Image, SoftCrypto, STACK_PAINT};
+
+#[test]
+fn high_water() {
+    // A painted area, with the top 40 bytes used.
+    let mut area = vec![STACK_PAINT; 64];
+    for word in &mut area[54..] {
+        *word = 0;
+    }
+    let bottom = area.as_ptr() as usize;
+    let top = bottom + 4 * area.len();
+    assert_eq!(unsafe { stack_used(bottom, top) }, 40);
+
+    area[0] = 0;
+    assert_eq!(unsafe { stack_used(bottom, top) }, 256);
+}
+
+#[inline(never)]
+fn deep() -> u8 {
+    let buf = std::hint::black_box([1u8; 16 * 1024]);
+    buf.iter().fold(0, |a, &b| a.wrapping_add(b))
+}
+
+#[test]
+fn measured() {
+    let (sum, used) = measure_stack(deep);
+    assert_eq!(sum, 0);
+    assert!(used >= 16 * 1024, "{} bytes", used);
+}
+
+/// The worst case of validating a signed image.
+#[test]
+fn validation() {
+    let data = include_bytes!("../data/sample-ecdsa.bin");
+    let key = include_bytes!("../data/ecdsa-p256-pub.der");
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(flash);
+
+    let (result, used) = measure_stack(|| {
+        Image::from_flash(&flash).and_then(|image| image.validate_signed(&mut SoftCrypto::new(), key))
+    });
+    result.unwrap();
+    println!("validate_signed: {} bytes of stack", used);
+    assert!(used > 0 && used < 256 * 1024);
+}
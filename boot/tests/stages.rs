@@ -0,0 +1,116 @@
+// Multi-stage boot testing.
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+
+use boot::{
+    chain_next, chain_stages, read_handoff, validate_stages, write_handoff, Chainer, Handoff, Image,
+    MappedFlash, SharedError, SoftCrypto, Stages, HANDOFF_SIZE, MAX_STAGES,
+};
+use simflash::SimFlash;
+use storage::ReadFlash;
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// A stage's slot, mapped into memory at `base`.
+struct Mapped {
+    flash: SimFlash,
+    base: usize,
+}
+
+impl ReadFlash for Mapped {
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl MappedFlash for Mapped {
+    fn get_base(&self) -> usize {
+        self.base
+    }
+}
+
+fn slot(data: &[u8], base: usize) -> RefCell<Mapped> {
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    RefCell::new(Mapped { flash, base })
+}
+
+/// Reports where it would have jumped, and with what, by panicking.
+struct Recorder;
+
+impl Chainer for Recorder {
+    unsafe fn chain(&self, base: usize) -> ! {
+        panic::panic_any((base, 0usize))
+    }
+}
+
+impl Handoff for Recorder {
+    unsafe fn chain_with(&self, base: usize, arg: usize) -> ! {
+        panic::panic_any((base, arg))
+    }
+}
+
+fn jump(chain: impl FnOnce()) -> (usize, usize) {
+    let err = panic::catch_unwind(AssertUnwindSafe(chain)).unwrap_err();
+    *err.downcast::<(usize, usize)>().unwrap()
+}
+
+#[test]
+fn stages() {
+    let loader = slot(SAMPLE, 0x1001_0000);
+    let app = slot(SAMPLE, 0x1004_0000);
+    let images = [Image::from_flash(&loader).unwrap(), Image::from_flash(&app).unwrap()];
+    let stages = validate_stages(&images, |image| image.validate_signed(&mut SoftCrypto::new(), KEY)).unwrap();
+    assert_eq!(stages.stages().len(), 2);
+    let first = stages.stages()[0];
+    let second = stages.stages()[1];
+    assert_eq!(first.code_base as usize, images[0].get_image_base().unwrap());
+    assert_eq!(second.code_base as usize, images[1].get_image_base().unwrap());
+    assert_eq!(second.image_size as usize, images[1].full_image_size());
+    assert_eq!(second.version, images[1].version());
+    assert_eq!(stages.next(first.code_base), Some(&second));
+    assert_eq!(stages.next(second.code_base), None);
+
+    // The whole chain is checked before anything is started.
+    let mut bad = SAMPLE.to_vec();
+    bad[300] ^= 1;
+    let app = slot(&bad, 0x1004_0000);
+    let images = [Image::from_flash(&loader).unwrap(), Image::from_flash(&app).unwrap()];
+    assert!(validate_stages(&images, |image| image.validate_signed(&mut SoftCrypto::new(), KEY)).is_err());
+    assert!(validate_stages::<Mapped, _>(&[], |_| Ok(())).is_err());
+    let many: Vec<_> = (0..=MAX_STAGES).map(|_| Image::from_flash(&loader).unwrap()).collect();
+    assert!(validate_stages(&many, |_| Ok(())).is_err());
+}
+
+#[test]
+fn handoff() {
+    let mut stages = Stages { count: 2, ..Stages::default() };
+    stages.stages[0].code_base = 0x1001_0200;
+    stages.stages[1].code_base = 0x1004_0200;
+
+    let mut buf = [0u8; HANDOFF_SIZE];
+    assert_eq!(read_handoff(&buf), Err(SharedError::Missing));
+    assert_eq!(write_handoff(&mut buf[..HANDOFF_SIZE - 1], &stages), Err(SharedError::TooSmall));
+    write_handoff(&mut buf, &stages).unwrap();
+    assert_eq!(read_handoff(&buf), Ok(stages));
+
+    // Each stage is given the block, and starts the next.
+    let arg = buf.as_ptr() as usize;
+    assert_eq!(jump(|| unsafe { chain_stages(&Recorder, &buf).unwrap(); }), (0x1001_0200, arg));
+    assert_eq!(jump(|| unsafe { chain_next(&Recorder, &buf, 0x1001_0200).unwrap(); }), (0x1004_0200, arg));
+    assert!(unsafe { chain_next(&Recorder, &buf, 0x1004_0200) }.is_err());
+
+    // Without a block, nothing is started.
+    buf[0] ^= 1;
+    assert!(unsafe { chain_stages(&Recorder, &buf) }.is_err());
+}
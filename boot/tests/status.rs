@@ -0,0 +1,457 @@
+// Status testing.
+
+use boot::{
+    advance_generation, check_generation, confirm, copy_done, image_ok, next_generation, request_upgrade,
+    set_copy_done, trailer_size, upgrade_requested, Error, Flag, FlashCounter, RollbackCounter, SlotInfo,
+    Status, StatusLayout, StatusRead, StatusStyle, MAX_PROGRESS_GROUP, MAX_STATUS_PAGES, STATUS_VERSION,
+    TRAILER_MAGIC,
+};
+use simflash::faulty::{Fault, FaultyFlash, Op};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+#[test]
+fn request() {
+    for flashes in simflash::styles::all_flashes() {
+        let (_, mut flash) = flashes.unwrap();
+        let size = flash.capacity();
+        flash.erase(0, size).unwrap();
+        assert!(!upgrade_requested(&mut flash).unwrap());
+
+        request_upgrade(&mut flash).unwrap();
+        assert!(upgrade_requested(&mut flash).unwrap());
+
+        // Erasing the slot clears the request.
+        flash.erase(0, size).unwrap();
+        assert!(!upgrade_requested(&mut flash).unwrap());
+    }
+}
+
+/// The status layout of each slot, and the offset of its tail.
+fn layout(flash: &SimFlash, other: &SimFlash) -> (StatusLayout, usize) {
+    let info = SlotInfo::from_data(flash.capacity() / 2, flash);
+    let other = SlotInfo::from_data(other.capacity() / 2, other);
+    let layout = info.status_layout(&other).unwrap();
+    let tail = layout.page_base(flash, 0).unwrap() + layout.tail_pos;
+    (layout, tail)
+}
+
+/// Write a tail, as raw bytes, as an older or newer bootloader would.
+fn write_raw(flash: &mut SimFlash, tail: usize, version: u8, status: &Status) {
+    let mut raw = vec![version, status.group];
+    let generation = if version >= 3 { status.generation.to_le_bytes() } else { [0xff; 2] };
+    raw.extend_from_slice(&generation);
+    raw.extend_from_slice(&status.enc_key);
+    raw.extend_from_slice(&status.main_size.to_le_bytes());
+    raw.extend_from_slice(&status.upgrade_size.to_le_bytes());
+    raw.extend_from_slice(&status.hash_seed.to_le_bytes());
+    raw.extend_from_slice(&[status.write_log, status.erase_log, status.flags, status.age]);
+    raw.extend_from_slice(&TRAILER_MAGIC);
+
+    let write_size = flash.write_size();
+    let start = tail / write_size * write_size;
+    let mut buf = vec![0xff; (tail + raw.len()).next_multiple_of(write_size) - start];
+    buf[tail - start..tail - start + raw.len()].copy_from_slice(&raw);
+    flash.write(start, &buf).unwrap();
+}
+
+fn sample() -> Status {
+    Status {
+        version: STATUS_VERSION,
+        group: 1,
+        enc_key: [0x5a; 16],
+        main_size: 0x1_2345,
+        upgrade_size: 0x1_3456,
+        hash_seed: 0xc0ff_ee00,
+        write_log: 3,
+        erase_log: 12,
+        flags: 0xfe,
+        age: 1,
+        generation: 7,
+    }
+}
+
+#[test]
+fn tail() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let (layout, _) = layout(&main, &upgrade);
+
+        main.erase(0, size).unwrap();
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Empty);
+
+        // A request alone isn't status.
+        request_upgrade(&mut main).unwrap();
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Empty);
+
+        main.erase(0, size).unwrap();
+        layout.write(&mut main, &sample()).unwrap();
+        let age = if layout.style == StatusStyle::Paged { 0 } else { 0xff };
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Valid(Status { age, ..sample() }));
+    }
+}
+
+#[test]
+fn versions() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let (layout, tail) = layout(&main, &upgrade);
+
+        // Tails from before the version are migrated.
+        main.erase(0, size).unwrap();
+        write_raw(&mut main, tail, 0xff, &sample());
+        let old = Status { version: 0, generation: 0, ..sample() };
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Valid(old));
+
+        main.erase(0, size).unwrap();
+        write_raw(&mut main, tail, 1, &sample());
+        let old = Status { version: 1, generation: 0, ..sample() };
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Valid(old));
+
+        main.erase(0, size).unwrap();
+        write_raw(&mut main, tail, 2, &sample());
+        let old = Status { version: 2, generation: 0, ..sample() };
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Valid(old));
+
+        // Newer ones are not interpreted.
+        main.erase(0, size).unwrap();
+        write_raw(&mut main, tail, STATUS_VERSION + 6, &sample());
+        assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Unknown(STATUS_VERSION + 6));
+    }
+}
+
+#[test]
+fn pages() {
+    let mut count = 0;
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let info = SlotInfo::from_data(size / 4, &main);
+        let other = SlotInfo::from_data(size / 4, &upgrade);
+        if info.status_style().unwrap() != StatusStyle::Paged {
+            continue;
+        }
+
+        for pages in [1, MAX_STATUS_PAGES + 1] {
+            let info = SlotInfo::from_data(size / 4, &main).with_status_pages(pages);
+            assert!(info.status_layout(&other).is_err());
+        }
+
+        let layout = info.with_status_pages(5).status_layout(&other).unwrap();
+        assert_eq!(layout.pages, 5);
+        main.erase(0, size).unwrap();
+
+        // Enough writes for the ages to wrap.
+        for n in 0..600u32 {
+            let status = Status { hash_seed: n, ..sample() };
+            layout.write(&mut main, &status).unwrap();
+            let age = (n % 255) as u8;
+            assert_eq!(layout.read(&mut main).unwrap(), StatusRead::Valid(Status { age, ..status }));
+
+            // Each page in turn, and only the pool, holds the status.
+            let page = n as usize % 5;
+            let tail = layout.page_base(&main, page).unwrap() + layout.tail_pos;
+            let mut magic = [0u8; 16];
+            main.read(tail + 36, &mut magic).unwrap();
+            assert_eq!(magic, TRAILER_MAGIC);
+        }
+        let mut buf = vec![0; layout.erase_size];
+        assert!(matches!(main.read(layout.page_base(&main, 5).unwrap(), &mut buf), Err(storage::Error::NotWritten)));
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn groups() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let info = |flash: &SimFlash| SlotInfo::from_data(size / 3, flash);
+
+        for group in [0, MAX_PROGRESS_GROUP + 1] {
+            assert!(info(&main).with_progress_group(group).status_layout(&info(&upgrade)).is_err());
+        }
+
+        let single = info(&main).status_layout(&info(&upgrade)).unwrap();
+        let layout = info(&main).with_progress_group(4).status_layout(&info(&upgrade)).unwrap();
+        let sectors = layout.image_sectors;
+        assert_eq!(single.progress_records(), sectors[0] + sectors[1]);
+        assert_eq!(layout.progress_records(), sectors[0].div_ceil(4) + sectors[1].div_ceil(4));
+        assert!(layout.hash_pages.iter().sum::<usize>() <= single.hash_pages.iter().sum::<usize>());
+
+        // Progress is recorded at the end of each group, and of the image.
+        let records: Vec<usize> = (0..sectors[0]).filter(|&n| layout.records_after(n, sectors[0])).collect();
+        assert_eq!(records.len(), sectors[0].div_ceil(4));
+        assert_eq!(records.last(), Some(&(sectors[0] - 1)));
+        assert!(records.iter().rev().skip(1).all(|n| (n + 1) % 4 == 0));
+        assert_eq!(layout.resume_sector(3), 12);
+
+        // The group is recorded with the status.
+        main.erase(0, size).unwrap();
+        layout.write(&mut main, &sample()).unwrap();
+        let StatusRead::Valid(status) = layout.read(&mut main).unwrap() else { panic!("No status") };
+        assert_eq!(status.group, 4);
+    }
+}
+
+#[test]
+fn minimal() {
+    let mut count = 0;
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let info = |flash: &SimFlash| SlotInfo::from_data(size / 3, flash).with_minimal_status();
+        let Ok(layout) = info(&main).status_layout(&info(&upgrade)) else {
+            // The counter doesn't fit at this write size.
+            assert!(main.write_size() > 32);
+            continue;
+        };
+        assert_eq!(layout.style, StatusStyle::Minimal);
+        assert_eq!(layout.inline_hashes, 0);
+        assert!(layout.hash_pages.is_empty());
+
+        main.erase(0, size).unwrap();
+        assert_eq!(layout.progress(&mut main).unwrap(), 0);
+        layout.write(&mut main, &sample()).unwrap();
+
+        let records = layout.progress_records();
+        for n in 1..=records {
+            layout.advance(&mut main).unwrap();
+            assert_eq!(layout.progress(&mut main).unwrap(), n);
+        }
+        assert!(layout.advance(&mut main).is_err());
+
+        // The counter leaves the tail alone.
+        let StatusRead::Valid(status) = layout.read(&mut main).unwrap() else { panic!("No status") };
+        assert_eq!(status.hash_seed, sample().hash_seed);
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn enc_key() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let minimal = |flash: &SimFlash| SlotInfo::from_data(size / 3, flash).with_minimal_status();
+        let layouts = [
+            layout(&main, &upgrade).0,
+            SlotInfo::from_data(size / 2, &main).with_status_pages(4)
+                .status_layout(&SlotInfo::from_data(size / 2, &upgrade)).unwrap(),
+        ];
+        let minimal = minimal(&main).status_layout(&minimal(&upgrade)).ok();
+
+        for layout in layouts.iter().chain(minimal.iter()) {
+            main.erase(0, size).unwrap();
+            assert_eq!(layout.enc_key(&mut main).unwrap(), None);
+            let status = Status { enc_key: [0xa5; 16], ..sample() };
+            layout.write(&mut main, &status).unwrap();
+            if layout.style == StatusStyle::Paged {
+                // Older pages hold the key too.
+                layout.write(&mut main, &status).unwrap();
+                layout.write(&mut main, &status).unwrap();
+            }
+            assert_eq!(layout.enc_key(&mut main).unwrap(), Some([0xa5; 16]));
+
+            // Something else written in the status sector.
+            let last = size - layout.erase_size;
+            if let Some(flags) = layout.flags {
+                main.write(last + flags[1], &vec![0x01; main.write_size()]).unwrap();
+            }
+            if layout.counter.is_some() {
+                layout.advance(&mut main).unwrap();
+                layout.advance(&mut main).unwrap();
+            }
+
+            // A revert may still need the key until the image is confirmed.
+            assert!(matches!(layout.clear_enc_key(&mut main), Err(Error::CannotUpgrade)));
+            assert_eq!(layout.enc_key(&mut main).unwrap(), Some([0xa5; 16]));
+
+            confirm(&mut main).unwrap();
+            layout.clear_enc_key(&mut main).unwrap();
+            assert_eq!(layout.enc_key(&mut main).unwrap(), None);
+            let StatusRead::Valid(cleared) = layout.read(&mut main).unwrap() else { panic!("No status") };
+            assert_eq!(cleared.enc_key, [0; 16]);
+            assert_eq!(cleared.hash_seed, status.hash_seed);
+            layout.clear_enc_key(&mut main).unwrap();
+
+            // In paged mode, the key is gone from the whole slot.  The rest is
+            // kept.
+            let dump = main.dump();
+            if layout.style == StatusStyle::Paged {
+                assert!(!dump.windows(16).any(|w| w == [0xa5; 16]));
+            }
+            if let Some(flags) = layout.flags {
+                assert_eq!(dump[last + flags[1]], 0x01);
+            }
+            if layout.counter.is_some() {
+                assert_eq!(layout.progress(&mut main).unwrap(), 2);
+            }
+        }
+    }
+}
+
+/// Cut the power at each write and erase while clearing the key from a
+/// confirmed image on test, including between the erase of a paged status
+/// and its rewrite.  The trailer, in the same sector as the status in
+/// overwrite and minimal modes, is never erased, and the status is either
+/// left as it was or cleared.  Clearing again after the cut finishes.
+#[test]
+fn enc_key_power_cut() {
+    for flashes in simflash::styles::all_flashes() {
+        let (main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let minimal = |flash: &SimFlash| SlotInfo::from_data(size / 3, flash).with_minimal_status();
+        let layouts = [
+            layout(&main, &upgrade).0,
+            SlotInfo::from_data(size / 2, &main).with_status_pages(4)
+                .status_layout(&SlotInfo::from_data(size / 2, &upgrade)).unwrap(),
+        ];
+        let minimal = minimal(&main).status_layout(&minimal(&upgrade)).ok();
+        let trailer = size - trailer_size(main.write_size())..size;
+
+        let mut main = Some(main);
+        for layout in layouts.iter().chain(minimal.iter()) {
+            let status = Status { enc_key: [0xa5; 16], ..sample() };
+            for op in [Op::Write, Op::Erase] {
+                for n in 0.. {
+                    let mut flash = main.take().unwrap();
+                    flash.erase(0, size).unwrap();
+                    layout.write(&mut flash, &status).unwrap();
+                    if layout.style == StatusStyle::Paged {
+                        layout.write(&mut flash, &status).unwrap();
+                    }
+                    request_upgrade(&mut flash).unwrap();
+                    set_copy_done(&mut flash).unwrap();
+                    confirm(&mut flash).unwrap();
+
+                    let mut faulty = FaultyFlash::new(flash)
+                        .fault(Fault::nth(op, n, storage::Error::Failed))
+                        .fault(Fault::range(Op::Erase, trailer.clone(), storage::Error::Failed));
+                    let result = layout.clear_enc_key(&mut faulty);
+                    let done = faulty.count(op) <= n;
+                    let mut flash = faulty.into_inner();
+                    assert_eq!(result.is_ok(), done);
+
+                    assert!(upgrade_requested(&mut flash).unwrap());
+                    assert_eq!(image_ok(&mut flash).unwrap(), Flag::Set);
+                    assert_eq!(copy_done(&mut flash).unwrap(), Flag::Set);
+                    let StatusRead::Valid(read) = layout.read(&mut flash).unwrap() else { panic!("No status") };
+                    assert_eq!(read.hash_seed, status.hash_seed);
+                    assert!(read.enc_key == [0xa5; 16] || read.enc_key == [0; 16]);
+
+                    layout.clear_enc_key(&mut flash).unwrap();
+                    assert_eq!(layout.enc_key(&mut flash).unwrap(), None);
+                    if layout.style == StatusStyle::Paged {
+                        assert!(!flash.dump().windows(16).any(|w| w == [0xa5; 16]));
+                    }
+                    main = Some(flash);
+                    if done {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn hashes() {
+    let mut count = 0;
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let layouts = [
+            layout(&main, &upgrade).0,
+            SlotInfo::from_data(size / 2, &main).with_status_pages(4)
+                .status_layout(&SlotInfo::from_data(size / 2, &upgrade)).unwrap(),
+        ];
+        for layout in &layouts {
+            let records = layout.progress_records();
+            if records == 0 {
+                continue;
+            }
+            let hashes: Vec<u32> = (0..records as u32).map(|n| 0x5a5a_0000 + n).collect();
+            let read = |main: &mut SimFlash| -> Vec<Option<u32>> {
+                (0..records).map(|n| layout.read_hash(main, n).unwrap()).collect()
+            };
+
+            main.erase(0, size).unwrap();
+            let status = Status { generation: 4, ..sample() };
+            layout.write(&mut main, &status).unwrap();
+            assert!(read(&mut main).iter().all(Option::is_none));
+            assert!(layout.write_hashes(&mut main, &hashes[1..]).is_err());
+            layout.write_hashes(&mut main, &hashes).unwrap();
+            let expected: Vec<_> = hashes.iter().copied().map(Some).collect();
+            assert_eq!(read(&mut main), expected);
+            assert_eq!(layout.read_hash(&mut main, records).unwrap(), None);
+
+            // The status pages of the same swap carry the hashes on, for as
+            // many pages as there are, and those of a new swap don't.
+            if layout.style == StatusStyle::Paged {
+                for _ in 0..layout.pages + 1 {
+                    layout.write(&mut main, &status).unwrap();
+                    assert_eq!(read(&mut main), expected);
+                }
+                layout.write(&mut main, &Status { generation: 5, ..sample() }).unwrap();
+                assert!(read(&mut main)[..layout.inline_hashes].iter().all(Option::is_none));
+            }
+            count += 1;
+        }
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn generations() {
+    let mut count = 0;
+    for flashes in simflash::styles::all_flashes() {
+        let (mut main, upgrade) = flashes.unwrap();
+        let size = main.capacity();
+        let (layout, tail) = layout(&main, &upgrade);
+        if layout.style != StatusStyle::Paged {
+            continue;
+        }
+        main.erase(0, size).unwrap();
+
+        // A swap's status can't be replaced by an earlier one.
+        let first = Status { generation: 5, ..sample() };
+        layout.write(&mut main, &first).unwrap();
+        layout.write(&mut main, &Status { hash_seed: 1, ..first.clone() }).unwrap();
+        assert!(layout.write(&mut main, &Status { generation: 4, ..first.clone() }).is_err());
+
+        // An old page replayed over the oldest, with a newer age, is refused.
+        let last_page = layout.page_base(&main, 0).unwrap();
+        main.erase(last_page, last_page + layout.erase_size).unwrap();
+        write_raw(&mut main, tail, STATUS_VERSION, &Status { generation: 4, age: 2, ..sample() });
+        assert!(layout.read(&mut main).is_err());
+        count += 1;
+    }
+    assert!(count > 0);
+
+    // Each swap is past both slots, and the device's counter.
+    let valid = |generation| StatusRead::Valid(Status { generation, ..sample() });
+    assert_eq!(next_generation(&StatusRead::Empty, &StatusRead::Empty, 0).unwrap(), 1);
+    assert_eq!(next_generation(&valid(3), &valid(8), 2).unwrap(), 9);
+    assert_eq!(next_generation(&valid(3), &StatusRead::Unknown(9), 12).unwrap(), 13);
+    assert!(next_generation(&valid(u16::MAX), &StatusRead::Empty, 0).is_err());
+
+    let (_, mut flash) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    let mut counter = FlashCounter::new(flash);
+    let status = Status { generation: 6, ..sample() };
+    check_generation(&status, &valid(6), None).unwrap();
+    assert!(check_generation(&status, &valid(7), None).is_err());
+    check_generation(&status, &StatusRead::Empty, Some(&mut counter)).unwrap();
+
+    // Once the swap is done, the counter holds its generation.
+    advance_generation(&status, &mut counter).unwrap();
+    assert_eq!(counter.read().unwrap(), 6);
+    let replay = Status { generation: 5, ..sample() };
+    assert!(check_generation(&replay, &StatusRead::Empty, Some(&mut counter)).is_err());
+}
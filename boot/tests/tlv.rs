@@ -0,0 +1,163 @@
+// TLV writing and rewriting.
+
+use std::cell::RefCell;
+
+use boot::{Image, SoftCrypto, TlvWriter, MAX_TLV_ENTRIES};
+use simflash::styles::K64_MAIN;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// A TLV kind that validation doesn't accept, as an AES key wrap would be.
+const TLV_ENC_KW: u16 = 0x31;
+
+/// The sample image, with a TLV appended.
+fn with_tlv(image: &[u8], kind: u16, data: &[u8]) -> Vec<u8> {
+    // The sample has no protected TLVs.
+    let start = 256 + u32::from_le_bytes(image[12..16].try_into().unwrap()) as usize;
+    let len = u16::from_le_bytes([image[start + 2], image[start + 3]]) as usize;
+    let mut result = image[..start + len].to_vec();
+    result.extend(kind.to_le_bytes());
+    result.extend((data.len() as u16).to_le_bytes());
+    result.extend(data);
+    let len = (len + 4 + data.len()) as u16;
+    result[start + 2..start + 4].copy_from_slice(&len.to_le_bytes());
+    result
+}
+
+/// Copy `image` to `dest` as the bootloader would before rewriting the TLVs:
+/// up to the hashed part, rounded down to the write size.
+fn copy_hashed<F: Flash>(image: &[u8], dest: &mut F) {
+    let size = dest.capacity();
+    dest.erase(0, size).unwrap();
+    let hashed = 256 + u32::from_le_bytes(image[12..16].try_into().unwrap()) as usize;
+    let end = hashed / dest.write_size() * dest.write_size();
+    let mut buf = vec![0u8; dest.write_size()];
+    for pos in (0..end).step_by(buf.len()) {
+        buf.copy_from_slice(&image[pos..pos + dest.write_size()]);
+        dest.write(pos, &buf).unwrap();
+    }
+}
+
+#[test]
+fn unchanged() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut dest, mut src) = flashes.unwrap();
+        src.install(IMAGE, 0).unwrap();
+        copy_hashed(IMAGE, &mut dest);
+
+        let src = RefCell::new(src);
+        let image = Image::from_flash(&src).unwrap();
+        let size = image.rewrite_tlvs(&mut dest, |_| true, &[]).unwrap();
+        assert_eq!(size, IMAGE.len());
+        assert_eq!(&dest.dump()[..size], IMAGE);
+    }
+}
+
+#[test]
+fn drop_tlv() {
+    let encrypted = with_tlv(IMAGE, TLV_ENC_KW, &[0x11; 24]);
+    for flashes in simflash::styles::all_flashes() {
+        let (mut dest, mut src) = flashes.unwrap();
+        src.install(&encrypted, 0).unwrap();
+        copy_hashed(&encrypted, &mut dest);
+
+        let src = RefCell::new(src);
+        let image = Image::from_flash(&src).unwrap();
+        assert!(image.validate_signed(&mut SoftCrypto::new(), KEY).is_err());
+
+        let size = image.rewrite_tlvs(&mut dest, |kind| kind != TLV_ENC_KW, &[]).unwrap();
+        assert_eq!(size, IMAGE.len());
+        let dest = RefCell::new(dest);
+        let image = Image::from_flash(&dest).unwrap();
+        assert_eq!(image.full_image_size(), IMAGE.len());
+        image.validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    }
+}
+
+#[test]
+fn add_tlv() {
+    let mut dest = K64_MAIN.build().unwrap();
+    let mut src = K64_MAIN.build().unwrap();
+    src.install(IMAGE, 0).unwrap();
+    copy_hashed(IMAGE, &mut dest);
+
+    let src = RefCell::new(src);
+    let image = Image::from_flash(&src).unwrap();
+    let size = image.rewrite_tlvs(&mut dest, |_| true, &[(TLV_ENC_KW, &[0x22; 24])]).unwrap();
+    let expected = with_tlv(IMAGE, TLV_ENC_KW, &[0x22; 24]);
+    assert_eq!(size, expected.len());
+    assert_eq!(&dest.dump()[..size], &expected[..]);
+}
+
+/// A slot holding `image`.
+fn slot(image: &[u8]) -> RefCell<SimFlash> {
+    let mut flash = K64_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(image, 0).unwrap();
+    RefCell::new(flash)
+}
+
+#[test]
+fn crafted() {
+    let sample = slot(IMAGE);
+    let sample = Image::from_flash(&sample).unwrap();
+
+    // A second hash, or signature, is rejected, however it checks out.
+    let hash = sample.stored_sha256().unwrap();
+    assert!(Image::from_flash(&slot(&with_tlv(IMAGE, 0x10, &hash))).is_err());
+    assert!(Image::from_flash(&slot(&with_tlv(IMAGE, 0x22, &[0x30; 8]))).is_err());
+
+    // Other kinds can repeat, up to a limit.
+    let mut image = IMAGE.to_vec();
+    for _ in sample.tlvs().unwrap().count()..MAX_TLV_ENTRIES {
+        image = with_tlv(&image, TLV_ENC_KW, &[]);
+    }
+    Image::from_flash(&slot(&image)).unwrap();
+    assert!(Image::from_flash(&slot(&with_tlv(&image, TLV_ENC_KW, &[]))).is_err());
+
+    // An entry can't run past the end of the block.
+    let mut image = with_tlv(IMAGE, TLV_ENC_KW, &[0x11; 4]);
+    let end = image.len();
+    image[end - 6] = 200;
+    image.extend([0; 200]);
+    assert!(Image::from_flash(&slot(&image)).is_err());
+}
+
+#[test]
+fn writer() {
+    let mut flash = K64_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+
+    // A protected area, written in pieces, after a payload that doesn't end
+    // on a write unit.
+    let mut writer = TlvWriter::new(&mut flash, 8, &[1, 2, 3], true, 4 + 4 + 10 + 4 + 2).unwrap();
+    writer.start_entry(0x50, 10).unwrap();
+    writer.data(&[5; 4]).unwrap();
+    writer.data(&[6; 6]).unwrap();
+    writer.entry(0x40, &[7, 8]).unwrap();
+    assert_eq!(writer.finish().unwrap(), 8 + 3 + 24);
+
+    let mut data = [0u8; 32];
+    flash.read(8, &mut data).unwrap();
+    assert_eq!(&data[..3], &[1, 2, 3]);
+    assert_eq!(&data[3..7], &[0x08, 0x69, 24, 0]);
+    assert_eq!(&data[7..11], &[0x50, 0, 10, 0]);
+    assert_eq!(&data[11..21], &[5, 5, 5, 5, 6, 6, 6, 6, 6, 6]);
+    assert_eq!(&data[21..27], &[0x40, 0, 2, 0, 7, 8]);
+    assert!(data[27..].iter().all(|&b| b == 0xff));
+
+    // The length given must be met exactly, and entries can't overrun.
+    let mut writer = TlvWriter::new(&mut flash, 64, &[], false, 12).unwrap();
+    assert!(writer.entry(0x10, &[0; 5]).is_err());
+    writer.start_entry(0x10, 4).unwrap();
+    assert!(writer.data(&[0; 5]).is_err());
+    assert!(writer.finish().is_err());
+
+    // Writing must start on a write unit.
+    assert!(TlvWriter::new(&mut flash, 130, &[], false, 4).is_err());
+}
@@ -0,0 +1,218 @@
+// Trailer state testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    boot_state, confirm, copy_done, image_ok, is_confirmed, last_boot_error, record_boot_error,
+    request_upgrade, set_copy_done, swap_type, upgrade_requested, BootError, Error, ErrorCode, Flag,
+    ImageVersion, SlotInfo, Status, StatusLayout, StatusRead, StatusStyle, SwapType, ERROR_FLASH,
+    ERROR_FLASH_NOT_ERASED, ERROR_INVALID_IMAGE,
+};
+use simflash::gen::ImageBuilder;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+/// A minimal image: a header, no payload, and an empty TLV.  Only the version
+/// is looked at.
+fn image(ver: ImageVersion) -> Vec<u8> {
+    ImageBuilder::default().version(ver.major, ver.minor, ver.revision, ver.build_num).no_hash().build()
+}
+
+#[test]
+fn swap_types() {
+    for flashes in simflash::styles::all_flashes() {
+        let (mut primary, mut secondary) = flashes.unwrap();
+        let size = primary.capacity();
+        primary.erase(0, size).unwrap();
+        let size = secondary.capacity();
+        secondary.erase(0, size).unwrap();
+
+        assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::None);
+        assert!(!is_confirmed(&mut primary).unwrap());
+
+        // A plain request is a test, confirming it makes it permanent.
+        request_upgrade(&mut secondary).unwrap();
+        assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Test);
+        confirm(&mut secondary).unwrap();
+        assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Perm);
+        secondary.erase(0, size).unwrap();
+
+        // After a test swap, the bootloader leaves the primary with the copy
+        // done, and a revert happens unless the image is confirmed.
+        request_upgrade(&mut primary).unwrap();
+        set_copy_done(&mut primary).unwrap();
+        assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::Revert);
+        assert!(!is_confirmed(&mut primary).unwrap());
+
+        confirm(&mut primary).unwrap();
+        assert_eq!(swap_type(&mut primary, &mut secondary).unwrap(), SwapType::None);
+        assert!(is_confirmed(&mut primary).unwrap());
+
+        // Confirming again is harmless.
+        confirm(&mut primary).unwrap();
+    }
+}
+
+#[test]
+fn versions() {
+    let v1 = ImageVersion { major: 1, minor: 0, revision: 0, build_num: 1 };
+    let v2 = ImageVersion { major: 2, minor: 1, revision: 3, build_num: 0 };
+
+    for flashes in simflash::styles::all_flashes() {
+        let (mut primary, mut secondary) = flashes.unwrap();
+        let size = secondary.capacity();
+        secondary.erase(0, size).unwrap();
+        primary.install(&image(v1), 0).unwrap();
+
+        let primary = RefCell::new(primary);
+        let secondary = RefCell::new(secondary);
+        let state = boot_state(&primary, &secondary).unwrap();
+        assert_eq!(state.swap_type, SwapType::None);
+        assert_eq!(state.primary_version, Some(v1));
+        assert_eq!(state.secondary_version, None);
+
+        secondary.borrow_mut().install(&image(v2), 0).unwrap();
+        request_upgrade(&mut *secondary.borrow_mut()).unwrap();
+        let state = boot_state(&primary, &secondary).unwrap();
+        assert_eq!(state.swap_type, SwapType::Test);
+        assert!(!state.confirmed);
+        assert_eq!(state.secondary_version, Some(v2));
+    }
+}
+
+#[test]
+fn boot_errors() {
+    for flashes in simflash::styles::all_flashes() {
+        let (_, mut secondary) = flashes.unwrap();
+        let size = secondary.capacity();
+        secondary.erase(0, size).unwrap();
+        assert_eq!(last_boot_error(&mut secondary).unwrap(), None);
+
+        // The record sits alongside the flags, without disturbing them.
+        request_upgrade(&mut secondary).unwrap();
+        let err = BootError::new(&Error::InvalidImage, 0x1200);
+        record_boot_error(&mut secondary, &err).unwrap();
+        assert_eq!(last_boot_error(&mut secondary).unwrap(), Some(err));
+        confirm(&mut secondary).unwrap();
+        assert_eq!(last_boot_error(&mut secondary).unwrap(), Some(err));
+
+        // Only the first is kept.
+        let later = BootError::new(&Error::Flash(storage::Error::Failed), 0);
+        record_boot_error(&mut secondary, &later).unwrap();
+        let got = last_boot_error(&mut secondary).unwrap().unwrap();
+        assert_eq!(got.code, ErrorCode::InvalidImage);
+        assert_eq!(got.offset, 0x1200);
+
+        secondary.erase(0, size).unwrap();
+        assert_eq!(last_boot_error(&mut secondary).unwrap(), None);
+    }
+}
+
+#[test]
+fn error_codes() {
+    // The numbers are fixed, as they are read by other software.
+    assert_eq!(ErrorCode::from(&Error::InvalidImage) as u16, ERROR_INVALID_IMAGE);
+    assert_eq!(ErrorCode::from(&Error::CannotUpgrade) as u16, 3);
+    let flash = ErrorCode::from(&Error::Flash(storage::Error::NotErased));
+    assert_eq!(flash, ErrorCode::FlashNotErased);
+    assert_eq!(flash as u16, ERROR_FLASH_NOT_ERASED);
+    assert_eq!(flash.kind(), ErrorCode::Flash);
+    assert_eq!(ErrorCode::InvalidImage.kind(), ErrorCode::InvalidImage);
+
+    // Records written before flash errors had their own codes still read.
+    assert_eq!(ErrorCode::from_u16(ERROR_FLASH), Some(ErrorCode::Flash));
+    assert_eq!(ErrorCode::from_u16(0x0501), Some(ErrorCode::FlashFailed));
+    assert_eq!(ErrorCode::from_u16(0x0601), None);
+    assert_eq!(ErrorCode::from_u16(0), None);
+
+    let (_, mut secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = secondary.capacity();
+    secondary.erase(0, size).unwrap();
+    let err = BootError::new(&Error::Flash(storage::Error::NotErased), 0x2000);
+    record_boot_error(&mut secondary, &err).unwrap();
+    assert_eq!(last_boot_error(&mut secondary).unwrap(), Some(err));
+    assert_eq!(err.code, ErrorCode::FlashNotErased);
+}
+
+/// Each pair of slots, along with one of 8 byte writes in 4k sectors.
+fn slot_pairs() -> impl Iterator<Item = (SimFlash, SimFlash)> {
+    let small = || SimFlash::new(8, 8, 4096, 32).unwrap();
+    simflash::styles::all_flashes().map(|flashes| flashes.unwrap()).chain([(small(), small())])
+}
+
+/// Each status layout a slot can have: its own style, paged with more pages,
+/// and minimal, where they fit.
+fn status_layouts(main: &SimFlash, upgrade: &SimFlash) -> Vec<StatusLayout> {
+    let size = main.capacity();
+    let main = SlotInfo::from_data(size / 4, main);
+    let upgrade = SlotInfo::from_data(size / 4, upgrade);
+    let mut layouts = vec![main.status_layout(&upgrade).unwrap()];
+    if main.status_style().unwrap() == StatusStyle::Paged {
+        layouts.push(SlotInfo { status_pages: 4, ..main }.status_layout(&upgrade).unwrap());
+    } else {
+        layouts.extend(SlotInfo { minimal: true, ..main }.status_layout(&upgrade));
+    }
+    layouts
+}
+
+#[test]
+fn with_status() {
+    for (mut main, upgrade) in slot_pairs() {
+        let size = main.capacity();
+        for layout in status_layouts(&main, &upgrade) {
+            let status = Status { hash_seed: 0x1234, enc_key: [0xa5; 16], ..Status::default() };
+            let written = |main: &mut SimFlash| {
+                matches!(layout.read(main).unwrap(), StatusRead::Valid(read) if read.hash_seed == 0x1234)
+            };
+
+            // The application confirms while there is a status.
+            main.erase(0, size).unwrap();
+            layout.write(&mut main, &status).unwrap();
+            confirm(&mut main).unwrap();
+            set_copy_done(&mut main).unwrap();
+            assert_eq!(image_ok(&mut main).unwrap(), Flag::Set);
+            assert_eq!(copy_done(&mut main).unwrap(), Flag::Set);
+            assert!(written(&mut main));
+
+            // And the other way round.
+            main.erase(0, size).unwrap();
+            request_upgrade(&mut main).unwrap();
+            confirm(&mut main).unwrap();
+            layout.write(&mut main, &status).unwrap();
+            assert!(written(&mut main));
+            assert!(is_confirmed(&mut main).unwrap());
+
+            // Clearing the key keeps the trailer.
+            layout.clear_enc_key(&mut main).unwrap();
+            assert_eq!(layout.enc_key(&mut main).unwrap(), None);
+            assert!(is_confirmed(&mut main).unwrap());
+            assert!(upgrade_requested(&mut main).unwrap());
+        }
+    }
+}
+
+#[test]
+fn boot_error_with_status() {
+    for (mut main, upgrade) in slot_pairs() {
+        let size = main.capacity();
+        for layout in status_layouts(&main, &upgrade) {
+            let status = Status { main_size: 0x4321, enc_key: [0x5a; 16], ..Status::default() };
+            let err = BootError::new(&Error::InvalidImage, 0x1200);
+            let intact = |main: &mut SimFlash| {
+                last_boot_error(main).unwrap() == Some(err) &&
+                    matches!(layout.read(main).unwrap(),
+                             StatusRead::Valid(read) if read.main_size == 0x4321 && read.enc_key == [0x5a; 16])
+            };
+
+            main.erase(0, size).unwrap();
+            layout.write(&mut main, &status).unwrap();
+            record_boot_error(&mut main, &err).unwrap();
+            assert!(intact(&mut main));
+
+            main.erase(0, size).unwrap();
+            record_boot_error(&mut main, &err).unwrap();
+            layout.write(&mut main, &status).unwrap();
+            assert!(intact(&mut main));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Cached validation testing.
+
+use std::cell::{Cell, RefCell};
+
+use boot::{
+    confirm, invalidate, validate_cached, GuardedFlash, Image, SlotInfo, Status, StatusLayout, StatusRead,
+    Validation,
+};
+use simflash::styles::AreaLayout;
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static SLOT: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 16,
+    erase_size: 4096,
+    sectors: 32,
+};
+
+fn install(data: &[u8]) -> RefCell<GuardedFlash<SimFlash>> {
+    let mut flash = SLOT.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(data, 0).unwrap();
+    RefCell::new(GuardedFlash::new(flash))
+}
+
+/// Validate, counting the full validations.
+fn boot<F: Flash>(slot: &RefCell<F>, full: &Cell<usize>) -> boot::Result<Validation> {
+    validate_cached(slot, |image| {
+        full.set(full.get() + 1);
+        image.validate()
+    })
+}
+
+#[test]
+fn cached() {
+    let data = include_bytes!("../data/sample-signed.bin");
+    let full = Cell::new(0);
+    let slot = install(data);
+
+    // Until confirmed, every boot validates in full.
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(full.get(), 2);
+
+    // Confirming, through the guard, doesn't count as changing the image.
+    confirm(&mut *slot.borrow_mut()).unwrap();
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+    assert_eq!(full.get(), 3);
+
+    // Writing to the image area forgets it, for good.
+    let end = Image::from_flash(&slot).unwrap().full_image_size().next_multiple_of(16);
+    slot.borrow_mut().write(end, &[0; 16]).unwrap();
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+
+    // Until the slot is erased, and a new image is installed and confirmed.
+    let mut flash = slot.borrow_mut();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    for (pos, chunk) in data.chunks(16).enumerate() {
+        let mut buf = [0xff; 16];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        flash.write(pos * 16, &buf).unwrap();
+    }
+    confirm(&mut *flash).unwrap();
+    drop(flash);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+
+    // And can be forgotten directly.
+    invalidate(&mut *slot.borrow_mut()).unwrap();
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+}
+
+#[test]
+fn not_cached() {
+    let data = include_bytes!("../data/sample-signed.bin");
+    let full = Cell::new(0);
+
+    // A failed validation isn't recorded.
+    let slot = install(data);
+    confirm(&mut *slot.borrow_mut()).unwrap();
+    assert!(validate_cached(&slot, |_| Err(boot::Error::InvalidImage)).is_err());
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+
+    // Nor is a corrupt image, which is rejected on every boot.
+    let mut bad = data.to_vec();
+    bad[1000] ^= 1;
+    let slot = install(&bad);
+    confirm(&mut *slot.borrow_mut()).unwrap();
+    assert!(boot(&slot, &full).is_err());
+    assert!(boot(&slot, &full).is_err());
+}
+
+#[test]
+fn with_status() {
+    let data = include_bytes!("../data/sample-signed.bin");
+    let full = Cell::new(0);
+    let status = Status { hash_seed: 0x1234, ..Status::default() };
+    let layout = |flash: &SimFlash| -> StatusLayout {
+        let info = SlotInfo::from_data(data.len(), flash);
+        info.status_layout(&SlotInfo::from_data(data.len(), flash)).unwrap()
+    };
+    let written = |layout: &StatusLayout, flash: &mut SimFlash| {
+        matches!(layout.read(flash).unwrap(), StatusRead::Valid(read) if read.hash_seed == 0x1234)
+    };
+
+    // A status written next to a record leaves both.
+    let slot = install(data);
+    confirm(&mut *slot.borrow_mut()).unwrap();
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    let mut flash = slot.into_inner().into_inner();
+    let layout = layout(&flash);
+    layout.write(&mut flash, &status).unwrap();
+    assert!(written(&layout, &mut flash));
+    let slot = RefCell::new(GuardedFlash::new(flash));
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+
+    // Forgetting the record leaves the status.
+    invalidate(&mut *slot.borrow_mut()).unwrap();
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert!(written(&layout, &mut slot.into_inner().into_inner()));
+
+    // And a record can be made with a status already there.
+    let slot = install(data);
+    let mut flash = slot.into_inner().into_inner();
+    layout.write(&mut flash, &status).unwrap();
+    confirm(&mut flash).unwrap();
+    let slot = RefCell::new(GuardedFlash::new(flash));
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Full);
+    assert_eq!(boot(&slot, &full).unwrap(), Validation::Cached);
+    assert!(written(&layout, &mut slot.into_inner().into_inner()));
+}
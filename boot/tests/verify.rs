@@ -0,0 +1,97 @@
+// Write verification testing.
+
+use std::cell::RefCell;
+
+use boot::{load_to_internal, Error, Image, SoftCrypto, VerifiedFlash};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+/// Flash with a worn cell, which reads as erased however it is written,
+/// without the write failing.
+struct Worn {
+    flash: SimFlash,
+    cell: usize,
+}
+
+impl ReadFlash for Worn {
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> storage::Result<()> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl Flash for Worn {
+    fn write_size(&self) -> usize {
+        self.flash.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.flash.erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> storage::Result<()> {
+        self.flash.erase(from, to)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> storage::Result<()> {
+        let mut bytes = bytes.to_vec();
+        if (offset..offset + bytes.len()).contains(&self.cell) {
+            bytes[self.cell - offset] = 0xff;
+        }
+        self.flash.write(offset, &bytes)
+    }
+}
+
+fn slots() -> (Worn, RefCell<SimFlash>) {
+    let (mut internal, mut external) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = internal.capacity();
+    internal.erase(0, size).unwrap();
+    external.install(IMAGE, 0).unwrap();
+    (Worn { flash: internal, cell: 1000 }, RefCell::new(external))
+}
+
+#[test]
+fn verify() {
+    // Checked, the copy stops at the page that didn't take.
+    let (worn, external) = slots();
+    let internal = RefCell::new(VerifiedFlash::new(worn, true));
+    let result = load_to_internal(&external, &internal, &mut SoftCrypto::new(), KEY);
+    assert!(matches!(result, Err(Error::Flash(storage::Error::Failed))));
+    let written = internal.into_inner().into_inner().flash.dump();
+    assert!(written[2048..].iter().all(|&b| b == 0xff));
+
+    // Unchecked, the copy is hashed as it goes by, so it isn't found at all
+    // until the image is next read in full.
+    let (worn, external) = slots();
+    let internal = RefCell::new(VerifiedFlash::new(worn, false));
+    load_to_internal(&external, &internal, &mut SoftCrypto::new(), KEY).unwrap();
+    assert!(Image::from_flash(&internal).unwrap().validate_signed(&mut SoftCrypto::new(), KEY).is_err());
+
+    // Good flash passes.
+    let (mut worn, external) = slots();
+    worn.cell = usize::MAX;
+    let internal = RefCell::new(VerifiedFlash::new(worn, true));
+    load_to_internal(&external, &internal, &mut SoftCrypto::new(), KEY).unwrap();
+    Image::from_flash(&internal).unwrap().validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+}
+
+#[test]
+fn unreadable_erased() {
+    // A device that can't read erased flash reports a write that didn't
+    // take at all as a failed write, not as a read of unwritten flash.
+    let mut flash = SimFlash::new(16, 16, 4096, 4).unwrap();
+    flash.erase(0, 4096).unwrap();
+    let mut worn = VerifiedFlash::new(Worn { flash, cell: 16 }, true);
+    worn.write(0, &[0x11; 16]).unwrap();
+    assert_eq!(worn.write(16, &[0x22; 16]), Err(storage::Error::Failed));
+}
@@ -0,0 +1,54 @@
+// Wake from standby testing.
+
+use std::cell::RefCell;
+
+use boot::{
+    confirm, set_wake_marker, wake_fast_path, Image, Retained, RetainedWord, SoftCrypto, WakeGuard,
+};
+use storage::{Flash, ReadFlash};
+
+static SAMPLE: &[u8] = include_bytes!("../data/sample-ecdsa.bin");
+static OTHER: &[u8] = include_bytes!("../data/sample-signed.bin");
+static KEY: &[u8] = include_bytes!("../data/ecdsa-p256-pub.der");
+
+#[test]
+fn wake() {
+    let mut backup = 0u32;
+    let word = RefCell::new(unsafe { RetainedWord::new(&mut backup as *mut u32 as usize) });
+    let mut flash = simflash::styles::LPC_MAIN.build().unwrap();
+    let size = flash.capacity();
+    flash.erase(0, size).unwrap();
+    flash.install(SAMPLE, 0).unwrap();
+    let slot = RefCell::new(WakeGuard::new(flash, &word));
+
+    // Nothing to skip until the image has been validated.
+    let image = Image::from_flash(&slot).unwrap();
+    assert!(!wake_fast_path(&mut *word.borrow_mut(), true, &image));
+    image.validate_signed(&mut SoftCrypto::new(), KEY).unwrap();
+    set_wake_marker(&mut *word.borrow_mut(), &image).unwrap();
+    assert!(wake_fast_path(&mut *word.borrow_mut(), true, &image));
+    assert!(wake_fast_path(&mut *word.borrow_mut(), true, &image));
+
+    // Any other reset validates in full, and clears the marker.
+    assert!(!wake_fast_path(&mut *word.borrow_mut(), false, &image));
+    assert!(!wake_fast_path(&mut *word.borrow_mut(), true, &image));
+
+    // Writing the slot clears it.
+    set_wake_marker(&mut *word.borrow_mut(), &image).unwrap();
+    confirm(&mut *slot.borrow_mut()).unwrap();
+    assert_eq!(word.borrow().read(), 0);
+    let image = Image::from_flash(&slot).unwrap();
+    assert!(!wake_fast_path(&mut *word.borrow_mut(), true, &image));
+
+    // The marker is only for the image it was set for.
+    let mut other = simflash::styles::LPC_MAIN.build().unwrap();
+    other.install(OTHER, 0).unwrap();
+    let other = RefCell::new(other);
+    let other = Image::from_flash(&other).unwrap();
+    set_wake_marker(&mut *word.borrow_mut(), &image).unwrap();
+    assert!(!wake_fast_path(&mut *word.borrow_mut(), true, &other));
+
+    set_wake_marker(&mut *word.borrow_mut(), &Image::from_flash(&slot).unwrap()).unwrap();
+    slot.borrow_mut().erase(0, size).unwrap();
+    assert_eq!(word.borrow().read(), 0);
+}
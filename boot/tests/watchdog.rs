@@ -0,0 +1,34 @@
+// Watchdog testing.
+
+use std::cell::{Cell, RefCell};
+
+use boot::{Image, Watchdog, WatchedFlash};
+
+/// A watchdog that just counts how often it is fed.
+#[derive(Default)]
+struct Counter {
+    feeds: Cell<usize>,
+}
+
+impl Watchdog for Counter {
+    fn feed(&self) {
+        self.feeds.set(self.feeds.get() + 1);
+    }
+}
+
+#[test]
+fn watchdog_fed() {
+    let data = include_bytes!("../data/sample-signed.bin");
+    let counter = Counter::default();
+
+    let mut flash = simflash::styles::K64_MAIN.build().unwrap();
+    flash.install(data, 0).unwrap();
+    let flash = RefCell::new(WatchedFlash::new(flash, &counter));
+
+    let image = Image::from_flash(&flash).unwrap();
+    image.validate().unwrap();
+
+    // The hash reads the whole image, so the watchdog should have been fed
+    // many times.
+    assert!(counter.feeds.get() > data.len() / 1024);
+}
@@ -0,0 +1,373 @@
+//! Upgrades on flash dumps
+//!
+//! Loads dumps of a device's slots into SimFlash devices, runs what the
+//! bootloader does on each boot, and gives back the resulting flash contents.
+//! This makes it possible to reproduce what a device in the field did with an
+//! upgrade, without the device.
+//!
+//! The decisions are the boot crate's own: the swap type from the trailers,
+//! validation of the images, what to do about them from a `BootPolicy`
+//! (`DefaultPolicy` unless one is given), the reason recorded when an upgrade
+//! is rejected, the trailer written after the swap, and the cleanup of the
+//! secondary slot after a permanent one.  The boot crate doesn't yet have a
+//! swap engine, so, as in its lifecycle example, the images are exchanged
+//! whole, in memory.  A scratch area, if the device has one, is loaded and written
+//! back out, but not yet used.
+//!
+//! The boot code sees each area as a `Slot`, as it does on a board, though
+//! here each is a device of its own.
+//!
+//! A test swap also writes the status, with the hashes of the two images, to
+//! the primary slot, and a revert copies the old image back from the
+//! secondary with the boot crate's `copy_back`, leaving alone the groups of
+//! sectors that already match their hashes.  Dumps without a status, such as
+//! those taken mid-upgrade from a device, are reverted by the whole exchange.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use boot::{
+    clean_secondary, confirm, copy_back, is_confirmed, new_hash_seed, record_boot_error, request_upgrade,
+    set_copy_done, swap_type, BootAction, BootError, BootPolicy, BootView, DefaultPolicy, Image, ImageVersion,
+    SeededEntropy, Slot, SlotInfo, SlotPurpose, SlotView, SoftCrypto, Status, StatusLayout, SwapType, VersionPolicy,
+};
+use simflash::styles::SlotMap;
+use simflash::{ChartOnPanic, Event, SimFlash};
+use storage::{Flash, ReadFlash};
+
+/// The flash of a device.
+pub struct Device {
+    pub primary: RefCell<SimFlash>,
+    pub secondary: RefCell<SimFlash>,
+    pub scratch: Option<RefCell<SimFlash>>,
+}
+
+impl Device {
+    /// Build the device described by `map`, with the given dumps loaded.  A
+    /// scratch dump is only allowed if the device has a scratch area.
+    pub fn load(map: &SlotMap, primary: &[u8], secondary: &[u8], scratch: Option<&[u8]>) -> Result<Device> {
+        let load = |name: &str, layout: &simflash::styles::AreaLayout, dump: &[u8]| {
+            let mut flash = layout.build().map_err(|e| anyhow!("{}: {:?}", name, e))?;
+            flash.load(dump)
+                .map_err(|_| anyhow!("The {} dump is larger than the slot ({} bytes)", name, flash.capacity()))?;
+            Ok::<_, anyhow::Error>(RefCell::new(flash))
+        };
+        let scratch = match (&map.scratch, scratch) {
+            (Some(layout), dump) => Some(load("scratch", layout, dump.unwrap_or(&[]))?),
+            (None, None) => None,
+            (None, Some(_)) => return Err(anyhow!("Device {} has no scratch area", map.name)),
+        };
+        Ok(Device {
+            primary: load("primary", &map.main, primary)?,
+            secondary: load("secondary", &map.upgrade, secondary)?,
+            scratch,
+        })
+    }
+
+    /// The slot for `purpose`, covering its whole area, for the boot code.
+    pub fn slot(&self, purpose: SlotPurpose) -> Result<RefCell<Slot<'_, SimFlash>>> {
+        let area = match purpose {
+            SlotPurpose::Primary => &self.primary,
+            SlotPurpose::Secondary => &self.secondary,
+            SlotPurpose::Scratch => self.scratch.as_ref().ok_or_else(|| anyhow!("The device has no scratch area"))?,
+        };
+        let size = area.borrow().capacity();
+        let slot = Slot::new(area, 0, size, purpose).map_err(|e| anyhow!("No {:?} slot: {:?}", purpose, e))?;
+        Ok(RefCell::new(slot))
+    }
+
+    /// Chart the page states of the device's flash if the caller panics
+    /// while this is held, such as on a failed assertion in a test.
+    pub fn chart_on_panic(&self) -> ChartOnPanic<'_> {
+        let mut flashes = vec![("primary", &self.primary), ("secondary", &self.secondary)];
+        if let Some(scratch) = &self.scratch {
+            flashes.push(("scratch", scratch));
+        }
+        ChartOnPanic::new(&flashes)
+    }
+
+    /// Check `invariant` after every erase and write to any of the device's
+    /// areas, panicking, with its name and what broke it, if it fails.  This
+    /// turns the documented states of an upgrade into checks at every step.
+    pub fn add_invariant<I>(&self, name: &'static str, invariant: I)
+    where
+        I: Fn(&Snapshot) -> std::result::Result<(), String> + 'static,
+    {
+        let snapshot = Rc::new(RefCell::new(Snapshot {
+            primary: self.primary.borrow().dump(),
+            secondary: self.secondary.borrow().dump(),
+            scratch: self.scratch.as_ref().map(|scratch| scratch.borrow().dump()),
+        }));
+        let invariant = Rc::new(invariant);
+        let mut areas = vec![(SlotPurpose::Primary, &self.primary), (SlotPurpose::Secondary, &self.secondary)];
+        if let Some(scratch) = &self.scratch {
+            areas.push((SlotPurpose::Scratch, scratch));
+        }
+        for (area, flash) in areas {
+            let snapshot = snapshot.clone();
+            let invariant = invariant.clone();
+            flash.borrow_mut().add_hook(Box::new(move |event: &Event, flash: &SimFlash| {
+                let mut snapshot = snapshot.borrow_mut();
+                *snapshot.area_mut(area) = flash.dump();
+                if let Err(msg) = invariant(&snapshot) {
+                    panic!("Invariant {:?} broken by {:?} of the {:?} slot: {}", name, event, area, msg);
+                }
+            }));
+        }
+    }
+}
+
+/// The contents of a device's areas, as given to invariants.
+pub struct Snapshot {
+    pub primary: Vec<u8>,
+    pub secondary: Vec<u8>,
+    pub scratch: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+    fn area_mut(&mut self, area: SlotPurpose) -> &mut Vec<u8> {
+        match area {
+            SlotPurpose::Primary => &mut self.primary,
+            SlotPurpose::Secondary => &mut self.secondary,
+            SlotPurpose::Scratch => self.scratch.as_mut().unwrap(),
+        }
+    }
+}
+
+/// What images are validated against.
+pub enum Trust<'a> {
+    /// Only the hash.
+    Hash,
+    /// A signature made with this key (a DER SubjectPublicKeyInfo).
+    Key(&'a [u8]),
+}
+
+impl Trust<'_> {
+    fn validate<F: ReadFlash>(&self, slot: &RefCell<F>) -> boot::Result<ImageVersion> {
+        let image = Image::from_flash(slot)?;
+        let mut crypto = SoftCrypto::new();
+        match self {
+            Trust::Hash => image.validate_with(&mut crypto)?,
+            Trust::Key(key) => image.validate_signed(&mut crypto, key)?,
+        }
+        Ok(image.version())
+    }
+
+    /// What the policy is told about a slot.  The slots of a single image
+    /// have nothing else to depend on, so a dependency is checked against the
+    /// image's own version.
+    fn view<F: ReadFlash>(&self, slot: &RefCell<F>) -> SlotView {
+        let image = match Image::from_flash(slot) {
+            Ok(image) => image,
+            Err(_) => return SlotView::default(),
+        };
+        let version = image.version();
+        SlotView {
+            version: Some(version),
+            valid: self.validate(slot).is_ok(),
+            dependencies_met: image.check_dependencies(&[version]).is_ok(),
+        }
+    }
+}
+
+/// What happened on one boot.
+#[derive(Debug)]
+pub struct Outcome {
+    pub swap_type: SwapType,
+    /// Why the upgrade was rejected, if it was.
+    pub rejected: Option<BootError>,
+    /// The version booted, or why the primary image couldn't be.
+    pub booted: boot::Result<ImageVersion>,
+    /// Groups of sectors a revert left alone, as they already matched the
+    /// hashes recorded by the swap.
+    pub skipped: usize,
+    /// What the policy decided.
+    pub action: BootAction,
+}
+
+/// Run the bootloader once, taking any upgrade.
+pub fn boot(dev: &Device, trust: &Trust) -> Result<Outcome> {
+    boot_with(dev, trust, &VersionPolicy::Any)
+}
+
+/// Run the bootloader once, only taking upgrades that `versions` allows.
+pub fn boot_with(dev: &Device, trust: &Trust, versions: &VersionPolicy) -> Result<Outcome> {
+    boot_policy(dev, trust, &DefaultPolicy { versions: *versions, ..DefaultPolicy::default() })
+}
+
+/// Run the bootloader once, doing what `policy` decides.
+pub fn boot_policy(dev: &Device, trust: &Trust, policy: &dyn BootPolicy) -> Result<Outcome> {
+    let primary = dev.slot(SlotPurpose::Primary)?;
+    let secondary = dev.slot(SlotPurpose::Secondary)?;
+    let kind = swap_type(&mut *primary.borrow_mut(), &mut *secondary.borrow_mut())
+        .map_err(|e| anyhow!("Unable to read the trailers: {:?}", e))?;
+    let confirmed = is_confirmed(&mut *primary.borrow_mut())
+        .map_err(|e| anyhow!("Unable to read the trailers: {:?}", e))?;
+    let view = BootView {
+        swap_type: kind,
+        confirmed,
+        primary: trust.view(&primary),
+        secondary: trust.view(&secondary),
+    };
+    let action = policy.decide(&view);
+
+    let mut rejected = None;
+    let mut skipped = 0;
+    match action {
+        BootAction::BootPrimary | BootAction::Recovery => (),
+        BootAction::Reject(code) => {
+            // A bad upgrade is left where it is, with the reason recorded.
+            let err = BootError { code, offset: 0 };
+            record_boot_error(&mut *secondary.borrow_mut(), &err)
+                .map_err(|e| anyhow!("Unable to record the boot error: {:?}", e))?;
+            rejected = Some(err);
+        }
+        BootAction::Swap { permanent } => {
+            swap(dev)?;
+            if !permanent {
+                record_status(dev)?;
+            }
+            finish_swap(&primary, permanent)?;
+            if permanent {
+                // Confirmed as it goes in, so the old image is done with.
+                clean_secondary(&mut *secondary.borrow_mut(), policy.cleanup())
+                    .map_err(|e| anyhow!("Unable to clean up the secondary slot: {:?}", e))?;
+            }
+        }
+        BootAction::Revert => {
+            // The old image goes back, and is known good.
+            skipped = revert(dev)?;
+            finish_swap(&primary, true)?;
+        }
+    }
+    Ok(Outcome { swap_type: kind, rejected, booted: trust.validate(&primary), skipped, action })
+}
+
+/// What the application does once it is running happily: confirm itself.
+/// Returns true if it wasn't already confirmed.
+pub fn confirm_primary(dev: &Device) -> Result<bool> {
+    let primary = dev.slot(SlotPurpose::Primary)?;
+    let mut primary = primary.borrow_mut();
+    let flash_err = |e| anyhow!("Unable to confirm the image: {:?}", e);
+    if is_confirmed(&mut *primary).map_err(flash_err)? {
+        return Ok(false);
+    }
+    confirm(&mut *primary).map_err(flash_err)?;
+    Ok(true)
+}
+
+/// Write the primary trailer as MCUboot does after a swap.
+fn finish_swap(primary: &RefCell<Slot<'_, SimFlash>>, permanent: bool) -> Result<()> {
+    let mut primary = primary.borrow_mut();
+    let result = request_upgrade(&mut *primary)
+        .and_then(|()| if permanent { confirm(&mut *primary) } else { Ok(()) })
+        .and_then(|()| set_copy_done(&mut *primary));
+    result.map_err(|e| anyhow!("Unable to write the trailer: {:?}", e))
+}
+
+/// Exchange the images in the two slots, clearing both trailers.
+fn swap(dev: &Device) -> Result<()> {
+    let a = image_bytes(&dev.primary)?;
+    let b = image_bytes(&dev.secondary)?;
+    for (slot, image) in [(&dev.primary, &b), (&dev.secondary, &a)] {
+        let mut slot = slot.borrow_mut();
+        let capacity = slot.capacity();
+        if image.len() > capacity {
+            return Err(anyhow!("An image of {} bytes doesn't fit in a slot of {}", image.len(), capacity));
+        }
+        slot.erase(0, capacity).map_err(|e| anyhow!("Erase failed: {:?}", e))?;
+        slot.install(image, 0).map_err(|e| anyhow!("Write failed: {:?}", e))?;
+    }
+    Ok(())
+}
+
+/// The status layout of a swap of `main_size` bytes from the primary slot
+/// with `upgrade_size` from the secondary, if the device keeps hashes, and
+/// both images fit below the status.
+fn status_layout(dev: &Device, main_size: usize, upgrade_size: usize) -> Option<StatusLayout> {
+    let primary = dev.primary.borrow();
+    let layout = SlotInfo::from_data(main_size, &*primary)
+        .status_layout(&SlotInfo::from_data(upgrade_size, &*dev.secondary.borrow()))
+        .ok()?;
+    let group_size = layout.group * layout.erase_size;
+    let groups = layout.image_groups(0).max(layout.image_groups(1));
+    let status_start = primary.capacity().checked_sub(layout.status_sectors() * layout.erase_size)?;
+    (layout.counter.is_none() && groups * group_size <= status_start).then_some(layout)
+}
+
+/// After a test swap, write the status to the primary slot, with the hashes
+/// of both images, so that a revert can go by them.
+fn record_status(dev: &Device) -> Result<()> {
+    let main_size = image_bytes(&dev.secondary)?.len();
+    let upgrade_size = image_bytes(&dev.primary)?.len();
+    let Some(layout) = status_layout(dev, main_size, upgrade_size) else {
+        return Ok(());
+    };
+    let status_err = |e| anyhow!("Unable to write the status: {:?}", e);
+    // Seeded the same each time, so a run can be reproduced.
+    let status = Status {
+        main_size: main_size as u32,
+        upgrade_size: upgrade_size as u32,
+        hash_seed: new_hash_seed(&mut SeededEntropy::new(main_size as u64)).map_err(status_err)?,
+        ..Status::default()
+    };
+    let mut primary = dev.primary.borrow_mut();
+    let mut secondary = dev.secondary.borrow_mut();
+    layout.write(&mut *primary, &status).map_err(status_err)?;
+
+    // The old image is now in the secondary slot, and the upgrade in the
+    // primary.
+    let mut crypto = SoftCrypto::new();
+    let mut hashes = vec![];
+    for image in 0..2 {
+        let flash = if image == 0 { &mut *secondary } else { &mut *primary };
+        for group in 0..layout.image_groups(image) {
+            hashes.push(layout.record_hash(&mut crypto, &status, image, group, flash).map_err(status_err)?);
+        }
+    }
+    layout.write_hashes(&mut *primary, &hashes).map_err(status_err)
+}
+
+/// Put back the image a test swap replaced, and the test image in the
+/// secondary slot, clearing both trailers.  Returns the number of groups of
+/// sectors that didn't need copying back.
+fn revert(dev: &Device) -> Result<usize> {
+    let test = image_bytes(&dev.primary)?;
+    let old = image_bytes(&dev.secondary)?;
+    let Some(layout) = status_layout(dev, old.len(), test.len()) else {
+        return swap(dev).map(|()| 0);
+    };
+    let result = copy_back(&mut SoftCrypto::new(), &layout, 0, &mut *dev.secondary.borrow_mut(),
+                           &mut *dev.primary.borrow_mut());
+    let done = match result {
+        Ok(done) => done,
+        // No status or hashes to go by.
+        Err(boot::Error::CannotUpgrade) => return swap(dev).map(|()| 0),
+        Err(e) => return Err(anyhow!("Unable to copy back the old image: {:?}", e)),
+    };
+    let mut primary = dev.primary.borrow_mut();
+
+    // What is left of the test image past the old one, then the status and
+    // the trailer.
+    let capacity = primary.capacity();
+    let end = layout.image_groups(0) * layout.group * layout.erase_size;
+    primary.erase(end, capacity).map_err(|e| anyhow!("Erase failed: {:?}", e))?;
+    drop(primary);
+
+    let mut secondary = dev.secondary.borrow_mut();
+    let capacity = secondary.capacity();
+    secondary.erase(0, capacity).map_err(|e| anyhow!("Erase failed: {:?}", e))?;
+    secondary.install(&test, 0).map_err(|e| anyhow!("Write failed: {:?}", e))?;
+    Ok(done.skipped)
+}
+
+/// The image in a slot, without its trailer.  An empty slot gives nothing.
+fn image_bytes(slot: &RefCell<SimFlash>) -> Result<Vec<u8>> {
+    let size = match Image::from_flash(slot) {
+        Ok(image) => image.full_image_size(),
+        Err(_) => return Ok(vec![]),
+    };
+    let data = slot.borrow().dump();
+    data.get(..size).map(<[u8]>::to_vec).ok_or_else(|| anyhow!("Image is larger than its slot"))
+}
@@ -0,0 +1,118 @@
+//! bootsim: run the bootloader on flash dumps.
+//!
+//!     cargo run -- layouts.toml lpc55 primary.bin secondary.bin --out result
+//!
+//! The layout file is in simflash's format (see `simflash::config`), and the
+//! device is one of the names in it.  Each boot prints what the bootloader
+//! decided.  The resulting flash contents are written to `primary.bin`,
+//! `secondary.bin` and, if there is a scratch area, `scratch.bin` in the
+//! output directory.
+
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use anyhow::{anyhow, bail, Result};
+
+use boot::{BootAction, VersionPolicy};
+use bootsim::{boot_with, confirm_primary, Device, Trust};
+
+const USAGE: &str = "\
+usage:
+    bootsim LAYOUT DEVICE PRIMARY SECONDARY [SCRATCH]
+            [--key PUBKEY.der] [--boots N] [--confirm] [--out DIR]
+            [--versions any|newer|rebuild]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {:#}", e);
+        exit(2);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let key = option(&mut args, "--key")?.map(fs::read).transpose()?;
+    let boots = match option(&mut args, "--boots")? {
+        Some(n) => n.parse().map_err(|_| anyhow!("Invalid boot count {:?}", n))?,
+        None => 1,
+    };
+    let out = option(&mut args, "--out")?;
+    let policy = match option(&mut args, "--versions")?.as_deref() {
+        None | Some("any") => VersionPolicy::Any,
+        Some("newer") => VersionPolicy::Newer,
+        Some("rebuild") => VersionPolicy::AllowRebuild,
+        Some(other) => bail!("Unknown version policy {:?}", other),
+    };
+    let confirm = match args.iter().position(|&arg| arg == "--confirm") {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    };
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with('-')) {
+        bail!("Unknown option {:?}\n{}", arg, USAGE);
+    }
+    let (layout, name, primary, secondary, scratch) = match args[..] {
+        [layout, name, primary, secondary] => (layout, name, primary, secondary, None),
+        [layout, name, primary, secondary, scratch] => (layout, name, primary, secondary, Some(scratch)),
+        _ => bail!("{}", USAGE),
+    };
+
+    let maps = simflash::config::load_slot_maps(layout)?;
+    let map = maps.iter().find(|map| map.name == name)
+        .ok_or_else(|| anyhow!("No device {:?} in {}", name, layout))?;
+    let scratch = scratch.map(fs::read).transpose()?;
+    let dev = Device::load(map, &fs::read(primary)?, &fs::read(secondary)?, scratch.as_deref())?;
+
+    let trust = match &key {
+        Some(key) => Trust::Key(key),
+        None => Trust::Hash,
+    };
+    for n in 1..=boots {
+        let outcome = boot_with(&dev, &trust, &policy)?;
+        println!("boot {}: {:?}", n, outcome.swap_type);
+        if let Some(err) = &outcome.rejected {
+            println!("    upgrade rejected: {:?} at 0x{:x}", err.code, err.offset);
+        }
+        if outcome.skipped > 0 {
+            println!("    {} groups of sectors already matched", outcome.skipped);
+        }
+        if outcome.action == BootAction::Recovery {
+            println!("    nothing to boot, recovery");
+        }
+        match &outcome.booted {
+            Ok(version) => println!("    booted {:?}", version),
+            Err(e) => println!("    no valid image: {:?}", e),
+        }
+        if confirm && outcome.booted.is_ok() && confirm_primary(&dev)? {
+            println!("    confirmed");
+        }
+    }
+
+    if let Some(out) = out {
+        let out = Path::new(&out);
+        fs::create_dir_all(out)?;
+        fs::write(out.join("primary.bin"), dev.primary.borrow().dump())?;
+        fs::write(out.join("secondary.bin"), dev.secondary.borrow().dump())?;
+        if let Some(scratch) = &dev.scratch {
+            fs::write(out.join("scratch.bin"), scratch.borrow().dump())?;
+        }
+    }
+    Ok(())
+}
+
+/// Take an option with a value out of the arguments.
+fn option(args: &mut Vec<&str>, name: &str) -> Result<Option<String>> {
+    match args.iter().position(|&arg| arg == name) {
+        Some(pos) if pos + 1 < args.len() => {
+            let value = args.remove(pos + 1).to_string();
+            args.remove(pos);
+            Ok(Some(value))
+        }
+        Some(_) => bail!("{} needs a value", name),
+        None => Ok(None),
+    }
+}
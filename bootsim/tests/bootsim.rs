@@ -0,0 +1,352 @@
+// Upgrades on dumps.
+
+use boot::{
+    confirm, confirm_after, copy_done, image_ok, last_boot_error, measure_stack, record_test_boot, request_upgrade, set_copy_done, swap_type, upgrade_requested,
+    BootAction, BootPolicy, BootView, DefaultPolicy, ErrorCode, RetainedWord, SecondaryCleanup, TestAttempts, Flag, Image, ImageVersion, SwapType, VersionPolicy,
+};
+use bootsim::{boot, boot_policy, boot_with, confirm_primary, Device, Trust};
+use simflash::gen::ImageBuilder;
+use simflash::styles::SlotMap;
+use storage::{Flash, ReadFlash};
+
+const HEADER_SIZE: usize = 256;
+
+const LAYOUT: &str = "
+[dev.main]
+read_size = 1
+write_size = 8
+erase_size = 4096
+sectors = 4
+
+[dev.upgrade]
+read_size = 1
+write_size = 8
+erase_size = 4096
+sectors = 4
+
+[dev.scratch]
+read_size = 1
+write_size = 8
+erase_size = 4096
+sectors = 1
+";
+
+fn map() -> SlotMap {
+    simflash::config::parse_slot_maps(LAYOUT).unwrap().remove(0)
+}
+
+fn version(major: u8) -> ImageVersion {
+    ImageVersion { major, minor: 0, revision: 0, build_num: 0 }
+}
+
+/// Build an image with the given version, and just a SHA256.
+fn build(major: u8) -> Vec<u8> {
+    let payload: Vec<u8> = (0..3000u32).map(|i| (i * major as u32) as u8).collect();
+    build_payload(major, &payload)
+}
+
+fn build_payload(major: u8, payload: &[u8]) -> Vec<u8> {
+    ImageBuilder::default().header_size(HEADER_SIZE).version(major, 0, 0, 0).payload(payload).build()
+}
+
+/// Check the states documented for an upgrade hold at every step.
+fn invariants(dev: &Device) {
+    dev.add_invariant("an image header", |slots| {
+        let header = |slot: &[u8]| slot[..4] == 0x96f3b83du32.to_le_bytes();
+        if header(&slots.primary) || header(&slots.secondary) {
+            Ok(())
+        } else {
+            Err("Neither slot has an image header".to_string())
+        }
+    });
+    dev.add_invariant("flag order", |slots| {
+        // The magic goes first, then image ok, then copy done.
+        let mut slot = map().main.build().unwrap();
+        slot.load(&slots.primary).unwrap();
+        let magic = upgrade_requested(&mut slot).unwrap();
+        let image_ok = image_ok(&mut slot).unwrap() != Flag::Unset;
+        let copy_done = copy_done(&mut slot).unwrap() != Flag::Unset;
+        if (image_ok || copy_done) && !magic {
+            return Err("A flag is set without the magic".to_string());
+        }
+        Ok(())
+    });
+}
+
+/// A device with version 1 installed, and the given upgrade pending.
+fn pending(upgrade: &[u8], permanent: bool) -> Device {
+    let dev = Device::load(&map(), &build(1), upgrade, None).unwrap();
+    let mut secondary = dev.secondary.borrow_mut();
+    request_upgrade(&mut *secondary).unwrap();
+    if permanent {
+        confirm(&mut *secondary).unwrap();
+    }
+    drop(secondary);
+    dev
+}
+
+#[test]
+fn upgrade_and_revert() {
+    // Not confirmed, the test image is reverted on the next boot.
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    invariants(&dev);
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    assert!(outcome.rejected.is_none());
+    assert_eq!(outcome.booted.unwrap(), version(2));
+
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Revert);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+}
+
+#[test]
+fn revert_skips_matching() {
+    // Two versions differing only in their first and last sectors.
+    let payload: Vec<u8> = (0..10000u32).map(|i| (i / 7) as u8).collect();
+    let v1 = build_payload(1, &payload);
+    let v2 = build_payload(2, &payload);
+    let dev = Device::load(&map(), &v1, &v2, None).unwrap();
+    let _chart = dev.chart_on_panic();
+    invariants(&dev);
+    request_upgrade(&mut *dev.secondary.borrow_mut()).unwrap();
+
+    // The swap records the hashes, and the revert copies back all but the
+    // second sector, which is the same in both images.
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    assert_eq!(outcome.skipped, 0);
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Revert);
+    assert_eq!(outcome.skipped, 1);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    // What is left is the same as swapping whole images.
+    assert_eq!(dev.primary.borrow().dump()[..v1.len()], v1);
+    assert_eq!(dev.secondary.borrow().dump()[..v2.len()], v2);
+    assert!(dev.secondary.borrow().dump()[v2.len()..].iter().all(|&b| b == 0xff));
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.skipped, 0);
+}
+
+#[test]
+fn revert_without_status() {
+    // Dumps of a device under test, from before it kept a status: it is
+    // reverted by exchanging the images whole.
+    let dev = Device::load(&map(), &build(2), &build(1), None).unwrap();
+    let _chart = dev.chart_on_panic();
+    invariants(&dev);
+    {
+        let mut primary = dev.primary.borrow_mut();
+        request_upgrade(&mut *primary).unwrap();
+        set_copy_done(&mut *primary).unwrap();
+    }
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Revert);
+    assert_eq!(outcome.skipped, 0);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+    assert_eq!(boot(&dev, &Trust::Hash).unwrap().swap_type, SwapType::None);
+}
+
+#[test]
+fn upgrade_and_confirm() {
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    invariants(&dev);
+    assert_eq!(boot(&dev, &Trust::Hash).unwrap().booted.unwrap(), version(2));
+    assert!(confirm_primary(&dev).unwrap());
+    assert!(!confirm_primary(&dev).unwrap());
+
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.booted.unwrap(), version(2));
+
+    // A permanent upgrade needs no confirmation.
+    let dev = pending(&build(3), true);
+    let _chart = dev.chart_on_panic();
+    invariants(&dev);
+    assert_eq!(boot(&dev, &Trust::Hash).unwrap().swap_type, SwapType::Perm);
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.booted.unwrap(), version(3));
+}
+
+#[test]
+fn corrupt_upgrade() {
+    let mut image = build(2);
+    image[HEADER_SIZE + 10] ^= 1;
+    let dev = pending(&image, false);
+    let _chart = dev.chart_on_panic();
+    let outcome = boot(&dev, &Trust::Hash).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    assert_eq!(outcome.rejected.unwrap().code, ErrorCode::InvalidImage);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    // The reason is left in the trailer for the application.
+    let err = last_boot_error(&mut *dev.secondary.borrow_mut()).unwrap().unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidImage);
+}
+
+#[test]
+fn dumps() {
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    boot(&dev, &Trust::Hash).unwrap();
+    let primary = dev.primary.borrow().dump();
+    let secondary = dev.secondary.borrow().dump();
+    assert_eq!(primary.len(), 4 * 4096);
+    assert_eq!(primary[..build(2).len()], build(2));
+    assert_eq!(secondary[..build(1).len()], build(1));
+
+    // Reloading the result carries on where it left off, with the revert.
+    let dev = Device::load(&map(), &primary, &secondary, None).unwrap();
+    let _chart = dev.chart_on_panic();
+    assert_eq!(boot(&dev, &Trust::Hash).unwrap().swap_type, SwapType::Revert);
+    assert_eq!(dev.primary.borrow().dump()[..build(1).len()], build(1));
+
+    // The scratch area is passed through, and must fit.
+    assert!(dev.scratch.is_some());
+    let dev = Device::load(&map(), &primary, &secondary, Some(&[0x5a; 100])).unwrap();
+    let _chart = dev.chart_on_panic();
+    let scratch = dev.scratch.as_ref().unwrap().borrow().dump();
+    assert_eq!(scratch[..100], [0x5a; 100]);
+    assert!(scratch[100..].iter().all(|&b| b == 0xff));
+    assert!(Device::load(&map(), &primary, &secondary, Some(&[0; 4097])).is_err());
+    assert!(Device::load(&map(), &primary, &[0; 5 * 4096], None).is_err());
+}
+
+#[test]
+fn stack() {
+    // The worst case of an upgrade, with its validation and swap.
+    let dev = pending(&build(2), false);
+    let (outcome, used) = measure_stack(|| boot(&dev, &Trust::Hash).unwrap());
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    println!("upgrade: {} bytes of stack", used);
+    assert!(used > 0 && used < 256 * 1024);
+}
+
+#[test]
+#[should_panic(expected = "Invariant \"empty\" broken by Erase")]
+fn broken_invariant() {
+    let dev = Device::load(&map(), &build(1), &build(2), None).unwrap();
+    dev.add_invariant("empty", |slots| {
+        if slots.secondary.iter().all(|&b| b == 0xff) {
+            Err("The secondary slot is empty".to_string())
+        } else {
+            Ok(())
+        }
+    });
+    let size = dev.secondary.borrow().capacity();
+    dev.secondary.borrow_mut().erase(0, size).unwrap();
+}
+
+#[test]
+fn downgrade_policy() {
+    // Version 1 is running, so an upgrade to 0 is refused when only newer
+    // versions are allowed, and the reason left in the trailer.
+    let dev = pending(&build(0), false);
+    let _chart = dev.chart_on_panic();
+    let outcome = boot_with(&dev, &Trust::Hash, &VersionPolicy::Newer).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    assert_eq!(outcome.rejected.unwrap().code, ErrorCode::InvalidImage);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    // With the flag, it goes ahead.
+    let dev = pending(&build(0), false);
+    let outcome = boot_with(&dev, &Trust::Hash, &VersionPolicy::AllowDowngrade(true)).unwrap();
+    assert!(outcome.rejected.is_none());
+    assert_eq!(outcome.booted.unwrap(), version(0));
+}
+
+/// Only take permanent upgrades, holding back anything still on test.
+struct PermanentOnly;
+
+impl BootPolicy for PermanentOnly {
+    fn decide(&self, view: &BootView) -> BootAction {
+        match view.swap_type {
+            SwapType::Perm if view.secondary.valid => BootAction::Swap { permanent: true },
+            _ => BootAction::BootPrimary,
+        }
+    }
+}
+
+#[test]
+fn custom_policy() {
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    let outcome = boot_policy(&dev, &Trust::Hash, &PermanentOnly).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::Test);
+    assert_eq!(outcome.action, BootAction::BootPrimary);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    confirm(&mut *dev.secondary.borrow_mut()).unwrap();
+    let outcome = boot_policy(&dev, &Trust::Hash, &PermanentOnly).unwrap();
+    assert_eq!(outcome.action, BootAction::Swap { permanent: true });
+    assert_eq!(outcome.booted.unwrap(), version(2));
+}
+
+#[test]
+fn cleanup_policy() {
+    // A permanent upgrade is confirmed as it goes in, so the old image can
+    // be erased straight away.
+    let dev = pending(&build(2), true);
+    let _chart = dev.chart_on_panic();
+    let policy = DefaultPolicy { cleanup: SecondaryCleanup::Erase, ..DefaultPolicy::default() };
+    let outcome = boot_policy(&dev, &Trust::Hash, &policy).unwrap();
+    assert_eq!(outcome.action, BootAction::Swap { permanent: true });
+    assert_eq!(outcome.booted.unwrap(), version(2));
+    assert!(dev.secondary.borrow().dump().iter().all(|&b| b == 0xff));
+
+    let outcome = boot_policy(&dev, &Trust::Hash, &policy).unwrap();
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.booted.unwrap(), version(2));
+
+    // By default it is kept.
+    let dev = pending(&build(2), true);
+    boot(&dev, &Trust::Hash).unwrap();
+    assert!(Image::from_flash(&dev.secondary).is_ok());
+}
+
+#[test]
+fn self_test() {
+    // The bootloader allows two boots of an image on test.
+    let mut word = 0u32;
+    let mut word = unsafe { RetainedWord::new(&mut word as *mut u32 as usize) };
+    let boot_counted = |dev: &Device, word: &mut RetainedWord| {
+        let kind = swap_type(&mut *dev.primary.borrow_mut(), &mut *dev.secondary.borrow_mut()).unwrap();
+        let attempts = record_test_boot(word, kind == SwapType::Revert);
+        let policy = TestAttempts { policy: DefaultPolicy::default(), attempts, max: 2 };
+        boot_policy(dev, &Trust::Hash, &policy).unwrap()
+    };
+
+    // A self-test that keeps failing uses up the attempts, then reverts.
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    assert_eq!(boot_counted(&dev, &mut word).booted.unwrap(), version(2));
+    for _ in 0..2 {
+        assert!(!confirm_after(&mut *dev.primary.borrow_mut(), || false).unwrap());
+        let outcome = boot_counted(&dev, &mut word);
+        assert_eq!(outcome.action, BootAction::BootPrimary);
+        assert_eq!(outcome.booted.unwrap(), version(2));
+    }
+    let outcome = boot_counted(&dev, &mut word);
+    assert_eq!(outcome.action, BootAction::Revert);
+    assert_eq!(outcome.booted.unwrap(), version(1));
+
+    // One that passes on a later attempt keeps the upgrade.
+    let dev = pending(&build(2), false);
+    let _chart = dev.chart_on_panic();
+    boot_counted(&dev, &mut word);
+    assert!(!confirm_after(&mut *dev.primary.borrow_mut(), || false).unwrap());
+    boot_counted(&dev, &mut word);
+    assert!(confirm_after(&mut *dev.primary.borrow_mut(), || true).unwrap());
+    let outcome = boot_counted(&dev, &mut word);
+    assert_eq!(outcome.swap_type, SwapType::None);
+    assert_eq!(outcome.booted.unwrap(), version(2));
+}
@@ -0,0 +1,205 @@
+//! ELF input
+//!
+//! The host tools and tests sign images made from the ELF files cargo builds,
+//! rather than needing them flattened with objcopy first.  `flatten` gives
+//! the same binary `objcopy -O binary` does: the contents of each loadable
+//! segment, placed by its physical (load) address, from the lowest one, with
+//! any gaps between them zero.  Segments with nothing in the file, such as
+//! `.bss`, are left out.  Initialized data, which runs from RAM, is loaded
+//! from flash, so its physical address is the one in flash.
+//!
+//! Both 32 and 64-bit little endian files are read.  `write` makes a minimal
+//! 32-bit file, for tests.
+
+use anyhow::{anyhow, bail, Result};
+
+/// The largest gap allowed between segments.  Anything further apart is
+/// almost certainly a segment in another memory, such as RAM code, that a
+/// flattened binary can't hold.
+pub const MAX_GAP: u64 = 1024 * 1024;
+
+const PT_LOAD: u32 = 1;
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_ARM: u16 = 40;
+
+/// A segment's contents, and where they are loaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub addr: u64,
+    pub data: Vec<u8>,
+}
+
+/// The loadable part of an ELF file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Elf {
+    /// The entry point.  On Cortex-M, this is the reset handler, with the
+    /// Thumb bit set.
+    pub entry: u64,
+    /// The segments with contents in the file, by address.
+    pub segments: Vec<Segment>,
+}
+
+/// A flattened image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Flat {
+    /// The address of the first byte.
+    pub base: u64,
+    pub data: Vec<u8>,
+    pub entry: u64,
+}
+
+impl Flat {
+    /// Is the entry point within the image?  The low bit is ignored, as it
+    /// marks Thumb code.
+    pub fn contains_entry(&self) -> bool {
+        let entry = self.entry & !1;
+        entry >= self.base && entry < self.base + self.data.len() as u64
+    }
+}
+
+/// Does `data` look like an ELF file?
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7fELF")
+}
+
+/// A little endian reader of the fields of a file of either class.
+struct Reader<'a> {
+    data: &'a [u8],
+    wide: bool,
+}
+
+impl Reader<'_> {
+    fn bytes(&self, pos: u64, len: u64) -> Result<&[u8]> {
+        let end = pos.checked_add(len).ok_or_else(|| anyhow!("ELF offset overflows"))?;
+        self.data
+            .get(pos as usize..end as usize)
+            .ok_or_else(|| anyhow!("ELF file is truncated at 0x{:x}", end))
+    }
+
+    fn u16(&self, pos: u64) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(pos, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, pos: u64) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(pos, 4)?.try_into().unwrap()))
+    }
+
+    /// An address or offset, which is the size of the class.
+    fn word(&self, pos: u64) -> Result<u64> {
+        if self.wide {
+            Ok(u64::from_le_bytes(self.bytes(pos, 8)?.try_into().unwrap()))
+        } else {
+            self.u32(pos).map(u64::from)
+        }
+    }
+}
+
+impl Elf {
+    pub fn parse(data: &[u8]) -> Result<Elf> {
+        if !is_elf(data) || data.len() < 16 {
+            bail!("Not an ELF file");
+        }
+        let wide = match data[4] {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            class => bail!("Unknown ELF class {}", class),
+        };
+        if data[5] != ELFDATA2LSB {
+            bail!("Only little endian ELF files are supported");
+        }
+        let r = Reader { data, wide };
+
+        // The header fields after e_entry move with the size of a word.
+        let w = if wide { 8 } else { 4 };
+        let entry = r.word(24)?;
+        let phoff = r.word(24 + w)?;
+        let phentsize = r.u16(24 + 3 * w + 6)? as u64;
+        let phnum = r.u16(24 + 3 * w + 8)? as u64;
+
+        let mut segments = vec![];
+        for n in 0..phnum {
+            let ph = phoff + n * phentsize;
+            if r.u32(ph)? != PT_LOAD {
+                continue;
+            }
+            // ELF64 moves p_flags up, before the offset.
+            let (offset, paddr, filesz) = if wide {
+                (r.word(ph + 8)?, r.word(ph + 24)?, r.word(ph + 32)?)
+            } else {
+                (r.word(ph + 4)?, r.word(ph + 12)?, r.word(ph + 16)?)
+            };
+            if filesz == 0 {
+                continue;
+            }
+            segments.push(Segment { addr: paddr, data: r.bytes(offset, filesz)?.to_vec() });
+        }
+        segments.sort_by_key(|seg| seg.addr);
+        Ok(Elf { entry, segments })
+    }
+
+    /// Lay the segments out as one binary.
+    pub fn flatten(&self) -> Result<Flat> {
+        let base = match self.segments.first() {
+            Some(seg) => seg.addr,
+            None => bail!("ELF file has nothing to load"),
+        };
+        let mut data: Vec<u8> = vec![];
+        for seg in &self.segments {
+            let pos = seg.addr - base;
+            let end = base + data.len() as u64;
+            if seg.addr < end {
+                bail!("Segments overlap at 0x{:x}", seg.addr);
+            }
+            if seg.addr - end > MAX_GAP {
+                bail!("Segment at 0x{:x} is too far from the one before, ending at 0x{:x}", seg.addr, end);
+            }
+            data.resize(pos as usize, 0);
+            data.extend(&seg.data);
+        }
+        Ok(Flat { base, data, entry: self.entry })
+    }
+}
+
+/// Flatten the ELF file in `data`.
+pub fn flatten(data: &[u8]) -> Result<Flat> {
+    Elf::parse(data)?.flatten()
+}
+
+/// Write a minimal 32-bit ARM executable loading `segments`, each with the
+/// same virtual and physical address.
+pub fn write(entry: u32, segments: &[Segment]) -> Vec<u8> {
+    const EHSIZE: usize = 52;
+    const PHENTSIZE: usize = 32;
+
+    let mut out = vec![];
+    out.extend(b"\x7fELF");
+    out.extend([ELFCLASS32, ELFDATA2LSB, 1]);
+    out.resize(16, 0);
+    out.extend(2u16.to_le_bytes()); // ET_EXEC
+    out.extend(EM_ARM.to_le_bytes());
+    out.extend(1u32.to_le_bytes());
+    out.extend(entry.to_le_bytes());
+    out.extend((EHSIZE as u32).to_le_bytes()); // e_phoff
+    out.extend(0u32.to_le_bytes()); // e_shoff
+    out.extend(0x0500_0200u32.to_le_bytes()); // e_flags: EABI5, hard float
+    out.extend((EHSIZE as u16).to_le_bytes());
+    out.extend((PHENTSIZE as u16).to_le_bytes());
+    out.extend((segments.len() as u16).to_le_bytes());
+    out.extend([0u8; 6]); // No sections.
+
+    let mut offset = EHSIZE + PHENTSIZE * segments.len();
+    for seg in segments {
+        let addr = seg.addr as u32;
+        let size = seg.data.len() as u32;
+        for field in [PT_LOAD, offset as u32, addr, addr, size, size, 5, 4] {
+            out.extend(field.to_le_bytes());
+        }
+        offset += seg.data.len();
+    }
+    for seg in segments {
+        out.extend(&seg.data);
+    }
+    out
+}
@@ -0,0 +1,43 @@
+// ELF flattening.
+
+use elf::{flatten, is_elf, write, Elf, Segment};
+
+fn segment(addr: u64, data: &[u8]) -> Segment {
+    Segment { addr, data: data.to_vec() }
+}
+
+#[test]
+fn flattened() {
+    // Code, and initialized data loaded after it, out of order in the file.
+    let file = write(0x1_0101, &[segment(0x1_0400, &[5, 6]), segment(0x1_0000, &[1, 2, 3, 4])]);
+    assert!(is_elf(&file));
+
+    let parsed = Elf::parse(&file).unwrap();
+    assert_eq!(parsed.entry, 0x1_0101);
+    assert_eq!(parsed.segments[0], segment(0x1_0000, &[1, 2, 3, 4]));
+
+    let flat = flatten(&file).unwrap();
+    assert_eq!(flat.base, 0x1_0000);
+    assert_eq!(flat.data.len(), 0x402);
+    assert_eq!(flat.data[..4], [1, 2, 3, 4]);
+    assert!(flat.data[4..0x400].iter().all(|&b| b == 0));
+    assert_eq!(flat.data[0x400..], [5, 6]);
+    assert!(flat.contains_entry());
+}
+
+#[test]
+fn rejected() {
+    assert!(!is_elf(b"\x3d\xb8\xf3\x96"));
+    assert!(flatten(b"\x7fELF").is_err());
+    assert!(flatten(&write(0, &[])).is_err());
+
+    // RAM is too far from flash to flatten together.
+    let file = write(0, &[segment(0x1_0000, &[1]), segment(0x2000_0000, &[2])]);
+    assert!(flatten(&file).is_err());
+    let file = write(0, &[segment(0x1_0000, &[1, 2]), segment(0x1_0001, &[3])]);
+    assert!(flatten(&file).is_err());
+
+    // Truncated files are caught.
+    let file = write(0, &[segment(0x1_0000, &[1, 2, 3, 4])]);
+    assert!(flatten(&file[..file.len() - 1]).is_err());
+}
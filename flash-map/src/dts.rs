@@ -0,0 +1,122 @@
+//! Devicetree partitions
+//!
+//! Only the `fixed-partitions` node is looked at, with one cell each for the
+//! address and size, as Zephyr's board files and overlays have it:
+//!
+//! ```dts
+//! &flash0 {
+//!     partitions {
+//!         compatible = "fixed-partitions";
+//!         #address-cells = <1>;
+//!         #size-cells = <1>;
+//!
+//!         boot_partition: partition@0 {
+//!             label = "mcuboot";
+//!             reg = <0x00000000 0x00010000>;
+//!         };
+//!         slot0_partition: partition@10000 {
+//!             label = "image-0";
+//!             reg = <0x00010000 0x00070000>;
+//!         };
+//!     };
+//! };
+//! ```
+//!
+//! A partition is named by its node label, or failing that its `label`.
+
+use anyhow::{anyhow, Result};
+
+use crate::{parse_int, Partition};
+
+/// Parse the partitions from devicetree source.
+pub fn parse_dts(text: &str) -> Result<Vec<Partition>> {
+    let text = strip_comments(text);
+    let start = text.find("\"fixed-partitions\"")
+        .ok_or_else(|| anyhow!("No fixed-partitions node"))?;
+    // Back up to the start of the node holding the compatible.
+    let start = text[..start].rfind('{').ok_or_else(|| anyhow!("Malformed devicetree"))?;
+    let body = block(&text[start..])?;
+
+    let mut parts = vec![];
+    let mut rest = body;
+    while let Some(pos) = rest.find("partition@") {
+        let before = &rest[..pos];
+        let statement = &before[before.rfind(['{', '}', ';']).map_or(0, |p| p + 1)..];
+        let node_label = statement.split(':').next().filter(|_| statement.contains(':'))
+            .map(|label| label.trim().to_string());
+
+        let open = pos + rest[pos..].find('{').ok_or_else(|| anyhow!("Malformed partition node"))?;
+        let unit = rest[pos + "partition@".len()..open].trim();
+        let node = block(&rest[open..])?;
+        rest = &rest[open + node.len() + 2..];
+
+        let mut label = None;
+        let mut reg = None;
+        for prop in node.split(';') {
+            match prop.split_once('=') {
+                Some((key, value)) if key.trim() == "label" => {
+                    label = Some(value.trim().trim_matches('"').to_string());
+                }
+                Some((key, value)) if key.trim() == "reg" => reg = Some(parse_reg(value, unit)?),
+                _ => (),
+            }
+        }
+        let (base, size) = reg.ok_or_else(|| anyhow!("Partition @{} has no reg", unit))?;
+        let name = node_label.or(label).ok_or_else(|| anyhow!("Partition @{} has no label", unit))?;
+        parts.push(Partition { name, base, size });
+    }
+    if parts.is_empty() {
+        return Err(anyhow!("No partitions in the fixed-partitions node"));
+    }
+    Ok(parts)
+}
+
+/// Parse `<base size>`.
+fn parse_reg(value: &str, unit: &str) -> Result<(usize, usize)> {
+    let bad = || anyhow!("Partition @{}: expecting reg = <base size>", unit);
+    let cells = value.trim().strip_prefix('<').and_then(|v| v.strip_suffix('>')).ok_or_else(bad)?;
+    let cells: Vec<usize> = cells.split_whitespace().map(parse_int).collect::<Option<_>>().ok_or_else(bad)?;
+    match cells[..] {
+        [base, size] => Ok((base, size)),
+        _ => Err(bad()),
+    }
+}
+
+/// The contents of the `{ ... }` block `text` starts with.
+fn block(text: &str) -> Result<&str> {
+    let mut depth = 0;
+    for (pos, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(&text[1..pos]);
+                }
+            }
+            _ => (),
+        }
+    }
+    Err(anyhow!("Unterminated block in devicetree"))
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        match (rest.find("//"), rest.find("/*")) {
+            (Some(line), block) if block.is_none_or(|block| line < block) => {
+                out.push_str(&rest[..line]);
+                rest = rest[line..].find('\n').map_or("", |end| &rest[line + end..]);
+            }
+            (_, Some(block)) => {
+                out.push_str(&rest[..block]);
+                rest = rest[block..].find("*/").map_or("", |end| &rest[block + end + 2..]);
+            }
+            _ => {
+                out.push_str(rest);
+                return out;
+            }
+        }
+    }
+}
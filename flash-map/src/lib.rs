@@ -0,0 +1,155 @@
+//! Zephyr flash layouts
+//!
+//! Projects coming from Zephyr and MCUboot already describe their flash
+//! layout, either as the `fixed-partitions` node of a devicetree, or as the
+//! Partition Manager's `pm_static.yml`.  This reads either, and generates the
+//! partition constants a board needs, so that file stays the only description
+//! of the layout.  It is meant to be used from a board's `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     flash_map::build("partitions.dts").unwrap();
+//! }
+//! ```
+//!
+//! and then in the board:
+//!
+//! ```ignore
+//! mod partitions {
+//!     include!(concat!(env!("OUT_DIR"), "/partitions.rs"));
+//! }
+//!
+//! let slot0 = flash.partition(partitions::PRIMARY.base, partitions::PRIMARY.size)?;
+//! ```
+//!
+//! Each partition becomes a constant named after it: the node label (without
+//! a `_partition` suffix) or `label` property for a devicetree, or the name
+//! for the Partition Manager, in upper case.  The slots MCUboot uses are also
+//! given as `PRIMARY`, `SECONDARY` and, if there is one, `SCRATCH`, and `ALL`
+//! lists every partition in address order.
+
+use std::fmt::Write;
+use std::{env, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+
+mod dts;
+mod yaml;
+
+pub use dts::parse_dts;
+pub use yaml::parse_pm_yaml;
+
+/// A partition, as described by the layout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Partition {
+    pub name: String,
+    pub base: usize,
+    pub size: usize,
+}
+
+impl Partition {
+    pub fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// The name of the constant for this partition.
+    pub fn const_name(&self) -> String {
+        let name = self.name.strip_suffix("_partition").unwrap_or(&self.name);
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect()
+    }
+}
+
+/// The names the MCUboot slots go by, in each form of layout.
+const ROLES: [(&str, [&str; 3]); 3] = [
+    ("PRIMARY", ["slot0_partition", "image-0", "mcuboot_primary"]),
+    ("SECONDARY", ["slot1_partition", "image-1", "mcuboot_secondary"]),
+    ("SCRATCH", ["scratch_partition", "image-scratch", "mcuboot_scratch"]),
+];
+
+/// Read a layout file: Partition Manager YAML if it is named `.yml` or
+/// `.yaml`, and devicetree source otherwise.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Partition>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yml" | "yaml") => parse_pm_yaml(&text),
+        _ => parse_dts(&text),
+    }
+}
+
+/// Generate the Rust source for the partitions.  They must not overlap, and
+/// the primary and secondary slots must be present.
+pub fn generate(parts: &[Partition]) -> Result<String> {
+    let mut parts = parts.to_vec();
+    parts.sort_by_key(|part| part.base);
+    for pair in parts.windows(2) {
+        if pair[0].end() > pair[1].base {
+            return Err(anyhow!("Partitions {} and {} overlap", pair[0].name, pair[1].name));
+        }
+    }
+    for (i, part) in parts.iter().enumerate() {
+        if part.size == 0 {
+            return Err(anyhow!("Partition {} is empty", part.name));
+        }
+        if parts[..i].iter().any(|other| other.const_name() == part.const_name()) {
+            return Err(anyhow!("More than one partition is named {}", part.const_name()));
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by flash-map.  Do not edit.").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "pub struct Partition {{").unwrap();
+    writeln!(out, "    pub name: &'static str,").unwrap();
+    writeln!(out, "    pub base: usize,").unwrap();
+    writeln!(out, "    pub size: usize,").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "impl Partition {{").unwrap();
+    writeln!(out, "    pub const fn end(&self) -> usize {{").unwrap();
+    writeln!(out, "        self.base + self.size").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    for part in &parts {
+        writeln!(out, "pub const {}: Partition = Partition {{ name: {:?}, base: 0x{:x}, size: 0x{:x} }};",
+                 part.const_name(), part.name, part.base, part.size).unwrap();
+    }
+    writeln!(out).unwrap();
+    for (role, names) in ROLES {
+        let part = parts.iter().find(|part| names.contains(&part.name.as_str()));
+        match part {
+            Some(part) => writeln!(out, "pub const {}: &Partition = &{};", role, part.const_name()).unwrap(),
+            None if role == "SCRATCH" => (),
+            None => return Err(anyhow!("No {} slot, expecting one of {:?}", role.to_lowercase(), names)),
+        }
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "/// All partitions, in address order.").unwrap();
+    let names: Vec<String> = parts.iter().map(|part| format!("&{}", part.const_name())).collect();
+    writeln!(out, "pub const ALL: [&Partition; {}] = [{}];", parts.len(), names.join(", ")).unwrap();
+    Ok(out)
+}
+
+/// For `build.rs`: generate `partitions.rs` in `OUT_DIR` from the given
+/// layout, and have cargo rebuild when it changes.
+pub fn build<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let out = env::var_os("OUT_DIR").ok_or_else(|| anyhow!("OUT_DIR is not set"))?;
+    let source = generate(&load(path)?)?;
+    fs::write(Path::new(&out).join("partitions.rs"), source)?;
+    println!("cargo:rerun-if-changed={}", path.display());
+    Ok(())
+}
+
+/// Parse an integer: decimal, or hex with `0x`.
+fn parse_int(text: &str) -> Option<usize> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
@@ -0,0 +1,101 @@
+//! Partition Manager layouts
+//!
+//! `pm_static.yml` (or the generated `partitions.yml`) maps each partition
+//! name to its properties:
+//!
+//! ```yaml
+//! mcuboot:
+//!   address: 0x0
+//!   size: 0xc000
+//! mcuboot_primary:
+//!   address: 0xc000
+//!   end_address: 0x80000
+//!   span: [mcuboot_pad, app]
+//! ```
+//!
+//! Only `address`, `size` and `end_address` are used, and only this block
+//! style of YAML is understood.  Partitions placed in another region (such as
+//! external flash) are left out, as are ones inside another (such as `app` in
+//! `mcuboot_primary`), which aren't useful to the bootloader.
+
+use anyhow::{anyhow, Result};
+
+use crate::{parse_int, Partition};
+
+#[derive(Default)]
+struct PartialPartition {
+    name: String,
+    address: Option<usize>,
+    size: Option<usize>,
+    end_address: Option<usize>,
+    region: Option<String>,
+}
+
+impl PartialPartition {
+    fn finish(&self) -> Result<Partition> {
+        let base = self.address.ok_or_else(|| anyhow!("{}: missing address", self.name))?;
+        let size = match (self.size, self.end_address) {
+            (Some(size), _) => size,
+            (None, Some(end)) if end >= base => end - base,
+            _ => return Err(anyhow!("{}: missing size", self.name)),
+        };
+        Ok(Partition { name: self.name.clone(), base, size })
+    }
+}
+
+/// Parse the partitions from Partition Manager YAML.
+pub fn parse_pm_yaml(text: &str) -> Result<Vec<Partition>> {
+    let mut partial: Vec<PartialPartition> = vec![];
+    for (lineno, line) in text.lines().enumerate() {
+        let lineno = lineno + 1;
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        }.trim_end();
+        // Block list items only appear in properties that aren't used.
+        if line.trim().is_empty() || line.trim() == "---" || line.trim().starts_with('-') {
+            continue;
+        }
+
+        let (key, value) = line.split_once(':')
+            .ok_or_else(|| anyhow!("line {}: expecting key: value", lineno))?;
+        if !key.starts_with(' ') {
+            partial.push(PartialPartition { name: key.to_string(), ..Default::default() });
+            continue;
+        }
+        let part = partial.last_mut()
+            .ok_or_else(|| anyhow!("line {}: property outside of a partition", lineno))?;
+        let field = match key.trim() {
+            "address" => &mut part.address,
+            "size" => &mut part.size,
+            "end_address" => &mut part.end_address,
+            "region" => {
+                part.region = Some(value.trim().to_string());
+                continue;
+            }
+            _ => continue,
+        };
+        *field = Some(parse_int(value).ok_or_else(|| anyhow!("line {}: invalid integer", lineno))?);
+    }
+
+    let parts: Vec<Partition> = partial.iter()
+        .filter(|part| part.region.as_deref().is_none_or(|region| region == "flash_primary"))
+        .map(PartialPartition::finish)
+        .collect::<Result<_>>()?;
+    // Drop those inside another, keeping the first of any with the same
+    // extent, as `app` and `mcuboot_primary_app` often are.
+    let inner = |i: usize, part: &Partition| {
+        parts.iter().enumerate().any(|(j, other)| {
+            j != i && other.base <= part.base && part.end() <= other.end()
+                && (other.size > part.size || j < i)
+        })
+    };
+    let parts: Vec<Partition> = parts.iter().enumerate()
+        .filter(|(i, part)| !inner(*i, part))
+        .map(|(_, part)| part.clone())
+        .collect();
+    if parts.is_empty() {
+        return Err(anyhow!("No partitions found"));
+    }
+    Ok(parts)
+}
@@ -0,0 +1,75 @@
+// Layout parsing and generation.
+
+use flash_map::{generate, load, parse_dts, parse_pm_yaml, Partition};
+
+fn part(name: &str, base: usize, size: usize) -> Partition {
+    Partition { name: name.to_string(), base, size }
+}
+
+#[test]
+fn devicetree() {
+    let parts = load("data/partitions.dts").unwrap();
+    assert_eq!(parts, [
+        part("boot_partition", 0, 0x10000),
+        part("slot0_partition", 0x10000, 0x40000),
+        part("slot0_ns_partition", 0x50000, 0x30000),
+        part("slot1_partition", 0x80000, 0x40000),
+        part("slot1_ns_partition", 0xc0000, 0x30000),
+        part("storage_partition", 0xf8000, 0x8000),
+    ]);
+
+    // Without node labels, the label property names them.
+    let parts = parse_dts("
+        partitions {
+            compatible = \"fixed-partitions\";
+            partition@0 { label = \"image-0\"; reg = <0x0 0x1000>; };
+            partition@1000 { reg = <0x1000 0x1000>; label = \"image-1\"; };
+        };").unwrap();
+    assert_eq!(parts, [part("image-0", 0, 0x1000), part("image-1", 0x1000, 0x1000)]);
+
+    assert!(parse_dts("/ { };").is_err());
+    assert!(parse_dts("p { compatible = \"fixed-partitions\"; partition@0 { label = \"a\"; }; };").is_err());
+    assert!(parse_dts("p { compatible = \"fixed-partitions\"; partition@0 { reg = <0 1 2>; }; };").is_err());
+}
+
+#[test]
+fn partition_manager() {
+    // The partitions within others, and those in external flash, are left
+    // out.
+    let parts = load("data/pm_static.yml").unwrap();
+    assert_eq!(parts, [
+        part("mcuboot", 0, 0xc000),
+        part("mcuboot_primary", 0xc000, 0x74000),
+        part("mcuboot_secondary", 0x80000, 0x74000),
+        part("settings_storage", 0xf8000, 0x8000),
+    ]);
+
+    let parts = parse_pm_yaml("a:\n  address: 0x100\n  end_address: 0x200\n").unwrap();
+    assert_eq!(parts, [part("a", 0x100, 0x100)]);
+    assert!(parse_pm_yaml("a:\n  size: 0x100\n").is_err());
+    assert!(parse_pm_yaml("  address: 0\n").is_err());
+}
+
+#[test]
+fn generated() {
+    let text = generate(&load("data/partitions.dts").unwrap()).unwrap();
+    assert!(text.contains("pub struct Partition {\n"));
+    assert!(text.contains(
+        "pub const SLOT0: Partition = Partition { name: \"slot0_partition\", base: 0x10000, size: 0x40000 };\n"));
+    assert!(text.contains("pub const SLOT0_NS: Partition"));
+    assert!(text.contains("pub const PRIMARY: &Partition = &SLOT0;\n"));
+    assert!(text.contains("pub const SECONDARY: &Partition = &SLOT1;\n"));
+    assert!(!text.contains("SCRATCH"));
+    assert!(text.ends_with(
+        "pub const ALL: [&Partition; 6] = [&BOOT, &SLOT0, &SLOT0_NS, &SLOT1, &SLOT1_NS, &STORAGE];\n"));
+
+    let text = generate(&load("data/pm_static.yml").unwrap()).unwrap();
+    assert!(text.contains("pub const PRIMARY: &Partition = &MCUBOOT_PRIMARY;\n"));
+
+    // Layouts that can't be right.
+    let slots = [part("image-0", 0, 0x1000), part("image-1", 0x1000, 0x1000)];
+    assert!(generate(&slots).is_ok());
+    assert!(generate(&slots[..1]).is_err());
+    assert!(generate(&[slots[0].clone(), part("image-1", 0xfff, 0x1000)]).is_err());
+    assert!(generate(&[slots[0].clone(), slots[1].clone(), part("image_0", 0x2000, 0x1000)]).is_err());
+}
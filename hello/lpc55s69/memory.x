@@ -1,13 +1,16 @@
-/* Partition table for development.
+/* Partition table for development.  This must agree with
+   boards/lpc55s69/src/partitions.rs.
    0    - 128k  - bootloader
-   128k - 384k  - slot 0
-   384k - 640k  - slot 1
+   128k - 256k  - slot 0
+   256k - 384k  - slot 1
+   384k - 512k  - core 1
 */
 MEMORY
 {
   BOOT_HEADER : ORIGIN = 0x0020000, LENGTH = 1024
-  FLASH : ORIGIN = 0x00020000 + 1024, LENGTH = 256K - 1024
-  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+  FLASH : ORIGIN = 0x00020000 + 1024, LENGTH = 128K - 1024
+  /* The last word of RAM is shared with the bootloader for boot requests. */
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K - 4
 }
 
 SECTIONS {
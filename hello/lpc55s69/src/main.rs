@@ -6,7 +6,8 @@ extern crate panic_semihosting;
 
 use cortex_m_rt::entry;
 
-use embedded_hal::digital::v2::OutputPin;
+use boot::RetainedWord;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 use hal::{drivers::pins::Level};
 use lpc55_hal as hal;
 use embedded_time::rate::Extensions;
@@ -17,6 +18,16 @@ use cortex_m_semihosting::{hprintln};
 #[used]
 pub static BOOT_HEADER: [u8; 1024] = [0; 1024];
 
+/// The word of RAM shared with the bootloader for boot requests.
+const BOOT_REQUEST: usize = 0x2003_fffc;
+
+/// Reset into the bootloader's recovery mode.
+fn enter_recovery() -> ! {
+    let mut request = unsafe { RetainedWord::new(BOOT_REQUEST) };
+    boot::request_recovery(&mut request);
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
 #[entry]
 fn main() -> ! {
     let hal = hal::new();
@@ -42,7 +53,16 @@ fn main() -> ! {
         .into_gpio_pin(&mut iocon, &mut gpio)
         .into_output(Level::High);
 
+    // Pressing the user button goes back to the bootloader for recovery.
+    let button = pins
+        .pio1_9
+        .into_gpio_pin(&mut iocon, &mut gpio)
+        .into_input();
+
     loop {
+        if button.is_low().unwrap() {
+            enter_recovery();
+        }
         red.set_low().unwrap();
         hal::wait_at_least(300_000);
         red.set_high().unwrap();
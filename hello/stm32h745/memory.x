@@ -0,0 +1,23 @@
+/* Partition table for development.  This must agree with
+   boards/stm32h745/src/main.rs.
+   0x08000000 - 128k - bootloader
+   0x08020000 - 512k - slot 0
+   0x080a0000 - 384k - CM4
+   Bank 2 (0x08100000) is laid out the same way, and its slot 0 is the upgrade
+   slot.  Images are always linked for bank 1's slot 0; bank swapping moves
+   them there.
+*/
+MEMORY
+{
+  BOOT_HEADER : ORIGIN = 0x08020000, LENGTH = 1024
+  FLASH : ORIGIN = 0x08020000 + 1024, LENGTH = 512K - 1024
+  RAM : ORIGIN = 0x20000000, LENGTH = 128K
+}
+
+SECTIONS {
+  /* ### Boot header */
+  .boot_header ORIGIN(BOOT_HEADER) :
+  {
+    KEEP(*(.boot_header));
+  } > BOOT_HEADER
+} INSERT BEFORE .text;
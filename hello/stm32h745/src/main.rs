@@ -0,0 +1,59 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use cortex_m_rt::entry;
+
+use hal::gpio::GpioExt;
+use hal::pac;
+use hal::pwr::PwrExt;
+use hal::rcc::RccExt;
+use stm32h7xx_hal as hal;
+
+#[link_section = ".boot_header"]
+#[used]
+pub static BOOT_HEADER: [u8; 1024] = [0; 1024];
+
+/// The bootloader leaves the independent watchdog running, so it must be fed.
+const IWDG_FEED: u32 = 0xaaaa;
+
+/// With the `hang` feature, stop after this many blinks.
+#[cfg(feature = "hang")]
+const HANG_AFTER: u32 = 5;
+
+/// Cycles per half blink, at the reset clock of 64 MHz.
+const HALF_BLINK: u32 = 16_000_000;
+
+fn feed_watchdog(iwdg: &pac::IWDG1) {
+    iwdg.kr.write(|w| unsafe { w.bits(IWDG_FEED) });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    let pwr = dp.PWR.constrain();
+    let pwrcfg = pwr.freeze();
+    let ccdr = dp.RCC.constrain().freeze(pwrcfg, &dp.SYSCFG);
+
+    let gpiob = dp.GPIOB.split(ccdr.peripheral.GPIOB);
+    let mut led = gpiob.pb14.into_push_pull_output();
+
+    let mut blinks = 0u32;
+    loop {
+        #[cfg(feature = "hang")]
+        if blinks == HANG_AFTER {
+            // Stop feeding the watchdog, so it resets into the bootloader.
+            loop {
+                cortex_m::asm::nop();
+            }
+        }
+        feed_watchdog(&dp.IWDG1);
+        led.set_high();
+        cortex_m::asm::delay(HALF_BLINK);
+        led.set_low();
+        cortex_m::asm::delay(HALF_BLINK);
+        blinks = blinks.wrapping_add(1);
+    }
+}
@@ -0,0 +1,12 @@
+#! /bin/bash
+
+# Keys made by openssl, to check the keys crate against.  These are test keys
+# only.
+
+openssl genpkey -algorithm ed25519 -out ed25519.pem
+openssl pkey -in ed25519.pem -pubout -outform der -out ed25519-pub.der
+
+openssl genpkey -algorithm rsa -pkeyopt rsa_keygen_bits:2048 -out rsa-2048.pem
+# imgtool uses the PKCS#1 form of RSA public keys.
+openssl rsa -in rsa-2048.pem -RSAPublicKey_out -outform der -out rsa-2048-pub.der
+openssl pkey -in rsa-2048.pem -pubout -out rsa-2048-spki.pem
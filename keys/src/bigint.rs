@@ -0,0 +1,229 @@
+//! Big unsigned integers, for RSA
+//!
+//! Little-endian 64-bit limbs, with no high zero limbs.  Only what key
+//! generation needs: comparison, the basic arithmetic, remainders, and modular
+//! exponentiation, which uses Montgomery multiplication as the moduli are all
+//! odd.
+
+use std::cmp::Ordering;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Big(Vec<u64>);
+
+impl Big {
+    pub fn from_u64(value: u64) -> Big {
+        Big(vec![value]).normalized()
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> Big {
+        let limbs = bytes
+            .rchunks(8)
+            .map(|chunk| chunk.iter().fold(0u64, |acc, &b| acc << 8 | b as u64))
+            .collect();
+        Big(limbs).normalized()
+    }
+
+    /// Big-endian bytes, without leading zeros (but at least one byte).
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = self.0.iter().rev().flat_map(|limb| limb.to_be_bytes()).collect();
+        let start = out.iter().position(|&b| b != 0).unwrap_or(out.len().saturating_sub(1));
+        out.drain(..start);
+        if out.is_empty() {
+            out.push(0);
+        }
+        out
+    }
+
+    fn normalized(mut self) -> Big {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.0.first().is_some_and(|limb| limb & 1 != 0)
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.0.last() {
+            Some(top) => self.0.len() * 64 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn bit(&self, bit: usize) -> bool {
+        self.0.get(bit / 64).is_some_and(|limb| limb >> (bit % 64) & 1 != 0)
+    }
+
+    pub fn add(&self, other: &Big) -> Big {
+        let len = self.0.len().max(other.0.len());
+        let mut out = Vec::with_capacity(len + 1);
+        let mut carry = 0u128;
+        for i in 0..len {
+            let sum = *self.0.get(i).unwrap_or(&0) as u128 + *other.0.get(i).unwrap_or(&0) as u128 + carry;
+            out.push(sum as u64);
+            carry = sum >> 64;
+        }
+        out.push(carry as u64);
+        Big(out).normalized()
+    }
+
+    /// self - other, which must not be negative.
+    pub fn sub(&self, other: &Big) -> Big {
+        assert!(*self >= *other);
+        let mut out = Vec::with_capacity(self.0.len());
+        let mut borrow = false;
+        for (i, &a) in self.0.iter().enumerate() {
+            let (d, b1) = a.overflowing_sub(*other.0.get(i).unwrap_or(&0));
+            let (d, b2) = d.overflowing_sub(borrow as u64);
+            out.push(d);
+            borrow = b1 || b2;
+        }
+        Big(out).normalized()
+    }
+
+    pub fn mul(&self, other: &Big) -> Big {
+        let mut out = vec![0u64; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, &b) in other.0.iter().enumerate() {
+                let t = a as u128 * b as u128 + out[i + j] as u128 + carry;
+                out[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            out[i + other.0.len()] = carry as u64;
+        }
+        Big(out).normalized()
+    }
+
+    fn shl1(&self, bit: bool) -> Big {
+        let mut out = Vec::with_capacity(self.0.len() + 1);
+        let mut carry = bit as u64;
+        for &limb in &self.0 {
+            out.push(limb << 1 | carry);
+            carry = limb >> 63;
+        }
+        out.push(carry);
+        Big(out).normalized()
+    }
+
+    /// self mod m, a bit at a time.  Only used a few times per key.
+    pub fn rem(&self, m: &Big) -> Big {
+        let mut r = Big(vec![]);
+        for bit in (0..self.bits()).rev() {
+            r = r.shl1(self.bit(bit));
+            if r >= *m {
+                r = r.sub(m);
+            }
+        }
+        r
+    }
+
+    /// Divide by a small value, returning the quotient and remainder.
+    pub fn div_small(&self, d: u64) -> (Big, u64) {
+        let mut out = vec![0u64; self.0.len()];
+        let mut rem = 0u128;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let cur = rem << 64 | limb as u128;
+            out[i] = (cur / d as u128) as u64;
+            rem = cur % d as u128;
+        }
+        (Big(out).normalized(), rem as u64)
+    }
+
+    /// self^exp mod m, for odd m.
+    pub fn modpow(&self, exp: &Big, m: &Big) -> Big {
+        let mont = Mont::new(m);
+        let base = mont.enter(&self.rem(m));
+        let mut acc = mont.enter(&Big::from_u64(1));
+        for bit in (0..exp.bits()).rev() {
+            acc = mont.mul(&acc, &acc);
+            if exp.bit(bit) {
+                acc = mont.mul(&acc, &base);
+            }
+        }
+        mont.leave(&acc)
+    }
+}
+
+impl Ord for Big {
+    fn cmp(&self, other: &Big) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for Big {
+    fn partial_cmp(&self, other: &Big) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Montgomery arithmetic modulo an odd m, with R = 2^(64 * limbs).
+struct Mont<'a> {
+    m: &'a Big,
+    /// -m^-1 mod 2^64.
+    minv: u64,
+    /// R^2 mod m.
+    r2: Big,
+}
+
+impl<'a> Mont<'a> {
+    fn new(m: &'a Big) -> Mont<'a> {
+        assert!(m.is_odd());
+        // Newton's iteration doubles the correct bits each time.
+        let m0 = m.0[0];
+        let mut inv = 1u64;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m0.wrapping_mul(inv)));
+        }
+        let mut r2 = vec![0u64; 2 * m.0.len()];
+        r2.push(1);
+        Mont { m, minv: inv.wrapping_neg(), r2: Big(r2).rem(m) }
+    }
+
+    /// a * b / R mod m, with a and b below m.
+    fn mul(&self, a: &Big, b: &Big) -> Big {
+        let n = self.m.0.len();
+        let m = &self.m.0;
+        let mut t = vec![0u64; n + 2];
+        for i in 0..n {
+            let ai = *a.0.get(i).unwrap_or(&0) as u128;
+            let mut carry = 0u128;
+            for (j, tj) in t[..n].iter_mut().enumerate() {
+                let v = *tj as u128 + ai * *b.0.get(j).unwrap_or(&0) as u128 + carry;
+                *tj = v as u64;
+                carry = v >> 64;
+            }
+            let v = t[n] as u128 + carry;
+            t[n] = v as u64;
+            t[n + 1] = (v >> 64) as u64;
+
+            let q = t[0].wrapping_mul(self.minv) as u128;
+            let mut carry = (t[0] as u128 + q * m[0] as u128) >> 64;
+            for j in 1..n {
+                let v = t[j] as u128 + q * m[j] as u128 + carry;
+                t[j - 1] = v as u64;
+                carry = v >> 64;
+            }
+            let v = t[n] as u128 + carry;
+            t[n - 1] = v as u64;
+            t[n] = t[n + 1] + (v >> 64) as u64;
+            t[n + 1] = 0;
+        }
+        let t = Big(t).normalized();
+        if t >= *self.m {
+            t.sub(self.m)
+        } else {
+            t
+        }
+    }
+
+    fn enter(&self, a: &Big) -> Big {
+        self.mul(a, &self.r2)
+    }
+
+    fn leave(&self, a: &Big) -> Big {
+        self.mul(a, &Big::from_u64(1))
+    }
+}
@@ -0,0 +1,122 @@
+//! Just enough DER
+//!
+//! Key files are a handful of nested sequences of integers, octet strings, bit
+//! strings and object identifiers.  Reading pulls out the contents of the
+//! expected elements in order, and writing builds them back up.
+
+use anyhow::{anyhow, Result};
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_SEQUENCE: u8 = 0x30;
+/// The explicit `[1]` tag SEC1 uses for the public key.
+pub const TAG_CONTEXT_1: u8 = 0xa1;
+
+/// The contents of a DER element, read from the front.
+pub struct Der<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    pub fn new(data: &'a [u8]) -> Der<'a> {
+        Der { data }
+    }
+
+    /// Is there nothing left?
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The tag of the next element, if there is one.
+    pub fn peek(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Take the next element, which must have the given tag, returning its
+    /// contents.
+    pub fn expect(&mut self, tag: u8) -> Result<Der<'a>> {
+        let bad = || anyhow!("Malformed key");
+        let (&found, rest) = self.data.split_first().ok_or_else(bad)?;
+        if found != tag {
+            return Err(bad());
+        }
+        let (&first, mut rest) = rest.split_first().ok_or_else(bad)?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 2 || rest.len() < count {
+                return Err(bad());
+            }
+            let len = rest[..count].iter().fold(0usize, |len, &b| len << 8 | b as usize);
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return Err(bad());
+        }
+        self.data = &rest[len..];
+        Ok(Der { data: &rest[..len] })
+    }
+
+    /// Take an integer, returning its big-endian magnitude without the sign
+    /// padding.
+    pub fn integer(&mut self) -> Result<&'a [u8]> {
+        let value = self.expect(TAG_INTEGER)?.data;
+        match value {
+            [] => Err(anyhow!("Malformed key")),
+            [0, rest @ ..] if !rest.is_empty() => Ok(rest),
+            _ => Ok(value),
+        }
+    }
+
+    /// Take a bit string, which must have no unused bits.
+    pub fn bit_string(&mut self) -> Result<&'a [u8]> {
+        match self.expect(TAG_BIT_STRING)?.data {
+            [0, rest @ ..] => Ok(rest),
+            _ => Err(anyhow!("Malformed key")),
+        }
+    }
+}
+
+/// Encode an element.
+pub fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len < 0x100 {
+        out.extend([0x81, len as u8]);
+    } else {
+        out.extend([0x82, (len >> 8) as u8, len as u8]);
+    }
+    out.extend(contents);
+    out
+}
+
+/// Encode a sequence of already encoded elements.
+pub fn sequence(items: &[&[u8]]) -> Vec<u8> {
+    element(TAG_SEQUENCE, &items.concat())
+}
+
+/// Encode a non-negative integer, given as big-endian bytes.
+pub fn integer(value: &[u8]) -> Vec<u8> {
+    let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    let mut contents = vec![];
+    if value.first().is_none_or(|&b| b & 0x80 != 0) {
+        contents.push(0);
+    }
+    contents.extend(value);
+    element(TAG_INTEGER, &contents)
+}
+
+/// Encode a bit string with no unused bits.
+pub fn bit_string(value: &[u8]) -> Vec<u8> {
+    let mut contents = vec![0];
+    contents.extend(value);
+    element(TAG_BIT_STRING, &contents)
+}
@@ -0,0 +1,182 @@
+//! Ed25519 public keys
+//!
+//! Deriving the public key is a single multiplication of the base point, by
+//! the clamped hash of the private seed (RFC 8032, section 5.1.5).  Field
+//! elements are five 51-bit limbs, and points are in extended coordinates,
+//! using the unified addition formula for doubling as well.  Nothing here
+//! needs to be constant time: it runs on the host, on keys being generated or
+//! loaded.
+
+use sha2::{Digest, Sha512};
+
+const MASK: u64 = (1 << 51) - 1;
+
+#[derive(Clone, Copy)]
+struct Fe([u64; 5]);
+
+const ZERO: Fe = Fe([0; 5]);
+const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+/// 2 * d, where d = -121665/121666.
+const D2: Fe = Fe([0x69b9426b2f159, 0x35050762add7a, 0x3cf44c0038052, 0x6738cc7407977, 0x2406d9dc56dff]);
+
+/// The base point.
+const BASE: Point = Point {
+    x: Fe([0x62d608f25d51a, 0x412a4b4f6592a, 0x75b7171a4b31d, 0x1ff60527118fe, 0x216936d3cd6e5]),
+    y: Fe([0x6666666666658, 0x4cccccccccccc, 0x1999999999999, 0x3333333333333, 0x6666666666666]),
+    z: ONE,
+    t: Fe([0x68ab3a5b7dda3, 0x00eea2a5eadbb, 0x2af8df483c27e, 0x332b375274732, 0x67875f0fd78b7]),
+};
+
+impl Fe {
+    fn add(&self, other: &Fe) -> Fe {
+        let mut r = [0u64; 5];
+        for (i, r) in r.iter_mut().enumerate() {
+            *r = self.0[i] + other.0[i];
+        }
+        Fe(r).carry()
+    }
+
+    fn sub(&self, other: &Fe) -> Fe {
+        // Add 2p first, so the limbs stay positive.
+        const P2: [u64; 5] = [0xfffffffffffda, 0xffffffffffffe, 0xffffffffffffe, 0xffffffffffffe, 0xffffffffffffe];
+        let mut r = [0u64; 5];
+        for (i, r) in r.iter_mut().enumerate() {
+            *r = self.0[i] + P2[i] - other.0[i];
+        }
+        Fe(r).carry()
+    }
+
+    fn mul(&self, other: &Fe) -> Fe {
+        let a = self.0.map(|x| x as u128);
+        let b = other.0.map(|x| x as u128);
+        // Limbs past the top wrap around multiplied by 19, as 2^255 = 19.
+        let b19 = b.map(|x| x * 19);
+        let r = [
+            a[0] * b[0] + a[1] * b19[4] + a[2] * b19[3] + a[3] * b19[2] + a[4] * b19[1],
+            a[0] * b[1] + a[1] * b[0] + a[2] * b19[4] + a[3] * b19[3] + a[4] * b19[2],
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + a[3] * b19[4] + a[4] * b19[3],
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + a[4] * b19[4],
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+        let mut out = [0u64; 5];
+        let mut carry = 0u128;
+        for i in 0..5 {
+            let v = r[i] + carry;
+            out[i] = v as u64 & MASK;
+            carry = v >> 51;
+        }
+        out[0] += carry as u64 * 19;
+        Fe(out).carry()
+    }
+
+    /// Bring each limb back to 51 bits, give or take.
+    fn carry(mut self) -> Fe {
+        for i in 0..4 {
+            self.0[i + 1] += self.0[i] >> 51;
+            self.0[i] &= MASK;
+        }
+        self.0[0] += (self.0[4] >> 51) * 19;
+        self.0[4] &= MASK;
+        self
+    }
+
+    /// self^(p-2), the inverse.
+    fn invert(&self) -> Fe {
+        // p - 2 = 2^255 - 21: all ones but for bits 2 and 4.
+        let mut r = ONE;
+        for bit in (0..255).rev() {
+            r = r.mul(&r);
+            if bit != 2 && bit != 4 {
+                r = r.mul(self);
+            }
+        }
+        r
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut h = self.carry().carry().carry().0;
+        // Subtract p if the value is at least p, by adding 19 and seeing if
+        // that carries out of the top.
+        let mut q = (h[0] + 19) >> 51;
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+        h[4] &= MASK;
+
+        let mut out = [0u8; 32];
+        let mut acc = 0u128;
+        let mut bits = 0;
+        let mut pos = 0;
+        for limb in h {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                out[pos] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                pos += 1;
+            }
+        }
+        out[pos] = acc as u8;
+        out
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+impl Point {
+    const IDENTITY: Point = Point { x: ZERO, y: ONE, z: ONE, t: ZERO };
+
+    fn add(&self, other: &Point) -> Point {
+        let a = self.y.sub(&self.x).mul(&other.y.sub(&other.x));
+        let b = self.y.add(&self.x).mul(&other.y.add(&other.x));
+        let c = self.t.mul(&D2).mul(&other.t);
+        let d = self.z.add(&self.z).mul(&other.z);
+        let (e, f, g, h) = (b.sub(&a), d.sub(&c), d.add(&c), b.add(&a));
+        Point { x: e.mul(&f), y: g.mul(&h), z: f.mul(&g), t: e.mul(&h) }
+    }
+
+    /// Multiply the base point by a little-endian scalar.
+    fn mul_base(scalar: &[u8; 32]) -> Point {
+        let mut r = Point::IDENTITY;
+        for bit in (0..256).rev() {
+            r = r.add(&r);
+            if scalar[bit / 8] >> (bit % 8) & 1 != 0 {
+                r = r.add(&BASE);
+            }
+        }
+        r
+    }
+
+    /// The encoding: y, with the low bit of x in the top bit.
+    fn encode(&self) -> [u8; 32] {
+        let zinv = self.z.invert();
+        let x = self.x.mul(&zinv).to_bytes();
+        let mut out = self.y.mul(&zinv).to_bytes();
+        out[31] |= (x[0] & 1) << 7;
+        out
+    }
+}
+
+/// The public key for a private key seed.
+pub fn public_key(seed: &[u8; 32]) -> [u8; 32] {
+    let hash = Sha512::digest(seed);
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    Point::mul_base(&scalar).encode()
+}
@@ -0,0 +1,373 @@
+//! Signing keys
+//!
+//! Generating, reading and writing the keys images are signed with, shared by
+//! `mcuboot-tool`, simflash's `GenBuilder`, and the build of each board (which
+//! embeds the public key, or its hash).  Three types are handled, with the
+//! names imgtool uses: `ecdsa-p256`, `ed25519`, and `rsa-2048`/`rsa-3072`.
+//!
+//! Private keys are read from PEM, as PKCS#8 ("PRIVATE KEY"), SEC1 ("EC
+//! PRIVATE KEY"), or PKCS#1 ("RSA PRIVATE KEY"), and always written as PKCS#8,
+//! which is what imgtool writes.  Public keys are in the form imgtool's
+//! `getpub` gives, which is also what goes in the PUBKEY TLV and is hashed
+//! for the KEYHASH: a SubjectPublicKeyInfo, except for RSA, which uses the
+//! PKCS#1 RSAPublicKey.
+//!
+//! Only ECDSA P-256 is used by the bootloader at this point.  The other types
+//! are here so that keys for them can be made and provisioned by the same
+//! tools.
+
+mod bigint;
+mod der;
+mod ed25519;
+mod pem;
+mod rsa;
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+use boot::{ecdsa_public_key, SoftMul};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::der::{Der, TAG_CONTEXT_1, TAG_NULL, TAG_OCTET_STRING, TAG_OID, TAG_SEQUENCE};
+use crate::rsa::RsaPrivate;
+
+/// The DER encoding of a P-256 SubjectPublicKeyInfo, up to the point itself.
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03,
+    0x42, 0x00,
+];
+
+/// The DER encoding of an Ed25519 SubjectPublicKeyInfo, up to the key.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// id-ecPublicKey, 1.2.840.10045.2.1.
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// prime256v1, 1.2.840.10045.3.1.7.
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// id-Ed25519, 1.3.101.112.
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// rsaEncryption, 1.2.840.113549.1.1.1.
+const OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// The kinds of key that can be generated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyType {
+    EcdsaP256,
+    Ed25519,
+    Rsa2048,
+    Rsa3072,
+}
+
+impl KeyType {
+    /// Look up a type by its imgtool name.
+    pub fn from_name(name: &str) -> Result<KeyType> {
+        match name {
+            "ecdsa-p256" => Ok(KeyType::EcdsaP256),
+            "ed25519" => Ok(KeyType::Ed25519),
+            "rsa-2048" => Ok(KeyType::Rsa2048),
+            "rsa-3072" => Ok(KeyType::Rsa3072),
+            _ => bail!("Unknown key type {:?}, expecting ecdsa-p256, ed25519, rsa-2048 or rsa-3072", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyType::EcdsaP256 => "ecdsa-p256",
+            KeyType::Ed25519 => "ed25519",
+            KeyType::Rsa2048 => "rsa-2048",
+            KeyType::Rsa3072 => "rsa-3072",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Private {
+    EcdsaP256 { scalar: [u8; 32], public: [u8; 65] },
+    Ed25519 { seed: [u8; 32], public: [u8; 32] },
+    Rsa(RsaPrivate),
+}
+
+/// A private key.
+#[derive(Clone, Debug)]
+pub struct PrivateKey {
+    inner: Private,
+}
+
+impl PrivateKey {
+    /// Generate a new key.  The random numbers should come from a
+    /// cryptographic source, such as `rand::rngs::OsRng`, unless the key is
+    /// only for tests.
+    pub fn generate<R: RngCore>(kind: KeyType, rng: &mut R) -> PrivateKey {
+        match kind {
+            KeyType::EcdsaP256 => loop {
+                let mut scalar = [0u8; 32];
+                rng.fill_bytes(&mut scalar);
+                // Nearly every value is in range.
+                if let Ok(key) = PrivateKey::from_ecdsa_scalar(scalar) {
+                    return key;
+                }
+            },
+            KeyType::Ed25519 => {
+                let mut seed = [0u8; 32];
+                rng.fill_bytes(&mut seed);
+                PrivateKey::from_ed25519_seed(seed)
+            }
+            KeyType::Rsa2048 => PrivateKey { inner: Private::Rsa(RsaPrivate::generate(2048, rng)) },
+            KeyType::Rsa3072 => PrivateKey { inner: Private::Rsa(RsaPrivate::generate(3072, rng)) },
+        }
+    }
+
+    pub fn from_ecdsa_scalar(scalar: [u8; 32]) -> Result<PrivateKey> {
+        let public = ecdsa_public_key(&mut SoftMul, &scalar)
+            .ok_or_else(|| anyhow!("Private key is out of range"))?;
+        Ok(PrivateKey { inner: Private::EcdsaP256 { scalar, public } })
+    }
+
+    pub fn from_ed25519_seed(seed: [u8; 32]) -> PrivateKey {
+        let public = ed25519::public_key(&seed);
+        PrivateKey { inner: Private::Ed25519 { seed, public } }
+    }
+
+    /// Read a PEM private key.
+    pub fn from_pem(text: &str) -> Result<PrivateKey> {
+        let (label, der) = pem::decode(text)?;
+        match label.as_str() {
+            "PRIVATE KEY" => PrivateKey::from_pkcs8(&der),
+            "EC PRIVATE KEY" => PrivateKey::from_sec1(&der),
+            "RSA PRIVATE KEY" => PrivateKey::from_rsa(&der),
+            _ => bail!("Not a private key: {}", label),
+        }
+    }
+
+    /// Read a PKCS#8 private key.
+    pub fn from_pkcs8(der: &[u8]) -> Result<PrivateKey> {
+        let mut outer = Der::new(der).expect(TAG_SEQUENCE)?;
+        outer.integer()?;
+        let mut alg = outer.expect(TAG_SEQUENCE)?;
+        let oid = alg.expect(TAG_OID)?.data;
+        let key = outer.expect(TAG_OCTET_STRING)?.data;
+        if oid == OID_EC_PUBLIC_KEY {
+            if alg.expect(TAG_OID)?.data != OID_P256 {
+                bail!("Only the P-256 curve is supported for ECDSA");
+            }
+            PrivateKey::from_sec1(key)
+        } else if oid == OID_ED25519 {
+            let seed = Der::new(key).expect(TAG_OCTET_STRING)?.data;
+            let seed = seed.try_into().map_err(|_| anyhow!("Malformed key"))?;
+            Ok(PrivateKey::from_ed25519_seed(seed))
+        } else if oid == OID_RSA {
+            PrivateKey::from_rsa(key)
+        } else {
+            bail!("Unsupported key algorithm")
+        }
+    }
+
+    fn from_sec1(der: &[u8]) -> Result<PrivateKey> {
+        let mut outer = Der::new(der).expect(TAG_SEQUENCE)?;
+        outer.integer()?;
+        let value = outer.expect(TAG_OCTET_STRING)?.data;
+        if value.len() > 32 {
+            bail!("Private key is too large for P-256");
+        }
+        let mut scalar = [0u8; 32];
+        scalar[32 - value.len()..].copy_from_slice(value);
+        PrivateKey::from_ecdsa_scalar(scalar)
+    }
+
+    fn from_rsa(der: &[u8]) -> Result<PrivateKey> {
+        let key = RsaPrivate::from_pkcs1(der)?;
+        if !key.check() {
+            bail!("RSA key is inconsistent");
+        }
+        Ok(PrivateKey { inner: Private::Rsa(key) })
+    }
+
+    /// The key as PKCS#8 DER.
+    pub fn to_pkcs8(&self) -> Vec<u8> {
+        let version = der::integer(&[0]);
+        match &self.inner {
+            Private::EcdsaP256 { scalar, public } => {
+                let sec1 = der::sequence(&[
+                    &der::integer(&[1]),
+                    &der::element(TAG_OCTET_STRING, scalar),
+                    &der::element(TAG_CONTEXT_1, &der::bit_string(public)),
+                ]);
+                let alg = der::sequence(&[
+                    &der::element(TAG_OID, OID_EC_PUBLIC_KEY),
+                    &der::element(TAG_OID, OID_P256),
+                ]);
+                der::sequence(&[&version, &alg, &der::element(TAG_OCTET_STRING, &sec1)])
+            }
+            Private::Ed25519 { seed, .. } => {
+                let alg = der::sequence(&[&der::element(TAG_OID, OID_ED25519)]);
+                let key = der::element(TAG_OCTET_STRING, seed);
+                der::sequence(&[&version, &alg, &der::element(TAG_OCTET_STRING, &key)])
+            }
+            Private::Rsa(key) => {
+                let alg = der::sequence(&[&der::element(TAG_OID, OID_RSA), &der::element(TAG_NULL, &[])]);
+                der::sequence(&[&version, &alg, &der::element(TAG_OCTET_STRING, &key.to_pkcs1())])
+            }
+        }
+    }
+
+    /// The key as PKCS#8 PEM, as `imgtool keygen` writes it.
+    pub fn to_pem(&self) -> String {
+        pem::encode("PRIVATE KEY", &self.to_pkcs8())
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match &self.inner {
+            Private::EcdsaP256 { .. } => KeyType::EcdsaP256,
+            Private::Ed25519 { .. } => KeyType::Ed25519,
+            Private::Rsa(key) if key.bits() <= 2048 => KeyType::Rsa2048,
+            Private::Rsa(_) => KeyType::Rsa3072,
+        }
+    }
+
+    /// The private scalar, for an ECDSA P-256 key.
+    pub fn ecdsa_scalar(&self) -> Option<&[u8; 32]> {
+        match &self.inner {
+            Private::EcdsaP256 { scalar, .. } => Some(scalar),
+            _ => None,
+        }
+    }
+
+    pub fn public(&self) -> PublicKey {
+        let inner = match &self.inner {
+            Private::EcdsaP256 { public, .. } => Public::EcdsaP256(*public),
+            Private::Ed25519 { public, .. } => Public::Ed25519(*public),
+            Private::Rsa(key) => Public::Rsa { n: key.n.to_be_bytes(), e: key.e.to_be_bytes() },
+        };
+        PublicKey { inner }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Public {
+    EcdsaP256([u8; 65]),
+    Ed25519([u8; 32]),
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+/// A public key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PublicKey {
+    inner: Public,
+}
+
+impl PublicKey {
+    /// Read a public key, either DER or PEM.
+    pub fn from_bytes(data: &[u8]) -> Result<PublicKey> {
+        if data.starts_with(b"-----BEGIN") {
+            let (label, der) = pem::decode(std::str::from_utf8(data)?)?;
+            return match label.as_str() {
+                "PUBLIC KEY" => PublicKey::from_der(&der),
+                "RSA PUBLIC KEY" => PublicKey::from_rsa(&der),
+                _ => bail!("Not a public key: {}", label),
+            };
+        }
+        PublicKey::from_der(data)
+    }
+
+    /// Read a SubjectPublicKeyInfo, or an RSAPublicKey.
+    pub fn from_der(der: &[u8]) -> Result<PublicKey> {
+        if der.len() == P256_SPKI_PREFIX.len() + 65 && der[..P256_SPKI_PREFIX.len()] == P256_SPKI_PREFIX {
+            let mut point = [0u8; 65];
+            point.copy_from_slice(&der[P256_SPKI_PREFIX.len()..]);
+            return Ok(PublicKey { inner: Public::EcdsaP256(point) });
+        }
+        if der.len() == ED25519_SPKI_PREFIX.len() + 32 && der[..ED25519_SPKI_PREFIX.len()] == ED25519_SPKI_PREFIX {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&der[ED25519_SPKI_PREFIX.len()..]);
+            return Ok(PublicKey { inner: Public::Ed25519(key) });
+        }
+
+        // What remains could be either form of RSA key.
+        let mut outer = Der::new(der).expect(TAG_SEQUENCE)?;
+        if outer.peek() == Some(TAG_SEQUENCE) {
+            let mut alg = outer.expect(TAG_SEQUENCE)?;
+            if alg.expect(TAG_OID)?.data != OID_RSA {
+                bail!("Unsupported public key algorithm");
+            }
+            return PublicKey::from_rsa(outer.bit_string()?);
+        }
+        PublicKey::from_rsa(der)
+    }
+
+    fn from_rsa(der: &[u8]) -> Result<PublicKey> {
+        let mut outer = Der::new(der).expect(TAG_SEQUENCE)?;
+        let n = outer.integer()?.to_vec();
+        let e = outer.integer()?.to_vec();
+        if !outer.is_empty() {
+            bail!("Malformed key");
+        }
+        Ok(PublicKey { inner: Public::Rsa { n, e } })
+    }
+
+    /// The key as `imgtool getpub` writes it.  This is the form the
+    /// bootloader is given, and that goes in the PUBKEY TLV.
+    pub fn to_der(&self) -> Vec<u8> {
+        match &self.inner {
+            Public::EcdsaP256(point) => [&P256_SPKI_PREFIX[..], point].concat(),
+            Public::Ed25519(key) => [&ED25519_SPKI_PREFIX[..], key].concat(),
+            Public::Rsa { n, e } => rsa::public_pkcs1(n, e),
+        }
+    }
+
+    pub fn to_pem(&self) -> String {
+        match &self.inner {
+            Public::Rsa { .. } => pem::encode("RSA PUBLIC KEY", &self.to_der()),
+            _ => pem::encode("PUBLIC KEY", &self.to_der()),
+        }
+    }
+
+    /// The uncompressed point, for an ECDSA P-256 key.
+    pub fn ecdsa_point(&self) -> Option<&[u8; 65]> {
+        match &self.inner {
+            Public::EcdsaP256(point) => Some(point),
+            _ => None,
+        }
+    }
+
+    /// A description of the key type, for display.
+    pub fn description(&self) -> String {
+        match &self.inner {
+            Public::EcdsaP256(_) => "ECDSA P-256".to_string(),
+            Public::Ed25519(_) => "Ed25519".to_string(),
+            Public::Rsa { n, .. } => format!("RSA-{}", n.len() * 8),
+        }
+    }
+
+    /// The hash of the DER key, as carried in the KEYHASH TLV.
+    pub fn key_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.to_der()).into()
+    }
+
+    /// Rust source defining the key and its hash as statics, `NAME` and
+    /// `NAME_HASH`, for a board to include in its build.
+    pub fn to_rust(&self, name: &str) -> String {
+        let mut out = format!("// {} public key, as given by `getpub`.\n", self.description());
+        out.push_str(&rust_array(name, &self.to_der()));
+        out.push_str("\n// The SHA256 of the key, as carried in the KEYHASH TLV.\n");
+        out.push_str(&rust_array(&format!("{}_HASH", name), &self.key_hash()));
+        out
+    }
+}
+
+/// A `pub static` byte array, twelve bytes to the line.
+fn rust_array(name: &str, data: &[u8]) -> String {
+    let mut out = format!("pub static {}: [u8; {}] = [\n", name, data.len());
+    for line in data.chunks(12) {
+        out.push_str("   ");
+        for byte in line {
+            write!(out, " 0x{:02x},", byte).unwrap();
+        }
+        out.push('\n');
+    }
+    out.push_str("];\n");
+    out
+}
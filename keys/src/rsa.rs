@@ -0,0 +1,188 @@
+//! RSA keys
+//!
+//! Generation picks two random primes of half the size, each checked by trial
+//! division and then Miller-Rabin, with the usual public exponent of 65537.
+//! Keys are kept in the PKCS#1 form, and the public key is the PKCS#1
+//! RSAPublicKey, which is what imgtool puts in an image and hashes for the
+//! KEYHASH (unlike the other key types, which use a SubjectPublicKeyInfo).
+
+use anyhow::{bail, Result};
+use rand::RngCore;
+
+use crate::bigint::Big;
+use crate::der::{self, Der, TAG_SEQUENCE};
+
+const E: u64 = 65537;
+
+/// Miller-Rabin rounds, enough for an error below 2^-100 at these sizes.
+const ROUNDS: usize = 5;
+
+#[derive(Clone, Debug)]
+pub struct RsaPrivate {
+    pub n: Big,
+    pub e: Big,
+    d: Big,
+    p: Big,
+    q: Big,
+    dp: Big,
+    dq: Big,
+    qinv: Big,
+}
+
+impl RsaPrivate {
+    pub fn generate<R: RngCore>(bits: usize, rng: &mut R) -> RsaPrivate {
+        loop {
+            let p = prime(bits / 2, rng);
+            let q = prime(bits - bits / 2, rng);
+            if p == q {
+                continue;
+            }
+            let n = p.mul(&q);
+            if n.bits() != bits {
+                continue;
+            }
+            return RsaPrivate::from_primes(p, q);
+        }
+    }
+
+    fn from_primes(p: Big, q: Big) -> RsaPrivate {
+        let one = Big::from_u64(1);
+        let (p1, q1) = (p.sub(&one), q.sub(&one));
+        let phi = p1.mul(&q1);
+
+        // d = e^-1 mod phi.  As e is a small prime, d = (1 + k * phi) / e,
+        // for the k in [0, e) that makes that divisible.
+        let (_, phi_mod_e) = phi.div_small(E);
+        let k = (E - pow_mod(phi_mod_e, E - 2, E)) % E;
+        let (d, rem) = phi.mul(&Big::from_u64(k)).add(&one).div_small(E);
+        debug_assert_eq!(rem, 0);
+
+        // q^-1 mod p, by Fermat, as p is prime.
+        let qinv = q.modpow(&p.sub(&Big::from_u64(2)), &p);
+        RsaPrivate {
+            n: p.mul(&q),
+            e: Big::from_u64(E),
+            dp: d.rem(&p1),
+            dq: d.rem(&q1),
+            d,
+            p,
+            q,
+            qinv,
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        self.n.bits()
+    }
+
+    /// Read a PKCS#1 RSAPrivateKey.
+    pub fn from_pkcs1(data: &[u8]) -> Result<RsaPrivate> {
+        let mut der = Der::new(data).expect(TAG_SEQUENCE)?;
+        if der.integer()? != [0] {
+            bail!("Only two prime RSA keys are supported");
+        }
+        let mut next = || der.integer().map(Big::from_be_bytes);
+        Ok(RsaPrivate {
+            n: next()?,
+            e: next()?,
+            d: next()?,
+            p: next()?,
+            q: next()?,
+            dp: next()?,
+            dq: next()?,
+            qinv: next()?,
+        })
+    }
+
+    pub fn to_pkcs1(&self) -> Vec<u8> {
+        let values = [&self.n, &self.e, &self.d, &self.p, &self.q, &self.dp, &self.dq, &self.qinv];
+        let mut items = vec![der::integer(&[0])];
+        items.extend(values.iter().map(|v| der::integer(&v.to_be_bytes())));
+        let items: Vec<&[u8]> = items.iter().map(Vec::as_slice).collect();
+        der::sequence(&items)
+    }
+
+    /// Check that the key works: that encrypting and decrypting with it
+    /// gets back where it started.
+    pub fn check(&self) -> bool {
+        let m = Big::from_u64(0x1234_5678_9abc_def0);
+        self.n.is_odd() && m.modpow(&self.e, &self.n).modpow(&self.d, &self.n) == m
+    }
+}
+
+/// Encode a PKCS#1 RSAPublicKey.
+pub fn public_pkcs1(n: &[u8], e: &[u8]) -> Vec<u8> {
+    der::sequence(&[&der::integer(n), &der::integer(e)])
+}
+
+/// A random prime of exactly `bits` bits, with the top two bits set (so the
+/// product of two is the full size), and with p - 1 coprime to e.
+fn prime<R: RngCore>(bits: usize, rng: &mut R) -> Big {
+    let mut bytes = vec![0u8; bits.div_ceil(8)];
+    loop {
+        rng.fill_bytes(&mut bytes);
+        let extra = bytes.len() * 8 - bits;
+        bytes[0] &= 0xff >> extra;
+        bytes[0] |= 0xc0 >> extra;
+        if extra == 7 {
+            bytes[1] |= 0x80;
+        }
+        *bytes.last_mut().unwrap() |= 1;
+        let candidate = Big::from_be_bytes(&bytes);
+        if candidate.div_small(E).1 == 1 {
+            continue;
+        }
+        if is_probable_prime(&candidate, rng) {
+            return candidate;
+        }
+    }
+}
+
+fn is_probable_prime<R: RngCore>(n: &Big, rng: &mut R) -> bool {
+    // Weed out most candidates cheaply.
+    for p in (3u64..2000).step_by(2) {
+        if (2..p).take_while(|d| d * d <= p).all(|d| p % d != 0) && n.div_small(p).1 == 0 {
+            return false;
+        }
+    }
+
+    let one = Big::from_u64(1);
+    let n1 = n.sub(&one);
+    let mut s = 0;
+    let mut d = n1.clone();
+    while !d.is_odd() {
+        d = d.div_small(2).0;
+        s += 1;
+    }
+    let mut bytes = vec![0u8; n.bits().div_ceil(8)];
+    'rounds: for _ in 0..ROUNDS {
+        // A base in [2, n - 2].
+        rng.fill_bytes(&mut bytes);
+        let a = Big::from_be_bytes(&bytes).rem(&n.sub(&Big::from_u64(3))).add(&Big::from_u64(2));
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.mul(&x).rem(n);
+            if x == n1 {
+                continue 'rounds;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut acc = 1u64;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            acc = acc * base % m;
+        }
+        base = base * base % m;
+        exp >>= 1;
+    }
+    acc
+}
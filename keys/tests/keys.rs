@@ -0,0 +1,81 @@
+// Key testing.
+
+use keys::{KeyType, PrivateKey, PublicKey};
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256Plus;
+
+static ECDSA: &str = include_str!("../../boot/data/ecdsa-p256.pem");
+static ECDSA_PUB: &[u8] = include_bytes!("../../boot/data/ecdsa-p256-pub.der");
+static ED25519: &str = include_str!("../data/ed25519.pem");
+static ED25519_PUB: &[u8] = include_bytes!("../data/ed25519-pub.der");
+static RSA: &str = include_str!("../data/rsa-2048.pem");
+static RSA_PUB: &[u8] = include_bytes!("../data/rsa-2048-pub.der");
+static RSA_SPKI: &[u8] = include_bytes!("../data/rsa-2048-spki.pem");
+
+fn unhex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn openssl_keys() {
+    // Each key reads, gives the same public key as openssl, and writes back
+    // out the same.
+    for (pem, public, kind) in [
+        (ECDSA, ECDSA_PUB, KeyType::EcdsaP256),
+        (ED25519, ED25519_PUB, KeyType::Ed25519),
+        (RSA, RSA_PUB, KeyType::Rsa2048),
+    ] {
+        let key = PrivateKey::from_pem(pem).unwrap();
+        assert_eq!(key.key_type(), kind);
+        assert_eq!(key.public().to_der(), public);
+        assert_eq!(key.to_pem(), pem);
+        assert_eq!(PublicKey::from_bytes(public).unwrap(), key.public());
+        let pem = key.public().to_pem();
+        assert_eq!(PublicKey::from_bytes(pem.as_bytes()).unwrap(), key.public());
+    }
+
+    // RSA public keys may also come as a SubjectPublicKeyInfo.
+    let key = PublicKey::from_bytes(RSA_SPKI).unwrap();
+    assert_eq!(key.to_der(), RSA_PUB);
+    assert_eq!(key.description(), "RSA-2048");
+}
+
+#[test]
+fn ed25519_rfc8032() {
+    // Test 1 from RFC 8032, section 7.1.
+    let seed = unhex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+    let key = PrivateKey::from_ed25519_seed(seed.try_into().unwrap());
+    let public = key.public().to_der();
+    assert_eq!(public[12..], unhex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"));
+}
+
+#[test]
+fn generate() {
+    let mut rng = Xoshiro256Plus::seed_from_u64(1);
+    for kind in [KeyType::EcdsaP256, KeyType::Ed25519, KeyType::Rsa2048] {
+        let key = PrivateKey::generate(kind, &mut rng);
+        assert_eq!(key.key_type(), kind);
+        assert_eq!(KeyType::from_name(kind.name()).unwrap(), kind);
+
+        // Reading it back checks the RSA values are consistent.
+        let again = PrivateKey::from_pem(&key.to_pem()).unwrap();
+        assert_eq!(again.public(), key.public());
+        assert_eq!(again.to_pkcs8(), key.to_pkcs8());
+    }
+
+    let other = PrivateKey::generate(KeyType::EcdsaP256, &mut rng);
+    assert_ne!(other.ecdsa_scalar(), PrivateKey::generate(KeyType::EcdsaP256, &mut rng).ecdsa_scalar());
+    assert!(KeyType::from_name("rsa-1024").is_err());
+}
+
+#[test]
+fn rust_output() {
+    let key = PublicKey::from_bytes(ECDSA_PUB).unwrap();
+    let text = key.to_rust("SIGNING_KEY");
+    assert!(text.starts_with("// ECDSA P-256 public key, as given by `getpub`.\n\
+                              pub static SIGNING_KEY: [u8; 91] = [\n    \
+                              0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,\n"));
+    assert!(text.contains("pub static SIGNING_KEY_HASH: [u8; 32] = [\n"));
+    assert!(text.ends_with("];\n"));
+    assert_eq!(text.lines().filter(|line| line.starts_with("    0x")).count(), 8 + 3);
+}
@@ -0,0 +1,310 @@
+//! Flash dump analysis
+//!
+//! Given a raw dump of a device's flash, and a description of where the slots
+//! are, decode what each slot holds (the image header, and the trailer fields)
+//! and place each pair of slots in the state table documented in the boot
+//! crate's `status` module.  This is meant for devices that come back from the
+//! field not booting, or stuck part way through an upgrade.
+//!
+//! The geometry is a small text file, one setting per line, with `#` starting
+//! a comment:
+//!
+//! ```text
+//! # The dump was read starting at the beginning of internal flash.
+//! base 0x08000000
+//! write-size 16
+//! erase-size 0x20000
+//! # Slots are given as name, address and size, primary then secondary.
+//! slot primary 0x08020000 0x80000
+//! slot secondary 0x08120000 0x80000
+//! ```
+//!
+//! The slots are taken in pairs, one pair for each image.  The write size
+//! matters, as it decides where the trailer fields are.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use storage::Flash;
+use boot::{
+    copy_done, find_remnant, image_ok, last_boot_error, swap_type, BootError, Flag, Image, SlotInfo, SwapType,
+    TRAILER_MAGIC,
+};
+
+use crate::flash::MemFlash;
+use crate::image::{ImageInfo, TLV_SHA256};
+
+/// Where a slot is, relative to the start of the dump.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Slot {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Geometry {
+    pub write_size: usize,
+    pub erase_size: usize,
+    pub slots: Vec<Slot>,
+}
+
+impl Geometry {
+    pub fn parse(text: &str) -> Result<Geometry> {
+        let mut base = 0;
+        let mut write_size = None;
+        let mut erase_size = None;
+        let mut slots = vec![];
+        for (num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let bad = || anyhow!("Line {}: can't understand {:?}", num + 1, line);
+            match words[..] {
+                [] => (),
+                ["base", addr] => base = parse_number(addr).ok_or_else(bad)?,
+                ["write-size", size] => write_size = Some(parse_number(size).ok_or_else(bad)?),
+                ["erase-size", size] => erase_size = Some(parse_number(size).ok_or_else(bad)?),
+                ["slot", name, addr, size] => {
+                    let addr = parse_number(addr).ok_or_else(bad)?;
+                    let size = parse_number(size).ok_or_else(bad)?;
+                    slots.push((name.to_string(), addr, size));
+                }
+                _ => return Err(bad()),
+            }
+        }
+        let write_size = write_size.ok_or_else(|| anyhow!("No write-size given"))?;
+        let erase_size = erase_size.ok_or_else(|| anyhow!("No erase-size given"))?;
+        if write_size == 0 || erase_size == 0 {
+            bail!("The write and erase sizes must not be zero");
+        }
+        if slots.is_empty() || slots.len() % 2 != 0 {
+            bail!("Slots come in pairs, primary then secondary, and there are {}", slots.len());
+        }
+        let slots = slots
+            .into_iter()
+            .map(|(name, addr, size)| {
+                let offset = addr.checked_sub(base)
+                    .ok_or_else(|| anyhow!("Slot {} is below the base address", name))?;
+                // Room for the magic, the two flags, and the boot error.
+                if size < 4 * write_size.max(16) {
+                    bail!("Slot {} is too small to hold a trailer", name);
+                }
+                Ok(Slot { name, offset, size })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Geometry { write_size, erase_size, slots })
+    }
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// The state of the magic at the end of a slot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Magic {
+    Good,
+    Erased,
+    /// Neither the magic nor erased, such as from an interrupted write.
+    Bad,
+}
+
+/// What is in one slot.
+#[derive(Debug)]
+pub struct SlotReport {
+    pub slot: Slot,
+    /// A summary of the image, or why there isn't one.
+    pub image: String,
+    pub magic: Magic,
+    pub image_ok: Flag,
+    pub copy_done: Flag,
+    /// The recorded boot error, or the reason it couldn't be decoded.
+    pub boot_error: Result<Option<BootError>, String>,
+    /// Where data is left between the image and the trailer, such as the
+    /// tail of a larger previous image.
+    pub remnant: Option<usize>,
+}
+
+impl SlotReport {
+    /// The slot's status, in the notation of the state table.
+    pub fn status(&self) -> String {
+        if self.magic == Magic::Bad || self.image_ok == Flag::Bad || self.copy_done == Flag::Bad {
+            return "damaged".to_string();
+        }
+        let mut parts = vec![];
+        if self.magic == Magic::Good {
+            parts.push("magic");
+        }
+        if self.copy_done == Flag::Set {
+            parts.push("cd");
+        }
+        if self.image_ok == Flag::Set {
+            parts.push("ok");
+        }
+        if parts.is_empty() {
+            "blank".to_string()
+        } else {
+            parts.join("+")
+        }
+    }
+}
+
+/// One image's pair of slots.
+#[derive(Debug)]
+pub struct PairReport {
+    pub primary: SlotReport,
+    pub secondary: SlotReport,
+    /// The row of the state table these slots are in.
+    pub state: &'static str,
+    /// What the bootloader will do on the next boot.
+    pub swap_type: Result<SwapType, String>,
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    pub images: Vec<PairReport>,
+}
+
+pub fn analyze(dump: &[u8], geometry: &Geometry) -> Result<Analysis> {
+    let mut images = vec![];
+    for pair in geometry.slots.chunks(2) {
+        let primary_data = slot_data(dump, &pair[0])?;
+        let secondary_data = slot_data(dump, &pair[1])?;
+        let mut primary = MemFlash::with_geometry(primary_data, geometry.write_size, geometry.erase_size);
+        let mut secondary = MemFlash::with_geometry(secondary_data, geometry.write_size, geometry.erase_size);
+        let primary_report = slot_report(&pair[0], primary_data, &mut primary);
+        let secondary_report = slot_report(&pair[1], secondary_data, &mut secondary);
+        images.push(PairReport {
+            state: state(&primary_report, &secondary_report),
+            swap_type: swap_type(&mut primary, &mut secondary).map_err(|e| format!("{:?}", e)),
+            primary: primary_report,
+            secondary: secondary_report,
+        });
+    }
+    Ok(Analysis { images })
+}
+
+fn slot_data<'a>(dump: &'a [u8], slot: &Slot) -> Result<&'a [u8]> {
+    dump.get(slot.offset..slot.offset + slot.size)
+        .ok_or_else(|| anyhow!("Slot {} is past the end of the dump", slot.name))
+}
+
+fn slot_report(slot: &Slot, data: &[u8], flash: &mut MemFlash) -> SlotReport {
+    let image = if data.iter().take(32).all(|&b| b == 0xff) {
+        "erased".to_string()
+    } else {
+        match ImageInfo::parse(data) {
+            Ok(info) => {
+                // Only the hash is checked, the dump says nothing about which
+                // keys the device trusts.
+                let h = &info.header;
+                let end = h.hdr_size as usize + h.img_size as usize + h.protected_tlv_size as usize;
+                let valid = match info.find(TLV_SHA256) {
+                    Some(tlv) if tlv.data[..] == Sha256::digest(&data[..end])[..] => "hash valid",
+                    Some(_) => "hash mismatch",
+                    None => "no hash",
+                };
+                format!("{}, {} bytes, {}", info.header.version, info.size, valid)
+            }
+            Err(e) => e.to_string(),
+        }
+    };
+
+    let magic = &data[data.len() - TRAILER_MAGIC.len()..];
+    let magic = if magic == TRAILER_MAGIC {
+        Magic::Good
+    } else if magic.iter().all(|&b| b == 0xff) {
+        Magic::Erased
+    } else {
+        Magic::Bad
+    };
+
+    // The status is laid out as if for the same image in both slots, which
+    // is enough to say where its sectors start.
+    let remnant = RefCell::new(MemFlash::with_geometry(data, flash.write_size(), flash.erase_size()));
+    let size = Image::from_flash(&remnant).map_or(0, |image| image.full_image_size());
+    let info = SlotInfo::from_data(size, &*remnant.borrow());
+    let remnant = match info.status_layout(&info) {
+        Ok(layout) => find_remnant(&remnant, &layout).unwrap_or(None),
+        Err(_) => None,
+    };
+
+    SlotReport {
+        slot: slot.clone(),
+        image,
+        magic,
+        image_ok: image_ok(flash).unwrap_or(Flag::Bad),
+        copy_done: copy_done(flash).unwrap_or(Flag::Bad),
+        boot_error: last_boot_error(flash).map_err(|_| "unrecognized record".to_string()),
+        remnant,
+    }
+}
+
+/// Place a pair of slots in the state table.  This tree doesn't record the
+/// swap metadata or move done, so the swap's internal steps can't be told
+/// apart, only that one was started and not finished.
+fn state(primary: &SlotReport, secondary: &SlotReport) -> &'static str {
+    let damaged = |report: &SlotReport| report.status() == "damaged";
+    if damaged(primary) || damaged(secondary) {
+        return "Damaged trailer";
+    }
+    let set = |flag| flag == Flag::Set;
+    if secondary.magic == Magic::Good {
+        if set(secondary.image_ok) {
+            "Request (permanent)"
+        } else {
+            "Request (test)"
+        }
+    } else if primary.magic == Magic::Good {
+        match (set(primary.copy_done), set(primary.image_ok)) {
+            (true, true) => "Image ok - no further changes",
+            (true, false) => "Copy Done - the image is on test, and reverts unless confirmed",
+            _ => "Started - a swap was interrupted",
+        }
+    } else {
+        "None"
+    }
+}
+
+impl fmt::Display for SlotReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let slot = &self.slot;
+        writeln!(f, "slot {}: dump offset 0x{:x}, 0x{:x} bytes", slot.name, slot.offset, slot.size)?;
+        writeln!(f, "    image:      {}", self.image)?;
+        writeln!(f, "    magic:      {:?}", self.magic)?;
+        writeln!(f, "    image ok:   {:?}", self.image_ok)?;
+        writeln!(f, "    copy done:  {:?}", self.copy_done)?;
+        match &self.boot_error {
+            Ok(None) => writeln!(f, "    boot error: none"),
+            Ok(Some(err)) => writeln!(f, "    boot error: {:?} (0x{:04x}) at slot offset 0x{:x}",
+                                         err.code, err.code as u16, err.offset),
+            Err(e) => writeln!(f, "    boot error: {}", e),
+        }?;
+        if let Some(offset) = self.remnant {
+            writeln!(f, "    remnant:    data after the image at slot offset 0x{:x}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (num, image) in self.images.iter().enumerate() {
+            write!(f, "{}", image.primary)?;
+            write!(f, "{}", image.secondary)?;
+            writeln!(f, "image {}:", num)?;
+            writeln!(f, "    {:<20} {:<20} state", image.primary.slot.name, image.secondary.slot.name)?;
+            writeln!(f, "    {:<20} {:<20} {}", image.primary.status(), image.secondary.status(), image.state)?;
+            match &image.swap_type {
+                Ok(kind) => writeln!(f, "    next boot: swap type {:?}", kind)?,
+                Err(e) => writeln!(f, "    next boot: unknown, {}", e)?,
+            }
+        }
+        Ok(())
+    }
+}
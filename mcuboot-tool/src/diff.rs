@@ -0,0 +1,62 @@
+//! Comparing images
+//!
+//! Describes how two images differ: the header fields, the TLVs (matched up by
+//! kind), and the payload.  Identical images give no lines.
+
+use anyhow::Result;
+
+use crate::image::{tlv_name, ImageInfo, Tlv};
+
+pub fn diff(a: &[u8], b: &[u8]) -> Result<Vec<String>> {
+    let ia = ImageInfo::parse(a)?;
+    let ib = ImageInfo::parse(b)?;
+    let mut out = vec![];
+
+    let (ha, hb) = (&ia.header, &ib.header);
+    let mut field = |name: &str, a: String, b: String| {
+        if a != b {
+            out.push(format!("header {}: {} -> {}", name, a, b));
+        }
+    };
+    field("version", ha.version.to_string(), hb.version.to_string());
+    field("load_addr", format!("0x{:x}", ha.load_addr), format!("0x{:x}", hb.load_addr));
+    field("hdr_size", ha.hdr_size.to_string(), hb.hdr_size.to_string());
+    field("protected_tlv_size", ha.protected_tlv_size.to_string(), hb.protected_tlv_size.to_string());
+    field("img_size", ha.img_size.to_string(), hb.img_size.to_string());
+    field("flags", format!("0x{:x}", ha.flags), format!("0x{:x}", hb.flags));
+
+    diff_tlvs(&mut out, "protected TLV", &ia.protected, &ib.protected);
+    diff_tlvs(&mut out, "TLV", &ia.tlvs, &ib.tlvs);
+
+    // The payloads are compared from the end of each header.
+    let pa = &a[ha.hdr_size as usize..][..ha.img_size as usize];
+    let pb = &b[hb.hdr_size as usize..][..hb.img_size as usize];
+    let common = pa.len().min(pb.len());
+    let differ = pa.iter().zip(pb).filter(|(x, y)| x != y).count();
+    if differ > 0 {
+        let first = pa.iter().zip(pb).position(|(x, y)| x != y).unwrap();
+        out.push(format!("payload: {} of {} bytes differ, the first at payload offset 0x{:x}",
+                         differ, common, first));
+    }
+    if pa.len() != pb.len() {
+        out.push(format!("payload: length {} -> {}", pa.len(), pb.len()));
+    }
+    Ok(out)
+}
+
+fn diff_tlvs(out: &mut Vec<String>, what: &str, a: &[Tlv], b: &[Tlv]) {
+    for ta in a {
+        match b.iter().find(|tb| tb.kind == ta.kind) {
+            None => out.push(format!("{} 0x{:02x} {}: removed", what, ta.kind, tlv_name(ta.kind))),
+            Some(tb) if tb.data != ta.data => {
+                out.push(format!("{} 0x{:02x} {}: changed", what, ta.kind, tlv_name(ta.kind)))
+            }
+            Some(_) => (),
+        }
+    }
+    for tb in b {
+        if !a.iter().any(|ta| ta.kind == tb.kind) {
+            out.push(format!("{} 0x{:02x} {}: added", what, tb.kind, tlv_name(tb.kind)));
+        }
+    }
+}
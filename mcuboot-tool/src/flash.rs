@@ -0,0 +1,121 @@
+//! Files as flash
+//!
+//! The boot crate reads everything through the storage traits.  This presents
+//! part of a file (an image, or a slot of a flash dump) as a flash device, so
+//! the tool can use the same code as the bootloader.  Nothing is ever written
+//! back: writes and erases fail.
+
+use storage::{Error, Flash, ReadFlash, Result};
+
+pub struct MemFlash<'a> {
+    data: &'a [u8],
+    write_size: usize,
+    erase_size: usize,
+}
+
+impl<'a> MemFlash<'a> {
+    /// A flash holding `data`, where the write and erase sizes don't matter.
+    pub fn new(data: &'a [u8]) -> MemFlash<'a> {
+        MemFlash::with_geometry(data, 1, 1)
+    }
+
+    /// A flash holding `data`, with the given geometry.  The write size
+    /// decides where the trailer fields are.
+    pub fn with_geometry(data: &'a [u8], write_size: usize, erase_size: usize) -> MemFlash<'a> {
+        MemFlash { data, write_size, erase_size }
+    }
+}
+
+impl<'a> ReadFlash for MemFlash<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        let data = self.data.get(offset..offset + bytes.len()).ok_or(Error::OutOfBounds)?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<'a> Flash for MemFlash<'a> {
+    fn write_size(&self) -> usize {
+        self.write_size
+    }
+
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
+
+    fn erase(&mut self, _from: usize, _to: usize) -> Result<()> {
+        Err(Error::Failed)
+    }
+
+    fn write(&mut self, _offset: usize, _bytes: &[u8]) -> Result<()> {
+        Err(Error::Failed)
+    }
+}
+
+/// A writable flash over a buffer.  As with real flash, writes must be to
+/// erased (0xff) bytes, and erasing sets them back.
+pub struct BufFlash<'a> {
+    data: &'a mut [u8],
+    write_size: usize,
+    erase_size: usize,
+}
+
+impl<'a> BufFlash<'a> {
+    pub fn with_geometry(data: &'a mut [u8], write_size: usize, erase_size: usize) -> BufFlash<'a> {
+        BufFlash { data, write_size, erase_size }
+    }
+}
+
+impl<'a> ReadFlash for BufFlash<'a> {
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        let data = self.data.get(offset..offset + bytes.len()).ok_or(Error::OutOfBounds)?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<'a> Flash for BufFlash<'a> {
+    fn write_size(&self) -> usize {
+        self.write_size
+    }
+
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        if !from.is_multiple_of(self.erase_size) || !to.is_multiple_of(self.erase_size) {
+            return Err(Error::NotAligned);
+        }
+        self.data.get_mut(from..to).ok_or(Error::OutOfBounds)?.fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        if !offset.is_multiple_of(self.write_size) || !bytes.len().is_multiple_of(self.write_size) {
+            return Err(Error::NotAligned);
+        }
+        let data = self.data.get_mut(offset..offset + bytes.len()).ok_or(Error::OutOfBounds)?;
+        if data.iter().any(|&b| b != 0xff) {
+            return Err(Error::NotErased);
+        }
+        data.copy_from_slice(bytes);
+        Ok(())
+    }
+}
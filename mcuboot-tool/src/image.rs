@@ -0,0 +1,213 @@
+//! Image layout
+//!
+//! An image is a header, the payload, an optional protected TLV block (covered
+//! by the hash), and the TLV block.  This parses all of it into plain values,
+//! without checking anything beyond what is needed to find the pieces.
+
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+use boot::ImageVersion;
+
+pub const IMAGE_MAGIC: u32 = 0x96f3b83d;
+pub const TLV_INFO_MAGIC: u16 = 0x6907;
+pub const TLV_PROT_INFO_MAGIC: u16 = 0x6908;
+
+/// Size of the header fields.  The header area given by `hdr_size` is at
+/// least this, padded with zeros.
+pub const HEADER_LEN: usize = 32;
+
+pub const TLV_KEYHASH: u16 = 0x01;
+pub const TLV_PUBKEY: u16 = 0x02;
+pub const TLV_SHA256: u16 = 0x10;
+pub const TLV_ECDSA_SIG: u16 = 0x22;
+pub const TLV_DEPENDENCY: u16 = 0x40;
+pub const TLV_SEC_CNT: u16 = 0x50;
+pub const TLV_MANIFEST: u16 = 0xa0;
+
+/// The name of a TLV kind, for display.
+pub fn tlv_name(kind: u16) -> &'static str {
+    match kind {
+        TLV_KEYHASH => "KEYHASH",
+        TLV_PUBKEY => "PUBKEY",
+        TLV_SHA256 => "SHA256",
+        TLV_ECDSA_SIG => "ECDSA_SIG",
+        TLV_DEPENDENCY => "DEPENDENCY",
+        TLV_SEC_CNT => "SEC_CNT",
+        TLV_MANIFEST => "MANIFEST",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub load_addr: u32,
+    pub hdr_size: u16,
+    pub protected_tlv_size: u16,
+    pub img_size: u32,
+    pub flags: u32,
+    pub version: ImageVersion,
+}
+
+impl Header {
+    pub fn from_bytes(data: &[u8]) -> Result<Header> {
+        if data.len() < HEADER_LEN {
+            bail!("Image is shorter than a header");
+        }
+        if u32le(data, 0) != IMAGE_MAGIC {
+            bail!("Bad image magic: 0x{:08x}", u32le(data, 0));
+        }
+        Ok(Header {
+            load_addr: u32le(data, 4),
+            hdr_size: u16le(data, 8),
+            protected_tlv_size: u16le(data, 10),
+            img_size: u32le(data, 12),
+            flags: u32le(data, 16),
+            version: ImageVersion {
+                major: data[20],
+                minor: data[21],
+                revision: u16le(data, 22),
+                build_num: u32le(data, 24),
+            },
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut data = [0u8; HEADER_LEN];
+        data[0..4].copy_from_slice(&IMAGE_MAGIC.to_le_bytes());
+        data[4..8].copy_from_slice(&self.load_addr.to_le_bytes());
+        data[8..10].copy_from_slice(&self.hdr_size.to_le_bytes());
+        data[10..12].copy_from_slice(&self.protected_tlv_size.to_le_bytes());
+        data[12..16].copy_from_slice(&self.img_size.to_le_bytes());
+        data[16..20].copy_from_slice(&self.flags.to_le_bytes());
+        data[20] = self.version.major;
+        data[21] = self.version.minor;
+        data[22..24].copy_from_slice(&self.version.revision.to_le_bytes());
+        data[24..28].copy_from_slice(&self.version.build_num.to_le_bytes());
+        data
+    }
+}
+
+/// A single TLV entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tlv {
+    pub kind: u16,
+    /// Offset of the entry (not its data) in the image.
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02x} {:<10} {:3} bytes at 0x{:x}",
+               self.kind, tlv_name(self.kind), self.data.len(), self.offset)
+    }
+}
+
+/// A parsed image.
+#[derive(Clone, Debug)]
+pub struct ImageInfo {
+    pub header: Header,
+    pub protected: Vec<Tlv>,
+    pub tlvs: Vec<Tlv>,
+    /// Size of the whole image, including the TLV blocks.
+    pub size: usize,
+}
+
+impl ImageInfo {
+    pub fn parse(data: &[u8]) -> Result<ImageInfo> {
+        let header = Header::from_bytes(data)?;
+        let payload_end = header.hdr_size as usize + header.img_size as usize;
+
+        let mut pos = payload_end;
+        let protected = if header.protected_tlv_size > 0 {
+            let (tlvs, len) = parse_block(data, pos, TLV_PROT_INFO_MAGIC)?;
+            if len != header.protected_tlv_size as usize {
+                bail!("Protected TLV size {} doesn't match the header's {}",
+                      len, header.protected_tlv_size);
+            }
+            pos += len;
+            tlvs
+        } else {
+            vec![]
+        };
+        let (tlvs, len) = parse_block(data, pos, TLV_INFO_MAGIC)?;
+
+        Ok(ImageInfo { header, protected, tlvs, size: pos + len })
+    }
+
+    /// The first TLV of a kind, protected or not.
+    pub fn find(&self, kind: u16) -> Option<&Tlv> {
+        self.protected.iter().chain(&self.tlvs).find(|tlv| tlv.kind == kind)
+    }
+}
+
+/// Parse a TLV block at `pos`, returning the entries, and the block's length.
+fn parse_block(data: &[u8], pos: usize, magic: u16) -> Result<(Vec<Tlv>, usize)> {
+    if pos + 4 > data.len() {
+        bail!("Image is truncated before its TLV at 0x{:x}", pos);
+    }
+    if u16le(data, pos) != magic {
+        bail!("Expected TLV magic 0x{:04x} at 0x{:x}, found 0x{:04x}", magic, pos, u16le(data, pos));
+    }
+    let len = u16le(data, pos + 2) as usize;
+    let end = pos + len;
+    if len < 4 || end > data.len() {
+        bail!("TLV at 0x{:x} runs past the end of the image", pos);
+    }
+
+    let mut tlvs = vec![];
+    let mut entry = pos + 4;
+    while entry < end {
+        if entry + 4 > end {
+            bail!("Truncated TLV entry at 0x{:x}", entry);
+        }
+        let kind = u16le(data, entry);
+        let data_len = u16le(data, entry + 2) as usize;
+        let data = data.get(entry + 4..entry + 4 + data_len)
+            .filter(|_| entry + 4 + data_len <= end)
+            .ok_or_else(|| anyhow!("TLV entry at 0x{:x} runs past its block", entry))?;
+        tlvs.push(Tlv { kind, offset: entry, data: data.to_vec() });
+        entry += 4 + data_len;
+    }
+    Ok((tlvs, len))
+}
+
+fn u16le(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32le(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Parse a version as imgtool takes it: "major.minor.revision+build", where
+/// everything after the major number may be left off.
+pub fn parse_version(text: &str) -> Result<ImageVersion> {
+    let bad = || anyhow!("Invalid version {:?}", text);
+    let (numbers, build) = match text.split_once('+') {
+        Some((numbers, build)) => (numbers, build.parse().map_err(|_| bad())?),
+        None => (text, 0),
+    };
+    let mut parts = numbers.split('.');
+    let major = parts.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+    let minor = parts.next().map(|p| p.parse()).transpose().map_err(|_| bad())?.unwrap_or(0);
+    let revision = parts.next().map(|p| p.parse()).transpose().map_err(|_| bad())?.unwrap_or(0);
+    if parts.next().is_some() {
+        return Err(bad());
+    }
+    Ok(ImageVersion { major, minor, revision, build_num: build })
+}
+
+/// Write data as hex, 16 bytes to a line, each line indented.
+pub fn hex_lines(data: &[u8], indent: &str) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(16) {
+        out.push_str(indent);
+        for byte in chunk {
+            out.push_str(&format!("{:02x}", byte));
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,236 @@
+//! mcuboot-tool: inspect, sign, verify and compare images, analyze flash
+//! dumps, build factory flash images, and make keys.
+//!
+//!     cargo run -- dump image.bin
+
+use std::fs;
+use std::process::exit;
+
+use anyhow::{anyhow, bail, Result};
+use rand::rngs::OsRng;
+
+use keys::{KeyType, PrivateKey, PublicKey};
+use mcuboot_tool::analyze::{analyze, Geometry};
+use mcuboot_tool::diff::diff;
+use mcuboot_tool::image::{hex_lines, parse_version, ImageInfo};
+use mcuboot_tool::provision::provision;
+use mcuboot_tool::sign::{flatten_input, KeyFormat, Signer};
+use mcuboot_tool::verify::{verify, Trust};
+
+const USAGE: &str = "\
+usage:
+    mcuboot-tool dump IMAGE
+    mcuboot-tool verify IMAGE [--key PUBKEY | --key-hash HEX]
+    mcuboot-tool sign [--key KEY.pem] [--public-key-format hash|full] [-v VERSION]
+                      [--header-size N] [--pad-header] [--load-addr ADDR]
+                      [--security-counter N] [--manifest CBOR] INPUT OUTPUT
+                      (INPUT is a raw binary, or an ELF file)
+    mcuboot-tool keygen -t ecdsa-p256|ed25519|rsa-2048|rsa-3072 OUTPUT
+    mcuboot-tool getpub --key KEY.pem [-e der|pem|lang-rust] OUTPUT
+    mcuboot-tool diff IMAGE IMAGE
+    mcuboot-tool analyze DUMP GEOMETRY
+    mcuboot-tool provision [--secondary IMAGE] GEOMETRY BOOTLOADER PRIMARY OUTPUT";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            exit(2);
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<i32> {
+    let (command, args) = args.split_first().ok_or_else(|| anyhow!("{}", USAGE))?;
+    let mut args = Args::new(args);
+    match command.as_str() {
+        "dump" => {
+            let [image] = args.files()?;
+            dump(&fs::read(image)?)?;
+        }
+        "verify" => {
+            let key = args.option("--key")?;
+            let key_hash = args.option("--key-hash")?;
+            let [image] = args.files()?;
+            let data = fs::read(image)?;
+            match (key, key_hash) {
+                (Some(key), None) => {
+                    let key = PublicKey::from_bytes(&fs::read(key)?)?;
+                    verify(&data, Trust::Key(&key.to_der()))?;
+                }
+                (None, Some(hash)) => verify(&data, Trust::KeyHash(&parse_hash(&hash)?))?,
+                (None, None) => verify(&data, Trust::None)?,
+                (Some(_), Some(_)) => bail!("Give either --key or --key-hash, not both"),
+            }
+            println!("{}: valid", image);
+        }
+        "sign" => {
+            let key = args.option("--key")?
+                .map(|path| PrivateKey::from_pem(&fs::read_to_string(path)?))
+                .transpose()?;
+            let format = match args.option("--public-key-format")?.as_deref() {
+                None | Some("hash") => KeyFormat::Hash,
+                Some("full") => KeyFormat::Full,
+                Some(other) => bail!("Unknown key format {:?}", other),
+            };
+            let manifest = args.option("--manifest")?.map(fs::read).transpose()?;
+            let mut signer = Signer::default();
+            if let Some(version) = args.option("-v")? {
+                signer.version(parse_version(&version)?);
+            }
+            if let Some(size) = args.option("--header-size")? {
+                signer.header_size(parse_number(&size)? as usize);
+            }
+            if let Some(addr) = args.option("--load-addr")? {
+                signer.load_addr(parse_number(&addr)?);
+            }
+            if let Some(counter) = args.option("--security-counter")? {
+                signer.security_counter(parse_number(&counter)?);
+            }
+            if let Some(manifest) = &manifest {
+                signer.manifest(manifest);
+            }
+            signer.pad_header(args.flag("--pad-header"));
+            if let Some(key) = &key {
+                signer.key(key, format);
+            }
+            let [input, output] = args.files()?;
+            fs::write(output, signer.sign(&flatten_input(fs::read(input)?)?)?)?;
+        }
+        "getpub" => {
+            let key = args.option("--key")?.ok_or_else(|| anyhow!("--key is required"))?;
+            let key = PrivateKey::from_pem(&fs::read_to_string(key)?)?;
+            let encoding = args.option("-e")?;
+            let [output] = args.files()?;
+            let public = key.public();
+            match encoding.as_deref() {
+                None | Some("der") => fs::write(output, public.to_der())?,
+                Some("pem") => fs::write(output, public.to_pem())?,
+                Some("lang-rust") => fs::write(output, public.to_rust("SIGNING_KEY"))?,
+                Some(other) => bail!("Unknown encoding {:?}", other),
+            }
+        }
+        "keygen" => {
+            let kind = args.option("-t")?.ok_or_else(|| anyhow!("-t is required"))?;
+            let kind = KeyType::from_name(&kind)?;
+            let [output] = args.files()?;
+            let key = PrivateKey::generate(kind, &mut OsRng);
+            fs::write(output, key.to_pem())?;
+        }
+        "diff" => {
+            let [a, b] = args.files()?;
+            let lines = diff(&fs::read(a)?, &fs::read(b)?)?;
+            for line in &lines {
+                println!("{}", line);
+            }
+            // Like diff(1), differences give a status of 1.
+            return Ok(if lines.is_empty() { 0 } else { 1 });
+        }
+        "analyze" => {
+            let [dump, geometry] = args.files()?;
+            let geometry = Geometry::parse(&fs::read_to_string(geometry)?)?;
+            print!("{}", analyze(&fs::read(dump)?, &geometry)?);
+        }
+        "provision" => {
+            let secondary = args.option("--secondary")?.map(fs::read).transpose()?;
+            let [geometry, bootloader, primary, output] = args.files()?;
+            let geometry = Geometry::parse(&fs::read_to_string(geometry)?)?;
+            let flash = provision(&geometry, &fs::read(bootloader)?, &fs::read(primary)?, secondary.as_deref())?;
+            fs::write(output, flash)?;
+        }
+        _ => bail!("Unknown command {:?}\n{}", command, USAGE),
+    }
+    Ok(0)
+}
+
+fn dump(data: &[u8]) -> Result<()> {
+    let info = ImageInfo::parse(data)?;
+    let h = &info.header;
+    println!("header:");
+    println!("    version:            {}", h.version);
+    println!("    load_addr:          0x{:x}", h.load_addr);
+    println!("    hdr_size:           {}", h.hdr_size);
+    println!("    protected_tlv_size: {}", h.protected_tlv_size);
+    println!("    img_size:           {}", h.img_size);
+    println!("    flags:              0x{:x}", h.flags);
+    println!("    total size:         {}", info.size);
+    for (name, tlvs) in [("protected TLV", &info.protected), ("TLV", &info.tlvs)] {
+        if tlvs.is_empty() {
+            continue;
+        }
+        println!("{}:", name);
+        for tlv in tlvs {
+            println!("    {}", tlv);
+            print!("{}", hex_lines(&tlv.data, "        "));
+        }
+    }
+    if data.len() > info.size {
+        println!("{} bytes follow the image", data.len() - info.size);
+    }
+    Ok(())
+}
+
+fn parse_number(text: &str) -> Result<u32> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    result.map_err(|_| anyhow!("Invalid number {:?}", text))
+}
+
+fn parse_hash(text: &str) -> Result<[u8; 32]> {
+    let bad = || anyhow!("A key hash is 64 hex digits");
+    if text.len() != 64 {
+        return Err(bad());
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).map_err(|_| bad())?;
+    }
+    Ok(hash)
+}
+
+/// The command line, taken apart as options are asked for.
+struct Args<'a> {
+    args: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    fn new(args: &'a [String]) -> Args<'a> {
+        Args { args: args.iter().map(String::as_str).collect() }
+    }
+
+    /// Take an option with a value.
+    fn option(&mut self, name: &str) -> Result<Option<String>> {
+        match self.args.iter().position(|&arg| arg == name) {
+            Some(pos) if pos + 1 < self.args.len() => {
+                let value = self.args.remove(pos + 1).to_string();
+                self.args.remove(pos);
+                Ok(Some(value))
+            }
+            Some(_) => bail!("{} needs a value", name),
+            None => Ok(None),
+        }
+    }
+
+    /// Take a flag.
+    fn flag(&mut self, name: &str) -> bool {
+        match self.args.iter().position(|&arg| arg == name) {
+            Some(pos) => {
+                self.args.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The remaining arguments, which must be exactly N file names.
+    fn files<const N: usize>(&self) -> Result<[&'a str; N]> {
+        if let Some(arg) = self.args.iter().find(|arg| arg.starts_with('-')) {
+            bail!("Unknown option {}\n{}", arg, USAGE);
+        }
+        self.args.clone().try_into().map_err(|_| anyhow!("{}", USAGE))
+    }
+}
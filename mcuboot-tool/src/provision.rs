@@ -0,0 +1,57 @@
+//! Factory flash images
+//!
+//! Assembles the whole of a new device's flash, for programming in the
+//! factory: the bootloader at the start, the primary image in its slot, an
+//! optional image in the secondary slot, and erased flash (0xff) everywhere
+//! else.  The primary trailer is written as the bootloader leaves it after a
+//! permanent upgrade (the magic, image ok and copy done), so the first boot
+//! just validates and runs the image, rather than starting with an upgrade.
+//!
+//! The slots are described with the same geometry file as `analyze`, and the
+//! output starts at its base address, where the bootloader goes.  The images
+//! go in the first pair of slots; any others are left erased.
+
+use anyhow::{anyhow, bail, Result};
+use boot::{confirm, request_upgrade, set_copy_done};
+
+use crate::analyze::{Geometry, Slot};
+use crate::flash::BufFlash;
+use crate::image::ImageInfo;
+
+pub fn provision(geometry: &Geometry, bootloader: &[u8], primary: &[u8], secondary: Option<&[u8]>) -> Result<Vec<u8>> {
+    let size = geometry.slots.iter().map(|slot| slot.offset + slot.size).max().unwrap_or(0);
+    let first = geometry.slots.iter().map(|slot| slot.offset).min().unwrap_or(0);
+    if bootloader.len() > first {
+        bail!("The bootloader ({} bytes) runs into the first slot, at offset 0x{:x}", bootloader.len(), first);
+    }
+    let mut flash = vec![0xff; size];
+    flash[..bootloader.len()].copy_from_slice(bootloader);
+
+    let (primary_slot, secondary_slot) = (&geometry.slots[0], &geometry.slots[1]);
+    place(&mut flash, geometry, primary_slot, primary)?;
+    if let Some(secondary) = secondary {
+        place(&mut flash, geometry, secondary_slot, secondary)?;
+    }
+
+    let slot = &mut flash[primary_slot.offset..primary_slot.offset + primary_slot.size];
+    let mut slot = BufFlash::with_geometry(slot, geometry.write_size, geometry.erase_size);
+    request_upgrade(&mut slot)
+        .and_then(|()| confirm(&mut slot))
+        .and_then(|()| set_copy_done(&mut slot))
+        .map_err(|e| anyhow!("Unable to write the primary trailer: {:?}", e))?;
+    Ok(flash)
+}
+
+/// Put `image` at the start of `slot`, checking it is an image, and that it
+/// leaves room for the trailer.
+fn place(flash: &mut [u8], geometry: &Geometry, slot: &Slot, image: &[u8]) -> Result<()> {
+    ImageInfo::parse(image).map_err(|e| anyhow!("The {} image: {:#}", slot.name, e))?;
+    // The magic, the two flags, and the boot error, as `Geometry` checks.
+    let trailer = 4 * geometry.write_size.max(16);
+    if image.len() + trailer > slot.size {
+        bail!("The {} image ({} bytes) and its trailer don't fit in the slot ({} bytes)",
+              slot.name, image.len(), slot.size);
+    }
+    flash[slot.offset..slot.offset + image.len()].copy_from_slice(image);
+    Ok(())
+}
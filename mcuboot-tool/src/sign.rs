@@ -0,0 +1,211 @@
+//! Signing
+//!
+//! Turns a raw binary into an image, as `imgtool sign` does: fill in the
+//! header, append the TLV with the hash, and, given a key, the key hash (or the
+//! key itself) and an ECDSA P-256 signature over the hash.  The signature
+//! comes from the boot crate's signer, which derives the nonce from the key and
+//! hash, so signing the same input twice gives the same image.
+//!
+//! The input can also be an ELF file, such as cargo builds, which is
+//! flattened as `objcopy -O binary` would (see `flatten_input`).
+
+use anyhow::{bail, Result};
+use boot::{ecdsa_sign, ImageVersion, SoftMul, MAX_HEADER_SIZE};
+use keys::PrivateKey;
+use sha2::{Digest, Sha256};
+
+use crate::image::{
+    Header, HEADER_LEN, TLV_ECDSA_SIG, TLV_INFO_MAGIC, TLV_KEYHASH, TLV_MANIFEST,
+    TLV_PROT_INFO_MAGIC, TLV_PUBKEY, TLV_SEC_CNT, TLV_SHA256,
+};
+
+/// The binary to sign, from the contents of an input file: the file itself,
+/// or, for an ELF file, its loadable segments, flattened.  The entry point
+/// of an ELF file must be within them.
+pub fn flatten_input(data: Vec<u8>) -> Result<Vec<u8>> {
+    if !elf::is_elf(&data) {
+        return Ok(data);
+    }
+    let flat = elf::flatten(&data)?;
+    if !flat.contains_entry() {
+        bail!("The entry point 0x{:x} is outside the image (0x{:x}..0x{:x})",
+              flat.entry, flat.base, flat.base + flat.data.len() as u64);
+    }
+    Ok(flat.data)
+}
+
+/// How the key is identified in the image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyFormat {
+    /// The hash of the public key, for devices that hold the key.
+    Hash,
+    /// The whole public key, for devices that only hold its hash.
+    Full,
+}
+
+pub struct Signer<'k> {
+    header_size: usize,
+    pad_header: bool,
+    load_addr: u32,
+    version: ImageVersion,
+    security_counter: Option<u32>,
+    manifest: Option<&'k [u8]>,
+    key: Option<&'k PrivateKey>,
+    key_format: KeyFormat,
+}
+
+impl<'k> Default for Signer<'k> {
+    fn default() -> Self {
+        Signer {
+            header_size: 256,
+            pad_header: false,
+            load_addr: 0,
+            version: ImageVersion::default(),
+            security_counter: None,
+            manifest: None,
+            key: None,
+            key_format: KeyFormat::Hash,
+        }
+    }
+}
+
+impl<'k> Signer<'k> {
+    pub fn header_size(&mut self, size: usize) -> &mut Self {
+        self.header_size = size;
+        self
+    }
+
+    /// Prepend the header, rather than expecting the input to start with
+    /// room for it.
+    pub fn pad_header(&mut self, pad: bool) -> &mut Self {
+        self.pad_header = pad;
+        self
+    }
+
+    pub fn load_addr(&mut self, addr: u32) -> &mut Self {
+        self.load_addr = addr;
+        self
+    }
+
+    pub fn version(&mut self, version: ImageVersion) -> &mut Self {
+        self.version = version;
+        self
+    }
+
+    /// Add a protected security counter.
+    pub fn security_counter(&mut self, counter: u32) -> &mut Self {
+        self.security_counter = Some(counter);
+        self
+    }
+
+    /// Add a protected manifest, already CBOR encoded.
+    pub fn manifest(&mut self, manifest: &'k [u8]) -> &mut Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn key(&mut self, key: &'k PrivateKey, format: KeyFormat) -> &mut Self {
+        self.key = Some(key);
+        self.key_format = format;
+        self
+    }
+
+    pub fn sign(&self, input: &[u8]) -> Result<Vec<u8>> {
+        if self.header_size < HEADER_LEN || self.header_size > MAX_HEADER_SIZE {
+            bail!("Header size {} is out of range", self.header_size);
+        }
+        let mut image = if self.pad_header {
+            let mut image = vec![0u8; self.header_size];
+            image.extend(input);
+            image
+        } else {
+            if input.len() < self.header_size || input[..self.header_size].iter().any(|&b| b != 0) {
+                bail!("The first {} bytes of the input must be zero, for the header", self.header_size);
+            }
+            input.to_vec()
+        };
+
+        let mut protected = vec![];
+        if let Some(counter) = self.security_counter {
+            tlv(&mut protected, TLV_SEC_CNT, &counter.to_le_bytes());
+        }
+        if let Some(manifest) = self.manifest {
+            tlv(&mut protected, TLV_MANIFEST, manifest);
+        }
+        let protected = block(TLV_PROT_INFO_MAGIC, &protected, !protected.is_empty())?;
+
+        let header = Header {
+            load_addr: self.load_addr,
+            hdr_size: self.header_size as u16,
+            protected_tlv_size: protected.len() as u16,
+            img_size: (image.len() - self.header_size) as u32,
+            flags: 0,
+            version: self.version,
+        };
+        image[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+        image.extend(&protected);
+
+        let hash: [u8; 32] = Sha256::digest(&image).into();
+        let mut tlvs = vec![];
+        tlv(&mut tlvs, TLV_SHA256, &hash);
+        if let Some(key) = self.key {
+            let public = key.public();
+            match self.key_format {
+                KeyFormat::Hash => tlv(&mut tlvs, TLV_KEYHASH, &public.key_hash()),
+                KeyFormat::Full => tlv(&mut tlvs, TLV_PUBKEY, &public.to_der()),
+            }
+            let scalar = match key.ecdsa_scalar() {
+                Some(scalar) => scalar,
+                None => bail!("Only ECDSA P-256 keys can sign images"),
+            };
+            let (r, s) = match ecdsa_sign(&mut SoftMul, scalar, &hash) {
+                Some(sig) => sig,
+                None => bail!("Unable to sign with this key"),
+            };
+            tlv(&mut tlvs, TLV_ECDSA_SIG, &der_signature(&r, &s));
+        }
+        image.extend(block(TLV_INFO_MAGIC, &tlvs, true)?);
+        Ok(image)
+    }
+}
+
+fn tlv(out: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    out.extend(kind.to_le_bytes());
+    out.extend((data.len() as u16).to_le_bytes());
+    out.extend(data);
+}
+
+/// Wrap entries in a TLV block.  An empty protected block is left out.
+fn block(magic: u16, entries: &[u8], present: bool) -> Result<Vec<u8>> {
+    if !present {
+        return Ok(vec![]);
+    }
+    let len = entries.len() + 4;
+    if len > u16::MAX as usize {
+        bail!("TLV block is too large");
+    }
+    let mut out = vec![];
+    out.extend(magic.to_le_bytes());
+    out.extend((len as u16).to_le_bytes());
+    out.extend(entries);
+    Ok(out)
+}
+
+/// Encode a signature as the DER sequence of two integers.
+fn der_signature(r: &[u8; 32], s: &[u8; 32]) -> Vec<u8> {
+    let mut body = vec![];
+    for value in [r, s] {
+        let start = value.iter().position(|&b| b != 0).unwrap_or(31);
+        let value = &value[start..];
+        let pad = value[0] & 0x80 != 0;
+        body.push(0x02);
+        body.push((value.len() + pad as usize) as u8);
+        if pad {
+            body.push(0);
+        }
+        body.extend(value);
+    }
+    let mut der = vec![0x30, body.len() as u8];
+    der.extend(body);
+    der
+}
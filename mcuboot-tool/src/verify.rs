@@ -0,0 +1,34 @@
+//! Verification
+//!
+//! Images are checked by the boot crate itself, reading from an in-memory
+//! flash, so the result is the one the bootloader would reach.
+
+use std::cell::RefCell;
+
+use anyhow::{anyhow, Result};
+use boot::{Hash256, Image, SoftCrypto};
+
+use crate::flash::MemFlash;
+
+/// What the image is checked against.
+pub enum Trust<'a> {
+    /// Only the hash.
+    None,
+    /// A signature made with this key (a DER SubjectPublicKeyInfo).
+    Key(&'a [u8]),
+    /// A signature made with the key in the image, whose hash this is.
+    KeyHash(&'a Hash256),
+}
+
+/// Validate an image, as the bootloader would.
+pub fn verify(data: &[u8], trust: Trust) -> Result<()> {
+    let flash = RefCell::new(MemFlash::new(data));
+    let image = Image::from_flash(&flash).map_err(|e| anyhow!("No image: {:?}", e))?;
+    let mut crypto = SoftCrypto::new();
+    let result = match trust {
+        Trust::None => image.validate_with(&mut crypto),
+        Trust::Key(key) => image.validate_signed(&mut crypto, key),
+        Trust::KeyHash(hash) => image.validate_key_hash(&mut crypto, hash),
+    };
+    result.map_err(|e| anyhow!("Image is not valid: {:?}", e))
+}
@@ -0,0 +1,142 @@
+// Flash dump analysis testing.
+
+use boot::{ErrorCode, Flag, SwapType, TRAILER_MAGIC};
+use mcuboot_tool::analyze::{analyze, Geometry, Magic};
+
+static SIGNED: &[u8] = include_bytes!("../../boot/data/sample-ecdsa.bin");
+
+static GEOMETRY: &str = "
+# Two slots, after a 64K bootloader.
+base 0x10000000
+write-size 8
+erase-size 0x1000
+slot primary 0x10010000 0x20000
+slot secondary 0x10030000 0x20000  # the upgrade
+";
+
+const PRIMARY: usize = 0x10000;
+const SECONDARY: usize = 0x30000;
+const SLOT: usize = 0x20000;
+
+/// A dump with the same image in both slots, and blank trailers.
+fn dump() -> Vec<u8> {
+    let mut dump = vec![0xff; SECONDARY + SLOT];
+    dump[PRIMARY..PRIMARY + SIGNED.len()].copy_from_slice(SIGNED);
+    dump[SECONDARY..SECONDARY + SIGNED.len()].copy_from_slice(SIGNED);
+    dump
+}
+
+// Trailer fields, with a write size of 8, from the end of the slot.
+fn set_magic(dump: &mut [u8], slot: usize) {
+    dump[slot + SLOT - 16..slot + SLOT].copy_from_slice(&TRAILER_MAGIC);
+}
+
+fn set_image_ok(dump: &mut [u8], slot: usize) {
+    dump[slot + SLOT - 24] = 1;
+}
+
+fn set_copy_done(dump: &mut [u8], slot: usize) {
+    dump[slot + SLOT - 32] = 1;
+}
+
+#[test]
+fn geometry() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+    assert_eq!(geometry.write_size, 8);
+    assert_eq!(geometry.erase_size, 0x1000);
+    assert_eq!(geometry.slots[0].offset, PRIMARY);
+    assert_eq!(geometry.slots[1].name, "secondary");
+    assert_eq!(geometry.slots[1].size, SLOT);
+
+    // The slots must pair up, and sit after the base.
+    assert!(Geometry::parse("write-size 8\nerase-size 8\nslot a 0 0x1000\n").is_err());
+    assert!(Geometry::parse("base 0x100\nwrite-size 8\nerase-size 8\nslot a 0 0x1000\nslot b 0x1000 0x1000\n").is_err());
+    assert!(Geometry::parse("write-size 8\nerase-size 8\nslot a\n").is_err());
+}
+
+#[test]
+fn states() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+
+    let analysis = analyze(&dump(), &geometry).unwrap();
+    let image = &analysis.images[0];
+    assert_eq!(image.primary.image, "0.1.0+0, 77207 bytes, hash valid");
+    assert_eq!(image.primary.magic, Magic::Erased);
+    assert_eq!((image.primary.status(), image.secondary.status()), ("blank".into(), "blank".into()));
+    assert_eq!(image.state, "None");
+    assert_eq!(image.swap_type, Ok(SwapType::None));
+
+    let mut data = dump();
+    set_magic(&mut data, SECONDARY);
+    let image = &analyze(&data, &geometry).unwrap().images[0];
+    assert_eq!(image.secondary.status(), "magic");
+    assert_eq!(image.state, "Request (test)");
+    assert_eq!(image.swap_type, Ok(SwapType::Test));
+
+    set_image_ok(&mut data, SECONDARY);
+    let image = &analyze(&data, &geometry).unwrap().images[0];
+    assert_eq!(image.state, "Request (permanent)");
+    assert_eq!(image.swap_type, Ok(SwapType::Perm));
+
+    // The swap finished, but the new image hasn't confirmed itself.
+    let mut data = dump();
+    set_magic(&mut data, PRIMARY);
+    set_copy_done(&mut data, PRIMARY);
+    let image = &analyze(&data, &geometry).unwrap().images[0];
+    assert_eq!(image.primary.status(), "magic+cd");
+    assert!(image.state.starts_with("Copy Done"));
+    assert_eq!(image.swap_type, Ok(SwapType::Revert));
+
+    set_image_ok(&mut data, PRIMARY);
+    let image = &analyze(&data, &geometry).unwrap().images[0];
+    assert_eq!(image.primary.status(), "magic+cd+ok");
+    assert_eq!(image.state, "Image ok - no further changes");
+    assert_eq!(image.swap_type, Ok(SwapType::None));
+}
+
+#[test]
+fn damage() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+
+    // A half written magic, a corrupted image, and a recorded rejection.
+    let mut data = dump();
+    set_magic(&mut data, SECONDARY);
+    data[SECONDARY + SLOT - 4..SECONDARY + SLOT].fill(0xff);
+    data[SECONDARY + 0x200] ^= 1;
+    data[SECONDARY + SLOT - 40..SECONDARY + SLOT - 32].copy_from_slice(&[2, 0, 0xff, 0xff, 0, 1, 0, 0]);
+    data[PRIMARY..PRIMARY + 32].fill(0xff);
+
+    let analysis = analyze(&data, &geometry).unwrap();
+    let image = &analysis.images[0];
+    assert_eq!(image.primary.image, "erased");
+    assert!(image.secondary.image.ends_with("hash mismatch"));
+    assert_eq!(image.secondary.magic, Magic::Bad);
+    assert_eq!(image.secondary.image_ok, Flag::Unset);
+    assert_eq!(image.secondary.status(), "damaged");
+    assert_eq!(image.state, "Damaged trailer");
+    let err = image.secondary.boot_error.as_ref().unwrap().unwrap();
+    assert_eq!((err.code, err.offset), (ErrorCode::InvalidImage, 0x100));
+
+    let text = analysis.to_string();
+    assert!(text.contains("boot error: InvalidImage (0x0002) at slot offset 0x100"));
+    assert!(text.contains("next boot: swap type None"));
+
+    // Slots must be in the dump.
+    assert!(analyze(&data[..SECONDARY], &geometry).is_err());
+}
+
+#[test]
+fn remnant() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+    let image = &analyze(&dump(), &geometry).unwrap().images[0];
+    assert_eq!(image.primary.remnant, None);
+
+    // The tail of an older, larger image.
+    let mut data = dump();
+    let stray = PRIMARY + 0x18000;
+    data[stray..stray + 0x100].fill(0x5a);
+    let analysis = analyze(&data, &geometry).unwrap();
+    assert_eq!(analysis.images[0].primary.remnant, Some(0x18000));
+    assert_eq!(analysis.images[0].secondary.remnant, None);
+    assert!(analysis.to_string().contains("remnant:    data after the image at slot offset 0x18000"));
+}
@@ -0,0 +1,100 @@
+// Compatibility with imgtool.
+//
+// Signs the same input with imgtool and with `Signer`, and compares the
+// results, to catch the two drifting apart.  imgtool's ECDSA signatures are
+// randomized, so signed images are compared up to the signature, and each
+// side checks the other's signature.  Needs imgtool, so only built with
+// `--features imgtool`.
+
+#![cfg(feature = "imgtool")]
+
+use std::fs;
+use std::process::{Command, Stdio};
+
+use keys::PrivateKey;
+use mcuboot_tool::diff::diff;
+use mcuboot_tool::image::{parse_version, ImageInfo};
+use mcuboot_tool::sign::{KeyFormat, Signer};
+use mcuboot_tool::verify::{verify, Trust};
+use temp_dir::TempDir;
+
+static RAW: &[u8] = include_bytes!("../../boot/data/sample.bin");
+static PRIVATE: &str = include_str!("../../boot/data/ecdsa-p256.pem");
+
+fn imgtool() -> Command {
+    Command::new(std::env::var("IMGTOOL").unwrap_or_else(|_| "imgtool".to_string()))
+}
+
+fn run(cmd: &mut Command) {
+    let status = cmd.stdin(Stdio::null()).status().expect("Unable to run imgtool");
+    assert!(status.success(), "imgtool failed: {}", status);
+}
+
+/// Sign `input` with imgtool, given the arguments matching a `Signer`.
+fn imgtool_sign(tmp: &TempDir, args: &[&str], input: &[u8]) -> Vec<u8> {
+    let src = tmp.path().join("input.bin");
+    let dest = tmp.path().join("imgtool.bin");
+    fs::write(&src, input).unwrap();
+    run(imgtool().arg("sign").args(["--align", "4"]).args(args).arg(&src).arg(&dest));
+    fs::read(dest).unwrap()
+}
+
+/// Have imgtool check an image.
+fn imgtool_verify(tmp: &TempDir, key: &str, image: &[u8]) {
+    let path = tmp.path().join("ours.bin");
+    fs::write(&path, image).unwrap();
+    run(imgtool().args(["verify", "--key", key]).arg(&path));
+}
+
+#[test]
+fn unsigned() {
+    let tmp = TempDir::new().unwrap();
+    let payload = &RAW[256..];
+
+    let theirs = imgtool_sign(&tmp, &["-v", "1.2.3+4", "--header-size", "512", "--pad-header"], payload);
+    let ours = Signer::default()
+        .version(parse_version("1.2.3+4").unwrap())
+        .header_size(512)
+        .pad_header(true)
+        .sign(payload)
+        .unwrap();
+    assert_eq!(diff(&ours, &theirs).unwrap(), Vec::<String>::new());
+    assert_eq!(ours, theirs);
+}
+
+#[test]
+fn signed() {
+    let tmp = TempDir::new().unwrap();
+    let key_path = tmp.path().join("key.pem");
+    fs::write(&key_path, PRIVATE).unwrap();
+    let key_path = key_path.to_str().unwrap();
+    let key = PrivateKey::from_pem(PRIVATE).unwrap();
+    let public = key.public();
+
+    for (format, name) in [(KeyFormat::Hash, "hash"), (KeyFormat::Full, "full")] {
+        let theirs = imgtool_sign(&tmp, &["-v", "0.1.0", "--key", key_path,
+                                          "--public-key-format", name,
+                                          "--security-counter", "7"], RAW);
+        let ours = Signer::default()
+            .version(parse_version("0.1.0").unwrap())
+            .security_counter(7)
+            .key(&key, format)
+            .sign(RAW)
+            .unwrap();
+
+        // Identical, up to the signature, whose length varies.
+        assert_eq!(diff(&ours, &theirs).unwrap(), ["TLV 0x22 ECDSA_SIG: changed"]);
+        let header = ImageInfo::parse(&ours).unwrap().header;
+        let end = header.hdr_size as usize + header.img_size as usize + header.protected_tlv_size as usize;
+        assert_eq!(ours[..end], theirs[..end]);
+
+        // Which each side accepts from the other.
+        let der = public.to_der();
+        let trust = match format {
+            KeyFormat::Hash => Trust::Key(&der),
+            KeyFormat::Full => Trust::KeyHash(&public.key_hash()),
+        };
+        verify(&theirs, trust).unwrap();
+        imgtool_verify(&tmp, key_path, &ours);
+    }
+}
@@ -0,0 +1,61 @@
+// Factory image testing.
+
+use boot::SwapType;
+use mcuboot_tool::analyze::{analyze, Geometry};
+use mcuboot_tool::provision::provision;
+use mcuboot_tool::verify::{verify, Trust};
+
+static SIGNED: &[u8] = include_bytes!("../../boot/data/sample-ecdsa.bin");
+static RAW: &[u8] = include_bytes!("../../boot/data/sample.bin");
+static PUBLIC: &[u8] = include_bytes!("../../boot/data/ecdsa-p256-pub.der");
+
+static GEOMETRY: &str = "
+base 0x10000000
+write-size 8
+erase-size 0x1000
+slot primary 0x10010000 0x20000
+slot secondary 0x10030000 0x20000
+";
+
+const PRIMARY: usize = 0x10000;
+const SECONDARY: usize = 0x30000;
+const SLOT: usize = 0x20000;
+
+#[test]
+fn factory_image() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+    let bootloader = [0x5a; 0x8000];
+    let flash = provision(&geometry, &bootloader, SIGNED, None).unwrap();
+    assert_eq!(flash.len(), SECONDARY + SLOT);
+    assert_eq!(flash[..bootloader.len()], bootloader);
+    assert!(flash[bootloader.len()..PRIMARY].iter().all(|&b| b == 0xff));
+    assert_eq!(flash[PRIMARY..PRIMARY + SIGNED.len()], *SIGNED);
+    verify(&flash[PRIMARY..PRIMARY + SLOT], Trust::Key(PUBLIC)).unwrap();
+    assert!(flash[SECONDARY..].iter().all(|&b| b == 0xff));
+
+    // The first boot has nothing to do but run the image.
+    let analysis = analyze(&flash, &geometry).unwrap();
+    let pair = &analysis.images[0];
+    assert_eq!(pair.state, "Image ok - no further changes");
+    assert_eq!(pair.primary.status(), "magic+cd+ok");
+    assert_eq!(pair.secondary.status(), "blank");
+    assert_eq!(pair.swap_type.as_ref().unwrap(), &SwapType::None);
+
+    // A secondary image is only placed, not requested.
+    let flash = provision(&geometry, &bootloader, SIGNED, Some(SIGNED)).unwrap();
+    assert_eq!(flash[SECONDARY..SECONDARY + SIGNED.len()], *SIGNED);
+    let analysis = analyze(&flash, &geometry).unwrap();
+    assert_eq!(analysis.images[0].swap_type.as_ref().unwrap(), &SwapType::None);
+}
+
+#[test]
+fn factory_errors() {
+    let geometry = Geometry::parse(GEOMETRY).unwrap();
+    // The bootloader must end before the first slot.
+    assert!(provision(&geometry, &[0; PRIMARY + 1], SIGNED, None).is_err());
+    // Images must be images, and leave room for the trailer.
+    assert!(provision(&geometry, &[], RAW, None).is_err());
+    let mut large = SIGNED.to_vec();
+    large.resize(SLOT - 16, 0xff);
+    assert!(provision(&geometry, &[], &large, None).is_err());
+}
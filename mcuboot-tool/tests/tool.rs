@@ -0,0 +1,139 @@
+// Host tool testing.
+
+use keys::{PrivateKey, PublicKey};
+use mcuboot_tool::diff::diff;
+use mcuboot_tool::image::{parse_version, ImageInfo, TLV_ECDSA_SIG, TLV_KEYHASH, TLV_MANIFEST, TLV_PUBKEY, TLV_SEC_CNT, TLV_SHA256};
+use mcuboot_tool::sign::{flatten_input, KeyFormat, Signer};
+use mcuboot_tool::verify::{verify, Trust};
+
+static RAW: &[u8] = include_bytes!("../../boot/data/sample.bin");
+static SIGNED: &[u8] = include_bytes!("../../boot/data/sample-ecdsa.bin");
+static SIGNED_PUBKEY: &[u8] = include_bytes!("../../boot/data/sample-ecdsa-pubkey.bin");
+static PRIVATE: &str = include_str!("../../boot/data/ecdsa-p256.pem");
+static PUBLIC: &[u8] = include_bytes!("../../boot/data/ecdsa-p256-pub.der");
+
+#[test]
+fn keys() {
+    let key = PrivateKey::from_pem(PRIVATE).unwrap();
+    assert_eq!(key.public().to_der(), PUBLIC);
+    assert_eq!(PublicKey::from_bytes(PUBLIC).unwrap(), key.public());
+}
+
+#[test]
+fn parse() {
+    let info = ImageInfo::parse(SIGNED).unwrap();
+    assert_eq!(info.header.version, parse_version("0.1.0").unwrap());
+    assert_eq!(info.header.hdr_size, 256);
+    assert_eq!(info.size, SIGNED.len());
+    let kinds: Vec<u16> = info.tlvs.iter().map(|tlv| tlv.kind).collect();
+    assert_eq!(kinds, [TLV_SHA256, TLV_KEYHASH, TLV_ECDSA_SIG]);
+
+    let version = parse_version("1.2.3+4").unwrap();
+    assert_eq!((version.major, version.minor, version.revision, version.build_num), (1, 2, 3, 4));
+    assert!(parse_version("1.2.3.4").is_err());
+}
+
+#[test]
+fn sign_like_imgtool() {
+    // The sample files come from separate runs of gen.sh, so rebuild the input
+    // imgtool was given from its own output.
+    let theirs = ImageInfo::parse(SIGNED).unwrap();
+    let end = 256 + theirs.header.img_size as usize;
+    let mut raw = vec![0u8; 256];
+    raw.extend(&SIGNED[256..end]);
+
+    let key = PrivateKey::from_pem(PRIVATE).unwrap();
+    let image = Signer::default()
+        .version(parse_version("0.1.0").unwrap())
+        .key(&key, KeyFormat::Hash)
+        .sign(&raw)
+        .unwrap();
+    verify(&image, Trust::Key(PUBLIC)).unwrap();
+
+    // Everything but the signature matches imgtool's output.
+    let ours = ImageInfo::parse(&image).unwrap();
+    assert_eq!(ours.header, theirs.header);
+    assert_eq!(&image[..end], &SIGNED[..end]);
+    assert_eq!(ours.find(TLV_SHA256), theirs.find(TLV_SHA256));
+    assert_eq!(ours.find(TLV_KEYHASH).unwrap().data, theirs.find(TLV_KEYHASH).unwrap().data);
+    assert_eq!(diff(&image, SIGNED).unwrap(), ["TLV 0x22 ECDSA_SIG: changed"]);
+
+    // Signing is repeatable.
+    let again = Signer::default()
+        .version(parse_version("0.1.0").unwrap())
+        .key(&key, KeyFormat::Hash)
+        .sign(&raw)
+        .unwrap();
+    assert_eq!(image, again);
+}
+
+#[test]
+fn sign_elf() {
+    // The sample's payload, linked after the header, with a vector table
+    // whose reset vector is in the image.
+    let mut raw = RAW.to_vec();
+    raw[256 + 4..256 + 8].copy_from_slice(&0x1_0201u32.to_le_bytes());
+    let segments = [
+        elf::Segment { addr: 0x1_0000, data: raw[..0x800].to_vec() },
+        elf::Segment { addr: 0x1_0800, data: raw[0x800..].to_vec() },
+    ];
+    let file = elf::write(0x1_0201, &segments);
+
+    let input = flatten_input(file).unwrap();
+    assert_eq!(input, raw);
+    let signer = Signer::default();
+    assert_eq!(signer.sign(&input).unwrap(), signer.sign(&raw).unwrap());
+
+    // Binaries are taken as they are.
+    assert_eq!(flatten_input(raw.clone()).unwrap(), raw);
+
+    // An entry point outside the image is a sign of the wrong file.
+    assert!(flatten_input(elf::write(0x2000_0001, &segments)).is_err());
+}
+
+#[test]
+fn sign_options() {
+    let key = PrivateKey::from_pem(PRIVATE).unwrap();
+    let payload = &RAW[256..];
+
+    // The full key, for key hash trust, a security counter, and a manifest.
+    let manifest = [0xa1, 0x01, 0x43, b'a', b'p', b'p'];
+    let image = Signer::default()
+        .pad_header(true)
+        .security_counter(7)
+        .manifest(&manifest)
+        .key(&key, KeyFormat::Full)
+        .sign(payload)
+        .unwrap();
+    let info = ImageInfo::parse(&image).unwrap();
+    assert_eq!(info.find(TLV_SEC_CNT).unwrap().data, 7u32.to_le_bytes());
+    assert_eq!(info.find(TLV_MANIFEST).unwrap().data, manifest);
+    assert!(info.protected.iter().any(|tlv| tlv.kind == TLV_MANIFEST));
+    assert_eq!(info.find(TLV_PUBKEY).unwrap().data, PUBLIC);
+    verify(&image, Trust::KeyHash(&key.public().key_hash())).unwrap();
+    assert!(verify(&image, Trust::KeyHash(&[0; 32])).is_err());
+
+    // Unsigned images only carry the hash.
+    let image = Signer::default().sign(RAW).unwrap();
+    verify(&image, Trust::None).unwrap();
+    assert!(verify(&image, Trust::Key(PUBLIC)).is_err());
+
+    // Without padding, the header area must be empty.
+    assert!(Signer::default().sign(payload).is_err());
+}
+
+#[test]
+fn differences() {
+    assert!(diff(SIGNED, SIGNED).unwrap().is_empty());
+    let lines = diff(SIGNED, SIGNED_PUBKEY).unwrap();
+    assert!(lines.contains(&"TLV 0x01 KEYHASH: removed".to_string()));
+    assert!(lines.contains(&"TLV 0x02 PUBKEY: added".to_string()));
+
+    let mut other = SIGNED.to_vec();
+    other[20] = 2;
+    other[300] ^= 1;
+    other[400] ^= 1;
+    let lines = diff(SIGNED, &other).unwrap();
+    assert_eq!(lines[0], "header version: 0.1.0+0 -> 2.1.0+0");
+    assert_eq!(lines[1], "payload: 2 of 76800 bytes differ, the first at payload offset 0x2c");
+}
@@ -0,0 +1,250 @@
+//! Data-driven flash styles
+//!
+//! The styles in `styles.rs` cover the device families we know about.  To allow
+//! other geometries to be tested without patching that file, slot maps can
+//! also be described in a small TOML file:
+//!
+//! ```toml
+//! # Each device is a table, with a sub-table for each area.
+//! [lpc55.main]
+//! read_size = 1
+//! write_size = 512
+//! erase_size = 512
+//! sectors = 256
+//!
+//! [lpc55.upgrade]
+//! read_size = 1
+//! write_size = 512
+//! erase_size = 512
+//! sectors = 256
+//!
+//! # The scratch area is optional.
+//! [lpc55.scratch]
+//! read_size = 1
+//! write_size = 512
+//! erase_size = 512
+//! sectors = 8
+//! ```
+//!
+//! Only this subset of TOML is understood: table headers, integer values
+//! (decimal or hex, with optional '_' separators) and comments.
+//!
+//! If the environment variable `SIMFLASH_LAYOUTS` names such a file, the
+//! devices described in it are added to the iterators in `styles`.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+
+use crate::styles::{AreaLayout, SlotMap};
+
+/// Name of the environment variable giving an extra layout file.
+pub const LAYOUTS_ENV: &str = "SIMFLASH_LAYOUTS";
+
+/// Load the slot maps from the given file.
+pub fn load_slot_maps<P: AsRef<Path>>(path: P) -> Result<Vec<SlotMap>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+    parse_slot_maps(&text)
+}
+
+/// Load the slot maps named by `SIMFLASH_LAYOUTS`, if that is set.
+pub fn env_slot_maps() -> Result<Vec<SlotMap>> {
+    match std::env::var_os(LAYOUTS_ENV) {
+        Some(path) => load_slot_maps(path),
+        None => Ok(vec![]),
+    }
+}
+
+/// Partially parsed area, where fields may not have been given yet.
+#[derive(Default)]
+struct PartialArea {
+    read_size: Option<usize>,
+    write_size: Option<usize>,
+    erase_size: Option<usize>,
+    sectors: Option<usize>,
+}
+
+impl PartialArea {
+    fn finish(&self, device: &str, area: &str) -> Result<AreaLayout> {
+        let get = |field: Option<usize>, name: &str| {
+            field.ok_or_else(|| anyhow!("{}.{}: missing {}", device, area, name))
+        };
+        let layout = AreaLayout {
+            read_size: get(self.read_size, "read_size")?,
+            write_size: get(self.write_size, "write_size")?,
+            erase_size: get(self.erase_size, "erase_size")?,
+            sectors: get(self.sectors, "sectors")?,
+        };
+        if layout.read_size == 0 || layout.write_size == 0 || layout.sectors == 0 {
+            return Err(anyhow!("{}.{}: sizes must be non-zero", device, area));
+        }
+        if layout.write_size > layout.erase_size || !layout.erase_size.is_multiple_of(layout.write_size) {
+            return Err(anyhow!("{}.{}: erase_size must be a multiple of write_size",
+                               device, area));
+        }
+        Ok(layout)
+    }
+}
+
+#[derive(Default)]
+struct PartialMap {
+    name: String,
+    main: Option<PartialArea>,
+    upgrade: Option<PartialArea>,
+    scratch: Option<PartialArea>,
+}
+
+impl PartialMap {
+    fn area(&mut self, area: &str) -> Option<&mut Option<PartialArea>> {
+        match area {
+            "main" => Some(&mut self.main),
+            "upgrade" => Some(&mut self.upgrade),
+            "scratch" => Some(&mut self.scratch),
+            _ => None,
+        }
+    }
+
+    fn finish(&self) -> Result<SlotMap> {
+        let need = |area: &Option<PartialArea>, name: &str| {
+            area.as_ref()
+                .ok_or_else(|| anyhow!("{}: missing {} area", self.name, name))?
+                .finish(&self.name, name)
+        };
+        let scratch = match self.scratch {
+            Some(ref s) => Some(s.finish(&self.name, "scratch")?),
+            None => None,
+        };
+        Ok(SlotMap {
+            name: self.name.clone(),
+            main: need(&self.main, "main")?,
+            upgrade: need(&self.upgrade, "upgrade")?,
+            scratch,
+        })
+    }
+}
+
+/// Parse slot maps from the TOML subset described above.  The devices are
+/// returned in the order they first appear.
+pub fn parse_slot_maps(text: &str) -> Result<Vec<SlotMap>> {
+    let mut maps: Vec<PartialMap> = Vec::new();
+    // Index into maps, and area name, of the current table.
+    let mut current: Option<(usize, String)> = None;
+
+    for (lineno, line) in text.lines().enumerate() {
+        let lineno = lineno + 1;
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        }.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.strip_suffix(']')
+                .ok_or_else(|| anyhow!("line {}: malformed table header", lineno))?
+                .trim();
+            let (device, area) = header.split_once('.')
+                .ok_or_else(|| anyhow!("line {}: expecting [device.area]", lineno))?;
+            let (device, area) = (device.trim(), area.trim());
+
+            let index = match maps.iter().position(|m| m.name == device) {
+                Some(index) => index,
+                None => {
+                    maps.push(PartialMap { name: device.to_string(), ..Default::default() });
+                    maps.len() - 1
+                }
+            };
+            let slot = maps[index].area(area)
+                .ok_or_else(|| anyhow!("line {}: unknown area {:?}", lineno, area))?;
+            if slot.is_some() {
+                return Err(anyhow!("line {}: duplicate table [{}]", lineno, header));
+            }
+            *slot = Some(PartialArea::default());
+            current = Some((index, area.to_string()));
+            continue;
+        }
+
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expecting key = value", lineno))?;
+        let (key, value) = (key.trim(), parse_int(value.trim())
+                            .ok_or_else(|| anyhow!("line {}: invalid integer", lineno))?);
+
+        let (index, area) = current.as_ref()
+            .ok_or_else(|| anyhow!("line {}: value outside of a table", lineno))?;
+        let area = maps[*index].area(area).unwrap().as_mut().unwrap();
+        let field = match key {
+            "read_size" => &mut area.read_size,
+            "write_size" => &mut area.write_size,
+            "erase_size" => &mut area.erase_size,
+            "sectors" => &mut area.sectors,
+            _ => return Err(anyhow!("line {}: unknown key {:?}", lineno, key)),
+        };
+        if field.is_some() {
+            return Err(anyhow!("line {}: duplicate key {:?}", lineno, key));
+        }
+        *field = Some(value);
+    }
+
+    maps.iter().map(|m| m.finish()).collect()
+}
+
+/// Parse a TOML integer.  Supports decimal and hex, with '_' separators.
+fn parse_int(text: &str) -> Option<usize> {
+    let text: String = text.chars().filter(|&c| c != '_').collect();
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let maps = parse_slot_maps(r#"
+            # A device with a scratch area.
+            [dev.main]
+            read_size = 1
+            write_size = 8
+            erase_size = 0x1000
+            sectors = 33
+
+            [dev.upgrade]
+            read_size = 1
+            write_size = 8
+            erase_size = 4_096
+            sectors = 33   # Same as main.
+
+            [dev.scratch]
+            read_size = 1
+            write_size = 8
+            erase_size = 4096
+            sectors = 2
+            "#).unwrap();
+        assert_eq!(maps.len(), 1);
+        assert_eq!(maps[0].name, "dev");
+        assert_eq!(maps[0].main, maps[0].upgrade);
+        assert_eq!(maps[0].main.erase_size, 4096);
+        assert_eq!(maps[0].scratch.as_ref().unwrap().sectors, 2);
+        maps[0].main.build().unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // Missing upgrade area.
+        assert!(parse_slot_maps("[a.main]\nread_size=1\nwrite_size=8\n\
+                                 erase_size=4096\nsectors=1\n").is_err());
+        // Bad geometry.
+        assert!(parse_slot_maps("[a.main]\nread_size=1\nwrite_size=3\n\
+                                 erase_size=4096\nsectors=1\n").is_err());
+        // Unknown key.
+        assert!(parse_slot_maps("[a.main]\nbogus=1\n").is_err());
+        // Unknown area.
+        assert!(parse_slot_maps("[a.other]\n").is_err());
+    }
+}
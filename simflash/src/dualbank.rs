@@ -0,0 +1,179 @@
+//! Dual-bank devices
+//!
+//! Parts such as the STM32H7 have two flash banks, and an option bit that
+//! swaps which of them appears first in the memory map.  A bank swap upgrade
+//! writes the new image into the second bank, programs the option, and
+//! resets.  The option is kept in flash, so it survives a power cut, but the
+//! memory map only follows it from the next reset.
+//!
+//! `DualBank` models this with two `SimFlash` banks.  As `Flash`, it is
+//! addressed as the memory map is: the first bank's worth of offsets go to
+//! whichever bank is mapped first.  An operation can't straddle the two.
+//! `program_option` changes the option, `reset` (which is also what a power
+//! cut ends in) latches it into the memory map, and `cut_option` makes the
+//! next program of the option lose power before it completes, leaving the
+//! option as it was.
+
+use storage::{Error, Flash, ReadFlash, Result};
+
+use crate::SimFlash;
+
+/// Two banks, and the option that orders them.
+pub struct DualBank {
+    banks: [SimFlash; 2],
+    /// The option as programmed: bank 1 is to be mapped first.
+    option: bool,
+    /// The option as of the last reset, which the memory map follows.
+    swapped: bool,
+    /// The next program of the option is cut short.
+    cut: bool,
+}
+
+impl DualBank {
+    /// Two banks of the same geometry, not swapped.
+    pub fn new(first: SimFlash, second: SimFlash) -> Result<DualBank> {
+        if first.capacity() != second.capacity() ||
+            first.erase_size() != second.erase_size() ||
+            first.write_size() != second.write_size()
+        {
+            return Err(Error::NotAligned);
+        }
+        Ok(DualBank { banks: [first, second], option: false, swapped: false, cut: false })
+    }
+
+    /// The size of each bank.
+    pub fn bank_size(&self) -> usize {
+        self.banks[0].capacity()
+    }
+
+    /// Is bank 1 mapped first?
+    pub fn swapped(&self) -> bool {
+        self.swapped
+    }
+
+    /// The option, as programmed, which takes effect at the next reset.
+    pub fn option(&self) -> bool {
+        self.option
+    }
+
+    /// Program the option.  If the program is cut, the option is left alone,
+    /// and this fails, as far as anything is left running to see it.
+    pub fn program_option(&mut self, swapped: bool) -> Result<()> {
+        if self.cut {
+            self.cut = false;
+            return Err(Error::Failed);
+        }
+        self.option = swapped;
+        Ok(())
+    }
+
+    /// Cut the power during the next program of the option.
+    pub fn cut_option(&mut self) {
+        self.cut = true;
+    }
+
+    /// Reset the device, or power it back up, mapping the banks as the
+    /// option says.
+    pub fn reset(&mut self) {
+        self.swapped = self.option;
+    }
+
+    /// A bank, by its own number rather than where it is mapped.
+    pub fn bank(&self, bank: usize) -> &SimFlash {
+        &self.banks[bank]
+    }
+
+    pub fn bank_mut(&mut self, bank: usize) -> &mut SimFlash {
+        &mut self.banks[bank]
+    }
+
+    /// The bank that an operation on `offset..offset + len` goes to, and the
+    /// offset within it.
+    fn locate(&self, offset: usize, len: usize) -> Result<(usize, usize)> {
+        let size = self.bank_size();
+        let end = offset.checked_add(len).ok_or(Error::OutOfBounds)?;
+        if end > 2 * size {
+            return Err(Error::OutOfBounds);
+        }
+        let mapped = offset / size;
+        if len > 0 && (end - 1) / size != mapped {
+            return Err(Error::OutOfBounds);
+        }
+        Ok((mapped ^ self.swapped as usize, offset - mapped * size))
+    }
+}
+
+impl ReadFlash for DualBank {
+    fn read_size(&self) -> usize {
+        self.banks[0].read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        let (bank, offset) = self.locate(offset, bytes.len())?;
+        self.banks[bank].read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        2 * self.bank_size()
+    }
+}
+
+impl Flash for DualBank {
+    fn write_size(&self) -> usize {
+        self.banks[0].write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.banks[0].erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        if to < from {
+            return Err(Error::OutOfBounds);
+        }
+        let (bank, start) = self.locate(from, to - from)?;
+        self.banks[bank].erase(start, start + (to - from))
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        let (bank, offset) = self.locate(offset, bytes.len())?;
+        self.banks[bank].write(offset, bytes)
+    }
+}
+
+#[test]
+fn test_dual_bank() {
+    let bank = || SimFlash::new(1, 8, 4096, 4).unwrap();
+    let mut dev = DualBank::new(bank(), bank()).unwrap();
+    let size = dev.bank_size();
+    dev.erase(0, size).unwrap();
+    dev.erase(size, 2 * size).unwrap();
+    dev.write(0, b"firstbnk").unwrap();
+    dev.write(size, b"secondbk").unwrap();
+    assert_eq!(dev.erase(size - 4096, size + 4096), Err(Error::OutOfBounds));
+
+    let mut buf = [0u8; 8];
+    let mut at = |dev: &mut DualBank, offset| {
+        dev.read(offset, &mut buf).unwrap();
+        buf
+    };
+
+    // The option only takes effect at the reset.
+    dev.program_option(true).unwrap();
+    assert_eq!(&at(&mut dev, 0), b"firstbnk");
+    dev.reset();
+    assert!(dev.swapped());
+    assert_eq!(&at(&mut dev, 0), b"secondbk");
+    assert_eq!(&at(&mut dev, size), b"firstbnk");
+
+    // Writes follow the memory map.
+    dev.erase(size, size + 4096).unwrap();
+    assert_eq!(dev.bank(0).dump()[..8], [0xff; 8]);
+
+    // A cut program leaves the option as it was, through the power up.
+    dev.cut_option();
+    assert_eq!(dev.program_option(false), Err(Error::Failed));
+    dev.reset();
+    assert!(dev.swapped());
+    assert_eq!(&at(&mut dev, 0), b"secondbk");
+}
@@ -0,0 +1,170 @@
+//! Injecting flash errors
+//!
+//! `FaultyFlash` wraps any `Flash`, and fails the operations scheduled in its
+//! faults, without performing them, returning the error given.  A fault can
+//! pick out the nth operation of a kind, or every one touching a range of
+//! the device, and can fire any number of times, such as just once for a
+//! transient failure.  This lets the error handling in the boot code be
+//! tested against any device, not just what SimFlash itself reports.
+
+use std::ops::Range;
+
+use storage::{Error, Flash, ReadFlash, Result};
+
+/// The kinds of operation.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Op {
+    Read,
+    Write,
+    Erase,
+}
+
+/// Which operations a fault applies to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum When {
+    /// The operation of this index, counting from 0, among those of its kind.
+    Nth(usize),
+    /// Operations touching any of this range.
+    Range(Range<usize>),
+}
+
+/// An error to return in place of some operations.
+#[derive(Debug, Clone)]
+pub struct Fault {
+    pub op: Op,
+    pub when: When,
+    pub error: Error,
+    /// How many more times the fault fires.
+    pub times: usize,
+}
+
+impl Fault {
+    /// Fail the nth operation of the given kind.
+    pub fn nth(op: Op, n: usize, error: Error) -> Fault {
+        Fault { op, when: When::Nth(n), error, times: 1 }
+    }
+
+    /// Fail every operation of the given kind touching `range`.
+    pub fn range(op: Op, range: Range<usize>, error: Error) -> Fault {
+        Fault { op, when: When::Range(range), error, times: usize::MAX }
+    }
+
+    /// Only fire `times` times, after which the operations succeed.
+    pub fn times(self, times: usize) -> Fault {
+        Fault { times, ..self }
+    }
+
+    fn applies(&self, op: Op, index: usize, span: &Range<usize>) -> bool {
+        self.op == op && self.times > 0 && match &self.when {
+            When::Nth(n) => *n == index,
+            When::Range(range) => range.start < span.end && span.start < range.end,
+        }
+    }
+}
+
+pub struct FaultyFlash<F> {
+    flash: F,
+    faults: Vec<Fault>,
+    /// Operations of each kind so far.
+    counts: [usize; 3],
+}
+
+impl<F: Flash> FaultyFlash<F> {
+    pub fn new(flash: F) -> FaultyFlash<F> {
+        FaultyFlash { flash, faults: vec![], counts: [0; 3] }
+    }
+
+    /// Add a fault to those scheduled.
+    pub fn fault(mut self, fault: Fault) -> FaultyFlash<F> {
+        self.faults.push(fault);
+        self
+    }
+
+    /// The number of operations of a kind attempted so far, including those
+    /// failed.
+    pub fn count(&self, op: Op) -> usize {
+        self.counts[op as usize]
+    }
+
+    /// Access the flash directly.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Count an operation, and fail it if a fault applies.
+    fn check(&mut self, op: Op, span: Range<usize>) -> Result<()> {
+        let index = self.counts[op as usize];
+        self.counts[op as usize] += 1;
+        match self.faults.iter_mut().find(|fault| fault.applies(op, index, &span)) {
+            Some(fault) => {
+                fault.times -= 1;
+                Err(fault.error)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<F: Flash> ReadFlash for FaultyFlash<F> {
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        self.check(Op::Read, offset..offset + bytes.len())?;
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        self.flash.memory_address()
+    }
+}
+
+impl<F: Flash> Flash for FaultyFlash<F> {
+    fn write_size(&self) -> usize {
+        self.flash.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.flash.erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check(Op::Erase, from..to)?;
+        self.flash.erase(from, to)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        self.check(Op::Write, offset..offset + bytes.len())?;
+        self.flash.write(offset, bytes)
+    }
+}
+
+#[test]
+fn test_faulty() {
+    let flash = crate::SimFlash::new(1, 8, 4096, 2).unwrap();
+    let mut flash = FaultyFlash::new(flash)
+        .fault(Fault::nth(Op::Write, 1, Error::Failed))
+        .fault(Fault::range(Op::Erase, 4096..4097, Error::NotErased).times(2));
+    let buf = [0u8; 8];
+
+    flash.erase(0, 4096).unwrap();
+    flash.write(0, &buf).unwrap();
+    assert_eq!(flash.write(8, &buf), Err(Error::Failed));
+    flash.write(8, &buf).unwrap();
+
+    // A transient failure, clearing after two tries.
+    assert_eq!(flash.erase(0, 8192), Err(Error::NotErased));
+    assert_eq!(flash.erase(4096, 8192), Err(Error::NotErased));
+    flash.erase(4096, 8192).unwrap();
+    assert_eq!(flash.count(Op::Erase), 4);
+    assert_eq!(flash.count(Op::Write), 3);
+}
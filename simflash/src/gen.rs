@@ -6,6 +6,8 @@ use rand::{SeedableRng, RngCore};
 use rand_xoshiro::Xoshiro256Plus;
 
 use anyhow::{Result, anyhow};
+use keys::PrivateKey;
+use sha2::{Digest, Sha256};
 use temp_dir::TempDir;
 
 pub struct GeneratedImage {
@@ -21,6 +23,10 @@ pub struct GenBuilder {
     seed: usize,
     /// Version
     version: String,
+    /// Key to sign with, as PEM.
+    key: Option<String>,
+    /// An ELF file to take the payload from, rather than generating it.
+    elf: Option<Vec<u8>>,
 }
 
 impl Default for GenBuilder {
@@ -30,6 +36,8 @@ impl Default for GenBuilder {
             size: 76_137,
             seed: 1,
             version: "0.1.0".to_string(),
+            key: None,
+            elf: None,
         }
     }
 }
@@ -45,13 +53,38 @@ impl GenBuilder {
         self
     }
 
+    /// Sign the image with this key, rather than only hashing it.
+    pub fn key(&mut self, key: &PrivateKey) -> &mut Self {
+        self.key = Some(key.to_pem());
+        self
+    }
+
+    /// Sign the loadable segments of this ELF file, such as an application
+    /// crate's build, instead of random data.  If the file doesn't start with
+    /// room for the header, the header is added before it.
+    pub fn elf(&mut self, data: &[u8]) -> &mut Self {
+        self.elf = Some(data.to_vec());
+        self
+    }
+
     pub fn build(&self) -> Result<GeneratedImage> {
-        let mut rng = Xoshiro256Plus::seed_from_u64(self.seed as u64);
-        let mut input = vec![0u8; self.size];
-        rng.fill_bytes(&mut input);
+        let (input, pad) = match &self.elf {
+            Some(data) => {
+                let flat = elf::flatten(data)?;
+                let room = flat.data.len() >= self.header_size &&
+                    flat.data[..self.header_size].iter().all(|&b| b == 0);
+                (flat.data, !room)
+            }
+            None => {
+                let mut rng = Xoshiro256Plus::seed_from_u64(self.seed as u64);
+                let mut input = vec![0u8; self.size];
+                rng.fill_bytes(&mut input);
 
-        // The header is required to be zeros, so just fill that in.
-        input[..self.header_size].fill(0);
+                // The header is required to be zeros, so just fill that in.
+                input[..self.header_size].fill(0);
+                (input, false)
+            }
+        };
 
         let tmp = TempDir::new()?;
 
@@ -66,6 +99,9 @@ impl GenBuilder {
 
         cmd.arg("--header-size");
         cmd.arg(&format!("{}", self.header_size));
+        if pad {
+            cmd.arg("--pad-header");
+        }
 
         cmd.arg("-v");
         cmd.arg(&self.version);
@@ -78,6 +114,13 @@ impl GenBuilder {
         cmd.arg("--slot-size");
         cmd.arg(format!("{}", 128*1024));
 
+        if let Some(key) = &self.key {
+            let path = tmp.path().join("key.pem");
+            fs::write(&path, key)?;
+            cmd.arg("--key");
+            cmd.arg(&path);
+        }
+
         cmd.arg(&src);
         cmd.arg(&dest);
 
@@ -94,6 +137,103 @@ impl GenBuilder {
     }
 }
 
+/// Builds a hashed, unsigned image directly, without imgtool, for tests that
+/// need particular header fields or protected TLVs.
+pub struct ImageBuilder {
+    header_size: usize,
+    version: (u8, u8, u16, u32),
+    payload: Vec<u8>,
+    /// Protected TLVs, as kind and data.
+    protected: Vec<(u16, Vec<u8>)>,
+    hash: bool,
+}
+
+impl Default for ImageBuilder {
+    fn default() -> Self {
+        ImageBuilder {
+            header_size: 256,
+            version: (0, 0, 0, 0),
+            payload: vec![],
+            protected: vec![],
+            hash: true,
+        }
+    }
+}
+
+impl ImageBuilder {
+    /// The size the header says it is.  A size smaller than the header
+    /// fields still has them all written, for testing bad sizes.
+    pub fn header_size(&mut self, size: usize) -> &mut Self {
+        self.header_size = size;
+        self
+    }
+
+    pub fn version(&mut self, major: u8, minor: u8, revision: u16, build_num: u32) -> &mut Self {
+        self.version = (major, minor, revision, build_num);
+        self
+    }
+
+    pub fn payload(&mut self, payload: &[u8]) -> &mut Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Add a protected TLV.
+    pub fn protected(&mut self, kind: u16, data: &[u8]) -> &mut Self {
+        self.protected.push((kind, data.to_vec()));
+        self
+    }
+
+    /// Leave out the SHA256, leaving an empty TLV area, for tests that only
+    /// look at the header.
+    pub fn no_hash(&mut self) -> &mut Self {
+        self.hash = false;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut protected = vec![];
+        for (kind, data) in &self.protected {
+            tlv(&mut protected, *kind, data);
+        }
+        let prot_size = if protected.is_empty() { 0 } else { protected.len() + 4 };
+
+        let (major, minor, revision, build_num) = self.version;
+        let mut image = vec![];
+        image.extend(0x96f3b83du32.to_le_bytes());
+        image.extend(0u32.to_le_bytes());
+        image.extend((self.header_size as u16).to_le_bytes());
+        image.extend((prot_size as u16).to_le_bytes());
+        image.extend((self.payload.len() as u32).to_le_bytes());
+        image.extend(0u32.to_le_bytes());
+        image.extend([major, minor]);
+        image.extend(revision.to_le_bytes());
+        image.extend(build_num.to_le_bytes());
+        image.resize(self.header_size.max(32), 0);
+        image.extend(&self.payload);
+        if prot_size > 0 {
+            image.extend(0x6908u16.to_le_bytes());
+            image.extend((prot_size as u16).to_le_bytes());
+            image.extend(&protected);
+        }
+
+        let mut tlvs = vec![];
+        if self.hash {
+            tlv(&mut tlvs, 0x10, &Sha256::digest(&image));
+        }
+        image.extend(0x6907u16.to_le_bytes());
+        image.extend((tlvs.len() as u16 + 4).to_le_bytes());
+        image.extend(&tlvs);
+        image
+    }
+}
+
+fn tlv(out: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    out.extend(kind.to_le_bytes());
+    out.extend((data.len() as u16).to_le_bytes());
+    out.extend(data);
+}
+
 #[cfg(test)]
 mod tester {
     use std::cell::RefCell;
@@ -101,7 +241,7 @@ mod tester {
 
     use crate::styles;
 
-    use super::GenBuilder;
+    use super::{GenBuilder, ImageBuilder};
 
     #[test]
     fn test_gen() {
@@ -114,4 +254,21 @@ mod tester {
         let image = Image::from_flash(&flash).unwrap();
         image.validate().unwrap();
     }
+
+    #[test]
+    fn test_image_builder() {
+        let data = ImageBuilder::default()
+            .version(1, 2, 3, 4)
+            .payload(&[0x5a; 1000])
+            .protected(0x50, &7u32.to_le_bytes())
+            .build();
+        let mut flash = styles::LPC_MAIN.build().unwrap();
+        flash.install(&data, 0).unwrap();
+        let flash = RefCell::new(flash);
+        let image = Image::from_flash(&flash).unwrap();
+        image.validate().unwrap();
+        assert_eq!(image.version().major, 1);
+        assert_eq!(image.version().build_num, 4);
+        assert_eq!(image.security_counter().unwrap(), 7);
+    }
 }
@@ -23,14 +23,26 @@
 //! - Paged: ERASE_SIZE is 512, WRITE_SIZE is 512.  The write size is much
 //!   larger than thye others, but the smaller erases allow us to treat the device
 //!   more like blocks.
+//!
+//! Devices that execute in place are read through a memory map, often with a
+//! cache or prefetch buffer in front of it that isn't updated by erases and
+//! writes.  `SimFlash::with_xip_cache` models this: the mapped view keeps
+//! returning what it last held until `invalidate` is called, as a board's
+//! driver must after each change.  With the `mapped` feature, the view is
+//! available to the boot code through `boot::MappedFlash`.
 
 use std::ops::Range;
+#[cfg(feature = "std")]
+use std::{cell::RefCell, fmt::Write};
 
 pub mod styles;
 pub mod gen;
+pub mod config;
+pub mod faulty;
+pub mod dualbank;
 
 use storage::{
-    Error, Flash, ReadFlash, Result,
+    Address, Error, Flash, Lock, ReadFlash, Result,
 };
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -44,10 +56,32 @@ pub struct SimFlash {
     read_size: usize,
     write_size: usize,
     erase_size: usize,
-    data: Vec<u8>,
-    page_state: Vec<PageState,>
+    /// The contents of each sector, only held once it has been written, so
+    /// that large devices can be simulated.
+    data: Vec<Option<Box<[u8]>>>,
+    page_state: Vec<PageState,>,
+    /// Writes and erases are refused, as a locked controller would.
+    locked: bool,
+    /// Called after each change to the device.
+    hooks: Vec<Hook>,
+    /// The contents as seen through the memory map, if modeled, as of the
+    /// last `invalidate`.
+    xip: Option<Box<[u8]>>,
+    /// The device has changed since the last `invalidate`.
+    stale: bool,
+}
+
+/// A change made to a device.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Event {
+    Erase(Range<Address>),
+    Write(Range<Address>),
 }
 
+/// A function called after each change to a device, given the device as it
+/// now is, such as to check invariants that must hold at every step.
+pub type Hook = Box<dyn FnMut(&Event, &SimFlash)>;
+
 impl SimFlash {
     // Some terminology:
     // - Page - the unit written
@@ -67,18 +101,114 @@ impl SimFlash {
         let pages_per_sector = erase_size / write_size;
 
         let page_state = vec![PageState::Unknown; sectors * pages_per_sector];
-        let data = vec![0xff; sectors * erase_size];
-        Ok(SimFlash {read_size, write_size, erase_size, data, page_state})
+        let data = vec![None; sectors];
+        Ok(SimFlash {read_size, write_size, erase_size, data, page_state, locked: false, hooks: vec![],
+                     xip: None, stale: false})
+    }
+
+    /// Model a memory-mapped view of the device behind a cache, holding the
+    /// current contents.  Reads through it, with `mapped` or the address from
+    /// `mapped_base`, return stale data after an erase or write, until
+    /// `invalidate` is called.  The view is as large as the device.
+    pub fn with_xip_cache(mut self) -> Self {
+        self.xip = Some(vec![0xff; self.capacity()].into_boxed_slice());
+        self.invalidate();
+        self
+    }
+
+    /// Discard what the cache holds, so mapped reads see the device as it now
+    /// is.  Pages that aren't written read as erased.
+    pub fn invalidate(&mut self) {
+        if let Some(mut xip) = self.xip.take() {
+            xip.copy_from_slice(&self.dump());
+            self.xip = Some(xip);
+        }
+        self.stale = false;
+    }
+
+    /// Has the device changed since the mapped view was last invalidated?
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// The device as seen through the memory map.
+    ///
+    /// # Panics
+    ///
+    /// If the device wasn't built `with_xip_cache`.
+    pub fn mapped(&self) -> &[u8] {
+        self.xip.as_deref().expect("SimFlash has no XIP cache")
+    }
+
+    /// The address the device is mapped at, for code that reads it through
+    /// pointers, as the boot code does.  This stays the same for the life of
+    /// the device.
+    ///
+    /// # Panics
+    ///
+    /// If the device wasn't built `with_xip_cache`.
+    pub fn mapped_base(&self) -> usize {
+        self.mapped().as_ptr() as usize
+    }
+
+    /// Copy out the contents at `offset`.  Sectors not written read as 0xff.
+    fn copy_out(&self, offset: Address, bytes: &mut [u8]) {
+        let erase_size = self.erase_size as Address;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let at = offset + pos as Address;
+            let within = (at % erase_size) as usize;
+            let count = (self.erase_size - within).min(bytes.len() - pos);
+            let out = &mut bytes[pos..pos + count];
+            match &self.data[(at / erase_size) as usize] {
+                Some(sector) => out.copy_from_slice(&sector[within..within + count]),
+                None => out.fill(0xff),
+            }
+            pos += count;
+        }
+    }
+
+    /// Store contents at `offset`.
+    fn copy_in(&mut self, offset: Address, bytes: &[u8]) {
+        let erase_size = self.erase_size;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let at = offset + pos as Address;
+            let within = (at % erase_size as Address) as usize;
+            let count = (erase_size - within).min(bytes.len() - pos);
+            let sector = self.data[(at / erase_size as Address) as usize]
+                .get_or_insert_with(|| vec![0xff; erase_size].into_boxed_slice());
+            sector[within..within + count].copy_from_slice(&bytes[pos..pos + count]);
+            pos += count;
+        }
+    }
+
+    /// Add a hook, called after each erase and write that succeeds.
+    pub fn add_hook(&mut self, hook: Hook) {
+        self.hooks.push(hook);
+    }
+
+    /// Run the hooks on a change.  They are taken out while they run, as they
+    /// are given the device.
+    fn run_hooks(&mut self, event: Event) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let mut hooks = std::mem::take(&mut self.hooks);
+        for hook in &mut hooks {
+            hook(&event, self);
+        }
+        self.hooks = hooks;
     }
 
     /// Given a byte value, return what page contains that byte.
-    fn page_of(&self, offset: usize) -> usize {
-        offset / self.write_size
+    fn page_of(&self, offset: Address) -> usize {
+        (offset / self.write_size as Address) as usize
     }
 
     /// Given a 'from' and 'to' value in bytes (a range), return a range over
     /// the page affected.
-    fn pages(&self, from: usize, to: usize) -> Range<usize> {
+    fn pages(&self, from: Address, to: Address) -> Range<usize> {
         self.page_of(from) .. self.page_of(to - 1) + 1
     }
 
@@ -109,30 +239,194 @@ impl SimFlash {
         }
         Ok(())
     }
+
+    /// Load the contents of a flash dump.  Pages that are all 0xff are taken
+    /// to be erased, and the rest to be written.  A dump shorter than the
+    /// device leaves the rest erased.
+    pub fn load(&mut self, dump: &[u8]) -> Result<()> {
+        if dump.len() > self.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        self.data.fill(None);
+        self.copy_in(0, dump);
+        let mut bytes = vec![0u8; self.write_size];
+        for page in 0..self.page_state.len() {
+            self.copy_out((page * self.write_size) as Address, &mut bytes);
+            self.page_state[page] = if bytes.iter().all(|&b| b == 0xff) {
+                PageState::Erased
+            } else {
+                PageState::Written
+            };
+        }
+        Ok(())
+    }
+
+    /// The contents of the device, as a dump of a real device would show them,
+    /// with pages that aren't written reading as erased.
+    pub fn dump(&self) -> Vec<u8> {
+        let mut out = vec![0xff; self.capacity()];
+        self.copy_out(0, &mut out);
+        for (page, state) in self.page_state.iter().enumerate() {
+            if *state != PageState::Written {
+                out[page * self.write_size .. (page + 1) * self.write_size].fill(0xff);
+            }
+        }
+        out
+    }
+
+    /// Chart the state of each page: a row for each sector, starting with its
+    /// offset, and a character for each page, 'E' if erased, 'W' if written,
+    /// and '?' if its state is unknown.  Sectors of more than `CHART_WIDTH`
+    /// pages have each character cover several, shown as '*' if they differ.
+    /// A line is drawn above each of the `marks`, such as slot boundaries,
+    /// naming it.
+    #[cfg(feature = "std")]
+    pub fn chart(&self, marks: &[(usize, &str)]) -> String {
+        let per_sector = self.pages_per_sector();
+        let per_char = per_sector.div_ceil(CHART_WIDTH);
+        let mut out = String::new();
+        for (sector, pages) in self.page_state.chunks(per_sector).enumerate() {
+            let base = sector * self.erase_size;
+            for (_, name) in marks.iter().filter(|(at, _)| (base..base + self.erase_size).contains(at)) {
+                writeln!(out, "---- {}", name).unwrap();
+            }
+            write!(out, "{:08x} ", base).unwrap();
+            for group in pages.chunks(per_char) {
+                let ch = match group[0] {
+                    _ if group.iter().any(|&state| state != group[0]) => '*',
+                    PageState::Erased => 'E',
+                    PageState::Written => 'W',
+                    PageState::Unknown => '?',
+                };
+                out.push(ch);
+            }
+            out.push('\n');
+        }
+        out
+    }
 }
 
-impl ReadFlash for SimFlash {
+/// Widest a row of `SimFlash::chart` gets.
+#[cfg(feature = "std")]
+pub const CHART_WIDTH: usize = 64;
+
+/// Prints charts of the given devices if dropped while panicking, such as
+/// when an assertion in a test fails, to show what state the flash was left
+/// in.
+#[cfg(feature = "std")]
+pub struct ChartOnPanic<'a> {
+    flashes: Vec<(&'a str, &'a RefCell<SimFlash>)>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> ChartOnPanic<'a> {
+    pub fn new(flashes: &[(&'a str, &'a RefCell<SimFlash>)]) -> ChartOnPanic<'a> {
+        ChartOnPanic { flashes: flashes.to_vec() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for ChartOnPanic<'_> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+        for (name, flash) in &self.flashes {
+            // The panic may have happened with the device borrowed.
+            match flash.try_borrow() {
+                Ok(flash) => eprint!("{}", flash.chart(&[(0, name)])),
+                Err(_) => eprintln!("---- {} (in use)", name),
+            }
+        }
+    }
+}
+
+impl storage::LargeFlash for SimFlash {
     fn read_size(&self) -> usize {
         self.read_size
     }
 
-    fn capacity(&self) -> usize {
-        self.data.len()
+    fn write_size(&self) -> usize {
+        self.write_size
     }
 
-    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
-        storage::check_read(self, offset, bytes.len())?;
-        let offset = offset as usize;
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
 
-        for i in self.pages(offset, offset + bytes.len()) {
+    fn capacity(&self) -> Address {
+        self.data.len() as Address * self.erase_size as Address
+    }
+
+    fn read(&mut self, offset: Address, bytes: &mut [u8]) -> Result<()> {
+        storage::check_large_read(self, offset, bytes.len())?;
+
+        for i in self.pages(offset, offset + bytes.len() as Address) {
             if self.page_state[i] != PageState::Written {
                 return Err(Error::NotWritten);
             }
         }
 
-        bytes.copy_from_slice(&self.data[offset .. offset + bytes.len()]);
+        self.copy_out(offset, bytes);
         Ok(())
     }
+
+    fn erase(&mut self, from: Address, to: Address) -> Result<()> {
+        storage::check_large_erase(self, from, to)?;
+        if self.locked {
+            return Err(Error::Failed);
+        }
+
+        for i in self.pages(from, to) {
+            self.page_state[i] = PageState::Erased;
+        }
+        let erase_size = self.erase_size as Address;
+        self.data[(from / erase_size) as usize .. (to / erase_size) as usize].fill(None);
+        self.stale = true;
+        self.run_hooks(Event::Erase(from..to));
+        Ok(())
+    }
+
+    fn write(&mut self, offset: Address, bytes: &[u8]) -> Result<()> {
+        storage::check_large_write(self, offset, bytes.len())?;
+        if self.locked {
+            return Err(Error::Failed);
+        }
+        let end = offset + bytes.len() as Address;
+
+        for i in self.pages(offset, end) {
+            if self.page_state[i] != PageState::Erased {
+                return Err(Error::NotErased);
+            }
+        }
+
+        for i in self.pages(offset, end) {
+            self.page_state[i] = PageState::Written;
+        }
+
+        self.copy_in(offset, bytes);
+        self.stale = true;
+        self.run_hooks(Event::Write(offset..end));
+        Ok(())
+    }
+}
+
+/// The `Flash` view reaches as much of the device as `usize` does, which is
+/// all of it on the host.  Past that, `LargeFlash` is needed.
+impl ReadFlash for SimFlash {
+    fn read_size(&self) -> usize {
+        self.read_size
+    }
+
+    fn capacity(&self) -> usize {
+        let reach = usize::MAX - usize::MAX % self.erase_size;
+        usize::try_from(storage::LargeFlash::capacity(self)).map_or(reach, |capacity| capacity.min(reach))
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        storage::check_read(self, offset, bytes.len())?;
+        storage::LargeFlash::read(self, offset as Address, bytes)
+    }
 }
 
 impl Flash for SimFlash {
@@ -146,29 +440,29 @@ impl Flash for SimFlash {
 
     fn erase(&mut self, from: usize, to: usize) -> Result<()> {
         storage::check_erase(self, from, to)?;
-
-        for i in self.pages(from as usize, to as usize) {
-            self.page_state[i] = PageState::Erased;
-        }
-        Ok(())
+        storage::LargeFlash::erase(self, from as Address, to as Address)
     }
 
     fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
         storage::check_write(self, offset, bytes.len())?;
-        let offset = offset as usize;
+        storage::LargeFlash::write(self, offset as Address, bytes)
+    }
+}
 
-        for i in self.pages(offset, offset + bytes.len()) {
-            if self.page_state[i] != PageState::Erased {
-                return Err(Error::NotErased);
-            }
-        }
+#[cfg(feature = "mapped")]
+impl boot::MappedFlash for SimFlash {
+    fn get_base(&self) -> usize {
+        self.mapped_base()
+    }
+}
 
-        for i in self.pages(offset, offset + bytes.len()) {
-            self.page_state[i] = PageState::Written;
-        }
+impl Lock for SimFlash {
+    fn lock(&mut self) {
+        self.locked = true;
+    }
 
-        self.data[offset .. offset + bytes.len()].copy_from_slice(bytes);
-        Ok(())
+    fn unlock(&mut self) {
+        self.locked = false;
     }
 }
 
@@ -184,3 +478,132 @@ fn test_simflash() {
     buf.fill(0x42);
     assert_eq!(f1.read(128*1024, &mut buf), Ok(()));
 }
+
+#[test]
+fn test_dump() {
+    let mut f1 = SimFlash::new(1, 8, 4096, 2).unwrap();
+    let mut dump = vec![0xffu8; 4096 + 100];
+    dump[10] = 0x42;
+    dump[4096 + 64] = 0;
+    f1.load(&dump).unwrap();
+
+    // Written pages read back, erased ones can be written.
+    let mut buf = [0u8; 8];
+    assert_eq!(f1.read(8, &mut buf), Ok(()));
+    assert_eq!(buf[2], 0x42);
+    assert_eq!(f1.read(0, &mut buf), Err(Error::NotWritten));
+    assert_eq!(f1.write(0, &buf), Ok(()));
+    assert_eq!(f1.write(4096 + 64, &buf), Err(Error::NotErased));
+
+    // Erasing clears what was loaded.
+    f1.erase(4096, 8192).unwrap();
+    let out = f1.dump();
+    assert_eq!(out.len(), 8192);
+    assert_eq!(out[10], 0x42);
+    assert!(out[4096..].iter().all(|&b| b == 0xff));
+    assert!(f1.load(&[0; 8193]).is_err());
+}
+
+#[test]
+fn test_lock() {
+    let mut f1 = storage::LockedFlash::new(SimFlash::new(1, 8, 4096, 2).unwrap());
+    let mut buf = [0x42u8; 8];
+    {
+        let mut unlocked = f1.unlock();
+        unlocked.erase(0, 4096).unwrap();
+        unlocked.write(0, &buf).unwrap();
+    }
+
+    // Reads work while locked, and the device is locked again.
+    assert_eq!(f1.read(0, &mut buf), Ok(()));
+    let mut f1 = f1.into_inner();
+    assert_eq!(f1.write(8, &buf), Err(Error::Failed));
+    assert_eq!(f1.erase(0, 4096), Err(Error::Failed));
+}
+
+#[test]
+fn test_xip_cache() {
+    let mut f1 = SimFlash::new(1, 8, 4096, 2).unwrap().with_xip_cache();
+    assert!(f1.mapped().iter().all(|&b| b == 0xff));
+    let base = f1.mapped_base();
+
+    // Changes aren't seen through the map until it is invalidated.
+    f1.erase(0, 4096).unwrap();
+    f1.write(0, &[0x42; 8]).unwrap();
+    assert!(f1.is_stale());
+    assert_eq!(f1.mapped()[0], 0xff);
+    f1.invalidate();
+    assert!(!f1.is_stale());
+    assert_eq!(f1.mapped()[..8], [0x42; 8]);
+    assert_eq!(unsafe { *(base as *const u8) }, 0x42);
+
+    f1.erase(0, 4096).unwrap();
+    assert_eq!(f1.mapped()[0], 0x42);
+    f1.invalidate();
+    assert_eq!(f1.mapped_base(), base);
+    assert_eq!(f1.mapped()[0], 0xff);
+}
+
+#[test]
+fn test_chart() {
+    let mut f1 = SimFlash::new(1, 512, 512, 3).unwrap();
+    f1.erase(0, 1024).unwrap();
+    f1.write(512, &[0; 512]).unwrap();
+    assert_eq!(f1.chart(&[(0, "main"), (1024, "end")]),
+               "---- main\n00000000 E\n00000200 W\n---- end\n00000400 ?\n");
+
+    // Several pages to each character.
+    let mut f1 = SimFlash::new(1, 8, 4096, 1).unwrap();
+    f1.erase(0, 4096).unwrap();
+    f1.write(0, &[0; 64]).unwrap();
+    f1.write(4096 - 8, &[0; 8]).unwrap();
+    assert_eq!(f1.chart(&[]), format!("00000000 W{}*\n", "E".repeat(62)));
+}
+
+#[test]
+fn test_large() {
+    use storage::Partition;
+
+    // A 6GB device, with a slot past 4GB, reached through `LargeFlash` alone
+    // so that this holds where `usize` is 32 bits.
+    let mut dev = SimFlash::new(1, 512, 65536, 6 * 16384).unwrap();
+    assert_eq!(storage::LargeFlash::capacity(&dev), 6 << 30);
+    let base: Address = 5 << 30;
+    storage::LargeFlash::erase(&mut dev, base, base + 65536).unwrap();
+    storage::LargeFlash::write(&mut dev, base, &[0x42; 512]).unwrap();
+    assert_eq!(storage::LargeFlash::write(&mut dev, base, &[0x42; 512]), Err(Error::NotErased));
+    assert_eq!(storage::LargeFlash::erase(&mut dev, base, 7 << 30), Err(Error::OutOfBounds));
+
+    // Nothing was written at the address truncated to 32 bits.
+    let mut buf = [0u8; 512];
+    assert_eq!(storage::LargeFlash::read(&mut dev, base - (4 << 30), &mut buf), Err(Error::NotWritten));
+
+    let mut slot = Partition::new(dev, base, 4 * 65536).unwrap();
+    slot.read(0, &mut buf).unwrap();
+    assert_eq!(buf, [0x42; 512]);
+    assert_eq!(slot.read(65536, &mut buf), Err(Error::NotWritten));
+    slot.erase(3 * 65536, 4 * 65536).unwrap();
+    boot::request_upgrade(&mut slot).unwrap();
+    assert!(boot::upgrade_requested(&mut slot).unwrap());
+    assert_eq!(slot.erase(0, 5 * 65536), Err(Error::OutOfBounds));
+
+    let dev = slot.into_inner();
+    assert!(Partition::new(dev, 6 << 30, 65536).is_err());
+}
+
+#[test]
+fn test_hooks() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut f1 = SimFlash::new(1, 8, 4096, 2).unwrap();
+    let log = events.clone();
+    f1.add_hook(Box::new(move |event, flash| {
+        log.borrow_mut().push((event.clone(), flash.dump()[0]));
+    }));
+
+    f1.erase(0, 4096).unwrap();
+    f1.write(0, &[0x42; 8]).unwrap();
+    assert!(f1.write(0, &[0; 8]).is_err());
+    assert_eq!(*events.borrow(), [(Event::Erase(0..4096), 0xff), (Event::Write(0..8), 0x42)]);
+}
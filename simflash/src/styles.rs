@@ -2,10 +2,12 @@
 //!
 //! Various microcontrollers have various types of flash memories available to them.
 
+use crate::config;
 use crate::SimFlash;
 use crate::Result;
 
 /// The configuration of a single flash area.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct AreaLayout {
     pub read_size: usize,
     pub write_size: usize,
@@ -24,6 +26,16 @@ impl AreaLayout {
     }
 }
 
+/// A full set of areas for a single device, as loaded from a description file.
+/// See the `config` module for the format.
+#[derive(Debug, Clone)]
+pub struct SlotMap {
+    pub name: String,
+    pub main: AreaLayout,
+    pub upgrade: AreaLayout,
+    pub scratch: Option<AreaLayout>,
+}
+
 /// STM32F4-style.
 /// These devices have a fairly small number of relatively large sectors.  Note
 /// that if you wish to use MCUboot across an area where the sector sizes
@@ -43,6 +55,12 @@ pub static STM32F_UPGRADE: AreaLayout = AreaLayout {
     erase_size: 128*1024,
     sectors: 1,
 };
+pub static STM32F_SCRATCH: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 8,
+    erase_size: 128*1024,
+    sectors: 1,
+};
 
 /// K64-style.
 /// These devices have small uniform sectors.
@@ -58,6 +76,12 @@ pub static K64_UPGRADE: AreaLayout = AreaLayout {
     erase_size: 4*1024,
     sectors: 128/4 + 1,
 };
+pub static K64_SCRATCH: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 8,
+    erase_size: 4*1024,
+    sectors: 2,
+};
 
 /// External flash configuration.  The external partition is the same size, so
 /// the image needs to have room.  The external flash has a large write alignment.
@@ -73,6 +97,13 @@ pub static EXT_UPGRADE: AreaLayout = AreaLayout {
     erase_size: 4*1024,
     sectors: 128/4,
 };
+/// The scratch area lives in internal flash, alongside the main slot.
+pub static EXT_SCRATCH: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 4,
+    erase_size: 4*1024,
+    sectors: 2,
+};
 
 /// Page-style devices.  Based on the LPC55S69.
 pub static LPC_MAIN: AreaLayout = AreaLayout {
@@ -87,6 +118,12 @@ pub static LPC_UPGRADE: AreaLayout = AreaLayout {
     erase_size: 512,
     sectors: 128*2,
 };
+pub static LPC_SCRATCH: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 512,
+    erase_size: 512,
+    sectors: 8,
+};
 
 /// Another large write, based on the STM32H745
 pub static STM32H_MAIN: AreaLayout = AreaLayout {
@@ -101,6 +138,12 @@ pub static STM32H_UPGRADE: AreaLayout = AreaLayout {
     erase_size: 128*1024,
     sectors: 3,
 };
+pub static STM32H_SCRATCH: AreaLayout = AreaLayout {
+    read_size: 1,
+    write_size: 32,
+    erase_size: 128*1024,
+    sectors: 1,
+};
 
 /// All of the flash devices, as pairs.
 pub static ALL_FLASHES: [(&'static AreaLayout, &'static AreaLayout); 5] = [
@@ -111,17 +154,55 @@ pub static ALL_FLASHES: [(&'static AreaLayout, &'static AreaLayout); 5] = [
     (&STM32H_MAIN, &STM32H_UPGRADE),
 ];
 
-/// An iterator that returns each of the device pairs on each iteration.
+/// All of the flash devices, with a scratch area, as triples.
+pub static ALL_FLASHES_WITH_SCRATCH: [(&AreaLayout, &AreaLayout, &AreaLayout); 5] = [
+    (&STM32F_MAIN, &STM32F_UPGRADE, &STM32F_SCRATCH),
+    (&K64_MAIN, &K64_UPGRADE, &K64_SCRATCH),
+    (&EXT_MAIN, &EXT_UPGRADE, &EXT_SCRATCH),
+    (&LPC_MAIN, &LPC_UPGRADE, &LPC_SCRATCH),
+    (&STM32H_MAIN, &STM32H_UPGRADE, &STM32H_SCRATCH),
+];
+
+/// An iterator that returns each of the device pairs on each iteration.  This
+/// includes any devices described by the file named in `SIMFLASH_LAYOUTS`.
 pub fn all_flashes() -> impl Iterator<Item = Result<(SimFlash, SimFlash)>> {
+    let extra = extra_slot_maps();
     ALL_FLASHES.iter().map(|(a, b)| {
-        let a = match a.build() {
-            Ok(a) => a,
-            Err(e) => return Err(e),
-        };
-        let b = match b.build() {
-            Ok(b) => b,
-            Err(e) => return Err(e),
-        };
-        Ok((a, b))
-    })
+        Ok((a.build()?, b.build()?))
+    }).chain(extra.into_iter().map(|m| {
+        Ok((m.main.build()?, m.upgrade.build()?))
+    }))
+}
+
+/// An iterator that returns each of the device triples (main, upgrade,
+/// scratch) on each iteration.  Described devices that have a scratch area are
+/// included.
+pub fn all_flashes_with_scratch() -> impl Iterator<Item = Result<(SimFlash, SimFlash, SimFlash)>> {
+    let extra = extra_slot_maps();
+    ALL_FLASHES_WITH_SCRATCH.iter().map(|(a, b, c)| {
+        Ok((a.build()?, b.build()?, c.build()?))
+    }).chain(extra.into_iter().filter(|m| m.scratch.is_some()).map(|m| {
+        let scratch = m.scratch.as_ref().unwrap();
+        Ok((m.main.build()?, m.upgrade.build()?, scratch.build()?))
+    }))
+}
+
+/// Devices loaded from `SIMFLASH_LAYOUTS`.  This is only used by tests, so a
+/// bad description is fatal.
+fn extra_slot_maps() -> Vec<SlotMap> {
+    match config::env_slot_maps() {
+        Ok(maps) => maps,
+        Err(e) => panic!("Invalid {}: {}", config::LAYOUTS_ENV, e),
+    }
+}
+
+#[test]
+fn test_scratch_sizes() {
+    // The scratch area must be able to hold at least one sector of either slot.
+    for (main, upgrade, scratch) in ALL_FLASHES_WITH_SCRATCH.iter() {
+        let scratch_size = scratch.erase_size * scratch.sectors;
+        assert!(scratch_size >= main.erase_size);
+        assert!(scratch_size >= upgrade.erase_size);
+    }
+    assert_eq!(all_flashes_with_scratch().count(), ALL_FLASHES_WITH_SCRATCH.len());
 }
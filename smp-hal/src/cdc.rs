@@ -0,0 +1,78 @@
+//! SMP over USB CDC-ACM.
+//!
+//! The device must be polled for USB to make progress, so both reading and
+//! writing poll it while they wait.  The serial port must be created before
+//! the device is built:
+//!
+//!     let serial = usbd_serial::SerialPort::new(&bus);
+//!     let device = UsbDeviceBuilder::new(&bus, UsbVidPid(0x1209, 0x0001))
+//!         .device_class(usbd_serial::USB_CLASS_CDC)
+//!         .build();
+//!     let mut transport = smp_hal::UsbCdc::new(device, serial);
+
+use smp::transport::Transport;
+use usb_device::bus::UsbBus;
+use usb_device::device::UsbDevice;
+use usb_device::UsbError;
+use usbd_serial::SerialPort;
+
+/// Size of a full speed bulk packet.
+const PACKET: usize = 64;
+
+pub struct UsbCdc<'a, B: UsbBus> {
+    device: UsbDevice<'a, B>,
+    serial: SerialPort<'a, B>,
+    /// Bytes received, but not yet read.
+    buf: [u8; PACKET],
+    pos: usize,
+    len: usize,
+}
+
+impl<'a, B: UsbBus> UsbCdc<'a, B> {
+    pub fn new(device: UsbDevice<'a, B>, serial: SerialPort<'a, B>) -> UsbCdc<'a, B> {
+        UsbCdc { device, serial, buf: [0; PACKET], pos: 0, len: 0 }
+    }
+
+    fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.serial]);
+    }
+}
+
+impl<'a, B: UsbBus> Transport for UsbCdc<'a, B> {
+    fn read(&mut self) -> Option<u8> {
+        while self.pos == self.len {
+            self.poll();
+            match self.serial.read(&mut self.buf) {
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                }
+                Err(UsbError::WouldBlock) => (),
+                Err(_) => return None,
+            }
+        }
+        let byte = self.buf[self.pos];
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // As with a UART, errors are left for the host to time out on.
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            self.poll();
+            match self.serial.write(bytes) {
+                Ok(len) => bytes = &bytes[len..],
+                Err(UsbError::WouldBlock) => (),
+                Err(_) => return,
+            }
+        }
+        loop {
+            self.poll();
+            match self.serial.flush() {
+                Err(UsbError::WouldBlock) => (),
+                _ => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! SMP transports for boards
+//!
+//! The `smp` crate speaks SMP over any `Transport`.  This crate provides the
+//! transports for the usual peripherals, so a board only has to set up the
+//! peripheral and hand it over:
+//!
+//! - `Uart`, over an embedded-hal serial port.
+//! - `UsbCdc`, over a usb-device CDC-ACM serial port.
+
+#![no_std]
+
+#[cfg(feature = "uart")]
+mod uart;
+#[cfg(feature = "usb")]
+mod cdc;
+
+#[cfg(feature = "uart")]
+pub use uart::Uart;
+#[cfg(feature = "usb")]
+pub use cdc::UsbCdc;
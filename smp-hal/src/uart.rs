@@ -0,0 +1,33 @@
+//! SMP over a UART.
+
+use embedded_hal::serial::{Read, Write};
+use smp::transport::Transport;
+
+/// Adapts an embedded-hal serial port.
+pub struct Uart<S>(S);
+
+impl<S> Uart<S> {
+    pub fn new(serial: S) -> Uart<S> {
+        Uart(serial)
+    }
+
+    /// Recover the serial port.
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+
+impl<S: Read<u8> + Write<u8>> Transport for Uart<S> {
+    fn read(&mut self) -> Option<u8> {
+        nb::block!(self.0.read()).ok()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // There is nothing useful to do with a transmit error, the host will
+        // time out and retry.
+        for &byte in bytes {
+            let _ = nb::block!(self.0.write(byte));
+        }
+        let _ = nb::block!(self.0.flush());
+    }
+}
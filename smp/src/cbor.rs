@@ -0,0 +1,273 @@
+//! Just enough CBOR for SMP
+//!
+//! SMP payloads are CBOR maps with text keys, holding integers, booleans,
+//! text and byte strings, and (in responses) arrays of maps.  The encoder
+//! writes definite lengths only.  The decoder also accepts indefinite length
+//! maps and arrays, which some clients send, and can skip over values it
+//! doesn't care about.
+
+/// Errors from encoding or decoding.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// The output buffer is full, or the input ended early.
+    Short,
+    /// The input is not CBOR this decoder understands.
+    Invalid,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// Major types.
+const UINT: u8 = 0;
+const NINT: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const BREAK: u8 = 0xff;
+
+/// Additional info for an indefinite length.
+const INDEFINITE: u8 = 31;
+
+/// Writes CBOR into a buffer.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Encoder<'a> {
+        Encoder { buf, pos: 0 }
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        let end = self.pos.checked_add(bytes.len()).ok_or(Error::Short)?;
+        if end > self.buf.len() {
+            return Err(Error::Short);
+        }
+        self.buf[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn head(&mut self, major: u8, value: u64) -> Result<()> {
+        let major = major << 5;
+        if value < 24 {
+            self.put(&[major | value as u8])
+        } else if value <= u8::MAX as u64 {
+            self.put(&[major | 24, value as u8])
+        } else if value <= u16::MAX as u64 {
+            self.put(&[major | 25])?;
+            self.put(&(value as u16).to_be_bytes())
+        } else if value <= u32::MAX as u64 {
+            self.put(&[major | 26])?;
+            self.put(&(value as u32).to_be_bytes())
+        } else {
+            self.put(&[major | 27])?;
+            self.put(&value.to_be_bytes())
+        }
+    }
+
+    pub fn uint(&mut self, value: u64) -> Result<()> {
+        self.head(UINT, value)
+    }
+
+    pub fn int(&mut self, value: i64) -> Result<()> {
+        if value < 0 {
+            self.head(NINT, !value as u64)
+        } else {
+            self.head(UINT, value as u64)
+        }
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<()> {
+        self.put(&[if value { TRUE } else { FALSE }])
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> Result<()> {
+        self.head(BYTES, value.len() as u64)?;
+        self.put(value)
+    }
+
+    pub fn text(&mut self, value: &str) -> Result<()> {
+        self.head(TEXT, value.len() as u64)?;
+        self.put(value.as_bytes())
+    }
+
+    /// Start an array of `len` items, which follow.
+    pub fn array(&mut self, len: usize) -> Result<()> {
+        self.head(ARRAY, len as u64)
+    }
+
+    /// Start a map of `len` pairs, which follow.
+    pub fn map(&mut self, len: usize) -> Result<()> {
+        self.head(MAP, len as u64)
+    }
+}
+
+/// A single decoded item.  Arrays and maps only give their length, and their
+/// contents follow as further items.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Item<'a> {
+    Uint(u64),
+    /// A negative integer, as its CBOR encoding of -1 - n.
+    Nint(u64),
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Bool(bool),
+    Null,
+    /// An array, with a length, or None if it is ended by a break.
+    Array(Option<usize>),
+    Map(Option<usize>),
+    /// The end of an indefinite length array or map.
+    Break,
+}
+
+/// Reads CBOR from a buffer.
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::Short)?;
+        if end > self.buf.len() {
+            return Err(Error::Short);
+        }
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Read the argument following an initial byte.
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        Ok(match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => {
+                let b = self.take(2)?;
+                u16::from_be_bytes([b[0], b[1]]) as u64
+            }
+            26 => {
+                let b = self.take(4)?;
+                u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64
+            }
+            27 => {
+                let b = self.take(8)?;
+                let mut value = [0u8; 8];
+                value.copy_from_slice(b);
+                u64::from_be_bytes(value)
+            }
+            _ => return Err(Error::Invalid),
+        })
+    }
+
+    /// Read the next item.
+    pub fn item(&mut self) -> Result<Item<'a>> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+        match major {
+            UINT => Ok(Item::Uint(self.argument(info)?)),
+            NINT => Ok(Item::Nint(self.argument(info)?)),
+            BYTES | TEXT => {
+                let len = self.argument(info)?;
+                let bytes = self.take(usize::try_from(len).map_err(|_| Error::Short)?)?;
+                if major == BYTES {
+                    Ok(Item::Bytes(bytes))
+                } else {
+                    core::str::from_utf8(bytes).map(Item::Text).map_err(|_| Error::Invalid)
+                }
+            }
+            ARRAY | MAP => {
+                let len = if info == INDEFINITE {
+                    None
+                } else {
+                    Some(usize::try_from(self.argument(info)?).map_err(|_| Error::Short)?)
+                };
+                Ok(if major == ARRAY { Item::Array(len) } else { Item::Map(len) })
+            }
+            SIMPLE => match initial {
+                FALSE => Ok(Item::Bool(false)),
+                TRUE => Ok(Item::Bool(true)),
+                NULL => Ok(Item::Null),
+                BREAK => Ok(Item::Break),
+                _ => Err(Error::Invalid),
+            },
+            // Tags, which SMP doesn't use.
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    /// Skip the rest of a value whose first item has been read.
+    pub fn skip(&mut self, item: Item<'a>) -> Result<()> {
+        let (len, per) = match item {
+            Item::Array(len) => (len, 1),
+            Item::Map(len) => (len, 2),
+            Item::Break => return Err(Error::Invalid),
+            _ => return Ok(()),
+        };
+        match len {
+            Some(len) => {
+                for _ in 0..len * per {
+                    let item = self.item()?;
+                    self.skip(item)?;
+                }
+            }
+            None => loop {
+                let item = self.item()?;
+                if item == Item::Break {
+                    break;
+                }
+                self.skip(item)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Visit each entry of a map with text keys, which must be the whole of
+    /// the input.  The visitor is given the key, and the first item of the
+    /// value.  Values it doesn't want must be passed back to `skip`.
+    pub fn map<V>(&mut self, mut visit: V) -> Result<()>
+        where V: FnMut(&mut Decoder<'a>, &'a str, Item<'a>) -> Result<()>,
+    {
+        let len = match self.item()? {
+            Item::Map(len) => len,
+            _ => return Err(Error::Invalid),
+        };
+        let mut count = 0;
+        loop {
+            if Some(count) == len {
+                break;
+            }
+            let key = match self.item()? {
+                Item::Break if len.is_none() => break,
+                Item::Text(key) => key,
+                _ => return Err(Error::Invalid),
+            };
+            let value = self.item()?;
+            visit(self, key, value)?;
+            count += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,432 @@
+//! mcumgr SMP server
+//!
+//! This implements enough of the Simple Management Protocol for the standard
+//! mcumgr tools to manage the images: list the images, upload a new one, mark
+//! it for test or confirm it, and reset.  It knows nothing about how packets
+//! arrive.  The transport (serial, USB, BLE) passes each request packet to
+//! `Smp::handle`, and sends back the response it builds.
+//!
+//! Each packet has an eight byte header, followed by a CBOR map:
+//!
+//! +-----+-------+--------+----------+-----+----+
+//! | op  | flags | len:16 | group:16 | seq | id |
+//! +-----+-------+--------+----------+-----+----+
+//!
+//! Multi-byte header fields are big endian.  The length is that of the CBOR
+//! payload.
+//!
+//! `transport` carries the packets over a serial byte stream.
+//!
+//! The image list is the boot crate's `list_slots`, so the slots are read
+//! here just as the bootloader reads them.
+//!
+//! Uploads go to the upgrade slot, through a `BufferedFlash`, so the chunks
+//! can be any size.  The image is not validated here; that happens at boot,
+//! once it has been marked for test or confirmed.  If the bootloader rejects
+//! it, the image state response carries the reason, as a "bootError" map with
+//! the error code, one of the stable codes of `boot::ErrorCode`, and offset.
+//!
+//! A device given a key with `with_wipe` also answers the basic group's erase
+//! command, which erases both slots, trailers included, to decommission a
+//! device or get it out of a status the state machine can't make sense of.
+//! The bootloader itself is not in either slot, so is left alone.  As this
+//! destroys the images, it must be authenticated.  A read of the command
+//! gives a "nonce", and the write that follows must carry, as "sig", an ECDSA
+//! P-256 signature of the SHA-256 of `WIPE_CONTEXT` followed by that nonce.
+//! Each nonce is good for one attempt.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+pub mod cbor;
+pub mod transport;
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use boot::{
+    confirm, last_boot_error, list_slots, request_upgrade, upgrade_requested, CryptoBackend, EcdsaP256,
+    EntropyError, EntropySource, FlashCell, Hash256, Listing, SignatureScheme, SlotSummary, SoftCrypto,
+};
+use cbor::{Decoder, Encoder, Item};
+use storage::{BufferedFlash, Flash, ReadFlash};
+
+/// Size of the packet header.
+pub const HEADER_SIZE: usize = 8;
+
+// Operations.
+pub const OP_READ: u8 = 0;
+pub const OP_READ_RSP: u8 = 1;
+pub const OP_WRITE: u8 = 2;
+pub const OP_WRITE_RSP: u8 = 3;
+
+// Groups, and the commands within them.
+pub const GROUP_OS: u16 = 0;
+pub const GROUP_IMAGE: u16 = 1;
+pub const GROUP_BASIC: u16 = 63;
+pub const ID_OS_RESET: u8 = 5;
+pub const ID_IMAGE_STATE: u8 = 0;
+pub const ID_IMAGE_UPLOAD: u8 = 1;
+pub const ID_BASIC_ERASE: u8 = 0;
+
+// Result codes.
+pub const RC_OK: u32 = 0;
+pub const RC_UNKNOWN: u32 = 1;
+pub const RC_NO_MEMORY: u32 = 2;
+pub const RC_INVALID: u32 = 3;
+pub const RC_NOT_FOUND: u32 = 5;
+pub const RC_BAD_STATE: u32 = 6;
+pub const RC_NOT_SUPPORTED: u32 = 8;
+pub const RC_ACCESS_DENIED: u32 = 11;
+
+/// What a wipe signature covers, ahead of the nonce, so that a signature made
+/// for anything else can't be used.
+pub const WIPE_CONTEXT: &[u8] = b"mcuboot wipe\0";
+
+/// The result of handling a request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Handled {
+    /// Length of the response packet.  Zero if there is nothing to send.
+    pub len: usize,
+    /// A reset was requested.  The transport should send the response, then
+    /// reset the device.
+    pub reset: bool,
+}
+
+/// An upload in progress.
+struct Upload {
+    /// Total size of the image.
+    len: usize,
+    /// Offset of the next chunk expected.
+    next: usize,
+}
+
+/// The key and challenge state for authenticating wipes.
+struct Wipe {
+    /// The key, as a DER SubjectPublicKeyInfo.
+    key: &'static [u8],
+    seed: Hash256,
+    /// Nonces given out, each the hash of the seed and this count.
+    count: u32,
+    /// The nonce awaiting a signed erase.
+    nonce: Option<Hash256>,
+}
+
+/// The SMP server, managing a primary and an upgrade slot.
+pub struct Smp<F> {
+    primary: RefCell<F>,
+    secondary: RefCell<BufferedFlash<F>>,
+    upload: Option<Upload>,
+    wipe: Option<Wipe>,
+}
+
+/// The reply to a request, or the code to reply with instead.
+type Reply = core::result::Result<(), u32>;
+
+impl<F: Flash> Smp<F> {
+    pub fn new(primary: F, secondary: F) -> storage::Result<Smp<F>> {
+        Ok(Smp {
+            primary: RefCell::new(primary),
+            secondary: RefCell::new(BufferedFlash::new(secondary)?),
+            upload: None,
+            wipe: None,
+        })
+    }
+
+    /// Allow the slots to be wiped, with erase commands signed by `key` (a DER
+    /// SubjectPublicKeyInfo, as `imgtool getpub` gives).  The nonces are
+    /// derived from `seed`, which must be different on every boot, such as
+    /// from a hardware random number generator, or an old signature could be
+    /// replayed.
+    pub fn with_wipe(mut self, key: &'static [u8], seed: Hash256) -> Smp<F> {
+        self.wipe = Some(Wipe { key, seed, count: 0, nonce: None });
+        self
+    }
+
+    /// Allow the slots to be wiped, as `with_wipe`, with the seed drawn from
+    /// `entropy`, such as the board's TRNG.
+    pub fn with_wipe_from<E: EntropySource + ?Sized>(self, key: &'static [u8], entropy: &mut E)
+        -> core::result::Result<Smp<F>, EntropyError>
+    {
+        let mut seed = [0u8; 32];
+        entropy.fill(&mut seed)?;
+        Ok(self.with_wipe(key, seed))
+    }
+
+    /// Recover the slots.
+    pub fn into_inner(self) -> storage::Result<(F, F)> {
+        Ok((self.primary.into_inner(), self.secondary.into_inner().finish()?))
+    }
+
+    /// Handle a single request packet, building the response packet.
+    /// Packets that aren't requests, or are malformed, get no response.
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8]) -> Handled {
+        let none = Handled { len: 0, reset: false };
+        if request.len() < HEADER_SIZE || response.len() < HEADER_SIZE {
+            return none;
+        }
+        let op = request[0] & 0x07;
+        let len = u16::from_be_bytes([request[2], request[3]]) as usize;
+        let group = u16::from_be_bytes([request[4], request[5]]);
+        let id = request[7];
+        if (op != OP_READ && op != OP_WRITE) || request.len() != HEADER_SIZE + len {
+            return none;
+        }
+        let payload = &request[HEADER_SIZE..];
+
+        let (head, body) = response.split_at_mut(HEADER_SIZE);
+        head.copy_from_slice(&request[..HEADER_SIZE]);
+        head[0] = (request[0] & !0x07) | (op + 1);
+
+        let mut reset = false;
+        let mut enc = Encoder::new(body);
+        let reply = match (group, id, op) {
+            (GROUP_OS, ID_OS_RESET, OP_WRITE) => {
+                reset = true;
+                enc.map(0).map_err(|_| RC_NO_MEMORY)
+            }
+            (GROUP_IMAGE, ID_IMAGE_STATE, OP_READ) => self.state(&mut enc),
+            (GROUP_IMAGE, ID_IMAGE_STATE, OP_WRITE) => {
+                self.set_state(payload).and_then(|()| self.state(&mut enc))
+            }
+            (GROUP_IMAGE, ID_IMAGE_UPLOAD, OP_WRITE) => self.upload(payload, &mut enc),
+            (GROUP_BASIC, ID_BASIC_ERASE, OP_READ) => self.wipe_nonce(&mut enc),
+            (GROUP_BASIC, ID_BASIC_ERASE, OP_WRITE) => {
+                self.wipe(payload).and_then(|()| enc.map(0).map_err(|_| RC_NO_MEMORY))
+            }
+            _ => Err(RC_NOT_SUPPORTED),
+        };
+        if let Err(rc) = reply {
+            enc = Encoder::new(&mut response[HEADER_SIZE..]);
+            if enc.map(1).and_then(|()| enc.text("rc")).and_then(|()| enc.uint(rc as u64)).is_err() {
+                return none;
+            }
+        }
+
+        let body_len = enc.len();
+        response[2..4].copy_from_slice(&(body_len as u16).to_be_bytes());
+        Handled { len: HEADER_SIZE + body_len, reset }
+    }
+
+    /// List the images, and their state.
+    fn state(&mut self, enc: &mut Encoder) -> Reply {
+        let listing = self.listing()?;
+        let boot_error = last_boot_error(self.secondary.borrow_mut().get_mut()).map_err(|_| RC_UNKNOWN)?;
+
+        let mut encode = || -> cbor::Result<()> {
+            enc.map(if boot_error.is_some() { 3 } else { 2 })?;
+            enc.text("images")?;
+            enc.array(listing.images().count())?;
+            for slot in listing.images() {
+                encode_slot(slot, enc)?;
+            }
+            enc.text("splitStatus")?;
+            enc.uint(0)?;
+            if let Some(err) = boot_error {
+                enc.text("bootError")?;
+                enc.map(2)?;
+                enc.text("rc")?;
+                enc.uint(err.code as u64)?;
+                enc.text("off")?;
+                enc.uint(err.offset as u64)?;
+            }
+            Ok(())
+        };
+        encode().map_err(|_| RC_NO_MEMORY)
+    }
+
+    /// Describe the slots.
+    fn listing(&self) -> core::result::Result<Listing, u32> {
+        list_slots(&self.primary, &Unbuffered(&self.secondary)).map_err(|_| RC_UNKNOWN)
+    }
+
+    /// Mark an image for test, or confirm it.
+    fn set_state(&mut self, payload: &[u8]) -> Reply {
+        let mut hash = None;
+        let mut confirm_image = false;
+        Decoder::new(payload).map(|dec, key, value| {
+            match (key, value) {
+                ("hash", Item::Bytes(value)) => hash = Some(value),
+                ("confirm", Item::Bool(value)) => confirm_image = value,
+                (_, value) => dec.skip(value)?,
+            }
+            Ok(())
+        }).map_err(|_| RC_INVALID)?;
+
+        let listing = self.listing()?;
+        let [primary, secondary] = listing.slots.map(|slot| slot.present().then_some(slot.hash));
+
+        match hash {
+            // Without a hash, only the running image can be confirmed.
+            None if confirm_image => confirm(&mut *self.primary.borrow_mut()).map_err(|_| RC_UNKNOWN),
+            None => Err(RC_INVALID),
+            Some(hash) if Some(hash) == primary.as_ref().map(|h| &h[..]) => {
+                if confirm_image {
+                    confirm(&mut *self.primary.borrow_mut()).map_err(|_| RC_UNKNOWN)?;
+                }
+                Ok(())
+            }
+            Some(hash) if Some(hash) == secondary.as_ref().map(|h| &h[..]) => {
+                let mut slot = self.secondary.borrow_mut();
+                let flash = slot.get_mut();
+                if !upgrade_requested(flash).map_err(|_| RC_UNKNOWN)? {
+                    request_upgrade(flash).map_err(|_| RC_UNKNOWN)?;
+                }
+                if confirm_image {
+                    confirm(flash).map_err(|_| RC_UNKNOWN)?;
+                }
+                Ok(())
+            }
+            Some(_) => Err(RC_NOT_FOUND),
+        }
+    }
+
+    /// Write a chunk of an image to the upgrade slot.
+    fn upload(&mut self, payload: &[u8], enc: &mut Encoder) -> Reply {
+        let mut off = None;
+        let mut len = None;
+        let mut data = None;
+        let mut image = 0;
+        Decoder::new(payload).map(|dec, key, value| {
+            match (key, value) {
+                ("off", Item::Uint(value)) => off = Some(value as usize),
+                ("len", Item::Uint(value)) => len = Some(value as usize),
+                ("data", Item::Bytes(value)) => data = Some(value),
+                ("image", Item::Uint(value)) => image = value,
+                (_, value) => dec.skip(value)?,
+            }
+            Ok(())
+        }).map_err(|_| RC_INVALID)?;
+        let (off, data) = off.zip(data).ok_or(RC_INVALID)?;
+        if image != 0 {
+            return Err(RC_NOT_FOUND);
+        }
+
+        let mut slot = self.secondary.borrow_mut();
+        if off == 0 {
+            // A new upload.  Erasing the whole slot also clears any request.
+            let len = len.ok_or(RC_INVALID)?;
+            if len > slot.capacity() {
+                return Err(RC_NO_MEMORY);
+            }
+            let size = slot.capacity();
+            slot.erase(0, size).map_err(|_| RC_UNKNOWN)?;
+            self.upload = Some(Upload { len, next: 0 });
+        }
+        let upload = self.upload.as_mut().ok_or(RC_INVALID)?;
+
+        // A chunk out of sequence, such as a retry of one that was written,
+        // is answered with where the upload is, so the client can resume.
+        if off == upload.next {
+            if off + data.len() > upload.len {
+                return Err(RC_INVALID);
+            }
+            slot.write(off, data).map_err(|_| RC_UNKNOWN)?;
+            upload.next += data.len();
+            if upload.next == upload.len {
+                slot.flush().map_err(|_| RC_UNKNOWN)?;
+            }
+        }
+
+        let next = upload.next;
+        let mut encode = || -> cbor::Result<()> {
+            enc.map(2)?;
+            enc.text("rc")?;
+            enc.uint(RC_OK as u64)?;
+            enc.text("off")?;
+            enc.uint(next as u64)
+        };
+        encode().map_err(|_| RC_NO_MEMORY)
+    }
+
+    /// Give out a new nonce for a wipe, replacing any earlier one.
+    fn wipe_nonce(&mut self, enc: &mut Encoder) -> Reply {
+        let wipe = self.wipe.as_mut().ok_or(RC_NOT_SUPPORTED)?;
+        wipe.count += 1;
+        let mut crypto = SoftCrypto::new();
+        crypto.sha256_start();
+        crypto.sha256_update(&wipe.seed);
+        crypto.sha256_update(&wipe.count.to_le_bytes());
+        let nonce = crypto.sha256_finish();
+        wipe.nonce = Some(nonce);
+
+        let mut encode = || -> cbor::Result<()> {
+            enc.map(1)?;
+            enc.text("nonce")?;
+            enc.bytes(&nonce)
+        };
+        encode().map_err(|_| RC_NO_MEMORY)
+    }
+
+    /// Erase both slots, if the request is signed.
+    fn wipe(&mut self, payload: &[u8]) -> Reply {
+        let wipe = self.wipe.as_mut().ok_or(RC_NOT_SUPPORTED)?;
+        let mut sig = None;
+        Decoder::new(payload).map(|dec, key, value| {
+            match (key, value) {
+                ("sig", Item::Bytes(value)) => sig = Some(value),
+                (_, value) => dec.skip(value)?,
+            }
+            Ok(())
+        }).map_err(|_| RC_INVALID)?;
+        // The nonce is used up whether or not this works.
+        let nonce = wipe.nonce.take().ok_or(RC_BAD_STATE)?;
+        let sig = sig.ok_or(RC_INVALID)?;
+
+        let mut crypto = SoftCrypto::new();
+        crypto.sha256_start();
+        crypto.sha256_update(WIPE_CONTEXT);
+        crypto.sha256_update(&nonce);
+        let hash = crypto.sha256_finish();
+        if !EcdsaP256.verify(&mut crypto, wipe.key, &hash, sig) {
+            return Err(RC_ACCESS_DENIED);
+        }
+
+        self.upload = None;
+        let mut primary = self.primary.borrow_mut();
+        let size = primary.capacity();
+        primary.erase(0, size).map_err(|_| RC_UNKNOWN)?;
+        let mut secondary = self.secondary.borrow_mut();
+        let size = secondary.capacity();
+        secondary.erase(0, size).map_err(|_| RC_UNKNOWN)
+    }
+}
+
+/// The upgrade slot as the bootloader sees it, without the buffering of
+/// uploads, which would hide the trailer's real write size.
+struct Unbuffered<'a, F>(&'a RefCell<BufferedFlash<F>>);
+
+impl<F: Flash> FlashCell for Unbuffered<'_, F> {
+    type Flash = F;
+
+    fn with<R>(&self, f: impl FnOnce(&mut F) -> R) -> R {
+        f(self.0.borrow_mut().get_mut())
+    }
+}
+
+/// One entry of the image list.
+fn encode_slot(slot: &SlotSummary, enc: &mut Encoder) -> cbor::Result<()> {
+    let mut version = heapless::String::<32>::new();
+    let v = &slot.version;
+    let _ = write!(version, "{}.{}.{}", v.major, v.minor, v.revision);
+    if v.build_num != 0 {
+        let _ = write!(version, ".{}", v.build_num);
+    }
+
+    enc.map(8)?;
+    enc.text("slot")?;
+    enc.uint(slot.slot as u64)?;
+    enc.text("version")?;
+    enc.text(&version)?;
+    enc.text("hash")?;
+    enc.bytes(&slot.hash)?;
+    enc.text("bootable")?;
+    enc.bool(true)?;
+    enc.text("pending")?;
+    enc.bool(slot.pending())?;
+    enc.text("confirmed")?;
+    enc.bool(slot.confirmed())?;
+    enc.text("active")?;
+    enc.bool(slot.active())?;
+    enc.text("permanent")?;
+    enc.bool(slot.permanent())
+}
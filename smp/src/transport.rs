@@ -0,0 +1,224 @@
+//! SMP over a byte stream
+//!
+//! Serial ports, and USB CDC-ACM, carry SMP packets as lines of base64 text,
+//! the same way as mcumgr's console transport, so they can share a port with
+//! log messages.  The first line of a packet starts with 0x06 0x09, and any
+//! continuation lines with 0x04 0x14.  Each line is at most 127 bytes,
+//! including the newline.
+//!
+//! The decoded lines, concatenated, are the length (big endian, 16 bits), the
+//! packet, then a CRC16-CCITT (XMODEM) of the packet, big endian.  The length
+//! includes the CRC.
+
+use boot::recovery::Crc16;
+use storage::Flash;
+
+use crate::Smp;
+
+/// A byte-oriented link to the host.
+pub trait Transport {
+    /// Wait for a byte.  Returns None on a receive error, such as a framing
+    /// error or overrun.
+    fn read(&mut self) -> Option<u8>;
+    /// Send bytes, waiting until they have been queued.
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Largest packet handled, including its header.
+pub const MAX_PACKET: usize = 1024;
+
+/// Start of the first line of a packet.
+const START: [u8; 2] = [0x06, 0x09];
+/// Start of each later line.
+const CONTINUE: [u8; 2] = [0x04, 0x14];
+
+/// Longest line, including the start bytes and newline.
+const MAX_LINE: usize = 127;
+
+/// Raw bytes carried per line.  This encodes to 124 characters.
+const LINE_DATA: usize = 93;
+
+/// Framed packet: length, packet, and CRC.
+const MAX_FRAMED: usize = MAX_PACKET + 4;
+
+/// Handle requests from the transport until a reset is requested.  The reset
+/// response has been sent when this returns, and the caller should then reset
+/// the device.
+pub fn serve<T: Transport, F: Flash>(transport: &mut T, smp: &mut Smp<F>) {
+    let mut request = [0u8; MAX_PACKET];
+    let mut response = [0u8; MAX_PACKET];
+    loop {
+        let len = match read_packet(transport, &mut request) {
+            Some(len) => len,
+            None => continue,
+        };
+        let handled = smp.handle(&request[..len], &mut response);
+        if handled.len > 0 {
+            write_packet(transport, &response[..handled.len]);
+        }
+        if handled.reset {
+            return;
+        }
+    }
+}
+
+/// Read lines until a whole packet has arrived, returning its length.  Returns
+/// None for a packet that is corrupt, or too large.  Lines that aren't part of
+/// a packet are ignored.
+pub fn read_packet<T: Transport>(transport: &mut T, packet: &mut [u8]) -> Option<usize> {
+    let mut framed = [0u8; MAX_FRAMED];
+    let mut have = 0;
+    let mut started = false;
+    let mut line = [0u8; MAX_LINE];
+
+    loop {
+        let len = read_line(transport, &mut line)?;
+        let line = &line[..len];
+        if line.starts_with(&START) {
+            started = true;
+            have = 0;
+        } else if !line.starts_with(&CONTINUE) || !started {
+            continue;
+        }
+        have += base64_decode(&line[2..], &mut framed[have..])?;
+
+        if have < 2 {
+            continue;
+        }
+        let total = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+        if total < 2 || total + 2 > framed.len() || total - 2 > packet.len() {
+            return None;
+        }
+        if have < total + 2 {
+            continue;
+        }
+
+        let body = &framed[2..total];
+        let crc = u16::from_be_bytes([framed[total], framed[total + 1]]);
+        let mut check = Crc16::new();
+        check.update(body);
+        if check.finish() != crc {
+            return None;
+        }
+        packet[..body.len()].copy_from_slice(body);
+        return Some(body.len());
+    }
+}
+
+/// Read a line, without its newline, returning its length.  An overlong line,
+/// or a receive error, gives None.
+fn read_line<T: Transport>(transport: &mut T, line: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let byte = transport.read()?;
+        if byte == b'\n' {
+            return Some(len);
+        }
+        if len == line.len() {
+            // Discard the rest of the line.
+            while transport.read()? != b'\n' {
+            }
+            return None;
+        }
+        line[len] = byte;
+        len += 1;
+    }
+}
+
+/// Send a packet, framed.
+pub fn write_packet<T: Transport>(transport: &mut T, packet: &[u8]) {
+    let mut framed = [0u8; MAX_FRAMED];
+    let packet = &packet[..packet.len().min(MAX_PACKET)];
+    let total = packet.len() + 2;
+    framed[..2].copy_from_slice(&(total as u16).to_be_bytes());
+    framed[2..total].copy_from_slice(packet);
+    let mut crc = Crc16::new();
+    crc.update(packet);
+    framed[total..total + 2].copy_from_slice(&crc.finish().to_be_bytes());
+
+    let mut line = [0u8; MAX_LINE];
+    for (i, chunk) in framed[..total + 2].chunks(LINE_DATA).enumerate() {
+        line[..2].copy_from_slice(if i == 0 { &START } else { &CONTINUE });
+        let len = 2 + base64_encode(chunk, &mut line[2..]);
+        line[len] = b'\n';
+        transport.write(&line[..len + 1]);
+    }
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode with padding, returning the number of characters written.
+fn base64_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            out[len + i] = if i <= chunk.len() {
+                ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]
+            } else {
+                b'='
+            };
+        }
+        len += 4;
+    }
+    len
+}
+
+/// Decode padded base64, returning the number of bytes written, or None if
+/// it is malformed or doesn't fit.
+fn base64_decode(text: &[u8], out: &mut [u8]) -> Option<usize> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut len = 0;
+    for quad in text.chunks(4) {
+        let mut n = 0u32;
+        let mut count = 3;
+        for (i, &c) in quad.iter().enumerate() {
+            let value = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' if i >= 2 => {
+                    count = count.min(i - 1);
+                    0
+                }
+                _ => return None,
+            };
+            n = n << 6 | value as u32;
+        }
+        if len + count > out.len() {
+            return None;
+        }
+        for i in 0..count {
+            out[len + i] = (n >> (16 - 8 * i)) as u8;
+        }
+        len += count;
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for len in 0..10 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 5) as u8).collect();
+            let mut text = [0u8; 16];
+            let n = base64_encode(&data, &mut text);
+            let mut back = [0u8; 10];
+            let m = base64_decode(&text[..n], &mut back).unwrap();
+            assert_eq!(&back[..m], &data[..]);
+        }
+        let mut text = [0u8; 8];
+        let n = base64_encode(b"smp", &mut text);
+        assert_eq!(&text[..n], b"c21w");
+        let n = base64_encode(b"sm", &mut text);
+        assert_eq!(&text[..n], b"c20=");
+    }
+}
@@ -0,0 +1,365 @@
+// SMP testing.
+
+use boot::{
+    ecdsa_public_key, ecdsa_sign, record_boot_error, BootError, CryptoBackend, EntropyError, EntropySource, Error,
+    SeededEntropy, SoftCrypto, SoftMul,
+};
+use smp::cbor::{Decoder, Encoder, Item};
+use smp::{
+    Handled, Smp, GROUP_BASIC, GROUP_IMAGE, GROUP_OS, HEADER_SIZE, ID_BASIC_ERASE, ID_IMAGE_STATE,
+    ID_IMAGE_UPLOAD, ID_OS_RESET, OP_READ, OP_READ_RSP, OP_WRITE, OP_WRITE_RSP, RC_ACCESS_DENIED,
+    RC_BAD_STATE, RC_NOT_FOUND, RC_NOT_SUPPORTED, WIPE_CONTEXT,
+};
+use simflash::SimFlash;
+use storage::{Flash, ReadFlash};
+
+static IMAGE: &[u8] = include_bytes!("../../boot/data/sample-ecdsa.bin");
+
+/// A CBOR value, for building requests and checking responses.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Bool(bool),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Value::Uint(v) => enc.uint(*v).unwrap(),
+            Value::Bytes(v) => enc.bytes(v).unwrap(),
+            Value::Text(v) => enc.text(v).unwrap(),
+            Value::Bool(v) => enc.bool(*v).unwrap(),
+            Value::Array(items) => {
+                enc.array(items.len()).unwrap();
+                for item in items {
+                    item.encode(enc);
+                }
+            }
+            Value::Map(entries) => {
+                enc.map(entries.len()).unwrap();
+                for (key, value) in entries {
+                    enc.text(key).unwrap();
+                    value.encode(enc);
+                }
+            }
+        }
+    }
+
+    fn decode(dec: &mut Decoder) -> Value {
+        let item = dec.item().unwrap();
+        Value::from_item(dec, item)
+    }
+
+    fn from_item(dec: &mut Decoder, item: Item) -> Value {
+        match item {
+            Item::Uint(v) => Value::Uint(v),
+            Item::Bytes(v) => Value::Bytes(v.to_vec()),
+            Item::Text(v) => Value::Text(v.to_string()),
+            Item::Bool(v) => Value::Bool(v),
+            Item::Array(Some(len)) => Value::Array((0..len).map(|_| Value::decode(dec)).collect()),
+            Item::Map(Some(len)) => Value::Map((0..len).map(|_| {
+                let key = match dec.item().unwrap() {
+                    Item::Text(key) => key.to_string(),
+                    item => panic!("Unexpected key {:?}", item),
+                };
+                (key, Value::decode(dec))
+            }).collect()),
+            item => panic!("Unexpected item {:?}", item),
+        }
+    }
+}
+
+fn map(entries: &[(&str, Value)]) -> Value {
+    Value::Map(entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+}
+
+/// Send a request, and return the decoded response.
+fn request(smp: &mut Smp<SimFlash>, op: u8, group: u16, id: u8, body: &Value) -> (Value, Handled) {
+    let mut payload = vec![0u8; 4096];
+    let mut enc = Encoder::new(&mut payload);
+    body.encode(&mut enc);
+    let len = enc.len();
+
+    let mut packet = vec![op, 0];
+    packet.extend((len as u16).to_be_bytes());
+    packet.extend(group.to_be_bytes());
+    packet.extend([0x42, id]);
+    packet.extend(&payload[..len]);
+
+    let mut response = vec![0u8; 1024];
+    let handled = smp.handle(&packet, &mut response);
+    assert!(handled.len >= HEADER_SIZE);
+    let rsp_op = if op == OP_READ { OP_READ_RSP } else { OP_WRITE_RSP };
+    assert_eq!(response[0], rsp_op);
+    assert_eq!(&response[4..8], &packet[4..8]);
+    let rsp_len = u16::from_be_bytes([response[2], response[3]]) as usize;
+    assert_eq!(handled.len, HEADER_SIZE + rsp_len);
+
+    let mut dec = Decoder::new(&response[HEADER_SIZE..handled.len]);
+    (Value::decode(&mut dec), handled)
+}
+
+fn images(smp: &mut Smp<SimFlash>) -> Vec<Value> {
+    let (rsp, _) = request(smp, OP_READ, GROUP_IMAGE, ID_IMAGE_STATE, &map(&[]));
+    match rsp.get("images") {
+        Some(Value::Array(images)) => images.clone(),
+        _ => panic!("No image list: {:?}", rsp),
+    }
+}
+
+/// Upload the image in chunks of the given size.
+fn upload(smp: &mut Smp<SimFlash>, chunk: usize) {
+    let mut off = 0;
+    while off < IMAGE.len() {
+        let end = (off + chunk).min(IMAGE.len());
+        let mut body = vec![
+            ("off", Value::Uint(off as u64)),
+            ("data", Value::Bytes(IMAGE[off..end].to_vec())),
+        ];
+        if off == 0 {
+            body.push(("image", Value::Uint(0)));
+            body.push(("len", Value::Uint(IMAGE.len() as u64)));
+        }
+        let (rsp, _) = request(smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_UPLOAD, &map(&body));
+        assert_eq!(rsp.get("rc"), Some(&Value::Uint(0)));
+        assert_eq!(rsp.get("off"), Some(&Value::Uint(end as u64)));
+        off = end;
+    }
+}
+
+fn new_smp(primary: SimFlash, secondary: SimFlash) -> Smp<SimFlash> {
+    let (mut primary, mut secondary) = (primary, secondary);
+    let size = primary.capacity();
+    primary.erase(0, size).unwrap();
+    let size = secondary.capacity();
+    secondary.erase(0, size).unwrap();
+    Smp::new(primary, secondary).unwrap()
+}
+
+#[test]
+fn upload_and_test() {
+    for flashes in simflash::styles::all_flashes() {
+        let (primary, secondary) = flashes.unwrap();
+        let mut smp = new_smp(primary, secondary);
+        assert!(images(&mut smp).is_empty());
+
+        // An odd chunk size exercises the coalescing.
+        upload(&mut smp, 123);
+        let list = images(&mut smp);
+        assert_eq!(list.len(), 1);
+        let slot = &list[0];
+        assert_eq!(slot.get("slot"), Some(&Value::Uint(1)));
+        assert_eq!(slot.get("pending"), Some(&Value::Bool(false)));
+        let hash = slot.get("hash").unwrap().clone();
+
+        // Marking it for test makes it pending.
+        let (rsp, _) = request(&mut smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_STATE,
+                               &map(&[("hash", hash.clone()), ("confirm", Value::Bool(false))]));
+        let slot = match rsp.get("images") {
+            Some(Value::Array(list)) => list[0].clone(),
+            _ => panic!("No image list"),
+        };
+        assert_eq!(slot.get("pending"), Some(&Value::Bool(true)));
+        assert_eq!(slot.get("permanent"), Some(&Value::Bool(false)));
+
+        // And confirming makes it permanent.
+        request(&mut smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_STATE,
+                &map(&[("hash", hash), ("confirm", Value::Bool(true))]));
+        let slot = images(&mut smp)[0].clone();
+        assert_eq!(slot.get("permanent"), Some(&Value::Bool(true)));
+
+        // The upload is intact.
+        let (_, mut secondary) = smp.into_inner().unwrap();
+        let mut data = vec![0u8; IMAGE.len()];
+        secondary.read(0, &mut data).unwrap();
+        assert_eq!(data, IMAGE);
+    }
+}
+
+#[test]
+fn resume() {
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut smp = new_smp(primary, secondary);
+    let first = map(&[
+        ("off", Value::Uint(0)),
+        ("len", Value::Uint(IMAGE.len() as u64)),
+        ("data", Value::Bytes(IMAGE[..100].to_vec())),
+    ]);
+    request(&mut smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_UPLOAD, &first);
+
+    // A chunk from the wrong place is answered with where to continue.
+    let wrong = map(&[("off", Value::Uint(300)), ("data", Value::Bytes(vec![0; 10]))]);
+    let (rsp, _) = request(&mut smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_UPLOAD, &wrong);
+    assert_eq!(rsp.get("off"), Some(&Value::Uint(100)));
+}
+
+#[test]
+fn errors_and_reset() {
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut smp = new_smp(primary, secondary);
+
+    // An unknown hash.
+    let (rsp, _) = request(&mut smp, OP_WRITE, GROUP_IMAGE, ID_IMAGE_STATE,
+                           &map(&[("hash", Value::Bytes(vec![1; 32]))]));
+    assert_eq!(rsp.get("rc"), Some(&Value::Uint(RC_NOT_FOUND as u64)));
+
+    // An unknown command.
+    let (rsp, handled) = request(&mut smp, OP_READ, 9, 0, &map(&[]));
+    assert_eq!(rsp.get("rc"), Some(&Value::Uint(RC_NOT_SUPPORTED as u64)));
+    assert!(!handled.reset);
+
+    let (_, handled) = request(&mut smp, OP_WRITE, GROUP_OS, ID_OS_RESET, &map(&[]));
+    assert!(handled.reset);
+
+    // Responses are not requests.
+    let mut response = [0u8; 64];
+    let packet = [OP_READ_RSP, 0, 0, 0, 0, 1, 0, 0];
+    assert_eq!(smp.handle(&packet, &mut response).len, 0);
+}
+
+#[test]
+fn indefinite_map() {
+    // Some clients send indefinite length maps.
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut smp = new_smp(primary, secondary);
+    let mut packet = vec![OP_WRITE, 0, 0, 0, 0, 1, 0, ID_IMAGE_STATE];
+    let body = [0xbf, 0x67, b'c', b'o', b'n', b'f', b'i', b'r', b'm', 0xf5, 0xff];
+    packet[2..4].copy_from_slice(&(body.len() as u16).to_be_bytes());
+    packet.extend(body);
+    let mut response = [0u8; 256];
+    let handled = smp.handle(&packet, &mut response);
+    let mut dec = Decoder::new(&response[HEADER_SIZE..handled.len]);
+    let rsp = Value::decode(&mut dec);
+    assert!(rsp.get("images").is_some());
+}
+
+#[test]
+fn boot_error() {
+    let (mut primary, mut secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = primary.capacity();
+    primary.erase(0, size).unwrap();
+    let size = secondary.capacity();
+    secondary.erase(0, size).unwrap();
+    let mut smp = Smp::new(primary, secondary).unwrap();
+    let (rsp, _) = request(&mut smp, OP_READ, GROUP_IMAGE, ID_IMAGE_STATE, &map(&[]));
+    assert_eq!(rsp.get("bootError"), None);
+
+    // As left by the bootloader after rejecting an upgrade.
+    let (primary, mut secondary) = smp.into_inner().unwrap();
+    record_boot_error(&mut secondary, &BootError::new(&Error::InvalidImage, 0x400)).unwrap();
+    let mut smp = Smp::new(primary, secondary).unwrap();
+    let (rsp, _) = request(&mut smp, OP_READ, GROUP_IMAGE, ID_IMAGE_STATE, &map(&[]));
+    let err = rsp.get("bootError").unwrap();
+    assert_eq!(err.get("rc"), Some(&Value::Uint(2)));
+    assert_eq!(err.get("off"), Some(&Value::Uint(0x400)));
+}
+
+/// The DER SubjectPublicKeyInfo prefix of a P-256 key.
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// Get a nonce, and sign the wipe of it with `private`.
+fn wipe_signature(smp: &mut Smp<SimFlash>, private: &[u8; 32]) -> Vec<u8> {
+    let (rsp, _) = request(smp, OP_READ, GROUP_BASIC, ID_BASIC_ERASE, &map(&[]));
+    let nonce = match rsp.get("nonce") {
+        Some(Value::Bytes(nonce)) => nonce.clone(),
+        _ => panic!("No nonce: {:?}", rsp),
+    };
+    let mut crypto = SoftCrypto::new();
+    crypto.sha256_start();
+    crypto.sha256_update(WIPE_CONTEXT);
+    crypto.sha256_update(&nonce);
+    let (r, s) = ecdsa_sign(&mut SoftMul, private, &crypto.sha256_finish()).unwrap();
+    [r, s].concat()
+}
+
+#[test]
+fn wipe() {
+    let private = [0x17; 32];
+    let mut key = P256_SPKI_PREFIX.to_vec();
+    key.extend(ecdsa_public_key(&mut SoftMul, &private).unwrap());
+    let key: &'static [u8] = Box::leak(key.into_boxed_slice());
+
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut smp = new_smp(primary, secondary);
+    upload(&mut smp, 512);
+    let erase = |smp: &mut Smp<SimFlash>, sig: Vec<u8>| {
+        let (rsp, _) = request(smp, OP_WRITE, GROUP_BASIC, ID_BASIC_ERASE, &map(&[("sig", Value::Bytes(sig))]));
+        rsp.get("rc").cloned()
+    };
+
+    // Without a key, there is no wiping.
+    assert_eq!(erase(&mut smp, vec![0; 64]), Some(Value::Uint(RC_NOT_SUPPORTED as u64)));
+    let (rsp, _) = request(&mut smp, OP_READ, GROUP_BASIC, ID_BASIC_ERASE, &map(&[]));
+    assert_eq!(rsp.get("rc"), Some(&Value::Uint(RC_NOT_SUPPORTED as u64)));
+
+    let mut smp = smp.with_wipe(key, [0x5a; 32]);
+    // A signature needs a nonce first, made with the right key, and each
+    // nonce is only good once.
+    assert_eq!(erase(&mut smp, vec![0; 64]), Some(Value::Uint(RC_BAD_STATE as u64)));
+    let sig = wipe_signature(&mut smp, &[0x18; 32]);
+    assert_eq!(erase(&mut smp, sig), Some(Value::Uint(RC_ACCESS_DENIED as u64)));
+    let sig = wipe_signature(&mut smp, &private);
+    let stale = wipe_signature(&mut smp, &private);
+    assert_eq!(erase(&mut smp, sig), Some(Value::Uint(RC_ACCESS_DENIED as u64)));
+    assert_eq!(images(&mut smp).len(), 1);
+
+    // Signed, both slots are erased, trailers and all.
+    let sig = wipe_signature(&mut smp, &private);
+    assert_eq!(erase(&mut smp, sig), None);
+    assert!(images(&mut smp).is_empty());
+    assert_eq!(erase(&mut smp, stale), Some(Value::Uint(RC_BAD_STATE as u64)));
+    let (mut primary, mut secondary) = smp.into_inner().unwrap();
+    for slot in [&mut primary, &mut secondary] {
+        let mut byte = [0u8; 1];
+        assert!(matches!(slot.read(0, &mut byte), Err(storage::Error::NotWritten)));
+    }
+}
+
+/// A TRNG that has failed its health checks.
+struct Broken;
+
+impl EntropySource for Broken {
+    fn fill(&mut self, _buf: &mut [u8]) -> Result<(), EntropyError> {
+        Err(EntropyError::Health)
+    }
+}
+
+#[test]
+fn wipe_entropy() {
+    let private = [0x17; 32];
+    let mut key = P256_SPKI_PREFIX.to_vec();
+    key.extend(ecdsa_public_key(&mut SoftMul, &private).unwrap());
+    let key: &'static [u8] = Box::leak(key.into_boxed_slice());
+
+    // The seed comes from the source, so the nonces are those of that seed.
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut smp = new_smp(primary, secondary).with_wipe_from(key, &mut SeededEntropy::new(9)).unwrap();
+    let mut seed = [0u8; 32];
+    SeededEntropy::new(9).fill(&mut seed).unwrap();
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let mut same = new_smp(primary, secondary).with_wipe(key, seed);
+    let nonce = |smp: &mut Smp<SimFlash>| request(smp, OP_READ, GROUP_BASIC, ID_BASIC_ERASE, &map(&[])).0;
+    assert_eq!(nonce(&mut smp), nonce(&mut same));
+
+    let sig = wipe_signature(&mut smp, &private);
+    let (rsp, _) = request(&mut smp, OP_WRITE, GROUP_BASIC, ID_BASIC_ERASE, &map(&[("sig", Value::Bytes(sig))]));
+    assert_eq!(rsp.get("rc"), None);
+
+    // Without entropy, there is no wiping.
+    let (primary, secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    assert_eq!(new_smp(primary, secondary).with_wipe_from(key, &mut Broken).err(), Some(EntropyError::Health));
+}
@@ -0,0 +1,93 @@
+// SMP serial transport testing.
+
+use std::collections::VecDeque;
+
+use smp::transport::{read_packet, serve, write_packet, Transport, MAX_PACKET};
+use smp::{Smp, GROUP_IMAGE, GROUP_OS, ID_IMAGE_STATE, ID_OS_RESET, OP_READ, OP_WRITE, OP_WRITE_RSP};
+use storage::{Flash, ReadFlash};
+
+/// A transport with canned input, collecting the output.
+#[derive(Default)]
+struct Fake {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Transport for Fake {
+    fn read(&mut self) -> Option<u8> {
+        // The tests must end with a reset.
+        Some(self.input.pop_front().expect("Out of input"))
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.output.extend(bytes);
+    }
+}
+
+/// An empty map, as a request.
+fn packet(op: u8, group: u16, id: u8) -> Vec<u8> {
+    let mut packet = vec![op, 0, 0, 1];
+    packet.extend(group.to_be_bytes());
+    packet.extend([7, id, 0xa0]);
+    packet
+}
+
+#[test]
+fn round_trip() {
+    // Large enough to need continuation lines.
+    let data: Vec<u8> = (0..700u32).map(|i| (i * 13) as u8).collect();
+    let mut fake = Fake::default();
+    write_packet(&mut fake, &data);
+
+    for line in fake.output.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+        assert!(line.len() < 127);
+    }
+
+    fake.input = fake.output.drain(..).collect();
+    let mut buf = [0u8; MAX_PACKET];
+    let len = read_packet(&mut fake, &mut buf).unwrap();
+    assert_eq!(&buf[..len], &data[..]);
+}
+
+#[test]
+fn corrupt() {
+    let mut fake = Fake::default();
+    write_packet(&mut fake, b"hello there");
+    let mut input: Vec<u8> = fake.output.drain(..).collect();
+    input[5] = if input[5] == b'A' { b'B' } else { b'A' };
+    fake.input = input.into();
+    let mut buf = [0u8; MAX_PACKET];
+    assert_eq!(read_packet(&mut fake, &mut buf), None);
+}
+
+#[test]
+fn session() {
+    let (mut primary, mut secondary) = simflash::styles::all_flashes().next().unwrap().unwrap();
+    let size = primary.capacity();
+    primary.erase(0, size).unwrap();
+    let size = secondary.capacity();
+    secondary.erase(0, size).unwrap();
+    let mut smp = Smp::new(primary, secondary).unwrap();
+
+    // Console output between packets is ignored.
+    let mut fake = Fake::default();
+    fake.output.extend(b"boot: hello\n");
+    write_packet(&mut fake, &packet(OP_READ, GROUP_IMAGE, ID_IMAGE_STATE));
+    write_packet(&mut fake, &packet(OP_WRITE, GROUP_OS, ID_OS_RESET));
+    fake.input = fake.output.drain(..).collect();
+
+    serve(&mut fake, &mut smp);
+    assert!(fake.input.is_empty());
+
+    // Two responses, the last to the reset.
+    fake.input = fake.output.drain(..).collect();
+    let mut buf = [0u8; MAX_PACKET];
+    let len = read_packet(&mut fake, &mut buf).unwrap();
+    assert_eq!(buf[7], ID_IMAGE_STATE);
+    assert!(len > 8);
+    let len = read_packet(&mut fake, &mut buf).unwrap();
+    assert_eq!(buf[0], OP_WRITE_RSP);
+    assert_eq!(buf[7], ID_OS_RESET);
+    assert_eq!(len, 9);
+    assert!(fake.input.is_empty());
+}
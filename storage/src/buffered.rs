@@ -0,0 +1,129 @@
+//! Coalescing writes
+//!
+//! Data often arrives in pieces that don't match the flash's write size, such
+//! as chunks of an upload.  `BufferedFlash` accepts writes of any size, as
+//! long as they are sequential, and passes them on to the flash in whole write
+//! units.  A partial unit is held until more data follows it, or until
+//! `flush`, which pads it with 0xff.
+//!
+//! Held data is not visible to `read` until it has been written.
+
+use crate::{check_erase, Error, Flash, ReadFlash, Result};
+
+/// Largest write size supported.
+pub const MAX_WRITE: usize = 512;
+
+pub struct BufferedFlash<F> {
+    flash: F,
+    buf: [u8; MAX_WRITE],
+    /// Offset of the unit being filled.
+    base: usize,
+    /// Bytes of it held so far.
+    len: usize,
+}
+
+impl<F: Flash> BufferedFlash<F> {
+    pub fn new(flash: F) -> Result<BufferedFlash<F>> {
+        if flash.write_size() > MAX_WRITE {
+            return Err(Error::NotAligned);
+        }
+        Ok(BufferedFlash { flash, buf: [0xff; MAX_WRITE], base: 0, len: 0 })
+    }
+
+    /// Write out any partial unit, padded with 0xff.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let size = self.flash.write_size();
+        self.buf[self.len..size].fill(0xff);
+        self.len = 0;
+        self.flash.write(self.base, &self.buf[..size])
+    }
+
+    /// Access the flash directly.  Anything held is not flushed.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Flush, and recover the underlying flash.
+    pub fn finish(mut self) -> Result<F> {
+        self.flush()?;
+        Ok(self.flash)
+    }
+}
+
+impl<F: Flash> ReadFlash for BufferedFlash<F> {
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        self.flash.memory_address()
+    }
+}
+
+impl<F: Flash> Flash for BufferedFlash<F> {
+    fn write_size(&self) -> usize {
+        1
+    }
+
+    fn erase_size(&self) -> usize {
+        self.flash.erase_size()
+    }
+
+    /// Erasing discards anything held.
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        check_erase(self, from, to)?;
+        self.len = 0;
+        self.flash.erase(from, to)
+    }
+
+    /// Writes must either continue the previous write, or start on a boundary
+    /// of the underlying write size.  Starting elsewhere flushes what is held.
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > self.capacity() || offset > self.capacity() - bytes.len() {
+            return Err(Error::OutOfBounds);
+        }
+        let size = self.flash.write_size();
+        if self.len > 0 && offset != self.base + self.len {
+            self.flush()?;
+        }
+        if self.len == 0 {
+            if !offset.is_multiple_of(size) {
+                return Err(Error::NotAligned);
+            }
+            self.base = offset;
+        }
+
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            if self.len == 0 && bytes.len() >= size {
+                // Whole units go straight through.
+                let whole = bytes.len() - bytes.len() % size;
+                self.flash.write(self.base, &bytes[..whole])?;
+                self.base += whole;
+                bytes = &bytes[whole..];
+                continue;
+            }
+            let count = (size - self.len).min(bytes.len());
+            self.buf[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+            self.len += count;
+            bytes = &bytes[count..];
+            if self.len == size {
+                self.len = 0;
+                self.flash.write(self.base, &self.buf[..size])?;
+                self.base += size;
+            }
+        }
+        Ok(())
+    }
+}
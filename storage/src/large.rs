@@ -0,0 +1,268 @@
+//! Large devices
+//!
+//! `Flash` addresses a device with `usize` offsets, which on the 32-bit
+//! targets the bootloader runs on limits it to 4GB.  Large external NOR and
+//! NAND arrays can exceed that, so `LargeFlash` addresses a device with
+//! `Address`, which is 64 bits everywhere.  A device that can be that large
+//! implements `LargeFlash` itself, checking its arguments with the
+//! `check_large_*` functions, as `Flash` devices use `check_read` and friends.
+//!
+//! The boot code works on slots, which are always far smaller than this, so
+//! it keeps using `Flash`.  A `Partition` presents a window of a large device,
+//! such as a slot, as a `Flash` of its own.
+
+use crate::{Error, Flash, ReadFlash, Result};
+
+/// An offset into a large device.
+pub type Address = u64;
+
+/// A device addressed by `Address`.  The sizes are those of `Flash`.
+pub trait LargeFlash {
+    fn read_size(&self) -> usize;
+    fn write_size(&self) -> usize;
+    fn erase_size(&self) -> usize;
+    fn capacity(&self) -> Address;
+
+    fn read(&mut self, offset: Address, bytes: &mut [u8]) -> Result<()>;
+    fn erase(&mut self, from: Address, to: Address) -> Result<()>;
+    fn write(&mut self, offset: Address, bytes: &[u8]) -> Result<()>;
+}
+
+pub fn check_large_read<T: LargeFlash + ?Sized>(
+    flash: &T,
+    offset: Address,
+    length: usize,
+) -> Result<()> {
+    check_large_slice(flash, flash.read_size(), offset, length)
+}
+
+pub fn check_large_erase<T: LargeFlash + ?Sized>(
+    flash: &T,
+    from: Address,
+    to: Address,
+) -> Result<()> {
+    if from > to || to > flash.capacity() {
+        return Err(Error::OutOfBounds);
+    }
+    let erase_size = flash.erase_size() as Address;
+    if !from.is_multiple_of(erase_size) || !to.is_multiple_of(erase_size) {
+        return Err(Error::NotAligned);
+    }
+    Ok(())
+}
+
+pub fn check_large_write<T: LargeFlash + ?Sized>(
+    flash: &T,
+    offset: Address,
+    length: usize,
+) -> Result<()> {
+    check_large_slice(flash, flash.write_size(), offset, length)
+}
+
+pub fn check_large_slice<T: LargeFlash + ?Sized>(
+    flash: &T,
+    align: usize,
+    offset: Address,
+    length: usize,
+) -> Result<()> {
+    let length = length as Address;
+    if length > flash.capacity() || offset > flash.capacity() - length {
+        return Err(Error::OutOfBounds);
+    }
+    let align = align as Address;
+    if !offset.is_multiple_of(align) || !length.is_multiple_of(align) {
+        return Err(Error::NotAligned);
+    }
+    Ok(())
+}
+
+/// A window of a large device, at `base`, presented as a `Flash`.
+pub struct Partition<D> {
+    dev: D,
+    base: Address,
+    size: usize,
+}
+
+impl<D: LargeFlash> Partition<D> {
+    /// The window must be within the device, and aligned to its erase size.
+    pub fn new(dev: D, base: Address, size: usize) -> Result<Partition<D>> {
+        let erase_size = dev.erase_size() as Address;
+        let end = base.checked_add(size as Address).ok_or(Error::OutOfBounds)?;
+        if end > dev.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        if !base.is_multiple_of(erase_size) || !(size as Address).is_multiple_of(erase_size) {
+            return Err(Error::NotAligned);
+        }
+        Ok(Partition { dev, base, size })
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dev
+    }
+}
+
+impl<D: LargeFlash> ReadFlash for Partition<D> {
+    fn read_size(&self) -> usize {
+        self.dev.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        crate::check_read(self, offset, bytes.len())?;
+        self.dev.read(self.base + offset as Address, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.size
+    }
+}
+
+impl<D: LargeFlash> Flash for Partition<D> {
+    fn write_size(&self) -> usize {
+        self.dev.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.dev.erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        crate::check_erase(self, from, to)?;
+        self.dev.erase(self.base + from as Address, self.base + to as Address)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        crate::check_write(self, offset, bytes.len())?;
+        self.dev.write(self.base + offset as Address, bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SECTOR: usize = 4096;
+
+    /// An 8GB device, holding only the sectors written to.  Pages aren't
+    /// tracked, so it checks only the arguments.
+    struct Sparse {
+        sectors: BTreeMap<Address, Vec<u8>>,
+    }
+
+    impl Sparse {
+        fn new() -> Sparse {
+            Sparse { sectors: BTreeMap::new() }
+        }
+    }
+
+    impl LargeFlash for Sparse {
+        fn read_size(&self) -> usize {
+            1
+        }
+
+        fn write_size(&self) -> usize {
+            8
+        }
+
+        fn erase_size(&self) -> usize {
+            SECTOR
+        }
+
+        fn capacity(&self) -> Address {
+            8 << 30
+        }
+
+        fn read(&mut self, offset: Address, bytes: &mut [u8]) -> Result<()> {
+            check_large_read(self, offset, bytes.len())?;
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                let at = offset + i as Address;
+                let sector = self.sectors.get(&(at / SECTOR as Address));
+                *byte = sector.map_or(0xff, |sector| sector[(at % SECTOR as Address) as usize]);
+            }
+            Ok(())
+        }
+
+        fn erase(&mut self, from: Address, to: Address) -> Result<()> {
+            check_large_erase(self, from, to)?;
+            for sector in from / SECTOR as Address .. to / SECTOR as Address {
+                self.sectors.remove(&sector);
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: Address, bytes: &[u8]) -> Result<()> {
+            check_large_write(self, offset, bytes.len())?;
+            for (i, byte) in bytes.iter().enumerate() {
+                let at = offset + i as Address;
+                let sector = self.sectors.entry(at / SECTOR as Address).or_insert_with(|| vec![0xff; SECTOR]);
+                sector[(at % SECTOR as Address) as usize] = *byte;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(Partition::new(Sparse::new(), 5 << 30, 4 * SECTOR).is_ok());
+        assert!(Partition::new(Sparse::new(), (8 << 30) - SECTOR as Address, SECTOR).is_ok());
+        assert!(matches!(Partition::new(Sparse::new(), 8 << 30, SECTOR), Err(Error::OutOfBounds)));
+        assert!(matches!(Partition::new(Sparse::new(), Address::MAX - 1, SECTOR), Err(Error::OutOfBounds)));
+        assert!(matches!(Partition::new(Sparse::new(), (5 << 30) + 8, SECTOR), Err(Error::NotAligned)));
+        assert!(matches!(Partition::new(Sparse::new(), 5 << 30, SECTOR + 8), Err(Error::NotAligned)));
+    }
+
+    #[test]
+    fn test_window() {
+        let base: Address = (5 << 30) + 2 * SECTOR as Address;
+        let mut part = Partition::new(Sparse::new(), base, 4 * SECTOR).unwrap();
+        assert_eq!(part.capacity(), 4 * SECTOR);
+        assert_eq!(part.erase_size(), SECTOR);
+        assert_eq!(part.write_size(), 8);
+
+        part.write(SECTOR + 16, &[0x42; 8]).unwrap();
+        let mut buf = [0u8; 8];
+        part.read(SECTOR + 16, &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 8]);
+
+        // The write landed past 4GB, where the window starts, and not at the
+        // address truncated to 32 bits.
+        let mut dev = part.into_inner();
+        assert_eq!(dev.sectors.keys().copied().collect::<Vec<_>>(), [base / SECTOR as Address + 1]);
+        LargeFlash::read(&mut dev, base + SECTOR as Address + 16, &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 8]);
+
+        let mut part = Partition::new(dev, base, 4 * SECTOR).unwrap();
+        part.erase(SECTOR, 2 * SECTOR).unwrap();
+        part.read(SECTOR + 16, &mut buf).unwrap();
+        assert_eq!(buf, [0xff; 8]);
+        assert!(part.into_inner().sectors.is_empty());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut part = Partition::new(Sparse::new(), 5 << 30, 4 * SECTOR).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(part.read(4 * SECTOR - 4, &mut buf), Err(Error::OutOfBounds));
+        assert_eq!(part.write(4 * SECTOR, &buf), Err(Error::OutOfBounds));
+        assert_eq!(part.write(4, &buf), Err(Error::NotAligned));
+        assert_eq!(part.erase(0, 5 * SECTOR), Err(Error::OutOfBounds));
+        assert_eq!(part.erase(0, SECTOR / 2), Err(Error::NotAligned));
+        assert_eq!(part.erase(2 * SECTOR, SECTOR), Err(Error::OutOfBounds));
+
+        // The last window of the device reaches its end, and no further.
+        let mut part = Partition::new(part.into_inner(), (8 << 30) - SECTOR as Address, SECTOR).unwrap();
+        part.write(SECTOR - 8, &buf).unwrap();
+        assert_eq!(part.read(SECTOR - 4, &mut buf), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn test_checks() {
+        let dev = Sparse::new();
+        assert_eq!(check_large_read(&dev, (8 << 30) - 1, 1), Ok(()));
+        assert_eq!(check_large_read(&dev, 8 << 30, 1), Err(Error::OutOfBounds));
+        assert_eq!(check_large_write(&dev, (6 << 30) + 4, 8), Err(Error::NotAligned));
+        assert_eq!(check_large_erase(&dev, 6 << 30, (6 << 30) + SECTOR as Address), Ok(()));
+        assert_eq!(check_large_erase(&dev, 6 << 30, 9 << 30), Err(Error::OutOfBounds));
+    }
+}
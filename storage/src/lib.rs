@@ -2,6 +2,16 @@
 
 #![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+mod buffered;
+mod large;
+mod lock;
+
+pub use buffered::{BufferedFlash, MAX_WRITE};
+pub use large::{
+    check_large_erase, check_large_read, check_large_slice, check_large_write, Address, LargeFlash, Partition,
+};
+pub use lock::{Lock, LockedFlash, UnlockedFlash};
+
 // TODO: Do we want to use errors?
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -10,6 +20,8 @@ pub enum Error {
     OutOfBounds,
     NotWritten,
     NotErased,
+    /// The device reported a failure performing the operation.
+    Failed,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -20,6 +32,13 @@ pub trait ReadFlash {
     fn read_size(&self) -> usize;
     fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()>;
     fn capacity(&self) -> usize;
+
+    /// If the contents can also be read directly through the memory map, the
+    /// address of offset 0.  This lets a DMA engine read the device without
+    /// going through `read`.
+    fn memory_address(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// Flash that can be written to.
@@ -0,0 +1,109 @@
+//! Locking flash against writes
+//!
+//! Most flash controllers come out of reset locked: writes and erases are
+//! refused until a key sequence unlocks them.  Leaving the device unlocked
+//! when chaining hands the application a flash that a stray write can
+//! corrupt.  Following the stm32h7xx-hal's banks, `LockedFlash` only allows
+//! reads, and `unlock` gives an `UnlockedFlash` that implements `Flash` for as
+//! long as it is held, locking the device again when it is dropped.
+//!
+//! Code that writes takes a `Flash`, so it can only be given the unlocked
+//! form, and the borrow ensures the device is locked again before anything
+//! that follows, such as chaining to the image.
+
+use crate::{Flash, ReadFlash, Result};
+
+/// A device whose writes and erases can be locked out.
+pub trait Lock {
+    fn lock(&mut self);
+    fn unlock(&mut self);
+}
+
+/// A device that is locked, and can only be read.
+pub struct LockedFlash<D: Lock> {
+    dev: D,
+}
+
+impl<D: Lock> LockedFlash<D> {
+    /// Take the device, locking it.
+    pub fn new(mut dev: D) -> LockedFlash<D> {
+        dev.lock();
+        LockedFlash { dev }
+    }
+
+    /// Unlock the device until the result is dropped.
+    pub fn unlock(&mut self) -> UnlockedFlash<'_, D> {
+        self.dev.unlock();
+        UnlockedFlash { dev: &mut self.dev }
+    }
+
+    /// Recover the device, still locked.
+    pub fn into_inner(self) -> D {
+        self.dev
+    }
+}
+
+impl<D: Lock + ReadFlash> ReadFlash for LockedFlash<D> {
+    fn read_size(&self) -> usize {
+        self.dev.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        self.dev.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.dev.capacity()
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        self.dev.memory_address()
+    }
+}
+
+/// A device unlocked for as long as this is held.
+pub struct UnlockedFlash<'a, D: Lock> {
+    dev: &'a mut D,
+}
+
+impl<D: Lock> Drop for UnlockedFlash<'_, D> {
+    fn drop(&mut self) {
+        self.dev.lock();
+    }
+}
+
+impl<D: Lock + ReadFlash> ReadFlash for UnlockedFlash<'_, D> {
+    fn read_size(&self) -> usize {
+        self.dev.read_size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<()> {
+        self.dev.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.dev.capacity()
+    }
+
+    fn memory_address(&self) -> Option<usize> {
+        self.dev.memory_address()
+    }
+}
+
+impl<D: Lock + Flash> Flash for UnlockedFlash<'_, D> {
+    fn write_size(&self) -> usize {
+        self.dev.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.dev.erase_size()
+    }
+
+    fn erase(&mut self, from: usize, to: usize) -> Result<()> {
+        self.dev.erase(from, to)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        self.dev.write(offset, bytes)
+    }
+}